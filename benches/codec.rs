@@ -0,0 +1,104 @@
+use anonymous_conference_client::{
+    codec::decode_server_event,
+    message_codec::{ClientToClientMessage, SignedMessage},
+    crypto::{self, EncryptionResult},
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use curve25519_dalek::{constants::RISTRETTO_BASEPOINT_POINT, RistrettoPoint, Scalar};
+use rand_core::OsRng;
+
+const MESSAGE_SIZES: &[usize] = &[64, 1024, 16 * 1024, 1024 * 1024];
+const RING_SIZE: usize = 8;
+
+fn bench_client_message(c: &mut Criterion) {
+    let mut group = c.benchmark_group("client_to_client_message");
+    for size in MESSAGE_SIZES {
+        let message = ClientToClientMessage::Message(vec![0x42u8; *size]);
+        let encoded = message.encode().unwrap();
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::new("encode", size), &message, |b, message| b.iter(|| message.encode().unwrap()));
+        group.bench_with_input(BenchmarkId::new("decode", size), &encoded, |b, encoded| b.iter(|| ClientToClientMessage::decode(encoded).unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_encryption_result(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encryption_result");
+    let key = crypto::generate_ephemeral_key();
+    for size in MESSAGE_SIZES {
+        let result = crypto::encrypt_message(&vec![0x42u8; *size], &key).unwrap();
+        let encoded = result.encode();
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::new("encode", size), &result, |b, result| b.iter(|| result.encode()));
+        group.bench_with_input(BenchmarkId::new("decode", size), &encoded, |b, encoded| b.iter(|| EncryptionResult::decode(encoded).unwrap()));
+    }
+    group.finish();
+}
+
+/// Encoding and encrypting an outbound message, with fresh buffers and with a reused scratch buffer
+fn bench_send_path(c: &mut Criterion) {
+    let mut group = c.benchmark_group("send_path");
+    let key = crypto::generate_ephemeral_key();
+    for size in MESSAGE_SIZES {
+        let message = ClientToClientMessage::Message(vec![0x42u8; *size]);
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::new("allocating", size), &message, |b, message| b.iter(|| {
+            crypto::encrypt_message(&message.encode().unwrap(), &key).unwrap().encode()
+        }));
+        let mut encode_buffer = Vec::new();
+        group.bench_with_input(BenchmarkId::new("scratch", size), &message, |b, message| b.iter(|| {
+            message.encode_into(&mut encode_buffer).unwrap();
+            let mut encrypted = Vec::new();
+            crypto::encrypt_message_into(&encode_buffer, &key, &mut encrypted).unwrap();
+            encrypted
+        }));
+    }
+    group.finish();
+}
+
+/// Decrypting and decoding an inbound message
+fn bench_receive_path(c: &mut Criterion) {
+    let mut group = c.benchmark_group("receive_path");
+    let key = crypto::generate_ephemeral_key();
+    for size in MESSAGE_SIZES {
+        let encrypted = crypto::encrypt_message(&ClientToClientMessage::Message(vec![0x42u8; *size]).encode().unwrap(), &key).unwrap().encode();
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::new("allocating", size), &encrypted, |b, encrypted| b.iter(|| {
+            let plaintext = crypto::decrypt_message(&key, &EncryptionResult::decode(encrypted).unwrap()).unwrap();
+            ClientToClientMessage::decode(&plaintext).unwrap()
+        }));
+        let mut decrypt_buffer = Vec::new();
+        group.bench_with_input(BenchmarkId::new("scratch", size), &encrypted, |b, encrypted| b.iter(|| {
+            crypto::decrypt_message_into(&key, encrypted, &mut decrypt_buffer).unwrap();
+            ClientToClientMessage::decode(&decrypt_buffer).unwrap()
+        }));
+    }
+    group.finish();
+}
+
+fn bench_signed_message(c: &mut Criterion) {
+    let private_key = Scalar::random(&mut OsRng);
+    let mut ring: Vec<RistrettoPoint> = (1..RING_SIZE).map(|_| RistrettoPoint::random(&mut OsRng)).collect();
+    ring.push(private_key * RISTRETTO_BASEPOINT_POINT);
+    let signature = crypto::sign_message(&private_key, ring.len() - 1, &ring, b"hello");
+    let signed_message = SignedMessage::new(signature, b"hello");
+    c.bench_function("encode_signed_message", |b| b.iter(|| signed_message.encode()));
+    let signed_message = signed_message.encode();
+    c.bench_function("decode_signed_message", |b| b.iter(|| SignedMessage::decode(&signed_message, RING_SIZE).unwrap()));
+}
+
+fn bench_server_event(c: &mut Criterion) {
+    let mut group = c.benchmark_group("server_event");
+    for size in MESSAGE_SIZES {
+        let mut frame = vec![0x07];
+        frame.extend_from_slice(&1u32.to_be_bytes());
+        frame.extend_from_slice(&(*size as u32).to_be_bytes());
+        frame.extend_from_slice(&vec![0x42u8; *size]);
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::new("decode_incoming_message", size), &frame, |b, frame| b.iter(|| decode_server_event(frame).unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_client_message, bench_encryption_result, bench_send_path, bench_receive_path, bench_signed_message, bench_server_event);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+use anonymous_conference_client::crypto;
+use argon2::{Algorithm, Argon2, Params, Version};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use curve25519_dalek::{constants::RISTRETTO_BASEPOINT_POINT, RistrettoPoint, Scalar};
+use rand_core::OsRng;
+
+/// (name, memory cost in KiB, iterations, parallelism)
+const ARGON2_PRESETS: &[(&str, u32, u32, u32)] = &[
+    ("default", Params::DEFAULT_M_COST, Params::DEFAULT_T_COST, Params::DEFAULT_P_COST),
+    ("moderate", 64 * 1024, 3, 1),
+    ("sensitive", 256 * 1024, 4, 1),
+];
+
+const MESSAGE_SIZES: &[usize] = &[64, 1024, 16 * 1024, 1024 * 1024];
+const RING_SIZES: &[usize] = &[2, 8, 32, 64];
+
+fn bench_argon2(c: &mut Criterion) {
+    let mut group = c.benchmark_group("argon2");
+    group.sample_size(10);
+    let salt = crypto::generate_salt();
+    for (name, m_cost, t_cost, p_cost) in ARGON2_PRESETS {
+        let params = Params::new(*m_cost, *t_cost, *p_cost, Some(32)).unwrap();
+        let argon = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+        group.bench_function(*name, |b| b.iter(|| {
+            let mut out = [0u8; 32];
+            argon.hash_password_into(b"correct horse battery staple", &salt, &mut out).unwrap();
+            out
+        }));
+    }
+    group.bench_function("hash_password_with_salt", |b| b.iter(|| crypto::hash_password_with_salt(b"correct horse battery staple", &salt)));
+    group.finish();
+}
+
+fn bench_chacha20(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chacha20poly1305");
+    let key = crypto::generate_ephemeral_key();
+    for size in MESSAGE_SIZES {
+        let message = vec![0x42u8; *size];
+        let encrypted = crypto::encrypt_message(&message, &key).unwrap();
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::new("encrypt", size), &message, |b, message| b.iter(|| crypto::encrypt_message(message, &key).unwrap()));
+        group.bench_with_input(BenchmarkId::new("decrypt", size), &encrypted, |b, encrypted| b.iter(|| crypto::decrypt_message(&key, encrypted).unwrap()));
+        let mut buffer = Vec::new();
+        group.bench_with_input(BenchmarkId::new("encrypt_into", size), &message, |b, message| b.iter(|| crypto::encrypt_message_into(message, &key, &mut buffer).unwrap()));
+        let encoded = encrypted.encode();
+        group.bench_with_input(BenchmarkId::new("decrypt_into", size), &encoded, |b, encoded| b.iter(|| crypto::decrypt_message_into(&key, encoded, &mut buffer).unwrap()));
+    }
+    group.finish();
+}
+
+fn bench_blsag(c: &mut Criterion) {
+    let mut group = c.benchmark_group("blsag");
+    let message = vec![0x42u8; 256];
+    for ring_size in RING_SIZES {
+        let private_key = Scalar::random(&mut OsRng);
+        let mut ring: Vec<RistrettoPoint> = (1..*ring_size).map(|_| RistrettoPoint::random(&mut OsRng)).collect();
+        ring.push(private_key * RISTRETTO_BASEPOINT_POINT);
+        let signature = crypto::sign_message(&private_key, ring.len() - 1, &ring, &message);
+        group.bench_with_input(BenchmarkId::new("sign", ring_size), &ring, |b, ring| b.iter(|| crypto::sign_message(&private_key, ring.len() - 1, ring, &message)));
+        group.bench_with_input(BenchmarkId::new("verify", ring_size), &ring, |b, ring| b.iter(|| crypto::verify_message(&signature, ring, &message)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_argon2, bench_chacha20, bench_blsag);
+criterion_main!(benches);
@@ -0,0 +1,10 @@
+#![no_main]
+
+use anonymous_conference_client::message_codec::ClientToClientMessage;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = ClientToClientMessage::decode(data) {
+        assert_eq!(message.encode(), data);
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use anonymous_conference_client::crypto::EncryptionResult;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(result) = EncryptionResult::decode(data) {
+        assert_eq!(result.encode(), data);
+    }
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use anonymous_conference_client::codec::decode_server_event;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_server_event(data);
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use anonymous_conference_client::message_codec::SignedMessage;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // the first byte selects the ring size
+    if let Some((ring_size, signed_message)) = data.split_first() {
+        let _ = SignedMessage::decode(signed_message, (*ring_size % 16) as usize + 1);
+    }
+});
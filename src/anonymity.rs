@@ -0,0 +1,98 @@
+//! How well a conference hides who sent a message, shown per conference by the UIs.
+//!
+//! Messages are signed with a ring signature over the public keys of all members, so a message can only be traced
+//! to the ring. The own key pair is generated when joining and dropped when leaving, but the key image is the same
+//! in every signature made with it, so the own messages in a conference can be linked to each other.
+//! In the unlinkable mode a new key pair is used after every key exchange, see [`Linkability`].
+
+use std::fmt;
+
+/// Below this ring size a sender can often be guessed from what they write
+pub const MIN_GOOD_RING_SIZE: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnonymityLevel {
+    /// Alone in the ring, or the only other member knows who wrote every message
+    Exposed,
+    /// A small ring, or a key that is reused across conferences
+    Weak,
+    Good,
+}
+
+impl fmt::Display for AnonymityLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnonymityLevel::Exposed => write!(f, "exposed"),
+            AnonymityLevel::Weak => write!(f, "weak"),
+            AnonymityLevel::Good => write!(f, "good"),
+        }
+    }
+}
+
+/// Which of the own messages peers can tell apart as coming from the same sender
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Linkability {
+    /// one key pair for the whole stay in the conference, all own messages share a key image
+    Conference,
+    /// a new key pair at every key exchange, only the messages between two key exchanges share a key image
+    KeyExchange,
+}
+
+/// The anonymity set of the own messages in a conference
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anonymity {
+    /// members whose keys are in the ring, including the own key
+    pub ring_size: usize,
+    /// the own key is only used in this conference and dropped when leaving it
+    pub ephemeral_key: bool,
+    pub linkability: Linkability,
+    /// nothing but the key exchange is sent to the conference, there are no own messages to trace
+    pub listen_only: bool,
+}
+
+impl Anonymity {
+    /// The anonymity of a ring of `ring_size` keys, with the own key only used in this conference if `ephemeral_key`
+    /// and replaced at every key exchange if `unlinkable`
+    pub fn of_ring(ring_size: usize, ephemeral_key: bool, unlinkable: bool, listen_only: bool) -> Anonymity {
+        let linkability = if unlinkable { Linkability::KeyExchange } else { Linkability::Conference };
+        Anonymity { ring_size, ephemeral_key, linkability, listen_only }
+    }
+
+    pub fn level(&self) -> AnonymityLevel {
+        if self.ring_size <= 2 {
+            AnonymityLevel::Exposed
+        } else if self.ring_size < MIN_GOOD_RING_SIZE || !self.ephemeral_key {
+            AnonymityLevel::Weak
+        } else {
+            AnonymityLevel::Good
+        }
+    }
+
+    /// One line for the conference info
+    pub fn summary(&self) -> String {
+        let key = if self.ephemeral_key { "new key for this conference" } else { "key reused across conferences" };
+        let linkable = match self.linkability {
+            _ if self.listen_only => "listening only, no messages are sent",
+            Linkability::Conference => "your messages can be linked to each other",
+            Linkability::KeyExchange => "your messages can only be linked until the next key exchange",
+        };
+        format!("{} anonymity: hidden among {} members, {}, {}", self.level(), self.ring_size, key, linkable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anonymity_level() {
+        assert_eq!(Anonymity::of_ring(1, true, false, false).level(), AnonymityLevel::Exposed);
+        assert_eq!(Anonymity::of_ring(2, true, true, false).level(), AnonymityLevel::Exposed);
+        assert_eq!(Anonymity::of_ring(3, true, false, false).level(), AnonymityLevel::Weak);
+        assert_eq!(Anonymity::of_ring(MIN_GOOD_RING_SIZE, true, false, false).level(), AnonymityLevel::Good);
+        assert_eq!(Anonymity::of_ring(20, false, false, false).level(), AnonymityLevel::Weak);
+        assert!(Anonymity::of_ring(3, true, false, false).summary().starts_with("weak anonymity: hidden among 3 members"));
+        assert!(Anonymity::of_ring(3, true, true, false).summary().ends_with("until the next key exchange"));
+        assert!(Anonymity::of_ring(3, true, true, true).summary().ends_with("listening only, no messages are sent"));
+    }
+}
@@ -0,0 +1,284 @@
+//! Building blocks for automated clients.
+//!
+//! ```no_run
+//! # async fn run() {
+//! use anonymous_conference_client::bot::Bot;
+//!
+//! Bot::new("localhost:7667")
+//!     .join(1234, "password")
+//!     .on_message(|ctx, message| {
+//!         if message.text == "ping" {
+//!             ctx.reply("pong");
+//!         }
+//!     })
+//!     .run()
+//!     .await
+//!     .unwrap();
+//! # }
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use log::{debug, info, warn};
+use crate::{
+    config::Config,
+    connection_manager,
+    constants::{ConferenceId, MessageID, Peer, Result, Sender, UIAction, UIEvent},
+    password_source::PasswordSource,
+    runtime,
+    state_manager,
+};
+
+const DEFAULT_MIN_SEND_INTERVAL: Duration = Duration::from_millis(500);
+const DEFAULT_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A text message received by the bot
+pub struct BotMessage {
+    pub conference_id: ConferenceId,
+    pub text: String,
+    pub signature_valid: bool,
+    pub peer: Peer,
+}
+
+/// Passed to the bot callbacks to queue messages
+pub struct BotContext {
+    conference_id: ConferenceId,
+    outbox: Vec<(ConferenceId, String)>,
+}
+
+impl BotContext {
+    /// The conference the callback was called for
+    pub fn conference_id(&self) -> ConferenceId {
+        self.conference_id
+    }
+
+    /// Send a message to the conference the callback was called for
+    pub fn reply(&mut self, text: impl Into<String>) {
+        self.outbox.push((self.conference_id, text.into()));
+    }
+
+    /// Send a message to any conference the bot is a part of
+    pub fn send(&mut self, conference_id: ConferenceId, text: impl Into<String>) {
+        self.outbox.push((conference_id, text.into()));
+    }
+}
+
+type MessageHandler = Box<dyn FnMut(&mut BotContext, &BotMessage) + Send>;
+type ReadyHandler = Box<dyn FnMut(&mut BotContext) + Send>;
+
+pub struct Bot {
+    server_address: String,
+    conferences: Vec<(ConferenceId, String)>,
+    message_handler: Option<MessageHandler>,
+    ready_handler: Option<ReadyHandler>,
+    min_send_interval: Duration,
+    reconnect_delay: Duration,
+    max_reconnects: Option<usize>,
+    /// see [`Bot::wait_for_server`]
+    wait_for_server: Option<Option<Duration>>,
+}
+
+impl Bot {
+    pub fn new(server_address: impl Into<String>) -> Bot {
+        Bot {
+            server_address: server_address.into(),
+            conferences: Vec::new(),
+            message_handler: None,
+            ready_handler: None,
+            min_send_interval: DEFAULT_MIN_SEND_INTERVAL,
+            reconnect_delay: DEFAULT_RECONNECT_DELAY,
+            max_reconnects: None,
+            wait_for_server: None,
+        }
+    }
+
+    /// Join the conference on every (re)connect
+    pub fn join(mut self, conference_id: ConferenceId, password: impl Into<String>) -> Bot {
+        self.conferences.push((conference_id, password.into()));
+        self
+    }
+
+    /// Like [`Bot::join`], with the password read once from `password_source` when this is called,
+    /// reconnects keep using that password. Fails if the password can not be read
+    pub fn join_from(self, conference_id: ConferenceId, password_source: &PasswordSource) -> Result<Bot> {
+        let password = password_source.read()?;
+        Ok(self.join(conference_id, password))
+    }
+
+    /// Called for every incoming text message
+    pub fn on_message(mut self, handler: impl FnMut(&mut BotContext, &BotMessage) + Send + 'static) -> Bot {
+        self.message_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Called every time a conference finished its setup and messages can be sent
+    pub fn on_ready(mut self, handler: impl FnMut(&mut BotContext) + Send + 'static) -> Bot {
+        self.ready_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Minimum time between two messages sent by the bot
+    pub fn min_send_interval(mut self, interval: Duration) -> Bot {
+        self.min_send_interval = interval;
+        self
+    }
+
+    pub fn reconnect_delay(mut self, delay: Duration) -> Bot {
+        self.reconnect_delay = delay;
+        self
+    }
+
+    /// Give up after this many reconnects, the default is to reconnect forever
+    pub fn max_reconnects(mut self, max_reconnects: usize) -> Bot {
+        self.max_reconnects = Some(max_reconnects);
+        self
+    }
+
+    /// Keep trying to reach the server before the first connection, forever without a timeout
+    pub fn wait_for_server(mut self, timeout: Option<Duration>) -> Bot {
+        self.wait_for_server = Some(timeout);
+        self
+    }
+
+    /// Run the bot until the reconnect limit is reached
+    pub async fn run(mut self) -> Result<()> {
+        if let Some(timeout) = self.wait_for_server {
+            connection_manager::wait_for_server(&Config::load().dns, &self.server_address, timeout, |attempts, waited, e| {
+                info!("Bot is waiting for {} ({} attempts, {} seconds): {}", self.server_address, attempts, waited.as_secs(), e);
+            }).await?;
+        }
+        let mut reconnects = 0;
+        loop {
+            self.run_session().await;
+            if self.max_reconnects.is_some_and(|max_reconnects| reconnects >= max_reconnects) {
+                return Err("Disconnected from the server, reconnect limit reached".into());
+            }
+            reconnects += 1;
+            info!("Bot disconnected, reconnecting in {:?}", self.reconnect_delay);
+            runtime::sleep(self.reconnect_delay).await;
+        }
+    }
+
+    /// Run a single connection to the server until it is closed
+    async fn run_session(&mut self) {
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let (mut ui_action_sender, ui_action_receiver) = mpsc::unbounded();
+        let server_address = self.server_address.clone();
+        runtime::spawn(async move {
+            state_manager::start_state_manager(server_address, ui_event_sender, ui_action_receiver).await;
+        });
+
+        for (conference_id, password) in self.conferences.iter() {
+            if ui_action_sender.send(UIAction::JoinConference((*conference_id, password.clone()))).await.is_err() {
+                return;
+            }
+        }
+
+        let mut session = Session {
+            ui_action_sender,
+            ready_conferences: HashSet::new(),
+            queued_messages: HashMap::new(),
+            last_message_id: 0,
+            last_send: None,
+            min_send_interval: self.min_send_interval,
+        };
+
+        while let Some(ui_event) = ui_event_receiver.next().await {
+            match ui_event {
+                UIEvent::ConferenceJoined((conference_id, number_of_peers)) => {
+                    info!("Bot joined conference {} ({} peers)", conference_id, number_of_peers);
+                },
+                UIEvent::ConferenceJoinFailed(conference_id) => {
+                    warn!("Bot could not join conference {}", conference_id);
+                },
+                UIEvent::ConferenceLeft(conference_id) => {
+                    session.ready_conferences.remove(&conference_id);
+                    session.queued_messages.remove(&conference_id);
+                },
+                UIEvent::ConferenceRestructuring((conference_id, _)) => {
+                    session.ready_conferences.remove(&conference_id);
+                },
+                UIEvent::ConferenceRestructuringFinished(conference_id) => {
+                    debug!("Bot is ready to send messages in conference {}", conference_id);
+                    session.ready_conferences.insert(conference_id);
+                    let mut ctx = BotContext { conference_id, outbox: Vec::new() };
+                    if let Some(handler) = self.ready_handler.as_mut() {
+                        handler(&mut ctx);
+                    }
+                    let queued = session.queued_messages.remove(&conference_id).unwrap_or_default();
+                    session.send_all(queued.into_iter().map(|text| (conference_id, text)).chain(ctx.outbox)).await;
+                },
+                UIEvent::IncomingMessage((conference_id, message, signature_valid, peer)) => {
+                    let message = BotMessage {
+                        conference_id,
+                        text: String::from_utf8_lossy(&message).to_string(),
+                        signature_valid,
+                        peer,
+                    };
+                    let mut ctx = BotContext { conference_id, outbox: Vec::new() };
+                    if let Some(handler) = self.message_handler.as_mut() {
+                        handler(&mut ctx, &message);
+                    }
+                    session.send_all(ctx.outbox).await;
+                },
+                UIEvent::MessageRejected((conference_id, _)) | UIEvent::MessageError((conference_id, _)) | UIEvent::MessageTimedOut((conference_id, _))
+                | UIEvent::MessageTooLarge((conference_id, _, _)) => {
+                    warn!("Bot message to conference {} was not delivered", conference_id);
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+/// State of a single connection to the server
+struct Session {
+    ui_action_sender: Sender<UIAction>,
+    ready_conferences: HashSet<ConferenceId>,
+    queued_messages: HashMap<ConferenceId, Vec<String>>,
+    last_message_id: MessageID,
+    last_send: Option<Instant>,
+    min_send_interval: Duration,
+}
+
+impl Session {
+    /// Send messages to ready conferences, queue the rest until their setup is finished
+    async fn send_all(&mut self, messages: impl IntoIterator<Item = (ConferenceId, String)>) {
+        for (conference_id, text) in messages {
+            if !self.ready_conferences.contains(&conference_id) {
+                self.queued_messages.entry(conference_id).or_default().push(text);
+                continue;
+            }
+            if let Some(last_send) = self.last_send {
+                let elapsed = last_send.elapsed();
+                if elapsed < self.min_send_interval {
+                    runtime::sleep(self.min_send_interval - elapsed).await;
+                }
+            }
+            self.last_send = Some(Instant::now());
+            self.last_message_id += 1;
+            if self.ui_action_sender.send(UIAction::SendMessage((conference_id, self.last_message_id, text))).await.is_err() {
+                warn!("Bot could not send message, state manager has exited");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bot_context_reply() {
+        let mut ctx = BotContext { conference_id: 3, outbox: Vec::new() };
+        ctx.reply("hello");
+        ctx.send(4, "world");
+        assert_eq!(ctx.conference_id(), 3);
+        assert_eq!(ctx.outbox, vec![(3, "hello".to_string()), (4, "world".to_string())]);
+    }
+}
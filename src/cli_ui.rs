@@ -1,54 +1,205 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
 
-use async_std::{io::BufReader, task};
-use async_std::prelude::*;
 use futures::channel::mpsc;
-use futures::{select, FutureExt, SinkExt};
+use futures::io::{AsyncBufReadExt, BufReader};
+use futures::{select, FutureExt, SinkExt, StreamExt};
 
 use crate::constants::MessageID;
 use crate::{
-    state_manager,
+    anonymity::Anonymity,
+    config::{Config, StorageConfig, TerminalNotification},
+    connection_manager::format_message_size,
+    device_link::{self, LinkBundle, LinkedConference},
+    file_transfer::{self, Direction},
+    history_store::RetentionPolicy,
+    invite::Invite,
+    multi_server,
+    password_source::PasswordSource,
+    peer_labels,
+    recent::Recent,
+    runtime,
+    terminal_notification::{self, Multiplexer},
+    update_check,
     constants::{
         Receiver,
         Sender,
         UIAction,
         UIEvent,
         ConferenceId,
+        KeyImage,
+        Migration,
+        DisconnectReason,
+        MigrationStage,
+        Peer,
+        PacketNonce,
     },
 };
 
+/// How many of the last sent messages are kept to show read receipts
+const DELIVERED_MESSAGES_KEPT: MessageID = 100;
+/// Ends a message composed with `/paste`
+const PASTE_END: &str = "/end";
+
+/// A line of output after its sender tag, `SYSTEM`, `YOU` or the name of a peer, and empty for
+/// output without a tag like a QR code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaggedLine {
+    pub tag: String,
+    pub text: String,
+}
+
+/// A multi-line message being typed
+struct Compose {
+    lines: Vec<String>,
+    /// started with `/paste` and sent with `/end`, otherwise continued by lines ending with a backslash
+    paste: bool,
+}
+
 #[allow(non_camel_case_types)]
 pub struct CLII_UI {
+    /// the server new conferences are created and joined on
+    server_address: String,
+    /// all servers connected to, see [`crate::multi_server`]
+    servers: Vec<String>,
+    /// the server hosting the current conference
+    conference_server: Option<String>,
     ui_event_receiver: Receiver<UIEvent>,
     ui_action_sender: Sender<UIAction>,
     conference_id: Option<ConferenceId>,
+    /// password of the current conference, for creating invites
+    conference_password: Option<String>,
+    /// password of the conference being joined, it becomes the conference password once the join succeeded
+    join_password: Option<String>,
     sent_messages: HashMap<MessageID, String>,
+    /// messages accepted by the server, to show read receipts
+    delivered_messages: HashMap<MessageID, String>,
+    /// peers that sent messages in this session, by fingerprint
+    known_peers: HashMap<String, Peer>,
     last_message_id: MessageID,
     can_send_messages: bool,
+    /// the current conference is left to join it again, the password is kept
+    rejoining: bool,
+    /// where a peer moved the current conference to, for `/follow`
+    announced_migration: Option<Migration>,
+    /// where `/join` without a password reads it from
+    password_source: Option<PasswordSource>,
+    /// the current conference was joined with `/join --listen`, nothing is sent to it
+    listen_only: bool,
+    /// servers and conferences used before, listed by `/recent`
+    recent: Recent,
+    /// whether the files of the data directory are encrypted, for the peer labels sent by `/link`
+    storage: StorageConfig,
+    /// show how long sent messages took to be accepted and seen
+    verbose: bool,
+    notification: TerminalNotification,
+    multiplexer: Option<Multiplexer>,
+    /// conferences muted with `/bell off`
+    notification_muted: HashSet<ConferenceId>,
+    /// connect again on the next input after disconnecting for inactivity
+    reconnect_on_activity: bool,
+    /// disconnected for inactivity, see [`crate::idle::IdleSession`]
+    idle_disconnected: bool,
+    /// last anonymity set shown per conference
+    anonymity: HashMap<ConferenceId, Anonymity>,
+    /// how long the messages are kept on disk, only known with `history.persist`
+    retention: HashMap<ConferenceId, RetentionPolicy>,
+    /// joins and creates that `/cancel` abandons, `None` for a create
+    pending_requests: HashMap<PacketNonce, Option<ConferenceId>>,
+    compose: Option<Compose>,
+    /// where the output goes instead of stdout, see [`crate::tui`]
+    output: Option<Sender<TaggedLine>>,
+    /// where the notification sequences go instead of stdout, written by [`crate::tui`] between frames
+    notification_output: Option<Sender<String>>,
 }
 
 impl CLII_UI {
-    pub fn new(server_address: String) -> Self {
+    pub fn new(server_address: String, config: &Config) -> Self {
         let (ui_event_sender, ui_event_receiver) = mpsc::unbounded();
         let (ui_action_sender, ui_action_receiver) = mpsc::unbounded();
-        
-        // start state manager
-        task::spawn(async move {
-            state_manager::start_state_manager(server_address, ui_event_sender, ui_action_receiver).await;
-        });
+
+        update_check::spawn_update_check(&config.update_check, ui_event_sender.clone());
+
+        let servers = config.servers.addresses(&server_address);
+        spawn_state_managers(servers.clone(), ui_event_sender, ui_action_receiver);
 
         Self {
+            server_address,
+            servers,
+            conference_server: None,
             ui_event_receiver,
             ui_action_sender,
             conference_id: None,
+            conference_password: None,
+            join_password: None,
             sent_messages: HashMap::new(),
+            delivered_messages: HashMap::new(),
+            known_peers: HashMap::new(),
             last_message_id: 0,
             can_send_messages: false,
+            rejoining: false,
+            announced_migration: None,
+            password_source: None,
+            listen_only: false,
+            recent: Recent::load(&config.recent, &config.storage),
+            storage: config.storage.clone(),
+            verbose: false,
+            notification: config.cli.notification,
+            multiplexer: Multiplexer::detect(),
+            notification_muted: HashSet::new(),
+            reconnect_on_activity: config.idle.reconnect_on_activity,
+            idle_disconnected: false,
+            anonymity: HashMap::new(),
+            retention: HashMap::new(),
+            pending_requests: HashMap::new(),
+            compose: None,
+            output: None,
+            notification_output: None,
         }
     }
 
+    /// Let `/join` read the password from a file or an environment variable when it is left out
+    pub fn with_password_source(mut self, password_source: Option<PasswordSource>) -> Self {
+        self.password_source = password_source;
+        self
+    }
+
+    /// Show the latency of sent messages
+    pub fn with_verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Send the output and the notifications to the full screen interface instead of printing them
+    pub fn with_output(mut self, output: Sender<TaggedLine>, notification_output: Sender<String>) -> Self {
+        self.output = Some(output);
+        self.notification_output = Some(notification_output);
+        self
+    }
+
+    /// Start new state managers after disconnecting for inactivity, the conferences are not joined again
+    fn reconnect(&mut self) {
+        let (ui_event_sender, ui_event_receiver) = mpsc::unbounded();
+        let (ui_action_sender, ui_action_receiver) = mpsc::unbounded();
+        spawn_state_managers(self.servers.clone(), ui_event_sender, ui_action_receiver);
+        self.ui_event_receiver = ui_event_receiver;
+        self.ui_action_sender = ui_action_sender;
+        self.idle_disconnected = false;
+        self.conference_id = None;
+        self.conference_server = None;
+        self.can_send_messages = false;
+        self.listen_only = false;
+        self.rejoining = false;
+        self.announced_migration = None;
+        self.sent_messages.clear();
+        self.delivered_messages.clear();
+        self.pending_requests.clear();
+        self.print_system("Connecting again");
+    }
+
     pub async fn start_ui(&mut self) {
-        let mut lines_from_stdin = BufReader::new(async_std::io::stdin()).lines().fuse();
+        let mut lines_from_stdin = BufReader::new(runtime::stdin()).lines().fuse();
 
         loop {
             select! {
@@ -69,11 +220,39 @@ impl CLII_UI {
         }
     }
 
-    async fn process_input(&mut self, input: String) {
+    pub(crate) async fn next_ui_event(&mut self) -> Option<UIEvent> {
+        self.ui_event_receiver.next().await
+    }
+
+    pub(crate) async fn process_input(&mut self, input: String) {
+        if let Some(compose) = self.compose.as_mut() {
+            // the lines are kept as typed, including empty ones and leading slashes
+            if compose.paste {
+                if input.trim() != PASTE_END {
+                    compose.lines.push(input);
+                    return;
+                }
+            } else if let Some(line) = input.strip_suffix('\\') {
+                compose.lines.push(line.to_string());
+                return;
+            } else {
+                compose.lines.push(input);
+            }
+            let message = self.compose.take().unwrap().lines.join("\n");
+            if !message.trim().is_empty() {
+                self.send_text(message).await;
+            }
+            return;
+        }
         let input = input.trim();
         if input.is_empty() {
             return;
         }
+        if self.idle_disconnected && self.reconnect_on_activity {
+            self.reconnect();
+        }
+        // the state managers are gone after disconnecting
+        let _ = self.ui_action_sender.send(UIAction::UserActive).await;
 
         if let Some(input) = input.strip_prefix('/') {
             // command
@@ -89,20 +268,105 @@ impl CLII_UI {
                     self.ui_action_sender.send(UIAction::CreateConference(password)).await.unwrap();
                 },
                 "join" => {
-                    // join conference
+                    // join conference, optionally without ever sending to it
                     if self.conference_id.is_some() {
                         self.print_system("You are already in a conference. Leave it first.");
                         return;
                     }
-                    if words.len() != 3 {
-                        self.print_system("Usage: /join <conference id> <conference password>");
+                    let listen_only = words.get(1) == Some(&"--listen");
+                    let words = if listen_only { [&words[..1], &words[2..]].concat() } else { words };
+                    if words.len() != 3 && (words.len() != 2 || self.password_source.is_none()) {
+                        self.print_system("Usage: /join [--listen] <conference id> <conference password>");
                         return;
                     }
                     let Ok(conference_id) = words[1].to_string().parse()
                     else { self.print_system("Invalid conference id"); return; };
-                    let password = words[2].to_string();
+                    let password = match (words.get(2), self.password_source.as_ref()) {
+                        (Some(password), _) => password.to_string(),
+                        (None, Some(password_source)) => match password_source.read() {
+                            Ok(password) => password,
+                            Err(e) => { self.print_system(e.to_string().as_str()); return; },
+                        },
+                        (None, None) => return,
+                    };
+                    self.join_password = Some(password.clone());
+                    self.listen_only = listen_only;
                     self.ui_action_sender.send(UIAction::JoinConference((conference_id, password))).await.unwrap();
                 },
+                "invite" => {
+                    // create an invite for the current conference
+                    let (Some(conference_id), Some(password)) = (self.conference_id, self.conference_password.as_ref())
+                    else { self.print_system("You are not in a conference."); return; };
+                    if words.len() > 2 {
+                        self.print_system("Usage: /invite [passphrase to include the password]");
+                        return;
+                    }
+                    let invite = Invite::new(self.current_server().to_string(), conference_id);
+                    let invite = match words.get(1) {
+                        Some(passphrase) => match invite.with_password(password, passphrase) {
+                            Ok(invite) => invite,
+                            Err(e) => { self.print_system(format!("Could not create invite: {}", e).as_str()); return; },
+                        },
+                        None => invite,
+                    };
+                    match invite.encode() {
+                        Ok(invite) => self.print_system(format!("Invite: {}", invite).as_str()),
+                        Err(e) => self.print_system(format!("Could not create invite: {}", e).as_str()),
+                    }
+                },
+                "accept" => {
+                    // join a conference from an invite
+                    if self.conference_id.is_some() {
+                        self.print_system("You are already in a conference. Leave it first.");
+                        return;
+                    }
+                    if words.len() != 3 {
+                        self.print_system("Usage: /accept <invite> <invite passphrase or conference password>");
+                        return;
+                    }
+                    let invite = match words[1].parse::<Invite>() {
+                        Ok(invite) => invite,
+                        Err(e) => { self.print_system(format!("Invalid invite: {}", e).as_str()); return; },
+                    };
+                    if !self.select_server(&invite.server_address).await {
+                        self.print_system(format!("The invite is for a server that is not configured: {}", invite.server_address).as_str());
+                        return;
+                    }
+                    let password = match invite.password(words[2]) {
+                        Ok(Some(password)) => password,
+                        Ok(None) => words[2].to_string(),
+                        Err(e) => { self.print_system(format!("Invalid invite: {}", e).as_str()); return; },
+                    };
+                    self.join_password = Some(password.clone());
+                    self.ui_action_sender.send(UIAction::JoinConference((invite.conference_id, password))).await.unwrap();
+                },
+                "paste" => {
+                    // compose a multi-line message
+                    if words.len() != 1 {
+                        self.print_system("Usage: /paste, then the message and /end on a line of its own");
+                        return;
+                    }
+                    self.print_system("Composing a multi-line message, end it with /end on a line of its own");
+                    self.compose = Some(Compose { lines: Vec::new(), paste: true });
+                },
+                "cancel" => {
+                    // abandon the pending joins and creates
+                    if self.pending_requests.is_empty() {
+                        self.print_system("Nothing to cancel");
+                        return;
+                    }
+                    for packet_nonce in self.pending_requests.keys().copied().collect::<Vec<_>>() {
+                        self.ui_action_sender.send(UIAction::CancelRequest(packet_nonce)).await.unwrap();
+                    }
+                },
+                "link" => {
+                    // share the conference and peer labels with another device, or import them
+                    match words.len() {
+                        1 => self.export_device_link(),
+                        3 => self.import_device_link(words[1], &words[2..].join("")).await,
+                        _ => self.print_system("Usage: /link [<link code> <authentication string>]"),
+                    }
+                },
                 "leave" => {
                     // leave conference
                     if self.conference_id.is_none() {
@@ -111,6 +375,234 @@ impl CLII_UI {
                     }
                     self.ui_action_sender.send(UIAction::LeaveConference(self.conference_id.unwrap())).await.unwrap();
                 },
+                "rejoin" => {
+                    // leave and join the current conference again
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    self.rejoining = true;
+                    self.ui_action_sender.send(UIAction::RejoinConference(conference_id)).await.unwrap();
+                },
+                "move" => {
+                    // move the current conference to another server
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    if words.len() != 2 {
+                        self.print_system("Usage: /move <server address>");
+                        return;
+                    }
+                    self.ui_action_sender.send(UIAction::MigrateConference((conference_id, words[1].to_string()))).await.unwrap();
+                },
+                "follow" => {
+                    // join the conference a peer moved the current conference to
+                    let (Some(conference_id), Some(migration)) = (self.conference_id, self.announced_migration.take())
+                    else { self.print_system("The conference was not moved."); return; };
+                    self.conference_password = Some(migration.password.clone());
+                    self.ui_action_sender.send(UIAction::FollowMigration((conference_id, migration))).await.unwrap();
+                },
+                "label" => {
+                    // name a peer locally
+                    if words.len() < 2 {
+                        self.print_system("Usage: /label <fingerprint or name> [new name, empty to remove]");
+                        return;
+                    }
+                    let Some(key_image) = self.find_peer(words[1])
+                    else { self.print_system(format!("Unknown peer: {}", words[1]).as_str()); return; };
+                    let label = Some(words[2..].join(" ")).filter(|label| !label.is_empty());
+                    for peer in self.known_peers.values_mut().filter(|peer| peer.key_image == key_image) {
+                        peer.label = label.clone();
+                    }
+                    self.ui_action_sender.send(UIAction::SetPeerLabel((key_image, label))).await.unwrap();
+                },
+                "breakout" => {
+                    // create a sub-conference and announce it in the current conference
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    if words.len() < 2 {
+                        self.print_system("Usage: /breakout <topic>");
+                        return;
+                    }
+                    self.ui_action_sender.send(UIAction::CreateBreakout((conference_id, words[1..].join(" ")))).await.unwrap();
+                },
+                "unmute" => {
+                    // show the messages of a sender muted for flooding
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    if words.len() != 2 {
+                        self.print_system("Usage: /unmute <fingerprint or name>");
+                        return;
+                    }
+                    let Some(key_image) = self.find_peer(words[1])
+                    else { self.print_system(format!("Unknown peer: {}", words[1]).as_str()); return; };
+                    self.ui_action_sender.send(UIAction::AllowFlooding((conference_id, key_image))).await.unwrap();
+                },
+                "receipts" => {
+                    // enable or disable read receipts
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    let enabled = match words.get(1) {
+                        Some(&"on") => true,
+                        Some(&"off") => false,
+                        _ => { self.print_system("Usage: /receipts <on|off>"); return; },
+                    };
+                    self.ui_action_sender.send(UIAction::SetReadReceipts((conference_id, enabled))).await.unwrap();
+                    if enabled {
+                        self.print_system("Read receipts enabled, other peers will see which messages you have read");
+                        self.ui_action_sender.send(UIAction::MessagesDisplayed(conference_id)).await.unwrap();
+                    } else {
+                        self.print_system("Read receipts disabled");
+                    }
+                },
+                "unlinkable" => {
+                    // sign with a new key after every key exchange
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    let enabled = match words.get(1) {
+                        Some(&"on") => true,
+                        Some(&"off") => false,
+                        _ => { self.print_system("Usage: /unlinkable <on|off>"); return; },
+                    };
+                    self.ui_action_sender.send(UIAction::SetUnlinkable((conference_id, enabled))).await.unwrap();
+                    if enabled {
+                        self.print_system("Unlinkable mode enabled, you get a new key at every key exchange. \
+                            Peers can no longer tell that your messages before and after it come from the same sender, \
+                            names they gave you and your read receipts do not carry over.");
+                    } else {
+                        self.print_system("Unlinkable mode disabled from the next key exchange on, \
+                            your messages can be linked to each other again");
+                    }
+                },
+                "listen" => {
+                    // toggle listen only mode
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    let enabled = match words.get(1) {
+                        Some(&"on") => true,
+                        Some(&"off") => false,
+                        _ => { self.print_system("Usage: /listen <on|off>"); return; },
+                    };
+                    self.listen_only = enabled;
+                    self.ui_action_sender.send(UIAction::SetListenOnly((conference_id, enabled))).await.unwrap();
+                    if enabled {
+                        self.print_system("Listen only, nothing is sent to the conference");
+                    } else {
+                        self.print_system("Listen only disabled, you can send messages again");
+                    }
+                },
+                "sendfile" => {
+                    // send a file to the current conference, the path may contain spaces
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    let Some(path) = input.split_once(' ').map(|(_, path)| path.trim()).filter(|path| !path.is_empty())
+                    else { self.print_system("Usage: /sendfile <path>"); return; };
+                    if self.listen_only {
+                        self.print_system("Listen only, the file was not sent. Use /listen off to send messages");
+                        return;
+                    }
+                    self.last_message_id += 1;
+                    let message_id = self.last_message_id;
+                    self.ui_action_sender.send(UIAction::SendFile((conference_id, message_id, PathBuf::from(path)))).await.unwrap();
+                    let description = format!("file {}", path);
+                    self.print_you(format!("(#{} sending) {}", message_id, description).as_str());
+                    self.sent_messages.insert(message_id, description);
+                },
+                "retention" => {
+                    // how long the messages of the current conference are kept on disk
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    let Some(current) = self.retention.get(&conference_id)
+                    else { self.print_system("Messages are not kept on disk, enable history.persist to keep them"); return; };
+                    let Some(policy) = words.get(1)
+                    else {
+                        self.print_system(format!("{} ({})", current.description(), current).as_str());
+                        return;
+                    };
+                    match policy.parse::<RetentionPolicy>() {
+                        Ok(policy) => self.ui_action_sender.send(UIAction::SetRetentionPolicy((conference_id, policy))).await.unwrap(),
+                        Err(e) => self.print_system(format!("{}. Usage: /retention [forever|never|until-leave|<days>d]", e).as_str()),
+                    }
+                },
+                "idle" => {
+                    // keep the current conference although nobody writes, or leave it when idle again
+                    let Some(conference_id) = self.conference_id
+                    else { self.print_system("You are not in a conference."); return; };
+                    let enabled = match words.get(1) {
+                        Some(&"on") => true,
+                        Some(&"off") => false,
+                        _ => { self.print_system("Usage: /idle <on|off>"); return; },
+                    };
+                    self.ui_action_sender.send(UIAction::SetAutoLeave((conference_id, enabled))).await.unwrap();
+                    if enabled {
+                        self.print_system("The conference is left when no messages are sent or received for a while");
+                    } else {
+                        self.print_system("The conference is kept even when nobody writes");
+                    }
+                },
+                "recent" => {
+                    // list recently used conferences and servers to join or select them again
+                    if self.recent.conferences().is_empty() {
+                        self.print_system("No recent conferences");
+                        return;
+                    }
+                    self.print_system("Recent conferences (/join <id>):");
+                    for conference_id in self.recent.conferences() {
+                        self.print_system(format!("  {}", conference_id).as_str());
+                    }
+                    self.print_system("Recent servers (/server <address>):");
+                    for server in self.recent.servers() {
+                        self.print_system(format!("  {}", server).as_str());
+                    }
+                },
+                "bell" => {
+                    // mute or unmute notifications of incoming messages for the current conference
+                    let Some(conference_id) = self.conference_id else {
+                        self.print_system("You are not in a conference.");
+                        return;
+                    };
+                    match words.get(1) {
+                        Some(&"on") => {
+                            self.notification_muted.remove(&conference_id);
+                            self.print_system("Notifications for incoming messages enabled");
+                        },
+                        Some(&"off") => {
+                            self.notification_muted.insert(conference_id);
+                            self.print_system("Notifications for incoming messages muted");
+                        },
+                        _ => self.print_system("Usage: /bell <on|off>"),
+                    }
+                    if self.notification == TerminalNotification::None {
+                        self.print_system("Notifications are disabled, set cli.notification to \"bell\" or \"osc777\" in the config file");
+                    }
+                },
+                "browse" => {
+                    // list public conferences
+                    self.ui_action_sender.send(UIAction::ListConferences).await.unwrap();
+                },
+                "server" => {
+                    // list the servers or select the one to create and join conferences on
+                    match words.get(1) {
+                        None => {
+                            for server in &self.servers {
+                                let marker = if *server == self.server_address { "*" } else { " " };
+                                self.print_system(format!("{} {}", marker, server).as_str());
+                            }
+                        },
+                        Some(server) => {
+                            if self.select_server(server).await {
+                                self.print_system(format!("Conferences are created and joined on {}", server).as_str());
+                            } else {
+                                self.print_system(format!("Unknown server: {}, add it to [servers] in the config file", server).as_str());
+                            }
+                        },
+                    }
+                },
+                "scripts" => {
+                    // list or reload scripts and plugins
+                    match words.get(1) {
+                        None | Some(&"list") => self.ui_action_sender.send(UIAction::ListPlugins).await.unwrap(),
+                        Some(&"reload") => self.ui_action_sender.send(UIAction::ReloadPlugins).await.unwrap(),
+                        _ => self.print_system("Usage: /scripts [list|reload]"),
+                    }
+                },
                 "exit" => {
                     // exit
                     self.ui_action_sender.send(UIAction::Disconnect).await.unwrap();
@@ -119,92 +611,490 @@ impl CLII_UI {
                     self.print_system(format!("Unknown command: /{}", words[0]).as_str());
                 },
             }
+        } else if let Some(line) = input.strip_suffix('\\') {
+            // continued on the next line
+            self.compose = Some(Compose { lines: vec![line.to_string()], paste: false });
         } else {
-            // text message
-            if self.conference_id.is_none() {
-                self.print_system("You are not in a conference.");
-                return;
-            }
-            self.last_message_id += 1;
-            let message_id = self.last_message_id;
-            self.ui_action_sender.send(
-                UIAction::SendMessage((self.conference_id.unwrap(), message_id, input.to_string()))
-            ).await.unwrap();
-            self.sent_messages.insert(message_id, input.to_string());
+            self.send_text(input.to_string()).await;
+        }
+    }
+
+    async fn send_text(&mut self, message: String) {
+        if self.conference_id.is_none() {
+            self.print_system("You are not in a conference.");
+            return;
+        }
+        if self.listen_only {
+            self.print_system("Listen only, the message was not sent. Use /listen off to send messages");
+            return;
         }
+        self.last_message_id += 1;
+        let message_id = self.last_message_id;
+        self.ui_action_sender.send(
+            UIAction::SendMessage((self.conference_id.unwrap(), message_id, message.clone()))
+        ).await.unwrap();
+        self.print_you(format!("(#{} sending) {}", message_id, message).as_str());
+        self.sent_messages.insert(message_id, message);
     }
 
-    async fn process_ui_event(&mut self, ui_event: UIEvent) {
+    pub(crate) async fn process_ui_event(&mut self, ui_event: UIEvent) {
         match ui_event {
             UIEvent::ConferenceCreated(conference_id) => {
+                self.pending_requests.retain(|_, pending| pending.is_some());
                 self.print_system(format!("Conference created: {}", conference_id).as_str());
             },
             UIEvent::ConferenceCreateFailed => {
+                self.pending_requests.retain(|_, pending| pending.is_some());
                 self.print_system("Failed to create conference.");
             },
             UIEvent::ConferenceJoined((conference_id, number_of_peers)) => {
+                self.pending_requests.retain(|_, pending| *pending != Some(conference_id));
                 self.print_system(format!("Joined conference: {} ({} peers)", conference_id, number_of_peers).as_str());
                 self.conference_id = Some(conference_id);
+                if let Some(password) = self.join_password.take() {
+                    self.conference_password = Some(password);
+                }
+                self.rejoining = false;
+                let server = self.current_server().to_string();
+                self.recent.used(&server, conference_id);
+                if self.listen_only {
+                    self.print_system("Listen only, nothing is sent to the conference");
+                    self.ui_action_sender.send(UIAction::SetListenOnly((conference_id, true))).await.unwrap();
+                }
             },
             UIEvent::ConferenceJoinFailed(conference_id) => {
+                self.pending_requests.retain(|_, pending| *pending != Some(conference_id));
                 self.print_system(format!("Failed to join conference: {}", conference_id).as_str());
+                self.join_password = None;
+                if self.rejoining {
+                    self.rejoining = false;
+                    self.conference_password = None;
+                }
             },
             UIEvent::ConferenceLeft(conference_id) => {
                 self.print_system(format!("Left conference: {}", conference_id).as_str());
+                self.anonymity.remove(&conference_id);
+                self.retention.remove(&conference_id);
+                // the conference it moved to may be joined already
+                if self.conference_id != Some(conference_id) {
+                    return;
+                }
                 self.conference_id = None;
+                self.conference_server = None;
+                self.announced_migration = None;
+                if !self.rejoining {
+                    self.conference_password = None;
+                    self.listen_only = false;
+                }
+                self.delivered_messages.clear();
                 self.can_send_messages = false;
             },
             UIEvent::ConferenceLeaveFailed(conference_id) => {
                 self.print_system(format!("Failed to leave conference: {}", conference_id).as_str());
             },
-            UIEvent::IncomingMessage((_, message, is_signature_valid)) => {
+            UIEvent::IncomingMessage((conference_id, message, is_signature_valid, peer)) => {
                 let message = String::from_utf8_lossy(&message);
                 if is_signature_valid {
-                    self.print_someone(format!("{}", message).as_str());
+                    self.print_someone(&peer, format!("{}", message).as_str());
+                    self.known_peers.insert(peer.fingerprint(), peer);
                 } else {
-                    self.print_someone(format!("(!invalid signature!) {}", message).as_str());
+                    self.print_someone(&peer, format!("(!invalid signature!) {}", message).as_str());
+                }
+                self.notify(conference_id, &message);
+                if self.conference_id == Some(conference_id) {
+                    // the terminal always shows the current conference
+                    self.ui_action_sender.send(UIAction::MessagesDisplayed(conference_id)).await.unwrap();
+                }
+            },
+            UIEvent::UploadProgress((_, message_id, accepted, total)) => {
+                // a line for every tenth of the chunks
+                if self.sent_messages.contains_key(&message_id) && accepted * 10 / total != accepted.saturating_sub(1) * 10 / total {
+                    self.print_you(format!("(#{} uploading, {} of {} chunks sent)", message_id, accepted, total).as_str());
+                }
+            },
+            UIEvent::FileTransferProgress((_, transfer)) => {
+                let tenth_done = transfer.chunks_done * 10 / transfer.chunks != transfer.chunks_done.saturating_sub(1) * 10 / transfer.chunks;
+                match transfer.direction {
+                    Direction::Sending(message_id) => {
+                        if self.sent_messages.contains_key(&message_id) && tenth_done {
+                            self.print_you(format!("(#{} uploading, {} of {} chunks sent)", message_id, transfer.chunks_done, transfer.chunks).as_str());
+                        }
+                    },
+                    Direction::Receiving(peer) if transfer.chunks_done == 0 => {
+                        self.print_someone(&peer, format!("(sending file {}, {})", transfer.name, file_transfer::format_file_size(transfer.size)).as_str());
+                    },
+                    Direction::Receiving(_) => {
+                        if self.verbose && tenth_done {
+                            self.print_system(format!("Receiving {}, {} of {} chunks", transfer.name, transfer.chunks_done, transfer.chunks).as_str());
+                        }
+                    },
+                }
+            },
+            UIEvent::FileReceived((conference_id, file)) => {
+                let text = format!("sent file {} ({}), saved to {}", file.name, file_transfer::format_file_size(file.size), file.path.display());
+                self.print_someone(&file.sender, &text);
+                self.notify(conference_id, &text);
+            },
+            UIEvent::FileNotSent((_, message_id, reason)) => {
+                if let Some(message) = self.sent_messages.remove(&message_id) {
+                    self.print_you(format!("(!#{} not sent, {}!) {}", message_id, reason, message).as_str());
                 }
             },
             UIEvent::MessageAccepted((_, message_id)) => {
-                if let Some(message) = self.sent_messages.get(&message_id) {
-                    self.print_you(message);
-                    self.sent_messages.remove(&message_id);
+                if let Some(message) = self.sent_messages.remove(&message_id) {
+                    self.print_you(format!("(#{} sent)", message_id).as_str());
+                    self.delivered_messages.retain(|id, _| id + DELIVERED_MESSAGES_KEPT > message_id);
+                    self.delivered_messages.insert(message_id, message);
+                }
+            },
+            UIEvent::MessageSeen((_, message_id, seen_by)) => {
+                if let Some(message) = self.delivered_messages.get(&message_id) {
+                    self.print_system(format!("Seen by {}: {}", seen_by, message).as_str());
+                }
+            },
+            UIEvent::MessageReceivedBy((_, message_id, received_by, other_peers)) => {
+                // every confirmation is only shown with --verbose, otherwise once all peers have the message
+                if self.verbose || received_by >= other_peers {
+                    self.print_you(format!("(#{} received by {} of {})", message_id, received_by, other_peers).as_str());
+                }
+            },
+            UIEvent::MessageLatency((_, message_id, latency)) => {
+                if self.verbose {
+                    self.print_system(format!("#{} {}", message_id, latency).as_str());
                 }
             },
             UIEvent::MessageRejected((_, message_id)) => {
-                if let Some(message) = self.sent_messages.get(&message_id) {
-                    self.print_you(format!("(!server rejected the message!) {}", message).as_str());
-                    self.sent_messages.remove(&message_id);
+                if let Some(message) = self.sent_messages.remove(&message_id) {
+                    self.print_you(format!("(!#{} rejected by the server!) {}", message_id, message).as_str());
                 }
             },
             UIEvent::MessageError((_, message_id)) => {
-                if let Some(message) = self.sent_messages.get(&message_id) {
-                    self.print_you(format!("(!error sending messsage!) {}", message).as_str());
-                    self.sent_messages.remove(&message_id);
+                if let Some(message) = self.sent_messages.remove(&message_id) {
+                    self.print_you(format!("(!#{} could not be sent!) {}", message_id, message).as_str());
+                }
+            },
+            UIEvent::MessageTimedOut((_, message_id)) => {
+                if let Some(message) = self.sent_messages.remove(&message_id) {
+                    self.print_you(format!("(!#{} timed out, the server did not answer!) {}", message_id, message).as_str());
+                }
+            },
+            UIEvent::MessageTooLarge((_, message_id, max_message_size)) => {
+                if let Some(message) = self.sent_messages.remove(&message_id) {
+                    self.print_you(format!("(!#{} too large, max {}!) {}", message_id, format_message_size(max_message_size), message).as_str());
                 }
             },
             UIEvent::ConferenceRestructuring((_, number_of_peers)) => {
                 self.can_send_messages = false;
                 self.print_system(format!("Conference restructuring: now has {} peers", number_of_peers).as_str());
             },
+            UIEvent::PeersChanged((_, joined, left)) => {
+                match (joined, left) {
+                    (1, _) => self.print_system("A participant joined"),
+                    (0, 1) => self.print_system("A participant left"),
+                    (0, left) => self.print_system(format!("{} participants left", left).as_str()),
+                    (joined, _) => self.print_system(format!("{} participants joined", joined).as_str()),
+                }
+            },
+            UIEvent::ConferenceRenegotiating(_) => {
+                self.can_send_messages = false;
+                self.print_system("A peer restarted the key exchange, renegotiating the conference keys...");
+            },
+            UIEvent::SetupProgress((_, stage)) => {
+                if self.verbose {
+                    self.print_system(format!("Setting up the conference: {}", stage).as_str());
+                }
+            },
+            UIEvent::RingMismatch(conference_id) => {
+                self.print_system(format!("WARNING: a peer of conference {} sees different participants than you, the server may be tampering with the conference. Compare fingerprints out of band before trusting it", conference_id).as_str());
+            },
+            UIEvent::InvalidMessages((conference_id, count, reason)) => {
+                self.print_system(format!("WARNING: dropped {} malformed messages of peers in conference {}, the last one: {}", count, conference_id, reason).as_str());
+            },
             UIEvent::ConferenceRestructuringFinished(_) => {
                 self.can_send_messages = true;
                 self.print_system("Ready to send messages");
             },
+            UIEvent::UpdateAvailable((version, url)) => {
+                self.print_system(format!("Version {} is available: {}", version, url).as_str());
+            },
+            UIEvent::ConferenceDirectory(None) => {
+                self.print_system("The server does not offer a directory of public conferences, or did not send it in time");
+            },
+            UIEvent::ConferenceDirectory(Some(conferences)) => {
+                if conferences.is_empty() {
+                    self.print_system("There are no public conferences");
+                }
+                for (conference_id, number_of_peers) in conferences {
+                    self.print_system(format!("Public conference {} ({} peers), join with /join {} <password>", conference_id, number_of_peers, conference_id).as_str());
+                }
+            },
+            UIEvent::SenderMuted((_, peer)) => {
+                self.print_system(format!("{} was muted for sending too many messages, use /unmute {} to show their messages", peer.display_name(), peer.fingerprint()).as_str());
+                self.known_peers.insert(peer.fingerprint(), peer);
+            },
+            UIEvent::SenderUnmuted((_, peer, suppressed)) => {
+                self.print_system(format!("{} is no longer muted, {} of their messages were hidden", peer.display_name(), suppressed).as_str());
+            },
+            UIEvent::BreakoutAnnounced((_, breakout, peer)) => {
+                let author = peer.map_or_else(|| "You".to_string(), |peer| peer.display_name());
+                self.print_system(format!(
+                    "{} opened the breakout \"{}\", join it with /leave and /join {} {}",
+                    author, breakout.topic, breakout.conference_id, breakout.password,
+                ).as_str());
+            },
+            UIEvent::ServerError(false) => {
+                self.print_system("The server reported an error");
+            },
+            UIEvent::ServerError(true) => {
+                self.print_system("The server reported too many errors, disconnecting");
+            },
+            UIEvent::ProtocolViolation(reason) => {
+                self.print_system(format!("Ignored an inconsistent server event: {}", reason).as_str());
+            },
+            UIEvent::DerivingKey(_) => {
+                self.print_system("Deriving the conference key...");
+            },
+            UIEvent::RequestPending((packet_nonce, conference_id)) => {
+                self.pending_requests.insert(packet_nonce, conference_id);
+            },
+            UIEvent::RequestCancelled(packet_nonce) => {
+                match self.pending_requests.remove(&packet_nonce) {
+                    Some(Some(conference_id)) => {
+                        self.print_system(format!("Cancelled joining conference {}", conference_id).as_str());
+                        self.join_password = None;
+                        if self.conference_id.is_none() {
+                            self.listen_only = false;
+                        }
+                    },
+                    _ => self.print_system("Cancelled creating the conference"),
+                }
+            },
+            UIEvent::ServerCapabilities(capabilities) => {
+                self.print_system(format!("Connected, the server supports {}", capabilities).as_str());
+            },
+            UIEvent::ConferenceStale((conference_id, true)) => {
+                self.print_system(format!("Conference {} may be stale, the server relays no messages and the peers do not answer. Rejoin with /rejoin", conference_id).as_str());
+            },
+            UIEvent::ConferenceStale((conference_id, false)) => {
+                self.print_system(format!("Conference {} is relaying messages again", conference_id).as_str());
+            },
+            UIEvent::HistoryLoaded((conference_id, entries)) => {
+                self.print_system(format!("The last {} messages of conference {} from earlier sessions:", entries.len(), conference_id).as_str());
+                for entry in entries {
+                    let time = humantime::format_rfc3339_seconds(entry.time);
+                    let invalid = if entry.is_signature_valid { "" } else { "(!invalid signature!) " };
+                    match &entry.sender {
+                        Some(peer) => self.print_someone(peer, format!("({}) {}{}", time, invalid, entry.text).as_str()),
+                        None => self.print_you(format!("({}) {}", time, entry.text).as_str()),
+                    }
+                }
+                self.print_system(format!("End of the history of conference {}", conference_id).as_str());
+            },
+            UIEvent::RetentionPolicy((conference_id, policy)) => {
+                if self.retention.insert(conference_id, policy) != Some(policy) {
+                    self.print_system(format!("Conference {}: {}, change it with /retention", conference_id, policy.description()).as_str());
+                }
+            },
+            UIEvent::Anonymity((conference_id, anonymity)) => {
+                // shown again only when the ring changed
+                if self.anonymity.insert(conference_id, anonymity) != Some(anonymity) {
+                    self.print_system(format!("Conference {}: {}", conference_id, anonymity.summary()).as_str());
+                }
+            },
+            UIEvent::OwnFingerprint((conference_id, fingerprint)) => {
+                self.print_system(format!("Your fingerprint in conference {}: {}, peers can compare it with you out of band", conference_id, fingerprint).as_str());
+            },
+            UIEvent::ConferenceIdle((conference_id, remaining)) => {
+                self.print_system(format!("No messages in conference {} for a while, leaving it in {} minutes. Send a message or use /idle off to stay",
+                    conference_id, remaining.as_secs().div_ceil(60)).as_str());
+            },
+            UIEvent::HandshakeFailed(diagnosis) => {
+                self.print_system(format!("Could not connect: {}", diagnosis).as_str());
+            },
+            // already explained by HandshakeFailed
+            UIEvent::Disconnected(DisconnectReason::HandshakeFailed(_)) => {},
+            UIEvent::Disconnected(reason) => {
+                self.print_system(reason.to_string().as_str());
+                if reason == DisconnectReason::Idle {
+                    self.idle_disconnected = true;
+                    if self.reconnect_on_activity {
+                        self.print_system("Type anything to connect again");
+                    }
+                }
+            },
+            UIEvent::ConferenceServer((conference_id, server)) => {
+                self.print_system(format!("Conference {} is hosted on {}", conference_id, server).as_str());
+                self.conference_server = Some(server);
+            },
+            UIEvent::Reconnecting(reason) => {
+                self.print_system(format!("{}, connecting again", reason).as_str());
+                // keeps the password and listen only mode, as with /rejoin
+                self.rejoining = self.conference_id.is_some();
+            },
+            UIEvent::ServerDisconnected((server, reason)) => {
+                self.print_system(format!("{}: {}", server, reason).as_str());
+            },
+            UIEvent::MigrationAnnounced((conference_id, migration, peer)) => {
+                self.print_system(format!("{} moved the conference to {} (conference {}), type /follow to move along",
+                    peer.display_name(), migration.server_address, migration.conference_id).as_str());
+                if self.conference_id == Some(conference_id) {
+                    self.announced_migration = Some(migration);
+                }
+            },
+            UIEvent::MigrationProgress((conference_id, stage)) => {
+                self.print_system(format!("Moving conference {}: {}", conference_id, stage).as_str());
+                if let MigrationStage::Finished(migration) = stage {
+                    self.conference_password = Some(migration.password);
+                }
+            },
+            UIEvent::PluginList(names) => {
+                if names.is_empty() {
+                    self.print_system("No scripts or plugins loaded");
+                } else {
+                    self.print_system(format!("Loaded scripts and plugins: {}", names.join(", ")).as_str());
+                }
+            },
+        }
+    }
+
+    /// The conference messages are sent to
+    pub(crate) fn conference_id(&self) -> Option<ConferenceId> {
+        self.conference_id
+    }
+
+    pub(crate) fn can_send_messages(&self) -> bool {
+        self.can_send_messages
+    }
+
+    /// The server of the current conference, or the selected one
+    pub(crate) fn current_server(&self) -> &str {
+        self.conference_server.as_deref().unwrap_or(&self.server_address)
+    }
+
+    /// Create and join conferences on `server` from now on, returns `false` if it is not connected
+    async fn select_server(&mut self, server: &str) -> bool {
+        if !self.servers.iter().any(|s| s == server) {
+            return false;
+        }
+        if server != self.server_address {
+            self.server_address = server.to_string();
+            self.ui_action_sender.send(UIAction::SelectServer(server.to_string())).await.unwrap();
+        }
+        true
+    }
+
+    fn export_device_link(&self) {
+        let conferences = match (self.conference_id, self.conference_password.as_ref()) {
+            (Some(conference_id), Some(password)) => vec![LinkedConference { conference_id, password: password.clone() }],
+            _ => Vec::new(),
+        };
+        let bundle = LinkBundle::new(self.current_server().to_string(), conferences, &self.storage);
+        let auth_string = device_link::generate_auth_string();
+        let link_code = match bundle.encode(&auth_string) {
+            Ok(link_code) => link_code,
+            Err(e) => { self.print_system(format!("Could not create link code: {}", e).as_str()); return; },
+        };
+        if let Ok(code) = qrcode::QrCode::new(link_code.as_bytes()) {
+            self.print_line("", &code.render::<qrcode::render::unicode::Dense1x2>().quiet_zone(true).build());
+        }
+        self.print_system(format!("Link code: {}", link_code).as_str());
+        self.print_system(format!("On the other device run /link <link code> {}", device_link::format_auth_string(&auth_string)).as_str());
+        self.print_system(device_link::SEPARATE_MEMBER_NOTICE);
+    }
+
+    async fn import_device_link(&mut self, link_code: &str, auth_string: &str) {
+        let bundle = match LinkBundle::decode(link_code, auth_string) {
+            Ok(bundle) => bundle,
+            Err(e) => { self.print_system(format!("Invalid link code: {}", e).as_str()); return; },
+        };
+        if !self.select_server(&bundle.server_address).await {
+            self.print_system(format!("The link code is for a server that is not configured: {}", bundle.server_address).as_str());
+            return;
+        }
+        for (key_image, label) in bundle.peer_labels.iter() {
+            if let Some(key_image) = peer_labels::key_image_from_hex(key_image) {
+                self.ui_action_sender.send(UIAction::SetPeerLabel((key_image, Some(label.clone())))).await.unwrap();
+            }
+        }
+        self.print_system(format!("Imported {} peer labels", bundle.peer_labels.len()).as_str());
+        let Some(conference) = bundle.conferences.into_iter().next()
+        else {
+            return;
+        };
+        if self.conference_id.is_some() {
+            self.print_system(format!("Leave the current conference to join conference {} of the other device", conference.conference_id).as_str());
+            return;
+        }
+        self.print_system(device_link::SEPARATE_MEMBER_NOTICE);
+        self.join_password = Some(conference.password.clone());
+        self.ui_action_sender.send(UIAction::JoinConference((conference.conference_id, conference.password))).await.unwrap();
+    }
+
+    /// Ring the bell or show a notification for an incoming message unless the conference is muted
+    fn notify(&self, conference_id: ConferenceId, message: &str) {
+        if self.notification_muted.contains(&conference_id) {
+            return;
+        }
+        let title = format!("Conference {}", conference_id);
+        let Some(sequence) = terminal_notification::sequence(self.notification, self.multiplexer, &title, message)
+        else {
+            return;
+        };
+        match self.notification_output.as_ref() {
+            Some(notification_output) => {
+                let _ = notification_output.unbounded_send(sequence);
+            },
+            None => {
+                let mut stdout = std::io::stdout();
+                let _ = stdout.write_all(sequence.as_bytes()).and_then(|_| stdout.flush());
+            },
         }
     }
 
     fn print_system(&self, message: &str) {
-        println!("[SYSTEM]: {}", message);
+        self.print_line("SYSTEM", message);
     }
 
-    fn print_someone(&self, message: &str) {
-        println!("[SOMEONE]: {}", message);
+    fn print_someone(&self, peer: &Peer, message: &str) {
+        self.print_line(&peer.display_name(), message);
+    }
+
+    /// Look up a peer that sent a message by fingerprint or label
+    fn find_peer(&self, name: &str) -> Option<KeyImage> {
+        self.known_peers.get(name)
+            .or_else(|| self.known_peers.values().find(|peer| peer.label.as_deref() == Some(name)))
+            .map(|peer| peer.key_image)
     }
 
     fn print_you(&self, message: &str) {
-        println!("[YOU]: {}", message);
+        self.print_line("YOU", message);
+    }
+
+    fn print_line(&self, tag: &str, message: &str) {
+        match self.output.as_ref() {
+            Some(output) => {
+                let _ = output.unbounded_send(TaggedLine { tag: tag.to_string(), text: message.to_string() });
+            },
+            None if tag.is_empty() => println!("{}", message),
+            None => print_tagged(tag, message),
+        }
     }
 }
 
+/// Print a message after its sender tag, further lines are indented below the first one
+fn print_tagged(tag: &str, message: &str) {
+    let tag = format!("[{}]: ", tag);
+    let indent = " ".repeat(tag.chars().count());
+    for (i, line) in message.lines().enumerate() {
+        println!("{}{}", if i == 0 { &tag } else { &indent }, line);
+    }
+    if message.is_empty() {
+        println!("{}", tag);
+    }
+}
 
+fn spawn_state_managers(servers: Vec<String>, ui_event_sender: Sender<UIEvent>, ui_action_receiver: Receiver<UIAction>) {
+    runtime::spawn(async move {
+        multi_server::start_state_managers(servers, ui_event_sender, ui_action_receiver).await;
+    });
+}
@@ -0,0 +1,515 @@
+//! Framing of the events exchanged with the server, without doing any io.
+//!
+//! The connection feeds the bytes it reads into a [`Decoder`] and writes what an [`Encoder`] produced,
+//! so the wire format can be tested without a socket. The handshake is not framed and stays in
+//! the connection manager.
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use log::warn;
+use crate::{
+    connection_manager::ServerCapabilities,
+    constants::{
+        ClientEvent, ConferenceEncryptionSalt, ConferenceJoinSalt, Result, ServerEvent, ServerToClientMessageTypePrimitive,
+        LENGTH_PREFIXED_EVENT_TYPE_START, SERVER_CAPABILITY_MAX_MESSAGE_SIZE, SERVER_CAPABILITY_WIRE_SCHEMA,
+    },
+};
+#[cfg(feature = "wire-schema")]
+use crate::wire_schema::SchemaCodec;
+
+/// Longer server frames are refused before they are buffered, large messages of peers arrive in chunks
+pub const MAX_SERVER_FRAME_LENGTH: usize = 16 * 1024 * 1024;
+
+pub trait Decoder {
+    type Item;
+
+    /// Decode the next item from the start of `buffer` and consume its bytes.
+    /// Returns `None` without consuming anything if `buffer` does not hold a complete frame yet.
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Self::Item>>;
+}
+
+pub trait Encoder {
+    type Item;
+
+    /// Append the frame of `item` to `buffer`
+    fn encode(&mut self, item: Self::Item, buffer: &mut BytesMut) -> Result<()>;
+}
+
+/// Decodes server events and encodes client events
+#[derive(Debug, Default)]
+pub struct ClientCodec;
+
+impl ClientCodec {
+    /// Split the next complete frame off the start of `buffer`, see [`Decoder::decode`]
+    pub fn split_frame(&mut self, buffer: &mut BytesMut) -> Result<Option<Bytes>> {
+        Ok(server_frame_length(buffer)?.map(|length| buffer.split_to(length).freeze()))
+    }
+
+    /// Decode a frame returned by [`ClientCodec::split_frame`]
+    pub fn decode_frame(frame: Bytes) -> ServerEvent {
+        decode_server_frame(frame)
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = ServerEvent;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<ServerEvent>> {
+        Ok(self.split_frame(buffer)?.map(decode_server_frame))
+    }
+}
+
+impl Encoder for ClientCodec {
+    type Item = ClientEvent;
+
+    fn encode(&mut self, event: ClientEvent, buffer: &mut BytesMut) -> Result<()> {
+        buffer.put_u8(event.value());
+        match event {
+            ClientEvent::CreateConference((nonce, password_hash, join_salt, encryption_salt)) => {
+                buffer.put_u32(nonce);
+                buffer.put_slice(&password_hash);
+                buffer.put_slice(&join_salt);
+                buffer.put_slice(&encryption_salt);
+            },
+            ClientEvent::GetConferenceJoinSalt((nonce, conference_id)) => {
+                buffer.put_u32(nonce);
+                buffer.put_u32(conference_id);
+            },
+            ClientEvent::JoinConference((nonce, conference_id, password_hash)) => {
+                buffer.put_u32(nonce);
+                buffer.put_u32(conference_id);
+                buffer.put_slice(&password_hash);
+            },
+            ClientEvent::LeaveConference((nonce, conference_id)) => {
+                buffer.put_u32(nonce);
+                buffer.put_u32(conference_id);
+            },
+            ClientEvent::SendMessage((nonce, message)) => {
+                let message_length = u32::try_from(message.message.len()).map_err(|_| "Message is too long")?;
+                buffer.put_u32(nonce);
+                buffer.put_u32(message.conference);
+                buffer.put_u32(message_length);
+                buffer.put_slice(&message.message);
+            },
+            ClientEvent::ListConferences(nonce) => {
+                buffer.put_u32(nonce);
+            },
+            ClientEvent::Disconnect => {},
+            ClientEvent::SelectWireSchema(version) => {
+                buffer.put_u8(version);
+            },
+            ClientEvent::Ping(number) | ClientEvent::Pong(number) => {
+                buffer.put_u32(number);
+            },
+        }
+        Ok(())
+    }
+}
+
+/// The framing of a connection, switched to the versioned wire schema once it was selected
+#[derive(Debug)]
+pub enum ConnectionCodec {
+    Classic(ClientCodec),
+    #[cfg(feature = "wire-schema")]
+    Schema(SchemaCodec),
+}
+
+impl ConnectionCodec {
+    /// Split the next complete frame off the start of `buffer`, see [`Decoder::decode`]
+    pub fn split_frame(&mut self, buffer: &mut BytesMut) -> Result<Option<Bytes>> {
+        match self {
+            ConnectionCodec::Classic(codec) => codec.split_frame(buffer),
+            #[cfg(feature = "wire-schema")]
+            ConnectionCodec::Schema(codec) => codec.split_frame(buffer),
+        }
+    }
+
+    /// Decode a frame returned by [`ConnectionCodec::split_frame`]
+    pub fn decode_frame(&self, frame: Bytes) -> Result<ServerEvent> {
+        match self {
+            ConnectionCodec::Classic(_) => Ok(ClientCodec::decode_frame(frame)),
+            #[cfg(feature = "wire-schema")]
+            ConnectionCodec::Schema(_) => SchemaCodec::decode_frame(frame),
+        }
+    }
+}
+
+impl Encoder for ConnectionCodec {
+    type Item = ClientEvent;
+
+    fn encode(&mut self, event: ClientEvent, buffer: &mut BytesMut) -> Result<()> {
+        match self {
+            ConnectionCodec::Classic(codec) => codec.encode(event, buffer),
+            #[cfg(feature = "wire-schema")]
+            ConnectionCodec::Schema(codec) => codec.encode(event, buffer),
+        }
+    }
+}
+
+/// Decode a server event from a complete frame (event type followed by the payload), e.g. from a trace
+pub fn decode_server_event(frame: &[u8]) -> Result<ServerEvent> {
+    match server_frame_length(frame)? {
+        Some(length) if length == frame.len() => Ok(decode_server_frame(Bytes::copy_from_slice(frame))),
+        Some(_) => Err("Trailing bytes after server event".into()),
+        None => Err("Server event is truncated".into()),
+    }
+}
+
+fn u32_at(buffer: &[u8], offset: usize) -> Option<u32> {
+    buffer.get(offset..offset + 4).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+}
+
+/// Length of the frame at the start of `buffer`, `None` until the length is known and that many bytes are buffered.
+/// A frame longer than [`MAX_SERVER_FRAME_LENGTH`] is an error
+fn server_frame_length(buffer: &[u8]) -> Result<Option<usize>> {
+    let length = server_frame_length_unchecked(buffer)?;
+    if let Some(length) = length.filter(|&length| length > MAX_SERVER_FRAME_LENGTH) {
+        return Err(format!("Server frame of {} bytes is too long", length).into());
+    }
+    Ok(length.filter(|&length| buffer.len() >= length))
+}
+
+/// Length of the frame at the start of `buffer` once the length is known, whether or not it is buffered completely
+fn server_frame_length_unchecked(buffer: &[u8]) -> Result<Option<usize>> {
+    let Some(&event_type) = buffer.first()
+    else {
+        return Ok(None);
+    };
+    let Ok(event_type) = ServerToClientMessageTypePrimitive::try_from(event_type)
+    else {
+        if event_type < LENGTH_PREFIXED_EVENT_TYPE_START {
+            return Err("Invalid server event type".into());
+        }
+        // an event of a newer server, skipped as a whole
+        let Some(payload_length) = u32_at(buffer, 1)
+        else {
+            return Ok(None);
+        };
+        let length = (payload_length as usize).checked_add(1 + 4).ok_or("Unknown server event is too long")?;
+        return Ok(Some(length));
+    };
+    let length = match event_type {
+        ServerToClientMessageTypePrimitive::HandshakeAcknowledged
+        | ServerToClientMessageTypePrimitive::GeneralError => 1,
+        ServerToClientMessageTypePrimitive::ConferenceCreationError
+        | ServerToClientMessageTypePrimitive::Ping
+        | ServerToClientMessageTypePrimitive::Pong => 1 + 4,
+        ServerToClientMessageTypePrimitive::HandshakeAcknowledgedWithCapabilities => {
+            let Some(bits) = u32_at(buffer, 1)
+            else {
+                return Ok(None);
+            };
+            1 + 4 + ServerCapabilities::extension_length(bits)
+        },
+        ServerToClientMessageTypePrimitive::ConferenceCreated
+        | ServerToClientMessageTypePrimitive::ConferenceLeft
+        | ServerToClientMessageTypePrimitive::MessageAccepted
+        | ServerToClientMessageTypePrimitive::ConferenceRestructuring
+        | ServerToClientMessageTypePrimitive::ConferenceJoinSaltError
+        | ServerToClientMessageTypePrimitive::ConferenceJoinError
+        | ServerToClientMessageTypePrimitive::ConferenceLeaveError
+        | ServerToClientMessageTypePrimitive::MessageError => 1 + 8,
+        ServerToClientMessageTypePrimitive::ConferenceJoinSalt => 1 + 8 + 32,
+        ServerToClientMessageTypePrimitive::ConferenceJoined => 1 + 12 + 32,
+        ServerToClientMessageTypePrimitive::IncomingMessage => {
+            // the length is untrusted, the buffer only grows as the bytes arrive
+            let Some(message_length) = u32_at(buffer, 5)
+            else {
+                return Ok(None);
+            };
+            1 + 8 + message_length as usize
+        },
+        ServerToClientMessageTypePrimitive::ConferenceList => {
+            let Some(number_of_conferences) = u32_at(buffer, 5)
+            else {
+                return Ok(None);
+            };
+            (number_of_conferences as usize).checked_mul(8)
+                .and_then(|list_length| list_length.checked_add(1 + 8))
+                .ok_or("Conference list is too long")?
+        },
+    };
+    Ok(Some(length))
+}
+
+/// Decode a frame of the length given by [`server_frame_length`]
+fn decode_server_frame(mut frame: Bytes) -> ServerEvent {
+    let event_type = frame.get_u8();
+    let Ok(event_type) = ServerToClientMessageTypePrimitive::try_from(event_type)
+    else {
+        // only length prefixed events pass `server_frame_length`
+        return ServerEvent::Unknown((event_type, frame.get_u32()));
+    };
+    match event_type {
+        ServerToClientMessageTypePrimitive::HandshakeAcknowledged => {
+            warn!("Server sent unexpected handshake acknowledgement");
+            ServerEvent::HandshakeAcknowledged
+        },
+        ServerToClientMessageTypePrimitive::ConferenceCreated => {
+            ServerEvent::ConferenceCreated((frame.get_u32(), frame.get_u32()))
+        },
+        ServerToClientMessageTypePrimitive::ConferenceJoinSalt => {
+            let nonce = frame.get_u32();
+            let conference_id = frame.get_u32();
+            let mut join_salt: ConferenceJoinSalt = [0; 32];
+            frame.copy_to_slice(&mut join_salt);
+            ServerEvent::ConferenceJoinSalt((nonce, conference_id, join_salt))
+        },
+        ServerToClientMessageTypePrimitive::ConferenceJoined => {
+            let nonce = frame.get_u32();
+            let conference_id = frame.get_u32();
+            let number_of_peers = frame.get_u32();
+            let mut encryption_salt: ConferenceEncryptionSalt = [0; 32];
+            frame.copy_to_slice(&mut encryption_salt);
+            ServerEvent::ConferenceJoined((nonce, conference_id, number_of_peers, encryption_salt))
+        },
+        ServerToClientMessageTypePrimitive::ConferenceLeft => {
+            ServerEvent::ConferenceLeft((frame.get_u32(), frame.get_u32()))
+        },
+        ServerToClientMessageTypePrimitive::MessageAccepted => {
+            ServerEvent::MessageAccepted((frame.get_u32(), frame.get_u32()))
+        },
+        ServerToClientMessageTypePrimitive::IncomingMessage => {
+            let conference_id = frame.get_u32();
+            let _message_length = frame.get_u32();
+            // the rest of the frame, without copying it
+            ServerEvent::IncomingMessage((conference_id, frame))
+        },
+        ServerToClientMessageTypePrimitive::ConferenceRestructuring => {
+            ServerEvent::ConferenceRestructuring((frame.get_u32(), frame.get_u32()))
+        },
+        ServerToClientMessageTypePrimitive::HandshakeAcknowledgedWithCapabilities => {
+            let bits = frame.get_u32();
+            let max_message_size = (bits & SERVER_CAPABILITY_MAX_MESSAGE_SIZE != 0).then(|| frame.get_u32());
+            let wire_schema_version = (bits & SERVER_CAPABILITY_WIRE_SCHEMA != 0).then(|| frame.get_u8());
+            ServerEvent::HandshakeAcknowledgedWithCapabilities(ServerCapabilities { bits, max_message_size, wire_schema_version })
+        },
+        ServerToClientMessageTypePrimitive::ConferenceList => {
+            let nonce = frame.get_u32();
+            let number_of_conferences = frame.get_u32();
+            let conferences = (0..number_of_conferences).map(|_| (frame.get_u32(), frame.get_u32())).collect();
+            ServerEvent::ConferenceList((nonce, conferences))
+        },
+        ServerToClientMessageTypePrimitive::Ping => ServerEvent::Ping(frame.get_u32()),
+        ServerToClientMessageTypePrimitive::Pong => ServerEvent::Pong(frame.get_u32()),
+        ServerToClientMessageTypePrimitive::GeneralError => ServerEvent::GeneralError,
+        ServerToClientMessageTypePrimitive::ConferenceCreationError => ServerEvent::ConferenceCreationError(frame.get_u32()),
+        ServerToClientMessageTypePrimitive::ConferenceJoinSaltError => {
+            ServerEvent::ConferenceJoinSaltError((frame.get_u32(), frame.get_u32()))
+        },
+        ServerToClientMessageTypePrimitive::ConferenceJoinError => {
+            ServerEvent::ConferenceJoinError((frame.get_u32(), frame.get_u32()))
+        },
+        ServerToClientMessageTypePrimitive::ConferenceLeaveError => {
+            ServerEvent::ConferenceLeaveError((frame.get_u32(), frame.get_u32()))
+        },
+        ServerToClientMessageTypePrimitive::MessageError => {
+            ServerEvent::MessageError((frame.get_u32(), frame.get_u32()))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{ConferenceId, Message, NumberOfPeers, PacketNonce};
+    use proptest::{collection::vec, prelude::*};
+    use super::*;
+
+    fn frame(event_type: u8, parts: &[&[u8]]) -> Vec<u8> {
+        let mut result = vec![event_type];
+        for part in parts {
+            result.extend_from_slice(part);
+        }
+        result
+    }
+
+    fn decode_all(data: &[u8]) -> Vec<ServerEvent> {
+        let mut buffer = BytesMut::from(data);
+        let mut events = Vec::new();
+        while let Some(event) = ClientCodec.decode(&mut buffer).unwrap() {
+            events.push(event);
+        }
+        assert!(buffer.is_empty());
+        events
+    }
+
+    #[test]
+    fn test_decode_every_server_event() {
+        let one = &1u32.to_be_bytes()[..];
+        let two = &2u32.to_be_bytes()[..];
+        let three = &3u32.to_be_bytes()[..];
+        let salt = &[7u8; 32][..];
+        let mut data = Vec::new();
+        for (event_type, parts) in [
+            (0x00, vec![]),
+            (0x01, vec![one, two]),
+            (0x02, vec![one, two, salt]),
+            (0x03, vec![one, two, three, salt]),
+            (0x04, vec![one, two]),
+            (0x05, vec![one, two]),
+            (0x06, vec![one, two]),
+            (0x07, vec![one, three, &b"abc"[..]]),
+            (0x08, vec![three]),
+            (0x08, vec![&0x10u32.to_be_bytes()[..], two]),
+            (0x08, vec![&0x30u32.to_be_bytes()[..], two, &[1][..]]),
+            (0x09, vec![one, two, one, two, three, one]),
+            (0x0B, vec![three]),
+            (0x0C, vec![three]),
+            (0x10, vec![]),
+            (0x11, vec![one]),
+            (0x12, vec![one, two]),
+            (0x13, vec![one, two]),
+            (0x14, vec![one, two]),
+            (0x15, vec![one, two]),
+        ] {
+            data.extend_from_slice(&frame(event_type, &parts));
+        }
+
+        let events = decode_all(&data);
+        assert!(matches!(events[..], [
+            ServerEvent::HandshakeAcknowledged,
+            ServerEvent::ConferenceCreated((1, 2)),
+            ServerEvent::ConferenceJoinSalt((1, 2, [7, ..])),
+            ServerEvent::ConferenceJoined((1, 2, 3, [7, ..])),
+            ServerEvent::ConferenceLeft((1, 2)),
+            ServerEvent::MessageAccepted((1, 2)),
+            ServerEvent::ConferenceRestructuring((1, 2)),
+            ServerEvent::IncomingMessage((1, _)),
+            ServerEvent::HandshakeAcknowledgedWithCapabilities(ServerCapabilities { bits: 3, max_message_size: None, wire_schema_version: None }),
+            ServerEvent::HandshakeAcknowledgedWithCapabilities(ServerCapabilities { bits: 0x10, max_message_size: Some(2), wire_schema_version: None }),
+            ServerEvent::HandshakeAcknowledgedWithCapabilities(ServerCapabilities { bits: 0x30, max_message_size: Some(2), wire_schema_version: Some(1) }),
+            ServerEvent::ConferenceList((1, _)),
+            ServerEvent::Ping(3),
+            ServerEvent::Pong(3),
+            ServerEvent::GeneralError,
+            ServerEvent::ConferenceCreationError(1),
+            ServerEvent::ConferenceJoinSaltError((1, 2)),
+            ServerEvent::ConferenceJoinError((1, 2)),
+            ServerEvent::ConferenceLeaveError((1, 2)),
+            ServerEvent::MessageError((1, 2)),
+        ]));
+        let ServerEvent::IncomingMessage((_, message)) = &events[7] else { unreachable!() };
+        assert_eq!(&message[..], b"abc");
+        let ServerEvent::ConferenceList((_, conferences)) = &events[11] else { unreachable!() };
+        assert_eq!(conferences, &vec![(1, 2), (3, 1)]);
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_decode_server_event(data in vec(any::<u8>(), 0..256)) {
+            // arbitrary bytes are rejected with an error, never a panic
+            let _ = decode_server_event(&data);
+            let mut buffer = BytesMut::from(&data[..]);
+            while let Ok(Some(_)) = ClientCodec.decode(&mut buffer) {}
+        }
+
+        #[test]
+        fn proptest_incoming_message_round_trip(conference_id in any::<ConferenceId>(), message in vec(any::<u8>(), 0..512)) {
+            let data = frame(0x07, &[&conference_id.to_be_bytes(), &(message.len() as u32).to_be_bytes(), &message]);
+            let Ok(ServerEvent::IncomingMessage((decoded_conference_id, decoded_message))) = decode_server_event(&data)
+            else {
+                panic!("incoming message was not decoded");
+            };
+            prop_assert_eq!(decoded_conference_id, conference_id);
+            prop_assert_eq!(&decoded_message[..], &message[..]);
+        }
+
+        #[test]
+        fn proptest_conference_list_round_trip(nonce in any::<PacketNonce>(), conferences in vec(any::<(ConferenceId, NumberOfPeers)>(), 0..32)) {
+            let mut payload = Vec::new();
+            for (conference_id, number_of_peers) in &conferences {
+                payload.extend_from_slice(&conference_id.to_be_bytes());
+                payload.extend_from_slice(&number_of_peers.to_be_bytes());
+            }
+            let data = frame(0x09, &[&nonce.to_be_bytes(), &(conferences.len() as u32).to_be_bytes(), &payload]);
+            let Ok(ServerEvent::ConferenceList((decoded_nonce, decoded_conferences))) = decode_server_event(&data)
+            else {
+                panic!("conference list was not decoded");
+            };
+            prop_assert_eq!(decoded_nonce, nonce);
+            prop_assert_eq!(decoded_conferences, conferences);
+        }
+
+        #[test]
+        fn proptest_encode_send_message(nonce in any::<PacketNonce>(), conference_id in any::<ConferenceId>(), message in vec(any::<u8>(), 0..512)) {
+            let event = ClientEvent::SendMessage((nonce, Message { conference: conference_id, message: Bytes::from(message.clone()), message_id: None }));
+            let mut buffer = BytesMut::new();
+            ClientCodec.encode(event, &mut buffer).unwrap();
+            let expected = frame(0x05, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes(), &(message.len() as u32).to_be_bytes(), &message]);
+            prop_assert_eq!(&buffer[..], &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_decode_partial_frames() {
+        let data = frame(0x07, &[&5u32.to_be_bytes(), &3u32.to_be_bytes(), b"abc"]);
+        let mut buffer = BytesMut::new();
+        // nothing is consumed until the frame is complete
+        for byte in &data[..data.len() - 1] {
+            buffer.put_u8(*byte);
+            assert!(ClientCodec.decode(&mut buffer).unwrap().is_none());
+            assert!(!buffer.is_empty());
+        }
+        buffer.put_u8(data[data.len() - 1]);
+        assert!(matches!(ClientCodec.decode(&mut buffer).unwrap(), Some(ServerEvent::IncomingMessage((5, _)))));
+        assert!(buffer.is_empty());
+        assert!(ClientCodec.decode(&mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_refuse_long_frames() {
+        // refused from the length alone, before the message arrives
+        let too_long = (MAX_SERVER_FRAME_LENGTH as u32).to_be_bytes();
+        assert!(ClientCodec.decode(&mut BytesMut::from(&frame(0x07, &[&5u32.to_be_bytes(), &too_long])[..])).is_err());
+        assert!(ClientCodec.decode(&mut BytesMut::from(&frame(0x42, &[&too_long])[..])).is_err());
+        let longest = ((MAX_SERVER_FRAME_LENGTH - 1 - 8) as u32).to_be_bytes();
+        assert!(ClientCodec.decode(&mut BytesMut::from(&frame(0x07, &[&5u32.to_be_bytes(), &longest])[..])).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_skip_unknown_events() {
+        let mut buffer = BytesMut::from(&frame(0x42, &[&2u32.to_be_bytes(), b"ab"])[..]);
+        buffer.put_u8(0x10);
+        assert!(matches!(ClientCodec.decode(&mut buffer).unwrap(), Some(ServerEvent::Unknown((0x42, 2)))));
+        assert!(matches!(ClientCodec.decode(&mut buffer).unwrap(), Some(ServerEvent::GeneralError)));
+        assert!(buffer.is_empty());
+        // the payload is skipped as a whole
+        let mut buffer = BytesMut::from(&frame(0x42, &[&2u32.to_be_bytes(), b"a"])[..]);
+        assert!(ClientCodec.decode(&mut buffer).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decode_invalid_frames() {
+        assert!(ClientCodec.decode(&mut BytesMut::from(&b"\x0b"[..])).is_err());
+        assert!(decode_server_event(b"").is_err());
+        // truncated, and trailing bytes
+        assert!(decode_server_event(b"\x07\x00\x00\x00\x01\xff\xff\xff\xff\x00").is_err());
+        assert!(decode_server_event(b"\x01\x00\x00\x00\x01\x00\x00\x00\x02\x00").is_err());
+        assert!(decode_server_event(b"\x09\x00\x00\x00\x01\xff\xff\xff\xff").is_err());
+        assert!(matches!(decode_server_event(b"\x01\x00\x00\x00\x01\x00\x00\x00\x02"), Ok(ServerEvent::ConferenceCreated((1, 2)))));
+    }
+
+    #[test]
+    fn test_encode_every_client_event() {
+        let one = &1u32.to_be_bytes()[..];
+        let two = &2u32.to_be_bytes()[..];
+        for (event, expected) in [
+            (ClientEvent::CreateConference((1, [3; 32], [4; 32], [5; 32])), frame(0x01, &[one, &[3; 32], &[4; 32], &[5; 32]])),
+            (ClientEvent::GetConferenceJoinSalt((1, 2)), frame(0x02, &[one, two])),
+            (ClientEvent::JoinConference((1, 2, [3; 32])), frame(0x03, &[one, two, &[3; 32]])),
+            (ClientEvent::LeaveConference((1, 2)), frame(0x04, &[one, two])),
+            (
+                ClientEvent::SendMessage((1, Message { conference: 2, message: Bytes::from_static(b"abc"), message_id: None })),
+                frame(0x05, &[one, two, &3u32.to_be_bytes(), b"abc"]),
+            ),
+            (ClientEvent::Disconnect, frame(0x06, &[])),
+            (ClientEvent::ListConferences(1), frame(0x07, &[one])),
+            (ClientEvent::SelectWireSchema(1), frame(0x09, &[&[1]])),
+            (ClientEvent::Ping(1), frame(0x0A, &[one])),
+            (ClientEvent::Pong(2), frame(0x0B, &[two])),
+        ] {
+            let mut buffer = BytesMut::new();
+            ClientCodec.encode(event, &mut buffer).unwrap();
+            assert_eq!(&buffer[..], &expected[..]);
+        }
+    }
+}
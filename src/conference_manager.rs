@@ -1,4 +1,7 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Duration, Instant, SystemTime},
+};
 
 use crate::{constants::{
     Receiver,
@@ -8,16 +11,23 @@ use crate::{constants::{
     ConferenceId,
     NumberOfPeers,
     EncryptionKey,
+    KeyImage,
     Message, ConferenceEvent,
-}, crypto::KEY_SIZE};
+    MessageID,
+    Peer,
+    Breakout,
+    Migration,
+    SetupStage,
+    MIGRATION_MESSAGE_ID,
+}, anonymity::Anonymity, config::FloodProtectionConfig, crypto::KEY_SIZE, file_transfer::{FileComplete, FileId, FileOffer, IncomingFiles, OutgoingFile}, flood_protection::{FloodGuard, Verdict}, history_store::{HistoryEntry, HistoryStore, RetentionPolicy}, message_codec::{self, ClientToClientMessage, CodecError, Epoch, SignedMessage}, ordering::{self, HoldBack, LamportClock, LogicalClock}, peer_labels::SharedPeerLabels, upload::{Chunk, Reassembly, UploadId}};
 
-use async_std::stream::StreamExt;
-use async_std::io::{Cursor, ReadExt};
+use bytes::Bytes;
 use curve25519_dalek::{Scalar, RistrettoPoint, ristretto::CompressedRistretto, constants::RISTRETTO_BASEPOINT_POINT};
-use futures::SinkExt;
+use futures::{SinkExt, StreamExt};
+use sha3::{Digest, Sha3_256};
 
 use log::{debug, warn, info};
-use crate::crypto;
+use crate::{crypto, fingerprint, runtime, sanitize};
 
 enum ConferenceState {
     Initial,
@@ -28,40 +38,105 @@ enum ConferenceState {
     NormalOperation,
 }
 
-#[repr(u8)]
-/// The different types of messages that can be sent between clients
-/// PublicKey = `0x01`
-/// EncryptionKeyPart = `0x02`
-/// Message = `0x03`
-enum ClientToClientMessage {
-    PublicKey([u8; 32]),
-    EncryptionKeyPart(Vec<u8>),
-    Message(Vec<u8>),
+/// How many message hashes are kept to match read receipts against
+const RECENT_MESSAGES_CAPACITY: usize = 200;
+/// Read receipts are batched and sent at most this often
+const READ_RECEIPT_MIN_INTERVAL: Duration = Duration::from_secs(5);
+/// Delivery receipts wait this long for more messages to confirm at once
+const DELIVERY_RECEIPT_DELAY: Duration = Duration::from_secs(1);
+/// Hashes confirmed by one delivery receipt, more are sent in the next one
+const MAX_DELIVERY_RECEIPT_HASHES: usize = 64;
+/// Probes are answered at most this often, every peer answers so a probe causes a burst of replies
+const PROBE_REPLY_MIN_INTERVAL: Duration = Duration::from_secs(10);
+/// A peer restarting the key exchange on its own makes the conference renegotiate at most this often
+const RENEGOTIATION_MIN_INTERVAL: Duration = Duration::from_secs(30);
+/// Own messages waiting for the server to accept them before they are kept in the history,
+/// the ones it refused or never answered are dropped with the oldest
+const UNACCEPTED_MESSAGES_CAPACITY: usize = 100;
+
+type MessageHash = [u8; 32];
+/// Epoch and hash of a ring and the number of public keys that follow
+const RING_HASH_HEADER_SIZE: usize = 4 + 32 + 2;
+
+/// The ring of an epoch, built once when the public key exchange finishes
+/// and reused to sign and verify every message until the ring changes
+struct RingContext {
+    /// epoch the public keys were exchanged in
+    epoch: Epoch,
+    ring: Vec<RistrettoPoint>,
+    personal_key_index: usize,
+    /// the key the own public key in the ring belongs to, the next ring may use a new one
+    personal_private_key: Scalar,
+    /// hash of the sorted ring, every peer of the epoch has to arrive at the same one
+    hash: MessageHash,
+    /// a differing ring hash of a peer was reported to the UI
+    mismatch_reported: bool,
 }
 
-impl ClientToClientMessage {
-    fn encode(&self) -> Vec<u8> {
-        match self {
-            ClientToClientMessage::PublicKey(pubkey) => {
-                let mut result = Vec::new();
-                result.push(0x01);
-                result.extend_from_slice(pubkey);
-                result
-            },
-            ClientToClientMessage::EncryptionKeyPart(key_part) => {
-                let mut result = Vec::new();
-                result.push(0x02);
-                result.extend_from_slice(key_part);
-                result
-            },
-            ClientToClientMessage::Message(message) => {
-                let mut result = Vec::new();
-                result.push(0x03);
-                result.extend_from_slice(&u32::try_from(message.len()).unwrap().to_be_bytes());
-                result.extend_from_slice(message);
-                result
-            },
+impl RingContext {
+    /// `compressed_ring` has to be sorted, an error if it holds an invalid point or not the public key of
+    /// `personal_private_key`
+    fn new(epoch: Epoch, compressed_ring: &[CompressedRistretto], personal_private_key: Scalar) -> std::result::Result<RingContext, String> {
+        let personal_public_key = (personal_private_key * RISTRETTO_BASEPOINT_POINT).compress();
+        let ring = compressed_ring.iter().map(CompressedRistretto::decompress).collect::<Option<Vec<_>>>()
+            .ok_or("The ring holds a public key that is not a valid point")?;
+        let personal_key_index = compressed_ring.iter().position(|key| key == &personal_public_key)
+            .ok_or("The ring does not hold the own public key")?;
+        Ok(RingContext {
+            epoch,
+            ring,
+            personal_key_index,
+            personal_private_key,
+            hash: RingContext::hash(compressed_ring),
+            mismatch_reported: false,
+        })
+    }
+
+    /// Hash of the sorted public keys, exchanged after the setup so that a server showing
+    /// different peers to different members is noticed
+    fn hash(compressed_ring: &[CompressedRistretto]) -> MessageHash {
+        let mut hasher = Sha3_256::new();
+        for key in compressed_ring {
+            hasher.update(key.as_bytes());
+        }
+        hasher.finalize().into()
+    }
+
+    /// The announced epoch, ring hash and ring, see [`ClientToClientMessage::RingHash`]
+    fn encode_hash(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(RING_HASH_HEADER_SIZE + self.ring.len() * 32);
+        result.extend_from_slice(&self.epoch.to_be_bytes());
+        result.extend_from_slice(&self.hash);
+        // rings are never larger, the server limits the number of peers
+        result.extend_from_slice(&u16::try_from(self.ring.len()).unwrap_or(u16::MAX).to_be_bytes());
+        for key in &self.ring {
+            result.extend_from_slice(key.compress().as_bytes());
+        }
+        result
+    }
+
+    /// Epoch, hash and ring of an announcement and the length of the announced part, the signed copy follows it.
+    /// `None` if the announcement is malformed or the ring does not have the announced hash
+    fn decode_hash(announcement: &[u8]) -> Option<(Epoch, MessageHash, Vec<RistrettoPoint>, usize)> {
+        let header = announcement.get(..RING_HASH_HEADER_SIZE)?;
+        let epoch = Epoch::from_be_bytes(header[..4].try_into().unwrap());
+        let hash: MessageHash = header[4..36].try_into().unwrap();
+        let ring_size = u16::from_be_bytes(header[36..].try_into().unwrap()) as usize;
+        let length = RING_HASH_HEADER_SIZE + ring_size * 32;
+        let compressed_ring: Vec<CompressedRistretto> = announcement.get(RING_HASH_HEADER_SIZE..length)?
+            .chunks_exact(32)
+            .map(|key| CompressedRistretto::from_slice(key).unwrap())
+            .collect();
+        if RingContext::hash(&compressed_ring) != hash {
+            return None;
         }
+        let ring = compressed_ring.iter().map(CompressedRistretto::decompress).collect::<Option<Vec<_>>>()?;
+        Some((epoch, hash, ring, length))
+    }
+
+    /// Returns the message, `true` if the signature is valid and the key image of the signer
+    fn verify(&self, signed_message: &[u8]) -> std::result::Result<(Vec<u8>, bool, KeyImage), CodecError> {
+        message_codec::check_message_signature(&self.ring, signed_message)
     }
 }
 
@@ -72,15 +147,62 @@ pub struct ConferenceManager {
     conference_event_receiver: Receiver<ConferenceEvent>,
     message_sender: Sender<Message>,
     ui_event_sender: Sender<UIEvent>,
+    peer_labels: SharedPeerLabels,
     _unsorted_public_keys: HashSet<CompressedRistretto>,
-    ring: Option<Vec<RistrettoPoint>>,
-    ring_personal_key_index: Option<usize>,
+    ring: Option<RingContext>,
     personal_private_key: Scalar,
     personal_public_key: RistrettoPoint,
     state: ConferenceState,
+    epoch: Epoch,
+    /// key parts of the current epoch that arrived before the own public key exchange finished
+    early_key_parts: Vec<Vec<u8>>,
     ephemeral_key_parts: NumberOfPeers,
     new_ephemeral_key: EncryptionKey,
     ephemeral_encryption_key: Option<EncryptionKey>,
+    personal_key_image: Option<KeyImage>,
+    /// hashes of the last signed messages in the order they were sent or received,
+    /// with the message id for own messages
+    recent_messages: VecDeque<(MessageHash, Option<MessageID>)>,
+    /// key images of the peers that have seen own messages
+    message_readers: HashMap<MessageID, HashSet<KeyImage>>,
+    /// key images of the peers that confirmed receiving own messages
+    message_recipients: HashMap<MessageID, HashSet<KeyImage>>,
+    read_receipts_enabled: bool,
+    /// only the key exchange is sent, see [`ConferenceEvent::SetListenOnly`]
+    listen_only: bool,
+    /// a new key pair for every key exchange, see [`ConferenceEvent::SetUnlinkable`]
+    unlinkable: bool,
+    last_read_receipt: Option<(MessageHash, Instant)>,
+    pending_read_receipt: Option<MessageHash>,
+    /// hashes of received messages to confirm and when the receipt is due
+    pending_delivery_receipts: Vec<MessageHash>,
+    delivery_receipt_due: Option<Instant>,
+    last_probe_reply: Option<Instant>,
+    /// when a stray key exchange message last made the conference renegotiate, by the public key of the sender.
+    /// Key parts do not tell the sender and share the `None` entry
+    last_renegotiations: HashMap<Option<[u8; 32]>, Instant>,
+    flood_guard: FloodGuard,
+    /// Lamport clock of the conference, see [`crate::ordering`]
+    clock: LogicalClock,
+    /// received text messages waiting to be shown in order
+    held_messages: HoldBack<(Bytes, bool, Peer)>,
+    /// chunks of large messages of the peers
+    incoming_uploads: Reassembly,
+    /// shown and sent messages are kept on disk with `history.persist`
+    history: Option<HistoryStore>,
+    /// own large messages so far and the index of the next chunk, kept for the history
+    outgoing_uploads: HashMap<UploadId, (u32, Vec<u8>)>,
+    /// own messages sent to the server, kept in the history once it accepts them
+    unaccepted_messages: VecDeque<(MessageID, Bytes)>,
+    /// files of the peers, `None` if receiving files is disabled
+    incoming_files: Option<IncomingFiles>,
+    /// own files offered by this manager, a file continued after a reconnect is offered again
+    offered_files: HashSet<FileId>,
+    /// scratch buffers reused for every message, they keep the capacity of the largest message so far
+    encode_buffer: Vec<u8>,
+    decrypt_buffer: Vec<u8>,
+    /// messages of peers that could not be read, by what was wrong with them
+    codec_errors: HashMap<CodecError, usize>,
 }
 
 impl ConferenceManager {
@@ -91,6 +213,8 @@ impl ConferenceManager {
         conference_event_receiver: Receiver<ConferenceEvent>,
         message_sender: Sender<Message>,
         ui_event_sender: Sender<UIEvent>,
+        peer_labels: SharedPeerLabels,
+        flood_protection: FloodProtectionConfig,
     ) -> ConferenceManager {
         debug!("Generating personal key pair for conference {}", conference_id);
         let mut csprng = rand_core::OsRng;
@@ -108,29 +232,138 @@ impl ConferenceManager {
             conference_event_receiver,
             message_sender,
             ui_event_sender,
+            peer_labels,
             _unsorted_public_keys,
             ring: None,
-            ring_personal_key_index: None,
             personal_private_key,
             personal_public_key,
             state: ConferenceState::Initial,
+            epoch: 0,
+            early_key_parts: Vec::new(),
             ephemeral_key_parts: 0,
             new_ephemeral_key: [0; 32], // temp value
             ephemeral_encryption_key: None,
+            personal_key_image: None,
+            recent_messages: VecDeque::with_capacity(RECENT_MESSAGES_CAPACITY),
+            message_readers: HashMap::new(),
+            message_recipients: HashMap::new(),
+            read_receipts_enabled: false,
+            listen_only: false,
+            unlinkable: false,
+            last_read_receipt: None,
+            pending_read_receipt: None,
+            pending_delivery_receipts: Vec::new(),
+            delivery_receipt_due: None,
+            last_probe_reply: None,
+            last_renegotiations: HashMap::new(),
+            flood_guard: FloodGuard::new(flood_protection),
+            clock: LogicalClock::default(),
+            held_messages: HoldBack::new(),
+            incoming_uploads: Reassembly::default(),
+            history: None,
+            outgoing_uploads: HashMap::new(),
+            unaccepted_messages: VecDeque::new(),
+            incoming_files: None,
+            offered_files: HashSet::new(),
+            encode_buffer: Vec::new(),
+            decrypt_buffer: Vec::new(),
+            codec_errors: HashMap::new(),
         }
     }
 
+    /// Keep the shown and sent messages in `history`
+    pub fn with_history(mut self, history: HistoryStore) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Receive the files of peers into `incoming_files`
+    pub fn with_incoming_files(mut self, incoming_files: IncomingFiles) -> Self {
+        self.incoming_files = Some(incoming_files);
+        self
+    }
+
     pub async fn start_conference_manager(&mut self) -> Result<()> {
         debug!("Starting conference manager for conference {}", self.conference_id);
+        self.send_own_fingerprint().await;
+        if let Some(policy) = self.history.as_ref().map(HistoryStore::policy) {
+            self.send_ui_event(UIEvent::RetentionPolicy((self.conference_id, policy))).await;
+        }
 
         // start initial public key exchange
         self.start_public_key_exchange().await;
 
-        while let Some(server_event) = self.conference_event_receiver.next().await {
+        loop {
+            self.release_held_messages().await;
+            let deadline = [self.read_receipt_deadline(), self.delivery_receipt_deadline(), self.held_messages.deadline()].into_iter().flatten().min();
+            let server_event = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match runtime::timeout(timeout, self.conference_event_receiver.next()).await {
+                        Some(server_event) => server_event,
+                        None => {
+                            if self.read_receipt_deadline().is_some_and(|deadline| deadline <= Instant::now()) {
+                                self.flush_read_receipt().await;
+                            }
+                            if self.delivery_receipt_deadline().is_some_and(|deadline| deadline <= Instant::now()) {
+                                self.flush_delivery_receipt().await;
+                            }
+                            continue;
+                        },
+                    }
+                },
+                None => self.conference_event_receiver.next().await,
+            };
+            let Some(server_event) = server_event
+            else {
+                break;
+            };
             match server_event {
                 ConferenceEvent::ConferenceRestructuring(number_of_peers) => self.initiate_conference_restructuring(number_of_peers).await,
                 ConferenceEvent::IncomingMessage(message) => self.process_incoming_message(message).await,
                 ConferenceEvent::OutboundMessage((message_id, message)) => self.process_outbound_message(message_id, message).await,
+                ConferenceEvent::OutboundChunk((message_id, chunk)) => self.process_outbound_chunk(message_id, chunk).await,
+                ConferenceEvent::OutboundFileChunk((message_id, file, chunk)) => self.process_outbound_file_chunk(message_id, file, chunk).await,
+                ConferenceEvent::MessagesDisplayed => self.queue_read_receipt().await,
+                ConferenceEvent::SetReadReceipts(enabled) => {
+                    debug!("Read receipts {} for conference {}", if enabled { "enabled" } else { "disabled" }, self.conference_id);
+                    self.read_receipts_enabled = enabled;
+                    if !enabled {
+                        self.pending_read_receipt = None;
+                    }
+                },
+                ConferenceEvent::SetUnlinkable(enabled) => {
+                    debug!("Unlinkable mode {} for conference {}", if enabled { "enabled" } else { "disabled" }, self.conference_id);
+                    let start = enabled && !self.unlinkable && matches!(self.state, ConferenceState::NormalOperation);
+                    self.unlinkable = enabled;
+                    // the current key stays linkable, move on to a new one right away, the key exchange reports the anonymity
+                    if start {
+                        self.initiate_conference_restructuring(self.number_of_peers).await;
+                    } else if !enabled {
+                        self.send_anonymity().await;
+                    }
+                },
+                ConferenceEvent::SetListenOnly(enabled) => {
+                    debug!("Listen only {} for conference {}", if enabled { "enabled" } else { "disabled" }, self.conference_id);
+                    self.listen_only = enabled;
+                    if enabled {
+                        self.pending_read_receipt = None;
+                        self.pending_delivery_receipts.clear();
+                        self.delivery_receipt_due = None;
+                    }
+                    self.send_anonymity().await;
+                },
+                ConferenceEvent::AllowFlooding(key_image) => self.allow_flooding(key_image).await,
+                ConferenceEvent::AnnounceBreakout(breakout) => self.announce_breakout(breakout).await,
+                ConferenceEvent::AnnounceMigration(migration) => self.announce_migration(migration).await,
+                ConferenceEvent::Probe => self.send_probe().await,
+                ConferenceEvent::SetRetentionPolicy(policy) => self.set_retention_policy(policy).await,
+                ConferenceEvent::MessageAccepted(message_id) => self.message_accepted(message_id).await,
+                ConferenceEvent::Left => {
+                    if let Some(Err(e)) = self.history.as_ref().map(HistoryStore::left) {
+                        warn!("Could not remove the history of conference {}: {}", self.conference_id, e);
+                    }
+                },
             }
         }
 
@@ -139,30 +372,63 @@ impl ConferenceManager {
     }
 
     async fn initiate_conference_restructuring(&mut self, new_number_of_peers: NumberOfPeers) {
-        debug!("Conference {} is being restructured to {} peers", self.conference_id, new_number_of_peers);
+        self.epoch = self.epoch.wrapping_add(1);
+        self.restart_key_exchange(new_number_of_peers).await;
+    }
+
+    /// Abort the key exchange in progress and start over in the current epoch
+    async fn restart_key_exchange(&mut self, new_number_of_peers: NumberOfPeers) {
+        debug!("Conference {} is being restructured to {} peers in epoch {}", self.conference_id, new_number_of_peers, self.epoch);
         self.number_of_peers = new_number_of_peers;
+        if self.unlinkable {
+            self.rotate_personal_key().await;
+        }
         self._unsorted_public_keys.clear();
         self._unsorted_public_keys.insert(self.personal_public_key.compress());
         // not resetting the self.ring yet because we might receive old messages while restructuring
         debug!("Generating own part of the new ephemeral key for conference {}", self.conference_id);
         self.new_ephemeral_key = crypto::generate_ephemeral_key();
         self.ephemeral_key_parts = 0;
+        self.early_key_parts.clear();
         self.start_public_key_exchange().await;
     }
 
+    /// Continue with a new key pair, the ring in use keeps signing with the old one until the exchange finished
+    async fn rotate_personal_key(&mut self) {
+        debug!("Generating a new personal key pair for conference {}", self.conference_id);
+        self.personal_private_key = Scalar::random(&mut rand_core::OsRng);
+        self.personal_public_key = self.personal_private_key * RISTRETTO_BASEPOINT_POINT;
+        self.send_own_fingerprint().await;
+    }
+
+    async fn send_own_fingerprint(&mut self) {
+        let fingerprint = fingerprint::key_image_fingerprint(&crypto::key_image(&self.personal_private_key));
+        self.send_ui_event(UIEvent::OwnFingerprint((self.conference_id, fingerprint))).await;
+    }
+
     async fn start_public_key_exchange(&mut self) {
         debug!("Starting initial public key exchange for conference {}", self.conference_id);
         self.state = ConferenceState::PublicKeyExchange;
-        self.send_message(ClientToClientMessage::PublicKey(*self.personal_public_key.compress().as_bytes()), None).await;
+        self.send_message(ClientToClientMessage::PublicKey((self.epoch, *self.personal_public_key.compress().as_bytes())), None).await;
+        let stage = SetupStage::KeyExchange((self._unsorted_public_keys.len(), self.number_of_peers as usize));
+        self.send_setup_progress(stage).await;
+    }
+
+    async fn send_setup_progress(&mut self, stage: SetupStage) {
+        self.send_ui_event(UIEvent::SetupProgress((self.conference_id, stage))).await;
     }
 
     async fn start_ephemeral_key_negotiation(&mut self) {
         debug!("Starting ephemeral encryption key negotiation for conference {}", self.conference_id);
         self.state = ConferenceState::EncryptionKeyNegotiation;
-        self.send_message(ClientToClientMessage::EncryptionKeyPart(self.new_ephemeral_key.to_vec()), None).await;
+        self.send_message(ClientToClientMessage::EncryptionKeyPart((self.epoch, self.new_ephemeral_key.to_vec())), None).await;
+        self.send_setup_progress(SetupStage::KeyNegotiation((0, (self.number_of_peers - 1) as usize))).await;
+        for key_part in std::mem::take(&mut self.early_key_parts) {
+            self.add_key_part(&key_part).await;
+        }
     }
 
-    async fn process_incoming_message(&mut self, message: Vec<u8>) {
+    async fn process_incoming_message(&mut self, message: Bytes) {
         debug!("Received message for conference {}, len is {}", self.conference_id, message.len());
         match self.state {
             ConferenceState::Initial => {
@@ -179,39 +445,113 @@ impl ConferenceManager {
         }
     }
 
-    async fn process_outbound_message(&mut self, message_id: usize, message: Vec<u8>) {
+    async fn process_outbound_message(&mut self, message_id: usize, message: Bytes) {
+        if self.listen_only {
+            warn!("Refused to send a message to conference {} in listen only mode", self.conference_id);
+            self.send_ui_event(UIEvent::MessageError((self.conference_id, message_id))).await;
+            return;
+        }
         match self.state {
             ConferenceState::NormalOperation => {
-                assert!(self.ring.is_some() && self.ring_personal_key_index.is_some() && self.ephemeral_encryption_key.is_some());
-                // sign message
-                let signed_message = self.sign_message(message).await;
+                assert!(self.ring.is_some() && self.ephemeral_encryption_key.is_some());
+                // sign message with the clock, so that all peers show it in the same place
+                let payload = ordering::encode_text(self.clock.tick(), &message);
+                let signed_message = self.sign_message(&payload).await;
+                self.remember_message(&signed_message, Some(message_id));
                 // send message
                 self.send_message(ClientToClientMessage::Message(signed_message), Some(message_id)).await;
+                self.wait_for_acceptance(message_id, message);
             }
             _ => {
                 warn!("Tried to send message for conference {} while not fully set up", self.conference_id);
-                self.ui_event_sender.send(UIEvent::MessageError((self.conference_id, message_id))).await.unwrap();
+                self.send_ui_event(UIEvent::MessageError((self.conference_id, message_id))).await;
             }
         }
     }
 
-    async fn process_message_public_key_exchange(&mut self, message: Vec<u8>) {
+    async fn process_outbound_chunk(&mut self, message_id: MessageID, chunk: Chunk) {
+        if self.listen_only {
+            warn!("Refused to send a chunk to conference {} in listen only mode", self.conference_id);
+            self.send_ui_event(UIEvent::MessageError((self.conference_id, message_id))).await;
+            return;
+        }
+        let ConferenceState::NormalOperation = self.state
+        else {
+            // the state manager sends it again once the key exchange finished
+            debug!("Not sending chunk {} of message {} to conference {} while not fully set up", chunk.index, message_id, self.conference_id);
+            return;
+        };
+        let payload = ordering::encode_text(self.clock.tick(), &chunk.encode());
+        let signed_chunk = self.sign_message(&payload).await;
+        // read receipts refer to the last chunk
+        if chunk.is_last() {
+            self.remember_message(&signed_chunk, Some(message_id));
+        }
+        self.send_message(ClientToClientMessage::Chunk(signed_chunk), Some(message_id)).await;
+        if self.history.is_some() {
+            self.record_outgoing_chunk(message_id, &chunk);
+        }
+    }
+
+    /// Send a part of an own file, offering the file before its first chunk and sending its hash after the last one
+    async fn process_outbound_file_chunk(&mut self, message_id: MessageID, file: OutgoingFile, chunk: Chunk) {
+        if self.listen_only {
+            warn!("Refused to send a file to conference {} in listen only mode", self.conference_id);
+            self.send_ui_event(UIEvent::MessageError((self.conference_id, message_id))).await;
+            return;
+        }
+        let ConferenceState::NormalOperation = self.state
+        else {
+            debug!("Not sending chunk {} of file {:016x} to conference {} while not fully set up", chunk.index, chunk.upload_id, self.conference_id);
+            return;
+        };
+        // peers that received the offer before a reconnect ignore it
+        if self.offered_files.insert(file.offer.file_id) {
+            let signed_offer = self.sign_message(&file.offer.encode()).await;
+            self.send_message(ClientToClientMessage::FileOffer(signed_offer), None).await;
+        }
+        let signed_chunk = self.sign_message(&chunk.encode()).await;
+        self.send_message(ClientToClientMessage::FileChunk(signed_chunk), Some(message_id)).await;
+        if chunk.is_last() {
+            let signed_complete = self.sign_message(&file.complete.encode()).await;
+            self.send_message(ClientToClientMessage::FileComplete(signed_complete), None).await;
+        }
+    }
+
+    /// Put the chunks of an own large message together for the history, chunks sent again are skipped.
+    /// An upload started over with a new key begins again at the first chunk
+    fn record_outgoing_chunk(&mut self, message_id: MessageID, chunk: &Chunk) {
+        let (next_index, message) = self.outgoing_uploads.entry(chunk.upload_id).or_default();
+        if chunk.index == *next_index {
+            message.extend_from_slice(&chunk.data);
+            *next_index += 1;
+        }
+        if chunk.is_last() {
+            if let Some((next_index, message)) = self.outgoing_uploads.remove(&chunk.upload_id) {
+                if next_index == chunk.total {
+                    self.wait_for_acceptance(message_id, message.into());
+                }
+            }
+        }
+    }
+
+    async fn process_message_public_key_exchange(&mut self, message: Bytes) {
         if let Some(message) = self.read_message(message).await {
             match message {
-                ClientToClientMessage::PublicKey(pubkey) => {
-                    let compressed = CompressedRistretto::from_slice(&pubkey).unwrap(); // should never fail since PublicKey has to be [u8; 32]
-                    self._unsorted_public_keys.insert(compressed);
-                    debug!("Received public key from peer in conference {}, now have {} public keys", self.conference_id, self._unsorted_public_keys.len());
-                    if self._unsorted_public_keys.len() == self.number_of_peers as usize {
-                        debug!("Received all public keys for conference {}", self.conference_id);
-                        self.finish_public_key_exchange().await;
-                    }
-                },
+                ClientToClientMessage::PublicKey((epoch, pubkey)) => self.process_public_key(epoch, pubkey).await,
+                ClientToClientMessage::EncryptionKeyPart((epoch, key_part)) => self.process_key_part(epoch, key_part).await,
                 ClientToClientMessage::Message(message) => {
                     // the message was decrypted with old encryption key
                     debug!("Received text message from peer for conference {} while in public key exchange state", self.conference_id);
                     self.process_text_message(message).await;
                 },
+                ClientToClientMessage::Chunk(chunk) => {
+                    debug!("Received chunk from peer for conference {} while in public key exchange state", self.conference_id);
+                    self.process_chunk(chunk).await;
+                },
+                ClientToClientMessage::FileOffer(offer) => self.process_file_offer(offer).await,
+                ClientToClientMessage::FileChunk(chunk) => self.process_file_chunk(chunk).await,
+                ClientToClientMessage::FileComplete(complete) => self.process_file_complete(complete).await,
                 _ => {
                     warn!("Received unexpected message from peer for conference {} while in public key exchange state", self.conference_id);
                 }
@@ -221,42 +561,140 @@ impl ConferenceManager {
         }
     }
 
+    /// `pubkey` is a valid point, see [`ConferenceManager::process_public_key`]
+    async fn add_public_key(&mut self, pubkey: [u8; 32]) {
+        self._unsorted_public_keys.insert(CompressedRistretto(pubkey));
+        debug!("Received public key from peer in conference {}, now have {} public keys", self.conference_id, self._unsorted_public_keys.len());
+        if self._unsorted_public_keys.len() == self.number_of_peers as usize {
+            debug!("Received all public keys for conference {}", self.conference_id);
+            self.finish_public_key_exchange().await;
+        } else {
+            let stage = SetupStage::KeyExchange((self._unsorted_public_keys.len(), self.number_of_peers as usize));
+            self.send_setup_progress(stage).await;
+        }
+    }
+
+    async fn process_public_key(&mut self, epoch: Epoch, pubkey: [u8; 32]) {
+        // anyone with the password can send it, a ring with an invalid point could not sign or verify
+        if CompressedRistretto(pubkey).decompress().is_none() {
+            warn!("Dropping public key of a peer in conference {}, it is not a valid point", self.conference_id);
+            return;
+        }
+        match epoch.cmp(&self.epoch) {
+            std::cmp::Ordering::Less => {
+                debug!("Dropping public key of epoch {} in conference {}, now in epoch {}", epoch, self.conference_id, self.epoch);
+            },
+            std::cmp::Ordering::Equal => match self.state {
+                ConferenceState::PublicKeyExchange => self.add_public_key(pubkey).await,
+                _ => self.renegotiate(Some(pubkey)).await,
+            },
+            std::cmp::Ordering::Greater => {
+                if !self.can_adopt_epoch(epoch) {
+                    return;
+                }
+                self.adopt_epoch(epoch).await;
+                self.add_public_key(pubkey).await;
+            },
+        }
+    }
+
+    async fn process_key_part(&mut self, epoch: Epoch, key_part: Vec<u8>) {
+        if key_part.len() != KEY_SIZE {
+            warn!("Received invalid encryption key part from peer for conference {}, key part too short", self.conference_id);
+            return;
+        }
+        match epoch.cmp(&self.epoch) {
+            std::cmp::Ordering::Less => {
+                debug!("Dropping encryption key part of epoch {} in conference {}, now in epoch {}", epoch, self.conference_id, self.epoch);
+            },
+            std::cmp::Ordering::Equal => match self.state {
+                // the peer already has all public keys, ours are still on their way
+                ConferenceState::PublicKeyExchange => self.early_key_parts.push(key_part),
+                ConferenceState::EncryptionKeyNegotiation => self.add_key_part(&key_part).await,
+                _ => self.renegotiate(None).await,
+            },
+            std::cmp::Ordering::Greater => {
+                if !self.can_adopt_epoch(epoch) {
+                    return;
+                }
+                self.adopt_epoch(epoch).await;
+                self.early_key_parts.push(key_part);
+            },
+        }
+    }
+
+    /// A peer that just joined takes the epoch of the others, whatever it is.
+    /// Once the conference is set up the peers only ever move on by one epoch at a time,
+    /// so a message further ahead is not followed
+    fn can_adopt_epoch(&self, epoch: Epoch) -> bool {
+        if self.ring.is_some() && epoch > self.epoch.saturating_add(1) {
+            warn!("Dropping key exchange message of epoch {} in conference {}, now in epoch {}", epoch, self.conference_id, self.epoch);
+            return false;
+        }
+        true
+    }
+
+    /// The other peers are in a newer epoch, because this peer joined after them or missed a restructuring
+    async fn adopt_epoch(&mut self, epoch: Epoch) {
+        info!("Peers of conference {} are in epoch {}, restarting the key exchange of epoch {}", self.conference_id, epoch, self.epoch);
+        if let ConferenceState::NormalOperation = self.state {
+            self.send_ui_event(UIEvent::ConferenceRenegotiating(self.conference_id)).await;
+        }
+        self.epoch = epoch;
+        self.restart_key_exchange(self.number_of_peers).await;
+    }
+
+    /// A peer restarted the key exchange of the current epoch on its own, e.g. because it crashed and rejoined.
+    /// Moving on to the next epoch makes all peers, including that one, restart the exchange together.
+    /// A sender that keeps restarting is only followed once every [`RENEGOTIATION_MIN_INTERVAL`]
+    async fn renegotiate(&mut self, sender: Option<[u8; 32]>) {
+        self.last_renegotiations.retain(|_, renegotiated| renegotiated.elapsed() < RENEGOTIATION_MIN_INTERVAL);
+        if self.last_renegotiations.contains_key(&sender) {
+            debug!("Dropping stray key exchange message in conference {}, renegotiated for its sender recently", self.conference_id);
+            return;
+        }
+        self.last_renegotiations.insert(sender, Instant::now());
+        warn!("Received a stray key exchange message in conference {}, renegotiating the keys", self.conference_id);
+        self.send_ui_event(UIEvent::ConferenceRenegotiating(self.conference_id)).await;
+        self.initiate_conference_restructuring(self.number_of_peers).await;
+    }
+
     async fn finish_public_key_exchange(&mut self) {
         self.state = ConferenceState::PublicKeyExchangeFinished;
 
         let mut compressed_ring: Vec<CompressedRistretto> = self._unsorted_public_keys.iter().cloned().collect();
         compressed_ring.sort_unstable(); // sort the keys in order
 
-        self.ring_personal_key_index = Some(compressed_ring.iter().position(|key| key == &self.personal_public_key.compress()).unwrap());
-        
-        self.ring = Some(compressed_ring.iter().map(|key| key.decompress().unwrap()).collect());
+        match RingContext::new(self.epoch, &compressed_ring, self.personal_private_key) {
+            Ok(ring) => self.ring = Some(ring),
+            Err(e) => {
+                warn!("Could not set up the ring of conference {}: {}", self.conference_id, e);
+                self.renegotiate(None).await;
+                return;
+            },
+        }
 
         self.start_ephemeral_key_negotiation().await;
     }
 
-    async fn process_message_ephemeral_key_negotiation(&mut self, message: Vec<u8>) {
+    async fn process_message_ephemeral_key_negotiation(&mut self, message: Bytes) {
         if let Some(message) = self.read_message(message).await {
             match message {
-                ClientToClientMessage::EncryptionKeyPart(key_part) => {
-                    if key_part.len() != KEY_SIZE {
-                        warn!("Received invalid encryption key part from peer for conference {}, key part too short", self.conference_id);
-                        return;
-                    }
-                    crypto::apply_ephemeral_key_part(&mut self.new_ephemeral_key, &key_part);
-                    self.ephemeral_key_parts += 1;
-                    debug!("Received {} of {} encryption key parts for conference {}", self.ephemeral_key_parts, self.number_of_peers - 1, self.conference_id);
-                    if self.ephemeral_key_parts == self.number_of_peers - 1 {
-                        debug!("Received all encryption key parts for conference {}", self.conference_id);
-                        self.ephemeral_encryption_key = Some(self.new_ephemeral_key);
-                        self.state = ConferenceState::EncryptionKeyNegotiationFinished;
-                        self.finish_conference_setup().await;
-                    }
-                },
+                ClientToClientMessage::EncryptionKeyPart((epoch, key_part)) => self.process_key_part(epoch, key_part).await,
                 ClientToClientMessage::Message(message) => {
                     // the message was decrypted with old encryption key
                     debug!("Received text message from peer for conference {} while in encryption key negotiation state", self.conference_id);
                     self.process_text_message(message).await;
                 },
+                ClientToClientMessage::Chunk(chunk) => {
+                    debug!("Received chunk from peer for conference {} while in encryption key negotiation state", self.conference_id);
+                    self.process_chunk(chunk).await;
+                },
+                ClientToClientMessage::FileOffer(offer) => self.process_file_offer(offer).await,
+                ClientToClientMessage::FileChunk(chunk) => self.process_file_chunk(chunk).await,
+                ClientToClientMessage::FileComplete(complete) => self.process_file_complete(complete).await,
+                ClientToClientMessage::PublicKey((epoch, pubkey)) => self.process_public_key(epoch, pubkey).await,
+                ClientToClientMessage::RingHash(announcement) => self.process_ring_hash(announcement).await,
                 _ => {
                     warn!("Received unexpected message from peer for conference {} while in encryption key negotiation state", self.conference_id);
                 },
@@ -266,247 +704,1258 @@ impl ConferenceManager {
         }
     }
 
+    async fn add_key_part(&mut self, key_part: &[u8]) {
+        crypto::apply_ephemeral_key_part(&mut self.new_ephemeral_key, key_part);
+        self.ephemeral_key_parts += 1;
+        debug!("Received {} of {} encryption key parts for conference {}", self.ephemeral_key_parts, self.number_of_peers - 1, self.conference_id);
+        if self.ephemeral_key_parts == self.number_of_peers - 1 {
+            debug!("Received all encryption key parts for conference {}", self.conference_id);
+            self.ephemeral_encryption_key = Some(self.new_ephemeral_key);
+            self.state = ConferenceState::EncryptionKeyNegotiationFinished;
+            self.finish_conference_setup().await;
+        } else {
+            let stage = SetupStage::KeyNegotiation((self.ephemeral_key_parts as usize, (self.number_of_peers - 1) as usize));
+            self.send_setup_progress(stage).await;
+        }
+    }
+
     async fn finish_conference_setup(&mut self) {
         debug!("Conference {} setup finished", self.conference_id);
         self.state = ConferenceState::NormalOperation;
-        self.ui_event_sender.send(UIEvent::ConferenceRestructuringFinished(self.conference_id)).await.unwrap();
+        self.send_ui_event(UIEvent::ConferenceRestructuringFinished(self.conference_id)).await;
+        self.send_anonymity().await;
+        self.announce_ring_hash().await;
     }
 
-    async fn process_message_normal_operation(&mut self, message: Vec<u8>) {
+    /// Report the anonymity of the own messages in the ring in use, with the current modes
+    async fn send_anonymity(&mut self) {
+        let Some(ring) = &self.ring
+        else {
+            return;
+        };
+        // the key pair is generated when joining, it is never used in another conference
+        let anonymity = Anonymity::of_ring(ring.ring.len(), true, self.unlinkable, self.listen_only);
+        self.send_ui_event(UIEvent::Anonymity((self.conference_id, anonymity))).await;
+    }
+
+    async fn process_message_normal_operation(&mut self, message: Bytes) {
         if let Some(message) = self.read_message(message).await {
             match message {
                 ClientToClientMessage::Message(message) => {
                     debug!("Received text message from peer for conference {}", self.conference_id);
                     self.process_text_message(message).await;
                 },
-                _ => {
-                    warn!("Received unexpected message from peer for conference {}", self.conference_id);
+                ClientToClientMessage::Chunk(chunk) => self.process_chunk(chunk).await,
+                ClientToClientMessage::FileOffer(offer) => self.process_file_offer(offer).await,
+                ClientToClientMessage::FileChunk(chunk) => self.process_file_chunk(chunk).await,
+                ClientToClientMessage::FileComplete(complete) => self.process_file_complete(complete).await,
+                ClientToClientMessage::ReadReceipt(receipt) => {
+                    self.process_read_receipt(receipt).await;
+                },
+                ClientToClientMessage::DeliveryReceipt(receipt) => self.process_delivery_receipt(receipt).await,
+                ClientToClientMessage::Breakout(announcement) => {
+                    self.process_breakout(announcement).await;
                 },
+                ClientToClientMessage::Migration(announcement) => {
+                    self.process_migration(announcement).await;
+                },
+                ClientToClientMessage::RingHash(announcement) => {
+                    self.process_ring_hash(announcement).await;
+                },
+                ClientToClientMessage::Probe => self.answer_probe().await,
+                // the state manager saw the traffic already
+                ClientToClientMessage::ProbeReply => {},
+                ClientToClientMessage::PublicKey((epoch, pubkey)) => self.process_public_key(epoch, pubkey).await,
+                ClientToClientMessage::EncryptionKeyPart((epoch, key_part)) => self.process_key_part(epoch, key_part).await,
             }
         } else {
             warn!("Received invalid message from peer for conference {}", self.conference_id);
         }
     }
 
+    /// Report an event to the UI, the manager stops once the state manager is gone
+    async fn send_ui_event(&mut self, ui_event: UIEvent) {
+        if self.ui_event_sender.send(ui_event).await.is_err() {
+            warn!("UI event channel of conference {} is closed, stopping", self.conference_id);
+            // the queued events are still handled before the receiver runs dry
+            self.conference_event_receiver.close();
+        }
+    }
+
     /// Send a message to the conference
     async fn send_message(&mut self, message: ClientToClientMessage, message_id: Option<usize>) {
-        match message {
-            ClientToClientMessage::PublicKey(_) | ClientToClientMessage::EncryptionKeyPart(_) => {
-                let encrypted_message = crypto::encrypt_message(&message.encode(), &self.initial_encryption_key).unwrap();
-                self.message_sender.send(
-                    Message{conference: self.conference_id, message: encrypted_message.encode(), message_id: None}
-                ).await.expect("Could not send message");
-            },
-            ClientToClientMessage::Message(_) => {
+        let key = match message {
+            // peers that are still waiting for key parts can not decrypt with the new key yet
+            ClientToClientMessage::PublicKey(_) | ClientToClientMessage::EncryptionKeyPart(_)
+                | ClientToClientMessage::RingHash(_) => self.initial_encryption_key,
+            ClientToClientMessage::ReadReceipt(_) | ClientToClientMessage::DeliveryReceipt(_) | ClientToClientMessage::Breakout(_) | ClientToClientMessage::Migration(_)
+                | ClientToClientMessage::Probe | ClientToClientMessage::ProbeReply
+                | ClientToClientMessage::FileOffer(_) | ClientToClientMessage::FileComplete(_) => self.ephemeral_encryption_key.unwrap(),
+            ClientToClientMessage::Message(_) | ClientToClientMessage::Chunk(_) | ClientToClientMessage::FileChunk(_) => {
                 assert!(self.ephemeral_encryption_key.is_some());
                 assert!(message_id.is_some());
-                let encrypted_message = crypto::encrypt_message(&message.encode(), &self.ephemeral_encryption_key.unwrap()).unwrap();
-                self.message_sender.send(
-                    Message{conference: self.conference_id, message: encrypted_message.encode(), message_id}
-                ).await.unwrap();
+                self.ephemeral_encryption_key.unwrap()
             },
+        };
+        if let Err(e) = message.encode_into(&mut self.encode_buffer) {
+            warn!("Could not send message to conference {}: {}", self.conference_id, e);
+            if let Some(message_id) = message_id {
+                self.send_ui_event(UIEvent::MessageTooLarge((self.conference_id, message_id, u32::MAX))).await;
+            }
+            return;
         }
+        // the encrypted message is handed over to the connection, so it needs its own buffer
+        let mut encrypted_message = Vec::new();
+        crypto::encrypt_message_into(&self.encode_buffer, &key, &mut encrypted_message).unwrap();
+        self.message_sender.send(
+            Message{conference: self.conference_id, message: encrypted_message.into(), message_id}
+        ).await.expect("Could not send message");
     }
 
     /// Sign a message with the ring signature
     /// returns the signature + message
-    async fn sign_message(&self, message: Vec<u8>) -> Vec<u8> {
-        assert!(self.ring.is_some());
-        assert!(self.ring_personal_key_index.is_some());
-        let signature = crypto::sign_message(&self.personal_private_key, self.ring_personal_key_index.unwrap(), self.ring.as_ref().unwrap(), &message);
-        let mut result = Vec::with_capacity(32 + 32 * self.number_of_peers as usize + 32 + message.len());
-        result.extend_from_slice(&signature.challenge.to_bytes());
-        for response in signature.responses.iter() {
-            result.extend_from_slice(&response.to_bytes());
-        }
-        result.extend_from_slice(&signature.key_image.compress().to_bytes());
-        result.extend_from_slice(&message);
-        result
+    async fn sign_message(&mut self, message: &[u8]) -> Vec<u8> {
+        let ring = self.ring.as_ref().expect("ring is established before signing");
+        let signature = crypto::sign_message(&ring.personal_private_key, ring.personal_key_index, &ring.ring, message);
+        let signed_message = SignedMessage::new(signature, message);
+        self.personal_key_image = Some(signed_message.key_image);
+        signed_message.encode()
     }
 
     /// Check the signature of a signed message
-    /// returns the message and `true` if the signature is valid
-    async fn check_message_signature(&mut self, message: Vec<u8>) -> Option<(Vec<u8>, bool)> {
-        if message.len() < 32 + 32 * self.number_of_peers as usize + 32 {
-            warn!("Received signed message with invalid length from peer for conference {} (not enough bytes to read signature)", self.conference_id);
-            return None;
+    /// returns the message, `true` if the signature is valid and the key image of the signer
+    async fn check_message_signature(&mut self, message: Vec<u8>) -> Option<(Vec<u8>, bool, KeyImage)> {
+        let verified = match self.ring.as_ref() {
+            Some(ring) => ring.verify(&message),
+            None => Err(CodecError::NoRing),
+        };
+        match verified {
+            Ok(verified) => Some(verified),
+            Err(e) => {
+                self.codec_error(e).await;
+                None
+            },
         }
+    }
 
-        const SCALAR_BYTE_SIZE: usize = 32;
-        let mut message_reader = Cursor::new(message);
-        let mut buffer = [0; SCALAR_BYTE_SIZE];
+    async fn read_message(&mut self, message: Bytes) -> Option<ClientToClientMessage> {
+        // could either be encrypted using the ephemeral key or the initial key, try the more likely one first
+        let keys = match (self.ephemeral_encryption_key, &self.state) {
+            (Some(ephemeral_encryption_key), ConferenceState::NormalOperation) => vec![ephemeral_encryption_key, self.initial_encryption_key],
+            // the ephemeral key is probably old
+            (Some(ephemeral_encryption_key), _) => vec![self.initial_encryption_key, ephemeral_encryption_key],
+            (None, _) => vec![self.initial_encryption_key],
+        };
+        match message_codec::read_message(&keys, &message, &mut self.decrypt_buffer) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                self.codec_error(e).await;
+                None
+            },
+        }
+    }
 
-        // parse signature
-        if message_reader.read_exact(&mut buffer).await.is_err() {
-            warn!("Received signed message with invalid signature from peer for conference {} (could not read challenge)", self.conference_id);
-            return None;
+    /// Count and log a message of a peer that could not be read. Malformed messages are reported to the UI at the
+    /// first one and whenever their number doubled, messages for another key or before the ring are expected while
+    /// the keys are exchanged
+    async fn codec_error(&mut self, error: CodecError) {
+        *self.codec_errors.entry(error).or_default() += 1;
+        warn!("Received invalid message from peer for conference {}: {} ({} times)", self.conference_id, error, self.codec_errors[&error]);
+        if matches!(error, CodecError::Undecryptable | CodecError::NoRing) {
+            return;
+        }
+        let malformed = self.codec_errors.iter()
+            .filter(|(error, _)| !matches!(error, CodecError::Undecryptable | CodecError::NoRing))
+            .map(|(_, count)| count)
+            .sum::<usize>();
+        if malformed.is_power_of_two() {
+            self.send_ui_event(UIEvent::InvalidMessages((self.conference_id, malformed, error.to_string()))).await;
         }
-        let Some(challenge) = Scalar::from_canonical_bytes(buffer).into()
+    }
+
+    async fn process_text_message(&mut self, message: Vec<u8>) {
+        let hash: MessageHash = Sha3_256::digest(&message).into();
+        self.remember_hash(hash, None);
+        let Some((message, is_signature_valid, key_image)) = self.check_message_signature(message).await
         else {
-            warn!("Received signed message with invalid signature from peer for conference {} (could not parse challenge)", self.conference_id);
-            return None;
+            warn!("Received invalid signed message from peer for conference {}", self.conference_id);
+            return;
+        };
+        self.queue_delivery_receipt(hash, is_signature_valid, key_image);
+        let Some((time, message)) = ordering::decode_text(&message)
+        else {
+            warn!("Received text message without clock from peer for conference {}", self.conference_id);
+            return;
         };
+        info!("Received message from peer for conference {}", self.conference_id);
+        self.show_text_message(time, message, is_signature_valid, key_image).await;
+    }
 
-        let mut responses = Vec::with_capacity(self.number_of_peers as usize);
-        for _ in 0..self.number_of_peers {
-            if message_reader.read_exact(&mut buffer).await.is_err() {
-                warn!("Received signed message with invalid signature from peer for conference {} (could not read response)", self.conference_id);
-                return None;
-            }
-            if let Some(response) = Scalar::from_canonical_bytes(buffer).into() {
-                responses.push(response);
-            } else {
-                warn!("Received signed message with invalid signature from peer for conference {} (could not parse response)", self.conference_id);
-                return None;
+    /// Put a part of a large message aside, the whole message is shown once all parts arrived
+    async fn process_chunk(&mut self, signed_chunk: Vec<u8>) {
+        let hash: MessageHash = Sha3_256::digest(&signed_chunk).into();
+        let Some((payload, is_signature_valid, key_image)) = self.check_message_signature(signed_chunk).await
+        else {
+            warn!("Received invalid signed chunk from peer for conference {}", self.conference_id);
+            return;
+        };
+        let Some((time, chunk)) = ordering::decode_text(&payload).and_then(|(time, chunk)| Some((time, Chunk::decode(chunk)?)))
+        else {
+            warn!("Received undecodable chunk from peer for conference {}", self.conference_id);
+            return;
+        };
+        debug!("Received chunk {} of {} of upload {:016x} for conference {}", chunk.index + 1, chunk.total, chunk.upload_id, self.conference_id);
+        if chunk.is_last() {
+            self.remember_hash(hash, None);
+            // chunks are sent one after another, the last one completes the message
+            self.queue_delivery_receipt(hash, is_signature_valid, key_image);
+        }
+        if !is_signature_valid {
+            // anyone can copy the key image of an upload, only the signature shows who sent a chunk
+            warn!("Dropped a chunk with an invalid signature in conference {}", self.conference_id);
+            return;
+        }
+        let Some(message) = self.incoming_uploads.add(key_image, chunk, Instant::now())
+        else {
+            return;
+        };
+        info!("Received message of {} bytes in chunks from peer for conference {}", message.len(), self.conference_id);
+        self.show_text_message(time, &message, true, key_image).await;
+    }
+
+    /// Start receiving a file a peer offered
+    async fn process_file_offer(&mut self, signed_offer: Vec<u8>) {
+        if self.incoming_files.is_none() {
+            debug!("Ignored a file offered in conference {}, receiving files is disabled", self.conference_id);
+            return;
+        }
+        let Some((payload, is_signature_valid, key_image)) = self.check_message_signature(signed_offer).await
+        else {
+            warn!("Received invalid signed file offer from peer for conference {}", self.conference_id);
+            return;
+        };
+        let Some(offer) = FileOffer::decode(&payload)
+        else {
+            warn!("Received undecodable file offer from peer for conference {}", self.conference_id);
+            return;
+        };
+        if !is_signature_valid {
+            warn!("Dropped a file offer with an invalid signature in conference {}", self.conference_id);
+            return;
+        }
+        info!("Peer offered a file of {} bytes in conference {}", offer.size, self.conference_id);
+        let sender = self.peer(key_image, true);
+        let Some(incoming_files) = &mut self.incoming_files else { return };
+        match incoming_files.offer(offer, sender, Instant::now()) {
+            Ok(Some(progress)) => self.send_ui_event(UIEvent::FileTransferProgress((self.conference_id, progress))).await,
+            Ok(None) => {},
+            Err(e) => warn!("Could not receive a file in conference {}: {}", self.conference_id, e),
+        }
+    }
+
+    /// Write a part of a file of a peer to disk
+    async fn process_file_chunk(&mut self, signed_chunk: Vec<u8>) {
+        if self.incoming_files.is_none() {
+            return;
+        }
+        let Some((payload, is_signature_valid, key_image)) = self.check_message_signature(signed_chunk).await
+        else {
+            warn!("Received invalid signed file chunk from peer for conference {}", self.conference_id);
+            return;
+        };
+        let Some(chunk) = Chunk::decode(&payload)
+        else {
+            warn!("Received undecodable file chunk from peer for conference {}", self.conference_id);
+            return;
+        };
+        if !is_signature_valid {
+            // anyone can copy the key image of the sender, only the signature shows who sent a chunk
+            warn!("Dropped a file chunk with an invalid signature in conference {}", self.conference_id);
+            return;
+        }
+        let Some(incoming_files) = &mut self.incoming_files else { return };
+        match incoming_files.chunk(&chunk, key_image, Instant::now()) {
+            Ok(Some(progress)) => self.send_ui_event(UIEvent::FileTransferProgress((self.conference_id, progress))).await,
+            Ok(None) => {},
+            Err(e) => {
+                warn!("Could not receive a file in conference {}: {}", self.conference_id, e);
+                return;
+            },
+        }
+        self.finish_file(chunk.upload_id).await;
+    }
+
+    /// The hash of a file of a peer, sent after its last chunk
+    async fn process_file_complete(&mut self, signed_complete: Vec<u8>) {
+        if self.incoming_files.is_none() {
+            return;
+        }
+        let Some((payload, is_signature_valid, key_image)) = self.check_message_signature(signed_complete).await
+        else {
+            warn!("Received invalid signed file hash from peer for conference {}", self.conference_id);
+            return;
+        };
+        let Some(complete) = FileComplete::decode(&payload)
+        else {
+            warn!("Received undecodable file hash from peer for conference {}", self.conference_id);
+            return;
+        };
+        if !is_signature_valid {
+            warn!("Dropped a file hash with an invalid signature in conference {}", self.conference_id);
+            return;
+        }
+        let file_id = complete.file_id;
+        if let Some(incoming_files) = &mut self.incoming_files {
+            incoming_files.complete(complete, key_image);
+        }
+        self.finish_file(file_id).await;
+    }
+
+    /// Report a file of a peer once all its chunks and its hash arrived
+    async fn finish_file(&mut self, file_id: FileId) {
+        let Some(incoming_files) = &mut self.incoming_files else { return };
+        match incoming_files.finish(file_id) {
+            Ok(Some(file)) => {
+                info!("Received a file of {} bytes in conference {}", file.size, self.conference_id);
+                self.send_ui_event(UIEvent::FileReceived((self.conference_id, file))).await;
+            },
+            Ok(None) => {},
+            Err(e) => warn!("Could not receive a file in conference {}: {}", self.conference_id, e),
+        }
+    }
+
+    /// The author of a signed message, only named if the signature is valid
+    fn peer(&self, key_image: KeyImage, is_signature_valid: bool) -> Peer {
+        // the key image of an invalid signature could be copied from someone else
+        let label = if is_signature_valid {
+            self.peer_labels.lock().unwrap().get(&key_image).map(str::to_string)
+        } else {
+            None
+        };
+        Peer { key_image, label }
+    }
+
+    /// Hold a received text message back until it can be shown in order, unless its sender is muted
+    async fn show_text_message(&mut self, time: LamportClock, message: &[u8], is_signature_valid: bool, key_image: KeyImage) {
+        let peer = self.peer(key_image, is_signature_valid);
+        let time = self.clock.clamp(time);
+        if is_signature_valid {
+            // a forged clock could push own messages far ahead
+            self.clock.observe(time);
+            match self.flood_guard.check(key_image, Instant::now()) {
+                Verdict::Allow => {},
+                Verdict::Muted => {
+                    warn!("Muting a flooding sender in conference {}", self.conference_id);
+                    self.send_ui_event(UIEvent::SenderMuted((self.conference_id, peer))).await;
+                    return;
+                },
+                Verdict::Suppressed => {
+                    debug!("Dropped message of a muted sender in conference {}", self.conference_id);
+                    return;
+                },
+                Verdict::Unmuted(suppressed) => {
+                    self.send_ui_event(UIEvent::SenderUnmuted((self.conference_id, peer.clone(), suppressed))).await;
+                },
             }
         }
+        // the signature covers the raw bytes, so the text is only cleaned up for displaying it
+        let message = Bytes::from(sanitize::sanitize_message(message));
+        self.held_messages.push(time, key_image, (message, is_signature_valid, peer), Instant::now());
+    }
 
-        if message_reader.read_exact(&mut buffer).await.is_err() {
-            warn!("Received signed message with invalid signature from peer for conference {} (could not read key image)", self.conference_id);
-            return None;
+    /// Show the held messages in order once they are due
+    async fn release_held_messages(&mut self) {
+        for (message, is_signature_valid, peer) in self.held_messages.release(Instant::now()) {
+            self.record_history(&message, is_signature_valid, Some(&peer)).await;
+            self.send_ui_event(UIEvent::IncomingMessage((self.conference_id, message, is_signature_valid, peer))).await;
         }
-        let Ok(key_image) = CompressedRistretto::from_slice(&buffer)
+    }
+
+    async fn set_retention_policy(&mut self, policy: RetentionPolicy) {
+        let Some(history) = &mut self.history
         else {
-            warn!("Received signed message with invalid signature from peer for conference {} (could not parse key image)", self.conference_id);
-            return None;
+            warn!("Attempted to set the retention policy of conference {} without history", self.conference_id);
+            return;
         };
-        let Some(key_image) = key_image.decompress()
+        debug!("Retention policy of conference {} set to {}", self.conference_id, policy);
+        if let Err(e) = history.set_policy(policy) {
+            warn!("Could not apply the retention policy to the history of conference {}: {}", self.conference_id, e);
+        }
+        let policy = history.policy();
+        self.send_ui_event(UIEvent::RetentionPolicy((self.conference_id, policy))).await;
+    }
+
+    /// Keep an own message in memory until the server accepts it, a refused message is not kept in the history
+    fn wait_for_acceptance(&mut self, message_id: MessageID, message: Bytes) {
+        if self.history.is_none() {
+            return;
+        }
+        if self.unaccepted_messages.len() == UNACCEPTED_MESSAGES_CAPACITY {
+            self.unaccepted_messages.pop_front();
+        }
+        self.unaccepted_messages.push_back((message_id, message));
+    }
+
+    async fn message_accepted(&mut self, message_id: MessageID) {
+        let Some(index) = self.unaccepted_messages.iter().position(|(unaccepted_id, _)| *unaccepted_id == message_id)
         else {
-            warn!("Received signed message with invalid signature from peer for conference {} (could not decompress key image)", self.conference_id);
-            return None;
+            return;
         };
+        if let Some((_, message)) = self.unaccepted_messages.remove(index) {
+            self.record_history(&message, true, None).await;
+        }
+    }
 
-        let signature = crypto::BLSAG_COMPACT {
-            challenge,
-            responses,
-            key_image,
+    /// Keep a shown or accepted message on disk, the history is not written again after it failed once.
+    /// The file is written on the blocking pool, the next message waits for it so the order is kept
+    async fn record_history(&mut self, message: &[u8], is_signature_valid: bool, sender: Option<&Peer>) {
+        let Some(history) = self.history.clone()
+        else {
+            return;
+        };
+        let entry = HistoryEntry {
+            time: SystemTime::now(),
+            sender: sender.map(|peer| Peer { key_image: peer.key_image, label: None }),
+            is_signature_valid,
+            text: String::from_utf8_lossy(message).into_owned(),
         };
+        if let Err(e) = runtime::spawn_blocking(move || history.append(&entry)).await {
+            warn!("Could not keep a message of conference {} in the history: {}", self.conference_id, e);
+            self.history = None;
+        }
+    }
 
-        let mut message = Vec::new();
-        if message_reader.read_to_end(&mut message).await.is_err() {
-            warn!("Received signed message with invalid signature from peer for conference {} (could not read message)", self.conference_id);
-            return None;
+    /// Stop muting a sender, the messages dropped so far are not recovered
+    async fn allow_flooding(&mut self, key_image: KeyImage) {
+        debug!("Flood protection disabled for a sender in conference {}", self.conference_id);
+        if let Some(suppressed) = self.flood_guard.exempt(key_image) {
+            let label = self.peer_labels.lock().unwrap().get(&key_image).map(str::to_string);
+            let peer = Peer { key_image, label };
+            self.send_ui_event(UIEvent::SenderUnmuted((self.conference_id, peer, suppressed))).await;
         }
-        let signature_valid = crypto::verify_message(&signature, self.ring.as_ref().unwrap(), &message);
-
-        Some((message, signature_valid))
-    }
-
-    async fn decrypt_message_helper(&self, message: Vec<u8>) -> Option<Vec<u8>> {
-        if let Ok(encrypted_message) = crypto::EncryptionResult::decode(&message) {
-            if let Some(ephemeral_encryption_key) = self.ephemeral_encryption_key {
-                // could either be encrypted using the ephemeral key or the initial key
-                match self.state {
-                    ConferenceState::NormalOperation => {
-                        // first try ephemeral_encryption_key, then initial_encryption_key
-                        if let Ok(decrypted_message) = crypto::decrypt_message(&ephemeral_encryption_key, &encrypted_message) {
-                            debug!("Decrypted message using ephemeral_encryption_key in conference {}", self.conference_id);
-                            return Some(decrypted_message);
-                        } else if let Ok(decrypted_message) = crypto::decrypt_message(&self.initial_encryption_key, &encrypted_message) {
-                            debug!("Decrypted message using initial_encryption_key in conference {}", self.conference_id);
-                            return Some(decrypted_message);
-                        } else {
-                            warn!("Received invalid message from peer for conference {} (could not decrypt message)", self.conference_id);
-                            return None;
-                        }
-                    },
-                    _ => {
-                        // first try initial_encryption_key, then ephemeral_encryption_key (probably old)
-                        if let Ok(decrypted_message) = crypto::decrypt_message(&self.initial_encryption_key, &encrypted_message) {
-                            debug!("Decrypted message using initial_encryption_key in conference {}", self.conference_id);
-                            return Some(decrypted_message);
-                        } else if let Ok(decrypted_message) = crypto::decrypt_message(&ephemeral_encryption_key, &encrypted_message) {
-                            debug!("Decrypted message using ephemeral_encryption_key in conference {}", self.conference_id);
-                            return Some(decrypted_message);
-                        } else {
-                            warn!("Received invalid message from peer for conference {} (could not decrypt message)", self.conference_id);
-                            return None;
-                        }
-                    },
-                }
-            } else {
-                // only initial_encryption_key is available
-                if let Ok(decrypted_message) = crypto::decrypt_message(&self.initial_encryption_key, &encrypted_message) {
-                    debug!("Decrypted message from peer for conference {} using initial_encryption_key", self.conference_id);
-                    return Some(decrypted_message);
-                } else {
-                    warn!("Received invalid message from peer for conference {} (could not decrypt message)", self.conference_id);
-                    return None;
-                }
+    }
+
+    /// Remember the hash of a signed message so read receipts can refer to it
+    fn remember_message(&mut self, signed_message: &[u8], message_id: Option<MessageID>) {
+        self.remember_hash(Sha3_256::digest(signed_message).into(), message_id);
+    }
+
+    fn remember_hash(&mut self, hash: MessageHash, message_id: Option<MessageID>) {
+        if self.recent_messages.len() == RECENT_MESSAGES_CAPACITY {
+            if let Some((_, Some(message_id))) = self.recent_messages.pop_front() {
+                self.message_readers.remove(&message_id);
+                self.message_recipients.remove(&message_id);
             }
-        } else {
-            warn!("Received invalid message from peer for conference {} (could not decode encrypted message)", self.conference_id);
+        }
+        self.recent_messages.push_back((hash, message_id));
+    }
+
+    /// When the pending read receipt is due, receipts wait until the conference is set up
+    fn read_receipt_deadline(&self) -> Option<Instant> {
+        self.pending_read_receipt?;
+        let ConferenceState::NormalOperation = self.state
+        else {
             return None;
+        };
+        Some(self.last_read_receipt.map_or_else(Instant::now, |(_, sent)| sent + READ_RECEIPT_MIN_INTERVAL))
+    }
+
+    /// Queue a read receipt for the last message received from a peer
+    async fn queue_read_receipt(&mut self) {
+        if !self.read_receipts_enabled || self.listen_only {
+            return;
+        }
+        let Some((last_received, _)) = self.recent_messages.iter().rev().find(|(_, message_id)| message_id.is_none())
+        else {
+            return;
+        };
+        if self.last_read_receipt.is_some_and(|(hash, _)| &hash == last_received) {
+            return;
+        }
+        self.pending_read_receipt = Some(*last_received);
+        if self.read_receipt_deadline().is_some_and(|deadline| deadline <= Instant::now()) {
+            self.flush_read_receipt().await;
         }
     }
 
-    async fn read_message(&mut self, message: Vec<u8>) -> Option<ClientToClientMessage> {
-        assert!(!message.is_empty());
-        let Some(message) = self.decrypt_message_helper(message).await
+    async fn flush_read_receipt(&mut self) {
+        let Some(hash) = self.pending_read_receipt.take()
+        else {
+            return;
+        };
+        debug!("Sending read receipt for conference {}", self.conference_id);
+        let signed_receipt = self.sign_message(&hash).await;
+        self.send_message(ClientToClientMessage::ReadReceipt(signed_receipt), None).await;
+        self.last_read_receipt = Some((hash, Instant::now()));
+    }
+
+    /// When the pending delivery receipt is due, receipts wait until the conference is set up
+    fn delivery_receipt_deadline(&self) -> Option<Instant> {
+        let ConferenceState::NormalOperation = self.state
         else {
-            warn!("Received invalid message from peer for conference {}", self.conference_id);
             return None;
         };
+        self.delivery_receipt_due
+    }
 
-        match message[0] {
-            0x01 => {
-                // PublicKey
-                if message.len() != 33 {
-                    warn!("Received public key message with invalid length from peer for conference {} (expected 33 bytes, got {})", self.conference_id, message.len());
-                    return None;
-                }
-                Some(ClientToClientMessage::PublicKey(message[1..].try_into().unwrap()))
-            },
-            0x02 => {
-                // EncryptionKeyPart
-                Some(ClientToClientMessage::EncryptionKeyPart(message[1..].to_vec()))
-            },
-            0x03 => {
-                // Message
-                if message.len() < 5 {
-                    warn!("Received text message with invalid length from peer for conference {} (not enought bytes to read message length)", self.conference_id);
-                    return None;
-                }
-                let message_length = u32::from_be_bytes(message[1..5].try_into().unwrap());
-                if message.len() != 5 + message_length as usize {
-                    warn!("Received text message with invalid length from peer for conference {} (message length is incorrect)", self.conference_id);
-                    return None;
-                }
-                Some(ClientToClientMessage::Message(message[5..].to_vec()))
+    /// Confirm receiving a message of a peer with the next delivery receipt, a forged message is not confirmed
+    fn queue_delivery_receipt(&mut self, hash: MessageHash, is_signature_valid: bool, key_image: KeyImage) {
+        if !is_signature_valid || self.listen_only || Some(key_image) == self.personal_key_image {
+            return;
+        }
+        self.pending_delivery_receipts.push(hash);
+        self.delivery_receipt_due.get_or_insert_with(|| Instant::now() + DELIVERY_RECEIPT_DELAY);
+    }
+
+    async fn flush_delivery_receipt(&mut self) {
+        let count = self.pending_delivery_receipts.len().min(MAX_DELIVERY_RECEIPT_HASHES);
+        let hashes: Vec<MessageHash> = self.pending_delivery_receipts.drain(..count).collect();
+        self.delivery_receipt_due = if self.pending_delivery_receipts.is_empty() { None } else { Some(Instant::now()) };
+        if hashes.is_empty() {
+            return;
+        }
+        debug!("Sending delivery receipt for {} messages for conference {}", hashes.len(), self.conference_id);
+        let signed_receipt = self.sign_message(&encode_delivery_receipt(&hashes)).await;
+        self.send_message(ClientToClientMessage::DeliveryReceipt(signed_receipt), None).await;
+    }
+
+    async fn announce_breakout(&mut self, breakout: Breakout) {
+        if self.listen_only {
+            warn!("Refused to announce a breakout in conference {} in listen only mode", self.conference_id);
+            return;
+        }
+        let ConferenceState::NormalOperation = self.state
+        else {
+            warn!("Tried to announce a breakout in conference {} while not fully set up", self.conference_id);
+            return;
+        };
+        debug!("Announcing breakout {} in conference {}", breakout.conference_id, self.conference_id);
+        let Some(announcement) = breakout.encode()
+        else {
+            warn!("Refused to announce breakout {} in conference {}, its password is too long", breakout.conference_id, self.conference_id);
+            return;
+        };
+        let signed_announcement = self.sign_message(&announcement).await;
+        self.send_message(ClientToClientMessage::Breakout(signed_announcement), None).await;
+    }
+
+    async fn process_breakout(&mut self, announcement: Vec<u8>) {
+        let Some((announcement, is_signature_valid, key_image)) = self.check_message_signature(announcement).await
+        else {
+            warn!("Received invalid breakout announcement from peer for conference {}", self.conference_id);
+            return;
+        };
+        // only members of the ring may announce breakouts
+        if !is_signature_valid {
+            warn!("Received breakout announcement with invalid signature for conference {}", self.conference_id);
+            return;
+        }
+        let Some(mut breakout) = Breakout::decode(&announcement)
+        else {
+            warn!("Received undecodable breakout announcement for conference {}", self.conference_id);
+            return;
+        };
+        breakout.topic = sanitize::sanitize_text(&breakout.topic);
+        let label = self.peer_labels.lock().unwrap().get(&key_image).map(str::to_string);
+        let peer = Peer { key_image, label };
+        self.send_ui_event(UIEvent::BreakoutAnnounced((self.conference_id, breakout, Some(peer)))).await;
+    }
+
+    async fn announce_migration(&mut self, migration: Migration) {
+        if self.listen_only {
+            warn!("Refused to announce a migration of conference {} in listen only mode", self.conference_id);
+            self.send_ui_event(UIEvent::MessageError((self.conference_id, MIGRATION_MESSAGE_ID))).await;
+            return;
+        }
+        let ConferenceState::NormalOperation = self.state
+        else {
+            warn!("Tried to announce a migration of conference {} while not fully set up", self.conference_id);
+            self.send_ui_event(UIEvent::MessageError((self.conference_id, MIGRATION_MESSAGE_ID))).await;
+            return;
+        };
+        debug!("Announcing the move of conference {} to {}", self.conference_id, migration.server_address);
+        let signed_announcement = self.sign_message(&migration.encode()).await;
+        self.send_message(ClientToClientMessage::Migration(signed_announcement), Some(MIGRATION_MESSAGE_ID)).await;
+    }
+
+    async fn process_migration(&mut self, announcement: Vec<u8>) {
+        let Some((announcement, is_signature_valid, key_image)) = self.check_message_signature(announcement).await
+        else {
+            warn!("Received invalid migration announcement from peer for conference {}", self.conference_id);
+            return;
+        };
+        // only members of the ring may move the conference
+        if !is_signature_valid {
+            warn!("Received migration announcement with invalid signature for conference {}", self.conference_id);
+            return;
+        }
+        let Some(mut migration) = Migration::decode(&announcement)
+        else {
+            warn!("Received undecodable migration announcement for conference {}", self.conference_id);
+            return;
+        };
+        migration.server_address = sanitize::sanitize_text(&migration.server_address);
+        let label = self.peer_labels.lock().unwrap().get(&key_image).map(str::to_string);
+        let peer = Peer { key_image, label };
+        self.send_ui_event(UIEvent::MigrationAnnounced((self.conference_id, migration, peer))).await;
+    }
+
+    async fn announce_ring_hash(&mut self) {
+        if self.listen_only {
+            debug!("Not announcing the ring hash of conference {} in listen only mode", self.conference_id);
+            return;
+        }
+        let mut announcement = self.ring.as_ref().expect("ring is established before the setup finishes").encode_hash();
+        // the plain copy can be read by peers with a ring of a different size and carries the ring to verify the signature with
+        let signed_announcement = self.sign_message(&announcement).await;
+        announcement.extend_from_slice(&signed_announcement);
+        self.send_message(ClientToClientMessage::RingHash(announcement), None).await;
+    }
+
+    /// Compare the ring a peer computed with the own one, a difference means the server showed
+    /// the peers different members, the UI is warned once per epoch.
+    /// The signature of a peer with a different ring can not be verified against the own ring,
+    /// so it is verified against the ring the peer announced, which has to have the announced hash
+    async fn process_ring_hash(&mut self, announcement: Vec<u8>) {
+        let Some((epoch, hash, announced_ring, length)) = RingContext::decode_hash(&announcement)
+        else {
+            warn!("Received invalid ring hash from peer for conference {}", self.conference_id);
+            return;
+        };
+        let is_signature_valid = match message_codec::check_message_signature(&announced_ring, &announcement[length..]) {
+            Ok((_, _, key_image)) if Some(key_image) == self.personal_key_image => return,
+            Ok((signed, is_signature_valid, _)) => is_signature_valid && signed == announcement[..length],
+            Err(e) => {
+                self.codec_error(e).await;
+                false
             },
-            _ => {
-                warn!("Received message with invalid message type {} from peer for conference {}", message[0], self.conference_id);
-                None
-            }
+        };
+        if !is_signature_valid {
+            warn!("Received ring hash with invalid signature from peer for conference {}", self.conference_id);
+            return;
+        }
+        let Some(ring) = self.ring.as_mut()
+        else {
+            return;
+        };
+        if epoch != ring.epoch {
+            debug!("Ignoring ring hash of epoch {} for conference {} (own ring is of epoch {})", epoch, self.conference_id, ring.epoch);
+            return;
+        }
+        if hash == ring.hash {
+            return;
+        }
+        warn!("A peer of conference {} computed a different ring in epoch {}", self.conference_id, epoch);
+        if !ring.mismatch_reported {
+            ring.mismatch_reported = true;
+            self.send_ui_event(UIEvent::RingMismatch(self.conference_id)).await;
+        }
+    }
 
+    async fn send_probe(&mut self) {
+        if self.listen_only {
+            warn!("Refused to probe conference {} in listen only mode", self.conference_id);
+            return;
         }
+        let ConferenceState::NormalOperation = self.state
+        else {
+            debug!("Not probing conference {} while it is set up", self.conference_id);
+            return;
+        };
+        debug!("Probing the peers of conference {}", self.conference_id);
+        self.send_message(ClientToClientMessage::Probe, None).await;
     }
 
-    async fn process_text_message(&mut self, message: Vec<u8>) {
-        let Some((message, is_signature_valid)) = self.check_message_signature(message).await
+    async fn answer_probe(&mut self) {
+        if self.listen_only {
+            debug!("Not answering a probe in conference {} in listen only mode", self.conference_id);
+            return;
+        }
+        if self.last_probe_reply.is_some_and(|sent| sent.elapsed() < PROBE_REPLY_MIN_INTERVAL) {
+            return;
+        }
+        self.send_message(ClientToClientMessage::ProbeReply, None).await;
+        self.last_probe_reply = Some(Instant::now());
+    }
+
+    /// Count a peer as reader of all own messages up to the hash in the receipt
+    async fn process_read_receipt(&mut self, receipt: Vec<u8>) {
+        let Some((hash, is_signature_valid, key_image)) = self.check_message_signature(receipt).await
         else {
-            warn!("Received invalid signed message from peer for conference {}", self.conference_id);
+            warn!("Received invalid read receipt from peer for conference {}", self.conference_id);
             return;
         };
-        info!("Received message from peer for conference {}", self.conference_id);
-        self.ui_event_sender.send(UIEvent::IncomingMessage((self.conference_id, message, is_signature_valid))).await.unwrap();
+        let Ok(hash) = MessageHash::try_from(hash)
+        else {
+            warn!("Received read receipt with invalid hash length from peer for conference {}", self.conference_id);
+            return;
+        };
+        if !is_signature_valid || Some(key_image) == self.personal_key_image {
+            return;
+        }
+        let Some(position) = self.recent_messages.iter().position(|(message_hash, _)| message_hash == &hash)
+        else {
+            debug!("Received read receipt for unknown message in conference {}", self.conference_id);
+            return;
+        };
+        let own_messages: Vec<MessageID> = self.recent_messages.iter()
+            .take(position + 1)
+            .filter_map(|(_, message_id)| *message_id)
+            .collect();
+        for message_id in own_messages {
+            let readers = self.message_readers.entry(message_id).or_default();
+            if readers.insert(key_image) {
+                let seen_by = readers.len();
+                self.send_ui_event(UIEvent::MessageSeen((self.conference_id, message_id, seen_by))).await;
+            }
+        }
     }
+
+    /// Count a peer as recipient of the own messages confirmed by the receipt
+    async fn process_delivery_receipt(&mut self, receipt: Vec<u8>) {
+        let Some((hashes, is_signature_valid, key_image)) = self.check_message_signature(receipt).await
+        else {
+            warn!("Received invalid delivery receipt from peer for conference {}", self.conference_id);
+            return;
+        };
+        let Some(hashes) = decode_delivery_receipt(&hashes)
+        else {
+            warn!("Received delivery receipt with invalid hashes from peer for conference {}", self.conference_id);
+            return;
+        };
+        if !is_signature_valid || Some(key_image) == self.personal_key_image {
+            return;
+        }
+        let own_messages: Vec<MessageID> = self.recent_messages.iter()
+            .filter(|(message_hash, _)| hashes.contains(message_hash))
+            .filter_map(|(_, message_id)| *message_id)
+            .collect();
+        let other_peers = self.number_of_peers.saturating_sub(1) as usize;
+        for message_id in own_messages {
+            let recipients = self.message_recipients.entry(message_id).or_default();
+            if recipients.insert(key_image) {
+                // a peer signs with a new key image after every restructuring and may confirm a message again
+                let received_by = recipients.len().min(other_peers);
+                self.send_ui_event(UIEvent::MessageReceivedBy((self.conference_id, message_id, received_by, other_peers))).await;
+            }
+        }
+    }
+}
+
+/// The hashes of the messages confirmed by a delivery receipt, one after another
+fn encode_delivery_receipt(hashes: &[MessageHash]) -> Vec<u8> {
+    hashes.concat()
+}
+
+fn decode_delivery_receipt(receipt: &[u8]) -> Option<Vec<MessageHash>> {
+    let hash_size = std::mem::size_of::<MessageHash>();
+    if receipt.is_empty() || receipt.len() % hash_size != 0 || receipt.len() / hash_size > MAX_DELIVERY_RECEIPT_HASHES {
+        return None;
+    }
+    Some(receipt.chunks_exact(hash_size).map(|hash| hash.try_into().unwrap()).collect())
 }
 
 #[cfg(test)]
 mod tests {
-    use async_std::task;
     use futures::channel::mpsc;
+    use proptest::{collection::vec, prelude::*};
 
     use super::*;
 
+    proptest! {
+        #[test]
+        fn proptest_breakout_round_trip(conference_id in any::<ConferenceId>(), password in "\\PC{0,40}", topic in "\\PC{0,40}") {
+            let breakout = Breakout { conference_id, password, topic };
+            prop_assert_eq!(Breakout::decode(&breakout.encode().unwrap()), Some(breakout));
+        }
+
+        #[test]
+        fn proptest_migration_round_trip(conference_id in any::<ConferenceId>(), password in "\\PC{0,40}", server_address in "\\PC{1,40}") {
+            let migration = Migration { server_address, conference_id, password };
+            prop_assert_eq!(Migration::decode(&migration.encode()), Some(migration));
+        }
+
+        #[test]
+        fn proptest_announcement_decode(data in vec(any::<u8>(), 0..128)) {
+            // never panic on the untrusted payload of a validly signed announcement
+            let _ = Breakout::decode(&data);
+            let _ = Migration::decode(&data);
+        }
+    }
+
+    #[test]
+    fn test_breakout_encode() {
+        let breakout = Breakout { conference_id: 7, password: "secret".to_string(), topic: "Design review".to_string() };
+        assert_eq!(Breakout::decode(&breakout.encode().unwrap()), Some(breakout));
+        assert_eq!(Breakout::decode(&[0, 0, 0, 7, 0, 10, b'a']), None);
+        assert_eq!(Breakout::decode(&[0, 0]), None);
+        // longer than a u8 length could describe
+        let breakout = Breakout { conference_id: 7, password: "p".repeat(300), topic: "Design review".to_string() };
+        assert_eq!(Breakout::decode(&breakout.encode().unwrap()), Some(breakout));
+        let breakout = Breakout { conference_id: 7, password: "p".repeat(u16::MAX as usize + 1), topic: String::new() };
+        assert_eq!(breakout.encode(), None);
+    }
+
+    #[test]
+    fn test_migration_encode() {
+        let migration = Migration { server_address: "other.example:7667".to_string(), conference_id: 7, password: "secret".to_string() };
+        assert_eq!(Migration::decode(&migration.encode()), Some(migration));
+        // without a server address
+        assert_eq!(Migration::decode(&[0, 0, 0, 7, 1, b'a']), None);
+        assert_eq!(Migration::decode(&[0, 0]), None);
+    }
+
+    #[test]
+    fn test_delivery_receipt_encode() {
+        let hashes = [[1; 32], [2; 32], [3; 32]];
+        assert_eq!(decode_delivery_receipt(&encode_delivery_receipt(&hashes)), Some(hashes.to_vec()));
+        assert_eq!(decode_delivery_receipt(&[]), None);
+        assert_eq!(decode_delivery_receipt(&[0; 33]), None);
+        assert_eq!(decode_delivery_receipt(&[0; 32 * (MAX_DELIVERY_RECEIPT_HASHES + 1)]), None);
+    }
+
+    #[test]
+    fn test_delivery_receipts_are_counted_once_per_peer() {
+        let (_, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, _message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+        let mut ring = [1, 2, 3].map(peer_public_key).into_iter().map(CompressedRistretto).collect::<Vec<_>>();
+        ring.sort_unstable();
+        let context = RingContext::new(0, &ring, Scalar::from(1u8)).unwrap();
+        let receipt = |seed: u8, index: usize| {
+            let payload = encode_delivery_receipt(&[[9; 32]]);
+            SignedMessage::new(crypto::sign_message(&Scalar::from(seed), index, &context.ring, &payload), &payload).encode()
+        };
+        let index = |seed: u8| ring.iter().position(|key| key.0 == peer_public_key(seed)).unwrap();
+        // the other peer before and after a restructuring, and someone outside of the ring
+        let receipts = [receipt(2, index(2)), receipt(3, index(3)), receipt(4, index(2))];
+        conference_manager.ring = Some(context);
+        conference_manager.remember_hash([9; 32], Some(1));
+
+        runtime::block_on(async move {
+            for receipt in receipts {
+                conference_manager.process_delivery_receipt(receipt).await;
+            }
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::MessageReceivedBy((0, 1, 1, 1)))));
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::MessageReceivedBy((0, 1, 1, 1)))));
+            assert!(ui_event_receiver.try_next().is_err());
+        });
+    }
+
+    #[test]
+    fn test_chunks_with_invalid_signatures_are_dropped() {
+        use crate::upload::{ChunkAccepted, Uploads};
+
+        let (_, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, _message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, _ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+        let mut ring = [1, 2, 3].map(peer_public_key).into_iter().map(CompressedRistretto).collect::<Vec<_>>();
+        ring.sort_unstable();
+        let context = RingContext::new(0, &ring, Scalar::from(1u8)).unwrap();
+        let index = ring.iter().position(|key| key.0 == peer_public_key(2)).unwrap();
+        let sign = |payload: &[u8], signed: &[u8]| {
+            SignedMessage::new(crypto::sign_message(&Scalar::from(2u8), index, &context.ring, signed), payload).encode()
+        };
+        let mut uploads = Uploads::default();
+        let first = ordering::encode_text(1, &uploads.start(0, 1, Bytes::from_static(b"hello"), 4).encode());
+        let ChunkAccepted::Next(last) = uploads.accepted(0, 1, 0) else { panic!("expected the next chunk") };
+        let forged = ordering::encode_text(1, &Chunk { data: Bytes::from_static(b"!"), ..last.clone() }.encode());
+        let last = ordering::encode_text(1, &last.encode());
+        // the forged chunk copies the key image of the sender with a signature over another chunk
+        let chunks = [sign(&first, &first), sign(&forged, &last), sign(&last, &last)];
+        conference_manager.ring = Some(context);
+
+        runtime::block_on(async move {
+            for chunk in chunks {
+                conference_manager.process_chunk(chunk).await;
+            }
+            let messages = conference_manager.held_messages.release(Instant::now() + ordering::HOLD_BACK);
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].0, Bytes::from_static(b"hello"));
+            assert!(messages[0].1);
+        });
+    }
+
+    #[test]
+    fn test_file_parts_with_invalid_signatures_are_dropped() {
+        let dir = std::env::temp_dir().join(format!("acc-files-test-{}", rand::random::<u32>()));
+        let (_, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, _message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default())
+            .with_incoming_files(IncomingFiles::new(dir.clone(), 1024));
+        let mut ring = [1, 2, 3].map(peer_public_key).into_iter().map(CompressedRistretto).collect::<Vec<_>>();
+        ring.sort_unstable();
+        let context = RingContext::new(0, &ring, Scalar::from(1u8)).unwrap();
+        let index = ring.iter().position(|key| key.0 == peer_public_key(2)).unwrap();
+        let sign = |payload: &[u8], signed: &[u8]| {
+            SignedMessage::new(crypto::sign_message(&Scalar::from(2u8), index, &context.ring, signed), payload).encode()
+        };
+        let content = b"hello world";
+        let file = OutgoingFile::new(9, "notes.txt".to_string(), content, 16);
+        let offer = file.offer.encode();
+        let chunk = Chunk { upload_id: 9, index: 0, total: 1, data: Bytes::from_static(content) }.encode();
+        let forged_chunk = Chunk { upload_id: 9, index: 0, total: 1, data: Bytes::from_static(b"evil world!") }.encode();
+        let complete = file.complete.encode();
+        let forged_complete = FileComplete { hash: [0; 32], ..file.complete.clone() }.encode();
+        // the forged parts copy the key image of the sender with a signature over another part
+        let (offer, forged_chunk, forged_complete) = (sign(&offer, &offer), sign(&forged_chunk, &chunk), sign(&forged_complete, &complete));
+        let (chunk, complete) = (sign(&chunk, &chunk), sign(&complete, &complete));
+        conference_manager.ring = Some(context);
+
+        runtime::block_on(async move {
+            conference_manager.process_file_offer(offer).await;
+            conference_manager.process_file_chunk(forged_chunk).await;
+            conference_manager.process_file_complete(forged_complete).await;
+            conference_manager.process_file_chunk(chunk).await;
+            conference_manager.process_file_complete(complete).await;
+            let mut received = None;
+            while let Ok(Some(ui_event)) = ui_event_receiver.try_next() {
+                if let UIEvent::FileReceived((0, file)) = ui_event {
+                    received = Some(file);
+                }
+            }
+            assert_eq!(std::fs::read(received.unwrap().path).unwrap(), content);
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_ring_context() {
+        let mut csprng = rand_core::OsRng;
+        let private_key = Scalar::random(&mut csprng);
+        let public_key = (private_key * RISTRETTO_BASEPOINT_POINT).compress();
+        let mut compressed_ring = vec![RistrettoPoint::random(&mut csprng).compress(), public_key, RistrettoPoint::random(&mut csprng).compress()];
+        compressed_ring.sort_unstable();
+        let context = RingContext::new(3, &compressed_ring, private_key).unwrap();
+        assert_eq!(compressed_ring[context.personal_key_index], public_key);
+
+        let signature = crypto::sign_message(&context.personal_private_key, context.personal_key_index, &context.ring, b"hi");
+        let key_image = signature.key_image.compress().to_bytes();
+        let mut signed_message = SignedMessage::new(signature, b"hi").encode();
+
+        let (message, signature_valid, verified_key_image) = context.verify(&signed_message).unwrap();
+        assert_eq!(message, b"hi");
+        assert!(signature_valid);
+        assert_eq!(verified_key_image, key_image);
+        // the same context verifies the next message
+        *signed_message.last_mut().unwrap() = b'o';
+        assert!(!context.verify(&signed_message).unwrap().1);
+
+        // a peer sent a key that is not a point, or the own key is missing
+        let mut invalid_ring = vec![CompressedRistretto([0xff; 32]), public_key];
+        invalid_ring.sort_unstable();
+        assert!(RingContext::new(3, &invalid_ring, private_key).is_err());
+        assert!(RingContext::new(3, &compressed_ring, Scalar::random(&mut csprng)).is_err());
+    }
+
+    #[test]
+    fn test_ring_hash() {
+        let mut csprng = rand_core::OsRng;
+        let private_key = Scalar::random(&mut csprng);
+        let public_key = (private_key * RISTRETTO_BASEPOINT_POINT).compress();
+        let mut compressed_ring = vec![RistrettoPoint::random(&mut csprng).compress(), public_key];
+        compressed_ring.sort_unstable();
+        let context = RingContext::new(3, &compressed_ring, private_key).unwrap();
+        let announcement = context.encode_hash();
+        assert_eq!(RingContext::decode_hash(&announcement), Some((3, context.hash, context.ring.clone(), announcement.len())));
+        assert_eq!(RingContext::decode_hash(&announcement[..announcement.len() - 1]), None);
+        // the announced ring has to have the announced hash
+        let mut forged = announcement.clone();
+        forged[4] ^= 1;
+        assert_eq!(RingContext::decode_hash(&forged), None);
+
+        // a peer that was shown another member computes a different hash
+        let mut other_ring = vec![RistrettoPoint::random(&mut csprng).compress(), public_key];
+        other_ring.sort_unstable();
+        assert_ne!(RingContext::new(3, &other_ring, private_key).unwrap().hash, context.hash);
+        assert_eq!(RingContext::new(4, &compressed_ring, private_key).unwrap().hash, context.hash);
+    }
+
     #[test]
     fn test_start_conference_manager() {
         let (_, conference_event_receiver) = mpsc::unbounded();
         let (message_sender, _) = mpsc::unbounded();
         let (ui_event_sender, _) = mpsc::unbounded();
-        let mut conference_manager = ConferenceManager::new( 0, 1, [0; 32], conference_event_receiver, message_sender, ui_event_sender);
+        let mut conference_manager = ConferenceManager::new( 0, 1, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {conference_manager.start_conference_manager().await.unwrap()});
+    }
+
+    #[test]
+    fn test_conference_manager_stops_when_ui_is_gone() {
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, _message_receiver) = mpsc::unbounded();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            let manager = runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::OwnFingerprint((0, _)))));
+            // the other peer never answers, so outbound messages are reported as errors
+            conference_event_sender.send(ConferenceEvent::OutboundMessage((1, Bytes::from_static(b"hi")))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::MessageError((0, 1)))));
+
+            drop(ui_event_receiver);
+            conference_event_sender.send(ConferenceEvent::OutboundMessage((2, Bytes::from_static(b"hi")))).await.unwrap();
+            runtime::timeout(Duration::from_secs(5), manager).await.unwrap().unwrap();
+        });
+    }
+
+    /// The next UI event of a manager, skipping the setup progress
+    async fn next_ui_event(ui_event_receiver: &mut Receiver<UIEvent>) -> Option<UIEvent> {
+        loop {
+            match ui_event_receiver.next().await {
+                Some(UIEvent::SetupProgress(_)) | Some(UIEvent::Anonymity(_)) => continue,
+                ui_event => return ui_event,
+            }
+        }
+    }
+
+    /// A key exchange message of a peer, encrypted with the initial key `[0; 32]`
+    fn key_exchange_message(message: ClientToClientMessage) -> ConferenceEvent {
+        let encrypted = crypto::encrypt_message(&message.encode().unwrap(), &[0; 32]).unwrap();
+        ConferenceEvent::IncomingMessage(encrypted.encode().into())
+    }
+
+    /// Decrypt a key exchange message sent by the manager
+    fn sent_key_exchange_message(message: Option<Message>) -> ClientToClientMessage {
+        let encrypted = crypto::EncryptionResult::decode(&message.unwrap().message).unwrap();
+        ClientToClientMessage::decode(&crypto::decrypt_message(&[0; 32], &encrypted).unwrap()).unwrap()
+    }
+
+    fn peer_public_key(seed: u8) -> [u8; 32] {
+        (Scalar::from(seed) * RISTRETTO_BASEPOINT_POINT).compress().to_bytes()
+    }
+
+    #[test]
+    fn test_stray_public_key_renegotiates() {
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, mut message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::OwnFingerprint((0, _)))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(1))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((0, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+
+            // the peer rejoined with a new key while the conference kept its size
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(2))))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRenegotiating(0))));
+            // public key, key part and ring hash of the first exchange, then the public key of the next epoch
+            assert!(matches!(sent_key_exchange_message(message_receiver.next().await), ClientToClientMessage::PublicKey((0, _))));
+            assert!(matches!(sent_key_exchange_message(message_receiver.next().await), ClientToClientMessage::EncryptionKeyPart((0, _))));
+            assert!(matches!(sent_key_exchange_message(message_receiver.next().await), ClientToClientMessage::RingHash(_)));
+            assert!(matches!(sent_key_exchange_message(message_receiver.next().await), ClientToClientMessage::PublicKey((1, _))));
+
+            // the peer follows into the next epoch
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((1, peer_public_key(2))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((1, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(sent_key_exchange_message(message_receiver.next().await), ClientToClientMessage::EncryptionKeyPart((1, _))));
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+        });
+    }
+
+    #[test]
+    fn test_setup_progress() {
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, _message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 3, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::OwnFingerprint((0, _)))));
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::SetupProgress((0, SetupStage::KeyExchange((1, 3)))))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(1))))).await.unwrap();
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::SetupProgress((0, SetupStage::KeyExchange((2, 3)))))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(2))))).await.unwrap();
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::SetupProgress((0, SetupStage::KeyNegotiation((0, 2)))))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((0, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::SetupProgress((0, SetupStage::KeyNegotiation((1, 2)))))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((0, vec![1; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::Anonymity((0, Anonymity { ring_size: 3, .. })))));
+        });
+        assert_eq!(SetupStage::KeyExchange((1, 3)).fraction(), 1.0 / 6.0);
+        assert_eq!(SetupStage::KeyNegotiation((2, 2)).fraction(), 1.0);
+    }
+
+    #[test]
+    fn test_anonymity_follows_the_modes() {
+        use crate::anonymity::Linkability;
+
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, _message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(1))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((0, vec![0; KEY_SIZE])))).await.unwrap();
+            let anonymity = next_anonymity(&mut ui_event_receiver).await;
+            assert_eq!((anonymity.linkability, anonymity.listen_only), (Linkability::Conference, false));
+
+            conference_event_sender.send(ConferenceEvent::SetListenOnly(true)).await.unwrap();
+            assert!(next_anonymity(&mut ui_event_receiver).await.listen_only);
+        });
+
+        async fn next_anonymity(ui_event_receiver: &mut Receiver<UIEvent>) -> Anonymity {
+            while let Some(ui_event) = ui_event_receiver.next().await {
+                if let UIEvent::Anonymity((0, anonymity)) = ui_event {
+                    return anonymity;
+                }
+            }
+            panic!("the conference manager stopped");
+        }
+    }
+
+    #[test]
+    fn test_invalid_public_key_is_dropped() {
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, _message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::OwnFingerprint((0, _)))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, [0xff; 32])))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(1))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((0, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+        });
+    }
+
+    #[test]
+    fn test_overlapping_restructurings() {
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, mut message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::OwnFingerprint((0, _)))));
+            // a peer joins and leaves again before the exchange of its epoch finishes
+            conference_event_sender.send(ConferenceEvent::ConferenceRestructuring(3)).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((1, peer_public_key(1))))).await.unwrap();
+            conference_event_sender.send(ConferenceEvent::ConferenceRestructuring(2)).await.unwrap();
+            // late messages of the overtaken epoch would complete the ring with the peer that left
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((1, peer_public_key(2))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((1, vec![1; KEY_SIZE])))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((2, peer_public_key(1))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((2, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+
+            let mut sent = Vec::new();
+            for _ in 0..4 {
+                sent.push(sent_key_exchange_message(message_receiver.next().await));
+            }
+            assert!(matches!(sent[..], [
+                ClientToClientMessage::PublicKey((0, _)),
+                ClientToClientMessage::PublicKey((1, _)),
+                ClientToClientMessage::PublicKey((2, _)),
+                ClientToClientMessage::EncryptionKeyPart((2, _)),
+            ]));
+        });
+    }
+
+    #[test]
+    fn test_joined_peer_adopts_newer_epoch() {
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, mut message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::OwnFingerprint((0, _)))));
+            // the other peer was already in the conference for a few restructurings
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((5, peer_public_key(1))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((5, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+
+            assert!(matches!(sent_key_exchange_message(message_receiver.next().await), ClientToClientMessage::PublicKey((0, _))));
+            assert!(matches!(sent_key_exchange_message(message_receiver.next().await), ClientToClientMessage::PublicKey((5, _))));
+            assert!(matches!(sent_key_exchange_message(message_receiver.next().await), ClientToClientMessage::EncryptionKeyPart((5, _))));
+        });
+    }
+
+    #[test]
+    fn test_renegotiation_is_rate_limited() {
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, _message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::OwnFingerprint((0, _)))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(1))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((0, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(2))))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRenegotiating(0))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((1, peer_public_key(2))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((1, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+
+            // the same peer restarts right away again, and jumps epochs ahead
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((1, peer_public_key(2))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((9, peer_public_key(2))))).await.unwrap();
+            drop(conference_event_sender);
+            assert!(next_ui_event(&mut ui_event_receiver).await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_ring_mismatch_is_reported() {
+        let (mut conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, mut message_receiver) = mpsc::unbounded::<Message>();
+        let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+        let mut conference_manager = ConferenceManager::new(0, 2, [0; 32], conference_event_receiver, message_sender, ui_event_sender, Default::default(), Default::default());
+
+        runtime::block_on(async move {
+            runtime::spawn(async move { conference_manager.start_conference_manager().await });
+            assert!(matches!(ui_event_receiver.next().await, Some(UIEvent::OwnFingerprint((0, _)))));
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::PublicKey((0, peer_public_key(1))))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::EncryptionKeyPart((0, vec![0; KEY_SIZE])))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::ConferenceRestructuringFinished(0))));
+            message_receiver.next().await;
+            message_receiver.next().await;
+            let ClientToClientMessage::RingHash(own_announcement) = sent_key_exchange_message(message_receiver.next().await)
+            else {
+                panic!("expected the ring hash after the setup");
+            };
+
+            // a hash that does not belong to the announced ring is no reason for alarm
+            let mut forged = own_announcement.clone();
+            forged[4] ^= 1;
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::RingHash(forged))).await.unwrap();
 
-        task::block_on(async move {conference_manager.start_conference_manager().await.unwrap()});
+            // the peer was shown another member instead of this one, its signature is verified against its own ring
+            let peer_private_key = Scalar::from(1u8);
+            let mut other_ring = vec![peer_public_key(1), peer_public_key(3)].into_iter().map(CompressedRistretto).collect::<Vec<_>>();
+            other_ring.sort_unstable();
+            let other_context = RingContext::new(0, &other_ring, peer_private_key).unwrap();
+            let mut announcement = other_context.encode_hash();
+            let signature = crypto::sign_message(&peer_private_key, other_context.personal_key_index, &other_context.ring, &announcement);
+            let signed_announcement = SignedMessage::new(signature, &announcement).encode();
+            announcement.extend_from_slice(&signed_announcement);
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::RingHash(announcement.clone()))).await.unwrap();
+            conference_event_sender.send(key_exchange_message(ClientToClientMessage::RingHash(announcement))).await.unwrap();
+            assert!(matches!(next_ui_event(&mut ui_event_receiver).await, Some(UIEvent::RingMismatch(0))));
+            // reported once per epoch
+            drop(conference_event_sender);
+            assert!(next_ui_event(&mut ui_event_receiver).await.is_none());
+        });
     }
 }
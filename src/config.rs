@@ -0,0 +1,728 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use crate::paths;
+
+const DEFAULT_UPDATE_MANIFEST_URL: &str = "https://raw.githubusercontent.com/L20L021902/anonymous-conference-client/main/version.toml";
+/// An address instead of a name, so reaching the resolver needs no lookup of its own
+const DEFAULT_DOH_URL: &str = "https://1.1.1.1/dns-query";
+const DEFAULT_SERVER_ADDRESS: &str = "localhost:7667";
+
+/// Settings read from the config file, every value is optional
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub log: LogConfig,
+    pub update_check: UpdateCheckConfig,
+    pub flood_protection: FloodProtectionConfig,
+    pub relay: RelayConfig,
+    pub dns: DnsConfig,
+    pub storage: StorageConfig,
+    pub lock: LockConfig,
+    pub history: HistoryConfig,
+    pub files: FilesConfig,
+    pub servers: ServersConfig,
+    pub appearance: AppearanceConfig,
+    pub recent: RecentConfig,
+    pub cli: CliConfig,
+    pub window: WindowConfig,
+    pub idle: IdleConfig,
+    pub ui: UiConfig,
+    pub tls: TlsConfig,
+    pub argon2: Argon2Config,
+    pub reconnect: ReconnectConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Per-module level filters in the `RUST_LOG` format, e.g. `info,anonymous_conference_client::connection_manager=debug`
+    pub filter: Option<String>,
+    /// Size in bytes after which the log file is rotated
+    pub max_file_size: u64,
+    /// Number of rotated log files to keep
+    pub max_files: usize,
+    /// Also write log lines to stderr
+    pub stderr: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            filter: None,
+            max_file_size: 5 * 1024 * 1024,
+            max_files: 3,
+            stderr: true,
+        }
+    }
+}
+
+/// The update check is opt-in, it makes a single request for the version manifest on startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateCheckConfig {
+    pub enabled: bool,
+    pub manifest_url: String,
+}
+
+impl Default for UpdateCheckConfig {
+    fn default() -> Self {
+        UpdateCheckConfig {
+            enabled: false,
+            manifest_url: DEFAULT_UPDATE_MANIFEST_URL.to_string(),
+        }
+    }
+}
+
+/// Limits for incoming messages of a single sender, beyond them the sender is muted for a while
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FloodProtectionConfig {
+    pub enabled: bool,
+    /// Number of messages allowed per interval
+    pub max_messages: usize,
+    pub interval_secs: u64,
+    /// How long a flooding sender stays muted
+    pub mute_secs: u64,
+}
+
+impl Default for FloodProtectionConfig {
+    fn default() -> Self {
+        FloodProtectionConfig {
+            enabled: true,
+            max_messages: 20,
+            interval_secs: 10,
+            mute_secs: 60,
+        }
+    }
+}
+
+/// Servers the connection is forwarded through before reaching the conference server
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RelayConfig {
+    /// Addresses of the relays, starting with the first hop, empty to connect directly
+    pub chain: Vec<String>,
+}
+
+/// How server names are resolved, see [`crate::dns`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsConfig {
+    pub resolver: ResolverKind,
+    /// Endpoint of the DNS-over-HTTPS resolver, queries are sent as RFC 8484 POST requests
+    pub doh_url: String,
+    /// Addresses by host name for the static resolver
+    pub hosts: BTreeMap<String, String>,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        DnsConfig {
+            resolver: ResolverKind::System,
+            doh_url: DEFAULT_DOH_URL.to_string(),
+            hosts: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResolverKind {
+    /// The resolver of the operating system
+    #[default]
+    System,
+    /// DNS over HTTPS, the local network only sees a TLS connection to the resolver
+    Doh,
+    /// Only the addresses in `dns.hosts`, nothing is looked up
+    Static,
+}
+
+/// Encryption of the files in the data directory, see [`crate::storage`]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Encrypt with a key derived from a master password asked for at startup
+    pub encrypt: bool,
+    /// Remember the master password in the keyring of the operating system
+    pub keyring: bool,
+}
+
+/// The lock of the GTK frontend, it needs the master password of [`StorageConfig`]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LockConfig {
+    /// Minutes without input before the window is locked, `0` only locks with the shortcut
+    pub after_mins: u64,
+}
+
+/// The server to connect to, and servers connected to in addition to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServersConfig {
+    /// Set by `--server-address`
+    pub address: String,
+    pub additional: Vec<String>,
+}
+
+impl Default for ServersConfig {
+    fn default() -> Self {
+        ServersConfig {
+            address: DEFAULT_SERVER_ADDRESS.to_string(),
+            additional: Vec::new(),
+        }
+    }
+}
+
+impl ServersConfig {
+    /// All servers to connect to, starting with `server_address`, without duplicates
+    pub fn addresses(&self, server_address: &str) -> Vec<String> {
+        let mut addresses = vec![server_address.to_string()];
+        for address in &self.additional {
+            if !addresses.contains(address) {
+                addresses.push(address.clone());
+            }
+        }
+        addresses
+    }
+}
+
+/// Messages kept in memory by the UIs and on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Messages kept per conference, the oldest are dropped beyond it
+    pub max_messages: usize,
+    /// Keep the messages of every conference on disk, encrypted with a key derived from its password, see [`crate::history_store`]
+    pub persist: bool,
+    /// Messages from disk shown when a conference is joined again
+    pub load_recent: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            max_messages: 1000,
+            persist: false,
+            load_recent: 50,
+        }
+    }
+}
+
+/// Files sent to and received from conferences, see [`crate::file_transfer`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilesConfig {
+    /// Write the files of peers to the downloads directory, otherwise their offers are ignored
+    pub receive: bool,
+    /// Largest file sent or received, in MiB
+    pub max_size_mib: u64,
+}
+
+impl FilesConfig {
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mib * 1024 * 1024
+    }
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        FilesConfig {
+            receive: true,
+            max_size_mib: 64,
+        }
+    }
+}
+
+/// How the GTK frontend looks
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppearanceConfig {
+    pub density: Density,
+}
+
+/// Spacing of the message list
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Density {
+    /// Every message with its author and status
+    #[default]
+    Cozy,
+    /// Less spacing, consecutive messages of the same author are grouped and only warnings have an icon
+    Compact,
+}
+
+/// Recently used servers and conferences offered as suggestions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecentConfig {
+    /// Remember servers and conferences in the data directory
+    pub enabled: bool,
+    /// Entries kept per list
+    pub max_entries: usize,
+}
+
+impl Default for RecentConfig {
+    fn default() -> Self {
+        RecentConfig {
+            enabled: true,
+            max_entries: 10,
+        }
+    }
+}
+
+/// Conferences without messages are left automatically, see [`crate::idle`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IdleConfig {
+    /// Minutes without sent or received messages before a conference is left, `0` never leaves
+    pub leave_after_mins: u64,
+    /// Minutes before leaving that the UI is warned
+    pub warn_before_mins: u64,
+    /// Minutes without user input before all conferences are left and the server is disconnected, `0` never disconnects
+    pub disconnect_after_mins: u64,
+    /// Connect again on the next input after disconnecting for inactivity
+    pub reconnect_on_activity: bool,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        IdleConfig {
+            leave_after_mins: 0,
+            warn_before_mins: 5,
+            disconnect_after_mins: 0,
+            reconnect_on_activity: false,
+        }
+    }
+}
+
+/// The GTK main window
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowConfig {
+    pub on_close: CloseAction,
+}
+
+/// What closing the main window does
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CloseAction {
+    /// Leave the server in an orderly way and quit
+    #[default]
+    Quit,
+    /// Minimize the window, GTK 4 has no tray icons
+    Minimize,
+    /// Hide the window and stay connected, incoming messages are announced with desktop notifications
+    Background,
+}
+
+/// The terminal frontend
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CliConfig {
+    /// Draw attention to incoming messages, e.g. to mark the window in a terminal multiplexer
+    pub notification: TerminalNotification,
+    /// Print lines instead of the full screen interface, set by `--cli-plain`
+    pub plain: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalNotification {
+    #[default]
+    None,
+    /// The terminal bell
+    Bell,
+    /// A desktop notification with the message, supported by e.g. VTE based terminals, kitty and WezTerm
+    Osc777,
+}
+
+/// Which frontend is started
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    /// Set to `cli` by `--cli`, builds with only one frontend always start it
+    pub frontend: Frontend,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Frontend {
+    #[default]
+    Gtk,
+    Cli,
+}
+
+/// Certificates trusted for the TLS sessions with the server and the relays, the system trust store is always used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM files with one root certificate each, for deployments with a certificate of their own
+    pub certificates: Vec<PathBuf>,
+    /// Also trust the certificate bundled with the client
+    pub bundled_certificate: bool,
+    /// Hex SHA-256 fingerprints of the public keys the server may present, see [`crate::pinning`]; empty to not pin
+    pub pins: Vec<String>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            certificates: Vec::new(),
+            bundled_certificate: true,
+            pins: Vec::new(),
+        }
+    }
+}
+
+/// Argon2id parameters for conference passwords, the hashes only match between clients using the same values
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Argon2Config {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Argon2Config {
+            memory_kib: argon2::Params::DEFAULT_M_COST,
+            iterations: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Argon2Config {
+    /// Values argon2 does not accept are reported and replaced by the defaults
+    pub fn params(&self) -> argon2::Params {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None).unwrap_or_else(|e| {
+            warn!("Invalid argon2 parameters {:?}, using the defaults: {}", self, e);
+            argon2::Params::default()
+        })
+    }
+}
+
+/// Connecting again after the connection to a server was lost, see [`crate::rejoin`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// Attempts without a successful handshake before giving up, `0` only reconnects after network changes
+    pub max_attempts: u32,
+    /// Delay before the first attempt, doubled for every further attempt
+    pub first_delay_secs: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            max_attempts: 5,
+            first_delay_secs: 1,
+        }
+    }
+}
+
+/// Prefix of the environment variables overriding settings, `ACC_LOG_MAX_FILES` sets `log.max_files`
+pub const ENV_PREFIX: &str = "ACC_";
+
+/// Settings without a default value, they are not in the serialized defaults
+const OPTIONAL_SETTINGS: &[&str] = &["log.filter"];
+
+/// Settings given on the command line, see [`set_flag_overrides`]
+static FLAG_OVERRIDES: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+/// Where the effective value of a setting comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SettingSource {
+    Default,
+    File(PathBuf),
+    Env(String),
+    Flag,
+}
+
+impl std::fmt::Display for SettingSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingSource::Default => write!(f, "default"),
+            SettingSource::File(path) => write!(f, "config file {}", path.display()),
+            SettingSource::Env(name) => write!(f, "environment variable {}", name),
+            SettingSource::Flag => write!(f, "command line"),
+        }
+    }
+}
+
+/// The effective configuration with the value and source of every setting
+#[derive(Debug, Clone)]
+pub struct ResolvedConfig {
+    pub config: Config,
+    /// by dotted key, e.g. `log.max_files`
+    pub settings: BTreeMap<String, (toml::Value, SettingSource)>,
+}
+
+impl std::fmt::Display for ResolvedConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (key, (value, source)) in &self.settings {
+            writeln!(f, "{} = {} # {}", key, value, source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Override settings for every later [`Config::load`], later overrides of the same key win.
+/// Only the first call has an effect, it is meant to be called once with the command line.
+pub fn set_flag_overrides(overrides: Vec<(String, String)>) {
+    let _ = FLAG_OVERRIDES.set(overrides);
+}
+
+impl Config {
+    /// Load the settings from the command line, the environment, the config file and the defaults, in that order
+    pub fn load() -> Config {
+        Config::resolve().config
+    }
+
+    /// Like [`Config::load`] but with the sources of the settings
+    pub fn resolve() -> ResolvedConfig {
+        let flags = FLAG_OVERRIDES.get().map(Vec::as_slice).unwrap_or_default();
+        Config::resolve_from(&paths::config_file(), |name| std::env::var(name).ok(), flags)
+    }
+
+    /// Settings that can not be used are reported on stderr and skipped, the logger is not initialized yet
+    pub fn resolve_from(path: &Path, env: impl Fn(&str) -> Option<String>, flags: &[(String, String)]) -> ResolvedConfig {
+        let defaults = toml::Value::try_from(Config::default()).expect("the defaults serialize");
+        let mut merged = defaults.as_table().cloned().unwrap_or_default();
+        let mut sources: BTreeMap<String, SettingSource> = BTreeMap::new();
+        let mut known: BTreeSet<String> = flatten(&merged).into_keys().collect();
+        known.extend(OPTIONAL_SETTINGS.iter().map(|key| key.to_string()));
+
+        if let Some(file) = read_config_file(path) {
+            for (key, value) in flatten(&file) {
+                set_dotted(&mut merged, &key, value);
+                sources.insert(key.clone(), SettingSource::File(path.to_path_buf()));
+                known.insert(key);
+            }
+        }
+
+        let env_overrides: Vec<(String, String, SettingSource)> = known.iter()
+            .filter_map(|key| {
+                let name = format!("{}{}", ENV_PREFIX, key.replace('.', "_").to_uppercase());
+                env(&name).map(|value| (key.clone(), value, SettingSource::Env(name)))
+            })
+            .collect();
+        let flag_overrides = flags.iter().map(|(key, value)| (key.clone(), value.clone(), SettingSource::Flag));
+        for (key, value, source) in env_overrides.into_iter().chain(flag_overrides) {
+            if !known.contains(&key) {
+                eprintln!("Unknown setting {} from {}", key, source);
+                continue;
+            }
+            let mut candidate = merged.clone();
+            set_dotted(&mut candidate, &key, parse_value(&value));
+            match toml::Value::Table(candidate.clone()).try_into::<Config>() {
+                Ok(_) => {
+                    merged = candidate;
+                    sources.insert(key, source);
+                },
+                Err(e) => eprintln!("Invalid value {:?} for {} from {}: {}", value, key, source, e),
+            }
+        }
+
+        let config = toml::Value::Table(merged.clone()).try_into::<Config>().unwrap_or_default();
+        let settings = flatten(&merged).into_iter()
+            .map(|(key, value)| {
+                let source = sources.remove(&key).unwrap_or(SettingSource::Default);
+                (key, (value, source))
+            })
+            .collect();
+        ResolvedConfig { config, settings }
+    }
+
+    /// Only the config file, without the environment and the command line
+    pub fn load_from(path: &Path) -> Config {
+        read_config_file(path)
+            .and_then(|file| toml::Value::Table(file).try_into().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn parse(contents: &str) -> std::result::Result<Config, toml::de::Error> {
+        toml::Value::Table(parse_table(contents)?).try_into()
+    }
+}
+
+/// The settings of a config file, with the settings of earlier versions moved to their current keys
+fn parse_table(contents: &str) -> std::result::Result<toml::Table, toml::de::Error> {
+    let mut table = contents.parse::<toml::Table>()?;
+    if let Some(toml::Value::Table(tls)) = table.get_mut("tls") {
+        // a single certificate trusted instead of the bundled one
+        if let Some(certificate) = tls.remove("certificate") {
+            eprintln!("tls.certificate is deprecated, use tls.certificates");
+            if let toml::Value::Array(certificates) = tls.entry("certificates").or_insert_with(|| toml::Value::Array(Vec::new())) {
+                certificates.push(certificate);
+            }
+            tls.entry("bundled_certificate").or_insert(toml::Value::Boolean(false));
+        }
+    }
+    Ok(table)
+}
+
+/// The settings of a valid config file, invalid files are reported and ignored as a whole
+fn read_config_file(path: &Path) -> Option<toml::Table> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            warn!("Could not read config file {}: {}", path.display(), e);
+            return None;
+        },
+    };
+    match Config::parse(&contents).and_then(|_| parse_table(&contents)) {
+        Ok(table) => Some(table),
+        Err(e) => {
+            // the logger is not initialized yet
+            eprintln!("Invalid config file {}: {}", path.display(), e);
+            None
+        },
+    }
+}
+
+/// Settings by dotted key, arrays are single settings
+fn flatten(table: &toml::Table) -> BTreeMap<String, toml::Value> {
+    let mut settings = BTreeMap::new();
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(inner) => {
+                for (inner_key, inner_value) in flatten(inner) {
+                    settings.insert(format!("{}.{}", key, inner_key), inner_value);
+                }
+            },
+            value => {
+                settings.insert(key.clone(), value.clone());
+            },
+        }
+    }
+    settings
+}
+
+fn set_dotted(table: &mut toml::Table, key: &str, value: toml::Value) {
+    match key.split_once('.') {
+        Some((section, rest)) => {
+            let inner = table.entry(section).or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if !inner.is_table() {
+                *inner = toml::Value::Table(toml::Table::new());
+            }
+            if let toml::Value::Table(inner) = inner {
+                set_dotted(inner, rest, value);
+            }
+        },
+        None => {
+            table.insert(key.to_string(), value);
+        },
+    }
+}
+
+/// A value from the environment or the command line in TOML syntax, anything else is a string
+fn parse_value(value: &str) -> toml::Value {
+    format!("value = {}", value).parse::<toml::Table>().ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse("[log]\nfilter = \"debug\"\nmax_files = 5\n").unwrap();
+        assert_eq!(config.log.filter.as_deref(), Some("debug"));
+        assert_eq!(config.log.max_files, 5);
+        assert_eq!(config.log.max_file_size, LogConfig::default().max_file_size);
+    }
+
+    #[test]
+    fn test_parse_empty_config() {
+        let config = Config::parse("").unwrap();
+        assert!(config.log.filter.is_none());
+        assert!(config.log.stderr);
+        assert!(!config.update_check.enabled);
+        assert!(config.flood_protection.enabled);
+        assert!(config.relay.chain.is_empty());
+        assert_eq!(config.history.max_messages, 1000);
+        assert_eq!(config.servers.addresses("a:1"), vec!["a:1"]);
+        assert_eq!(config.appearance.density, Density::Cozy);
+        assert_eq!(config.servers.address, "localhost:7667");
+        assert_eq!(config.ui.frontend, Frontend::Gtk);
+        assert!(config.tls.certificates.is_empty());
+        assert!(config.tls.bundled_certificate);
+        assert_eq!(config.argon2.params(), argon2::Params::default());
+    }
+
+    #[test]
+    fn test_parse_connection_settings() {
+        let config = Config::parse("[ui]\nfrontend = \"cli\"\n[tls]\ncertificates = [\"/etc/acc/server.pem\"]\nbundled_certificate = false\n[argon2]\nmemory_kib = 65536\n[reconnect]\nmax_attempts = 0\n").unwrap();
+        assert_eq!(config.ui.frontend, Frontend::Cli);
+        assert_eq!(config.tls.certificates, vec![PathBuf::from("/etc/acc/server.pem")]);
+        assert!(!config.tls.bundled_certificate);
+        assert_eq!(config.argon2.params().m_cost(), 65536);
+        assert_eq!(config.argon2.params().t_cost(), argon2::Params::DEFAULT_T_COST);
+        assert_eq!(config.reconnect.max_attempts, 0);
+        assert_eq!(config.reconnect.first_delay_secs, 1);
+        // parallelism 0 is rejected by argon2
+        let config = Config::parse("[argon2]\nparallelism = 0\n").unwrap();
+        assert_eq!(config.argon2.params(), argon2::Params::default());
+    }
+
+    #[test]
+    fn test_parse_single_certificate() {
+        let config = Config::parse("[tls]\ncertificate = \"/etc/acc/server.pem\"\n").unwrap();
+        assert_eq!(config.tls.certificates, vec![PathBuf::from("/etc/acc/server.pem")]);
+        assert!(!config.tls.bundled_certificate);
+        let config = Config::parse("[tls]\ncertificate = \"/etc/acc/old.pem\"\ncertificates = [\"/etc/acc/new.pem\"]\nbundled_certificate = true\n").unwrap();
+        assert_eq!(config.tls.certificates, vec![PathBuf::from("/etc/acc/new.pem"), PathBuf::from("/etc/acc/old.pem")]);
+        assert!(config.tls.bundled_certificate);
+    }
+
+    #[test]
+    fn test_parse_density() {
+        let config = Config::parse("[appearance]\ndensity = \"compact\"\n").unwrap();
+        assert_eq!(config.appearance.density, Density::Compact);
+        assert!(Config::parse("[appearance]\ndensity = \"tiny\"\n").is_err());
+    }
+
+    #[test]
+    fn test_resolve_config() {
+        let path = std::env::temp_dir().join(format!("acc-config-test-{}.toml", rand::random::<u32>()));
+        std::fs::write(&path, "[log]\nmax_files = 5\nmax_file_size = 100\n").unwrap();
+        let env = |name: &str| match name {
+            "ACC_LOG_MAX_FILES" => Some("7".to_string()),
+            "ACC_HISTORY_MAX_MESSAGES" => Some("many".to_string()),
+            "ACC_APPEARANCE_DENSITY" => Some("compact".to_string()),
+            _ => None,
+        };
+        let flags = [("log.max_files".to_string(), "9".to_string()), ("log.colour".to_string(), "true".to_string())];
+        let resolved = Config::resolve_from(&path, env, &flags);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(resolved.config.log.max_files, 9);
+        assert_eq!(resolved.settings["log.max_files"].1, SettingSource::Flag);
+        assert_eq!(resolved.config.log.max_file_size, 100);
+        assert_eq!(resolved.settings["log.max_file_size"].1, SettingSource::File(path));
+        assert_eq!(resolved.config.appearance.density, Density::Compact);
+        assert_eq!(resolved.settings["appearance.density"].1, SettingSource::Env("ACC_APPEARANCE_DENSITY".to_string()));
+        // invalid and unknown overrides are skipped
+        assert_eq!(resolved.config.history.max_messages, 1000);
+        assert_eq!(resolved.settings["history.max_messages"].1, SettingSource::Default);
+        assert!(!resolved.settings.contains_key("log.colour"));
+        assert!(resolved.to_string().contains("log.max_files = 9 # command line\n"));
+    }
+
+    #[test]
+    fn test_server_addresses() {
+        let config = Config::parse("[servers]\nadditional = [\"b:2\", \"a:1\", \"c:3\"]\n").unwrap();
+        assert_eq!(config.servers.addresses("a:1"), vec!["a:1", "b:2", "c:3"]);
+    }
+}
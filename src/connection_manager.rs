@@ -1,234 +1,541 @@
-use log::{debug, warn};
-use async_native_tls::{TlsConnector, Certificate};
-use async_std::{
-    io::{BufReader, BufRead, BufWriter},
-    net::{TcpStream, ToSocketAddrs},
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use async_native_tls::{TlsConnector, TlsStream, Certificate};
+use bytes::BytesMut;
+use futures::{select, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, FutureExt, sink::SinkExt, StreamExt};
+use crate::{
+    codec::{self, ClientCodec, ConnectionCodec, Encoder},
+    config::{DnsConfig, TlsConfig},
+    dns,
+    heartbeat::{self, Beat, Heartbeat},
+    pinning,
+    constants::{
+        Result, Sender, Receiver, ServerEvent, ClientEvent, DisconnectReason, HandshakeDiagnosis, SERVER_NAME, PROTOCOL_HEADER, ServerToClientMessageTypePrimitive, ServerCapabilityBits,
+        SERVER_CAPABILITY_DIRECTORY, SERVER_CAPABILITY_RELAY, SERVER_CAPABILITY_HEARTBEAT, SERVER_CAPABILITY_RESUME, SERVER_CAPABILITY_MAX_MESSAGE_SIZE,
+        SERVER_CAPABILITY_WIRE_SCHEMA,
+        RELAY_OPEN, RELAY_OPENED, RELAY_ERROR,
+    },
+    runtime::{self, ToSocketAddrs},
+    protocol_dump::{self, DumpedEvent},
+    trace::{self, Direction, TraceRecord},
 };
-use futures::{select, AsyncReadExt, AsyncWriteExt, FutureExt, sink::SinkExt, StreamExt};
-use crate::constants::{Result, Sender, Receiver, ServerEvent, ClientEvent, SERVER_NAME, PROTOCOL_HEADER, ServerToClientMessageTypePrimitive, ConferenceJoinSalt, ConferenceEncryptionSalt};
+#[cfg(feature = "wire-schema")]
+use crate::wire_schema;
+
+/// Bytes read from the socket at once, frames may span several reads
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+/// Most bytes of an unexpected handshake answer kept to diagnose it
+const HANDSHAKE_DIAGNOSIS_LENGTH: usize = 64;
+/// How long to wait for the rest of an unexpected handshake answer
+const HANDSHAKE_DIAGNOSIS_TIMEOUT: Duration = Duration::from_millis(500);
+/// Time between two attempts to reach a server that is not up yet
+const WAIT_FOR_SERVER_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The optional features of a server, announced in the handshake acknowledgement
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerCapabilities {
+    pub bits: ServerCapabilityBits,
+    /// Largest message the server relays in bytes, if it announces one
+    pub max_message_size: Option<u32>,
+    /// Highest version of the wire schema the server speaks
+    pub wire_schema_version: Option<u8>,
+}
+
+impl ServerCapabilities {
+    pub fn directory(&self) -> bool {
+        self.bits & SERVER_CAPABILITY_DIRECTORY != 0
+    }
+
+    pub fn relay(&self) -> bool {
+        self.bits & SERVER_CAPABILITY_RELAY != 0
+    }
+
+    pub fn heartbeat(&self) -> bool {
+        self.bits & SERVER_CAPABILITY_HEARTBEAT != 0
+    }
+
+    pub fn resume(&self) -> bool {
+        self.bits & SERVER_CAPABILITY_RESUME != 0
+    }
+
+    /// Number of bytes following the bitmap in the handshake acknowledgement
+    pub fn extension_length(bits: ServerCapabilityBits) -> usize {
+        let max_message_size = if bits & SERVER_CAPABILITY_MAX_MESSAGE_SIZE != 0 { 4 } else { 0 };
+        let wire_schema_version = if bits & SERVER_CAPABILITY_WIRE_SCHEMA != 0 { 1 } else { 0 };
+        max_message_size + wire_schema_version
+    }
+}
+
+impl std::fmt::Display for ServerCapabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let features: Vec<&str> = [
+            (self.directory(), "directory"),
+            (self.relay(), "relay"),
+            (self.heartbeat(), "heartbeat"),
+            (self.resume(), "resume"),
+        ].into_iter().filter_map(|(supported, name)| supported.then_some(name)).collect();
+        if features.is_empty() {
+            write!(f, "no optional features")?;
+        } else {
+            write!(f, "{}", features.join(", "))?;
+        }
+        if let Some(max_message_size) = self.max_message_size {
+            write!(f, ", messages up to {}", format_message_size(max_message_size))?;
+        }
+        if let Some(wire_schema_version) = self.wire_schema_version {
+            write!(f, ", wire schema version {}", wire_schema_version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Size for display, in KB from 1 KB on
+pub fn format_message_size(size: u32) -> String {
+    if size < 1024 {
+        format!("{} bytes", size)
+    } else {
+        format!("{} KB", size / 1024)
+    }
+}
 
 pub async fn start_connection_manager(
+    tls: &TlsConfig,
     server_address: impl ToSocketAddrs,
     mut server_event_sender: Sender<ServerEvent>,
-    mut client_event_receiver: Receiver<ClientEvent>
+    client_event_receiver: Receiver<ClientEvent>
 ) -> Result<()> {
-    let stream = TcpStream::connect(server_address).await?;
+    let stream = runtime::connect(server_address).await
+        .map_err(|e| DisconnectReason::ConnectionFailed(e.to_string()))?;
     debug!("Connected to server");
-    let stream = TlsConnector::new()
-        .add_root_certificate(get_cert())
-        .connect(SERVER_NAME, stream)
-        .await?;
+    let roots = root_certificates(tls)?;
+    let stream = connect_tls(stream, &roots).await
+        .map_err(|e| DisconnectReason::TlsFailed(e.to_string()))?;
     debug!("TLS handshake complete");
-    let (reader, writer) = stream.split();
-    let mut buf_reader = BufReader::new(reader);
-    let mut buf_writer = BufWriter::new(writer);
-    let mut server_event_type: [u8; 1] = [0; 1];
+    check_pins(&stream, &tls.pins, &mut server_event_sender).await?;
+    run_connection_manager(stream, server_event_sender, client_event_receiver).await
+}
 
-    // Handshake
-    handle_handshake(&mut buf_reader, &mut buf_writer).await?;
+/// Connect to the server through a chain of relays, starting with the first hop.
+/// Every relay is a server of the same protocol that forwards the connection to the next hop,
+/// with a separate TLS session per hop nested inside the previous one, so a relay only learns
+/// the address of the next hop and the first hop never sees the address of the server.
+/// Only the name of the first hop is resolved locally, with the resolver in `dns`, the relays resolve the others.
+pub async fn start_connection_manager_via_relays(
+    dns: &DnsConfig,
+    tls: &TlsConfig,
+    relays: &[String],
+    server_address: String,
+    mut server_event_sender: Sender<ServerEvent>,
+    client_event_receiver: Receiver<ClientEvent>
+) -> Result<()> {
+    let Some((first_hop, other_hops)) = relays.split_first()
+    else {
+        let addresses = dns::resolve(dns, &server_address).await
+            .map_err(|e| DisconnectReason::ConnectionFailed(e.to_string()))?;
+        return start_connection_manager(tls, &addresses[..], server_event_sender, client_event_receiver).await;
+    };
+    let addresses = dns::resolve(dns, first_hop).await
+        .map_err(|e| DisconnectReason::ConnectionFailed(e.to_string()))?;
+    let roots = root_certificates(tls)?;
+    let stream = runtime::connect(&addresses[..]).await
+        .map_err(|e| DisconnectReason::ConnectionFailed(e.to_string()))?;
+    let mut stream: Box<dyn Connection> = Box::new(connect_tls(stream, &roots).await
+        .map_err(|e| DisconnectReason::TlsFailed(e.to_string()))?);
+    debug!("Connected to the first relay");
+    for (hop, next_hop) in other_hops.iter().chain(std::iter::once(&server_address)).enumerate() {
+        open_relay(&mut stream, next_hop).await
+            .map_err(|e| DisconnectReason::ConnectionFailed(e.to_string()))?;
+        let tls_stream = connect_tls(stream, &roots).await
+            .map_err(|e| DisconnectReason::TlsFailed(e.to_string()))?;
+        // only the key of the server is pinned, not those of the relays
+        if hop == other_hops.len() {
+            check_pins(&tls_stream, &tls.pins, &mut server_event_sender).await?;
+        }
+        stream = Box::new(tls_stream);
+        debug!("Connected through relay {} of {}", hop + 1, relays.len());
+    }
+    run_connection_manager(stream, server_event_sender, client_event_receiver).await
+}
 
+/// Connect without TLS, only meant for talking to the in-process mock server
+#[cfg(any(test, feature = "test-util"))]
+pub async fn start_connection_manager_plaintext(
+    server_address: impl ToSocketAddrs,
+    server_event_sender: Sender<ServerEvent>,
+    client_event_receiver: Receiver<ClientEvent>
+) -> Result<()> {
+    let stream = runtime::connect(server_address).await
+        .map_err(|e| DisconnectReason::ConnectionFailed(e.to_string()))?;
+    debug!("Connected to server without TLS");
+    run_connection_manager(stream, server_event_sender, client_event_receiver).await
+}
+
+/// Try to reach `server_address` until it accepts a connection or `timeout` passed, without a timeout it is tried forever.
+/// `on_attempt` is called after every failed attempt with the number of attempts, the time waited so far and the error.
+/// The name is resolved again on every attempt, with the resolver in `dns`.
+pub async fn wait_for_server(
+    dns: &DnsConfig,
+    server_address: &str,
+    timeout: Option<Duration>,
+    mut on_attempt: impl FnMut(usize, Duration, &std::io::Error),
+) -> Result<()> {
+    let started = std::time::Instant::now();
+    let mut attempts = 0;
     loop {
+        attempts += 1;
+        let result = match dns::resolve(dns, server_address).await {
+            Ok(addresses) => runtime::connect(&addresses[..]).await.map(|_| ()),
+            Err(e) => Err(std::io::Error::other(e.to_string())),
+        };
+        let error = match result {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let waited = started.elapsed();
+        on_attempt(attempts, waited, &error);
+        let delay = match timeout {
+            Some(timeout) if waited >= timeout => {
+                let reason = format!("{} is not reachable after {} seconds: {}", server_address, waited.as_secs(), error);
+                return Err(DisconnectReason::ConnectionFailed(reason).into());
+            },
+            Some(timeout) => WAIT_FOR_SERVER_INTERVAL.min(timeout - waited),
+            None => WAIT_FOR_SERVER_INTERVAL,
+        };
+        runtime::sleep(delay).await;
+    }
+}
+
+/// Errors are returned as a boxed [`DisconnectReason`]
+async fn run_connection_manager(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    server_event_sender: Sender<ServerEvent>,
+    client_event_receiver: Receiver<ClientEvent>
+) -> Result<()> {
+    let capabilities = handle_handshake(&mut stream).await
+        .map_err(|e| match e.downcast::<HandshakeDiagnosis>() {
+            Ok(diagnosis) => DisconnectReason::HandshakeFailed(*diagnosis),
+            Err(e) => DisconnectReason::HandshakeRejected(e.to_string()),
+        })?;
+    relay_events(stream, capabilities, server_event_sender, client_event_receiver).await
+        .map_err(|e| connection_lost(e).into())
+}
+
+/// An error on an established connection, the server closing it shows up as an unexpected end of file
+fn connection_lost(error: Box<dyn std::error::Error + Send + Sync>) -> DisconnectReason {
+    let error = match error.downcast::<DisconnectReason>() {
+        Ok(reason) => return *reason,
+        Err(error) => error,
+    };
+    match error.downcast_ref::<std::io::Error>() {
+        Some(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => DisconnectReason::ServerClosed,
+        _ => DisconnectReason::ConnectionLost(error.to_string()),
+    }
+}
+
+async fn relay_events(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    capabilities: ServerCapabilities,
+    mut server_event_sender: Sender<ServerEvent>,
+    mut client_event_receiver: Receiver<ClientEvent>
+) -> Result<()> {
+    server_event_sender.send(ServerEvent::HandshakeAcknowledgedWithCapabilities(capabilities)).await?;
+
+    let (mut reader, mut writer) = stream.split();
+    let mut codec = ConnectionCodec::Classic(ClientCodec);
+    let mut chunk = vec![0; READ_BUFFER_SIZE];
+    let mut read_buffer = BytesMut::new();
+    let mut write_buffer = BytesMut::new();
+    let mut heartbeat = capabilities.heartbeat().then(|| Heartbeat::new(Instant::now()));
+
+    #[cfg(feature = "wire-schema")]
+    if let Some(version) = capabilities.wire_schema_version.and_then(wire_schema::select_version) {
+        write_event(&mut writer, &mut codec, &mut write_buffer, ClientEvent::SelectWireSchema(version)).await?;
+        debug!("Using wire schema version {}", version);
+        codec = ConnectionCodec::Schema(wire_schema::SchemaCodec);
+    }
+
+    loop {
+        let until_ping = heartbeat.as_ref().map(|heartbeat| heartbeat.next_ping().saturating_duration_since(Instant::now()));
         select! {
-            s = async_std::io::ReadExt::read_exact(&mut buf_reader, &mut server_event_type).fuse() => match s {
-                Ok(()) => {
-                    let event = read_server_event(server_event_type[0], &mut buf_reader).await?;
-                    server_event_sender.send(event).await?;
-                },
-                Err(e) => { return Err(e.into()); },
+            read = reader.read(&mut chunk).fuse() => {
+                let read = read?;
+                if read == 0 {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+                read_buffer.extend_from_slice(&chunk[..read]);
+                while let Some(frame) = codec.split_frame(&mut read_buffer)? {
+                    trace::record(Direction::Inbound, &frame);
+                    let frame_length = frame.len();
+                    let event = codec.decode_frame(frame)?;
+                    protocol_dump::dump(Direction::Inbound, DumpedEvent::from(&event), frame_length);
+                    match event {
+                        ServerEvent::Ping(number) => write_event(&mut writer, &mut codec, &mut write_buffer, ClientEvent::Pong(number)).await?,
+                        ServerEvent::Pong(number) => {
+                            if let Some(heartbeat) = heartbeat.as_mut() {
+                                heartbeat.pong(number);
+                            }
+                        },
+                        event => server_event_sender.send(event).await?,
+                    }
+                }
             },
             client_event = client_event_receiver.next().fuse() => match client_event {
                 Some(event) => {
-                    if !write_client_event(event, &mut buf_writer).await? {
+                    let keep_open = !matches!(event, ClientEvent::Disconnect);
+                    write_event(&mut writer, &mut codec, &mut write_buffer, event).await?;
+                    if !keep_open {
                         break;
                     }
                 },
                 None => break,
             },
+            _ = sleep_or_forever(until_ping).fuse() => match heartbeat.as_mut().and_then(|heartbeat| heartbeat.tick(Instant::now())) {
+                Some(Beat::Ping(number)) => write_event(&mut writer, &mut codec, &mut write_buffer, ClientEvent::Ping(number)).await?,
+                Some(Beat::Dead) => {
+                    warn!("The server did not answer {} pings, giving up on the connection", heartbeat::MAX_MISSED_PONGS);
+                    return Err(DisconnectReason::ConnectionTimedOut.into());
+                },
+                None => {},
+            },
         }
     }
 
     Ok(())
 }
 
-async fn handle_handshake(reader: &mut (impl AsyncReadExt + Unpin), writer: &mut (impl AsyncWriteExt + Unpin)) -> Result<()> {
-    let mut server_response: [u8; 1] = [0; 1];
-    writer.write_all(PROTOCOL_HEADER).await?;
+async fn write_event(writer: &mut (impl AsyncWrite + Unpin), codec: &mut ConnectionCodec, write_buffer: &mut BytesMut, event: ClientEvent) -> Result<()> {
+    let dumped = DumpedEvent::from(&event);
+    codec.encode(event, write_buffer)?;
+    trace::record(Direction::Outbound, write_buffer);
+    protocol_dump::dump(Direction::Outbound, dumped, write_buffer.len());
+    writer.write_all(write_buffer).await?;
     writer.flush().await?;
+    write_buffer.clear();
+    Ok(())
+}
 
-    reader.read_exact(&mut server_response).await?;
-    if server_response[0] != ServerToClientMessageTypePrimitive::HandshakeAcknowledged as u8 {
-        return Err("Handshake failed".into());
+/// Never finishes without a duration
+async fn sleep_or_forever(duration: Option<Duration>) {
+    match duration {
+        Some(duration) => runtime::sleep(duration).await,
+        None => futures::future::pending().await,
     }
-
-    debug!("Handshake complete");
-
-    Ok(())
 }
 
-async fn read_server_event(event_type: u8, reader: &mut (impl BufRead + Unpin)) -> Result<ServerEvent> {
-    if let Ok(server_event_type) = ServerToClientMessageTypePrimitive::try_from(event_type) {
-        match server_event_type {
-            ServerToClientMessageTypePrimitive::HandshakeAcknowledged => {
-                warn!("Server sent unexpected handshake acknowledgement");
-                Ok(ServerEvent::HandshakeAcknowledged)
-            },
-            ServerToClientMessageTypePrimitive::ConferenceCreated => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let nonce = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                Ok(ServerEvent::ConferenceCreated((nonce, conference_id)))
-            },
-            ServerToClientMessageTypePrimitive::ConferenceJoinSalt => {
-                let mut buffer_small: [u8; 4] = [0; 4];
-                let mut join_salt: ConferenceJoinSalt = [0; 32];
-                reader.read_exact(&mut buffer_small).await?;
-                let nonce = u32::from_be_bytes(buffer_small);
-                reader.read_exact(&mut buffer_small).await?;
-                let conference_id = u32::from_be_bytes(buffer_small);
-                reader.read_exact(&mut join_salt).await?;
-                Ok(ServerEvent::ConferenceJoinSalt((nonce, conference_id, join_salt)))
-            },
-            ServerToClientMessageTypePrimitive::ConferenceJoined => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let nonce = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let number_of_peers = u32::from_be_bytes(buffer);
-                let mut buffer_large: ConferenceEncryptionSalt = [0; 32];
-                reader.read_exact(&mut buffer_large).await?;
-                Ok(ServerEvent::ConferenceJoined((nonce, conference_id, number_of_peers, buffer_large)))
-            },
-            ServerToClientMessageTypePrimitive::ConferenceLeft => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let nonce = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                Ok(ServerEvent::ConferenceLeft((nonce, conference_id)))
-            },
-            ServerToClientMessageTypePrimitive::MessageAccepted => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let nonce = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                Ok(ServerEvent::MessageAccepted((nonce, conference_id)))
-            },
-            ServerToClientMessageTypePrimitive::IncomingMessage => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let message_length = u32::from_be_bytes(buffer);
-                let mut message = Vec::with_capacity(message_length as usize);
-                reader.take(message_length.into()).read_to_end(&mut message).await?;
-                Ok(ServerEvent::IncomingMessage((conference_id, message)))
-            },
-            ServerToClientMessageTypePrimitive::ConferenceRestructuring => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let number_of_peers = u32::from_be_bytes(buffer);
-                Ok(ServerEvent::ConferenceRestructuring((conference_id, number_of_peers)))
-            },
-            ServerToClientMessageTypePrimitive::GeneralError => {
-                Ok(ServerEvent::GeneralError)
-            },
-            ServerToClientMessageTypePrimitive::ConferenceCreationError => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let nonce = u32::from_be_bytes(buffer);
-                Ok(ServerEvent::ConferenceCreationError(nonce))
-            },
-            ServerToClientMessageTypePrimitive::ConferenceJoinSaltError => {
-                let mut buffer_small: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer_small).await?;
-                let nonce = u32::from_be_bytes(buffer_small);
-                reader.read_exact(&mut buffer_small).await?;
-                let conference_id = u32::from_be_bytes(buffer_small);
-                Ok(ServerEvent::ConferenceJoinSaltError((nonce, conference_id)))
-            },
-            ServerToClientMessageTypePrimitive::ConferenceJoinError => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let nonce = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                Ok(ServerEvent::ConferenceJoinError((nonce, conference_id)))
-            },
-            ServerToClientMessageTypePrimitive::ConferenceLeaveError => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let nonce = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                Ok(ServerEvent::ConferenceLeaveError((nonce, conference_id)))
+/// Feed the inbound frames of a trace to the state manager instead of talking to a server.
+/// Every recorded outbound frame waits for the client to send an event of the same type, so
+/// the server events arrive in the same order relative to the client events as when recording.
+/// Encrypted conference traffic only decrypts if the keys happen to match the recording,
+/// so this reproduces bugs in parsing and state handling, not in decryption.
+/// The connection is closed once the trace is exhausted.
+pub async fn replay_trace(
+    records: Vec<TraceRecord>,
+    mut server_event_sender: Sender<ServerEvent>,
+    mut client_event_receiver: Receiver<ClientEvent>
+) -> Result<()> {
+    for (index, record) in records.into_iter().enumerate() {
+        match record.direction {
+            Direction::Inbound => {
+                let event = codec::decode_server_event(&record.frame)
+                    .map_err(|e| format!("Invalid inbound frame in trace record {}: {}", index, e))?;
+                protocol_dump::dump(Direction::Inbound, DumpedEvent::from(&event), record.frame.len());
+                server_event_sender.send(event).await?;
             },
-            ServerToClientMessageTypePrimitive::MessageError => {
-                let mut buffer: [u8; 4] = [0; 4];
-                reader.read_exact(&mut buffer).await?;
-                let nonce = u32::from_be_bytes(buffer);
-                reader.read_exact(&mut buffer).await?;
-                let conference_id = u32::from_be_bytes(buffer);
-                Ok(ServerEvent::MessageError((nonce, conference_id)))
+            // the keepalives of the recording are not sent by the state manager
+            Direction::Outbound if heartbeat::is_keepalive_frame(&record.frame) => {},
+            Direction::Outbound => {
+                let Some(event) = client_event_receiver.next().await
+                else {
+                    return Ok(());
+                };
+                let keep_open = !matches!(event, ClientEvent::Disconnect);
+                let mut frame = BytesMut::new();
+                ClientCodec.encode(event, &mut frame)?;
+                if frame.first() != record.frame.first() {
+                    warn!("Replay diverged at trace record {}: client sent event type {:?}, recorded {:?}", index, frame.first(), record.frame.first());
+                }
+                if !keep_open {
+                    return Ok(());
+                }
             },
         }
-    } else {
-        Err("Invalid server event type".into())
     }
+    info!("Replay finished");
+    Ok(())
 }
 
-/// Write a client event to the server, returning whether the connection should be kept open
-async fn write_client_event(event: ClientEvent, writer: &mut (impl AsyncWriteExt + Unpin)) -> Result<bool> {
-    writer.write_all(&[event.value()]).await?;
-    match event {
-        ClientEvent::CreateConference((nonce, password_hash, join_salt, encryption_salt)) => {
-            writer.write_all(&nonce.to_be_bytes()).await?;
-            writer.write_all(&password_hash).await?;
-            writer.write_all(&join_salt).await?;
-            writer.write_all(&encryption_salt).await?;
-        },
-        ClientEvent::GetConferenceJoinSalt((nonce, conference_id)) => {
-            writer.write_all(&nonce.to_be_bytes()).await?;
-            writer.write_all(&conference_id.to_be_bytes()).await?;
-        },
-        ClientEvent::JoinConference((nonce, conference_id, password_hash)) => {
-            writer.write_all(&nonce.to_be_bytes()).await?;
-            writer.write_all(&conference_id.to_be_bytes()).await?;
-            writer.write_all(&password_hash).await?;
-        },
-        ClientEvent::LeaveConference((nonce, conference_id)) => {
-            writer.write_all(&nonce.to_be_bytes()).await?;
-            writer.write_all(&conference_id.to_be_bytes()).await?;
-        },
-        ClientEvent::SendMessage((nonce, message)) => {
-            writer.write_all(&nonce.to_be_bytes()).await?;
-            writer.write_all(&message.conference.to_be_bytes()).await?;
-            writer.write_all(&u32::try_from(message.message.len()).unwrap().to_be_bytes()).await?;
-            writer.write_all(&message.message).await?;
+/// Returns the capabilities advertised by the server, servers without optional features have none
+async fn handle_handshake(stream: &mut (impl AsyncRead + AsyncWrite + Unpin)) -> Result<ServerCapabilities> {
+    let mut server_response: [u8; 1] = [0; 1];
+    stream.write_all(PROTOCOL_HEADER).await?;
+    stream.flush().await?;
+
+    if let Err(e) = stream.read_exact(&mut server_response).await {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            return Err(HandshakeDiagnosis::Closed.into());
+        }
+        return Err(e.into());
+    }
+    let capabilities = match ServerToClientMessageTypePrimitive::try_from(server_response[0]) {
+        Ok(ServerToClientMessageTypePrimitive::HandshakeAcknowledged) => ServerCapabilities::default(),
+        Ok(ServerToClientMessageTypePrimitive::HandshakeAcknowledgedWithCapabilities) => {
+            let mut buffer: [u8; 4] = [0; 4];
+            stream.read_exact(&mut buffer).await?;
+            let bits = ServerCapabilityBits::from_be_bytes(buffer);
+            let max_message_size = if bits & SERVER_CAPABILITY_MAX_MESSAGE_SIZE != 0 {
+                stream.read_exact(&mut buffer).await?;
+                Some(u32::from_be_bytes(buffer))
+            } else {
+                None
+            };
+            let wire_schema_version = if bits & SERVER_CAPABILITY_WIRE_SCHEMA != 0 {
+                let mut version: [u8; 1] = [0; 1];
+                stream.read_exact(&mut version).await?;
+                Some(version[0])
+            } else {
+                None
+            };
+            ServerCapabilities { bits, max_message_size, wire_schema_version }
         },
-        ClientEvent::Disconnect => {
-            writer.flush().await?;
-            return Ok(false);
+        Ok(ServerToClientMessageTypePrimitive::GeneralError) => return Err(HandshakeDiagnosis::ServerError.into()),
+        _ => {
+            let answer = read_unexpected_answer(stream, server_response[0]).await;
+            warn!("Unexpected handshake answer: {:02x?}", answer);
+            return Err(diagnose_handshake(&answer).into());
         },
+    };
+
+    debug!("Handshake complete, server capabilities: {:#x}", capabilities.bits);
+
+    Ok(capabilities)
+}
+
+/// Collect what else the server sent after an unexpected first byte, until it pauses or closes
+async fn read_unexpected_answer(stream: &mut (impl AsyncRead + Unpin), first_byte: u8) -> Vec<u8> {
+    let mut answer = vec![first_byte];
+    let mut buffer = [0; HANDSHAKE_DIAGNOSIS_LENGTH];
+    while answer.len() < HANDSHAKE_DIAGNOSIS_LENGTH {
+        let wanted = HANDSHAKE_DIAGNOSIS_LENGTH - answer.len();
+        match runtime::timeout(HANDSHAKE_DIAGNOSIS_TIMEOUT, stream.read(&mut buffer[..wanted])).await {
+            Some(Ok(read)) if read > 0 => answer.extend_from_slice(&buffer[..read]),
+            _ => break,
+        }
     }
+    answer
+}
 
-    writer.flush().await?;
-    Ok(true)
+/// Recognize common services that answer in place of a conference server
+fn diagnose_handshake(answer: &[u8]) -> HandshakeDiagnosis {
+    let first_line = |bytes: &[u8]| {
+        let line = bytes.split(|&b| b == b'\r' || b == b'\n').next().unwrap_or_default();
+        String::from_utf8_lossy(line).into_owned()
+    };
+    // the header without its length byte, the version follows it
+    let protocol_name = &PROTOCOL_HEADER[1..PROTOCOL_HEADER.len() - b" protocol".len()];
+    if answer.starts_with(b"HTTP/") {
+        HandshakeDiagnosis::HttpResponse(first_line(answer))
+    } else if answer.len() > 1 && answer[1..].starts_with(protocol_name) {
+        let length = (answer[0] as usize).min(answer.len() - 1);
+        HandshakeDiagnosis::ProtocolVersion(first_line(&answer[1..=length]))
+    } else if answer.len() >= 2 && matches!(answer[0], 0x15 | 0x16) && answer[1] == 0x03 {
+        HandshakeDiagnosis::TlsRecord
+    } else if answer.iter().all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace()) {
+        HandshakeDiagnosis::OtherService(first_line(answer))
+    } else {
+        let preview: Vec<String> = answer.iter().take(16).map(|b| format!("{:02x}", b)).collect();
+        HandshakeDiagnosis::OtherService(preview.join(" "))
+    }
 }
 
-fn get_cert() -> Certificate {
-    debug!("Loading certificate");
-    Certificate::from_pem(include_bytes!("../certs/certificate.pem")).expect("Invalid certificate")
+/// A connection to a server or through relays to a server
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+async fn connect_tls<S: AsyncRead + AsyncWrite + Unpin>(stream: S, roots: &[Certificate]) -> Result<TlsStream<S>> {
+    let connector = roots.iter().fold(TlsConnector::new(), |connector, root| connector.add_root_certificate(root.clone()));
+    Ok(connector.connect(SERVER_NAME, stream).await?)
+}
+
+/// Ask the relay at the other end of `stream` to forward the connection to `next_hop`
+async fn open_relay(stream: &mut (impl AsyncRead + AsyncWrite + Unpin), next_hop: &str) -> Result<()> {
+    let capabilities = handle_handshake(stream).await?;
+    if !capabilities.relay() {
+        return Err("Relay server does not forward connections".into());
+    }
+    let address = next_hop.as_bytes();
+    let mut frame = vec![RELAY_OPEN, u8::try_from(address.len()).map_err(|_| "Relay address is too long")?];
+    frame.extend_from_slice(address);
+    stream.write_all(&frame).await?;
+    stream.flush().await?;
+
+    let mut response: [u8; 1] = [0; 1];
+    stream.read_exact(&mut response).await?;
+    match response[0] {
+        RELAY_OPENED => Ok(()),
+        RELAY_ERROR => Err(format!("Relay could not connect to {}", next_hop).into()),
+        other => Err(format!("Unexpected relay response {:#x}", other).into()),
+    }
+}
+
+
+/// End the session if the key of the server matches none of `tls.pins`, see [`crate::pinning`].
+/// The mismatch is reported as [`ServerEvent::PinMismatch`] first
+async fn check_pins<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &TlsStream<S>,
+    pins: &[String],
+    server_event_sender: &mut Sender<ServerEvent>
+) -> std::result::Result<(), DisconnectReason> {
+    let Some(mismatch) = pin_mismatch(stream, pins)
+    else {
+        return Ok(());
+    };
+    let _ = server_event_sender.send(ServerEvent::PinMismatch(mismatch.clone())).await;
+    Err(DisconnectReason::PinMismatch(mismatch))
+}
+
+/// Why the key of the server matches none of `pins`, `None` if it matches one or nothing is pinned
+fn pin_mismatch<S: AsyncRead + AsyncWrite + Unpin>(stream: &TlsStream<S>, pins: &[String]) -> Option<String> {
+    if pins.is_empty() {
+        return None;
+    }
+    let Some(certificate) = stream.peer_certificate().ok().flatten().and_then(|certificate| certificate.to_der().ok())
+    else {
+        return Some("the server presented no certificate".to_string());
+    };
+    let Some(fingerprint) = pinning::spki_fingerprint(&certificate)
+    else {
+        return Some("the server certificate could not be parsed".to_string());
+    };
+    if !pinning::matches_pins(&fingerprint, pins) {
+        warn!("The key of the server {} matches none of the {} pins", fingerprint, pins.len());
+        return Some(format!("the server presented the key {}", fingerprint));
+    }
+    debug!("The key of the server matches a pin");
+    None
+}
+
+/// The roots trusted in addition to the system trust store, read again for every connection so changed files are picked up
+fn root_certificates(tls: &TlsConfig) -> std::result::Result<Vec<Certificate>, DisconnectReason> {
+    let mut roots = Vec::new();
+    if tls.bundled_certificate {
+        debug!("Loading the bundled certificate");
+        roots.push(Certificate::from_pem(include_bytes!("../certs/certificate.pem")).expect("Invalid certificate"));
+    }
+    for path in &tls.certificates {
+        debug!("Loading certificate {}", path.display());
+        let pem = std::fs::read(path)
+            .map_err(|e| DisconnectReason::CertificateFailed(format!("could not read {}: {}", path.display(), e)))?;
+        let certificate = Certificate::from_pem(&pem)
+            .map_err(|e| DisconnectReason::CertificateFailed(format!("{} is not a PEM certificate: {}", path.display(), e)))?;
+        roots.push(certificate);
+    }
+    Ok(roots)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{pin::Pin, task::{Context, Poll}};
     use super::*;
     use async_std::task;
     use futures::channel::mpsc;
@@ -240,31 +547,160 @@ mod tests {
         let (mut client_event_sender, client_event_receiver) = mpsc::unbounded();
         let server_address = "localhost:7667";
         client_event_sender.send(ClientEvent::Disconnect).await?;
-        task::block_on(start_connection_manager(server_address, server_event_sender, client_event_receiver))?;
+        task::block_on(start_connection_manager(&TlsConfig::default(), server_address, server_event_sender, client_event_receiver))?;
         Ok(())
     }
 
-    #[test]
-    fn test_read_server_event_conference_created() {
-        let event_type = ServerToClientMessageTypePrimitive::ConferenceCreated as u8;
-        let mut reader = BufReader::new(&b"\x00\x00\x00\x01\x00\x00\x00\x02"[..]);
-        let event = task::block_on(read_server_event(event_type, &mut reader)).unwrap();
-        match event {
-            ServerEvent::ConferenceCreated((nonce, conference_id)) => {
-                assert_eq!(nonce, 1);
-                assert_eq!(conference_id, 2);
-            },
-            _ => panic!("Unexpected event type"),
+    #[async_std::test]
+    async fn test_replay_trace() -> Result<()> {
+        let (server_event_sender, mut server_event_receiver) = mpsc::unbounded();
+        let (mut client_event_sender, client_event_receiver) = mpsc::unbounded();
+        let records = vec![
+            TraceRecord { direction: Direction::Outbound, timestamp: 0, frame: b"\x02\x00\x00\x00\x01\x00\x00\x00\x02".to_vec() },
+            TraceRecord { direction: Direction::Inbound, timestamp: 5, frame: b"\x01\x00\x00\x00\x01\x00\x00\x00\x02".to_vec() },
+        ];
+        let replay = task::spawn(replay_trace(records, server_event_sender, client_event_receiver));
+        client_event_sender.send(ClientEvent::GetConferenceJoinSalt((1, 2))).await?;
+        match server_event_receiver.next().await {
+            Some(ServerEvent::ConferenceCreated((1, 2))) => {},
+            _ => panic!("Unexpected event"),
+        }
+        replay.await?;
+        assert!(server_event_receiver.next().await.is_none());
+        Ok(())
+    }
+
+    /// Reads from a fixed input and collects everything written
+    struct MockStream {
+        input: futures::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl AsyncRead for MockStream {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.input).poll_read(cx, buf)
         }
     }
 
+    impl AsyncWrite for MockStream {
+        fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            self.output.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[async_std::test]
+    async fn test_open_relay() -> Result<()> {
+        let mut stream = MockStream { input: futures::io::Cursor::new(vec![0x08, 0, 0, 0, 0x02, RELAY_OPENED]), output: Vec::new() };
+        open_relay(&mut stream, "next.example:7667").await?;
+        let mut expected = PROTOCOL_HEADER.to_vec();
+        expected.extend_from_slice(&[RELAY_OPEN, 17]);
+        expected.extend_from_slice(b"next.example:7667");
+        assert_eq!(stream.output, expected);
+
+        // servers without the capability are not asked to forward
+        let mut stream = MockStream { input: futures::io::Cursor::new(vec![0x00]), output: Vec::new() };
+        assert!(open_relay(&mut stream, "next.example:7667").await.is_err());
+        assert_eq!(stream.output, PROTOCOL_HEADER);
+
+        let mut stream = MockStream { input: futures::io::Cursor::new(vec![0x08, 0, 0, 0, 0x02, RELAY_ERROR]), output: Vec::new() };
+        assert!(open_relay(&mut stream, "next.example:7667").await.is_err());
+        Ok(())
+    }
+
     #[test]
-    fn test_read_server_event_conference_created_failed() {
-        let event_type = ServerToClientMessageTypePrimitive::ConferenceCreated as u8;
-        let mut reader = BufReader::new(&b"\x00\x00\x00\x01"[..]);
-        let event = task::block_on(read_server_event(event_type, &mut reader));
-        assert!(event.is_err());
+    fn test_format_message_size() {
+        assert_eq!(format_message_size(512), "512 bytes");
+        assert_eq!(format_message_size(4096), "4 KB");
+        assert_eq!(format_message_size(65 * 1024 + 1), "65 KB");
     }
 
-}
+    #[async_std::test]
+    async fn test_handle_handshake() -> Result<()> {
+        let mut stream = MockStream { input: futures::io::Cursor::new(vec![0x00]), output: Vec::new() };
+        assert_eq!(handle_handshake(&mut stream).await?, ServerCapabilities::default());
+
+        // directory and a message size limit of 4096 bytes
+        let mut stream = MockStream { input: futures::io::Cursor::new(vec![0x08, 0, 0, 0, 0x11, 0, 0, 0x10, 0]), output: Vec::new() };
+        let capabilities = handle_handshake(&mut stream).await?;
+        assert!(capabilities.directory() && !capabilities.relay());
+        assert_eq!(capabilities.max_message_size, Some(4096));
+        assert_eq!(capabilities.to_string(), "directory, messages up to 4 KB");
 
+        // wire schema version 1 without a message size limit
+        let mut stream = MockStream { input: futures::io::Cursor::new(vec![0x08, 0, 0, 0, 0x20, 1]), output: Vec::new() };
+        let capabilities = handle_handshake(&mut stream).await?;
+        assert_eq!(capabilities.wire_schema_version, Some(1));
+        assert_eq!(capabilities.to_string(), "no optional features, wire schema version 1");
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_handshake_diagnosis() {
+        let mut stream = MockStream { input: futures::io::Cursor::new(b"HTTP/1.1 400 Bad Request\r\nServer: nginx\r\n\r\n".to_vec()), output: Vec::new() };
+        let error = handle_handshake(&mut stream).await.unwrap_err();
+        assert_eq!(*error.downcast::<HandshakeDiagnosis>().unwrap(), HandshakeDiagnosis::HttpResponse("HTTP/1.1 400 Bad Request".to_string()));
+
+        let mut stream = MockStream { input: futures::io::Cursor::new(Vec::new()), output: Vec::new() };
+        let error = handle_handshake(&mut stream).await.unwrap_err();
+        assert_eq!(*error.downcast::<HandshakeDiagnosis>().unwrap(), HandshakeDiagnosis::Closed);
+
+        let mut stream = MockStream { input: futures::io::Cursor::new(vec![0x10]), output: Vec::new() };
+        let error = handle_handshake(&mut stream).await.unwrap_err();
+        assert_eq!(*error.downcast::<HandshakeDiagnosis>().unwrap(), HandshakeDiagnosis::ServerError);
+    }
+
+    #[test]
+    fn test_diagnose_handshake() {
+        assert_eq!(diagnose_handshake(b"\x18AnonymousConference v0.9"), HandshakeDiagnosis::ProtocolVersion("AnonymousConference v0.9".to_string()));
+        assert_eq!(diagnose_handshake(&[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x46]), HandshakeDiagnosis::TlsRecord);
+        assert_eq!(diagnose_handshake(b"SSH-2.0-OpenSSH_9.6\r\n"), HandshakeDiagnosis::OtherService("SSH-2.0-OpenSSH_9.6".to_string()));
+        assert_eq!(diagnose_handshake(&[0xff, 0xfb, 0x01]), HandshakeDiagnosis::OtherService("ff fb 01".to_string()));
+    }
+
+    #[async_std::test]
+    async fn test_wait_for_server() {
+        let listener = async_std::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let dns = DnsConfig::default();
+        wait_for_server(&dns, &address, Some(Duration::ZERO), |_, _, _| panic!("The server is up")).await.unwrap();
+
+        drop(listener);
+        let mut attempts = 0;
+        let error = wait_for_server(&dns, &address, Some(Duration::ZERO), |attempt, _, _| attempts = attempt).await.unwrap_err();
+        assert_eq!(attempts, 1);
+        assert!(matches!(*error.downcast::<DisconnectReason>().unwrap(), DisconnectReason::ConnectionFailed(_)));
+    }
+
+    #[test]
+    fn test_connection_lost() {
+        let eof = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "eof");
+        assert_eq!(connection_lost(eof.into()), DisconnectReason::ServerClosed);
+        let reset = std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset");
+        assert_eq!(connection_lost(reset.into()), DisconnectReason::ConnectionLost("reset".to_string()));
+        assert_eq!(connection_lost(DisconnectReason::ConnectionTimedOut.into()), DisconnectReason::ConnectionTimedOut);
+    }
+
+    #[test]
+    fn test_root_certificates() {
+        assert_eq!(root_certificates(&TlsConfig::default()).unwrap().len(), 1);
+        let path = std::env::temp_dir().join(format!("acc-certificate-test-{}.pem", rand::random::<u32>()));
+        std::fs::write(&path, include_bytes!("../certs/certificate.pem")).unwrap();
+        let tls = TlsConfig { certificates: vec![path.clone()], bundled_certificate: false, pins: Vec::new() };
+        assert_eq!(root_certificates(&tls).unwrap().len(), 1);
+
+        std::fs::write(&path, "not a certificate").unwrap();
+        assert!(matches!(root_certificates(&tls), Err(DisconnectReason::CertificateFailed(_))));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(root_certificates(&tls), Err(DisconnectReason::CertificateFailed(_))));
+        assert!(root_certificates(&TlsConfig { bundled_certificate: false, ..TlsConfig::default() }).unwrap().is_empty());
+    }
+}
@@ -1,4 +1,12 @@
+use std::{path::PathBuf, time::Duration};
+
+use bytes::Bytes;
 use futures::channel::mpsc;
+use crate::anonymity::Anonymity;
+use crate::connection_manager::ServerCapabilities;
+use crate::file_transfer::{FileTransfer, OutgoingFile, ReceivedFile};
+use crate::history_store::{HistoryEntry, RetentionPolicy};
+use crate::upload::Chunk;
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
@@ -13,12 +21,29 @@ pub type MessageLength = u32;
 pub type PasswordHash = [u8; 32];
 pub type ConferenceJoinSalt = [u8; 32];
 pub type ConferenceEncryptionSalt = [u8; 32];
+pub type KeyImage = [u8; 32];
+/// Bitmap of the optional features a server supports, see the `SERVER_CAPABILITY_*` constants
+pub type ServerCapabilityBits = u32;
+
+/// The server keeps a directory of public conferences
+pub const SERVER_CAPABILITY_DIRECTORY: ServerCapabilityBits = 1 << 0;
+/// The server forwards connections to other servers, see [`RELAY_OPEN`]
+pub const SERVER_CAPABILITY_RELAY: ServerCapabilityBits = 1 << 1;
+/// The server sends keepalive pings and expects them to be answered
+pub const SERVER_CAPABILITY_HEARTBEAT: ServerCapabilityBits = 1 << 2;
+/// The server hands out resume tokens to continue a session after reconnecting
+pub const SERVER_CAPABILITY_RESUME: ServerCapabilityBits = 1 << 3;
+/// The bitmap in the handshake acknowledgement is followed by the largest message size the server relays
+pub const SERVER_CAPABILITY_MAX_MESSAGE_SIZE: ServerCapabilityBits = 1 << 4;
+/// The server speaks the versioned wire schema, its highest version follows in one byte after the maximum message size,
+/// see [`crate::wire_schema`]
+pub const SERVER_CAPABILITY_WIRE_SCHEMA: ServerCapabilityBits = 1 << 5;
 
 
 #[derive(Clone)]
 pub struct Message {
     pub conference: ConferenceId,
-    pub message: Vec<u8>,
+    pub message: Bytes,
     pub message_id: Option<MessageID>,
 }
 
@@ -31,6 +56,13 @@ pub enum ClientEvent {
     LeaveConference((PacketNonce, ConferenceId)) = 0x04,
     SendMessage((PacketNonce, Message)) = 0x05,
     Disconnect = 0x06,
+    ListConferences(PacketNonce) = 0x07,
+    /// All following frames in both directions use this version of the wire schema, 0x08 is taken by [`RELAY_OPEN`]
+    SelectWireSchema(u8) = 0x09,
+    /// Keepalive sent to servers with [`SERVER_CAPABILITY_HEARTBEAT`], answered by a `ServerEvent::Pong` with the same number
+    Ping(u32) = 0x0A,
+    /// Answer to a `ServerEvent::Ping`
+    Pong(u32) = 0x0B,
 }
 
 impl ClientEvent {
@@ -40,6 +72,7 @@ impl ClientEvent {
 }
 
 #[repr(u8)]
+#[derive(Debug)]
 pub enum ServerEvent {
     HandshakeAcknowledged = 0x00,
     ConferenceCreated((PacketNonce, ConferenceId)) = 0x01,
@@ -48,7 +81,15 @@ pub enum ServerEvent {
     ConferenceLeft((PacketNonce, ConferenceId)) = 0x04,
     MessageAccepted((PacketNonce, ConferenceId)) = 0x05,
     ConferenceRestructuring((ConferenceId, NumberOfPeers)) = 0x06,
-    IncomingMessage((ConferenceId, Vec<u8>)) = 0x07,
+    IncomingMessage((ConferenceId, Bytes)) = 0x07,
+    /// Sent instead of `HandshakeAcknowledged` by servers with optional features
+    HandshakeAcknowledgedWithCapabilities(ServerCapabilities) = 0x08,
+    /// Public conferences and their number of peers
+    ConferenceList((PacketNonce, Vec<(ConferenceId, NumberOfPeers)>)) = 0x09,
+    /// Keepalive from the server, answered by a `ClientEvent::Pong` with the same number, 0x0A is taken by [`RELAY_OPENED`]
+    Ping(u32) = 0x0B,
+    /// Answer to a `ClientEvent::Ping`
+    Pong(u32) = 0x0C,
 
     GeneralError = 0x10,
     ConferenceCreationError(PacketNonce) = 0x11,
@@ -56,12 +97,44 @@ pub enum ServerEvent {
     ConferenceJoinError((PacketNonce, ConferenceId)) = 0x13,
     ConferenceLeaveError((PacketNonce, ConferenceId)) = 0x14,
     MessageError((PacketNonce, ConferenceId)) = 0x15,
+    /// `(event type, payload length)` of a skipped event this client does not know, see [`LENGTH_PREFIXED_EVENT_TYPE_START`]
+    Unknown((u8, u32)),
+    /// The key of the server matches none of `tls.pins`, reported by the connection manager before it ends the session
+    /// with [`DisconnectReason::PinMismatch`], never sent by the server
+    PinMismatch(String),
 }
 
 pub enum ConferenceEvent {
     ConferenceRestructuring(NumberOfPeers),
-    IncomingMessage(Vec<u8>),
-    OutboundMessage((MessageID, Vec<u8>)),
+    IncomingMessage(Bytes),
+    OutboundMessage((MessageID, Bytes)),
+    /// A part of a message too large to send at once, see [`crate::upload`]
+    OutboundChunk((MessageID, Chunk)),
+    /// A part of a file, the file is offered before its first chunk and its hash sent after the last one,
+    /// see [`crate::file_transfer`]
+    OutboundFileChunk((MessageID, OutgoingFile, Chunk)),
+    /// All messages received so far were shown to the user
+    MessagesDisplayed,
+    /// Enable or disable sending read receipts
+    SetReadReceipts(bool),
+    /// Only take part in the key exchange, refuse to send messages, receipts and announcements
+    SetListenOnly(bool),
+    /// Use a new key pair after every key exchange, enabling it starts a key exchange right away
+    SetUnlinkable(bool),
+    /// Stop muting a sender for flooding
+    AllowFlooding(KeyImage),
+    /// Announce a sub-conference to the peers
+    AnnounceBreakout(Breakout),
+    /// Announce where the conference moved to, sent with [`MIGRATION_MESSAGE_ID`]
+    AnnounceMigration(Migration),
+    /// Ask the peers to answer, the conference had no traffic for a while
+    Probe,
+    /// How long the messages are kept on disk
+    SetRetentionPolicy(RetentionPolicy),
+    /// The server accepted an own message, it is kept in the history from now on
+    MessageAccepted(MessageID),
+    /// The user left the conference, sent before the channel is closed
+    Left,
 }
 
 #[repr(u8)]
@@ -74,6 +147,10 @@ pub enum ServerToClientMessageTypePrimitive {
     MessageAccepted = 0x05,
     ConferenceRestructuring = 0x06,
     IncomingMessage = 0x07,
+    HandshakeAcknowledgedWithCapabilities = 0x08,
+    ConferenceList = 0x09,
+    Ping = 0x0B,
+    Pong = 0x0C,
 
     GeneralError = 0x10,
     ConferenceCreationError = 0x11,
@@ -96,6 +173,10 @@ impl TryFrom<u8> for ServerToClientMessageTypePrimitive {
             x if x == ServerToClientMessageTypePrimitive::MessageAccepted as u8 => Ok(ServerToClientMessageTypePrimitive::MessageAccepted),
             x if x == ServerToClientMessageTypePrimitive::ConferenceRestructuring as u8 => Ok(ServerToClientMessageTypePrimitive::ConferenceRestructuring),
             x if x == ServerToClientMessageTypePrimitive::IncomingMessage as u8 => Ok(ServerToClientMessageTypePrimitive::IncomingMessage),
+            x if x == ServerToClientMessageTypePrimitive::HandshakeAcknowledgedWithCapabilities as u8 => Ok(ServerToClientMessageTypePrimitive::HandshakeAcknowledgedWithCapabilities),
+            x if x == ServerToClientMessageTypePrimitive::ConferenceList as u8 => Ok(ServerToClientMessageTypePrimitive::ConferenceList),
+            x if x == ServerToClientMessageTypePrimitive::Ping as u8 => Ok(ServerToClientMessageTypePrimitive::Ping),
+            x if x == ServerToClientMessageTypePrimitive::Pong as u8 => Ok(ServerToClientMessageTypePrimitive::Pong),
 
             x if x == ServerToClientMessageTypePrimitive::GeneralError as u8 => Ok(ServerToClientMessageTypePrimitive::GeneralError),
             x if x == ServerToClientMessageTypePrimitive::ConferenceCreationError as u8 => Ok(ServerToClientMessageTypePrimitive::ConferenceCreationError),
@@ -110,6 +191,208 @@ impl TryFrom<u8> for ServerToClientMessageTypePrimitive {
 
 pub type MessageID = usize;
 
+/// The author of an incoming message, only identified by the key image of the ring signature,
+/// which stays the same for all messages of a peer while they are in the conference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub key_image: KeyImage,
+    /// Name assigned to the key image by the user
+    pub label: Option<String>,
+}
+
+impl Peer {
+    /// Word fingerprint of the key image, the peer sees the same one for themselves
+    pub fn fingerprint(&self) -> String {
+        crate::fingerprint::key_image_fingerprint(&self.key_image)
+    }
+
+    /// The label if there is one, otherwise the fingerprint
+    pub fn display_name(&self) -> String {
+        self.label.clone().unwrap_or_else(|| self.fingerprint())
+    }
+}
+
+/// A sub-conference announced to the members of a parent conference
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakout {
+    pub conference_id: ConferenceId,
+    pub password: String,
+    pub topic: String,
+}
+
+impl Breakout {
+    /// `conference id | password length (u16) | password | topic`, `None` if the password is longer than `u16::MAX` bytes
+    pub fn encode(&self) -> Option<Vec<u8>> {
+        let password_length = u16::try_from(self.password.len()).ok()?;
+        let mut result = Vec::with_capacity(6 + self.password.len() + self.topic.len());
+        result.extend_from_slice(&self.conference_id.to_be_bytes());
+        result.extend_from_slice(&password_length.to_be_bytes());
+        result.extend_from_slice(self.password.as_bytes());
+        result.extend_from_slice(self.topic.as_bytes());
+        Some(result)
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Breakout> {
+        let conference_id = ConferenceId::from_be_bytes(data.get(..4)?.try_into().ok()?);
+        let password_length = u16::from_be_bytes(data.get(4..6)?.try_into().ok()?) as usize;
+        let data = &data[6..];
+        let password = data.get(..password_length)?;
+        Some(Breakout {
+            conference_id,
+            password: String::from_utf8(password.to_vec()).ok()?,
+            topic: String::from_utf8_lossy(&data[password_length..]).into_owned(),
+        })
+    }
+}
+
+/// The message id of migration announcements, the server accepting it is the signal to leave the old conference
+pub const MIGRATION_MESSAGE_ID: MessageID = MessageID::MAX;
+
+/// Where a conference moved to, announced by a member of the ring
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    pub server_address: String,
+    pub conference_id: ConferenceId,
+    pub password: String,
+}
+
+impl Migration {
+    /// `conference id | password length | password | server address`
+    pub fn encode(&self) -> Vec<u8> {
+        let password = &self.password.as_bytes()[..self.password.len().min(u8::MAX as usize)];
+        let mut result = Vec::with_capacity(5 + password.len() + self.server_address.len());
+        result.extend_from_slice(&self.conference_id.to_be_bytes());
+        result.push(password.len() as u8);
+        result.extend_from_slice(password);
+        result.extend_from_slice(self.server_address.as_bytes());
+        result
+    }
+
+    pub fn decode(data: &[u8]) -> Option<Migration> {
+        let conference_id = ConferenceId::from_be_bytes(data.get(..4)?.try_into().ok()?);
+        let (&password_length, data) = data[4..].split_first()?;
+        let password = data.get(..password_length as usize)?;
+        let server_address = String::from_utf8(data[password_length as usize..].to_vec()).ok()?;
+        if server_address.is_empty() {
+            return None;
+        }
+        Some(Migration { server_address, conference_id, password: String::from_utf8(password.to_vec()).ok()? })
+    }
+}
+
+/// The steps of moving a conference to another server, see [`crate::multi_server`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrationStage {
+    Connecting,
+    Creating,
+    Joining,
+    /// The new conference is announced to the ring of the old one
+    Announcing,
+    Leaving,
+    /// The old conference was left, with where it moved to
+    Finished(Migration),
+    Failed(String),
+}
+
+impl std::fmt::Display for MigrationStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MigrationStage::Connecting => write!(f, "connecting to the new server"),
+            MigrationStage::Creating => write!(f, "creating the new conference"),
+            MigrationStage::Joining => write!(f, "joining the new conference"),
+            MigrationStage::Announcing => write!(f, "announcing the new conference to the members"),
+            MigrationStage::Leaving => write!(f, "leaving the old conference"),
+            MigrationStage::Finished(migration) => write!(f, "moved to conference {} on {}", migration.conference_id, migration.server_address),
+            MigrationStage::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// Why the session with the server ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The server could not be reached
+    ConnectionFailed(String),
+    /// The TLS handshake with the server failed
+    TlsFailed(String),
+    /// A certificate from `tls.certificates` could not be loaded
+    CertificateFailed(String),
+    /// The key of the server matches none of `tls.pins`, the connection may be intercepted
+    PinMismatch(String),
+    /// The server rejected the protocol handshake
+    HandshakeRejected(String),
+    /// The server answered the protocol handshake with something this client does not speak
+    HandshakeFailed(HandshakeDiagnosis),
+    /// Reading from or writing to the server failed
+    ConnectionLost(String),
+    /// The server stopped answering keepalives, see [`crate::heartbeat`]
+    ConnectionTimedOut,
+    /// The server closed the connection
+    ServerClosed,
+    /// The server reported too many errors in a short time
+    ServerErrors,
+    /// The user disconnected
+    UserRequested,
+    /// There was no user input for `idle.disconnect_after_mins`
+    Idle,
+    /// The default route moved to another network, see [`crate::network_monitor`]
+    NetworkChanged,
+}
+
+impl std::fmt::Display for DisconnectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisconnectReason::ConnectionFailed(e) => write!(f, "Could not reach the server, check the address and your network: {}", e),
+            DisconnectReason::TlsFailed(e) => write!(f, "TLS handshake failed, check the server certificate and tls.certificates: {}", e),
+            DisconnectReason::CertificateFailed(e) => write!(f, "Could not load the certificates to trust, check tls.certificates: {}", e),
+            DisconnectReason::PinMismatch(e) => write!(f, "The server key matches none of tls.pins, someone may be intercepting the connection: {}", e),
+            DisconnectReason::HandshakeRejected(e) => write!(f, "The server rejected the handshake, check the server version: {}", e),
+            DisconnectReason::HandshakeFailed(diagnosis) => write!(f, "Handshake failed: {}", diagnosis),
+            DisconnectReason::ConnectionLost(e) => write!(f, "Connection to the server lost: {}", e),
+            DisconnectReason::ConnectionTimedOut => write!(f, "The server stopped answering, the connection is probably dead"),
+            DisconnectReason::ServerClosed => write!(f, "The server closed the connection"),
+            DisconnectReason::ServerErrors => write!(f, "Disconnected after repeated server errors"),
+            DisconnectReason::UserRequested => write!(f, "Disconnected"),
+            DisconnectReason::Idle => write!(f, "Left all conferences and disconnected, there was no activity for too long"),
+            DisconnectReason::NetworkChanged => write!(f, "The network changed"),
+        }
+    }
+}
+
+impl std::error::Error for DisconnectReason {}
+
+/// What the server sent instead of acknowledging the protocol header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeDiagnosis {
+    /// An HTTP server answered, with its status line
+    HttpResponse(String),
+    /// A server of this protocol answered with a header of another version
+    ProtocolVersion(String),
+    /// The server reported an error, it speaks the protocol but not this version
+    ServerError,
+    /// The server expects a TLS handshake inside the connection
+    TlsRecord,
+    /// Another service answered, with a preview of what it sent
+    OtherService(String),
+    /// The server closed the connection without answering
+    Closed,
+}
+
+impl std::fmt::Display for HandshakeDiagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeDiagnosis::HttpResponse(status) => write!(f, "The address belongs to a web server ({}), check the port of the conference server", status),
+            HandshakeDiagnosis::ProtocolVersion(header) => write!(f, "The server speaks another version of the protocol ({}), update the client or the server", header),
+            HandshakeDiagnosis::ServerError => write!(f, "The server refused the protocol header, it probably runs a newer or older version"),
+            HandshakeDiagnosis::TlsRecord => write!(f, "The server expects TLS inside the connection, check the relays and the port of the conference server"),
+            HandshakeDiagnosis::OtherService(preview) => write!(f, "Another service answered ({}), check the address and port of the conference server", preview),
+            HandshakeDiagnosis::Closed => write!(f, "The server closed the connection without answering, it may not be a conference server"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeDiagnosis {}
+
 pub enum UIAction {
     /// Create a new conference with the given password.
     CreateConference(String),
@@ -117,12 +400,104 @@ pub enum UIAction {
     JoinConference((ConferenceId, String)),
     /// Leave a conference with the given ID.
     LeaveConference(ConferenceId),
+    /// Leave every joined conference but stay connected.
+    LeaveAllConferences,
     /// Send a message to a conference.
     SendMessage((ConferenceId, MessageID, String)),
+    /// Send the file at the path to a conference, it is reported like a message with the id, see [`crate::file_transfer`].
+    SendFile((ConferenceId, MessageID, PathBuf)),
     /// Disconnect from the server.
     Disconnect,
+    /// List the loaded plugins and scripts.
+    ListPlugins,
+    /// Reload all plugins and scripts from disk.
+    ReloadPlugins,
+    /// Assign a local name to a key image, `None` removes it.
+    SetPeerLabel((KeyImage, Option<String>)),
+    /// All messages of a conference received so far were shown in a focused view.
+    MessagesDisplayed(ConferenceId),
+    /// Enable or disable sending read receipts in a conference, they are disabled by default.
+    SetReadReceipts((ConferenceId, bool)),
+    /// Sign with a new key after every key exchange, so the own messages cannot be linked across key exchanges.
+    /// Peers cannot tell that the messages after a key exchange come from the same sender.
+    SetUnlinkable((ConferenceId, bool)),
+    /// Listen to a conference without ever sending messages, read receipts or announcements to it.
+    SetListenOnly((ConferenceId, bool)),
+    /// Enable or disable leaving a conference after `idle.leave_after_mins` without messages, see [`crate::idle`].
+    SetAutoLeave((ConferenceId, bool)),
+    /// Choose how long the messages of a conference are kept on disk, only with `history.persist`, see [`crate::history_store`].
+    SetRetentionPolicy((ConferenceId, RetentionPolicy)),
+    /// List the public conferences of the server.
+    ListConferences,
+    /// Show all messages of a sender in a conference, even if they are flooding.
+    AllowFlooding((ConferenceId, KeyImage)),
+    /// Create a sub-conference with the given topic and announce it in the parent conference.
+    CreateBreakout((ConferenceId, String)),
+    /// Leave a conference and join it again with the same password.
+    RejoinConference(ConferenceId),
+    /// Create, join and list conferences on this server from now on, see [`crate::multi_server`].
+    SelectServer(String),
+    /// Move a conference to another server: create it there, announce it to the ring and leave the old one.
+    MigrateConference((ConferenceId, String)),
+    /// Join the conference a peer moved to and leave the old one.
+    FollowMigration((ConferenceId, Migration)),
+    /// Announce the new coordinates of a conference to its ring, sent by [`crate::multi_server`] while migrating.
+    AnnounceMigration((ConferenceId, Migration)),
+    /// Abandon a pending join or create announced with [`UIEvent::RequestPending`], a late reply of the server is ignored
+    CancelRequest(PacketNonce),
+    /// The user interacted with the UI, postpones disconnecting after `idle.disconnect_after_mins`.
+    /// Every other action counts as well, except [`UIAction::MessagesDisplayed`].
+    UserActive,
+}
+
+/// How long a sent message took until it was
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MessageLatency {
+    /// accepted by the server
+    Accepted(Duration),
+    /// seen by the first peer, only with read receipts
+    FirstSeen(Duration),
+}
+
+impl std::fmt::Display for MessageLatency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageLatency::Accepted(latency) => write!(f, "accepted after {} ms", latency.as_millis()),
+            MessageLatency::FirstSeen(latency) => write!(f, "first seen after {} ms", latency.as_millis()),
+        }
+    }
+}
+
+/// The steps of setting up a conference after joining or a restructuring,
+/// with the keys received so far and the number of keys needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupStage {
+    KeyExchange((usize, usize)),
+    KeyNegotiation((usize, usize)),
 }
 
+impl SetupStage {
+    /// Progress of the whole setup between `0.0` and `1.0`, both steps count half
+    pub fn fraction(&self) -> f64 {
+        let (step, (received, needed)) = match *self {
+            SetupStage::KeyExchange(keys) => (0.0, keys),
+            SetupStage::KeyNegotiation(keys) => (1.0, keys),
+        };
+        let step_fraction = if needed == 0 { 1.0 } else { received.min(needed) as f64 / needed as f64 };
+        (step + step_fraction) / 2.0
+    }
+}
+
+impl std::fmt::Display for SetupStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SetupStage::KeyExchange((received, needed)) => write!(f, "exchanging public keys ({} of {})", received, needed),
+            SetupStage::KeyNegotiation((received, needed)) => write!(f, "negotiating the encryption key ({} of {} parts)", received, needed),
+        }
+    }
+}
+
+#[derive(Debug)]
 pub enum UIEvent {
     ConferenceCreated(ConferenceId),
     ConferenceCreateFailed,
@@ -130,15 +505,223 @@ pub enum UIEvent {
     ConferenceJoinFailed(ConferenceId),
     ConferenceLeft(ConferenceId),
     ConferenceLeaveFailed(ConferenceId),
-    IncomingMessage((ConferenceId, Vec<u8>, bool)),
+    /// The text is valid UTF-8 without control characters, see [`crate::sanitize`]
+    IncomingMessage((ConferenceId, Bytes, bool, Peer)),
     MessageAccepted((ConferenceId, MessageID)),
     MessageRejected((ConferenceId, MessageID)),
     MessageError((ConferenceId, MessageID)),
+    /// The server did not accept the message in time, also after it was sent again, see [`crate::outbound`]
+    MessageTimedOut((ConferenceId, MessageID)),
+    /// A message was not sent as it exceeds the maximum message size of the server, or of an upload, in bytes
+    MessageTooLarge((ConferenceId, MessageID, u32)),
+    /// The server accepted this many of the chunks of a large message, the last one is reported as
+    /// [`UIEvent::MessageAccepted`] instead
+    UploadProgress((ConferenceId, MessageID, usize, usize)),
+    /// Chunks of a file sent or received so far, an own file is finished with [`UIEvent::MessageAccepted`]
+    /// and a file of a peer with [`UIEvent::FileReceived`]
+    FileTransferProgress((ConferenceId, FileTransfer)),
+    /// A file of a peer arrived completely and was written to the downloads directory
+    FileReceived((ConferenceId, ReceivedFile)),
+    /// A file could not be read or is too large, with the reason
+    FileNotSent((ConferenceId, MessageID, String)),
     ConferenceRestructuring((ConferenceId, NumberOfPeers)),
+    /// Sent after `ConferenceRestructuring` with the number of peers that joined and left, one of them is `0`
+    PeersChanged((ConferenceId, NumberOfPeers, NumberOfPeers)),
     ConferenceRestructuringFinished(ConferenceId),
+    /// The setup of a conference advanced, sent until `ConferenceRestructuringFinished`
+    SetupProgress((ConferenceId, SetupStage)),
+    /// A peer restarted the key exchange, sending is disabled until `ConferenceRestructuringFinished`
+    ConferenceRenegotiating(ConferenceId),
+    /// A peer computed a different ring of public keys after the setup, the server may show the peers
+    /// different members of the conference, sent at most once per restructuring
+    RingMismatch(ConferenceId),
+    /// Malformed messages of peers were dropped, with their number so far and what was wrong with the last one,
+    /// sent for the first one and whenever the number doubled
+    InvalidMessages((ConferenceId, usize, String)),
+    /// A sent message was seen by this many peers
+    MessageSeen((ConferenceId, MessageID, usize)),
+    /// A sent message was confirmed by this many of the other peers, see [`crate::message_codec::ClientToClientMessage::DeliveryReceipt`]
+    MessageReceivedBy((ConferenceId, MessageID, usize, usize)),
+    /// No messages were sent or received for a while, the conference is left after this long unless that changes
+    ConferenceIdle((ConferenceId, Duration)),
+    /// Time a sent message took to reach a stage, measured from `UIAction::SendMessage`
+    MessageLatency((ConferenceId, MessageID, MessageLatency)),
+    /// Public conferences and their number of peers, `None` if the server has no directory or did not send it in time
+    ConferenceDirectory(Option<Vec<(ConferenceId, NumberOfPeers)>>),
+    /// A sender exceeded the message rate limit, their messages are dropped for a while
+    SenderMuted((ConferenceId, Peer)),
+    /// A muted sender is shown again, with the number of their dropped messages
+    SenderUnmuted((ConferenceId, Peer, usize)),
+    /// A sub-conference was announced in a parent conference, by a peer or by us (`None`)
+    BreakoutAnnounced((ConferenceId, Breakout, Option<Peer>)),
+    /// The server reported a general error, `true` if the session is closed because of repeated errors
+    ServerError(bool),
+    /// A server event did not match the pending requests or joined conferences and was ignored
+    ProtocolViolation(String),
+    /// The session with the server ended, sent last
+    Disconnected(DisconnectReason),
+    /// The server hosting a conference, sent before `ConferenceJoined` when connected to several servers
+    ConferenceServer((ConferenceId, String)),
+    /// The session with one of several servers ended, its conferences were left and the other sessions are kept
+    ServerDisconnected((String, DisconnectReason)),
+    /// The session ended and is established again right away, followed by `ConferenceLeft` for the conferences that are joined again
+    Reconnecting(DisconnectReason),
+    /// The anonymity set of the own messages, sent whenever the key exchange of a conference finished
+    Anonymity((ConferenceId, Anonymity)),
+    /// The word fingerprint of the own public key in a joined conference, for peers to confirm out of band
+    OwnFingerprint((ConferenceId, String)),
+    /// The protocol handshake failed, sent before the session ends with [`DisconnectReason::HandshakeFailed`]
+    HandshakeFailed(HandshakeDiagnosis),
+    /// A peer announced that a conference moved to another server
+    MigrationAnnounced((ConferenceId, Migration, Peer)),
+    /// Progress of moving a conference to another server or following a peer there, by old conference id
+    MigrationProgress((ConferenceId, MigrationStage)),
+    /// The optional features of the server, sent after connecting
+    ServerCapabilities(ServerCapabilities),
+    /// `true` if a conference had no traffic and its peers did not answer a probe while the server was reachable,
+    /// `false` once traffic is relayed again, see [`crate::liveness`]
+    ConferenceStale((ConferenceId, bool)),
+    /// Messages of earlier sessions kept on disk, oldest first, sent after `ConferenceJoined` with `history.persist`
+    HistoryLoaded((ConferenceId, Vec<HistoryEntry>)),
+    /// How long the messages of a conference are kept on disk, sent after joining and when it changed, only with `history.persist`
+    RetentionPolicy((ConferenceId, RetentionPolicy)),
+    /// A password is being hashed for joining a conference, `None` when creating one as the server assigns the id
+    DerivingKey(Option<ConferenceId>),
+    /// A join, or a create for `None`, can be cancelled with [`UIAction::CancelRequest`] until it is answered
+    RequestPending((PacketNonce, Option<ConferenceId>)),
+    /// The request was cancelled, no join or create event follows for it
+    RequestCancelled(PacketNonce),
+    PluginList(Vec<String>),
+    UpdateAvailable((String, String)),
 }
 
+impl UIEvent {
+    /// The conference the event is about, `None` for events of the session or of a request
+    pub fn conference_id(&self) -> Option<ConferenceId> {
+        match self {
+            UIEvent::ConferenceCreated(conference_id)
+            | UIEvent::ConferenceJoinFailed(conference_id)
+            | UIEvent::ConferenceLeft(conference_id)
+            | UIEvent::ConferenceLeaveFailed(conference_id)
+            | UIEvent::ConferenceRestructuringFinished(conference_id)
+            | UIEvent::ConferenceRenegotiating(conference_id)
+            | UIEvent::RingMismatch(conference_id) => Some(*conference_id),
+            UIEvent::ConferenceJoined((conference_id, _))
+            | UIEvent::IncomingMessage((conference_id, _, _, _))
+            | UIEvent::MessageAccepted((conference_id, _))
+            | UIEvent::MessageRejected((conference_id, _))
+            | UIEvent::MessageError((conference_id, _))
+            | UIEvent::MessageTimedOut((conference_id, _))
+            | UIEvent::MessageTooLarge((conference_id, _, _))
+            | UIEvent::UploadProgress((conference_id, _, _, _))
+            | UIEvent::FileTransferProgress((conference_id, _))
+            | UIEvent::FileReceived((conference_id, _))
+            | UIEvent::FileNotSent((conference_id, _, _))
+            | UIEvent::ConferenceRestructuring((conference_id, _))
+            | UIEvent::PeersChanged((conference_id, _, _))
+            | UIEvent::SetupProgress((conference_id, _))
+            | UIEvent::InvalidMessages((conference_id, _, _))
+            | UIEvent::MessageSeen((conference_id, _, _))
+            | UIEvent::MessageReceivedBy((conference_id, _, _, _))
+            | UIEvent::ConferenceIdle((conference_id, _))
+            | UIEvent::MessageLatency((conference_id, _, _))
+            | UIEvent::SenderMuted((conference_id, _))
+            | UIEvent::SenderUnmuted((conference_id, _, _))
+            | UIEvent::BreakoutAnnounced((conference_id, _, _))
+            | UIEvent::ConferenceServer((conference_id, _))
+            | UIEvent::Anonymity((conference_id, _))
+            | UIEvent::OwnFingerprint((conference_id, _))
+            | UIEvent::MigrationAnnounced((conference_id, _, _))
+            | UIEvent::MigrationProgress((conference_id, _))
+            | UIEvent::ConferenceStale((conference_id, _))
+            | UIEvent::HistoryLoaded((conference_id, _))
+            | UIEvent::RetentionPolicy((conference_id, _)) => Some(*conference_id),
+            UIEvent::DerivingKey(conference_id) => *conference_id,
+            UIEvent::ConferenceCreateFailed
+            | UIEvent::ConferenceDirectory(_)
+            | UIEvent::ServerError(_)
+            | UIEvent::ProtocolViolation(_)
+            | UIEvent::Disconnected(_)
+            | UIEvent::ServerDisconnected(_)
+            | UIEvent::Reconnecting(_)
+            | UIEvent::HandshakeFailed(_)
+            | UIEvent::ServerCapabilities(_)
+            | UIEvent::RequestPending(_)
+            | UIEvent::RequestCancelled(_)
+            | UIEvent::PluginList(_)
+            | UIEvent::UpdateAvailable(_) => None,
+        }
+    }
+
+    /// The id of the conference the event is about, to rewrite it, see [`UIEvent::conference_id`]
+    pub fn conference_id_mut(&mut self) -> Option<&mut ConferenceId> {
+        match self {
+            UIEvent::ConferenceCreated(conference_id)
+            | UIEvent::ConferenceJoinFailed(conference_id)
+            | UIEvent::ConferenceLeft(conference_id)
+            | UIEvent::ConferenceLeaveFailed(conference_id)
+            | UIEvent::ConferenceRestructuringFinished(conference_id)
+            | UIEvent::ConferenceRenegotiating(conference_id)
+            | UIEvent::RingMismatch(conference_id) => Some(conference_id),
+            UIEvent::ConferenceJoined((conference_id, _))
+            | UIEvent::IncomingMessage((conference_id, _, _, _))
+            | UIEvent::MessageAccepted((conference_id, _))
+            | UIEvent::MessageRejected((conference_id, _))
+            | UIEvent::MessageError((conference_id, _))
+            | UIEvent::MessageTimedOut((conference_id, _))
+            | UIEvent::MessageTooLarge((conference_id, _, _))
+            | UIEvent::UploadProgress((conference_id, _, _, _))
+            | UIEvent::FileTransferProgress((conference_id, _))
+            | UIEvent::FileReceived((conference_id, _))
+            | UIEvent::FileNotSent((conference_id, _, _))
+            | UIEvent::ConferenceRestructuring((conference_id, _))
+            | UIEvent::PeersChanged((conference_id, _, _))
+            | UIEvent::SetupProgress((conference_id, _))
+            | UIEvent::InvalidMessages((conference_id, _, _))
+            | UIEvent::MessageSeen((conference_id, _, _))
+            | UIEvent::MessageReceivedBy((conference_id, _, _, _))
+            | UIEvent::ConferenceIdle((conference_id, _))
+            | UIEvent::MessageLatency((conference_id, _, _))
+            | UIEvent::SenderMuted((conference_id, _))
+            | UIEvent::SenderUnmuted((conference_id, _, _))
+            | UIEvent::BreakoutAnnounced((conference_id, _, _))
+            | UIEvent::ConferenceServer((conference_id, _))
+            | UIEvent::Anonymity((conference_id, _))
+            | UIEvent::OwnFingerprint((conference_id, _))
+            | UIEvent::MigrationAnnounced((conference_id, _, _))
+            | UIEvent::MigrationProgress((conference_id, _))
+            | UIEvent::ConferenceStale((conference_id, _))
+            | UIEvent::HistoryLoaded((conference_id, _))
+            | UIEvent::RetentionPolicy((conference_id, _)) => Some(conference_id),
+            UIEvent::DerivingKey(conference_id) => conference_id.as_mut(),
+            UIEvent::ConferenceCreateFailed
+            | UIEvent::ConferenceDirectory(_)
+            | UIEvent::ServerError(_)
+            | UIEvent::ProtocolViolation(_)
+            | UIEvent::Disconnected(_)
+            | UIEvent::ServerDisconnected(_)
+            | UIEvent::Reconnecting(_)
+            | UIEvent::HandshakeFailed(_)
+            | UIEvent::ServerCapabilities(_)
+            | UIEvent::RequestPending(_)
+            | UIEvent::RequestCancelled(_)
+            | UIEvent::PluginList(_)
+            | UIEvent::UpdateAvailable(_) => None,
+        }
+    }
+}
+
+/// Server event types from this one on are followed by the length of their payload as u32,
+/// so clients can skip the events of newer servers they do not know
+pub const LENGTH_PREFIXED_EVENT_TYPE_START: u8 = 0x20;
+
 pub const SERVER_NAME: &str = "anonymous-conference.program";
 
 pub const PROTOCOL_HEADER: &[u8] = b"\x1CAnonymousConference protocol";
 
+/// Sent after the handshake with the length and address of the next hop, the server
+/// answers with [`RELAY_OPENED`] and forwards all following bytes, or with [`RELAY_ERROR`]
+pub const RELAY_OPEN: u8 = 0x08;
+pub const RELAY_OPENED: u8 = 0x0A;
+pub const RELAY_ERROR: u8 = 0x16;
+
@@ -0,0 +1,80 @@
+use std::{
+    backtrace::Backtrace,
+    fs,
+    panic::PanicHookInfo,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use crate::{constants::PROTOCOL_HEADER, logging, paths};
+
+const REPORT_EXTENSION: &str = "txt";
+const SEEN_REPORT_EXTENSION: &str = "seen";
+
+/// Directory the crash reports are written to, reports are never uploaded anywhere
+pub fn crash_reports_dir() -> PathBuf {
+    paths::data_dir().join("crash-reports")
+}
+
+/// Write a crash report for every panic, including panics in spawned tasks, before running the default hook.
+/// The hook writes to stderr directly, the panic may have happened while the logger held its lock.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        match write_crash_report(info) {
+            Ok(path) => eprintln!("{}, crash report written to {}", info, path.display()),
+            Err(e) => eprintln!("{}, could not write crash report: {}", info, e),
+        }
+        default_hook(info);
+    }));
+}
+
+fn write_crash_report(info: &PanicHookInfo) -> std::io::Result<PathBuf> {
+    let dir = crash_reports_dir();
+    fs::create_dir_all(&dir)?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default();
+    let path = dir.join(format!("crash-{}.{}", timestamp, REPORT_EXTENSION));
+    fs::write(&path, format_crash_report(info))?;
+    Ok(path)
+}
+
+fn format_crash_report(info: &PanicHookInfo) -> String {
+    let thread = std::thread::current();
+    let mut report = String::new();
+    report.push_str(&format!("{} {}\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+    report.push_str(&format!("Protocol: {}\n", String::from_utf8_lossy(&PROTOCOL_HEADER[1..])));
+    report.push_str(&format!("Platform: {} {}\n", std::env::consts::OS, std::env::consts::ARCH));
+    report.push_str(&format!("Thread: {}\n", thread.name().unwrap_or("<unnamed>")));
+    report.push_str(&format!("\n{}\n", info));
+    report.push_str(&format!("\nBacktrace:\n{}\n", Backtrace::force_capture()));
+    report.push_str("\nRecent log lines:\n");
+    for line in logging::recent_lines() {
+        report.push_str(&line);
+    }
+    report
+}
+
+/// Crash reports that have not been shown to the user yet, oldest first
+pub fn unseen_crash_reports() -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(crash_reports_dir())
+    else {
+        return Vec::new();
+    };
+    let mut reports: Vec<PathBuf> = entries.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some(REPORT_EXTENSION))
+        .collect();
+    reports.sort();
+    reports
+}
+
+/// Mark a crash report as shown, returns the new path of the report
+pub fn mark_seen(report: &PathBuf) -> PathBuf {
+    let seen = report.with_extension(SEEN_REPORT_EXTENSION);
+    if let Err(e) = fs::rename(report, &seen) {
+        warn!("Could not mark crash report {} as seen: {}", report.display(), e);
+        return report.clone();
+    }
+    seen
+}
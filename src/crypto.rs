@@ -1,10 +1,11 @@
-use argon2::Argon2;
-use curve25519_dalek::{Scalar, RistrettoPoint};
+use argon2::{Algorithm, Argon2, Params, Version};
+use curve25519_dalek::{constants::RISTRETTO_BASEPOINT_POINT, Scalar, RistrettoPoint};
 pub use nazgul::blsag::BLSAG_COMPACT;
 use rand_core::{OsRng, RngCore};
+use crate::constants::KeyImage;
 
 use chacha20poly1305::{
-    aead::{Aead, KeyInit}, AeadCore, ChaCha20Poly1305, Key, Nonce
+    aead::{Aead, AeadInPlace, KeyInit}, AeadCore, ChaCha20Poly1305, Key, Nonce, Tag
 };
 
 const CIPHER: fn(&Key) -> ChaCha20Poly1305 = ChaCha20Poly1305::new;
@@ -66,6 +67,35 @@ pub fn decrypt_message(key: &[u8; KEY_SIZE], encrypted_data: &EncryptionResult)
     }
 }
 
+/// Encrypts a message into `out` in the layout of [`EncryptionResult::encode`], `out` is cleared first.
+/// Only `out` may grow, so reusing it avoids the intermediate buffers of [`encrypt_message`].
+pub fn encrypt_message_into(message: &[u8], key: &[u8; KEY_SIZE], out: &mut Vec<u8>) -> Result<(), ()> {
+    let iv = generate_iv();
+    out.clear();
+    out.reserve(IV_SIZE + message.len() + TAG_SIZE);
+    out.extend_from_slice(&iv);
+    out.extend_from_slice(message);
+    let tag = CIPHER(Key::from_slice(key))
+        .encrypt_in_place_detached(Nonce::from_slice(&iv), b"", &mut out[IV_SIZE..])
+        .map_err(|_| ())?;
+    out.extend_from_slice(&tag);
+    Ok(())
+}
+
+/// Decrypts data in the layout of [`EncryptionResult::encode`] into `out`, `out` is cleared first
+pub fn decrypt_message_into(key: &[u8; KEY_SIZE], data: &[u8], out: &mut Vec<u8>) -> Result<(), ()> {
+    if data.len() < IV_SIZE + TAG_SIZE {
+        return Err(());
+    }
+    let (iv, rest) = data.split_at(IV_SIZE);
+    let (ciphertext, tag) = rest.split_at(rest.len() - TAG_SIZE);
+    out.clear();
+    out.extend_from_slice(ciphertext);
+    CIPHER(Key::from_slice(key))
+        .decrypt_in_place_detached(Nonce::from_slice(iv), b"", out.as_mut_slice(), Tag::from_slice(tag))
+        .map_err(|_| ())
+}
+
 pub fn generate_ephemeral_key() -> [u8; KEY_SIZE] {
     let mut out = [0u8; KEY_SIZE];
     OsRng.fill_bytes(&mut out);
@@ -83,6 +113,12 @@ pub fn sign_message(private_key: &Scalar, personal_key_insertion_index: usize, r
     BLSAG_COMPACT::sign::<sha3::Keccak512, OsRng>(private_key, ring, personal_key_insertion_index, message)
 }
 
+/// The key image in every signature made with `private_key`, it does not depend on the ring
+pub fn key_image(private_key: &Scalar) -> KeyImage {
+    let ring = [private_key * RISTRETTO_BASEPOINT_POINT];
+    sign_message(private_key, 0, &ring, &[]).key_image.compress().to_bytes()
+}
+
 /// Verifies a BLSAG signature
 pub fn verify_message(signature: &BLSAG_COMPACT, ring: &[RistrettoPoint], message: &[u8]) -> bool {
     BLSAG_COMPACT::verify::<sha3::Keccak512>(signature, ring, message)
@@ -106,7 +142,12 @@ pub fn hash_password(password: &[u8]) -> ([u8; 32], [u8; SALT_SIZE]) {
 
 /// Hashes a password using Argon2 with a given salt
 pub fn hash_password_with_salt(password: &[u8], salt: &[u8; SALT_SIZE]) -> [u8; 32] {
-    let argon = Argon2::default();
+    hash_password_with_params(password, salt, Params::default())
+}
+
+/// Hashes a password using Argon2id with a given salt and parameters, see [`crate::config::Argon2Config`]
+pub fn hash_password_with_params(password: &[u8], salt: &[u8; SALT_SIZE], params: Params) -> [u8; 32] {
+    let argon = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
     let mut out = [0u8; 32];
     argon.hash_password_into(password, salt, &mut out).unwrap();
     out
@@ -114,7 +155,7 @@ pub fn hash_password_with_salt(password: &[u8], salt: &[u8; SALT_SIZE]) -> [u8;
 
 #[cfg(test)]
 mod tests {
-    use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+    use proptest::{collection::vec, prelude::*};
     use super::*;
 
     #[test]
@@ -129,6 +170,25 @@ mod tests {
         assert!(decrypt_message(&key, &result).is_err());
     }
 
+    #[test]
+    fn test_encrypt_decrypt_into() {
+        let key = generate_ephemeral_key();
+        let message = b"Hello, world!";
+        let mut encrypted = vec![0xff; 100];
+        encrypt_message_into(message, &key, &mut encrypted).unwrap();
+        // same layout as the allocating functions
+        let result = EncryptionResult::decode(&encrypted).unwrap();
+        assert_eq!(decrypt_message(&key, &result).unwrap(), message);
+
+        let mut plaintext = vec![0xff; 100];
+        decrypt_message_into(&key, &result.encode(), &mut plaintext).unwrap();
+        assert_eq!(plaintext, message);
+
+        encrypted[IV_SIZE] ^= 0x01;
+        assert!(decrypt_message_into(&key, &encrypted, &mut plaintext).is_err());
+        assert!(decrypt_message_into(&key, &encrypted[..IV_SIZE + TAG_SIZE - 1], &mut plaintext).is_err());
+    }
+
     #[test]
     fn test_sign_verify_message() {
         let message = "hi".as_bytes().to_vec();
@@ -140,6 +200,7 @@ mod tests {
         ring.push(pubkey);
         let signature = sign_message(&key, ring.len()-1, &ring, &message);
         assert!(verify_message(&signature, &ring, &message));
+        assert_eq!(key_image(&key), signature.key_image.compress().to_bytes());
     }
 
     #[test]
@@ -148,6 +209,9 @@ mod tests {
         let (hash, salt) = hash_password(password);
         assert_eq!(hash, hash_password_with_salt(password, &salt));
         assert_ne!(hash, hash_password_with_salt(b"password1", &salt));
+        assert_eq!(hash, hash_password_with_params(password, &salt, Params::default()));
+        let params = Params::new(Params::MIN_M_COST * 8, 1, 1, None).unwrap();
+        assert_ne!(hash, hash_password_with_params(password, &salt, params));
     }
 
     #[test]
@@ -159,4 +223,18 @@ mod tests {
         let decoded = EncryptionResult::decode(&encoded).unwrap();
         assert_eq!(expected_encryption_result, decoded);
     }
+
+    proptest! {
+        #[test]
+        fn proptest_encryption_result_round_trip(iv in any::<[u8; IV_SIZE]>(), ciphertext in vec(any::<u8>(), TAG_SIZE..512)) {
+            let result = EncryptionResult { ciphertext, iv };
+            prop_assert_eq!(EncryptionResult::decode(&result.encode()), Ok(result));
+        }
+
+        #[test]
+        fn proptest_encryption_result_decode(data in vec(any::<u8>(), 0..512)) {
+            // never panics, and only frames with room for a tag decode
+            prop_assert_eq!(EncryptionResult::decode(&data).is_ok(), data.len() >= IV_SIZE + TAG_SIZE);
+        }
+    }
 }
@@ -0,0 +1,134 @@
+//! Linking another device of the same user.
+//!
+//! The conferences and peer labels of this device are bundled, encrypted with a key derived from a
+//! random short authentication string and shared as a link code (as text or a QR code). The short
+//! authentication string is read from the screen of this device and typed on the other device, so
+//! the link code alone is useless.
+//!
+//! No identity or key material is shared. Ring signatures need a distinct key for every member of a
+//! conference, so a linked device joins the conferences as a separate, unrelated member with its own
+//! key image, and peers can not tell that both devices belong to the same user.
+
+use std::collections::BTreeMap;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::{
+    config::StorageConfig,
+    constants::{ConferenceId, Result},
+    crypto::{self, EncryptionResult, SALT_SIZE},
+    peer_labels::{self, PeerLabels},
+};
+
+const LINK_PREFIX: &str = "accl";
+const LINK_VERSION: u8 = 1;
+
+/// Shown wherever a link code is created or imported
+pub const SEPARATE_MEMBER_NOTICE: &str = "The other device joins as a separate member with its own key, peers see a new fingerprint and can not tell it is you";
+
+const AUTH_STRING_LENGTH: usize = 10;
+/// Lower case letters and digits without the easily confused `0`, `1`, `l` and `o`
+const AUTH_STRING_ALPHABET: &[u8] = b"23456789abcdefghijkmnpqrstuvwxyz";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkedConference {
+    pub conference_id: ConferenceId,
+    pub password: String,
+}
+
+/// Everything shared with the linked device
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkBundle {
+    pub server_address: String,
+    #[serde(default)]
+    pub conferences: Vec<LinkedConference>,
+    /// Peer labels by hex encoded key image
+    #[serde(default)]
+    pub peer_labels: BTreeMap<String, String>,
+}
+
+/// A new random short authentication string, about 50 bits
+pub fn generate_auth_string() -> String {
+    let mut rng = rand::thread_rng();
+    (0..AUTH_STRING_LENGTH)
+        .map(|_| AUTH_STRING_ALPHABET[rng.gen_range(0..AUTH_STRING_ALPHABET.len())] as char)
+        .collect()
+}
+
+impl LinkBundle {
+    /// The conferences of `server_address` with the peer labels of this device
+    pub fn new(server_address: String, conferences: Vec<LinkedConference>, storage_config: &StorageConfig) -> LinkBundle {
+        let peer_labels = PeerLabels::load(storage_config).iter()
+            .map(|(key_image, label)| (peer_labels::key_image_to_hex(key_image), label.to_string()))
+            .collect();
+        LinkBundle { server_address, conferences, peer_labels }
+    }
+
+    /// Encrypt the bundle into a link code using the short authentication string
+    pub fn encode(&self, auth_string: &str) -> Result<String> {
+        let salt = crypto::generate_salt();
+        let key = crypto::hash_password_with_salt(normalize_auth_string(auth_string).as_bytes(), &salt);
+        let encrypted = crypto::encrypt_message(toml::to_string(self)?.as_bytes(), &key)
+            .map_err(|_| "Could not encrypt the link bundle")?;
+        let mut data = vec![LINK_VERSION];
+        data.extend_from_slice(&salt);
+        data.extend_from_slice(&encrypted.encode());
+        Ok(format!("{}{}", LINK_PREFIX, bs58::encode(data).into_string()))
+    }
+
+    pub fn decode(link_code: &str, auth_string: &str) -> Result<LinkBundle> {
+        let data = link_code.trim()
+            .strip_prefix(LINK_PREFIX)
+            .ok_or("Not a link code")?;
+        let data = bs58::decode(data).into_vec()?;
+        let (&version, data) = data.split_first().ok_or("Link code is empty")?;
+        if version != LINK_VERSION {
+            return Err(format!("Unsupported link code version {}", version).into());
+        }
+        if data.len() <= SALT_SIZE {
+            return Err("Link code is truncated".into());
+        }
+        let salt = data[..SALT_SIZE].try_into()?;
+        let key = crypto::hash_password_with_salt(normalize_auth_string(auth_string).as_bytes(), &salt);
+        let encrypted = EncryptionResult::decode(&data[SALT_SIZE..]).map_err(|_| "Invalid link code")?;
+        let bundle = crypto::decrypt_message(&key, &encrypted).map_err(|_| "Wrong authentication string")?;
+        Ok(toml::from_str(std::str::from_utf8(&bundle)?)?)
+    }
+}
+
+/// The string is shown in groups and may be typed in any case
+fn normalize_auth_string(auth_string: &str) -> String {
+    auth_string.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Show the short authentication string in groups of five
+pub fn format_auth_string(auth_string: &str) -> String {
+    let chars: Vec<char> = auth_string.chars().collect();
+    chars.chunks(5).map(|chunk| chunk.iter().collect::<String>()).collect::<Vec<_>>().join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_link_bundle_roundtrip() {
+        let bundle = LinkBundle {
+            server_address: "example.com:7667".to_string(),
+            conferences: vec![LinkedConference { conference_id: 42, password: "secret".to_string() }],
+            peer_labels: BTreeMap::from([("ab".repeat(32), "Alice".to_string())]),
+        };
+        let auth_string = generate_auth_string();
+        assert_eq!(auth_string.len(), AUTH_STRING_LENGTH);
+        let link_code = bundle.encode(&auth_string).unwrap();
+
+        // the formatted string is accepted as well
+        let formatted = format_auth_string(&auth_string).to_uppercase();
+        assert_eq!(LinkBundle::decode(&link_code, &formatted).unwrap(), bundle);
+        assert!(LinkBundle::decode(&link_code, "wrong").is_err());
+        assert!(LinkBundle::decode("acc123", &auth_string).is_err());
+    }
+}
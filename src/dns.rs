@@ -0,0 +1,208 @@
+//! Resolution of server names, so the lookups do not have to go to the resolver of the local network.
+//!
+//! Besides the resolver of the operating system, names can be resolved with DNS over HTTPS or from a
+//! static mapping in the config, see [`DnsConfig`]. Addresses that are already IP addresses are never looked up.
+
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use async_native_tls::TlsConnector;
+use futures::{AsyncReadExt, AsyncWriteExt};
+use log::debug;
+use crate::{
+    config::{DnsConfig, ResolverKind},
+    constants::Result,
+    runtime,
+    update_check::split_https_url,
+};
+
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+const RECORD_TYPE_A: u16 = 1;
+const RECORD_TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const HEADER_SIZE: usize = 12;
+
+/// The socket addresses of `address` in the `host:port` format, IPv6 addresses in brackets
+pub async fn resolve(config: &DnsConfig, address: &str) -> Result<Vec<SocketAddr>> {
+    let Some((host, port)) = address.rsplit_once(':')
+    else {
+        return Err(format!("Missing port in address {}", address).into());
+    };
+    let port = port.parse::<u16>().map_err(|_| format!("Invalid port in address {}", address))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let addresses = match config.resolver {
+        ResolverKind::System => {
+            let address = address.to_string();
+            runtime::spawn_blocking(move || address.to_socket_addrs().map(|addresses| addresses.collect::<Vec<_>>())).await?
+        },
+        ResolverKind::Doh => {
+            let mut addresses = Vec::new();
+            for record_type in [RECORD_TYPE_A, RECORD_TYPE_AAAA] {
+                let ips = query_doh(&config.doh_url, host, record_type).await
+                    .map_err(|e| format!("Could not resolve {} over HTTPS: {}", host, e))?;
+                addresses.extend(ips.into_iter().map(|ip| SocketAddr::new(ip, port)));
+            }
+            addresses
+        },
+        ResolverKind::Static => {
+            let Some(ip) = config.hosts.get(host)
+            else {
+                return Err(format!("No address for {} in dns.hosts", host).into());
+            };
+            let ip = ip.parse::<IpAddr>().map_err(|_| format!("Invalid address {} for {} in dns.hosts", ip, host))?;
+            vec![SocketAddr::new(ip, port)]
+        },
+    };
+    if addresses.is_empty() {
+        return Err(format!("No addresses found for {}", host).into());
+    }
+    debug!("Resolved {} to {:?}", host, addresses);
+    Ok(addresses)
+}
+
+/// Ask the resolver at `doh_url` for the records of `record_type`, with a single POST request
+async fn query_doh(doh_url: &str, host: &str, record_type: u16) -> Result<Vec<IpAddr>> {
+    let (doh_host, port, path) = split_https_url(doh_url)?;
+    let query = encode_query(host, record_type)?;
+
+    let stream = runtime::connect((doh_host, port)).await?;
+    let mut stream = TlsConnector::new().connect(doh_host, stream).await?;
+    // HTTP/1.0 so that the response is never chunked
+    let request = format!(
+        "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/dns-message\r\nAccept: application/dns-message\r\nContent-Length: {}\r\n\r\n",
+        path, doh_host, query.len(),
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.write_all(&query).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.take(MAX_RESPONSE_SIZE).read_to_end(&mut response).await?;
+    decode_answer(http_body(&response)?, record_type)
+}
+
+/// A DNS query for a single question, with id 0 as recommended for DNS over HTTPS
+fn encode_query(host: &str, record_type: u16) -> Result<Vec<u8>> {
+    let mut query = vec![0, 0, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(format!("Invalid host name {}", host).into());
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+fn http_body(response: &[u8]) -> Result<&[u8]> {
+    let Some(index) = response.windows(4).position(|window| window == b"\r\n\r\n")
+    else {
+        return Err("Invalid HTTP response".into());
+    };
+    let head = String::from_utf8_lossy(&response[..index]);
+    let status_line = head.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("Unexpected HTTP status: {}", status_line).into());
+    }
+    Ok(&response[index + 4..])
+}
+
+/// The addresses in the answer section of a DNS response, records of other types such as CNAMEs are skipped
+fn decode_answer(message: &[u8], record_type: u16) -> Result<Vec<IpAddr>> {
+    let u16_at = |offset: usize| -> Result<u16> {
+        message.get(offset..offset + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(|| "DNS response is truncated".into())
+    };
+    let flags = u16_at(2)?;
+    match flags & 0x0f {
+        0 => {},
+        3 => return Err("The name does not exist".into()),
+        rcode => return Err(format!("DNS error code {}", rcode).into()),
+    }
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+
+    let mut offset = HEADER_SIZE;
+    for _ in 0..questions {
+        offset = skip_name(message, offset)? + 4;
+    }
+    let mut addresses = Vec::new();
+    for _ in 0..answers {
+        offset = skip_name(message, offset)?;
+        let answer_type = u16_at(offset)?;
+        let data_length = u16_at(offset + 8)? as usize;
+        let data = message.get(offset + 10..offset + 10 + data_length).ok_or("DNS response is truncated")?;
+        offset += 10 + data_length;
+        if answer_type != record_type {
+            continue;
+        }
+        match (answer_type, data.len()) {
+            (RECORD_TYPE_A, 4) => addresses.push(IpAddr::from(<[u8; 4]>::try_from(data).unwrap())),
+            (RECORD_TYPE_AAAA, 16) => addresses.push(IpAddr::from(<[u8; 16]>::try_from(data).unwrap())),
+            _ => return Err("Invalid address record in DNS response".into()),
+        }
+    }
+    Ok(addresses)
+}
+
+/// Offset after the name at `offset`, names may end with a pointer to an earlier name
+fn skip_name(message: &[u8], mut offset: usize) -> Result<usize> {
+    loop {
+        let length = *message.get(offset).ok_or("DNS response is truncated")?;
+        match length {
+            0 => return Ok(offset + 1),
+            length if length & 0xc0 == 0xc0 => return Ok(offset + 2),
+            length => offset += 1 + length as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, net::Ipv4Addr};
+    use super::*;
+
+    #[test]
+    fn test_resolve_without_lookup() {
+        runtime::block_on(async {
+            let mut config = DnsConfig { resolver: ResolverKind::Static, hosts: BTreeMap::new(), ..DnsConfig::default() };
+            config.hosts.insert("conference.example".to_string(), "192.0.2.7".to_string());
+            assert_eq!(resolve(&config, "conference.example:7667").await.unwrap(), vec!["192.0.2.7:7667".parse().unwrap()]);
+            assert!(resolve(&config, "other.example:7667").await.is_err());
+            assert!(resolve(&config, "conference.example").await.is_err());
+            // addresses are used as they are with every resolver
+            assert_eq!(resolve(&config, "[::1]:7667").await.unwrap(), vec!["[::1]:7667".parse().unwrap()]);
+            config.resolver = ResolverKind::Doh;
+            assert_eq!(resolve(&config, "127.0.0.1:7667").await.unwrap(), vec!["127.0.0.1:7667".parse().unwrap()]);
+        });
+    }
+
+    #[test]
+    fn test_decode_answer() {
+        let mut response = encode_query("conference.example", RECORD_TYPE_A).unwrap();
+        // a response with two answers
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        // a CNAME pointing to another name, then its address
+        response.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 4, 1, b'a', 0xc0, 12]);
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 7]);
+        assert_eq!(decode_answer(&response, RECORD_TYPE_A).unwrap(), vec![IpAddr::from(Ipv4Addr::new(192, 0, 2, 7))]);
+        assert!(decode_answer(&response[..response.len() - 1], RECORD_TYPE_A).is_err());
+
+        // NXDOMAIN
+        response[3] = 0x83;
+        assert!(decode_answer(&response, RECORD_TYPE_A).is_err());
+
+        assert!(http_body(b"HTTP/1.1 200 OK\r\nContent-Type: application/dns-message\r\n\r\n\x00").is_ok());
+        assert!(http_body(b"HTTP/1.1 400 Bad Request\r\n\r\n").is_err());
+        assert!(encode_query("bad..example", RECORD_TYPE_A).is_err());
+    }
+}
@@ -0,0 +1,529 @@
+//! Files sent to a conference.
+//!
+//! A file is offered with its name, size and chunk size, then uploaded like a large message (see [`crate::upload`])
+//! in signed chunks that continue from the last accepted chunk after a reconnect, and finished with the hash of its
+//! content. Peers write the chunks into a partial file in the downloads directory as they arrive and give it its
+//! name once all chunks and the hash arrived and the content matches the hash. Only the parts with valid
+//! signatures are passed on, anyone can copy the key image of the sender.
+
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use log::{debug, warn};
+use sha3::{Digest, Sha3_256};
+use crate::{
+    constants::{KeyImage, MessageID, Peer, Result},
+    paths,
+    sanitize,
+    upload::{Chunk, UploadId},
+};
+
+/// Files are identified by the id of the upload carrying their content
+pub type FileId = UploadId;
+
+/// Name of the directory in the data directory received files are written to
+const DOWNLOADS_DIR_NAME: &str = "downloads";
+const PARTIAL_FILE_EXTENSION: &str = "part";
+/// Longer names are cut off, in bytes
+const MAX_FILE_NAME_LENGTH: usize = 255;
+/// Used for names that are empty once cleaned up
+const DEFAULT_FILE_NAME: &str = "file";
+/// file id, size, chunk size and number of chunks in front of the name
+const OFFER_HEADER_SIZE: usize = 8 + 8 + 4 + 4;
+const HASH_SIZE: usize = 32;
+/// Incomplete files of peers are dropped after this long without a new chunk
+const INCOMING_FILE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Files of peers received at the same time, the oldest one is dropped for a new one
+const MAX_INCOMING_FILES: usize = 4;
+
+/// Directory received files are written to
+pub fn downloads_dir() -> PathBuf {
+    paths::data_dir().join(DOWNLOADS_DIR_NAME)
+}
+
+/// Announces a file before its chunks, the chunks have `chunk_size` bytes except for the last one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileOffer {
+    pub file_id: FileId,
+    pub name: String,
+    pub size: u64,
+    pub chunk_size: u32,
+    pub chunks: u32,
+}
+
+impl FileOffer {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(OFFER_HEADER_SIZE + self.name.len());
+        result.extend_from_slice(&self.file_id.to_be_bytes());
+        result.extend_from_slice(&self.size.to_be_bytes());
+        result.extend_from_slice(&self.chunk_size.to_be_bytes());
+        result.extend_from_slice(&self.chunks.to_be_bytes());
+        result.extend_from_slice(self.name.as_bytes());
+        result
+    }
+
+    /// `None` unless the number of chunks fits the size and chunk size
+    pub fn decode(offer: &[u8]) -> Option<FileOffer> {
+        if offer.len() < OFFER_HEADER_SIZE {
+            return None;
+        }
+        let file_id = FileId::from_be_bytes(offer[..8].try_into().unwrap());
+        let size = u64::from_be_bytes(offer[8..16].try_into().unwrap());
+        let chunk_size = u32::from_be_bytes(offer[16..20].try_into().unwrap());
+        let chunks = u32::from_be_bytes(offer[20..24].try_into().unwrap());
+        let name = String::from_utf8(offer[OFFER_HEADER_SIZE..].to_vec()).ok()?;
+        if chunk_size == 0 || size == 0 || size.div_ceil(chunk_size as u64) != chunks as u64 {
+            return None;
+        }
+        Some(FileOffer { file_id, name, size, chunk_size, chunks })
+    }
+
+    /// The length chunk `index` must have
+    fn chunk_length(&self, index: u32) -> usize {
+        let start = index as u64 * self.chunk_size as u64;
+        (self.size - start).min(self.chunk_size as u64) as usize
+    }
+}
+
+/// Sent after the last chunk of a file with the hash of its content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileComplete {
+    pub file_id: FileId,
+    pub hash: [u8; HASH_SIZE],
+}
+
+impl FileComplete {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(8 + HASH_SIZE);
+        result.extend_from_slice(&self.file_id.to_be_bytes());
+        result.extend_from_slice(&self.hash);
+        result
+    }
+
+    pub fn decode(complete: &[u8]) -> Option<FileComplete> {
+        if complete.len() != 8 + HASH_SIZE {
+            return None;
+        }
+        let file_id = FileId::from_be_bytes(complete[..8].try_into().unwrap());
+        Some(FileComplete { file_id, hash: complete[8..].try_into().unwrap() })
+    }
+}
+
+/// An own file, kept with its upload to announce it again after a reconnect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingFile {
+    pub offer: FileOffer,
+    pub complete: FileComplete,
+}
+
+impl OutgoingFile {
+    pub fn new(file_id: FileId, name: String, content: &[u8], chunk_size: usize) -> OutgoingFile {
+        let offer = FileOffer {
+            file_id,
+            name,
+            size: content.len() as u64,
+            chunk_size: chunk_size as u32,
+            chunks: content.len().div_ceil(chunk_size) as u32,
+        };
+        let complete = FileComplete { file_id, hash: Sha3_256::digest(content).into() };
+        OutgoingFile { offer, complete }
+    }
+
+    /// The progress after `chunks_done` chunks were accepted by the server
+    pub fn progress(&self, message_id: MessageID, chunks_done: usize) -> FileTransfer {
+        FileTransfer::new(&self.offer, Direction::Sending(message_id), chunks_done)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// an own file, sent as the message with this id
+    Sending(MessageID),
+    Receiving(Peer),
+}
+
+/// How far a file got, see [`crate::constants::UIEvent::FileTransferProgress`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTransfer {
+    pub file_id: FileId,
+    pub name: String,
+    pub size: u64,
+    pub direction: Direction,
+    pub chunks_done: usize,
+    pub chunks: usize,
+}
+
+impl FileTransfer {
+    fn new(offer: &FileOffer, direction: Direction, chunks_done: usize) -> FileTransfer {
+        FileTransfer {
+            file_id: offer.file_id,
+            name: offer.name.clone(),
+            size: offer.size,
+            direction,
+            chunks_done,
+            chunks: offer.chunks as usize,
+        }
+    }
+}
+
+/// A file of a peer that arrived completely and matched its hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedFile {
+    pub file_id: FileId,
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub sender: Peer,
+}
+
+/// The size of a file for the UIs
+pub fn format_file_size(size: u64) -> String {
+    if size < 1024 {
+        format!("{} bytes", size)
+    } else if size < 1024 * 1024 {
+        format!("{} KB", size / 1024)
+    } else {
+        format!("{:.1} MB", size as f64 / (1024.0 * 1024.0))
+    }
+}
+
+/// Read a file to send, its name is sent along without the directory
+pub fn read_file(path: &Path, max_size: u64) -> Result<(String, Bytes)> {
+    let size = fs::metadata(path)?.len();
+    if size == 0 {
+        return Err(format!("{} is empty", path.display()).into());
+    }
+    if size > max_size {
+        return Err(format!("{} is larger than {} bytes", path.display(), max_size).into());
+    }
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    Ok((safe_file_name(&name), fs::read(path)?.into()))
+}
+
+/// The last component of a name sent by a peer without controls and leading dots, so it can not leave the
+/// downloads directory or hide there
+fn safe_file_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = sanitize::sanitize_text(name).chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    let mut name = name.trim().trim_start_matches('.').to_string();
+    while name.len() > MAX_FILE_NAME_LENGTH {
+        name.pop();
+    }
+    if name.is_empty() {
+        return DEFAULT_FILE_NAME.to_string();
+    }
+    name
+}
+
+/// `name` in `dir`, with a number added before the extension if a file of that name exists
+fn unused_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    (1..)
+        .map(|number| dir.join(format!("{} ({}){}", stem, number, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+struct IncomingFile {
+    offer: FileOffer,
+    /// signed the offer, the chunks and the hash of other key images are dropped
+    sender: Peer,
+    received: Vec<bool>,
+    chunks_received: usize,
+    hash: Option<[u8; HASH_SIZE]>,
+    last_update: Instant,
+}
+
+/// Writes the files of peers to the downloads directory
+pub struct IncomingFiles {
+    dir: PathBuf,
+    max_size: u64,
+    files: HashMap<FileId, IncomingFile>,
+}
+
+impl IncomingFiles {
+    /// Files larger than `max_size` bytes are refused
+    pub fn new(dir: PathBuf, max_size: u64) -> IncomingFiles {
+        IncomingFiles { dir, max_size, files: HashMap::new() }
+    }
+
+    /// Start receiving a file offered with a valid signature, returns its progress unless it is refused or was
+    /// offered before, which happens when the sender continues the upload after a reconnect
+    pub fn offer(&mut self, offer: FileOffer, sender: Peer, now: Instant) -> Result<Option<FileTransfer>> {
+        self.expire(now);
+        if self.files.contains_key(&offer.file_id) {
+            return Ok(None);
+        }
+        if offer.size > self.max_size {
+            warn!("Refused a file of {} bytes, files are received up to {} bytes", offer.size, self.max_size);
+            return Ok(None);
+        }
+        if self.files.len() >= MAX_INCOMING_FILES {
+            if let Some(oldest) = self.files.iter().min_by_key(|(_, file)| file.last_update).map(|(file_id, _)| *file_id) {
+                warn!("Too many incomplete files, dropping the oldest one");
+                self.remove(oldest);
+            }
+        }
+        fs::create_dir_all(&self.dir)?;
+        let offer = FileOffer { name: safe_file_name(&offer.name), ..offer };
+        debug!("Receiving file {:016x} of {} bytes in {} chunks", offer.file_id, offer.size, offer.chunks);
+        let transfer = FileTransfer::new(&offer, Direction::Receiving(sender.clone()), 0);
+        self.files.insert(offer.file_id, IncomingFile {
+            received: vec![false; offer.chunks as usize],
+            offer,
+            sender,
+            chunks_received: 0,
+            hash: None,
+            last_update: now,
+        });
+        Ok(Some(transfer))
+    }
+
+    /// Write a chunk with a valid signature to the partial file, returns the progress unless the chunk is unknown,
+    /// arrived before or was not signed by the sender of the offer
+    pub fn chunk(&mut self, chunk: &Chunk, key_image: KeyImage, now: Instant) -> Result<Option<FileTransfer>> {
+        let part_path = self.part_path(chunk.upload_id);
+        let Some(file) = self.files.get_mut(&chunk.upload_id)
+        else {
+            return Ok(None);
+        };
+        if file.sender.key_image != key_image {
+            warn!("Dropped chunk {} of file {:016x} signed by another peer than its offer", chunk.index, chunk.upload_id);
+            return Ok(None);
+        }
+        if chunk.total != file.offer.chunks || chunk.data.len() != file.offer.chunk_length(chunk.index) {
+            return Err(format!("chunk {} of file {:016x} does not match its offer", chunk.index, chunk.upload_id).into());
+        }
+        file.last_update = now;
+        if file.received[chunk.index as usize] {
+            return Ok(None);
+        }
+        let mut part = OpenOptions::new().create(true).write(true).truncate(false).open(part_path)?;
+        part.seek(SeekFrom::Start(chunk.index as u64 * file.offer.chunk_size as u64))?;
+        part.write_all(&chunk.data)?;
+        file.received[chunk.index as usize] = true;
+        file.chunks_received += 1;
+        Ok(Some(FileTransfer::new(&file.offer, Direction::Receiving(file.sender.clone()), file.chunks_received)))
+    }
+
+    /// Remember the hash sent after the last chunk, only the first one validly signed by the sender of the offer is kept
+    pub fn complete(&mut self, complete: FileComplete, key_image: KeyImage) {
+        let Some(file) = self.files.get_mut(&complete.file_id)
+        else {
+            return;
+        };
+        if file.sender.key_image != key_image {
+            warn!("Dropped the hash of file {:016x} signed by another peer than its offer", complete.file_id);
+            return;
+        }
+        if file.hash.is_some() {
+            debug!("Dropped another hash of file {:016x}", complete.file_id);
+            return;
+        }
+        file.hash = Some(complete.hash);
+    }
+
+    /// Give the file its name once all chunks and the hash arrived, a file that does not match its hash is removed
+    pub fn finish(&mut self, file_id: FileId) -> Result<Option<ReceivedFile>> {
+        let Some(file) = self.files.get(&file_id)
+        else {
+            return Ok(None);
+        };
+        let Some(hash) = file.hash.filter(|_| file.chunks_received == file.received.len())
+        else {
+            return Ok(None);
+        };
+        let file = self.files.remove(&file_id).unwrap();
+        let part_path = self.part_path(file_id);
+        let matches = fs::read(&part_path).map(|content| <[u8; HASH_SIZE]>::from(Sha3_256::digest(content)) == hash);
+        if !matches.as_ref().is_ok_and(|matches| *matches) {
+            remove_partial_file(&part_path);
+            matches?;
+            return Err(format!("file {} does not match its hash", file.offer.name).into());
+        }
+        let path = unused_path(&self.dir, &file.offer.name);
+        fs::rename(&part_path, &path)?;
+        Ok(Some(ReceivedFile {
+            file_id,
+            name: file.offer.name,
+            path,
+            size: file.offer.size,
+            sender: file.sender,
+        }))
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let expired: Vec<FileId> = self.files.iter()
+            .filter(|(_, file)| now.duration_since(file.last_update) >= INCOMING_FILE_TIMEOUT)
+            .map(|(file_id, _)| *file_id)
+            .collect();
+        for file_id in expired {
+            debug!("Dropping file {:016x}, no chunk arrived for {:?}", file_id, INCOMING_FILE_TIMEOUT);
+            self.remove(file_id);
+        }
+    }
+
+    fn remove(&mut self, file_id: FileId) {
+        self.files.remove(&file_id);
+        remove_partial_file(&self.part_path(file_id));
+    }
+
+    fn part_path(&self, file_id: FileId) -> PathBuf {
+        self.dir.join(format!(".{:016x}.{}", file_id, PARTIAL_FILE_EXTENSION))
+    }
+}
+
+impl Drop for IncomingFiles {
+    /// Incomplete files can not be continued by a later conference manager
+    fn drop(&mut self) {
+        for file_id in self.files.keys() {
+            remove_partial_file(&self.part_path(*file_id));
+        }
+    }
+}
+
+fn remove_partial_file(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Could not remove partial file {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("acc-files-test-{}", rand::random::<u32>()))
+    }
+
+    fn chunks(file_id: FileId, content: &[u8], chunk_size: usize) -> Vec<Chunk> {
+        let total = content.len().div_ceil(chunk_size) as u32;
+        content.chunks(chunk_size).enumerate()
+            .map(|(index, data)| Chunk { upload_id: file_id, index: index as u32, total, data: Bytes::copy_from_slice(data) })
+            .collect()
+    }
+
+    #[test]
+    fn test_offer_and_complete_encoding() {
+        let file = OutgoingFile::new(5, "notes.txt".to_string(), b"hello world", 4);
+        assert_eq!(file.offer.chunks, 3);
+        assert_eq!(FileOffer::decode(&file.offer.encode()), Some(file.offer.clone()));
+        assert_eq!(FileComplete::decode(&file.complete.encode()), Some(file.complete.clone()));
+        // the number of chunks does not fit the size
+        let bad_offer = FileOffer { chunks: 2, ..file.offer };
+        assert_eq!(FileOffer::decode(&bad_offer.encode()), None);
+        assert_eq!(FileOffer::decode(&[0; OFFER_HEADER_SIZE - 1]), None);
+        assert_eq!(FileComplete::decode(&[0; 8 + HASH_SIZE + 1]), None);
+    }
+
+    #[test]
+    fn test_format_file_size() {
+        assert_eq!(format_file_size(1023), "1023 bytes");
+        assert_eq!(format_file_size(4096), "4 KB");
+        assert_eq!(format_file_size(3 * 1024 * 1024 / 2), "1.5 MB");
+    }
+
+    #[test]
+    fn test_safe_file_name() {
+        assert_eq!(safe_file_name("../../.bashrc"), "bashrc");
+        assert_eq!(safe_file_name("C:\\Users\\me\\report.pdf"), "report.pdf");
+        assert_eq!(safe_file_name("a\u{202e}b\u{7}.txt"), "ab␇.txt");
+        assert_eq!(safe_file_name(".."), DEFAULT_FILE_NAME);
+        assert_eq!(safe_file_name(&"x".repeat(300)).len(), MAX_FILE_NAME_LENGTH);
+    }
+
+    #[test]
+    fn test_receive_file() {
+        let dir = temporary_dir();
+        let content = b"hello world";
+        let file = OutgoingFile::new(7, "notes.txt".to_string(), content, 4);
+        let sender = Peer { key_image: [3; 32], label: None };
+        let now = Instant::now();
+        let mut incoming = IncomingFiles::new(dir.clone(), 1024);
+        assert!(incoming.offer(file.offer.clone(), sender.clone(), now).unwrap().is_some());
+        // offered again after the sender reconnected
+        assert_eq!(incoming.offer(file.offer.clone(), sender.clone(), now).unwrap(), None);
+
+        let mut chunks = chunks(7, content, 4);
+        // chunks may arrive out of order and twice
+        chunks.swap(0, 2);
+        for (received, chunk) in chunks.iter().enumerate() {
+            let progress = incoming.chunk(chunk, sender.key_image, now).unwrap().unwrap();
+            assert_eq!((progress.chunks_done, progress.chunks), (received + 1, 3));
+        }
+        assert_eq!(incoming.chunk(&chunks[0], sender.key_image, now).unwrap(), None);
+        assert_eq!(incoming.finish(7).unwrap(), None);
+        incoming.complete(file.complete.clone(), sender.key_image);
+        let received = incoming.finish(7).unwrap().unwrap();
+        assert_eq!((received.name.as_str(), received.size), ("notes.txt", 11));
+        assert_eq!(fs::read(&received.path).unwrap(), content);
+
+        // a second file of the same name is kept next to the first
+        let file = OutgoingFile::new(8, "notes.txt".to_string(), content, 16);
+        incoming.offer(file.offer, sender.clone(), now).unwrap();
+        incoming.chunk(&chunks(8, content, 16)[0], sender.key_image, now).unwrap();
+        incoming.complete(file.complete, sender.key_image);
+        assert_eq!(incoming.finish(8).unwrap().unwrap().path, dir.join("notes (1).txt"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_refused_and_corrupted_files() {
+        let dir = temporary_dir();
+        let sender = Peer { key_image: [3; 32], label: None };
+        let now = Instant::now();
+        let mut incoming = IncomingFiles::new(dir.clone(), 8);
+        let large = OutgoingFile::new(1, "large".to_string(), &[1; 9], 4);
+        assert_eq!(incoming.offer(large.offer, sender.clone(), now).unwrap(), None);
+
+        let file = OutgoingFile::new(2, "small".to_string(), &[1; 8], 4);
+        incoming.offer(file.offer, sender.clone(), now).unwrap();
+        let wrong_length = Chunk { upload_id: 2, index: 1, total: 2, data: Bytes::from_static(&[1; 3]) };
+        assert!(incoming.chunk(&wrong_length, sender.key_image, now).is_err());
+        for chunk in chunks(2, &[2; 8], 4) {
+            incoming.chunk(&chunk, sender.key_image, now).unwrap();
+        }
+        incoming.complete(file.complete, sender.key_image);
+        assert!(incoming.finish(2).is_err());
+        assert!(!incoming.part_path(2).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parts_of_other_peers_are_dropped() {
+        let dir = temporary_dir();
+        let sender = Peer { key_image: [3; 32], label: None };
+        let other = [4; 32];
+        let now = Instant::now();
+        let mut incoming = IncomingFiles::new(dir.clone(), 1024);
+        let content = b"hello world";
+        let file = OutgoingFile::new(9, "notes.txt".to_string(), content, 16);
+        incoming.offer(file.offer, sender.clone(), now).unwrap();
+        let chunk = &chunks(9, content, 16)[0];
+        assert_eq!(incoming.chunk(&Chunk { data: Bytes::from_static(b"evil world!"), ..chunk.clone() }, other, now).unwrap(), None);
+        assert!(incoming.chunk(chunk, sender.key_image, now).unwrap().is_some());
+        incoming.complete(FileComplete { file_id: 9, hash: [0; HASH_SIZE] }, other);
+        incoming.complete(file.complete.clone(), sender.key_image);
+        // the first hash stays
+        incoming.complete(FileComplete { file_id: 9, hash: [0; HASH_SIZE] }, sender.key_image);
+        assert_eq!(fs::read(incoming.finish(9).unwrap().unwrap().path).unwrap(), content);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+//! Fingerprints of key images as words, easier to compare out of band than hex.
+//!
+//! Peers only ever see the key image of a member, so the own fingerprint is derived from the own key image too.
+
+use sha3::{Digest, Sha3_256};
+use crate::constants::KeyImage;
+
+/// Words encoding the first bytes of the key image hash, 32 bits
+const FINGERPRINT_WORDS: usize = 4;
+
+/// One word per byte value
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adult", "agent", "alarm", "album", "alley",
+    "amber", "angle", "ankle", "apple", "apron", "arena", "armor", "arrow",
+    "atlas", "attic", "audio", "award", "bacon", "badge", "bagel", "baker",
+    "bamboo", "banjo", "barn", "basin", "beach", "beard", "berry", "bison",
+    "blade", "blank", "bloom", "board", "boat", "bonus", "boot", "brick",
+    "bridge", "brush", "bucket", "bulb", "cabin", "cable", "cactus", "camel",
+    "candle", "canoe", "canyon", "carbon", "cargo", "carpet", "cedar", "chalk",
+    "cherry", "chess", "chimney", "cider", "cinema", "circus", "clay", "cliff",
+    "clock", "cloud", "clover", "coast", "cobra", "comet", "coral", "cotton",
+    "crane", "crater", "crown", "cube", "cupid", "daisy", "dance", "delta",
+    "denim", "desert", "diary", "dingo", "diver", "dock", "dolphin", "donkey",
+    "dragon", "drum", "eagle", "easel", "echo", "elbow", "elder", "ember",
+    "engine", "fabric", "falcon", "fence", "ferry", "fiber", "field", "film",
+    "flame", "flute", "fossil", "frost", "galaxy", "garden", "garlic", "gecko",
+    "geyser", "ginger", "glacier", "glove", "goat", "gold", "gravel", "guitar",
+    "hammer", "harbor", "harp", "hazel", "helmet", "hermit", "hollow", "honey",
+    "hornet", "hotel", "igloo", "index", "ink", "iris", "island", "ivory",
+    "jacket", "jade", "jaguar", "jelly", "jewel", "jungle", "kayak", "kernel",
+    "kettle", "kiwi", "koala", "ladder", "lagoon", "lamp", "lantern", "laser",
+    "lemon", "lens", "lilac", "linen", "lizard", "llama", "lobster", "locket",
+    "lotus", "lunar", "magnet", "mango", "maple", "marble", "meadow", "melon",
+    "meteor", "mint", "mirror", "mitten", "monk", "moose", "mosaic", "motor",
+    "mural", "nectar", "needle", "nest", "nickel", "noodle", "oasis", "ocean",
+    "olive", "onion", "opal", "orbit", "orchid", "otter", "oven", "owl",
+    "paddle", "palm", "panda", "paper", "parrot", "pasta", "peach", "pearl",
+    "pebble", "pepper", "piano", "pilot", "pine", "planet", "plum", "pocket",
+    "polar", "pony", "poppy", "prism", "pumpkin", "puzzle", "quartz", "quill",
+    "rabbit", "radar", "radio", "raven", "reef", "ribbon", "river", "robin",
+    "rocket", "ruby", "saddle", "salmon", "satin", "scarf", "shadow", "shell",
+    "silver", "sketch", "sled", "slope", "snail", "socket", "solar", "sonar",
+    "spark", "spider", "spoon", "squid", "stamp", "storm", "sugar", "summit",
+    "swan", "tablet", "tango", "teapot", "temple", "thistle", "thunder", "tiger",
+];
+
+/// Encode every byte as a word, separated by dashes
+pub fn words(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| WORDS[byte as usize]).collect::<Vec<&str>>().join("-")
+}
+
+/// Word fingerprint of a key image, the same for the member and every peer
+pub fn key_image_fingerprint(key_image: &KeyImage) -> String {
+    let hash = Sha3_256::digest(key_image);
+    words(&hash[..FINGERPRINT_WORDS])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_words_are_unique() {
+        assert_eq!(WORDS.iter().collect::<HashSet<_>>().len(), WORDS.len());
+    }
+
+    #[test]
+    fn test_key_image_fingerprint() {
+        assert_eq!(words(&[0, 1, 255]), "acid-acorn-tiger");
+        let fingerprint = key_image_fingerprint(&[7; 32]);
+        assert_eq!(fingerprint.split('-').count(), FINGERPRINT_WORDS);
+        assert_eq!(fingerprint, key_image_fingerprint(&[7; 32]));
+        assert_ne!(fingerprint, key_image_fingerprint(&[8; 32]));
+    }
+}
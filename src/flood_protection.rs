@@ -0,0 +1,153 @@
+//! Per sender rate limiting of incoming messages.
+//!
+//! Senders are identified by the key image of their ring signature, so only messages with a valid
+//! signature are counted, otherwise a peer could get someone else muted by copying their key image.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{config::FloodProtectionConfig, constants::KeyImage};
+
+/// Senders without recent messages are forgotten once this many are tracked
+const TRACKED_SENDERS_CLEANUP_THRESHOLD: usize = 256;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Show the message
+    Allow,
+    /// The sender just crossed the threshold, drop the message and notify the user
+    Muted,
+    /// The sender is muted, drop the message
+    Suppressed,
+    /// The mute of the sender has ended, show the message and report how many were dropped
+    Unmuted(usize),
+}
+
+#[derive(Default)]
+struct SenderState {
+    recent: VecDeque<Instant>,
+    muted_until: Option<Instant>,
+    suppressed: usize,
+    /// The user allowed the sender to send at any rate
+    exempt: bool,
+}
+
+pub struct FloodGuard {
+    config: FloodProtectionConfig,
+    senders: HashMap<KeyImage, SenderState>,
+}
+
+impl FloodGuard {
+    pub fn new(config: FloodProtectionConfig) -> FloodGuard {
+        FloodGuard { config, senders: HashMap::new() }
+    }
+
+    /// Count a message of `key_image` received at `now`
+    pub fn check(&mut self, key_image: KeyImage, now: Instant) -> Verdict {
+        if !self.config.enabled {
+            return Verdict::Allow;
+        }
+        if self.senders.len() >= TRACKED_SENDERS_CLEANUP_THRESHOLD {
+            self.forget_idle_senders(now);
+        }
+        let interval = Duration::from_secs(self.config.interval_secs);
+        let state = self.senders.entry(key_image).or_default();
+        if state.exempt {
+            return Verdict::Allow;
+        }
+
+        let mut unmuted = None;
+        if let Some(muted_until) = state.muted_until {
+            if now < muted_until {
+                state.suppressed += 1;
+                return Verdict::Suppressed;
+            }
+            state.muted_until = None;
+            state.recent.clear();
+            unmuted = Some(std::mem::take(&mut state.suppressed));
+        }
+
+        while state.recent.front().is_some_and(|&time| now.duration_since(time) >= interval) {
+            state.recent.pop_front();
+        }
+        state.recent.push_back(now);
+        if state.recent.len() > self.config.max_messages {
+            state.muted_until = Some(now + Duration::from_secs(self.config.mute_secs));
+            state.suppressed = 1;
+            return Verdict::Muted;
+        }
+        match unmuted {
+            Some(suppressed) => Verdict::Unmuted(suppressed),
+            None => Verdict::Allow,
+        }
+    }
+
+    /// Stop limiting a sender, returns the number of dropped messages if the sender was muted
+    pub fn exempt(&mut self, key_image: KeyImage) -> Option<usize> {
+        let state = self.senders.entry(key_image).or_default();
+        state.exempt = true;
+        state.recent.clear();
+        state.muted_until.take().map(|_| std::mem::take(&mut state.suppressed))
+    }
+
+    fn forget_idle_senders(&mut self, now: Instant) {
+        let interval = Duration::from_secs(self.config.interval_secs);
+        self.senders.retain(|_, state| {
+            state.exempt
+                || state.muted_until.is_some()
+                || state.recent.back().is_some_and(|&time| now.duration_since(time) < interval)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> FloodProtectionConfig {
+        FloodProtectionConfig { enabled: true, max_messages: 3, interval_secs: 10, mute_secs: 60 }
+    }
+
+    #[test]
+    fn test_flood_guard_mutes_and_unmutes() {
+        let mut guard = FloodGuard::new(config());
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(guard.check([1; 32], start), Verdict::Allow);
+        }
+        assert_eq!(guard.check([1; 32], start), Verdict::Muted);
+        assert_eq!(guard.check([1; 32], start + Duration::from_secs(1)), Verdict::Suppressed);
+        // other senders are not affected
+        assert_eq!(guard.check([2; 32], start), Verdict::Allow);
+        assert_eq!(guard.check([1; 32], start + Duration::from_secs(61)), Verdict::Unmuted(2));
+        assert_eq!(guard.check([1; 32], start + Duration::from_secs(62)), Verdict::Allow);
+    }
+
+    #[test]
+    fn test_flood_guard_window_and_exempt() {
+        let mut guard = FloodGuard::new(config());
+        let start = Instant::now();
+        for index in 0..10 {
+            // one message every 5 seconds stays below 3 per 10 seconds
+            assert_eq!(guard.check([1; 32], start + Duration::from_secs(index * 5)), Verdict::Allow);
+        }
+
+        for _ in 0..4 {
+            guard.check([2; 32], start);
+        }
+        assert_eq!(guard.exempt([2; 32]), Some(1));
+        for _ in 0..10 {
+            assert_eq!(guard.check([2; 32], start), Verdict::Allow);
+        }
+    }
+
+    #[test]
+    fn test_flood_guard_disabled() {
+        let mut guard = FloodGuard::new(FloodProtectionConfig { enabled: false, ..config() });
+        for _ in 0..10 {
+            assert_eq!(guard.check([1; 32], Instant::now()), Verdict::Allow);
+        }
+    }
+}
@@ -2,6 +2,12 @@ pub mod main_window;
 mod stack;
 mod create_conference_frame;
 mod join_conference_frame;
+mod browse_conferences_frame;
 mod conference_widget_factory;
 mod message_list_item;
 mod constants;
+mod layout;
+mod notification;
+mod file_dialogs;
+#[cfg(all(target_os = "macos", feature = "macos"))]
+mod macos;
@@ -0,0 +1,104 @@
+use gtk::prelude::*;
+use relm4::*;
+use crate::{
+    constants::{ConferenceId, NumberOfPeers},
+    gtk_ui::constants::GUIAction,
+};
+
+const BROWSE_FRAME_TITLE: &str = "Public Conferences";
+const REFRESH_BUTTON_TEXT: &str = "Refresh";
+const JOIN_BUTTON_TEXT: &str = "Join";
+const NOT_LOADED_TEXT: &str = "Press refresh to load the public conferences of the server";
+const NO_DIRECTORY_TEXT: &str = "The server does not offer a directory of public conferences";
+const NO_CONFERENCES_TEXT: &str = "There are no public conferences";
+
+pub struct BrowseConferencesFrame {
+    list_box: gtk::ListBox,
+    status_text: &'static str,
+}
+
+#[derive(Debug)]
+pub enum BrowseConferencesInput {
+    Directory(Option<Vec<(ConferenceId, NumberOfPeers)>>),
+}
+
+#[relm4::component(pub)]
+impl SimpleComponent for BrowseConferencesFrame {
+    type Init = ();
+    type Input = BrowseConferencesInput;
+    type Output = GUIAction;
+
+    view! {
+        #[root]
+        gtk::Frame {
+            set_label: Some(BROWSE_FRAME_TITLE),
+            set_halign: gtk::Align::Center,
+            set_valign: gtk::Align::Center,
+            set_width_request: 300,
+
+            gtk::Box {
+                set_orientation: gtk::Orientation::Vertical,
+                set_spacing: 10,
+                set_margin_all: 10,
+
+                gtk::Button {
+                    set_label: REFRESH_BUTTON_TEXT,
+                    connect_clicked[sender] => move |_| {
+                        sender.output(GUIAction::ListConferences).unwrap();
+                    },
+                },
+                gtk::Label {
+                    #[watch]
+                    set_visible: !model.status_text.is_empty(),
+                    #[watch]
+                    set_text: model.status_text,
+                    set_wrap: true,
+                },
+                gtk::ScrolledWindow {
+                    set_min_content_height: 300,
+                    set_child: Some(&model.list_box),
+                },
+            }
+        }
+    }
+
+    fn init(
+        _params: Self::Init,
+        root: Self::Root,
+        sender: ComponentSender<Self>,
+    ) -> ComponentParts<Self> {
+        let list_box = gtk::ListBox::new();
+        list_box.set_selection_mode(gtk::SelectionMode::None);
+        let model = BrowseConferencesFrame { list_box, status_text: NOT_LOADED_TEXT };
+        let widgets = view_output!();
+        ComponentParts { model, widgets }
+    }
+
+    fn update(&mut self, message: Self::Input, sender: ComponentSender<Self>) {
+        match message {
+            BrowseConferencesInput::Directory(directory) => {
+                self.list_box.remove_all();
+                let Some(conferences) = directory
+                else {
+                    self.status_text = NO_DIRECTORY_TEXT;
+                    return;
+                };
+                self.status_text = if conferences.is_empty() { NO_CONFERENCES_TEXT } else { "" };
+                for (conference_id, number_of_peers) in conferences {
+                    let row = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+                    let label = gtk::Label::new(Some(&format!("{} ({} peers)", conference_id, number_of_peers)));
+                    label.set_hexpand(true);
+                    label.set_halign(gtk::Align::Start);
+                    let join_button = gtk::Button::with_label(JOIN_BUTTON_TEXT);
+                    let sender = sender.clone();
+                    join_button.connect_clicked(move |_| {
+                        sender.output(GUIAction::JoinFromDirectory(conference_id)).unwrap();
+                    });
+                    row.append(&label);
+                    row.append(&join_button);
+                    self.list_box.append(&row);
+                }
+            },
+        }
+    }
+}
@@ -1,6 +1,22 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    path::PathBuf,
+    time::Duration,
+};
+use bytes::Bytes;
 use crate::constants::{
-    ConferenceId, NumberOfPeers, MessageID,
+    ConferenceId, NumberOfPeers, MessageID, MessageLatency, KeyImage, Peer, Breakout, Migration, SetupStage,
+};
+use crate::{
+    anonymity::{Anonymity, AnonymityLevel, Linkability},
+    config::Density,
+    connection_manager::format_message_size,
+    file_transfer::{self, Direction, FileId, FileTransfer, ReceivedFile},
+    history,
+    history_store::{HistoryEntry, RetentionPolicy},
+    runtime,
+    thumbnail::{self, Thumbnail},
 };
 use log::debug;
 use relm4::{prelude::*, typed_view::list::TypedListView};
@@ -10,39 +26,187 @@ use super::message_list_item::{MessageListItem, MessageStatus};
 
 const MESSAGE_INPUT_PLACEHOLDER: &str = "Type your message here...";
 const MESSAGE_SEND_BUTTON_TEXT: &str = "Send Message";
+const SEND_FILE_BUTTON_TEXT: &str = "Send File";
+const SEND_FILE_BUTTON_TOOLTIP: &str = "Send a file to the peers, it is saved in their downloads folder";
+const SEND_FILE_DIALOG_TITLE: &str = "Send File";
+const FILE_NOT_SENT_TEXT: &str = "The file was not sent:";
 const CONFERENCE_LEAVE_BUTTON_TEXT: &str = "Leave Conference";
+const READ_RECEIPTS_CHECK_TEXT: &str = "Send Read Receipts";
+const READ_RECEIPTS_CHECK_TOOLTIP: &str = "Let the other peers see which messages you have read";
+const OWN_FINGERPRINT_TOOLTIP: &str = "Your public key in this conference, peers can compare it with you out of band";
+const ANONYMITY_TOOLTIP: &str = "Your messages are signed with the keys of all members, so they can only be traced to the whole ring.\nSmall rings and linkable messages make it easier to guess who wrote what.";
+const UNLINKABLE_CHECK_TEXT: &str = "Unlinkable Messages";
+const UNLINKABLE_CHECK_TOOLTIP: &str = "Sign with a new key after every key exchange, so your messages cannot be linked across key exchanges.\nPeers can no longer tell that they come from the same sender, names they gave you and read receipts are lost.";
+const LISTEN_ONLY_SWITCH_TEXT: &str = "Listen Only";
+const LISTEN_ONLY_SWITCH_TOOLTIP: &str = "Take part in the key exchange but never send messages, read receipts or announcements";
+const AUTO_LEAVE_CHECK_TEXT: &str = "Leave When Idle";
+const AUTO_LEAVE_CHECK_TOOLTIP: &str = "Leave the conference when no messages were sent or received for a while";
+const PEER_LABELS_BUTTON_TEXT: &str = "Name Peers";
+const PEER_LABELS_DIALOG_TITLE: &str = "Name Peers";
+const PEER_LABELS_DIALOG_TEXT: &str = "Names are only stored on this device, peers stay anonymous to everyone else.\nPeers are identified by their fingerprint until they leave the conference.";
+const PEER_LABELS_DIALOG_EMPTY_TEXT: &str = "No peer has sent a message yet.";
+const PEER_LABEL_PLACEHOLDER: &str = "Name";
+/// Incoming messages are appended to the list in chunks of this size, one chunk per main loop iteration
+const MESSAGES_APPENDED_PER_FRAME: usize = 50;
+const NOTICE_AUTHOR_NAME: &str = "SYSTEM";
+const SENDER_MUTED_DIALOG_TITLE: &str = "Sender Muted";
+const SENDER_MUTED_DIALOG_TEXT: &str = "is sending too many messages, their messages are hidden for a while.";
+const SENDER_MUTED_KEEP_BUTTON_TEXT: &str = "Keep Muted";
+const SENDER_MUTED_ALLOW_BUTTON_TEXT: &str = "Show Their Messages";
+const BREAKOUT_BUTTON_TEXT: &str = "Breakout";
+const BREAKOUT_BUTTON_TOOLTIP: &str = "Start a sub-conference and invite the members of this conference";
+const BREAKOUT_DIALOG_TITLE: &str = "Start Breakout";
+const BREAKOUT_DIALOG_TEXT: &str = "The members of this conference will be invited to join the breakout.";
+const BREAKOUT_TOPIC_PLACEHOLDER: &str = "Topic";
+const BREAKOUT_CREATE_BUTTON_TEXT: &str = "Start";
+const BREAKOUT_ANNOUNCED_DIALOG_TITLE: &str = "Breakout Started";
+const BREAKOUT_IGNORE_BUTTON_TEXT: &str = "Ignore";
+const BREAKOUT_JOIN_BUTTON_TEXT: &str = "Join";
+const MIGRATE_BUTTON_TEXT: &str = "Move";
+const MIGRATE_BUTTON_TOOLTIP: &str = "Move this conference to another server, its members are invited to follow";
+const MIGRATE_DIALOG_TITLE: &str = "Move Conference";
+const MIGRATE_DIALOG_TEXT: &str = "A new conference is created on the server and announced to the members of this conference, then this one is left.";
+const MIGRATE_SERVER_PLACEHOLDER: &str = "Server address";
+const MIGRATE_CONFIRM_BUTTON_TEXT: &str = "Move";
+const MIGRATION_ANNOUNCED_DIALOG_TITLE: &str = "Conference Moved";
+const MIGRATION_IGNORE_BUTTON_TEXT: &str = "Stay";
+const MIGRATION_FOLLOW_BUTTON_TEXT: &str = "Follow";
+const SETUP_WAITING_TEXT: &str = "Waiting for the peers";
+const SETUP_PROGRESS_TEXT: &str = "Setting up the conference:";
+const MESSAGE_TOO_LARGE_TEXT: &str = "The message was not sent, the server accepts messages up to";
+const HISTORY_END_TEXT: &str = "End of the messages from earlier sessions";
+const MESSAGE_TIMED_OUT_TEXT: &str = "The server did not answer, the message may not have been delivered";
+const RETENTION_BUTTON_TEXT: &str = "History";
+const RETENTION_BUTTON_TOOLTIP: &str = "Choose how long the messages of this conference are kept on this device";
+const RETENTION_FOREVER_BUTTON_TEXT: &str = "Keep Messages";
+const RETENTION_NEVER_BUTTON_TEXT: &str = "Do Not Store Messages";
+const RETENTION_UNTIL_LEAVE_BUTTON_TEXT: &str = "Keep Until Leaving";
+const RETENTION_DAYS_BUTTON_TEXT: &str = "Keep for Days";
+/// offered in the spin button until the conference keeps its messages for a number of days
+const RETENTION_DEFAULT_DAYS: f64 = 30.0;
 
 pub struct Conference {
     conference_id: ConferenceId,
     number_of_peers: NumberOfPeers,
     conference_id_string: String,
+    /// page title, breakouts are shown under their parent conference
+    title: String,
+    /// the server hosting the conference when connected to several
+    server: Option<String>,
+    /// word fingerprint of the own public key, for peers to confirm out of band
+    own_fingerprint: Option<String>,
+    /// known once the first key exchange finished
+    anonymity: Option<Anonymity>,
     can_send_messages: bool,
+    /// step of the key exchange shown while the conference is set up, `None` before the first one
+    setup_stage: Option<SetupStage>,
+    /// the composer stays disabled and nothing is sent to the conference
+    listen_only: bool,
+    /// a new key pair for every key exchange, follows the mode reported with the anonymity
+    unlinkable: bool,
+    /// idle conferences are left automatically, see [`crate::idle`]
+    auto_leave_available: bool,
+    /// known once the message history was opened, `None` while history is not persisted
+    retention: Option<RetentionPolicy>,
     last_sent_message_id: MessageID,
-    sent_messages: HashMap<MessageID, String>,
+    /// position in the message list and text of own messages shown as pending until the server answers
+    sent_messages: HashMap<MessageID, (u32, String)>,
+    /// peers that sent a validly signed message
+    known_peers: HashMap<KeyImage, Peer>,
+    /// position in the message list and text of the notices of files peers are sending
+    incoming_files: HashMap<FileId, (u32, String)>,
+    /// position in the message list and text of delivered own messages, to show read receipts
+    delivered_messages: HashMap<MessageID, (u32, String)>,
+    /// peers that confirmed receiving own messages and the number of other peers
+    message_recipients: HashMap<MessageID, (usize, usize)>,
+    /// latency of own messages, shown on the status icon
+    message_latencies: HashMap<MessageID, Vec<MessageLatency>>,
     messages: TypedListView<MessageListItem, gtk::NoSelection>,
+    /// incoming messages that are not in the list yet
+    pending_messages: VecDeque<MessageListItem>,
+    /// the oldest messages are removed from the list beyond this
+    max_messages: usize,
+    density: Density,
 }
 
 #[derive(Debug)]
 pub enum ConferenceInput {
     SendMessage(String),
-    IncomingMessage((Vec<u8>, bool)),
+    PickFile,
+    SendFile(PathBuf),
+    IncomingMessages(Vec<(Bytes, bool, Peer)>),
+    AppendPendingMessages,
     MessageAccepted(MessageID),
     MessageRejected(MessageID),
     MessageError(MessageID),
+    MessageTimedOut(MessageID),
+    MessageTooLarge((MessageID, u32)),
+    /// chunks of a large message accepted by the server and the number of chunks
+    UploadProgress((MessageID, usize, usize)),
+    /// a file a peer is sending
+    FileTransferProgress(FileTransfer),
+    FileReceived(ReceivedFile),
+    /// a received file with the preview of an image, decoded off the UI thread
+    ShowReceivedFile((ReceivedFile, Option<Thumbnail>)),
+    FileNotSent((MessageID, String)),
     ConferenceRestructuring(NumberOfPeers),
+    PeersChanged((NumberOfPeers, NumberOfPeers)),
     ConferenceRestructuringFinished,
+    SetupProgress(SetupStage),
+    ConferenceRenegotiating,
     LeaveConference,
+    ShowPeerLabels,
+    SetPeerLabel((KeyImage, Option<String>)),
+    SetReadReceipts(bool),
+    SetUnlinkable(bool),
+    SetListenOnly(bool),
+    MessageSeen((MessageID, usize)),
+    MessageReceivedBy((MessageID, usize, usize)),
+    MessageLatency((MessageID, MessageLatency)),
+    SenderMuted(Peer),
+    SenderUnmuted((Peer, usize)),
+    AllowFlooding(KeyImage),
+    ShowCreateBreakout,
+    CreateBreakout(String),
+    BreakoutAnnounced((Breakout, Peer)),
+    JoinBreakout(Breakout),
+    ShowMigrate,
+    Migrate(String),
+    MigrationAnnounced((Migration, Peer)),
+    FollowMigration(Migration),
+    OwnFingerprint(String),
+    Anonymity(Anonymity),
+    SetAutoLeave(bool),
+    /// The conference is left after this long without messages
+    ConferenceIdle(Duration),
+    /// Messages of earlier sessions, shown before the new ones
+    HistoryLoaded(Vec<HistoryEntry>),
+    RetentionPolicy(RetentionPolicy),
+    SetRetentionPolicy(RetentionPolicy),
 }
 
 #[derive(Debug)]
 pub enum ConferenceOutput {
     SendMessage((ConferenceId, MessageID, String)),
+    SendFile((ConferenceId, MessageID, PathBuf)),
     LeaveConference(ConferenceId),
+    SetPeerLabel((KeyImage, Option<String>)),
+    SetReadReceipts((ConferenceId, bool)),
+    SetUnlinkable((ConferenceId, bool)),
+    SetListenOnly((ConferenceId, bool)),
+    SetAutoLeave((ConferenceId, bool)),
+    SetRetentionPolicy((ConferenceId, RetentionPolicy)),
+    AllowFlooding((ConferenceId, KeyImage)),
+    CreateBreakout((ConferenceId, String)),
+    JoinBreakout((ConferenceId, Breakout)),
+    Migrate((ConferenceId, String)),
+    FollowMigration((ConferenceId, Migration)),
 }
 
 #[relm4::factory(pub)]
 impl FactoryComponent for Conference {
-    type Init = (ConferenceId, NumberOfPeers);
+    /// with the parent conference and topic for breakouts, the number of messages kept and the density of the list
+    type Init = (ConferenceId, NumberOfPeers, Option<(ConferenceId, String)>, Option<String>, usize, Density, bool);
     type Input = ConferenceInput;
     type Output = ConferenceOutput;
     type CommandOutput = ();
@@ -68,7 +232,145 @@ impl FactoryComponent for Conference {
                 gtk::Label {
                     set_use_markup: true,
                     #[watch]
-                    set_label: &format!("Conference ID: <b>{}</b>, number of peers: <b>{}</b>", self.conference_id, self.number_of_peers),
+                    set_label: &match &self.server {
+                        Some(server) => format!("Conference ID: <b>{}</b> on <b>{}</b>, number of peers: <b>{}</b>", self.conference_id, server, self.number_of_peers),
+                        None => format!("Conference ID: <b>{}</b>, number of peers: <b>{}</b>", self.conference_id, self.number_of_peers),
+                    },
+                },
+                gtk::Label {
+                    set_use_markup: true,
+                    set_tooltip_text: Some(OWN_FINGERPRINT_TOOLTIP),
+                    set_selectable: true,
+                    #[watch]
+                    set_visible: self.own_fingerprint.is_some(),
+                    #[watch]
+                    set_label: &format!("Your fingerprint: <b>{}</b>", self.own_fingerprint.as_deref().unwrap_or_default()),
+                },
+                gtk::Label {
+                    set_tooltip_text: Some(ANONYMITY_TOOLTIP),
+                    #[watch]
+                    set_visible: self.anonymity.is_some(),
+                    #[watch]
+                    set_label: &self.anonymity.map(|anonymity| anonymity.summary()).unwrap_or_default(),
+                },
+                gtk::CheckButton {
+                    set_label: Some(READ_RECEIPTS_CHECK_TEXT),
+                    set_tooltip_text: Some(READ_RECEIPTS_CHECK_TOOLTIP),
+                    set_active: false,
+                    connect_toggled[sender] => move |check| {
+                        sender.input(ConferenceInput::SetReadReceipts(check.is_active()));
+                    },
+                },
+                gtk::CheckButton {
+                    set_label: Some(UNLINKABLE_CHECK_TEXT),
+                    set_tooltip_text: Some(UNLINKABLE_CHECK_TOOLTIP),
+                    #[watch]
+                    set_active: self.unlinkable,
+                    connect_toggled[sender] => move |check| {
+                        sender.input(ConferenceInput::SetUnlinkable(check.is_active()));
+                    },
+                },
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Horizontal,
+                    set_spacing: 5,
+                    set_tooltip_text: Some(LISTEN_ONLY_SWITCH_TOOLTIP),
+
+                    gtk::Label {
+                        set_label: LISTEN_ONLY_SWITCH_TEXT,
+                    },
+                    gtk::Switch {
+                        set_valign: gtk::Align::Center,
+                        set_active: false,
+                        connect_active_notify[sender] => move |switch| {
+                            sender.input(ConferenceInput::SetListenOnly(switch.is_active()));
+                        },
+                    },
+                },
+                gtk::CheckButton {
+                    set_label: Some(AUTO_LEAVE_CHECK_TEXT),
+                    set_tooltip_text: Some(AUTO_LEAVE_CHECK_TOOLTIP),
+                    set_visible: self.auto_leave_available,
+                    set_active: true,
+                    connect_toggled[sender] => move |check| {
+                        sender.input(ConferenceInput::SetAutoLeave(check.is_active()));
+                    },
+                },
+                gtk::MenuButton {
+                    set_label: RETENTION_BUTTON_TEXT,
+                    set_tooltip_text: Some(RETENTION_BUTTON_TOOLTIP),
+                    #[watch]
+                    set_visible: self.retention.is_some(),
+                    #[wrap(Some)]
+                    #[name(retention_popover)]
+                    set_popover = &gtk::Popover {
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            set_spacing: 5,
+
+                            gtk::Label {
+                                #[watch]
+                                set_label: &self.retention.map(|policy| policy.description()).unwrap_or_default(),
+                            },
+                            gtk::Button {
+                                set_label: RETENTION_FOREVER_BUTTON_TEXT,
+                                connect_clicked[sender, retention_popover] => move |_| {
+                                    retention_popover.popdown();
+                                    sender.input(ConferenceInput::SetRetentionPolicy(RetentionPolicy::Forever));
+                                },
+                            },
+                            gtk::Button {
+                                set_label: RETENTION_NEVER_BUTTON_TEXT,
+                                connect_clicked[sender, retention_popover] => move |_| {
+                                    retention_popover.popdown();
+                                    sender.input(ConferenceInput::SetRetentionPolicy(RetentionPolicy::Never));
+                                },
+                            },
+                            gtk::Button {
+                                set_label: RETENTION_UNTIL_LEAVE_BUTTON_TEXT,
+                                connect_clicked[sender, retention_popover] => move |_| {
+                                    retention_popover.popdown();
+                                    sender.input(ConferenceInput::SetRetentionPolicy(RetentionPolicy::UntilLeave));
+                                },
+                            },
+                            gtk::Box {
+                                set_orientation: gtk::Orientation::Horizontal,
+                                set_spacing: 5,
+
+                                #[name(retention_days)]
+                                gtk::SpinButton::with_range(1.0, 3650.0, 1.0) {
+                                    set_value: RETENTION_DEFAULT_DAYS,
+                                },
+                                gtk::Button {
+                                    set_hexpand: true,
+                                    set_label: RETENTION_DAYS_BUTTON_TEXT,
+                                    connect_clicked[sender, retention_popover, retention_days] => move |_| {
+                                        retention_popover.popdown();
+                                        sender.input(ConferenceInput::SetRetentionPolicy(RetentionPolicy::Days(retention_days.value_as_int() as u32)));
+                                    },
+                                },
+                            },
+                        },
+                    },
+                },
+                gtk::Button {
+                    set_label: PEER_LABELS_BUTTON_TEXT,
+                    connect_clicked[sender] => move |_| {
+                        sender.input(ConferenceInput::ShowPeerLabels);
+                    },
+                },
+                gtk::Button {
+                    set_label: BREAKOUT_BUTTON_TEXT,
+                    set_tooltip_text: Some(BREAKOUT_BUTTON_TOOLTIP),
+                    connect_clicked[sender] => move |_| {
+                        sender.input(ConferenceInput::ShowCreateBreakout);
+                    },
+                },
+                gtk::Button {
+                    set_label: MIGRATE_BUTTON_TEXT,
+                    set_tooltip_text: Some(MIGRATE_BUTTON_TOOLTIP),
+                    connect_clicked[sender] => move |_| {
+                        sender.input(ConferenceInput::ShowMigrate);
+                    },
                 },
                 gtk::Button {
                     set_label: CONFERENCE_LEAVE_BUTTON_TEXT,
@@ -78,6 +380,20 @@ impl FactoryComponent for Conference {
                 },
             },
 
+            // SETUP PROGRESS
+            gtk::ProgressBar {
+                set_show_text: true,
+                #[watch]
+                set_visible: !self.can_send_messages,
+                #[watch]
+                set_fraction: self.setup_stage.map_or(0.0, |stage| stage.fraction()),
+                #[watch]
+                set_text: Some(&match self.setup_stage {
+                    Some(stage) => format!("{} {}", SETUP_PROGRESS_TEXT, stage),
+                    None => SETUP_WAITING_TEXT.to_string(),
+                }),
+            },
+
             // MESSAGES
             gtk::ScrolledWindow {
                 set_vexpand: true,
@@ -98,17 +414,27 @@ impl FactoryComponent for Conference {
                     set_margin_all: 10,
                     set_hexpand: true,
                     #[watch]
-                    set_sensitive: self.can_send_messages,
+                    set_sensitive: self.can_send_messages && !self.listen_only,
                     connect_activate[send_message_button] => move |_entry| {
                         send_message_button.emit_clicked()
                     }
                 },
+                gtk::Button {
+                    set_label: SEND_FILE_BUTTON_TEXT,
+                    set_tooltip_text: Some(SEND_FILE_BUTTON_TOOLTIP),
+                    set_margin_all: 10,
+                    #[watch]
+                    set_sensitive: self.can_send_messages && !self.listen_only,
+                    connect_clicked[sender] => move |_| {
+                        sender.input(ConferenceInput::PickFile);
+                    },
+                },
                 #[name(send_message_button)]
                 gtk::Button {
                     set_label: MESSAGE_SEND_BUTTON_TEXT,
                     set_margin_all: 10,
                     #[watch]
-                    set_sensitive: self.can_send_messages,
+                    set_sensitive: self.can_send_messages && !self.listen_only,
                     connect_clicked[message_input] => move |_button| {
                         let message = message_input.text().to_string();
                         if message.is_empty() {
@@ -124,7 +450,8 @@ impl FactoryComponent for Conference {
         #[local_ref]
         returned_widget -> gtk::StackPage {
             set_name: &self.conference_id_string,
-            set_title: &self.conference_id_string,
+            #[watch]
+            set_title: &self.page_title(),
         }
     }
 
@@ -133,54 +460,354 @@ impl FactoryComponent for Conference {
         let list_view_wrapper: TypedListView<MessageListItem, gtk::NoSelection> =
             TypedListView::new();
 
+        let (conference_id, number_of_peers, parent, server, max_messages, density, auto_leave_available) = value;
+        let title = match parent {
+            Some((parent_conference_id, topic)) if topic.is_empty() => format!("{} › {}", parent_conference_id, conference_id),
+            Some((parent_conference_id, topic)) => format!("{} › {}", parent_conference_id, topic),
+            None => conference_id.to_string(),
+        };
+        let title = match &server {
+            Some(server) => format!("{} ({})", title, server),
+            None => title,
+        };
         Self {
-            conference_id: value.0,
-            number_of_peers: value.1,
-            conference_id_string: value.0.to_string(),
+            conference_id,
+            number_of_peers,
+            conference_id_string: conference_id.to_string(),
+            title,
+            server,
+            own_fingerprint: None,
+            anonymity: None,
             can_send_messages: false,
+            setup_stage: None,
+            listen_only: false,
+            unlinkable: false,
+            auto_leave_available,
+            retention: None,
             last_sent_message_id: 0,
             sent_messages: HashMap::new(),
-            messages: list_view_wrapper
+            incoming_files: HashMap::new(),
+            known_peers: HashMap::new(),
+            delivered_messages: HashMap::new(),
+            message_recipients: HashMap::new(),
+            message_latencies: HashMap::new(),
+            messages: list_view_wrapper,
+            pending_messages: VecDeque::new(),
+            max_messages,
+            density,
         }
     }
 
+    fn update_with_view(&mut self, widgets: &mut Self::Widgets, msg: Self::Input, sender: FactorySender<Self>) {
+        // only set when the policy changes, so the days being chosen are not reset by every update
+        if let ConferenceInput::RetentionPolicy(RetentionPolicy::Days(days)) = msg {
+            widgets.retention_days.set_value(days as f64);
+        }
+        self.update(msg, sender.clone());
+        self.update_view(widgets, sender);
+    }
+
     fn update( &mut self, msg: Self::Input, sender: FactorySender<Self>,) -> Self::CommandOutput {
         match msg {
             ConferenceInput::SendMessage(message) => {
                 self.last_sent_message_id += 1;
-                self.sent_messages.insert(self.last_sent_message_id, message.clone());
+                self.append_pending_messages(usize::MAX);
+                self.sent_messages.insert(self.last_sent_message_id, (self.messages.len(), message.clone()));
+                self.append_message(MessageListItem::new(true, message.clone(), MessageStatus::MessagePending));
                 sender.output(ConferenceOutput::SendMessage((self.conference_id, self.last_sent_message_id, message))).unwrap();
             }
-            ConferenceInput::IncomingMessage((message, is_signature_valid)) => {
-                let message = String::from_utf8_lossy(&message);
-                let message_status = if is_signature_valid {
-                    MessageStatus::SignatureValid
-                } else {
-                    MessageStatus::SignatureInvalid
-                };
-                self.messages.append(MessageListItem::new(false, message.to_string(), message_status));
+            ConferenceInput::PickFile => {
+                if let Some(window) = relm4::main_application().active_window() {
+                    relm4::spawn_local(async move {
+                        if let Some(path) = super::file_dialogs::open_file(&window, SEND_FILE_DIALOG_TITLE).await {
+                            sender.input(ConferenceInput::SendFile(path));
+                        }
+                    });
+                }
+            }
+            ConferenceInput::SendFile(path) => {
+                self.last_sent_message_id += 1;
+                self.append_pending_messages(usize::MAX);
+                let name = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().to_string());
+                let message = format!("File: {}", name);
+                self.sent_messages.insert(self.last_sent_message_id, (self.messages.len(), message.clone()));
+                self.append_message(MessageListItem::new(true, message, MessageStatus::MessagePending));
+                sender.output(ConferenceOutput::SendFile((self.conference_id, self.last_sent_message_id, path))).unwrap();
+            }
+            ConferenceInput::IncomingMessages(messages) => {
+                let schedule_append = self.pending_messages.is_empty();
+                for (message, is_signature_valid, peer) in messages {
+                    let message = String::from_utf8_lossy(&message);
+                    let message_status = if is_signature_valid {
+                        MessageStatus::SignatureValid
+                    } else {
+                        MessageStatus::SignatureInvalid
+                    };
+                    let author_name = peer.display_name();
+                    if is_signature_valid {
+                        self.known_peers.insert(peer.key_image, peer);
+                    }
+                    self.pending_messages.push_back(MessageListItem::new(false, message.to_string(), message_status).with_author_name(author_name));
+                }
+                // would be evicted right after being appended
+                let excess = self.pending_messages.len().saturating_sub(self.max_messages);
+                self.pending_messages.drain(..excess);
+                if schedule_append {
+                    schedule_append_pending_messages(sender);
+                }
+            }
+            ConferenceInput::AppendPendingMessages => {
+                self.append_pending_messages(MESSAGES_APPENDED_PER_FRAME);
+                if !self.pending_messages.is_empty() {
+                    schedule_append_pending_messages(sender);
+                }
+            }
+            ConferenceInput::ShowPeerLabels => {
+                show_peer_labels_dialog(&self.known_peers, sender);
+            }
+            ConferenceInput::SetPeerLabel((key_image, label)) => {
+                if let Some(peer) = self.known_peers.get_mut(&key_image) {
+                    peer.label = label.clone();
+                }
+                sender.output(ConferenceOutput::SetPeerLabel((key_image, label))).unwrap();
             }
             ConferenceInput::MessageAccepted(message_id) => {
-                if let Some(message) = self.sent_messages.remove(&message_id) {
-                    self.messages.append(MessageListItem::new(true, message, MessageStatus::MessageDelivered));
+                if let Some((position, message)) = self.sent_messages.remove(&message_id) {
+                    let item = self.delivered_item(message_id, message.clone(), 0);
+                    self.replace_message(position, item);
+                    self.delivered_messages.insert(message_id, (position, message));
+                }
+            }
+            ConferenceInput::MessageSeen((message_id, seen_by)) => {
+                if let Some((position, message)) = self.delivered_messages.get(&message_id) {
+                    let item = self.delivered_item(message_id, message.clone(), seen_by);
+                    self.replace_message(*position, item);
                 }
             }
-            ConferenceInput::MessageRejected(message_id) => {
-                if let Some(message) = self.sent_messages.remove(&message_id) {
-                    self.messages.append(MessageListItem::new(true, message, MessageStatus::MessageError));
+            ConferenceInput::MessageReceivedBy((message_id, received_by, other_peers)) => {
+                self.message_recipients.insert(message_id, (received_by, other_peers));
+                history::evict_oldest(&mut self.message_recipients, self.max_messages);
+                if let Some((position, message)) = self.delivered_messages.get(&message_id) {
+                    let seen_by = self.messages.get(*position).map_or(0, |item| item.borrow().seen_by());
+                    let item = self.delivered_item(message_id, message.clone(), seen_by);
+                    self.replace_message(*position, item);
                 }
             }
-            ConferenceInput::MessageError(message_id) => {
-                if let Some(message) = self.sent_messages.remove(&message_id) {
-                    self.messages.append(MessageListItem::new(true, message, MessageStatus::MessageError));
+            ConferenceInput::MessageLatency((message_id, latency)) => {
+                self.message_latencies.entry(message_id).or_default().push(latency);
+                history::evict_oldest(&mut self.message_latencies, self.max_messages);
+                if let Some((position, message)) = self.delivered_messages.get(&message_id) {
+                    let seen_by = self.messages.get(*position).map_or(0, |item| item.borrow().seen_by());
+                    let item = self.delivered_item(message_id, message.clone(), seen_by);
+                    self.replace_message(*position, item);
                 }
             }
+            ConferenceInput::SetReadReceipts(enabled) => {
+                sender.output(ConferenceOutput::SetReadReceipts((self.conference_id, enabled))).unwrap();
+            }
+            ConferenceInput::SetUnlinkable(enabled) => {
+                // also toggled when the reported mode is shown
+                if enabled != self.unlinkable {
+                    self.unlinkable = enabled;
+                    sender.output(ConferenceOutput::SetUnlinkable((self.conference_id, enabled))).unwrap();
+                }
+            }
+            ConferenceInput::SetListenOnly(enabled) => {
+                self.listen_only = enabled;
+                sender.output(ConferenceOutput::SetListenOnly((self.conference_id, enabled))).unwrap();
+            }
+            ConferenceInput::SetAutoLeave(enabled) => {
+                sender.output(ConferenceOutput::SetAutoLeave((self.conference_id, enabled))).unwrap();
+            }
+            ConferenceInput::RetentionPolicy(policy) => {
+                self.retention = Some(policy);
+            }
+            ConferenceInput::SetRetentionPolicy(policy) => {
+                sender.output(ConferenceOutput::SetRetentionPolicy((self.conference_id, policy))).unwrap();
+            }
+            ConferenceInput::ConferenceIdle(remaining) => {
+                self.append_pending_messages(usize::MAX);
+                let notice = format!("No messages for a while, the conference is left in {} minutes unless a message is sent or received", remaining.as_secs().div_ceil(60));
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+            }
+            ConferenceInput::HistoryLoaded(entries) => {
+                self.append_pending_messages(usize::MAX);
+                let notice = format!("{} messages from earlier sessions", entries.len());
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+                for entry in entries {
+                    let text = format!("({}) {}", humantime::format_rfc3339_seconds(entry.time), entry.text);
+                    let item = match entry.sender {
+                        Some(peer) => {
+                            let message_status = if entry.is_signature_valid { MessageStatus::SignatureValid } else { MessageStatus::SignatureInvalid };
+                            MessageListItem::new(false, text, message_status).with_author_name(peer.display_name())
+                        },
+                        None => MessageListItem::new(true, text, MessageStatus::MessageDelivered),
+                    };
+                    self.append_message(item);
+                }
+                self.append_message(MessageListItem::new(false, HISTORY_END_TEXT.to_string(), MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+            }
+            ConferenceInput::SenderMuted(peer) => {
+                self.append_pending_messages(usize::MAX);
+                let notice = format!("{} was muted for sending too many messages", peer.display_name());
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+                show_sender_muted_dialog(&peer, sender);
+            }
+            ConferenceInput::SenderUnmuted((peer, suppressed)) => {
+                self.append_pending_messages(usize::MAX);
+                let notice = format!("{} is no longer muted, {} of their messages were hidden", peer.display_name(), suppressed);
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+            }
+            ConferenceInput::AllowFlooding(key_image) => {
+                sender.output(ConferenceOutput::AllowFlooding((self.conference_id, key_image))).unwrap();
+            }
+            ConferenceInput::ShowCreateBreakout => {
+                show_create_breakout_dialog(sender);
+            }
+            ConferenceInput::CreateBreakout(topic) => {
+                sender.output(ConferenceOutput::CreateBreakout((self.conference_id, topic))).unwrap();
+            }
+            ConferenceInput::BreakoutAnnounced((breakout, peer)) => {
+                self.append_pending_messages(usize::MAX);
+                let notice = format!("{} started the breakout \"{}\" (conference {})", peer.display_name(), breakout.topic, breakout.conference_id);
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+                show_breakout_announced_dialog(&peer, breakout, sender);
+            }
+            ConferenceInput::JoinBreakout(breakout) => {
+                sender.output(ConferenceOutput::JoinBreakout((self.conference_id, breakout))).unwrap();
+            }
+            ConferenceInput::ShowMigrate => {
+                show_migrate_dialog(sender);
+            }
+            ConferenceInput::Migrate(server_address) => {
+                sender.output(ConferenceOutput::Migrate((self.conference_id, server_address))).unwrap();
+            }
+            ConferenceInput::OwnFingerprint(fingerprint) => {
+                self.own_fingerprint = Some(fingerprint);
+            }
+            ConferenceInput::Anonymity(anonymity) => {
+                // e.g. a new conference manager after rejoining starts linkable again
+                self.unlinkable = anonymity.linkability == Linkability::KeyExchange;
+                self.anonymity = Some(anonymity);
+            }
+            ConferenceInput::MigrationAnnounced((migration, peer)) => {
+                self.append_pending_messages(usize::MAX);
+                let notice = format!("{} moved the conference to {} (conference {})", peer.display_name(), migration.server_address, migration.conference_id);
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+                show_migration_announced_dialog(&peer, migration, sender);
+            }
+            ConferenceInput::FollowMigration(migration) => {
+                sender.output(ConferenceOutput::FollowMigration((self.conference_id, migration))).unwrap();
+            }
+            ConferenceInput::MessageRejected(message_id) | ConferenceInput::MessageError(message_id) => {
+                if let Some((position, message)) = self.sent_messages.remove(&message_id) {
+                    self.replace_message(position, MessageListItem::new(true, message, MessageStatus::MessageError));
+                }
+            }
+            ConferenceInput::MessageTimedOut(message_id) => {
+                if let Some((position, message)) = self.sent_messages.remove(&message_id) {
+                    self.replace_message(position, MessageListItem::new(true, message, MessageStatus::MessageError));
+                    self.append_pending_messages(usize::MAX);
+                    self.append_message(MessageListItem::new(false, MESSAGE_TIMED_OUT_TEXT.to_string(), MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+                }
+            }
+            ConferenceInput::MessageTooLarge((message_id, max_message_size)) => {
+                if let Some((position, message)) = self.sent_messages.remove(&message_id) {
+                    self.replace_message(position, MessageListItem::new(true, message, MessageStatus::MessageError));
+                    self.append_pending_messages(usize::MAX);
+                    let notice = format!("{} {}", MESSAGE_TOO_LARGE_TEXT, format_message_size(max_message_size));
+                    self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+                }
+            }
+            ConferenceInput::UploadProgress((message_id, accepted, total)) => {
+                if let Some((position, message)) = self.sent_messages.get(&message_id) {
+                    let item = MessageListItem::new(true, message.clone(), MessageStatus::MessagePending).with_upload_progress(accepted, total);
+                    self.replace_message(*position, item);
+                }
+            }
+            ConferenceInput::FileTransferProgress(FileTransfer { file_id, name, size, direction: Direction::Receiving(peer), chunks_done, chunks }) => {
+                let notice = format!("{} is sending the file {} ({}): {} of {} chunks", peer.display_name(), name, file_transfer::format_file_size(size), chunks_done, chunks);
+                let item = MessageListItem::new(false, notice.clone(), MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string());
+                match self.incoming_files.get_mut(&file_id) {
+                    Some((position, text)) => {
+                        *text = notice;
+                        let position = *position;
+                        self.replace_message(position, item);
+                    }
+                    None => {
+                        self.append_pending_messages(usize::MAX);
+                        self.incoming_files.insert(file_id, (self.messages.len(), notice));
+                        self.append_message(item);
+                    }
+                }
+            }
+            // own files are shown as uploads of their message
+            ConferenceInput::FileTransferProgress(_) => {}
+            ConferenceInput::FileReceived(file) if thumbnail::is_previewable(&file.path) => {
+                runtime::spawn(async move {
+                    let path = file.path.clone();
+                    let preview = match runtime::spawn_blocking(move || thumbnail::decode(&path)).await {
+                        Ok(preview) => Some(preview),
+                        Err(e) => {
+                            debug!("No preview of {}: {}", file.path.display(), e);
+                            None
+                        }
+                    };
+                    sender.input(ConferenceInput::ShowReceivedFile((file, preview)));
+                });
+            }
+            ConferenceInput::FileReceived(file) => {
+                sender.input(ConferenceInput::ShowReceivedFile((file, None)));
+            }
+            ConferenceInput::ShowReceivedFile((file, preview)) => {
+                let text = format!("File: {} ({}) saved to {}", file.name, file_transfer::format_file_size(file.size), file.path.display());
+                // only the parts of a file with valid signatures are kept
+                let mut item = MessageListItem::new(false, text, MessageStatus::SignatureValid).with_author_name(file.sender.display_name());
+                if let Some(preview) = preview {
+                    item = item.with_preview(preview, file.path);
+                }
+                match self.incoming_files.remove(&file.file_id) {
+                    Some((position, _)) => self.replace_message(position, item),
+                    None => {
+                        self.append_pending_messages(usize::MAX);
+                        self.append_message(item);
+                    }
+                }
+            }
+            ConferenceInput::FileNotSent((message_id, reason)) => {
+                if let Some((position, message)) = self.sent_messages.remove(&message_id) {
+                    self.replace_message(position, MessageListItem::new(true, message, MessageStatus::MessageError));
+                }
+                self.append_pending_messages(usize::MAX);
+                let notice = format!("{} {}", FILE_NOT_SENT_TEXT, reason);
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+            }
             ConferenceInput::ConferenceRestructuring(new_number_of_peers) => {
                 self.number_of_peers = new_number_of_peers;
                 self.can_send_messages = false;
             }
+            ConferenceInput::PeersChanged((joined, left)) => {
+                self.append_pending_messages(usize::MAX);
+                let notice = match (joined, left) {
+                    (1, _) => "A participant joined".to_string(),
+                    (0, 1) => "A participant left".to_string(),
+                    (0, left) => format!("{} participants left", left),
+                    (joined, _) => format!("{} participants joined", joined),
+                };
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+            }
+            ConferenceInput::ConferenceRenegotiating => {
+                self.can_send_messages = false;
+                self.append_pending_messages(usize::MAX);
+                let notice = "A peer restarted the key exchange, the conference keys are renegotiated".to_string();
+                self.append_message(MessageListItem::new(false, notice, MessageStatus::Notice).with_author_name(NOTICE_AUTHOR_NAME.to_string()));
+            }
             ConferenceInput::ConferenceRestructuringFinished => {
                 self.can_send_messages = true;
+                self.setup_stage = None;
+            }
+            ConferenceInput::SetupProgress(stage) => {
+                self.setup_stage = Some(stage);
             }
             ConferenceInput::LeaveConference => {
                 sender.output(ConferenceOutput::LeaveConference(self.conference_id)).unwrap();
@@ -193,3 +820,251 @@ impl FactoryComponent for Conference {
     }
 }
 
+impl Conference {
+    /// The title in the conference list, marked with the anonymity level once it is known
+    fn page_title(&self) -> String {
+        let marker = match self.anonymity.map(|anonymity| anonymity.level()) {
+            None => return self.title.clone(),
+            Some(AnonymityLevel::Exposed) => "⚠",
+            Some(AnonymityLevel::Weak) => "◐",
+            Some(AnonymityLevel::Good) => "●",
+        };
+        format!("{} {}", marker, self.title)
+    }
+
+    /// Move up to `limit` pending incoming messages to the list
+    fn append_pending_messages(&mut self, limit: usize) {
+        let count = limit.min(self.pending_messages.len());
+        let last_item = self.messages.len().checked_sub(1).and_then(|position| self.messages.get(position));
+        let last = last_item.as_ref().map(|item| item.borrow());
+        let mut items: Vec<MessageListItem> = Vec::with_capacity(count);
+        for item in self.pending_messages.drain(..count) {
+            let previous = items.last().or(last.as_deref());
+            items.push(item.with_density(self.density, previous));
+        }
+        drop(last);
+        self.messages.extend_from_iter(items);
+        self.evict_oldest_messages();
+    }
+
+    fn append_message(&mut self, item: MessageListItem) {
+        let item = self.laid_out(self.messages.len(), item);
+        self.messages.append(item);
+        self.evict_oldest_messages();
+    }
+
+    /// Update the message at `position`, list items are only rebound when they are replaced
+    fn replace_message(&mut self, position: u32, item: MessageListItem) {
+        let item = self.laid_out(position, item);
+        self.messages.remove(position);
+        self.messages.insert(position, item);
+    }
+
+    /// An own message accepted by the server, with its delivery and read receipts and latency
+    fn delivered_item(&self, message_id: MessageID, message: String, seen_by: usize) -> MessageListItem {
+        let latencies = self.message_latencies.get(&message_id).cloned().unwrap_or_default();
+        let item = MessageListItem::new(true, message, MessageStatus::MessageDelivered).with_seen_by(seen_by).with_latencies(latencies);
+        match self.message_recipients.get(&message_id) {
+            Some(&(received_by, other_peers)) => item.with_received_by(received_by, other_peers),
+            None => item,
+        }
+    }
+
+    /// Lay out a message that is shown at `position` for the configured density
+    fn laid_out(&self, position: u32, item: MessageListItem) -> MessageListItem {
+        let previous_item = position.checked_sub(1).and_then(|position| self.messages.get(position));
+        let previous = previous_item.as_ref().map(|item| item.borrow());
+        item.with_density(self.density, previous.as_deref())
+    }
+
+    /// Remove the oldest messages from the list beyond `max_messages`
+    fn evict_oldest_messages(&mut self) {
+        let max_messages = u32::try_from(self.max_messages).unwrap_or(u32::MAX);
+        let excess = self.messages.len().saturating_sub(max_messages);
+        if excess == 0 {
+            return;
+        }
+        for _ in 0..excess {
+            self.messages.remove(0);
+        }
+        // positions of the remaining messages moved up, evicted ones can not show their status anymore
+        shift_positions(&mut self.delivered_messages, excess);
+        shift_positions(&mut self.sent_messages, excess);
+        shift_positions(&mut self.incoming_files, excess);
+    }
+}
+
+/// Move the tracked positions up by `excess` evicted messages, forgetting the evicted ones
+fn shift_positions<K: Eq + Hash>(positions: &mut HashMap<K, (u32, String)>, excess: u32) {
+    positions.retain(|_, (position, _)| {
+        if *position < excess {
+            return false;
+        }
+        *position -= excess;
+        true
+    });
+}
+
+/// Append the next chunk of pending messages once the pending events are handled and the list was drawn
+fn schedule_append_pending_messages(sender: FactorySender<Conference>) {
+    gtk::glib::idle_add_local_once(move || sender.input(ConferenceInput::AppendPendingMessages));
+}
+
+#[allow(deprecated)]
+fn show_peer_labels_dialog(known_peers: &HashMap<KeyImage, Peer>, sender: FactorySender<Conference>) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(true)
+        .title(PEER_LABELS_DIALOG_TITLE)
+        .text(PEER_LABELS_DIALOG_TEXT)
+        .build();
+    if let Some(window) = relm4::main_application().active_window() {
+        dialog.set_transient_for(Some(&window));
+    }
+    let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+    if known_peers.is_empty() {
+        message_area.append(&gtk::Label::new(Some(PEER_LABELS_DIALOG_EMPTY_TEXT)));
+    }
+    let mut entries = Vec::new();
+    for peer in known_peers.values() {
+        let row = gtk::Box::new(gtk::Orientation::Horizontal, 10);
+        let fingerprint = gtk::Label::new(Some(&peer.fingerprint()));
+        fingerprint.add_css_class("monospace");
+        let entry = gtk::Entry::new();
+        entry.set_hexpand(true);
+        entry.set_placeholder_text(Some(PEER_LABEL_PLACEHOLDER));
+        entry.set_text(peer.label.as_deref().unwrap_or_default());
+        row.append(&fingerprint);
+        row.append(&entry);
+        message_area.append(&row);
+        entries.push((peer.key_image, peer.label.clone(), entry));
+    }
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    dialog.add_button("Save", gtk::ResponseType::Apply);
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            for (key_image, old_label, entry) in entries.iter() {
+                let label = Some(entry.text().trim().to_string()).filter(|label| !label.is_empty());
+                if &label != old_label {
+                    sender.input(ConferenceInput::SetPeerLabel((*key_image, label)));
+                }
+            }
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_sender_muted_dialog(peer: &Peer, sender: FactorySender<Conference>) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(false)
+        .title(SENDER_MUTED_DIALOG_TITLE)
+        .text(format!("{} {}", peer.display_name(), SENDER_MUTED_DIALOG_TEXT))
+        .build();
+    if let Some(window) = relm4::main_application().active_window() {
+        dialog.set_transient_for(Some(&window));
+    }
+    dialog.add_button(SENDER_MUTED_KEEP_BUTTON_TEXT, gtk::ResponseType::Close);
+    dialog.add_button(SENDER_MUTED_ALLOW_BUTTON_TEXT, gtk::ResponseType::Apply);
+    let key_image = peer.key_image;
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            sender.input(ConferenceInput::AllowFlooding(key_image));
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_create_breakout_dialog(sender: FactorySender<Conference>) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(true)
+        .title(BREAKOUT_DIALOG_TITLE)
+        .text(BREAKOUT_DIALOG_TEXT)
+        .build();
+    if let Some(window) = relm4::main_application().active_window() {
+        dialog.set_transient_for(Some(&window));
+    }
+    let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+    let topic_entry = gtk::Entry::new();
+    topic_entry.set_placeholder_text(Some(BREAKOUT_TOPIC_PLACEHOLDER));
+    message_area.append(&topic_entry);
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    dialog.add_button(BREAKOUT_CREATE_BUTTON_TEXT, gtk::ResponseType::Apply);
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            sender.input(ConferenceInput::CreateBreakout(topic_entry.text().trim().to_string()));
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_breakout_announced_dialog(peer: &Peer, breakout: Breakout, sender: FactorySender<Conference>) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(false)
+        .title(BREAKOUT_ANNOUNCED_DIALOG_TITLE)
+        .text(format!("{} started the breakout \"{}\"", peer.display_name(), breakout.topic))
+        .build();
+    if let Some(window) = relm4::main_application().active_window() {
+        dialog.set_transient_for(Some(&window));
+    }
+    dialog.add_button(BREAKOUT_IGNORE_BUTTON_TEXT, gtk::ResponseType::Close);
+    dialog.add_button(BREAKOUT_JOIN_BUTTON_TEXT, gtk::ResponseType::Apply);
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            sender.input(ConferenceInput::JoinBreakout(breakout.clone()));
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_migrate_dialog(sender: FactorySender<Conference>) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(true)
+        .title(MIGRATE_DIALOG_TITLE)
+        .text(MIGRATE_DIALOG_TEXT)
+        .build();
+    if let Some(window) = relm4::main_application().active_window() {
+        dialog.set_transient_for(Some(&window));
+    }
+    let message_area = dialog.message_area().downcast::<gtk::Box>().unwrap();
+    let server_entry = gtk::Entry::new();
+    server_entry.set_placeholder_text(Some(MIGRATE_SERVER_PLACEHOLDER));
+    message_area.append(&server_entry);
+    dialog.add_button("Cancel", gtk::ResponseType::Cancel);
+    dialog.add_button(MIGRATE_CONFIRM_BUTTON_TEXT, gtk::ResponseType::Apply);
+    dialog.connect_response(move |dialog, response_id| {
+        let server_address = server_entry.text().trim().to_string();
+        if gtk::ResponseType::Apply == response_id && !server_address.is_empty() {
+            sender.input(ConferenceInput::Migrate(server_address));
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_migration_announced_dialog(peer: &Peer, migration: Migration, sender: FactorySender<Conference>) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(false)
+        .title(MIGRATION_ANNOUNCED_DIALOG_TITLE)
+        .text(format!("{} moved the conference to {}", peer.display_name(), migration.server_address))
+        .build();
+    if let Some(window) = relm4::main_application().active_window() {
+        dialog.set_transient_for(Some(&window));
+    }
+    dialog.add_button(MIGRATION_IGNORE_BUTTON_TEXT, gtk::ResponseType::Close);
+    dialog.add_button(MIGRATION_FOLLOW_BUTTON_TEXT, gtk::ResponseType::Apply);
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            sender.input(ConferenceInput::FollowMigration(migration.clone()));
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
@@ -1,5 +1,16 @@
-use crate::constants::{
-    ConferenceId, NumberOfPeers, MessageID,
+use std::{path::PathBuf, time::Duration};
+
+use bytes::Bytes;
+use crate::{
+    anonymity::Anonymity,
+    device_link::LinkBundle,
+    connection_manager::ServerCapabilities,
+    file_transfer::{FileTransfer, ReceivedFile},
+    history_store::{HistoryEntry, RetentionPolicy},
+    constants::{
+        ConferenceId, NumberOfPeers, MessageID, MessageLatency, KeyImage, Peer, Breakout, DisconnectReason, HandshakeDiagnosis, Migration, MigrationStage,
+        SetupStage, PacketNonce,
+    },
 };
 
 #[derive(Debug)]
@@ -8,9 +19,63 @@ pub enum GUIAction {
     Join((ConferenceId, String)),
     Leave(ConferenceId),
     SendMessage((ConferenceId, MessageID, String)),
-    Disconnected,
+    SendFile((ConferenceId, MessageID, PathBuf)),
+    /// `None` when the state manager stopped without reporting a reason
+    Disconnected(Option<DisconnectReason>),
+    /// A password is being hashed for creating or joining a conference
+    DerivingKey(Option<ConferenceId>),
+    /// A join, or a create for `None`, that can still be cancelled
+    RequestPending((PacketNonce, Option<ConferenceId>)),
+    RequestCancelled(PacketNonce),
+    /// Cancel all pending joins and creates
+    CancelRequests,
     Reconnect,
+    /// Create and join conferences on this server, connecting to it in place of the current one if it is not connected
+    SwitchServer(String),
     NotConnectedToServerError,
+    OpenLogFile,
+    ExportSettings,
+    ImportSettings,
+    CrashReportFound(std::path::PathBuf),
+    UpdateAvailable((String, String)),
+    DismissUpdate,
+    ImportInvite(String),
+    OpenInviteImage,
+    /// Show a link code for the conferences on the current server, see [`crate::device_link`]
+    LinkDevice,
+    ImportDeviceLink,
+    DeviceLinkImported(LinkBundle),
+    SetPeerLabel((KeyImage, Option<String>)),
+    MessagesDisplayed(ConferenceId),
+    SetReadReceipts((ConferenceId, bool)),
+    SetUnlinkable((ConferenceId, bool)),
+    SetListenOnly((ConferenceId, bool)),
+    SetAutoLeave((ConferenceId, bool)),
+    SetRetentionPolicy((ConferenceId, RetentionPolicy)),
+    WindowActiveChanged,
+    ListConferences,
+    JoinFromDirectory(ConferenceId),
+    AllowFlooding((ConferenceId, KeyImage)),
+    CreateBreakout((ConferenceId, String)),
+    JoinBreakout((ConferenceId, Breakout)),
+    Rejoin(ConferenceId),
+    Migrate((ConferenceId, String)),
+    FollowMigration((ConferenceId, Migration)),
+    LeaveAll,
+    /// The window is being closed, see [`crate::config::CloseAction`]
+    CloseRequested,
+    /// Show the window again after it was hidden to stay connected in the background
+    ShowWindow,
+    /// Disconnect and quit, regardless of [`crate::config::CloseAction`]
+    Quit,
+    Minimize,
+    /// Hide the conferences until the master password is entered, the connections stay open
+    Lock,
+    Unlock(String),
+    /// Whether the entered master password was correct
+    UnlockChecked(bool),
+    /// Keyboard or pointer input in the window, sent at most once a minute
+    UserActive,
 
     ConferenceCreated(ConferenceId),
     ConferenceCreateFailed,
@@ -18,10 +83,43 @@ pub enum GUIAction {
     ConferenceJoinFailed(ConferenceId),
     ConferenceLeft(ConferenceId),
     ConferenceLeaveFailed(ConferenceId),
-    IncomingMessage((ConferenceId, Vec<u8>, bool)),
+    IncomingMessages(Vec<(ConferenceId, Bytes, bool, Peer)>),
     MessageAccepted((ConferenceId, MessageID)),
     MessageRejected((ConferenceId, MessageID)),
     MessageError((ConferenceId, MessageID)),
+    MessageTimedOut((ConferenceId, MessageID)),
+    MessageTooLarge((ConferenceId, MessageID, u32)),
+    UploadProgress((ConferenceId, MessageID, usize, usize)),
+    /// a file of a peer, own files are reported as [`GUIAction::UploadProgress`]
+    FileTransferProgress((ConferenceId, FileTransfer)),
+    FileReceived((ConferenceId, ReceivedFile)),
+    FileNotSent((ConferenceId, MessageID, String)),
     ConferenceRestructuring((ConferenceId, NumberOfPeers)),
+    PeersChanged((ConferenceId, NumberOfPeers, NumberOfPeers)),
     ConferenceRestructuringFinished(ConferenceId),
+    SetupProgress((ConferenceId, SetupStage)),
+    ConferenceRenegotiating(ConferenceId),
+    RingMismatch(ConferenceId),
+    InvalidMessages((ConferenceId, usize, String)),
+    MessageSeen((ConferenceId, MessageID, usize)),
+    MessageReceivedBy((ConferenceId, MessageID, usize, usize)),
+    MessageLatency((ConferenceId, MessageID, MessageLatency)),
+    ConferenceDirectory(Option<Vec<(ConferenceId, NumberOfPeers)>>),
+    SenderMuted((ConferenceId, Peer)),
+    SenderUnmuted((ConferenceId, Peer, usize)),
+    BreakoutAnnounced((ConferenceId, Breakout, Option<Peer>)),
+    ServerError(bool),
+    ServerCapabilities(ServerCapabilities),
+    ConferenceStale((ConferenceId, bool)),
+    ConferenceServer((ConferenceId, String)),
+    ServerDisconnected((String, DisconnectReason)),
+    Reconnecting(DisconnectReason),
+    MigrationAnnounced((ConferenceId, Migration, Peer)),
+    MigrationProgress((ConferenceId, MigrationStage)),
+    HandshakeFailed(HandshakeDiagnosis),
+    OwnFingerprint((ConferenceId, String)),
+    Anonymity((ConferenceId, Anonymity)),
+    ConferenceIdle((ConferenceId, Duration)),
+    HistoryLoaded((ConferenceId, Vec<HistoryEntry>)),
+    RetentionPolicy((ConferenceId, RetentionPolicy)),
 }
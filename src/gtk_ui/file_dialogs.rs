@@ -0,0 +1,100 @@
+//! Choosing files and opening them in other apps.
+//!
+//! With the `portals` feature the file chooser and open URI portals of the XDG desktop are used, so the client
+//! works inside a Flatpak or Snap sandbox without access to the whole file system. Where no portal answers the
+//! GTK dialogs are shown instead.
+
+use std::path::{Path, PathBuf};
+
+use gtk::prelude::*;
+#[cfg(all(target_os = "linux", feature = "portals"))]
+use log::warn;
+use crate::constants::Result;
+
+/// The file the user picked to read, `None` if cancelled
+pub async fn open_file(parent: &gtk::Window, title: &str) -> Option<PathBuf> {
+    #[cfg(all(target_os = "linux", feature = "portals"))]
+    match portal::open_file(parent, title).await {
+        Ok(path) => return path,
+        Err(e) => warn!("The file chooser portal failed, using the GTK dialog: {}", e),
+    }
+    let dialog = gtk::FileDialog::builder().title(title).modal(true).build();
+    dialog.open_future(Some(parent)).await.ok().and_then(|file| file.path())
+}
+
+/// The file the user picked to write, `None` if cancelled
+pub async fn save_file(parent: &gtk::Window, title: &str, initial_name: &str) -> Option<PathBuf> {
+    #[cfg(all(target_os = "linux", feature = "portals"))]
+    match portal::save_file(parent, title, initial_name).await {
+        Ok(path) => return path,
+        Err(e) => warn!("The file chooser portal failed, using the GTK dialog: {}", e),
+    }
+    let dialog = gtk::FileDialog::builder().title(title).modal(true).initial_name(initial_name).build();
+    dialog.save_future(Some(parent)).await.ok().and_then(|file| file.path())
+}
+
+/// Open `path` in the app the desktop uses for its type
+pub async fn open_path(parent: &gtk::Window, path: &Path) -> Result<()> {
+    #[cfg(all(target_os = "linux", feature = "portals"))]
+    match portal::open_path(parent, path).await {
+        Ok(()) => return Ok(()),
+        Err(e) => warn!("The open URI portal failed, using the GTK launcher: {}", e),
+    }
+    let file = gtk::gio::File::for_path(path);
+    gtk::FileLauncher::new(Some(&file)).launch_future(Some(parent)).await?;
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", feature = "portals"))]
+mod portal {
+    use std::path::{Path, PathBuf};
+
+    use ashpd::{
+        desktop::{file_chooser::SelectedFiles, open_uri::OpenFileRequest, ResponseError},
+        WindowIdentifier,
+    };
+    use crate::constants::Result;
+
+    /// `Ok(None)` if cancelled
+    pub async fn open_file(parent: &gtk::Window, title: &str) -> Result<Option<PathBuf>> {
+        let request = SelectedFiles::open_file()
+            .identifier(WindowIdentifier::from_native(parent).await)
+            .title(title)
+            .modal(true)
+            .multiple(false)
+            .send()
+            .await?;
+        selected_path(request.response())
+    }
+
+    /// `Ok(None)` if cancelled
+    pub async fn save_file(parent: &gtk::Window, title: &str, initial_name: &str) -> Result<Option<PathBuf>> {
+        let request = SelectedFiles::save_file()
+            .identifier(WindowIdentifier::from_native(parent).await)
+            .title(title)
+            .modal(true)
+            .current_name(initial_name)
+            .send()
+            .await?;
+        selected_path(request.response())
+    }
+
+    /// The portal only gets the open file, not access to its directory
+    pub async fn open_path(parent: &gtk::Window, path: &Path) -> Result<()> {
+        let file = std::fs::File::open(path)?;
+        OpenFileRequest::default()
+            .identifier(WindowIdentifier::from_native(parent).await)
+            .send_file(&file)
+            .await?;
+        Ok(())
+    }
+
+    fn selected_path(response: ashpd::Result<SelectedFiles>) -> Result<Option<PathBuf>> {
+        match response {
+            // files from the document portal have a path below /run/user
+            Ok(files) => Ok(files.uris().first().and_then(|uri| uri.to_file_path().ok())),
+            Err(ashpd::Error::Response(ResponseError::Cancelled)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
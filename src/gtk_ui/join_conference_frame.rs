@@ -1,17 +1,31 @@
 use gtk::{glib, prelude::*};
 use relm4::*;
-use crate::gtk_ui::constants::GUIAction;
+use crate::{constants::ConferenceId, gtk_ui::constants::GUIAction};
 
 const JOIN_CONFERENCE_BUTTON_TEXT: &str = "Join Conference";
 const JOIN_CONFERENCE_ENTRY_PLACEHOLDER: &str = "Conference ID";
 const JOIN_CONFERENCE_ENTRY_PASSWORD_PLACEHOLDER: &str = "Conference Password";
+const RECENT_CONFERENCES_PLACEHOLDER: &str = "Recent";
+const RECENT_CONFERENCES_TOOLTIP: &str = "Recently Joined Conferences";
+const IMPORT_INVITE_ENTRY_PLACEHOLDER: &str = "Paste Invite";
+const IMPORT_INVITE_BUTTON_TEXT: &str = "Import Invite";
+const IMPORT_INVITE_IMAGE_TOOLTIP: &str = "Import Invite from QR Code Image";
 
-pub struct JoinConferenceFrame;
+pub struct JoinConferenceFrame {
+    /// the placeholder followed by the recent conference ids
+    recent_conferences: gtk::StringList,
+}
+
+#[derive(Debug)]
+pub enum JoinConferenceInput {
+    RecentConferences(Vec<ConferenceId>),
+}
 
 #[relm4::component(pub)]
 impl SimpleComponent for JoinConferenceFrame {
-    type Init = ();
-    type Input = ();
+    /// recently joined conferences, most recent first
+    type Init = Vec<ConferenceId>;
+    type Input = JoinConferenceInput;
     type Output = GUIAction;
 
     view! {
@@ -38,13 +52,35 @@ impl SimpleComponent for JoinConferenceFrame {
                         sender.output(GUIAction::Join((conference_id, conference_password))).unwrap();
                     }
                 },
-                #[name="join_conference_entry"]
-                append = &gtk::Entry {
-                    set_placeholder_text: Some(JOIN_CONFERENCE_ENTRY_PLACEHOLDER),
-                    set_max_length: 10, // u32::MAX character len
-                    EntryExt::set_alignment: 0.5,
-                    connect_changed[join_conference_button, join_conference_entry_password] => move |entry| {
-                        join_conference_button.set_sensitive(!entry.text().is_empty() && !join_conference_entry_password.text().is_empty());
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Horizontal,
+                    set_spacing: 5,
+                    #[name="join_conference_entry"]
+                    append = &gtk::Entry {
+                        set_hexpand: true,
+                        set_placeholder_text: Some(JOIN_CONFERENCE_ENTRY_PLACEHOLDER),
+                        set_max_length: 10, // u32::MAX character len
+                        EntryExt::set_alignment: 0.5,
+                        connect_changed[join_conference_button, join_conference_entry_password] => move |entry| {
+                            join_conference_button.set_sensitive(!entry.text().is_empty() && !join_conference_entry_password.text().is_empty());
+                        },
+                    },
+                    append = &gtk::DropDown {
+                        set_model: Some(&model.recent_conferences),
+                        set_tooltip_text: Some(RECENT_CONFERENCES_TOOLTIP),
+                        #[watch]
+                        set_visible: model.recent_conferences.n_items() > 1,
+                        connect_selected_notify[join_conference_entry] => move |dropdown| {
+                            // the placeholder stays selected, a suggestion only fills the entry
+                            let selected = dropdown.selected();
+                            if selected == 0 || selected == gtk::INVALID_LIST_POSITION {
+                                return;
+                            }
+                            if let Some(conference_id) = dropdown.model().and_downcast::<gtk::StringList>().and_then(|list| list.string(selected)) {
+                                join_conference_entry.set_text(&conference_id);
+                            }
+                            dropdown.set_selected(0);
+                        },
                     },
                 },
                 #[name="join_conference_entry_password"]
@@ -55,17 +91,50 @@ impl SimpleComponent for JoinConferenceFrame {
                     connect_changed[join_conference_button, join_conference_entry] => move |entry| {
                         join_conference_button.set_sensitive(!entry.text().is_empty() && !join_conference_entry.text().is_empty());
                     },
-                }
+                },
+                gtk::Separator {},
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Horizontal,
+                    set_spacing: 5,
+                    #[name="import_invite_entry"]
+                    append = &gtk::Entry {
+                        set_hexpand: true,
+                        set_placeholder_text: Some(IMPORT_INVITE_ENTRY_PLACEHOLDER),
+                        connect_activate[sender] => move |entry| {
+                            if !entry.text().is_empty() {
+                                sender.output(GUIAction::ImportInvite(entry.text().to_string())).unwrap();
+                                entry.set_text("");
+                            }
+                        },
+                    },
+                    append = &gtk::Button {
+                        set_icon_name: "image-x-generic",
+                        set_tooltip_text: Some(IMPORT_INVITE_IMAGE_TOOLTIP),
+                        connect_clicked[sender] => move |_| {
+                            sender.output(GUIAction::OpenInviteImage).unwrap();
+                        },
+                    },
+                },
+                append = &gtk::Button {
+                    set_label: IMPORT_INVITE_BUTTON_TEXT,
+                    connect_clicked[sender, import_invite_entry] => move |_| {
+                        if !import_invite_entry.text().is_empty() {
+                            sender.output(GUIAction::ImportInvite(import_invite_entry.text().to_string())).unwrap();
+                            import_invite_entry.set_text("");
+                        }
+                    },
+                },
             }
         }
     }
 
     fn init(
-        _params: Self::Init,
+        recent_conferences: Self::Init,
         root: Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
-        let model = Self;
+        let model = JoinConferenceFrame { recent_conferences: gtk::StringList::new(&[RECENT_CONFERENCES_PLACEHOLDER]) };
+        model.set_recent_conferences(&recent_conferences);
         let widgets = view_output!();
         widgets.join_conference_entry.delegate().unwrap().connect_insert_text(move |entry, text, position| {
             if text.chars().any(|c| !c.is_numeric()) {
@@ -75,4 +144,18 @@ impl SimpleComponent for JoinConferenceFrame {
         }); // TODO: move to view! macro if possible
         ComponentParts { model, widgets }
     }
+
+    fn update(&mut self, message: Self::Input, _sender: ComponentSender<Self>) {
+        match message {
+            JoinConferenceInput::RecentConferences(recent_conferences) => self.set_recent_conferences(&recent_conferences),
+        }
+    }
+}
+
+impl JoinConferenceFrame {
+    fn set_recent_conferences(&self, recent_conferences: &[ConferenceId]) {
+        let ids = recent_conferences.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        let ids = ids.iter().map(|id| id.as_str()).collect::<Vec<_>>();
+        self.recent_conferences.splice(1, self.recent_conferences.n_items() - 1, &ids);
+    }
 }
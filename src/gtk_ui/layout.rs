@@ -0,0 +1,34 @@
+//! Sizes of the main window panes the user chose, kept between runs in the data directory.
+
+use std::fs;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use crate::{constants::Result, paths};
+
+const LAYOUT_FILE_NAME: &str = "layout.toml";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    /// width of the conference list, `None` until the window was closed once
+    pub sidebar_width: Option<i32>,
+}
+
+impl Layout {
+    pub fn load() -> Layout {
+        let path = paths::data_dir().join(LAYOUT_FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                warn!("Could not parse the window layout {}: {}", path.display(), e);
+                Layout::default()
+            }),
+            Err(_) => Layout::default(), // never resized
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        fs::write(paths::data_dir().join(LAYOUT_FILE_NAME), toml::to_string(self)?)?;
+        Ok(())
+    }
+}
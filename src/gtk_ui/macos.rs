@@ -0,0 +1,63 @@
+//! The parts of a macOS app GTK does not provide on its own: the application menu in the menu bar and the
+//! unread count on the dock icon. Built with the `macos` feature.
+//!
+//! Shortcuts use `<Primary>`, which GTK maps to Cmd on macOS and to Ctrl elsewhere.
+
+use gtk::prelude::*;
+use objc2_app_kit::NSApplication;
+use objc2_foundation::{MainThreadMarker, NSString};
+use crate::gtk_ui::constants::GUIAction;
+
+const LOCK_MENU_TEXT: &str = "Lock";
+const MINIMIZE_MENU_TEXT: &str = "Minimize";
+const CLOSE_WINDOW_MENU_TEXT: &str = "Close Window";
+const QUIT_MENU_TEXT: &str = "Quit Anonymous Conference Client";
+const WINDOW_MENU_TEXT: &str = "Window";
+
+/// Put the application menu with the standard items into the menu bar
+pub fn install_app_menu(input: relm4::Sender<GUIAction>) {
+    let app = relm4::main_application();
+    // action name, GUI action and accelerator of each menu item
+    let menu_actions: [(&str, fn() -> GUIAction, &str); 4] = [
+        ("lock", || GUIAction::Lock, "<Primary>l"),
+        ("minimize", || GUIAction::Minimize, "<Primary>m"),
+        ("close-window", || GUIAction::CloseRequested, "<Primary>w"),
+        ("quit", || GUIAction::Quit, "<Primary>q"),
+    ];
+    for (name, action, accelerator) in menu_actions {
+        let simple_action = gtk::gio::SimpleAction::new(name, None);
+        let input = input.clone();
+        simple_action.connect_activate(move |_, _| input.emit(action()));
+        app.add_action(&simple_action);
+        app.set_accels_for_action(&format!("app.{}", name), &[accelerator]);
+    }
+
+    // the first submenu is shown under the application name
+    let app_menu = gtk::gio::Menu::new();
+    let lock_section = gtk::gio::Menu::new();
+    lock_section.append(Some(LOCK_MENU_TEXT), Some("app.lock"));
+    app_menu.append_section(None, &lock_section);
+    let quit_section = gtk::gio::Menu::new();
+    quit_section.append(Some(QUIT_MENU_TEXT), Some("app.quit"));
+    app_menu.append_section(None, &quit_section);
+
+    let window_menu = gtk::gio::Menu::new();
+    window_menu.append(Some(MINIMIZE_MENU_TEXT), Some("app.minimize"));
+    window_menu.append(Some(CLOSE_WINDOW_MENU_TEXT), Some("app.close-window"));
+
+    let menubar = gtk::gio::Menu::new();
+    menubar.append_submenu(None, &app_menu);
+    menubar.append_submenu(Some(WINDOW_MENU_TEXT), &window_menu);
+    app.set_menubar(Some(&menubar));
+}
+
+/// Show the number of unread messages on the dock icon, `0` removes the badge
+pub fn set_dock_badge(unread: usize) {
+    let Some(main_thread) = MainThreadMarker::new()
+    else {
+        return;
+    };
+    let label = (unread > 0).then(|| NSString::from_str(&unread.to_string()));
+    let app = NSApplication::sharedApplication(main_thread);
+    unsafe { app.dockTile().setBadgeLabel(label.as_deref()) };
+}
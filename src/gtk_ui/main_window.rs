@@ -1,16 +1,33 @@
-use async_std::task::{self, JoinHandle};
+use std::{cell::Cell, collections::HashMap, rc::Rc, time::{Duration, Instant}};
+
+use bytes::Bytes;
 use futures::{channel::mpsc, SinkExt, StreamExt};
 use gtk::prelude::*;
 use log::debug;
 use relm4::*;
 use crate::{
+    config::{CloseAction, Config, RecentConfig, StorageConfig},
+    update_check,
     constants::{
-        Receiver, Sender, UIAction, UIEvent, ConferenceId,
+        Receiver, Sender, UIAction, UIEvent, ConferenceId, DisconnectReason, MigrationStage, Peer, PacketNonce,
     },
-    state_manager,
+    multi_server,
+    logging,
+    crash_report,
+    device_link::{self, LinkBundle, LinkedConference},
+    file_transfer::{Direction, FileTransfer},
+    invite::{self, Invite},
+    recent::Recent,
+    runtime::{self, JoinHandle},
+    settings_bundle,
+    storage,
     gtk_ui::{
         stack::{StackAction, StackWidgets},
         constants::GUIAction,
+        notification::Notifier,
+        file_dialogs,
+        #[cfg(all(target_os = "macos", feature = "macos"))]
+        macos,
     }
 };
 
@@ -32,10 +49,113 @@ const NOT_CONNECTED_TO_SERVER_TITLE: &str = "Failed to connect to the server";
 const NOT_CONNECTED_TO_SERVER_TEXT: &str = "Could not connect to the server, make sure that the server is running";
 
 const RECONNECT_BUTTON_TEXT: &str = "Reconnect";
+const DERIVING_KEY_TEXT: &str = "Deriving the conference key...";
+const CANCEL_BUTTON_TEXT: &str = "Cancel";
+const CANCEL_TOOLTIP: &str = "Cancel Joining or Creating the Conference";
+const CANCELLED_TEXT: &str = "Cancelled";
+const OPEN_LOG_FILE_TOOLTIP: &str = "Open Log File";
+const LEAVE_ALL_TOOLTIP: &str = "Leave All Conferences";
+const SERVER_SELECTOR_TOOLTIP: &str = "Server for New Conferences";
+const SETTINGS_MENU_TOOLTIP: &str = "Settings";
+const EXPORT_SETTINGS_BUTTON_TEXT: &str = "Export Settings…";
+const IMPORT_SETTINGS_BUTTON_TEXT: &str = "Import Settings…";
+const SETTINGS_FILE_NAME: &str = "anonymous-conference-settings.toml";
+const EXPORT_SETTINGS_DIALOG_TITLE: &str = "Export Settings";
+const EXPORT_SETTINGS_DIALOG_TEXT: &str = "The settings were exported to";
+const IMPORT_SETTINGS_DIALOG_TITLE: &str = "Import Settings";
+const IMPORT_SETTINGS_DIALOG_TEXT: &str = "The settings are used from the next start, imported:";
+const LINK_DEVICE_BUTTON_TEXT: &str = "Link Another Device…";
+const IMPORT_DEVICE_LINK_BUTTON_TEXT: &str = "Import Device Link…";
+const LINK_DEVICE_DIALOG_TITLE: &str = "Link Another Device";
+const LINK_DEVICE_DIALOG_TEXT: &str = "Enter the link code and this authentication string on the other device:";
+const IMPORT_DEVICE_LINK_DIALOG_TITLE: &str = "Import Device Link";
+const LINK_CODE_PLACEHOLDER: &str = "Link Code";
+const AUTH_STRING_PLACEHOLDER: &str = "Authentication String";
+const INVALID_DEVICE_LINK_DIALOG_TITLE: &str = "Invalid Link Code";
+const DISCONNECTING_TEXT: &str = "Leaving the server...";
+/// Quit even if the server does not answer the disconnect in time
+const QUIT_TIMEOUT: Duration = Duration::from_secs(3);
+
+const BACKGROUND_NOTIFICATION_ID: &str = "incoming-messages";
+const BACKGROUND_NOTIFICATION_TITLE: &str = "New Messages";
+const SHOW_WINDOW_ACTION: &str = "show-window";
+const IDLE_NOTIFICATION_ID: &str = "conference-idle";
+const IDLE_NOTIFICATION_TITLE: &str = "Leaving Idle Conference";
+
+const UPDATE_AVAILABLE_TEXT: &str = "A new version is available:";
+const UPDATE_DOWNLOAD_LINK_TEXT: &str = "Download";
+
+const INVITE_INCLUDE_PASSWORD_TEXT: &str = "Include the password, protected by a passphrase:";
+const INVITE_PASSPHRASE_PLACEHOLDER: &str = "Invite Passphrase (press Enter to apply)";
+const INVITE_COPY_BUTTON_TEXT: &str = "Copy Invite";
+const INVITE_QR_CODE_SCALE: usize = 6;
+const INVITE_QR_CODE_QUIET_ZONE: usize = 4;
+
+const INVITE_INVALID_DIALOG_TITLE: &str = "Invalid Invite";
+const OPEN_INVITE_IMAGE_DIALOG_TITLE: &str = "Open Invite QR Code";
+const INVITE_WRONG_SERVER_TEXT: &str = "This invite is for a different server";
+const INVITE_JOIN_DIALOG_TITLE: &str = "Join Conference from Invite";
+const INVITE_JOIN_PASSPHRASE_PLACEHOLDER: &str = "Invite Passphrase";
+const INVITE_JOIN_PASSWORD_PLACEHOLDER: &str = "Conference Password";
+
+/// At most this many queued incoming messages are forwarded to the window at once
+const INCOMING_MESSAGES_BATCH_SIZE: usize = 500;
+
+const CONFERENCE_STALE_DIALOG_TITLE: &str = "Conference May Be Stale";
+const CONFERENCE_STALE_DIALOG_TEXT: &str = "The server relays no messages for this conference and its peers do not answer, rejoin it?\nConference ID is:";
+const CONFERENCE_STALE_IGNORE_BUTTON_TEXT: &str = "Ignore";
+const CONFERENCE_STALE_REJOIN_BUTTON_TEXT: &str = "Rejoin";
+
+const MIGRATION_FAILED_DIALOG_TITLE: &str = "Error Moving Conference";
+const HANDSHAKE_FAILED_DIALOG_TITLE: &str = "Not a Conference Server";
+const CERTIFICATE_DIALOG_TITLE: &str = "Server Certificate Not Trusted";
+const PIN_MISMATCH_DIALOG_TITLE: &str = "Connection May Be Intercepted";
+const RING_MISMATCH_DIALOG_TITLE: &str = "Conference May Be Compromised";
+const RING_MISMATCH_DIALOG_TEXT: &str = "A peer sees different participants than you, the server may be tampering with the conference.\nCompare fingerprints out of band before trusting it. Conference ID is:";
+const INVALID_MESSAGES_TEXT: &str = "Dropped malformed messages of peers in conference";
+
+/// Cmd+L on macOS
+const LOCK_SHORTCUT: &str = "<Primary>l";
+/// How often the time since the last input is checked
+const LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Input is reported to the state managers at most this often, see [`crate::idle::IdleSession`]
+const USER_ACTIVE_INTERVAL: Duration = Duration::from_secs(60);
+const CONTENT_PAGE_NAME: &str = "content";
+const LOCKED_PAGE_NAME: &str = "locked";
+const LOCKED_TEXT: &str = "Locked, enter the master password to continue";
+const STORAGE_LOCKED_TEXT: &str = "Enter the master password to open the data directory";
+const UNLOCK_PASSWORD_PLACEHOLDER: &str = "Master Password";
+const UNLOCK_BUTTON_TEXT: &str = "Unlock";
+const UNLOCK_WRONG_PASSWORD_TEXT: &str = "Wrong master password";
+const LOCK_UNAVAILABLE_TEXT: &str = "Locking needs a master password, enable storage.encrypt in the config file";
+
+const CRASH_REPORT_DIALOG_TITLE: &str = "The Client Crashed";
+const CRASH_REPORT_DIALOG_TEXT: &str = "The client crashed the last time it was running.\nA crash report was saved locally, it is never sent anywhere:";
+
+/// Replacing the current server, waits for the old state managers to disconnect
+struct ServerSwitch {
+    server_address: String,
+    /// `(server, conference id, password)` of the conferences on the servers that stay connected
+    rejoin: Vec<(String, ConferenceId, String)>,
+}
 
 struct AppModel {
+    /// the server new conferences are created and joined on
     server_address: String,
-    state_manager_handle: JoinHandle<()>,
+    /// all servers connected to, see [`crate::multi_server`]
+    servers: Vec<String>,
+    /// the server hosting each conference, when connected to several
+    conference_servers: HashMap<ConferenceId, String>,
+    /// the connected servers followed by the recently used ones, offered by the server selector
+    server_list: gtk::StringList,
+    server_switch: Option<ServerSwitch>,
+    /// passwords of the conferences being joined, to join them again after switching servers
+    join_passwords: HashMap<ConferenceId, String>,
+    conference_passwords: HashMap<ConferenceId, String>,
+    /// `None` until the data directory is unlocked, the state managers read the peer labels from it
+    state_manager_handle: Option<JoinHandle<()>>,
+    /// the receiver for the state managers started once the master password is entered
+    waiting_for_unlock: Option<Receiver<UIAction>>,
     ui_action_sender: Sender<UIAction>,
     ui_event_handler_handle: JoinHandle<()>,
     stack: Controller<StackWidgets>,
@@ -43,6 +163,32 @@ struct AppModel {
     last_created_conference_password: Option<String>,
     ui_event_sender: Sender<UIEvent>,
     reconnect_button_visible: bool,
+    /// a password is being hashed, shows a spinner until the conference is created or joined
+    deriving_key: bool,
+    /// joins and creates that can still be cancelled, `None` for a create
+    pending_requests: HashMap<PacketNonce, Option<ConferenceId>>,
+    available_update: Option<(String, String)>,
+    /// parent conference and topic of the breakouts being joined, by breakout conference id
+    breakout_parents: HashMap<ConferenceId, (ConferenceId, String)>,
+    /// servers and conferences used before, suggested by the join form
+    recent: Recent,
+    recent_config: RecentConfig,
+    /// whether the files of the data directory are encrypted
+    storage: StorageConfig,
+    close_action: CloseAction,
+    /// the window was closed, the app quits once the server connections are closed
+    quitting: bool,
+    /// the conferences are hidden until the master password is entered
+    locked: bool,
+    /// shown below the password entry while locked
+    lock_text: String,
+    /// connect again on the next input after disconnecting for inactivity
+    reconnect_on_activity: bool,
+    /// disconnected for inactivity, see [`crate::idle::IdleSession`]
+    idle_disconnected: bool,
+    notifier: Notifier,
+    /// messages received while the window was not active
+    unread: usize,
 }
 
 #[relm4::component]
@@ -53,11 +199,16 @@ impl Component for AppModel {
     /// The type of the messages that this component can send.
     type Output = ();
     /// The type of data with which this component will be initialized.
-    type Init = String; // server address
+    type Init = (String, Config); // server address, config
 
     view!{
         #[root]
         gtk::Window {
+            connect_is_active_notify => GUIAction::WindowActiveChanged,
+            connect_close_request[sender] => move |_| {
+                sender.input(GUIAction::CloseRequested);
+                gtk::glib::Propagation::Stop
+            },
             set_default_width: 700,
             set_default_height: 700,
             #[wrap(Some)]
@@ -66,68 +217,247 @@ impl Component for AppModel {
                 #[wrap(Some)]
                 set_title_widget = &gtk::Label {
                     set_text: MAIN_WINDOW_TITLE_TEXT,
-                }
+                },
+                pack_start = &gtk::Button {
+                    #[watch]
+                    set_sensitive: !model.locked,
+                    set_icon_name: "system-log-out",
+                    set_tooltip_text: Some(LEAVE_ALL_TOOLTIP),
+                    connect_clicked[sender] => move |_| {
+                        sender.input(GUIAction::LeaveAll)
+                    },
+                },
+                pack_start = &gtk::DropDown {
+                    set_model: Some(&model.server_list),
+                    set_tooltip_text: Some(SERVER_SELECTOR_TOOLTIP),
+                    #[watch]
+                    set_sensitive: !model.locked && model.server_switch.is_none(),
+                    #[watch]
+                    set_selected: model.server_position(&model.server_address),
+                    connect_selected_notify[sender] => move |dropdown| {
+                        if let Some(server) = dropdown.selected_item().and_downcast::<gtk::StringObject>() {
+                            sender.input(GUIAction::SwitchServer(server.string().to_string()));
+                        }
+                    },
+                },
+                pack_end = &gtk::MenuButton {
+                    #[watch]
+                    set_sensitive: !model.locked,
+                    set_icon_name: "open-menu-symbolic",
+                    set_tooltip_text: Some(SETTINGS_MENU_TOOLTIP),
+                    #[wrap(Some)]
+                    #[name="settings_popover"]
+                    set_popover = &gtk::Popover {
+                        gtk::Box {
+                            set_orientation: gtk::Orientation::Vertical,
+                            gtk::Button {
+                                set_label: EXPORT_SETTINGS_BUTTON_TEXT,
+                                add_css_class: "flat",
+                                connect_clicked[sender, settings_popover] => move |_| {
+                                    settings_popover.popdown();
+                                    sender.input(GUIAction::ExportSettings)
+                                },
+                            },
+                            gtk::Button {
+                                set_label: IMPORT_SETTINGS_BUTTON_TEXT,
+                                add_css_class: "flat",
+                                connect_clicked[sender, settings_popover] => move |_| {
+                                    settings_popover.popdown();
+                                    sender.input(GUIAction::ImportSettings)
+                                },
+                            },
+                            gtk::Button {
+                                set_label: LINK_DEVICE_BUTTON_TEXT,
+                                add_css_class: "flat",
+                                connect_clicked[sender, settings_popover] => move |_| {
+                                    settings_popover.popdown();
+                                    sender.input(GUIAction::LinkDevice)
+                                },
+                            },
+                            gtk::Button {
+                                set_label: IMPORT_DEVICE_LINK_BUTTON_TEXT,
+                                add_css_class: "flat",
+                                connect_clicked[sender, settings_popover] => move |_| {
+                                    settings_popover.popdown();
+                                    sender.input(GUIAction::ImportDeviceLink)
+                                },
+                            },
+                        },
+                    },
+                },
+                pack_end = &gtk::Button {
+                    #[watch]
+                    set_sensitive: !model.locked,
+                    set_icon_name: "text-x-generic",
+                    set_tooltip_text: Some(OPEN_LOG_FILE_TOOLTIP),
+                    connect_clicked[sender] => move |_| {
+                        sender.input(GUIAction::OpenLogFile)
+                    },
+                },
             },
 
-            gtk::Box {
-                set_orientation: gtk::Orientation::Vertical,
-                set_spacing: 0,
-                set_valign: gtk::Align::Fill,
-                    
-                append = model.stack.widget(),
-
-                gtk::Box {
-                    set_orientation: gtk::Orientation::Horizontal,
-                    set_spacing: 20,
-                    #[name="statusbar"]
-                    append = &gtk::Label {
-                        set_halign: gtk::Align::Start,
-                        set_margin_all: 10,
-                        #[watch]
-                        set_text: &model.statusbar_string,
-                    },
-                    append = &gtk::Button {
+            gtk::Stack {
+                #[watch]
+                set_visible_child_name: if model.locked { LOCKED_PAGE_NAME } else { CONTENT_PAGE_NAME },
+
+                add_named[Some(CONTENT_PAGE_NAME)] = &gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 0,
+                    set_valign: gtk::Align::Fill,
+
+                    gtk::Revealer {
                         #[watch]
-                        set_visible: model.reconnect_button_visible,
-                        connect_clicked[sender] => move |_| {
-                            sender.input(GUIAction::Reconnect)
-                        },
-                        #[wrap(Some)]
-                        set_child = &gtk::Box {
+                        set_reveal_child: model.available_update.is_some(),
+                        gtk::Box {
                             set_orientation: gtk::Orientation::Horizontal,
                             set_spacing: 10,
-                            append = &gtk::Label::new(Some(RECONNECT_BUTTON_TEXT)),
-                            append = &gtk::Image::from_icon_name("view-refresh"),
+                            set_margin_all: 5,
+                            add_css_class: "update-banner",
+                            gtk::Label {
+                                set_hexpand: true,
+                                set_halign: gtk::Align::Start,
+                                set_use_markup: true,
+                                #[watch]
+                                set_markup: &model.available_update.as_ref().map(|(version, url)| format!(
+                                    "{} {} <a href=\"{}\">{}</a>",
+                                    UPDATE_AVAILABLE_TEXT,
+                                    gtk::glib::markup_escape_text(version),
+                                    gtk::glib::markup_escape_text(url),
+                                    UPDATE_DOWNLOAD_LINK_TEXT,
+                                )).unwrap_or_default(),
+                            },
+                            gtk::Button {
+                                set_icon_name: "window-close",
+                                add_css_class: "flat",
+                                connect_clicked[sender] => move |_| {
+                                    sender.input(GUIAction::DismissUpdate)
+                                },
+                            },
+                        },
+                    },
+
+                    append = model.stack.widget(),
+
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Horizontal,
+                        set_spacing: 20,
+                        append = &gtk::Spinner {
+                            set_margin_start: 10,
+                            #[watch]
+                            set_visible: model.deriving_key,
+                            #[watch]
+                            set_spinning: model.deriving_key,
+                        },
+                        #[name="statusbar"]
+                        append = &gtk::Label {
+                            set_halign: gtk::Align::Start,
+                            set_margin_all: 10,
+                            #[watch]
+                            set_text: &model.statusbar_string,
+                        },
+                        append = &gtk::Button {
+                            set_label: CANCEL_BUTTON_TEXT,
+                            set_tooltip_text: Some(CANCEL_TOOLTIP),
+                            #[watch]
+                            set_visible: !model.pending_requests.is_empty(),
+                            connect_clicked[sender] => move |_| {
+                                sender.input(GUIAction::CancelRequests)
+                            },
+                        },
+                        append = &gtk::Button {
+                            #[watch]
+                            set_visible: model.reconnect_button_visible,
+                            connect_clicked[sender] => move |_| {
+                                sender.input(GUIAction::Reconnect)
+                            },
+                            #[wrap(Some)]
+                            set_child = &gtk::Box {
+                                set_orientation: gtk::Orientation::Horizontal,
+                                set_spacing: 10,
+                                append = &gtk::Label::new(Some(RECONNECT_BUTTON_TEXT)),
+                                append = &gtk::Image::from_icon_name("view-refresh"),
+                            }
                         }
                     }
-                }
+                },
+
+                add_named[Some(LOCKED_PAGE_NAME)] = &gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 10,
+                    set_halign: gtk::Align::Center,
+                    set_valign: gtk::Align::Center,
+                    gtk::Image {
+                        set_icon_name: Some("system-lock-screen"),
+                        set_pixel_size: 64,
+                    },
+                    gtk::Label {
+                        #[watch]
+                        set_text: &model.lock_text,
+                    },
+                    gtk::Box {
+                        set_orientation: gtk::Orientation::Horizontal,
+                        set_spacing: 10,
+                        #[name="unlock_entry"]
+                        gtk::PasswordEntry {
+                            set_show_peek_icon: true,
+                            set_placeholder_text: Some(UNLOCK_PASSWORD_PLACEHOLDER),
+                            connect_activate[sender] => move |entry| {
+                                sender.input(GUIAction::Unlock(entry.text().to_string()));
+                                entry.set_text("");
+                            },
+                        },
+                        gtk::Button {
+                            set_label: UNLOCK_BUTTON_TEXT,
+                            connect_clicked[sender, unlock_entry] => move |_| {
+                                sender.input(GUIAction::Unlock(unlock_entry.text().to_string()));
+                                unlock_entry.set_text("");
+                            },
+                        },
+                    },
+                },
             }
         }
     }
 
     /// Initialize the UI and model.
     fn init(
-        server_address: Self::Init,
+        (server_address, config): Self::Init,
         window: Self::Root,
         sender: relm4::ComponentSender<Self>,
     ) -> relm4::ComponentParts<Self> {
         let (ui_event_sender, ui_event_receiver) = mpsc::unbounded();
 
-        let stack = StackWidgets::builder().launch(()).forward(sender.input_sender(), |x| x);
+        // the master password is asked for by the lock page when it is not in the keyring
+        let needs_unlock = !storage::unlock_from_keyring(&config.storage);
+        let recent = Recent::load(&config.recent, &config.storage);
+        let stack = StackWidgets::builder()
+            .launch((config.history.max_messages, config.appearance.density, recent.conferences().to_vec(), config.idle.leave_after_mins > 0))
+            .forward(sender.input_sender(), |x| x);
 
         // start state manager
         let (ui_action_sender, ui_action_receiver) = mpsc::unbounded();
-        let state_manager_handle = spawn_state_manager(server_address.clone(), sender.clone(), ui_event_sender.clone(), ui_action_receiver);
-        let statusbar_string = format!("Connected to {}", &server_address);
+        let servers = config.servers.addresses(&server_address);
+        let (state_manager_handle, waiting_for_unlock) = if needs_unlock {
+            (None, Some(ui_action_receiver))
+        } else {
+            (Some(spawn_state_manager(servers.clone(), sender.clone(), ui_event_sender.clone(), ui_action_receiver)), None)
+        };
+        let statusbar_string = format!("Connected to {}", servers.join(", "));
+        let server_list = gtk::StringList::new(&[]);
+
+        update_check::spawn_update_check(&config.update_check, ui_event_sender.clone());
 
         // start ui event handler
         let component_sender_clone = sender.clone();
-        let ui_event_handler_handle = task::spawn(async move {
+        let ui_event_handler_handle = runtime::spawn(async move {
             translate_ui_events(ui_event_receiver, component_sender_clone).await;
             debug!("UI event handler exited");
         });
 
         let custom_css = r#"
+            .update-banner {
+                border-bottom: 1px solid #888888;
+            }
             .message-box {
                 border: 1px solid #888888;
                 border-radius: 8px;
@@ -146,7 +476,14 @@ impl Component for AppModel {
 
         let model = AppModel {
             server_address,
+            servers,
+            conference_servers: HashMap::new(),
+            server_list,
+            server_switch: None,
+            join_passwords: HashMap::new(),
+            conference_passwords: HashMap::new(),
             state_manager_handle,
+            waiting_for_unlock,
             ui_action_sender,
             ui_event_handler_handle,
             stack,
@@ -154,9 +491,39 @@ impl Component for AppModel {
             last_created_conference_password: None,
             ui_event_sender,
             reconnect_button_visible: false,
+            deriving_key: false,
+            pending_requests: HashMap::new(),
+            available_update: None,
+            breakout_parents: HashMap::new(),
+            recent,
+            recent_config: config.recent.clone(),
+            storage: config.storage.clone(),
+            close_action: config.window.on_close,
+            quitting: false,
+            locked: needs_unlock,
+            lock_text: if needs_unlock { STORAGE_LOCKED_TEXT } else { LOCKED_TEXT }.to_string(),
+            reconnect_on_activity: config.idle.reconnect_on_activity,
+            idle_disconnected: false,
+            notifier: Notifier::new(APP_ID, MAIN_WINDOW_TITLE_TEXT, format!("app.{}", SHOW_WINDOW_ACTION)),
+            unread: 0,
         };
+        model.update_server_list();
+
+        // activated by the notifications shown while running in the background
+        let show_window = gtk::gio::SimpleAction::new(SHOW_WINDOW_ACTION, None);
+        let sender_clone = sender.clone();
+        show_window.connect_activate(move |_, _| sender_clone.input(GUIAction::ShowWindow));
+        relm4::main_application().add_action(&show_window);
 
         let widgets = view_output!();
+        add_input_controllers(&window, config.lock.after_mins, config.storage.encrypt, sender.clone());
+        #[cfg(all(target_os = "macos", feature = "macos"))]
+        macos::install_app_menu(sender.input_sender().clone());
+
+        // offer to show crash reports from previous runs
+        if let Some(report) = crash_report::unseen_crash_reports().pop() {
+            sender.input(GUIAction::CrashReportFound(report));
+        }
 
         relm4::ComponentParts { model, widgets }
     }
@@ -171,7 +538,7 @@ impl Component for AppModel {
                 }
                 self.last_created_conference_password = Some(password.clone());
                 let mut ui_action_sender_clone = self.ui_action_sender.clone();
-                task::spawn(async move {
+                runtime::spawn(async move {
                     if ui_action_sender_clone.send(UIAction::CreateConference(password)).await.is_err() {
                         sender.input(GUIAction::NotConnectedToServerError);
                     }
@@ -179,8 +546,11 @@ impl Component for AppModel {
             }
             GUIAction::ConferenceCreated(conference_id) => {
                 debug!("Conference created with id: \"{}\"", conference_id);
+                self.deriving_key = false;
+                self.pending_requests.retain(|_, pending| pending.is_some());
                 show_conference_created_success_dialog(conference_id,
                     self.last_created_conference_password.as_ref().unwrap().clone(),
+                    &self.server_address,
                     sender.clone(),
                     root
                 );
@@ -188,13 +558,16 @@ impl Component for AppModel {
             }
             GUIAction::ConferenceCreateFailed => {
                 debug!("Conference create failed");
+                self.deriving_key = false;
+                self.pending_requests.retain(|_, pending| pending.is_some());
                 show_simple_dialog(CONFERENCE_CREATED_DIALOG_TITLE_ERROR, CONFERENCE_CREATED_DIALOG_TEXT_ERROR, root);
                 self.last_created_conference_password = None;
             }
             GUIAction::Join((conference_id, password)) => {
                 debug!("Join conference with id: \"{}\" and password: \"{}\"", conference_id, password);
+                self.join_passwords.insert(conference_id, password.clone());
                 let mut sender_clone = self.ui_action_sender.clone();
-                task::spawn(async move {
+                runtime::spawn(async move {
                     if sender_clone.send(UIAction::JoinConference((conference_id, password))).await.is_err() {
                         sender.input(GUIAction::NotConnectedToServerError);
                     }
@@ -203,25 +576,47 @@ impl Component for AppModel {
             GUIAction::ConferenceJoined((conference_id, number_of_peers)) => {
                 debug!("Joined conference with id: \"{}\" and number of peers: \"{}\"", conference_id, number_of_peers);
                 self.statusbar_string = format!("Joined conference with id: \"{}\" and number of peers: \"{}\"", conference_id, number_of_peers);
-                self.stack.sender().send(StackAction::NewConference((conference_id, number_of_peers))).unwrap();
+                self.deriving_key = false;
+                self.pending_requests.retain(|_, pending| *pending != Some(conference_id));
+                if let Some(password) = self.join_passwords.remove(&conference_id) {
+                    self.conference_passwords.insert(conference_id, password);
+                }
+                let parent = self.breakout_parents.remove(&conference_id);
+                let server = self.conference_servers.get(&conference_id).cloned();
+                self.recent.used(server.as_deref().unwrap_or(&self.server_address), conference_id);
+                self.update_server_list();
+                self.stack.sender().send(StackAction::RecentConferences(self.recent.conferences().to_vec())).unwrap();
+                self.stack.sender().send(StackAction::NewConference((conference_id, number_of_peers, parent, server))).unwrap();
             }
             GUIAction::ConferenceJoinFailed(conference_id) => {
                 debug!("Join conference failed, conference ID: {}", conference_id);
+                self.deriving_key = false;
+                self.pending_requests.retain(|_, pending| *pending != Some(conference_id));
+                self.join_passwords.remove(&conference_id);
                 show_simple_dialog(CONFERENCE_JOIN_DIALOG_TITLE_ERROR, CONFERENCE_JOIN_DIALOG_TEXT_ERROR, root);
             }
             GUIAction::SendMessage((conference_id, message_id, message)) => {
                 debug!("Sending message in conference with ID: {}", conference_id);
                 let mut sender_clone = self.ui_action_sender.clone();
-                task::spawn(async move {
+                runtime::spawn(async move {
                     if sender_clone.send(UIAction::SendMessage((conference_id, message_id, message))).await.is_err() {
                         sender.input(GUIAction::NotConnectedToServerError);
                     }
                 });
             }
+            GUIAction::SendFile((conference_id, message_id, path)) => {
+                debug!("Sending file {} in conference with ID: {}", path.display(), conference_id);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::SendFile((conference_id, message_id, path))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
             GUIAction::Leave(conference_id) => {
                 debug!("Leaving conference with ID {}", conference_id);
                 let mut sender_clone = self.ui_action_sender.clone();
-                task::spawn(async move {
+                runtime::spawn(async move {
                     if sender_clone.send(UIAction::LeaveConference(conference_id)).await.is_err() {
                         sender.input(GUIAction::NotConnectedToServerError);
                     }
@@ -234,11 +629,103 @@ impl Component for AppModel {
             GUIAction::ConferenceLeft(conference_id) => {
                 debug!("Left conference with ID {}", conference_id);
                 self.stack.sender().send(StackAction::RemoveConference(conference_id)).unwrap();
+                self.conference_servers.remove(&conference_id);
+                self.conference_passwords.remove(&conference_id);
                 self.statusbar_string = format!("Left conference with id: \"{}\"", conference_id);
             }
-            GUIAction::IncomingMessage((conference_id, message, signature_valid)) => {
-                debug!("Incoming message in conference with ID: {}", conference_id);
-                self.stack.sender().send(StackAction::IncomingMessage((conference_id, message, signature_valid))).unwrap();
+            GUIAction::IncomingMessages(messages) => {
+                debug!("{} incoming messages", messages.len());
+                if !root.is_visible() {
+                    send_background_notification(&self.notifier, &messages);
+                }
+                if !root.is_active() {
+                    self.unread += messages.len();
+                    #[cfg(all(target_os = "macos", feature = "macos"))]
+                    macos::set_dock_badge(self.unread);
+                }
+                self.stack.sender().send(StackAction::IncomingMessages(messages)).unwrap();
+            }
+            GUIAction::MessageSeen((conference_id, message_id, seen_by)) => {
+                self.stack.sender().send(StackAction::MessageSeen((conference_id, message_id, seen_by))).unwrap();
+            }
+            GUIAction::MessageReceivedBy((conference_id, message_id, received_by, other_peers)) => {
+                self.stack.sender().send(StackAction::MessageReceivedBy((conference_id, message_id, received_by, other_peers))).unwrap();
+            }
+            GUIAction::MessageLatency((conference_id, message_id, latency)) => {
+                debug!("Message {} in conference {} {}", message_id, conference_id, latency);
+                self.stack.sender().send(StackAction::MessageLatency((conference_id, message_id, latency))).unwrap();
+            }
+            GUIAction::WindowActiveChanged => {
+                if root.is_active() {
+                    // the visible conference is shown to the user again
+                    self.stack.sender().send(StackAction::ChangedPage).unwrap();
+                    self.unread = 0;
+                    #[cfg(all(target_os = "macos", feature = "macos"))]
+                    macos::set_dock_badge(self.unread);
+                }
+            }
+            GUIAction::MessagesDisplayed(conference_id) => {
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    let _ = sender_clone.send(UIAction::MessagesDisplayed(conference_id)).await;
+                });
+            }
+            GUIAction::SetReadReceipts((conference_id, enabled)) => {
+                debug!("Setting read receipts in conference {} to {}", conference_id, enabled);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::SetReadReceipts((conference_id, enabled))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+                if enabled {
+                    self.stack.sender().send(StackAction::ChangedPage).unwrap();
+                }
+            }
+            GUIAction::SetUnlinkable((conference_id, enabled)) => {
+                debug!("Setting unlinkable mode in conference {} to {}", conference_id, enabled);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::SetUnlinkable((conference_id, enabled))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::SetListenOnly((conference_id, enabled)) => {
+                debug!("Setting listen only in conference {} to {}", conference_id, enabled);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::SetListenOnly((conference_id, enabled))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::SetAutoLeave((conference_id, enabled)) => {
+                debug!("Setting auto leave in conference {} to {}", conference_id, enabled);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::SetAutoLeave((conference_id, enabled))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::SetRetentionPolicy((conference_id, policy)) => {
+                debug!("Setting retention policy in conference {} to {}", conference_id, policy);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::SetRetentionPolicy((conference_id, policy))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::SetPeerLabel((key_image, label)) => {
+                debug!("Setting peer label to {:?}", label);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::SetPeerLabel((key_image, label))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
             }
             GUIAction::MessageAccepted((conference_id, message_id)) => {
                 debug!("Message accepted in conference with ID: {}", conference_id);
@@ -252,27 +739,508 @@ impl Component for AppModel {
                 debug!("Message error in conference with ID: {}", conference_id);
                 self.stack.sender().send(StackAction::MessageError((conference_id, message_id))).unwrap();
             }
+            GUIAction::MessageTimedOut((conference_id, message_id)) => {
+                debug!("Message timed out in conference with ID: {}", conference_id);
+                self.stack.sender().send(StackAction::MessageTimedOut((conference_id, message_id))).unwrap();
+            }
+            GUIAction::MessageTooLarge((conference_id, message_id, max_message_size)) => {
+                debug!("Message too large in conference with ID: {}", conference_id);
+                self.stack.sender().send(StackAction::MessageTooLarge((conference_id, message_id, max_message_size))).unwrap();
+            }
+            GUIAction::UploadProgress((conference_id, message_id, accepted, total)) => {
+                self.stack.sender().send(StackAction::UploadProgress((conference_id, message_id, accepted, total))).unwrap();
+            }
+            GUIAction::FileTransferProgress((conference_id, transfer)) => {
+                self.stack.sender().send(StackAction::FileTransferProgress((conference_id, transfer))).unwrap();
+            }
+            GUIAction::FileReceived((conference_id, file)) => {
+                debug!("Received file {} in conference with ID: {}", file.path.display(), conference_id);
+                self.stack.sender().send(StackAction::FileReceived((conference_id, file))).unwrap();
+            }
+            GUIAction::FileNotSent((conference_id, message_id, reason)) => {
+                debug!("File not sent in conference with ID {}: {}", conference_id, reason);
+                self.stack.sender().send(StackAction::FileNotSent((conference_id, message_id, reason))).unwrap();
+            }
             GUIAction::ConferenceRestructuring((conference_id, number_of_peers)) => {
                 debug!("Conference restructuring in conference with ID: {}", conference_id);
                 self.stack.sender().send(StackAction::ConferenceRestructuring((conference_id, number_of_peers))).unwrap();
             }
+            GUIAction::PeersChanged((conference_id, joined, left)) => {
+                self.stack.sender().send(StackAction::PeersChanged((conference_id, joined, left))).unwrap();
+            }
+            GUIAction::ConferenceRenegotiating(conference_id) => {
+                debug!("Renegotiating the keys of conference with ID: {}", conference_id);
+                self.stack.sender().send(StackAction::ConferenceRenegotiating(conference_id)).unwrap();
+            }
+            GUIAction::RingMismatch(conference_id) => {
+                debug!("A peer of conference with ID: {} computed a different ring", conference_id);
+                show_simple_dialog(RING_MISMATCH_DIALOG_TITLE, &format!("{} {}", RING_MISMATCH_DIALOG_TEXT, conference_id), root);
+            }
+            GUIAction::InvalidMessages((conference_id, count, error)) => {
+                log::warn!("Dropped {} malformed messages in conference with ID: {}, the last one: {}", count, conference_id, error);
+                self.statusbar_string = format!("{} {} ({})", INVALID_MESSAGES_TEXT, conference_id, count);
+            }
+            GUIAction::SetupProgress((conference_id, stage)) => {
+                self.stack.sender().send(StackAction::SetupProgress((conference_id, stage))).unwrap();
+            }
             GUIAction::ConferenceRestructuringFinished(conference_id) => {
                 debug!("Conference restructuring finished in conference with ID: {}", conference_id);
                 self.stack.sender().send(StackAction::ConferenceRestructuringFinished(conference_id)).unwrap();
             }
-            GUIAction::Disconnected => {
-                debug!("Disconnected from server");
-                self.statusbar_string = "Disconnected from server".to_string();
+            GUIAction::Disconnected(reason) => {
+                debug!("Disconnected from server: {:?}", reason);
+                if self.quitting {
+                    relm4::main_application().quit();
+                    return;
+                }
+                if self.server_switch.is_some() {
+                    // the state managers stopped, the reason reported before is not shown
+                    if reason.is_none() {
+                        let server_switch = self.server_switch.take().unwrap();
+                        self.finish_server_switch(server_switch, sender);
+                    }
+                    return;
+                }
+                // the reason may arrive after the state manager is already known to have stopped
+                if (reason.is_none() && self.reconnect_button_visible) || reason == Some(DisconnectReason::UserRequested) {
+                    // only a quit or server switch disconnects on request
+                    return;
+                }
+                self.idle_disconnected = reason == Some(DisconnectReason::Idle);
+                match &reason {
+                    Some(reason @ (DisconnectReason::TlsFailed(_) | DisconnectReason::CertificateFailed(_))) => {
+                        show_simple_dialog(CERTIFICATE_DIALOG_TITLE, &reason.to_string(), root);
+                    },
+                    Some(reason @ DisconnectReason::PinMismatch(_)) => show_simple_dialog(PIN_MISMATCH_DIALOG_TITLE, &reason.to_string(), root),
+                    _ => {},
+                }
+                self.statusbar_string = reason.map_or_else(|| "Disconnected from server".to_string(), |reason| reason.to_string());
                 self.stack.sender().send(StackAction::ClearConferences).unwrap();
                 self.reconnect_button_visible = true;
+                self.deriving_key = false;
+                self.pending_requests.clear();
+                self.join_passwords.clear();
+                self.conference_passwords.clear();
+            }
+            GUIAction::ServerError(disconnecting) => {
+                debug!("Server reported an error, disconnecting: {}", disconnecting);
+                // the disconnect itself is reported once the state manager has stopped
+                self.statusbar_string = if disconnecting {
+                    "The server reported too many errors, disconnecting...".to_string()
+                } else {
+                    "The server reported an error".to_string()
+                };
+            }
+            GUIAction::UserActive => {
+                if self.idle_disconnected {
+                    if self.reconnect_on_activity {
+                        sender.input(GUIAction::Reconnect);
+                    }
+                    return;
+                }
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    // nothing to postpone once disconnected
+                    let _ = sender_clone.send(UIAction::UserActive).await;
+                });
+            }
+            GUIAction::Reconnect => self.reconnect(sender),
+            GUIAction::SwitchServer(server) => {
+                if server == self.server_address || self.server_switch.is_some() {
+                    return;
+                }
+                if self.servers.contains(&server) {
+                    self.select_server(&server);
+                    self.statusbar_string = format!("Conferences are created and joined on {}", server);
+                    return;
+                }
+                // the other servers stay, the current one is replaced
+                let servers: Vec<String> = self.servers[1..].to_vec();
+                if self.reconnect_button_visible {
+                    self.servers = [vec![server], servers].concat();
+                    self.update_server_list();
+                    sender.input(GUIAction::Reconnect);
+                    return;
+                }
+                debug!("Switching from {} to {}", self.servers[0], server);
+                let rejoin = self.conference_passwords.iter()
+                    .filter_map(|(conference_id, password)| {
+                        let conference_server = self.conference_servers.get(conference_id).filter(|s| servers.contains(s))?;
+                        Some((conference_server.clone(), *conference_id, password.clone()))
+                    })
+                    .collect();
+                self.statusbar_string = format!("Switching to {}...", server);
+                self.server_switch = Some(ServerSwitch { server_address: server, rejoin });
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::Disconnect).await.is_err() {
+                        // the state manager already stopped
+                        sender.input(GUIAction::Disconnected(None));
+                    }
+                });
+            }
+            GUIAction::LeaveAll => {
+                debug!("Leaving all conferences");
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::LeaveAllConferences).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::CloseRequested => match self.close_action {
+                CloseAction::Quit => sender.input(GUIAction::Quit),
+                CloseAction::Minimize => root.minimize(),
+                CloseAction::Background => {
+                    debug!("Window closed, staying connected in the background");
+                    root.set_visible(false);
+                }
+            },
+            GUIAction::Quit => {
+                if self.quitting {
+                    return;
+                }
+                debug!("Disconnecting before quitting");
+                self.quitting = true;
+                self.statusbar_string = DISCONNECTING_TEXT.to_string();
+                gtk::glib::timeout_add_local_once(QUIT_TIMEOUT, || relm4::main_application().quit());
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::Disconnect).await.is_err() {
+                        // the state manager already stopped
+                        sender.input(GUIAction::Disconnected(None));
+                    }
+                });
+            }
+            GUIAction::Minimize => root.minimize(),
+            GUIAction::ShowWindow => {
+                root.present();
+            }
+            GUIAction::Lock => {
+                if self.locked {
+                    return;
+                }
+                if !storage::is_unlocked() {
+                    self.statusbar_string = LOCK_UNAVAILABLE_TEXT.to_string();
+                    return;
+                }
+                debug!("Locking the window");
+                self.locked = true;
+                self.lock_text = LOCKED_TEXT.to_string();
+            }
+            GUIAction::Unlock(password) => {
+                let storage_config = self.storage.clone();
+                runtime::spawn(async move {
+                    // deriving the key takes a moment
+                    let correct = runtime::spawn_blocking(move || {
+                        if storage::is_unlocked() {
+                            storage::verify(&password)
+                        } else {
+                            storage::unlock_entered(&password, &storage_config)
+                                .map_err(|e| log::warn!("Could not unlock the data directory: {}", e))
+                                .is_ok()
+                        }
+                    }).await;
+                    sender.input(GUIAction::UnlockChecked(correct));
+                });
+            }
+            GUIAction::UnlockChecked(correct) => {
+                if correct {
+                    debug!("Unlocking the window");
+                    self.locked = false;
+                    if let Some(ui_action_receiver) = self.waiting_for_unlock.take() {
+                        // the recent conferences could not be read before
+                        self.recent = Recent::load(&self.recent_config, &self.storage);
+                        self.update_server_list();
+                        self.stack.sender().send(StackAction::RecentConferences(self.recent.conferences().to_vec())).unwrap();
+                        self.state_manager_handle = Some(spawn_state_manager(self.servers.clone(), sender, self.ui_event_sender.clone(), ui_action_receiver));
+                    }
+                } else {
+                    self.lock_text = UNLOCK_WRONG_PASSWORD_TEXT.to_string();
+                }
+            }
+            GUIAction::OpenLogFile => {
+                let log_file = logging::log_file_path();
+                debug!("Opening log file {:?}", log_file);
+                let root_clone = root.clone();
+                relm4::spawn_local(async move {
+                    if let Err(e) = file_dialogs::open_path(&root_clone, &log_file).await {
+                        log::warn!("Could not open log file: {}", e);
+                    }
+                });
+            }
+            GUIAction::ExportSettings => {
+                let root_clone = root.clone();
+                relm4::spawn_local(async move {
+                    let Some(path) = file_dialogs::save_file(&root_clone, EXPORT_SETTINGS_DIALOG_TITLE, SETTINGS_FILE_NAME).await
+                    else {
+                        return; // cancelled
+                    };
+                    match settings_bundle::export(&path) {
+                        Ok(()) => show_simple_dialog(EXPORT_SETTINGS_DIALOG_TITLE, &format!("{} {}", EXPORT_SETTINGS_DIALOG_TEXT, path.display()), &root_clone),
+                        Err(e) => show_simple_dialog(EXPORT_SETTINGS_DIALOG_TITLE, &e.to_string(), &root_clone),
+                    }
+                });
+            }
+            GUIAction::ImportSettings => {
+                let storage_config = self.storage.clone();
+                let root_clone = root.clone();
+                relm4::spawn_local(async move {
+                    let Some(path) = file_dialogs::open_file(&root_clone, IMPORT_SETTINGS_DIALOG_TITLE).await
+                    else {
+                        return; // cancelled
+                    };
+                    match settings_bundle::import(&path, &storage_config) {
+                        Ok(files) => show_simple_dialog(IMPORT_SETTINGS_DIALOG_TITLE, &format!("{} {}", IMPORT_SETTINGS_DIALOG_TEXT, files.join(", ")), &root_clone),
+                        Err(e) => show_simple_dialog(IMPORT_SETTINGS_DIALOG_TITLE, &e.to_string(), &root_clone),
+                    }
+                });
+            }
+            GUIAction::LinkDevice => {
+                let conferences = self.conference_passwords.iter()
+                    .filter(|(conference_id, _)| self.conference_servers.get(conference_id).unwrap_or(&self.server_address) == &self.server_address)
+                    .map(|(&conference_id, password)| LinkedConference { conference_id, password: password.clone() })
+                    .collect();
+                let server_address = self.server_address.clone();
+                let storage_config = self.storage.clone();
+                let root_clone = root.clone();
+                relm4::spawn_local(async move {
+                    // deriving the key takes a while
+                    let link = runtime::spawn_blocking(move || {
+                        let auth_string = device_link::generate_auth_string();
+                        LinkBundle::new(server_address, conferences, &storage_config).encode(&auth_string)
+                            .map(|link_code| (link_code, auth_string))
+                            .map_err(|e| e.to_string())
+                    }).await;
+                    match link {
+                        Ok((link_code, auth_string)) => show_device_link_dialog(&link_code, &auth_string, &root_clone),
+                        Err(e) => show_simple_dialog(LINK_DEVICE_DIALOG_TITLE, &e, &root_clone),
+                    }
+                });
+            }
+            GUIAction::ImportDeviceLink => show_import_device_link_dialog(sender, root),
+            GUIAction::DeviceLinkImported(bundle) => {
+                if !self.servers.contains(&bundle.server_address) {
+                    show_simple_dialog(INVALID_DEVICE_LINK_DIALOG_TITLE, &format!("{}: {}", INVITE_WRONG_SERVER_TEXT, bundle.server_address), root);
+                    return;
+                }
+                self.select_server(&bundle.server_address);
+                for (key_image, label) in bundle.peer_labels {
+                    if let Some(key_image) = crate::peer_labels::key_image_from_hex(&key_image) {
+                        sender.input(GUIAction::SetPeerLabel((key_image, Some(label))));
+                    }
+                }
+                for conference in bundle.conferences {
+                    if !self.conference_passwords.contains_key(&conference.conference_id) {
+                        sender.input(GUIAction::Join((conference.conference_id, conference.password)));
+                    }
+                }
+                self.statusbar_string = device_link::SEPARATE_MEMBER_NOTICE.to_string();
+            }
+            GUIAction::UpdateAvailable(update) => {
+                debug!("Update available: {:?}", update);
+                self.available_update = Some(update);
+            }
+            GUIAction::DismissUpdate => {
+                self.available_update = None;
+            }
+            GUIAction::CrashReportFound(report) => {
+                debug!("Found crash report {}", report.display());
+                for unseen_report in crash_report::unseen_crash_reports() {
+                    if unseen_report != report {
+                        crash_report::mark_seen(&unseen_report);
+                    }
+                }
+                let report = crash_report::mark_seen(&report);
+                show_crash_report_dialog(report, root);
+            }
+            GUIAction::ImportInvite(text) => {
+                match Invite::decode(&text) {
+                    Ok(invite) if !self.servers.contains(&invite.server_address) => {
+                        show_simple_dialog(INVITE_INVALID_DIALOG_TITLE, &format!("{}: {}", INVITE_WRONG_SERVER_TEXT, invite.server_address), root);
+                    },
+                    Ok(invite) => {
+                        self.select_server(&invite.server_address);
+                        show_join_invite_dialog(invite, sender, root);
+                    },
+                    Err(e) => show_simple_dialog(INVITE_INVALID_DIALOG_TITLE, &e.to_string(), root),
+                }
             }
-            GUIAction::Reconnect => {
-                self.reconnect_button_visible = false;
-                let (ui_action_sender, ui_action_receiver) = mpsc::unbounded();
-                self.ui_action_sender = ui_action_sender;
-                self.state_manager_handle = spawn_state_manager(self.server_address.clone(), sender, self.ui_event_sender.clone(), ui_action_receiver);
-                debug!("Reconnecting to the server, restarting the state manager...");
-                self.statusbar_string = format!("Connected to {}", self.server_address);
+            GUIAction::OpenInviteImage => {
+                let root_clone = root.clone();
+                relm4::spawn_local(async move {
+                    let Some(path) = file_dialogs::open_file(&root_clone, OPEN_INVITE_IMAGE_DIALOG_TITLE).await
+                    else {
+                        return; // cancelled
+                    };
+                    // decoding a large image takes a while
+                    match runtime::spawn_blocking(move || invite::decode_qr_image(&path)).await {
+                        Ok(text) => sender.input(GUIAction::ImportInvite(text)),
+                        Err(e) => show_simple_dialog(INVITE_INVALID_DIALOG_TITLE, &e.to_string(), &root_clone),
+                    }
+                });
+            }
+            GUIAction::ListConferences => {
+                debug!("Listing public conferences");
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::ListConferences).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::AllowFlooding((conference_id, key_image)) => {
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::AllowFlooding((conference_id, key_image))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::CreateBreakout((parent_conference_id, topic)) => {
+                debug!("Creating breakout of conference {}", parent_conference_id);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::CreateBreakout((parent_conference_id, topic))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::DerivingKey(conference_id) => {
+                debug!("Deriving key for conference: {:?}", conference_id);
+                self.statusbar_string = DERIVING_KEY_TEXT.to_string();
+                self.deriving_key = true;
+            }
+            GUIAction::RequestPending((packet_nonce, conference_id)) => {
+                self.pending_requests.insert(packet_nonce, conference_id);
+            }
+            GUIAction::CancelRequests => {
+                let mut sender_clone = self.ui_action_sender.clone();
+                let requests: Vec<PacketNonce> = self.pending_requests.keys().copied().collect();
+                runtime::spawn(async move {
+                    for packet_nonce in requests {
+                        let _ = sender_clone.send(UIAction::CancelRequest(packet_nonce)).await;
+                    }
+                });
+            }
+            GUIAction::RequestCancelled(packet_nonce) => {
+                debug!("Request {} cancelled", packet_nonce);
+                if self.pending_requests.remove(&packet_nonce) == Some(None) {
+                    // another conference can be created now
+                    self.last_created_conference_password = None;
+                }
+                self.deriving_key = false;
+                self.statusbar_string = CANCELLED_TEXT.to_string();
+            }
+            GUIAction::BreakoutAnnounced((parent_conference_id, breakout, None)) => {
+                self.deriving_key = false;
+                // created by us, join right away
+                sender.input(GUIAction::JoinBreakout((parent_conference_id, breakout)));
+            }
+            GUIAction::BreakoutAnnounced((parent_conference_id, breakout, Some(peer))) => {
+                self.stack.sender().send(StackAction::BreakoutAnnounced((parent_conference_id, breakout, peer))).unwrap();
+            }
+            GUIAction::JoinBreakout((parent_conference_id, breakout)) => {
+                self.breakout_parents.insert(breakout.conference_id, (parent_conference_id, breakout.topic));
+                sender.input(GUIAction::Join((breakout.conference_id, breakout.password)));
+            }
+            GUIAction::SenderMuted((conference_id, peer)) => {
+                self.stack.sender().send(StackAction::SenderMuted((conference_id, peer))).unwrap();
+            }
+            GUIAction::SenderUnmuted((conference_id, peer, suppressed)) => {
+                self.stack.sender().send(StackAction::SenderUnmuted((conference_id, peer, suppressed))).unwrap();
+            }
+            GUIAction::ConferenceDirectory(directory) => {
+                self.stack.sender().send(StackAction::ConferenceDirectory(directory)).unwrap();
+            }
+            GUIAction::ServerCapabilities(capabilities) => {
+                debug!("Server capabilities: {}", capabilities);
+                self.stack.sender().send(StackAction::DirectoryAvailable(capabilities.directory())).unwrap();
+            }
+            GUIAction::ConferenceStale((conference_id, true)) => {
+                debug!("Conference {} may be stale", conference_id);
+                show_conference_stale_dialog(conference_id, sender, root);
+            }
+            GUIAction::ConferenceStale((conference_id, false)) => {
+                self.statusbar_string = format!("Conference with id: \"{}\" is relaying messages again", conference_id);
+            }
+            GUIAction::Rejoin(conference_id) => {
+                debug!("Rejoining conference with ID {}", conference_id);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::RejoinConference(conference_id)).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::JoinFromDirectory(conference_id) => {
+                show_join_invite_dialog(Invite::new(self.server_address.clone(), conference_id), sender, root);
+            }
+            GUIAction::Migrate((conference_id, server_address)) => {
+                debug!("Moving conference {} to {}", conference_id, server_address);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::MigrateConference((conference_id, server_address))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::FollowMigration((conference_id, migration)) => {
+                debug!("Following conference {} to {}", conference_id, migration.server_address);
+                let mut sender_clone = self.ui_action_sender.clone();
+                runtime::spawn(async move {
+                    if sender_clone.send(UIAction::FollowMigration((conference_id, migration))).await.is_err() {
+                        sender.input(GUIAction::NotConnectedToServerError);
+                    }
+                });
+            }
+            GUIAction::OwnFingerprint((conference_id, fingerprint)) => {
+                self.stack.sender().send(StackAction::OwnFingerprint((conference_id, fingerprint))).unwrap();
+            }
+            GUIAction::Anonymity((conference_id, anonymity)) => {
+                self.stack.sender().send(StackAction::Anonymity((conference_id, anonymity))).unwrap();
+            }
+            GUIAction::ConferenceIdle((conference_id, remaining)) => {
+                debug!("Conference {} is idle, leaving it in {:?}", conference_id, remaining);
+                if !root.is_active() {
+                    let body = format!("Conference {} is left in {} minutes unless a message is sent or received", conference_id, remaining.as_secs().div_ceil(60));
+                    self.notifier.send(IDLE_NOTIFICATION_ID, IDLE_NOTIFICATION_TITLE, &body);
+                }
+                self.stack.sender().send(StackAction::ConferenceIdle((conference_id, remaining))).unwrap();
+            }
+            GUIAction::HistoryLoaded((conference_id, entries)) => {
+                debug!("Loaded {} messages of conference {} from the history", entries.len(), conference_id);
+                self.stack.sender().send(StackAction::HistoryLoaded((conference_id, entries))).unwrap();
+            }
+            GUIAction::RetentionPolicy((conference_id, policy)) => {
+                self.stack.sender().send(StackAction::RetentionPolicy((conference_id, policy))).unwrap();
+            }
+            GUIAction::MigrationAnnounced((conference_id, migration, peer)) => {
+                self.stack.sender().send(StackAction::MigrationAnnounced((conference_id, migration, peer))).unwrap();
+            }
+            GUIAction::MigrationProgress((conference_id, MigrationStage::Failed(reason))) => {
+                self.statusbar_string = format!("Moving conference with id: \"{}\" failed", conference_id);
+                show_simple_dialog(MIGRATION_FAILED_DIALOG_TITLE, &reason, root);
+            }
+            GUIAction::MigrationProgress((conference_id, stage)) => {
+                self.statusbar_string = format!("Moving conference with id: \"{}\": {}", conference_id, stage);
+            }
+            GUIAction::ConferenceServer((conference_id, server)) => {
+                self.conference_servers.insert(conference_id, server);
+            }
+            GUIAction::ServerDisconnected((server, reason)) => {
+                debug!("Session with {} ended: {}", server, reason);
+                self.statusbar_string = format!("Disconnected from {}: {}", server, reason);
+            }
+            GUIAction::Reconnecting(reason) => {
+                debug!("Reconnecting: {}", reason);
+                // the state manager joins the conferences again, keep their passwords for server switches
+                self.join_passwords.extend(self.conference_passwords.drain());
+                self.statusbar_string = format!("{}, connecting again to {}", reason, self.servers.join(", "));
+            }
+            GUIAction::HandshakeFailed(diagnosis) => {
+                debug!("Handshake failed: {:?}", diagnosis);
+                show_simple_dialog(HANDSHAKE_FAILED_DIALOG_TITLE, &diagnosis.to_string(), root);
             }
             GUIAction::NotConnectedToServerError => {
                 debug!("Could not process gui action, not connected to a server");
@@ -283,7 +1251,15 @@ impl Component for AppModel {
 }
 
 async fn translate_ui_events(mut ui_event_receiver: Receiver<UIEvent>, sender: relm4::ComponentSender<AppModel>) {
-    while let Some(ui_event) = ui_event_receiver.next().await {
+    let mut next_ui_event = None;
+    loop {
+        let ui_event = match next_ui_event.take() {
+            Some(ui_event) => ui_event,
+            None => match ui_event_receiver.next().await {
+                Some(ui_event) => ui_event,
+                None => break,
+            },
+        };
         match ui_event {
             UIEvent::ConferenceCreated(conference_id) => sender.input(GUIAction::ConferenceCreated(conference_id)),
             UIEvent::ConferenceCreateFailed => sender.input(GUIAction::ConferenceCreateFailed),
@@ -291,31 +1267,218 @@ async fn translate_ui_events(mut ui_event_receiver: Receiver<UIEvent>, sender: r
             UIEvent::ConferenceJoinFailed(conference_id) => sender.input(GUIAction::ConferenceJoinFailed(conference_id)),
             UIEvent::ConferenceLeft(conference_id) => sender.input(GUIAction::ConferenceLeft(conference_id)),
             UIEvent::ConferenceLeaveFailed(conference_id) => sender.input(GUIAction::ConferenceLeaveFailed(conference_id)),
-            UIEvent::IncomingMessage((conference_id, message, is_private)) => sender.input(GUIAction::IncomingMessage((conference_id, message, is_private))),
+            UIEvent::IncomingMessage(message) => {
+                // messages arriving in bulk (e.g. after reconnecting) are forwarded together,
+                // so the window is not updated once per message
+                let mut messages = vec![message];
+                while messages.len() < INCOMING_MESSAGES_BATCH_SIZE {
+                    match ui_event_receiver.try_next() {
+                        Ok(Some(UIEvent::IncomingMessage(message))) => messages.push(message),
+                        Ok(Some(ui_event)) => {
+                            next_ui_event = Some(ui_event);
+                            break;
+                        },
+                        _ => break, // nothing queued or closed
+                    }
+                }
+                sender.input(GUIAction::IncomingMessages(messages));
+            },
             UIEvent::MessageAccepted((conference_id, message_id)) => sender.input(GUIAction::MessageAccepted((conference_id, message_id))),
             UIEvent::MessageRejected((conference_id, message_id)) => sender.input(GUIAction::MessageRejected((conference_id, message_id))),
             UIEvent::MessageError((conference_id, message_id)) => sender.input(GUIAction::MessageError((conference_id, message_id))),
+            UIEvent::MessageTimedOut((conference_id, message_id)) => sender.input(GUIAction::MessageTimedOut((conference_id, message_id))),
+            UIEvent::MessageTooLarge((conference_id, message_id, max_message_size)) => sender.input(GUIAction::MessageTooLarge((conference_id, message_id, max_message_size))),
+            UIEvent::UploadProgress((conference_id, message_id, accepted, total)) => sender.input(GUIAction::UploadProgress((conference_id, message_id, accepted, total))),
+            // own files are shown like large messages
+            UIEvent::FileTransferProgress((conference_id, FileTransfer { direction: Direction::Sending(message_id), chunks_done, chunks, .. })) =>
+                sender.input(GUIAction::UploadProgress((conference_id, message_id, chunks_done, chunks))),
+            UIEvent::FileTransferProgress((conference_id, transfer)) => sender.input(GUIAction::FileTransferProgress((conference_id, transfer))),
+            UIEvent::FileReceived((conference_id, file)) => sender.input(GUIAction::FileReceived((conference_id, file))),
+            UIEvent::FileNotSent((conference_id, message_id, reason)) => sender.input(GUIAction::FileNotSent((conference_id, message_id, reason))),
             UIEvent::ConferenceRestructuring((conference_id, number_of_peers)) => sender.input(GUIAction::ConferenceRestructuring((conference_id, number_of_peers))),
+            UIEvent::PeersChanged((conference_id, joined, left)) => sender.input(GUIAction::PeersChanged((conference_id, joined, left))),
             UIEvent::ConferenceRestructuringFinished(conference_id) => sender.input(GUIAction::ConferenceRestructuringFinished(conference_id)),
+            UIEvent::ConferenceRenegotiating(conference_id) => sender.input(GUIAction::ConferenceRenegotiating(conference_id)),
+            UIEvent::SetupProgress((conference_id, stage)) => sender.input(GUIAction::SetupProgress((conference_id, stage))),
+            UIEvent::RingMismatch(conference_id) => sender.input(GUIAction::RingMismatch(conference_id)),
+            UIEvent::InvalidMessages(invalid_messages) => sender.input(GUIAction::InvalidMessages(invalid_messages)),
+            UIEvent::MessageSeen((conference_id, message_id, seen_by)) => sender.input(GUIAction::MessageSeen((conference_id, message_id, seen_by))),
+            UIEvent::MessageReceivedBy((conference_id, message_id, received_by, other_peers)) => sender.input(GUIAction::MessageReceivedBy((conference_id, message_id, received_by, other_peers))),
+            UIEvent::MessageLatency((conference_id, message_id, latency)) => sender.input(GUIAction::MessageLatency((conference_id, message_id, latency))),
+            UIEvent::ConferenceDirectory(directory) => sender.input(GUIAction::ConferenceDirectory(directory)),
+            UIEvent::SenderMuted((conference_id, peer)) => sender.input(GUIAction::SenderMuted((conference_id, peer))),
+            UIEvent::SenderUnmuted((conference_id, peer, suppressed)) => sender.input(GUIAction::SenderUnmuted((conference_id, peer, suppressed))),
+            UIEvent::BreakoutAnnounced(announcement) => sender.input(GUIAction::BreakoutAnnounced(announcement)),
+            UIEvent::ServerError(disconnecting) => sender.input(GUIAction::ServerError(disconnecting)),
+            UIEvent::ProtocolViolation(reason) => debug!("Ignored an inconsistent server event: {}", reason),
+            UIEvent::Disconnected(reason) => sender.input(GUIAction::Disconnected(Some(reason))),
+            UIEvent::DerivingKey(conference_id) => sender.input(GUIAction::DerivingKey(conference_id)),
+            UIEvent::RequestPending(request) => sender.input(GUIAction::RequestPending(request)),
+            UIEvent::RequestCancelled(packet_nonce) => sender.input(GUIAction::RequestCancelled(packet_nonce)),
+            UIEvent::ServerCapabilities(capabilities) => sender.input(GUIAction::ServerCapabilities(capabilities)),
+            UIEvent::ConferenceStale((conference_id, stale)) => sender.input(GUIAction::ConferenceStale((conference_id, stale))),
+            UIEvent::ConferenceServer(conference_server) => sender.input(GUIAction::ConferenceServer(conference_server)),
+            UIEvent::ServerDisconnected(server_disconnected) => sender.input(GUIAction::ServerDisconnected(server_disconnected)),
+            UIEvent::Reconnecting(reason) => sender.input(GUIAction::Reconnecting(reason)),
+            UIEvent::MigrationAnnounced(announcement) => sender.input(GUIAction::MigrationAnnounced(announcement)),
+            UIEvent::MigrationProgress(progress) => sender.input(GUIAction::MigrationProgress(progress)),
+            UIEvent::HandshakeFailed(diagnosis) => sender.input(GUIAction::HandshakeFailed(diagnosis)),
+            UIEvent::OwnFingerprint(own_fingerprint) => sender.input(GUIAction::OwnFingerprint(own_fingerprint)),
+            UIEvent::Anonymity(anonymity) => sender.input(GUIAction::Anonymity(anonymity)),
+            UIEvent::ConferenceIdle((conference_id, remaining)) => sender.input(GUIAction::ConferenceIdle((conference_id, remaining))),
+            UIEvent::HistoryLoaded((conference_id, entries)) => sender.input(GUIAction::HistoryLoaded((conference_id, entries))),
+            UIEvent::RetentionPolicy((conference_id, policy)) => sender.input(GUIAction::RetentionPolicy((conference_id, policy))),
+            UIEvent::PluginList(names) => debug!("Loaded plugins: {:?}", names),
+            UIEvent::UpdateAvailable(update) => sender.input(GUIAction::UpdateAvailable(update)),
+        }
+    }
+}
+
+impl AppModel {
+    /// Start the state managers for the new server and join the kept conferences again
+    fn finish_server_switch(&mut self, server_switch: ServerSwitch, sender: ComponentSender<Self>) {
+        let ServerSwitch { server_address, rejoin } = server_switch;
+        self.servers = [vec![server_address.clone()], self.servers[1..].to_vec()].concat();
+        self.update_server_list();
+        self.stack.sender().send(StackAction::ClearConferences).unwrap();
+        self.deriving_key = false;
+        self.pending_requests.clear();
+        self.join_passwords.clear();
+        self.conference_passwords.clear();
+        self.reconnect(sender.clone());
+        // queued until the new state managers run, the router joins on the selected server, so one task sends
+        // them in order
+        let mut actions = Vec::new();
+        for (server, conference_id, password) in rejoin {
+            self.join_passwords.insert(conference_id, password.clone());
+            actions.push(UIAction::SelectServer(server));
+            actions.push(UIAction::JoinConference((conference_id, password)));
+        }
+        actions.push(UIAction::SelectServer(server_address));
+        let mut sender_clone = self.ui_action_sender.clone();
+        runtime::spawn(async move {
+            for action in actions {
+                if sender_clone.send(action).await.is_err() {
+                    sender.input(GUIAction::NotConnectedToServerError);
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Start new state managers for all servers, the first one is selected
+    fn reconnect(&mut self, sender: ComponentSender<Self>) {
+        self.reconnect_button_visible = false;
+        self.idle_disconnected = false;
+        let (ui_action_sender, ui_action_receiver) = mpsc::unbounded();
+        self.ui_action_sender = ui_action_sender;
+        self.server_address = self.servers[0].clone();
+        self.conference_servers.clear();
+        self.state_manager_handle = Some(spawn_state_manager(self.servers.clone(), sender, self.ui_event_sender.clone(), ui_action_receiver));
+        debug!("Reconnecting to the server, restarting the state manager...");
+        self.statusbar_string = format!("Connected to {}", self.servers.join(", "));
+    }
+
+    /// Offer the connected and recently used servers, entries are only added so the selection stays valid
+    fn update_server_list(&self) {
+        for server in self.servers.iter().chain(self.recent.servers()) {
+            if self.server_position(server) == gtk::INVALID_LIST_POSITION {
+                self.server_list.append(server);
+            }
+        }
+    }
+
+    fn server_position(&self, server: &str) -> u32 {
+        (0..self.server_list.n_items())
+            .find(|&position| self.server_list.string(position).is_some_and(|s| s == server))
+            .unwrap_or(gtk::INVALID_LIST_POSITION)
+    }
+
+    /// Create and join conferences on `server` from now on
+    fn select_server(&mut self, server: &str) {
+        if server == self.server_address {
+            return;
+        }
+        self.server_address = server.to_string();
+        let mut sender_clone = self.ui_action_sender.clone();
+        let server = server.to_string();
+        runtime::spawn(async move {
+            let _ = sender_clone.send(UIAction::SelectServer(server)).await;
+        });
+    }
+}
+
+/// Tell the user about new messages while the window is hidden, without their text
+fn send_background_notification(notifier: &Notifier, messages: &[(ConferenceId, Bytes, bool, Peer)]) {
+    let mut conference_ids: Vec<ConferenceId> = messages.iter().map(|(conference_id, _, _, _)| *conference_id).collect();
+    conference_ids.sort_unstable();
+    conference_ids.dedup();
+    let conference_ids: Vec<String> = conference_ids.iter().map(|id| id.to_string()).collect();
+    let body = format!("{} new messages in conference {}", messages.len(), conference_ids.join(", "));
+    notifier.send(BACKGROUND_NOTIFICATION_ID, BACKGROUND_NOTIFICATION_TITLE, &body);
+}
+
+/// Lock with the shortcut, and after `after_mins` minutes without input if it is not `0`
+fn add_input_controllers(window: &gtk::Window, lock_after_mins: u64, encrypted: bool, sender: ComponentSender<AppModel>) {
+    let shortcuts = gtk::ShortcutController::new();
+    shortcuts.set_scope(gtk::ShortcutScope::Global);
+    let sender_clone = sender.clone();
+    shortcuts.add_shortcut(gtk::Shortcut::new(
+        gtk::ShortcutTrigger::parse_string(LOCK_SHORTCUT),
+        Some(gtk::CallbackAction::new(move |_, _| {
+            sender_clone.input(GUIAction::Lock);
+            gtk::glib::Propagation::Stop
+        })),
+    ));
+    window.add_controller(shortcuts);
+
+    let last_input = Rc::new(Cell::new(Instant::now()));
+    let last_reported = Cell::new(Instant::now());
+    let input = gtk::EventControllerLegacy::new();
+    input.set_propagation_phase(gtk::PropagationPhase::Capture);
+    let last_input_clone = last_input.clone();
+    let sender_clone = sender.clone();
+    input.connect_event(move |_, _| {
+        last_input_clone.set(Instant::now());
+        if last_reported.get().elapsed() >= USER_ACTIVE_INTERVAL {
+            last_reported.set(Instant::now());
+            sender_clone.input(GUIAction::UserActive);
         }
+        gtk::glib::Propagation::Proceed
+    });
+    window.add_controller(input);
+
+    if lock_after_mins == 0 {
+        return;
     }
+    if !encrypted {
+        log::warn!("{}", LOCK_UNAVAILABLE_TEXT);
+        return;
+    }
+    let lock_after = Duration::from_secs(lock_after_mins * 60);
+    gtk::glib::timeout_add_local(LOCK_CHECK_INTERVAL, move || {
+        if last_input.get().elapsed() >= lock_after {
+            sender.input(GUIAction::Lock);
+        }
+        gtk::glib::ControlFlow::Continue
+    });
 }
 
 fn spawn_state_manager(
-    server_address: String,
+    servers: Vec<String>,
     component_sender: ComponentSender<AppModel>,
     ui_event_sender: Sender<UIEvent>,
     ui_action_receiver: Receiver<UIAction>
 ) -> JoinHandle<()> {
-    task::spawn(async move {
-        state_manager::start_state_manager(server_address, ui_event_sender, ui_action_receiver).await;
+    runtime::spawn(async move {
+        multi_server::start_state_managers(servers, ui_event_sender, ui_action_receiver).await;
         debug!("State manager exited");
-        component_sender.input(GUIAction::Disconnected);
+        component_sender.input(GUIAction::Disconnected(None));
     })
 }
 
 #[allow(deprecated)]
-fn show_conference_created_success_dialog(conference_id: ConferenceId, conference_password: String, sender: relm4::ComponentSender<AppModel>, root: &gtk::Window) {
+fn show_conference_created_success_dialog(conference_id: ConferenceId, conference_password: String, server_address: &str, sender: relm4::ComponentSender<AppModel>, root: &gtk::Window) {
     let dialog = gtk::MessageDialog::builder()
         .modal(true)
         .transient_for(root)
@@ -326,6 +1489,7 @@ fn show_conference_created_success_dialog(conference_id: ConferenceId, conferenc
     let dialog_text = dialog_text_label.downcast_ref::<gtk::Label>().unwrap();
     dialog_text.set_selectable(true);
     dialog_text.set_halign(gtk::Align::Center); // TODO: not working
+    append_invite_widgets(&dialog.message_area().downcast::<gtk::Box>().unwrap(), Invite::new(server_address, conference_id), conference_password.clone());
     dialog.add_button("Close", gtk::ResponseType::Close);
     dialog.add_button("Join Conference", gtk::ResponseType::Apply);
     let sender_clone = sender.clone();
@@ -344,6 +1508,236 @@ fn show_conference_created_success_dialog(conference_id: ConferenceId, conferenc
     dialog.show();
 }
 
+/// Show the invite as a QR code and as text, optionally including the password
+fn append_invite_widgets(container: &gtk::Box, invite: Invite, conference_password: String) {
+    let picture = gtk::Picture::new();
+    picture.set_can_shrink(false);
+    let invite_label = gtk::Label::new(None);
+    invite_label.set_selectable(true);
+    invite_label.set_wrap(true);
+    invite_label.set_wrap_mode(gtk::pango::WrapMode::Char);
+    invite_label.set_max_width_chars(40);
+    let include_password = gtk::CheckButton::with_label(INVITE_INCLUDE_PASSWORD_TEXT);
+    let passphrase_entry = gtk::PasswordEntry::new();
+    passphrase_entry.set_show_peek_icon(true);
+    passphrase_entry.set_placeholder_text(Some(INVITE_PASSPHRASE_PLACEHOLDER));
+    passphrase_entry.set_sensitive(false);
+
+    let update = {
+        let (picture, invite_label, include_password, passphrase_entry) = (picture.clone(), invite_label.clone(), include_password.clone(), passphrase_entry.clone());
+        move || {
+            let passphrase = passphrase_entry.text();
+            let invite = if include_password.is_active() && !passphrase.is_empty() {
+                match invite.clone().with_password(&conference_password, &passphrase) {
+                    Ok(invite) => invite,
+                    Err(e) => {
+                        log::warn!("Could not add the password to the invite: {}", e);
+                        invite.clone()
+                    },
+                }
+            } else {
+                invite.clone()
+            };
+            match invite.encode() {
+                Ok(text) => invite_label.set_text(&text),
+                Err(e) => log::warn!("Could not create invite: {}", e),
+            }
+            match qr_code_texture(&invite) {
+                Ok(texture) => picture.set_paintable(Some(&texture)),
+                Err(e) => log::warn!("Could not create invite QR code: {}", e),
+            }
+        }
+    };
+    update();
+    let update = std::rc::Rc::new(update);
+    let update_clone = update.clone();
+    let passphrase_entry_clone = passphrase_entry.clone();
+    include_password.connect_toggled(move |check| {
+        passphrase_entry_clone.set_sensitive(check.is_active());
+        update_clone();
+    });
+    // deriving the key is slow, only update on enter
+    passphrase_entry.connect_activate(move |_| update());
+
+    let copy_button = gtk::Button::with_label(INVITE_COPY_BUTTON_TEXT);
+    let invite_label_clone = invite_label.clone();
+    copy_button.connect_clicked(move |button| {
+        button.clipboard().set_text(&invite_label_clone.text());
+    });
+
+    container.append(&picture);
+    container.append(&invite_label);
+    container.append(&copy_button);
+    container.append(&include_password);
+    container.append(&passphrase_entry);
+}
+
+/// Render the invite QR code with a white border, scaled so it can be scanned from the screen
+fn qr_code_texture(invite: &Invite) -> crate::constants::Result<gtk::gdk::MemoryTexture> {
+    Ok(modules_texture(invite.qr_code()?))
+}
+
+/// Render QR code modules as [`qr_code_texture`]
+fn modules_texture((modules, width): (Vec<bool>, usize)) -> gtk::gdk::MemoryTexture {
+    let size = (width + 2 * INVITE_QR_CODE_QUIET_ZONE) * INVITE_QR_CODE_SCALE;
+    let mut pixels = vec![0xffu8; size * size * 3];
+    for (index, _) in modules.iter().enumerate().filter(|(_, dark)| **dark) {
+        let (module_x, module_y) = (index % width + INVITE_QR_CODE_QUIET_ZONE, index / width + INVITE_QR_CODE_QUIET_ZONE);
+        for y in module_y * INVITE_QR_CODE_SCALE..(module_y + 1) * INVITE_QR_CODE_SCALE {
+            let row = y * size * 3;
+            pixels[row + module_x * INVITE_QR_CODE_SCALE * 3..row + (module_x + 1) * INVITE_QR_CODE_SCALE * 3].fill(0);
+        }
+    }
+    gtk::gdk::MemoryTexture::new(
+        size as i32,
+        size as i32,
+        gtk::gdk::MemoryFormat::R8g8b8,
+        &gtk::glib::Bytes::from_owned(pixels),
+        size * 3,
+    )
+}
+
+#[allow(deprecated)]
+fn show_device_link_dialog(link_code: &str, auth_string: &str, root: &gtk::Window) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(true)
+        .transient_for(root)
+        .title(LINK_DEVICE_DIALOG_TITLE)
+        .text(format!("{}\n{}\n\n{}", LINK_DEVICE_DIALOG_TEXT, device_link::format_auth_string(auth_string), device_link::SEPARATE_MEMBER_NOTICE))
+        .build();
+    let container = dialog.message_area().downcast::<gtk::Box>().unwrap();
+    match invite::qr_code(link_code) {
+        Ok(modules) => {
+            let picture = gtk::Picture::for_paintable(&modules_texture(modules));
+            picture.set_can_shrink(false);
+            container.append(&picture);
+        },
+        Err(e) => log::warn!("Could not create link code QR code: {}", e),
+    }
+    let link_code_label = gtk::Label::new(Some(link_code));
+    link_code_label.set_selectable(true);
+    link_code_label.set_wrap(true);
+    link_code_label.set_wrap_mode(gtk::pango::WrapMode::Char);
+    link_code_label.set_max_width_chars(40);
+    container.append(&link_code_label);
+    dialog.add_button("Close", gtk::ResponseType::Close);
+    dialog.connect_response(|dialog, _| dialog.close());
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_import_device_link_dialog(sender: relm4::ComponentSender<AppModel>, root: &gtk::Window) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(true)
+        .transient_for(root)
+        .title(IMPORT_DEVICE_LINK_DIALOG_TITLE)
+        .text(device_link::SEPARATE_MEMBER_NOTICE)
+        .build();
+    let link_code_entry = gtk::Entry::new();
+    link_code_entry.set_placeholder_text(Some(LINK_CODE_PLACEHOLDER));
+    let auth_string_entry = gtk::Entry::new();
+    auth_string_entry.set_placeholder_text(Some(AUTH_STRING_PLACEHOLDER));
+    let container = dialog.message_area().downcast::<gtk::Box>().unwrap();
+    container.append(&link_code_entry);
+    container.append(&auth_string_entry);
+    dialog.add_button("Close", gtk::ResponseType::Close);
+    dialog.add_button("Import", gtk::ResponseType::Apply);
+    let root_clone = root.clone();
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            let (link_code, auth_string) = (link_code_entry.text().to_string(), auth_string_entry.text().to_string());
+            let (sender, root) = (sender.clone(), root_clone.clone());
+            relm4::spawn_local(async move {
+                let bundle = runtime::spawn_blocking(move || LinkBundle::decode(&link_code, &auth_string).map_err(|e| e.to_string())).await;
+                match bundle {
+                    Ok(bundle) => sender.input(GUIAction::DeviceLinkImported(bundle)),
+                    Err(e) => show_simple_dialog(INVALID_DEVICE_LINK_DIALOG_TITLE, &e, &root),
+                }
+            });
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_join_invite_dialog(invite: Invite, sender: relm4::ComponentSender<AppModel>, root: &gtk::Window) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(true)
+        .transient_for(root)
+        .title(INVITE_JOIN_DIALOG_TITLE)
+        .text(format!("Join conference {} on {}", invite.conference_id, invite.server_address))
+        .build();
+    let entry = gtk::PasswordEntry::new();
+    entry.set_show_peek_icon(true);
+    entry.set_placeholder_text(Some(if invite.has_password() { INVITE_JOIN_PASSPHRASE_PLACEHOLDER } else { INVITE_JOIN_PASSWORD_PLACEHOLDER }));
+    dialog.message_area().downcast::<gtk::Box>().unwrap().append(&entry);
+    dialog.add_button("Close", gtk::ResponseType::Close);
+    dialog.add_button("Join Conference", gtk::ResponseType::Apply);
+    let root_clone = root.clone();
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            let secret = entry.text().to_string();
+            let password = match invite.password(&secret) {
+                Ok(Some(password)) => password,
+                Ok(None) => secret,
+                Err(e) => {
+                    show_simple_dialog(INVITE_INVALID_DIALOG_TITLE, &e.to_string(), &root_clone);
+                    return;
+                },
+            };
+            sender.input(GUIAction::Join((invite.conference_id, password)));
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_conference_stale_dialog(conference_id: ConferenceId, sender: relm4::ComponentSender<AppModel>, root: &gtk::Window) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(true)
+        .transient_for(root)
+        .title(CONFERENCE_STALE_DIALOG_TITLE)
+        .text(format!("{} {}", CONFERENCE_STALE_DIALOG_TEXT, conference_id))
+        .build();
+    dialog.add_button(CONFERENCE_STALE_IGNORE_BUTTON_TEXT, gtk::ResponseType::Close);
+    dialog.add_button(CONFERENCE_STALE_REJOIN_BUTTON_TEXT, gtk::ResponseType::Apply);
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            sender.input(GUIAction::Rejoin(conference_id));
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
+#[allow(deprecated)]
+fn show_crash_report_dialog(report: std::path::PathBuf, root: &gtk::Window) {
+    let dialog = gtk::MessageDialog::builder()
+        .modal(true)
+        .transient_for(root)
+        .title(CRASH_REPORT_DIALOG_TITLE)
+        .text(format!("{}\n{}", CRASH_REPORT_DIALOG_TEXT, report.display()))
+        .build();
+    dialog.add_button("Close", gtk::ResponseType::Close);
+    dialog.add_button("Show Report", gtk::ResponseType::Apply);
+    let root_clone = root.clone();
+    dialog.connect_response(move |dialog, response_id| {
+        if let gtk::ResponseType::Apply = response_id {
+            let report = report.clone();
+            let root_clone = root_clone.clone();
+            relm4::spawn_local(async move {
+                if let Err(e) = file_dialogs::open_path(&root_clone, &report).await {
+                    log::warn!("Could not open crash report: {}", e);
+                }
+            });
+        }
+        dialog.close();
+    });
+    dialog.show();
+}
+
 #[allow(deprecated)]
 fn show_simple_dialog(title: &str, text: &str, root: &gtk::Window) {
     let dialog = gtk::MessageDialog::builder()
@@ -360,11 +1754,11 @@ fn show_simple_dialog(title: &str, text: &str, root: &gtk::Window) {
     });
     dialog.show();
 }
-pub fn start_gtk_ui(server_address: String) {
+pub fn start_gtk_ui(server_address: String, config: Config) {
     // Create a new application
     let random = rand::random::<u32>(); // allow multiple instances
     let app = relm4::RelmApp::new(&format!("{}{}", APP_ID, random))
     .with_args(vec![]);
-    app.run::<AppModel>(server_address);
+    app.run::<AppModel>((server_address, config));
 }
 
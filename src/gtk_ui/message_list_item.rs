@@ -1,24 +1,49 @@
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
 use gtk::prelude::*;
+use log::warn;
 use relm4::{
     binding::U8Binding,
     prelude::*,
     typed_view::list::RelmListItem,
     view,
 };
+use crate::{config::Density, constants::MessageLatency, thumbnail::Thumbnail};
+
+const COZY_MARGIN: i32 = 10;
+const COMPACT_MARGIN: i32 = 2;
+const PREVIEW_TOOLTIP: &str = "Open the image";
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum MessageStatus {
     SignatureValid,
     SignatureInvalid,
+    /// Shown right away, waiting for the server to accept it
+    MessagePending,
     MessageDelivered,
     MessageError,
+    /// Not a message, a notice about the conference
+    Notice,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct MessageListItem {
     sent_by_me: bool,
+    author_name: Option<String>,
     text: String,
     status: MessageStatus,
+    seen_by: usize,
+    /// peers that confirmed receiving an own message and the number of other peers
+    received_by: Option<(usize, usize)>,
+    /// chunks of a large own message accepted by the server and the number of chunks
+    upload_progress: Option<(usize, usize)>,
+    /// shown on the status icon of own messages
+    latencies: Vec<MessageLatency>,
+    /// a received image and where it was saved, opened when the preview is clicked
+    preview: Option<(Thumbnail, PathBuf)>,
+    density: Density,
+    /// follows a message of the same author, the author is not repeated
+    grouped: bool,
     binding: U8Binding, // MessageID is 32 bytes
 }
 
@@ -27,16 +52,82 @@ impl MessageListItem {
     pub fn new(sent_by_me: bool, text: String, status: MessageStatus) -> Self {
         Self {
             sent_by_me,
+            author_name: None,
             text,
             status,
+            seen_by: 0,
+            received_by: None,
+            upload_progress: None,
+            latencies: Vec::new(),
+            preview: None,
+            density: Density::Cozy,
+            grouped: false,
             binding: U8Binding::new(0),
         }
     }
+
+    /// Show how many peers have read the message
+    pub fn with_seen_by(mut self, seen_by: usize) -> Self {
+        self.seen_by = seen_by;
+        self
+    }
+
+    /// Show how many of the other peers confirmed receiving the message
+    pub fn with_received_by(mut self, received_by: usize, other_peers: usize) -> Self {
+        self.received_by = Some((received_by, other_peers));
+        self
+    }
+
+    pub fn with_upload_progress(mut self, accepted: usize, total: usize) -> Self {
+        self.upload_progress = Some((accepted, total));
+        self
+    }
+
+    pub fn seen_by(&self) -> usize {
+        self.seen_by
+    }
+
+    /// Show how long the message took to be accepted and seen when hovering the status icon
+    pub fn with_latencies(mut self, latencies: Vec<MessageLatency>) -> Self {
+        self.latencies = latencies;
+        self
+    }
+
+    /// Show a received image below the text
+    pub fn with_preview(mut self, thumbnail: Thumbnail, path: PathBuf) -> Self {
+        self.preview = Some((thumbnail, path));
+        self
+    }
+
+    /// Show the peer's label or fingerprint instead of "SOMEONE"
+    pub fn with_author_name(mut self, author_name: String) -> Self {
+        self.author_name = Some(author_name);
+        self
+    }
+
+    /// Lay the message out for `density`, `previous` is the message shown above it
+    pub fn with_density(mut self, density: Density, previous: Option<&MessageListItem>) -> Self {
+        self.density = density;
+        self.grouped = density == Density::Compact
+            && self.status != MessageStatus::Notice
+            && previous.is_some_and(|previous| previous.status != MessageStatus::Notice
+                && previous.sent_by_me == self.sent_by_me
+                && previous.author_name == self.author_name);
+        self
+    }
+
+    fn has_warning(&self) -> bool {
+        matches!(self.status, MessageStatus::SignatureInvalid | MessageStatus::MessagePending | MessageStatus::MessageError)
+    }
 }
 
 pub struct MessageWidgets {
     author: gtk::Label,
     text: gtk::Label,
+    preview: gtk::Picture,
+    /// the file of the bound item, read when the preview is clicked
+    preview_path: Rc<RefCell<Option<PathBuf>>>,
+    seen: gtk::Label,
     status: gtk::Image,
 }
 
@@ -58,13 +149,30 @@ impl RelmListItem for MessageListItem {
                     set_halign: gtk::Align::Start,
                     set_valign: gtk::Align::Start,
                 },
-                #[name(text)]
-                gtk::Label {
+                gtk::Box {
+                    set_orientation: gtk::Orientation::Vertical,
+                    set_spacing: 6,
                     set_hexpand: true,
-                    set_wrap: true,
-                    set_wrap_mode: gtk::pango::WrapMode::WordChar,
-                    set_halign: gtk::Align::Start,
                     set_valign: gtk::Align::Center,
+
+                    #[name(text)]
+                    gtk::Label {
+                        set_wrap: true,
+                        set_wrap_mode: gtk::pango::WrapMode::WordChar,
+                        set_halign: gtk::Align::Start,
+                    },
+                    #[name(preview)]
+                    gtk::Picture {
+                        set_halign: gtk::Align::Start,
+                        set_can_shrink: false,
+                        set_tooltip_text: Some(PREVIEW_TOOLTIP),
+                        set_cursor_from_name: Some("pointer"),
+                    },
+                },
+                #[name(seen)]
+                gtk::Label {
+                    set_valign: gtk::Align::End,
+                    add_css_class: "dim-label",
                 },
                 #[name(status)]
                 gtk::Image {
@@ -74,35 +182,122 @@ impl RelmListItem for MessageListItem {
 
         }
 
+        let preview_path: Rc<RefCell<Option<PathBuf>>> = Rc::new(RefCell::new(None));
+        let click = gtk::GestureClick::new();
+        let clicked_path = preview_path.clone();
+        click.connect_released(move |gesture, _, _, _| {
+            let Some(path) = clicked_path.borrow().clone()
+            else {
+                return;
+            };
+            let Some(window) = gesture.widget().root().and_downcast::<gtk::Window>()
+            else {
+                return;
+            };
+            relm4::spawn_local(async move {
+                if let Err(e) = super::file_dialogs::open_path(&window, &path).await {
+                    warn!("Could not open {}: {}", path.display(), e);
+                }
+            });
+        });
+        preview.add_controller(click);
+
         let widgets = Self::Widgets {
             author,
             text,
+            preview,
+            preview_path,
+            seen,
             status,
         };
 
         (hbox, widgets)
     }
 
-    fn bind(&mut self, widgets: &mut Self::Widgets, _root: &mut Self::Root) {
+    fn bind(&mut self, widgets: &mut Self::Widgets, root: &mut Self::Root) {
         let Self::Widgets {
             author,
             text,
+            preview,
+            preview_path,
+            seen,
             status,
         } = widgets;
 
+        let margin = match self.density {
+            Density::Cozy => COZY_MARGIN,
+            Density::Compact => COMPACT_MARGIN,
+        };
+        root.set_margin_top(margin);
+        root.set_margin_bottom(margin);
+        root.set_spacing(if self.density == Density::Compact { 6 } else { 10 });
+
+        // grouped messages keep the space of the author so the texts stay aligned
+        author.set_opacity(if self.grouped { 0.0 } else { 1.0 });
         if self.sent_by_me {
             author.set_text("YOU:")
+        } else if let Some(author_name) = self.author_name.as_ref() {
+            author.set_text(&format!("{}:", author_name))
         } else {
             author.set_text("SOMEONE:")
         }
 
         text.set_text(&self.text);
+        if self.status == MessageStatus::MessagePending {
+            text.add_css_class("dim-label");
+        } else {
+            text.remove_css_class("dim-label");
+        }
 
+        match &self.preview {
+            Some((thumbnail, path)) => {
+                let texture = gtk::gdk::MemoryTexture::new(
+                    thumbnail.width as i32,
+                    thumbnail.height as i32,
+                    gtk::gdk::MemoryFormat::R8g8b8a8,
+                    &gtk::glib::Bytes::from(&thumbnail.rgba[..]),
+                    thumbnail.stride(),
+                );
+                preview.set_paintable(Some(&texture));
+                preview.set_visible(true);
+                preview_path.replace(Some(path.clone()));
+            }
+            None => {
+                preview.set_paintable(None::<&gtk::gdk::Paintable>);
+                preview.set_visible(false);
+                preview_path.replace(None);
+            }
+        }
+
+        if let Some((accepted, total)) = self.upload_progress {
+            seen.set_visible(true);
+            seen.set_text(&format!("uploading {} of {}", accepted, total));
+        } else {
+            let mut counters = Vec::new();
+            if let Some((received_by, other_peers)) = self.received_by {
+                counters.push(format!("received by {} of {}", received_by, other_peers));
+            }
+            if self.seen_by > 0 {
+                counters.push(format!("seen by {}", self.seen_by));
+            }
+            seen.set_visible(!counters.is_empty());
+            seen.set_text(&counters.join(", "));
+        }
+
+        status.set_visible(self.density == Density::Cozy || self.has_warning());
+        if self.latencies.is_empty() {
+            status.set_tooltip_text(None);
+        } else {
+            let latencies = self.latencies.iter().map(|latency| latency.to_string()).collect::<Vec<_>>();
+            status.set_tooltip_text(Some(&latencies.join(", ")));
+        }
         match self.status {
             MessageStatus::SignatureValid => status.set_from_icon_name(Some("security-high")),
             MessageStatus::SignatureInvalid => status.set_from_icon_name(Some("security-low")),
+            MessageStatus::MessagePending => status.set_from_icon_name(Some("content-loading-symbolic")),
             MessageStatus::MessageDelivered => status.set_from_icon_name(Some("emblem-ok")),
             MessageStatus::MessageError => status.set_from_icon_name(Some("emblem-unreadable")),
+            MessageStatus::Notice => status.set_from_icon_name(Some("dialog-information")),
         }
     }
 }
@@ -0,0 +1,128 @@
+//! Desktop notifications of the GUI.
+//!
+//! GNotification needs a notification server, which Windows does not have and which is often missing outside
+//! a GNOME session. On Windows native toasts are tried first, under an app id registered for the current user so
+//! they show the name of the client. Every backend that fails falls back to the next one.
+
+use gtk::prelude::*;
+use log::{debug, warn};
+use crate::constants::Result;
+
+pub trait NotificationBackend {
+    fn name(&self) -> &'static str;
+    /// Show a notification, replacing an earlier one with the same `id` where supported
+    fn send(&self, id: &str, title: &str, body: &str) -> Result<()>;
+}
+
+/// Notifications through the notification server of the desktop, clicking one runs `default_action`
+pub struct GioNotifications {
+    default_action: String,
+}
+
+impl GioNotifications {
+    pub fn new(default_action: String) -> GioNotifications {
+        GioNotifications { default_action }
+    }
+}
+
+impl NotificationBackend for GioNotifications {
+    fn name(&self) -> &'static str {
+        "gio"
+    }
+
+    fn send(&self, id: &str, title: &str, body: &str) -> Result<()> {
+        let notification = gtk::gio::Notification::new(title);
+        notification.set_body(Some(body));
+        notification.set_default_action(&self.default_action);
+        relm4::main_application().send_notification(Some(id), &notification);
+        Ok(())
+    }
+}
+
+/// Native toasts of the Windows action center, clicking one activates `default_action` like [`GioNotifications`]
+#[cfg(windows)]
+pub struct WindowsToasts {
+    app_id: String,
+    default_action: String,
+    registered: bool,
+}
+
+#[cfg(windows)]
+impl WindowsToasts {
+    pub fn new(app_id: &str, display_name: &str, default_action: String) -> WindowsToasts {
+        let registered = register_app_id(app_id, display_name)
+            .map_err(|e| warn!("Could not register the app id {} for toasts: {}", app_id, e))
+            .is_ok();
+        WindowsToasts { app_id: app_id.to_string(), default_action, registered }
+    }
+}
+
+/// An app without an installer has no shortcut carrying its app id, Windows only shows its toasts if the id is
+/// listed in the registry
+#[cfg(windows)]
+fn register_app_id(app_id: &str, display_name: &str) -> Result<()> {
+    use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+    let (key, _) = RegKey::predef(HKEY_CURRENT_USER).create_subkey(format!(r"Software\Classes\AppUserModelId\{}", app_id))?;
+    key.set_value("DisplayName", &display_name)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+impl NotificationBackend for WindowsToasts {
+    fn name(&self) -> &'static str {
+        "windows toast"
+    }
+
+    fn send(&self, _id: &str, title: &str, body: &str) -> Result<()> {
+        use tauri_winrt_notification::Toast;
+        if !self.registered {
+            return Err("The app id is not registered".into());
+        }
+        let default_action = self.default_action.clone();
+        Toast::new(&self.app_id)
+            .title(title)
+            .text1(body)
+            .on_activated(move |_| {
+                // called on a thread of the notification platform
+                let default_action = default_action.clone();
+                gtk::glib::MainContext::default().invoke(move || {
+                    let action = default_action.strip_prefix("app.").unwrap_or(&default_action);
+                    relm4::main_application().activate_action(action, None);
+                });
+                Ok(())
+            })
+            .show()
+            .map_err(|e| format!("Could not show a toast: {:?}", e))?;
+        Ok(())
+    }
+}
+
+/// The backends available on this platform, tried in order
+pub struct Notifier {
+    backends: Vec<Box<dyn NotificationBackend>>,
+}
+
+impl Notifier {
+    /// `app_id` and `display_name` are only used for the Windows toasts
+    pub fn new(app_id: &str, display_name: &str, default_action: String) -> Notifier {
+        let mut backends: Vec<Box<dyn NotificationBackend>> = Vec::new();
+        #[cfg(windows)]
+        backends.push(Box::new(WindowsToasts::new(app_id, display_name, default_action.clone())));
+        #[cfg(not(windows))]
+        let _ = (app_id, display_name);
+        backends.push(Box::new(GioNotifications::new(default_action)));
+        Notifier { backends }
+    }
+
+    pub fn send(&self, id: &str, title: &str, body: &str) {
+        for backend in &self.backends {
+            match backend.send(id, title, body) {
+                Ok(()) => {
+                    debug!("Sent notification {} with the {} backend", id, backend.name());
+                    return;
+                },
+                Err(e) => warn!("Notification backend {} failed, trying the next one: {}", backend.name(), e),
+            }
+        }
+    }
+}
@@ -1,110 +1,190 @@
+use std::time::Duration;
+
 use gtk::prelude::*;
-use log::debug;
+use log::{debug, warn};
 use relm4::factory::FactoryHashMap;
+use bytes::Bytes;
 use relm4::*;
 use crate::constants::{
-    ConferenceId, NumberOfPeers, MessageID,
+    ConferenceId, NumberOfPeers, MessageID, MessageLatency, Peer, Breakout, Migration, SetupStage,
 };
+use crate::anonymity::Anonymity;
+use crate::config::Density;
+use crate::file_transfer::{FileTransfer, ReceivedFile};
+use crate::history_store::{HistoryEntry, RetentionPolicy};
 use crate::gtk_ui::conference_widget_factory::{ConferenceInput, ConferenceOutput};
 use crate::gtk_ui::{
     constants::GUIAction,
     create_conference_frame::CreateConferenceFrame,
-    join_conference_frame::JoinConferenceFrame,
+    join_conference_frame::{JoinConferenceFrame, JoinConferenceInput},
+    browse_conferences_frame::{BrowseConferencesFrame, BrowseConferencesInput},
     conference_widget_factory::Conference,
+    layout::Layout,
 };
 
 const ADD_CONFERENCE_PAGE: &str = "add_conference_page";
 const ADD_CONFERENCE_PAGE_TEXT: &str = "Add Conference";
+const BROWSE_CONFERENCES_PAGE: &str = "browse_conferences_page";
+const BROWSE_CONFERENCES_PAGE_TEXT: &str = "Browse";
+/// Width of the conference list until the user moves the divider
+const DEFAULT_SIDEBAR_WIDTH: i32 = 200;
 
 pub struct StackWidgets {
     create_conference_frame: Controller<CreateConferenceFrame>,
     join_conference_frame: Controller<JoinConferenceFrame>,
+    browse_conferences_frame: Controller<BrowseConferencesFrame>,
     conferences: FactoryHashMap<String, Conference>,
+    /// messages kept per conference
+    max_messages: usize,
+    density: Density,
+    /// conferences are left when idle, see [`crate::idle`]
+    auto_leave: bool,
+    /// saved once the window is closed
+    layout: Layout,
 }
 
 #[derive(Debug)]
 pub enum StackAction {
-    NewConference((ConferenceId, NumberOfPeers)),
+    /// with the parent conference and topic for breakouts, and the server when connected to several
+    NewConference((ConferenceId, NumberOfPeers, Option<(ConferenceId, String)>, Option<String>)),
     RemoveConference(ConferenceId),
     ChangedPage,
-    IncomingMessage((ConferenceId, Vec<u8>, bool)),
+    IncomingMessages(Vec<(ConferenceId, Bytes, bool, Peer)>),
     MessageAccepted((ConferenceId, MessageID)),
     MessageRejected((ConferenceId, MessageID)),
     MessageError((ConferenceId, MessageID)),
+    MessageTimedOut((ConferenceId, MessageID)),
+    MessageTooLarge((ConferenceId, MessageID, u32)),
+    UploadProgress((ConferenceId, MessageID, usize, usize)),
+    FileTransferProgress((ConferenceId, FileTransfer)),
+    FileReceived((ConferenceId, ReceivedFile)),
+    FileNotSent((ConferenceId, MessageID, String)),
     ConferenceRestructuring((ConferenceId, NumberOfPeers)),
+    /// peers that joined and left
+    PeersChanged((ConferenceId, NumberOfPeers, NumberOfPeers)),
     ConferenceRestructuringFinished(ConferenceId),
+    SetupProgress((ConferenceId, SetupStage)),
+    ConferenceRenegotiating(ConferenceId),
+    MessageSeen((ConferenceId, MessageID, usize)),
+    MessageReceivedBy((ConferenceId, MessageID, usize, usize)),
+    MessageLatency((ConferenceId, MessageID, MessageLatency)),
+    ConferenceDirectory(Option<Vec<(ConferenceId, NumberOfPeers)>>),
+    /// Show the browse page only if the server keeps a directory
+    DirectoryAvailable(bool),
+    SenderMuted((ConferenceId, Peer)),
+    SenderUnmuted((ConferenceId, Peer, usize)),
+    BreakoutAnnounced((ConferenceId, Breakout, Peer)),
+    MigrationAnnounced((ConferenceId, Migration, Peer)),
+    OwnFingerprint((ConferenceId, String)),
+    Anonymity((ConferenceId, Anonymity)),
+    ConferenceIdle((ConferenceId, Duration)),
+    HistoryLoaded((ConferenceId, Vec<HistoryEntry>)),
+    RetentionPolicy((ConferenceId, RetentionPolicy)),
+    /// The divider between the conference list and the conference was moved
+    SidebarResized(i32),
+    /// Suggest these conferences in the join form, most recently used first
+    RecentConferences(Vec<ConferenceId>),
     ClearConferences,
 }
 
 #[relm4::component(pub)]
 impl Component for StackWidgets {
     type CommandOutput = ();
-    type Init = ();
+    /// messages kept per conference, the density of the message lists, the recent conferences and whether idle conferences are left
+    type Init = (usize, Density, Vec<ConferenceId>, bool);
     type Input = StackAction;
     type Output = GUIAction;
 
     view! {
         #[root]
-        gtk::Box {
-            set_orientation: gtk::Orientation::Vertical,
-            set_spacing: 0,
-            set_halign: gtk::Align::Fill,
+        gtk::Paned {
+            set_orientation: gtk::Orientation::Horizontal,
+            set_vexpand: true,
+            set_position: model.layout.sidebar_width.unwrap_or(DEFAULT_SIDEBAR_WIDTH),
+            set_resize_start_child: false,
+            set_shrink_start_child: false,
+            set_shrink_end_child: false,
+            connect_position_notify[sender] => move |paned| {
+                sender.input(StackAction::SidebarResized(paned.position()));
+            },
 
-            #[name="stack_switcher"]
-            gtk::StackSwitcher {
-                set_halign: gtk::Align::Start,
-                set_hexpand: true,
-                set_stack = Some(stack_widget),
+            #[wrap(Some)]
+            set_start_child = &gtk::StackSidebar {
+                set_stack: stack_widget,
             },
-            #[local_ref]
-            stack_widget -> gtk::Stack {
-                set_transition_type: gtk::StackTransitionType::None,
-                set_vexpand: true,
-                set_hexpand: true,
-                set_valign: gtk::Align::Fill,
-                connect_visible_child_notify => StackAction::ChangedPage,
+            #[wrap(Some)]
+            set_end_child = &gtk::Box {
+                #[local_ref]
+                stack_widget -> gtk::Stack {
+                    set_transition_type: gtk::StackTransitionType::None,
+                    set_vexpand: true,
+                    set_hexpand: true,
+                    set_valign: gtk::Align::Fill,
+                    connect_visible_child_notify => StackAction::ChangedPage,
 
-                // Add conference page
-                add_titled[Some(ADD_CONFERENCE_PAGE), ADD_CONFERENCE_PAGE_TEXT] = &gtk::Box {
-                    set_orientation: gtk::Orientation::Vertical,
-                    set_valign: gtk::Align::Center,
-                    set_spacing: 60,
+                    // Add conference page
+                    add_titled[Some(ADD_CONFERENCE_PAGE), ADD_CONFERENCE_PAGE_TEXT] = &gtk::Box {
+                        set_orientation: gtk::Orientation::Vertical,
+                        set_valign: gtk::Align::Center,
+                        set_spacing: 60,
 
-                    model.create_conference_frame.widget(),
-                    model.join_conference_frame.widget(),
-                },
-            }
+                        model.create_conference_frame.widget(),
+                        model.join_conference_frame.widget(),
+                    },
+
+                    // Public conferences page
+                    add_titled[Some(BROWSE_CONFERENCES_PAGE), BROWSE_CONFERENCES_PAGE_TEXT] = model.browse_conferences_frame.widget(),
+                }
+            },
         }
     }
 
     fn init(
-        _params: Self::Init,
+        (max_messages, density, recent_conferences, auto_leave): Self::Init,
         root: Self::Root,
         sender: ComponentSender<Self>,
     ) -> ComponentParts<Self> {
         let create_conference_frame = CreateConferenceFrame::builder().launch(()).forward(sender.output_sender(), |x| x);
-        let join_conference_frame = JoinConferenceFrame::builder().launch(()).forward(sender.output_sender(), |x| x);
+        let join_conference_frame = JoinConferenceFrame::builder().launch(recent_conferences).forward(sender.output_sender(), |x| x);
+        let browse_conferences_frame = BrowseConferencesFrame::builder().launch(()).forward(sender.output_sender(), |x| x);
         let conferences_stack = FactoryHashMap::builder()
             .launch_default()
             .forward(sender.output_sender(), |x| match x {
                 ConferenceOutput::SendMessage((conference_id, message_id, message)) => GUIAction::SendMessage((conference_id, message_id, message)),
+                ConferenceOutput::SendFile((conference_id, message_id, path)) => GUIAction::SendFile((conference_id, message_id, path)),
                 ConferenceOutput::LeaveConference(conference_id) => GUIAction::Leave(conference_id),
+                ConferenceOutput::SetPeerLabel((key_image, label)) => GUIAction::SetPeerLabel((key_image, label)),
+                ConferenceOutput::SetReadReceipts((conference_id, enabled)) => GUIAction::SetReadReceipts((conference_id, enabled)),
+                ConferenceOutput::SetUnlinkable((conference_id, enabled)) => GUIAction::SetUnlinkable((conference_id, enabled)),
+                ConferenceOutput::SetListenOnly((conference_id, enabled)) => GUIAction::SetListenOnly((conference_id, enabled)),
+                ConferenceOutput::SetAutoLeave((conference_id, enabled)) => GUIAction::SetAutoLeave((conference_id, enabled)),
+                ConferenceOutput::SetRetentionPolicy((conference_id, policy)) => GUIAction::SetRetentionPolicy((conference_id, policy)),
+                ConferenceOutput::AllowFlooding((conference_id, key_image)) => GUIAction::AllowFlooding((conference_id, key_image)),
+                ConferenceOutput::CreateBreakout((conference_id, topic)) => GUIAction::CreateBreakout((conference_id, topic)),
+                ConferenceOutput::JoinBreakout((conference_id, breakout)) => GUIAction::JoinBreakout((conference_id, breakout)),
+                ConferenceOutput::Migrate((conference_id, server_address)) => GUIAction::Migrate((conference_id, server_address)),
+                ConferenceOutput::FollowMigration((conference_id, migration)) => GUIAction::FollowMigration((conference_id, migration)),
             });
         let model = StackWidgets {
             create_conference_frame,
             join_conference_frame,
+            browse_conferences_frame,
             conferences: conferences_stack,
+            max_messages,
+            density,
+            auto_leave,
+            layout: Layout::load(),
         };
         let stack_widget = model.conferences.widget();
         let widgets = view_output!();
         ComponentParts { model, widgets }
     }
 
-    fn update(&mut self, msg: Self::Input, _sender: ComponentSender<Self>, _root: &Self::Root) {
+    fn update(&mut self, msg: Self::Input, sender: ComponentSender<Self>, _root: &Self::Root) {
         match msg {
-            StackAction::NewConference((conference_id, number_of_peers)) => {
+            StackAction::NewConference((conference_id, number_of_peers, parent, server)) => {
                 debug!("Added new conference with id: {}", conference_id);
-                self.conferences.insert(conference_id.to_string(), (conference_id, number_of_peers));
+                self.conferences.insert(conference_id.to_string(), (conference_id, number_of_peers, parent, server, self.max_messages, self.density, self.auto_leave));
             }
             StackAction::RemoveConference(conference_id) => {
                 debug!("Removed conference with id: {}", conference_id);
@@ -115,12 +195,32 @@ impl Component for StackWidgets {
             }
             StackAction::ChangedPage => {
                 debug!("Changed page");
+                if self.conferences.widget().visible_child_name().is_some_and(|name| name == BROWSE_CONFERENCES_PAGE) {
+                    sender.output(GUIAction::ListConferences).unwrap();
+                }
+                if let Some(conference_id) = self.focused_conference() {
+                    sender.output(GUIAction::MessagesDisplayed(conference_id)).unwrap();
+                }
             }
-            StackAction::IncomingMessage((conference_id, message, signature_valid)) => {
-                debug!("Incoming message: {}", conference_id);
-                let conference_id_string = conference_id.to_string();
-                if self.conferences.keys().any(|x| x == &conference_id_string) {
-                    self.conferences.send(&conference_id_string, ConferenceInput::IncomingMessage((message, signature_valid)));
+            StackAction::IncomingMessages(messages) => {
+                debug!("Incoming messages: {}", messages.len());
+                // group by conference, keeping the order within each conference
+                let mut conference_messages: Vec<(ConferenceId, Vec<(Vec<u8>, bool, Peer)>)> = Vec::new();
+                for (conference_id, message, signature_valid, peer) in messages {
+                    match conference_messages.iter_mut().find(|(id, _)| *id == conference_id) {
+                        Some((_, messages)) => messages.push((message, signature_valid, peer)),
+                        None => conference_messages.push((conference_id, vec![(message, signature_valid, peer)])),
+                    }
+                }
+                let focused_conference = self.focused_conference();
+                for (conference_id, messages) in conference_messages {
+                    let conference_id_string = conference_id.to_string();
+                    if self.conferences.keys().any(|x| x == &conference_id_string) {
+                        self.conferences.send(&conference_id_string, ConferenceInput::IncomingMessages(messages));
+                        if focused_conference == Some(conference_id) {
+                            sender.output(GUIAction::MessagesDisplayed(conference_id)).unwrap();
+                        }
+                    }
                 }
             }
             StackAction::MessageAccepted((conference_id, message_id)) => {
@@ -144,6 +244,44 @@ impl Component for StackWidgets {
                     self.conferences.send(&conference_id_string, ConferenceInput::MessageError(message_id));
                 }
             }
+            StackAction::MessageTimedOut((conference_id, message_id)) => {
+                debug!("Message timed out: {}", conference_id);
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::MessageTimedOut(message_id));
+                }
+            }
+            StackAction::MessageTooLarge((conference_id, message_id, max_message_size)) => {
+                debug!("Message too large: {}", conference_id);
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::MessageTooLarge((message_id, max_message_size)));
+                }
+            }
+            StackAction::UploadProgress((conference_id, message_id, accepted, total)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::UploadProgress((message_id, accepted, total)));
+                }
+            }
+            StackAction::FileTransferProgress((conference_id, transfer)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::FileTransferProgress(transfer));
+                }
+            }
+            StackAction::FileReceived((conference_id, file)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::FileReceived(file));
+                }
+            }
+            StackAction::FileNotSent((conference_id, message_id, reason)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::FileNotSent((message_id, reason)));
+                }
+            }
             StackAction::ConferenceRestructuring((conference_id, number_of_peers)) => {
                 debug!("Conference restructuring: {}", conference_id);
                 let conference_id_string = conference_id.to_string();
@@ -151,6 +289,12 @@ impl Component for StackWidgets {
                     self.conferences.send(&conference_id_string, ConferenceInput::ConferenceRestructuring(number_of_peers));
                 }
             }
+            StackAction::PeersChanged((conference_id, joined, left)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::PeersChanged((joined, left)));
+                }
+            }
             StackAction::ConferenceRestructuringFinished(conference_id) => {
                 debug!("Conference restructuring finished: {}", conference_id);
                 let conference_id_string = conference_id.to_string();
@@ -158,10 +302,128 @@ impl Component for StackWidgets {
                     self.conferences.send(&conference_id_string, ConferenceInput::ConferenceRestructuringFinished);
                 }
             }
+            StackAction::SetupProgress((conference_id, stage)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::SetupProgress(stage));
+                }
+            }
+            StackAction::ConferenceRenegotiating(conference_id) => {
+                debug!("Conference renegotiating: {}", conference_id);
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::ConferenceRenegotiating);
+                }
+            }
+            StackAction::MessageSeen((conference_id, message_id, seen_by)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::MessageSeen((message_id, seen_by)));
+                }
+            }
+            StackAction::MessageReceivedBy((conference_id, message_id, received_by, other_peers)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::MessageReceivedBy((message_id, received_by, other_peers)));
+                }
+            }
+            StackAction::MessageLatency((conference_id, message_id, latency)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::MessageLatency((message_id, latency)));
+                }
+            }
+            StackAction::SenderMuted((conference_id, peer)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::SenderMuted(peer));
+                }
+            }
+            StackAction::SenderUnmuted((conference_id, peer, suppressed)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::SenderUnmuted((peer, suppressed)));
+                }
+            }
+            StackAction::BreakoutAnnounced((conference_id, breakout, peer)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::BreakoutAnnounced((breakout, peer)));
+                }
+            }
+            StackAction::MigrationAnnounced((conference_id, migration, peer)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::MigrationAnnounced((migration, peer)));
+                }
+            }
+            StackAction::OwnFingerprint((conference_id, fingerprint)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::OwnFingerprint(fingerprint));
+                }
+            }
+            StackAction::Anonymity((conference_id, anonymity)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::Anonymity(anonymity));
+                }
+            }
+            StackAction::ConferenceDirectory(directory) => {
+                debug!("Conference directory: {:?}", directory);
+                self.browse_conferences_frame.emit(BrowseConferencesInput::Directory(directory));
+            }
+            StackAction::DirectoryAvailable(available) => {
+                let stack = self.conferences.widget();
+                if let Some(browse_page) = stack.child_by_name(BROWSE_CONFERENCES_PAGE) {
+                    stack.page(&browse_page).set_visible(available);
+                }
+            }
+            StackAction::RecentConferences(recent_conferences) => {
+                self.join_conference_frame.emit(JoinConferenceInput::RecentConferences(recent_conferences));
+            }
+            StackAction::ConferenceIdle((conference_id, remaining)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::ConferenceIdle(remaining));
+                }
+            }
+            StackAction::HistoryLoaded((conference_id, entries)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::HistoryLoaded(entries));
+                }
+            }
+            StackAction::RetentionPolicy((conference_id, policy)) => {
+                let conference_id_string = conference_id.to_string();
+                if self.conferences.keys().any(|x| x == &conference_id_string) {
+                    self.conferences.send(&conference_id_string, ConferenceInput::RetentionPolicy(policy));
+                }
+            }
+            StackAction::SidebarResized(width) => {
+                self.layout.sidebar_width = Some(width);
+            }
             StackAction::ClearConferences => {
                 debug!("Clearing all conferences");
                 self.conferences.clear();
             }
         }
     }
+
+    fn shutdown(&mut self, _widgets: &mut Self::Widgets, _output: relm4::Sender<Self::Output>) {
+        if let Err(e) = self.layout.save() {
+            warn!("Could not save the window layout: {}", e);
+        }
+    }
+}
+
+impl StackWidgets {
+    /// The conference that is visible in the active window
+    fn focused_conference(&self) -> Option<ConferenceId> {
+        let window_active = relm4::main_application().active_window().is_some_and(|window| window.is_active());
+        if !window_active {
+            return None;
+        }
+        self.conferences.widget().visible_child_name()?.parse().ok()
+    }
 }
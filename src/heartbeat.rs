@@ -0,0 +1,110 @@
+//! Detection of a dead connection to a server with [`SERVER_CAPABILITY_HEARTBEAT`].
+//! A silent server can not be told apart from a dead TCP connection, which may take minutes to fail on
+//! its own, so the connection manager pings the server on an interval and gives up on the connection
+//! once several pings in a row stayed unanswered.
+//!
+//! [`SERVER_CAPABILITY_HEARTBEAT`]: crate::constants::SERVER_CAPABILITY_HEARTBEAT
+
+use std::time::{Duration, Instant};
+
+use crate::constants::ClientEvent;
+
+/// Time between two pings
+pub const PING_INTERVAL: Duration = Duration::from_secs(15);
+/// The connection is dead after this many pings in a row were not answered before the next one was due
+pub const MAX_MISSED_PONGS: u32 = 3;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Beat {
+    /// Send a ping with this number
+    Ping(u32),
+    /// Too many pings were not answered
+    Dead,
+}
+
+pub struct Heartbeat {
+    next_ping: Instant,
+    last_ping: u32,
+    answered: bool,
+    missed: u32,
+}
+
+impl Heartbeat {
+    pub fn new(now: Instant) -> Self {
+        Heartbeat { next_ping: now + PING_INTERVAL, last_ping: 0, answered: true, missed: 0 }
+    }
+
+    pub fn next_ping(&self) -> Instant {
+        self.next_ping
+    }
+
+    /// Returns what to do once the next ping is due, `None` before
+    pub fn tick(&mut self, now: Instant) -> Option<Beat> {
+        if now < self.next_ping {
+            return None;
+        }
+        if !self.answered {
+            self.missed += 1;
+            if self.missed >= MAX_MISSED_PONGS {
+                return Some(Beat::Dead);
+            }
+        }
+        self.last_ping = self.last_ping.wrapping_add(1);
+        self.answered = false;
+        self.next_ping = now + PING_INTERVAL;
+        Some(Beat::Ping(self.last_ping))
+    }
+
+    /// A late pong for an older ping still shows that the connection is alive
+    pub fn pong(&mut self, number: u32) {
+        if number == self.last_ping {
+            self.answered = true;
+        }
+        self.missed = 0;
+    }
+}
+
+/// Whether a recorded outbound frame is a ping or pong, which the connection manager sends on its own
+pub fn is_keepalive_frame(frame: &[u8]) -> bool {
+    frame.first().is_some_and(|&event_type| event_type == ClientEvent::Ping(0).value() || event_type == ClientEvent::Pong(0).value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answered_pings() {
+        let start = Instant::now();
+        let mut heartbeat = Heartbeat::new(start);
+        assert_eq!(heartbeat.tick(start), None);
+        for round in 1..=MAX_MISSED_PONGS * 2 {
+            let now = heartbeat.next_ping();
+            assert_eq!(heartbeat.tick(now), Some(Beat::Ping(round)));
+            heartbeat.pong(round);
+        }
+        assert_eq!(heartbeat.next_ping(), start + PING_INTERVAL * (MAX_MISSED_PONGS * 2 + 1));
+    }
+
+    #[test]
+    fn test_missed_pongs() {
+        let mut heartbeat = Heartbeat::new(Instant::now());
+        for round in 1..=MAX_MISSED_PONGS {
+            assert_eq!(heartbeat.tick(heartbeat.next_ping()), Some(Beat::Ping(round)));
+        }
+        // a late pong for the first ping resets the count
+        heartbeat.pong(1);
+        for _ in 1..MAX_MISSED_PONGS {
+            assert!(matches!(heartbeat.tick(heartbeat.next_ping()), Some(Beat::Ping(_))));
+        }
+        assert_eq!(heartbeat.tick(heartbeat.next_ping()), Some(Beat::Dead));
+    }
+
+    #[test]
+    fn test_keepalive_frames() {
+        assert!(is_keepalive_frame(&[0x0A, 0, 0, 0, 1]));
+        assert!(is_keepalive_frame(&[0x0B, 0, 0, 0, 1]));
+        assert!(!is_keepalive_frame(&[0x06]));
+        assert!(!is_keepalive_frame(&[]));
+    }
+}
@@ -0,0 +1,45 @@
+//! Bounds on the in-memory message history of the UIs.
+//!
+//! Long sessions would otherwise keep every message in memory, the history of a conference
+//! is capped at [`HistoryConfig::max_messages`](crate::config::HistoryConfig) and the oldest messages are dropped first.
+
+use std::collections::HashMap;
+
+use crate::constants::MessageID;
+
+/// Remove the oldest messages until at most `max` are left.
+/// Message IDs only grow within a session, so the smallest IDs are the oldest.
+///
+/// Only for the details of finished messages: own messages waiting for the server are
+/// never evicted, they leave the `sent_messages` of the UIs once their status is final.
+pub fn evict_oldest<T>(messages: &mut HashMap<MessageID, T>, max: usize) {
+    if messages.len() <= max {
+        return;
+    }
+    let mut ids: Vec<MessageID> = messages.keys().copied().collect();
+    let excess = ids.len() - max;
+    ids.select_nth_unstable(excess - 1);
+    for id in &ids[..excess] {
+        messages.remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evict_oldest() {
+        let mut messages: HashMap<MessageID, &str> = (1..=5).map(|id| (id, "message")).collect();
+        evict_oldest(&mut messages, 5);
+        assert_eq!(messages.len(), 5);
+        evict_oldest(&mut messages, 2);
+        let mut left: Vec<MessageID> = messages.into_keys().collect();
+        left.sort();
+        assert_eq!(left, vec![4, 5]);
+
+        let mut messages: HashMap<MessageID, &str> = HashMap::from([(1, "message")]);
+        evict_oldest(&mut messages, 0);
+        assert!(messages.is_empty());
+    }
+}
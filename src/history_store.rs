@@ -0,0 +1,539 @@
+//! Messages of a conference kept on disk, shown again when the conference is joined in a later session.
+//!
+//! Every conference has its own append-only log in the `history` directory of the data directory. Each entry is
+//! encrypted on its own with ChaCha20-Poly1305 under a key derived from the initial encryption key of the conference,
+//! which is derived from the conference password and the salt of the server, so only someone who knows the password
+//! can read the log. The file name is derived from the same key, it does not reveal the conference id and a
+//! conference id the server reused for another conference gets another log.
+//!
+//! The log is `magic | version` followed by entries of `length as u32 | iv | ciphertext`. An entry cut off by a
+//! crash while appending is removed when the log is opened again.
+//!
+//! How long the history is kept is chosen per conference with a [`RetentionPolicy`], stored encrypted as the first
+//! entry of the log unless it is the default. Old messages are removed whenever the log is opened, when the policy
+//! changes and when a message is appended after the oldest one expired.
+
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+use sha2::{Digest, Sha256};
+use crate::{
+    constants::{EncryptionKey, KeyImage, Peer, Result},
+    crypto::{self, EncryptionResult},
+    paths,
+};
+
+pub const HISTORY_DIR_NAME: &str = "history";
+const HISTORY_MAGIC: &[u8; 8] = b"ACCHISTO";
+const HISTORY_VERSION: u8 = 1;
+const HEADER_SIZE: usize = HISTORY_MAGIC.len() + 1;
+const KEY_CONTEXT: &[u8] = b"anonymous-conference history key";
+const FILE_NAME_CONTEXT: &[u8] = b"anonymous-conference history file";
+/// Time, flags and key image before the text of an entry
+const ENTRY_HEADER_SIZE: usize = 8 + 1 + 32;
+const FLAG_OWN: u8 = 0x01;
+const FLAG_SIGNATURE_VALID: u8 = 0x02;
+/// The entry holds the retention policy as its text instead of a message
+const FLAG_POLICY: u8 = 0x04;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// How long the messages of a conference are kept on disk
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    /// Every message is kept
+    #[default]
+    Forever,
+    /// No message is stored, the stored ones are removed.
+    /// Not kept in the log, that would leave it behind, the state manager applies it again until the client exits
+    Never,
+    /// Messages are removed after this many days
+    Days(u32),
+    /// The history is removed once the conference is left
+    UntilLeave,
+}
+
+impl RetentionPolicy {
+    /// A sentence for the UIs
+    pub fn description(&self) -> String {
+        match self {
+            RetentionPolicy::Forever => "Messages are kept on this device".to_string(),
+            RetentionPolicy::Never => "Messages are not stored until the client exits".to_string(),
+            RetentionPolicy::Days(days) => format!("Messages are kept on this device for {} days", days),
+            RetentionPolicy::UntilLeave => "Messages are kept on this device until you leave the conference".to_string(),
+        }
+    }
+}
+
+/// `forever`, `never`, `until-leave` or the number of days like `30d`
+impl fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetentionPolicy::Forever => write!(f, "forever"),
+            RetentionPolicy::Never => write!(f, "never"),
+            RetentionPolicy::Days(days) => write!(f, "{}d", days),
+            RetentionPolicy::UntilLeave => write!(f, "until-leave"),
+        }
+    }
+}
+
+impl FromStr for RetentionPolicy {
+    type Err = String;
+
+    /// The format of [`RetentionPolicy::fmt`], the `d` after the days may be left out
+    fn from_str(policy: &str) -> std::result::Result<Self, Self::Err> {
+        match policy.trim() {
+            "forever" => Ok(RetentionPolicy::Forever),
+            "never" => Ok(RetentionPolicy::Never),
+            "until-leave" => Ok(RetentionPolicy::UntilLeave),
+            days => match days.strip_suffix('d').unwrap_or(days).parse() {
+                Ok(0) | Err(_) => Err(format!("Unknown retention policy {:?}, expected forever, never, until-leave or a number of days", policy)),
+                Ok(days) => Ok(RetentionPolicy::Days(days)),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// When the message was shown or sent, stored in milliseconds
+    pub time: SystemTime,
+    /// `None` for own messages, labels are not stored
+    pub sender: Option<Peer>,
+    pub is_signature_valid: bool,
+    pub text: String,
+}
+
+impl HistoryEntry {
+    /// `milliseconds since the epoch as u64 | flags | key image | text`
+    fn encode(&self) -> Vec<u8> {
+        let millis = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let mut flags = 0;
+        if self.sender.is_none() {
+            flags |= FLAG_OWN;
+        }
+        if self.is_signature_valid {
+            flags |= FLAG_SIGNATURE_VALID;
+        }
+        let key_image = self.sender.as_ref().map_or([0; 32], |peer| peer.key_image);
+        let mut result = Vec::with_capacity(ENTRY_HEADER_SIZE + self.text.len());
+        result.extend_from_slice(&millis.to_be_bytes());
+        result.push(flags);
+        result.extend_from_slice(&key_image);
+        result.extend_from_slice(self.text.as_bytes());
+        result
+    }
+
+    /// `None` for the policy, see [`decode_policy`]
+    fn decode(data: &[u8]) -> Option<HistoryEntry> {
+        if data.len() < ENTRY_HEADER_SIZE || data[8] & FLAG_POLICY != 0 {
+            return None;
+        }
+        let millis = u64::from_be_bytes(data[..8].try_into().unwrap());
+        let flags = data[8];
+        let key_image: KeyImage = data[9..ENTRY_HEADER_SIZE].try_into().unwrap();
+        Some(HistoryEntry {
+            time: UNIX_EPOCH + Duration::from_millis(millis),
+            sender: (flags & FLAG_OWN == 0).then_some(Peer { key_image, label: None }),
+            is_signature_valid: flags & FLAG_SIGNATURE_VALID != 0,
+            text: String::from_utf8(data[ENTRY_HEADER_SIZE..].to_vec()).ok()?,
+        })
+    }
+}
+
+/// An entry at the epoch with [`FLAG_POLICY`], no key image and the policy as its text
+fn encode_policy(policy: RetentionPolicy) -> Vec<u8> {
+    let mut result = vec![0; ENTRY_HEADER_SIZE];
+    result[8] = FLAG_POLICY;
+    result.extend_from_slice(policy.to_string().as_bytes());
+    result
+}
+
+fn decode_policy(data: &[u8]) -> Option<RetentionPolicy> {
+    if data.len() < ENTRY_HEADER_SIZE || data[8] & FLAG_POLICY == 0 {
+        return None;
+    }
+    std::str::from_utf8(&data[ENTRY_HEADER_SIZE..]).ok()?.parse().ok()
+}
+
+/// The log of one conference, a clone appends to the same log
+#[derive(Clone)]
+pub struct HistoryStore {
+    path: PathBuf,
+    key: EncryptionKey,
+    policy: RetentionPolicy,
+}
+
+impl HistoryStore {
+    /// The log of the conference with this initial encryption key in `dir`, the file is created on the first append.
+    /// Messages the retention policy does not keep any longer are removed
+    pub fn open(dir: &Path, initial_encryption_key: &EncryptionKey) -> Result<HistoryStore> {
+        let file_name: String = derive(FILE_NAME_CONTEXT, initial_encryption_key)[..16].iter().map(|byte| format!("{:02x}", byte)).collect();
+        let mut store = HistoryStore {
+            path: dir.join(file_name),
+            key: derive(KEY_CONTEXT, initial_encryption_key),
+            policy: RetentionPolicy::default(),
+        };
+        store.remove_incomplete_entry()?;
+        store.policy = store.read_policy()?;
+        store.enforce_policy(SystemTime::now())?;
+        Ok(store)
+    }
+
+    pub fn policy(&self) -> RetentionPolicy {
+        self.policy
+    }
+
+    /// Keep the policy in the log for the next sessions and remove the messages it does not keep.
+    /// [`RetentionPolicy::Never`] removes the whole log
+    pub fn set_policy(&mut self, policy: RetentionPolicy) -> Result<()> {
+        self.policy = policy;
+        if policy == RetentionPolicy::Never {
+            return remove_if_exists(&self.path);
+        }
+        let entries = self.read_all()?;
+        self.rewrite(&entries)?;
+        self.enforce_policy(SystemTime::now())
+    }
+
+    /// The conference was left, the messages are removed with [`RetentionPolicy::UntilLeave`]
+    pub fn left(&self) -> Result<()> {
+        if self.policy == RetentionPolicy::UntilLeave {
+            self.rewrite(&[])?;
+        }
+        Ok(())
+    }
+
+    fn enforce_policy(&self, now: SystemTime) -> Result<()> {
+        match self.policy {
+            RetentionPolicy::Never => remove_if_exists(&self.path),
+            RetentionPolicy::Days(_) => {
+                if let Some(before) = self.expiry(now) {
+                    self.purge(before)?;
+                }
+                Ok(())
+            },
+            RetentionPolicy::Forever | RetentionPolicy::UntilLeave => Ok(()),
+        }
+    }
+
+    /// Messages from before this time are no longer kept with [`RetentionPolicy::Days`]
+    fn expiry(&self, now: SystemTime) -> Option<SystemTime> {
+        match self.policy {
+            // nothing is that old
+            RetentionPolicy::Days(days) => now.checked_sub(Duration::from_secs(days as u64 * SECONDS_PER_DAY)),
+            _ => None,
+        }
+    }
+
+    /// Does nothing with [`RetentionPolicy::Never`], removes the expired messages with [`RetentionPolicy::Days`]
+    pub fn append(&self, entry: &HistoryEntry) -> Result<()> {
+        if self.policy == RetentionPolicy::Never {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        let mut data = Vec::new();
+        if file.metadata()?.len() == 0 {
+            self.encode_header(&mut data)?;
+        }
+        self.encode_entry(entry, &mut data)?;
+        // a single write, so an interrupted append only cuts off this entry
+        file.write_all(&data)?;
+        drop(file);
+        let now = SystemTime::now();
+        if let (Some(before), Some(oldest)) = (self.expiry(now), self.oldest()?) {
+            if oldest < before {
+                self.purge(before)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The entries from `from` until before `to`, oldest first
+    pub fn query(&self, from: SystemTime, to: SystemTime) -> Result<Vec<HistoryEntry>> {
+        let mut entries = self.read_all()?;
+        entries.retain(|entry| entry.time >= from && entry.time < to);
+        Ok(entries)
+    }
+
+    /// The last `count` entries, oldest first
+    pub fn recent(&self, count: usize) -> Result<Vec<HistoryEntry>> {
+        let mut entries = self.read_all()?;
+        entries.drain(..entries.len().saturating_sub(count));
+        Ok(entries)
+    }
+
+    /// Remove the entries from before `before`, returns how many were removed.
+    /// The log is written anew, see [`HistoryStore::rewrite`]
+    pub fn purge(&self, before: SystemTime) -> Result<usize> {
+        let mut entries = self.read_all()?;
+        let count = entries.len();
+        entries.retain(|entry| entry.time >= before);
+        let removed = count - entries.len();
+        if removed > 0 {
+            self.rewrite(&entries)?;
+        }
+        Ok(removed)
+    }
+
+    /// Replace the log with the policy and `entries`, the file is deleted once it would hold neither
+    fn rewrite(&self, entries: &[HistoryEntry]) -> Result<()> {
+        if entries.is_empty() && self.policy == RetentionPolicy::default() {
+            return remove_if_exists(&self.path);
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut data = Vec::new();
+        self.encode_header(&mut data)?;
+        for entry in entries {
+            self.encode_entry(entry, &mut data)?;
+        }
+        // replaced at once, so a crash keeps either the old or the new log
+        let temporary_path = self.path.with_extension("tmp");
+        fs::write(&temporary_path, data)?;
+        fs::rename(&temporary_path, &self.path)?;
+        Ok(())
+    }
+
+    /// `magic | version`, followed by the policy unless it is the default
+    fn encode_header(&self, out: &mut Vec<u8>) -> Result<()> {
+        out.extend_from_slice(HISTORY_MAGIC);
+        out.push(HISTORY_VERSION);
+        if self.policy != RetentionPolicy::default() {
+            self.encrypt_into(&encode_policy(self.policy), out)?;
+        }
+        Ok(())
+    }
+
+    /// `length | iv | ciphertext` of an entry
+    fn encode_entry(&self, entry: &HistoryEntry, out: &mut Vec<u8>) -> Result<()> {
+        self.encrypt_into(&entry.encode(), out)
+    }
+
+    fn encrypt_into(&self, plaintext: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let encrypted = crypto::encrypt_message(plaintext, &self.key).map_err(|_| "Could not encrypt the history entry")?.encode();
+        out.extend_from_slice(&(encrypted.len() as u32).to_be_bytes());
+        out.extend_from_slice(&encrypted);
+        Ok(())
+    }
+
+    fn decrypt(&self, encrypted: &[u8]) -> Option<Vec<u8>> {
+        EncryptionResult::decode(encrypted).ok()
+            .and_then(|encrypted| crypto::decrypt_message(&self.key, &encrypted).ok())
+    }
+
+    /// The policy stored in the log, the default without one
+    fn read_policy(&self) -> Result<RetentionPolicy> {
+        Ok(self.read_records()?.iter().find_map(|record| decode_policy(record)).unwrap_or_default())
+    }
+
+    /// The time of the first message, only the start of the log is read
+    fn oldest(&self) -> Result<Option<SystemTime>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => BufReader::new(file),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut header = [0; HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        let mut length = [0; 4];
+        // the policy comes before the first message
+        while file.read_exact(&mut length).is_ok() {
+            let mut encrypted = vec![0; u32::from_be_bytes(length) as usize];
+            file.read_exact(&mut encrypted)?;
+            if let Some(entry) = self.decrypt(&encrypted).and_then(|record| HistoryEntry::decode(&record)) {
+                return Ok(Some(entry.time));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Cut the log after its last complete entry, so appending continues at an entry boundary
+    fn remove_incomplete_entry(&self) -> Result<()> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        // a log without a complete header is written anew
+        let mut complete = 0;
+        if data.len() >= HEADER_SIZE {
+            complete = HEADER_SIZE;
+            while let Some(length) = data.get(complete..complete + 4).map(|length| u32::from_be_bytes(length.try_into().unwrap()) as usize) {
+                if complete + 4 + length > data.len() {
+                    break;
+                }
+                complete += 4 + length;
+            }
+        }
+        if complete < data.len() {
+            warn!("Removing an incomplete entry at the end of the history log {}", self.path.display());
+            OpenOptions::new().write(true).open(&self.path)?.set_len(complete as u64)?;
+        }
+        Ok(())
+    }
+
+    fn read_all(&self) -> Result<Vec<HistoryEntry>> {
+        Ok(self.read_records()?.iter().filter_map(|record| HistoryEntry::decode(record)).collect())
+    }
+
+    /// The decrypted entries of the log including the policy, unreadable ones are skipped
+    fn read_records(&self) -> Result<Vec<Vec<u8>>> {
+        let data = match fs::read(&self.path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        if !data.starts_with(HISTORY_MAGIC) {
+            return Err(format!("{} is not a history log", self.path.display()).into());
+        }
+        if data.get(HISTORY_MAGIC.len()) != Some(&HISTORY_VERSION) {
+            return Err("Unsupported history version".into());
+        }
+        let mut records = Vec::new();
+        let mut rest = &data[HEADER_SIZE..];
+        while !rest.is_empty() {
+            // incomplete entries are only left by a crash since the log was opened
+            let Some(length) = rest.get(..4).map(|length| u32::from_be_bytes(length.try_into().unwrap()) as usize)
+            else { break };
+            let Some(encrypted) = rest.get(4..4 + length)
+            else { break };
+            rest = &rest[4 + length..];
+            match self.decrypt(encrypted) {
+                Some(record) => records.push(record),
+                None => warn!("Skipped an unreadable entry of the history log {}", self.path.display()),
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// Directory holding the logs of all conferences
+pub fn history_dir() -> PathBuf {
+    paths::data_dir().join(HISTORY_DIR_NAME)
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn derive(context: &[u8], initial_encryption_key: &EncryptionKey) -> EncryptionKey {
+    Sha256::new().chain_update(context).chain_update(initial_encryption_key).finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(millis: u64, sender: Option<Peer>, text: &str) -> HistoryEntry {
+        HistoryEntry { time: UNIX_EPOCH + Duration::from_millis(millis), sender, is_signature_valid: true, text: text.to_string() }
+    }
+
+    fn temporary_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("acc-history-test-{}", rand::random::<u32>()))
+    }
+
+    #[test]
+    fn test_append_query_purge() {
+        let dir = temporary_dir();
+        let store = HistoryStore::open(&dir, &[1; 32]).unwrap();
+        assert!(store.recent(10).unwrap().is_empty());
+        let peer = Peer { key_image: [7; 32], label: None };
+        for (millis, sender) in [(1000, Some(peer.clone())), (2000, None), (3000, Some(peer.clone()))] {
+            store.append(&entry(millis, sender, "hello")).unwrap();
+        }
+        assert_eq!(store.recent(2).unwrap(), vec![entry(2000, None, "hello"), entry(3000, Some(peer.clone()), "hello")]);
+        assert_eq!(store.query(UNIX_EPOCH + Duration::from_secs(1), UNIX_EPOCH + Duration::from_secs(3)).unwrap().len(), 2);
+        // another password can not read the log
+        assert!(HistoryStore::open(&dir, &[2; 32]).unwrap().recent(10).unwrap().is_empty());
+
+        assert_eq!(store.purge(UNIX_EPOCH + Duration::from_secs(2)).unwrap(), 1);
+        assert_eq!(store.recent(10).unwrap().len(), 2);
+        store.append(&entry(4000, None, "again")).unwrap();
+        assert_eq!(store.recent(1).unwrap(), vec![entry(4000, None, "again")]);
+        assert_eq!(store.purge(UNIX_EPOCH + Duration::from_secs(5)).unwrap(), 3);
+        assert!(!store.path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retention_policy() {
+        for policy in [RetentionPolicy::Forever, RetentionPolicy::Never, RetentionPolicy::Days(30), RetentionPolicy::UntilLeave] {
+            assert_eq!(policy.to_string().parse::<RetentionPolicy>(), Ok(policy));
+        }
+        assert_eq!("7".parse::<RetentionPolicy>(), Ok(RetentionPolicy::Days(7)));
+        assert!("0d".parse::<RetentionPolicy>().is_err());
+        assert!("always".parse::<RetentionPolicy>().is_err());
+
+        let dir = temporary_dir();
+        let mut store = HistoryStore::open(&dir, &[1; 32]).unwrap();
+        let now = SystemTime::now();
+        store.append(&entry(1000, None, "old")).unwrap();
+        store.append(&HistoryEntry { time: now, ..entry(0, None, "new") }).unwrap();
+        store.set_policy(RetentionPolicy::Days(1)).unwrap();
+        assert_eq!(store.recent(10).unwrap().len(), 1);
+        // the policy is kept for the next session
+        let mut store = HistoryStore::open(&dir, &[1; 32]).unwrap();
+        assert_eq!(store.policy(), RetentionPolicy::Days(1));
+
+        store.set_policy(RetentionPolicy::UntilLeave).unwrap();
+        store.append(&entry(2000, None, "until leave")).unwrap();
+        store.left().unwrap();
+        assert!(store.recent(10).unwrap().is_empty());
+        assert_eq!(HistoryStore::open(&dir, &[1; 32]).unwrap().policy(), RetentionPolicy::UntilLeave);
+
+        store.set_policy(RetentionPolicy::Never).unwrap();
+        store.append(&entry(3000, None, "never")).unwrap();
+        // nothing tells that the conference was ever joined
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(HistoryStore::open(&dir, &[1; 32]).unwrap().policy(), RetentionPolicy::Forever);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_purge_on_append() {
+        let dir = temporary_dir();
+        let mut store = HistoryStore::open(&dir, &[1; 32]).unwrap();
+        store.set_policy(RetentionPolicy::Days(1)).unwrap();
+        store.append(&entry(1000, None, "old")).unwrap();
+        assert!(store.recent(10).unwrap().is_empty());
+        let now = UNIX_EPOCH + Duration::from_millis(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64);
+        store.append(&HistoryEntry { time: now, ..entry(0, None, "new") }).unwrap();
+        // the policy is not taken for the oldest message
+        assert_eq!(store.oldest().unwrap(), Some(now));
+        assert_eq!(store.recent(10).unwrap().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncated_log() {
+        let dir = temporary_dir();
+        let store = HistoryStore::open(&dir, &[1; 32]).unwrap();
+        store.append(&entry(1000, None, "kept")).unwrap();
+        store.append(&entry(2000, None, "cut off")).unwrap();
+        let data = fs::read(&store.path).unwrap();
+        fs::write(&store.path, &data[..data.len() - 3]).unwrap();
+        assert_eq!(store.recent(10).unwrap(), vec![entry(1000, None, "kept")]);
+        // appending continues after the last complete entry once opened again
+        let store = HistoryStore::open(&dir, &[1; 32]).unwrap();
+        store.append(&entry(3000, None, "new")).unwrap();
+        assert_eq!(store.recent(10).unwrap(), vec![entry(1000, None, "kept"), entry(3000, None, "new")]);
+
+        fs::write(&store.path, b"not a log").unwrap();
+        assert!(store.recent(10).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+//! Conferences without messages for a while are left automatically, so forgotten conferences do not
+//! count as peers on the server. The UI is warned some time before, any message keeps the conference.
+//!
+//! Without any user input for a while the whole session ends, see [`IdleSession`].
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::{config::IdleConfig, constants::ConferenceId};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IdleCheck {
+    /// The conference is left after this long unless a message is sent or received
+    Warn((ConferenceId, Duration)),
+    Leave(ConferenceId),
+}
+
+struct Activity {
+    last_message: Instant,
+    /// disabled for this conference
+    kept: bool,
+    warned: bool,
+}
+
+pub struct IdleConferences {
+    /// `None` never leaves conferences
+    leave_after: Option<Duration>,
+    warn_before: Duration,
+    conferences: HashMap<ConferenceId, Activity>,
+}
+
+impl IdleConferences {
+    pub fn new(config: &IdleConfig) -> IdleConferences {
+        let leave_after = Some(Duration::from_secs(config.leave_after_mins * 60)).filter(|leave_after| !leave_after.is_zero());
+        let warn_before = Duration::from_secs(config.warn_before_mins * 60);
+        IdleConferences { leave_after, warn_before, conferences: HashMap::new() }
+    }
+
+    pub fn add(&mut self, conference_id: ConferenceId, now: Instant) {
+        self.conferences.insert(conference_id, Activity { last_message: now, kept: false, warned: false });
+    }
+
+    pub fn remove(&mut self, conference_id: &ConferenceId) {
+        self.conferences.remove(conference_id);
+    }
+
+    /// A message was sent or received in the conference
+    pub fn message(&mut self, conference_id: ConferenceId, now: Instant) {
+        if let Some(activity) = self.conferences.get_mut(&conference_id) {
+            activity.last_message = now;
+            activity.warned = false;
+        }
+    }
+
+    /// Enable or disable leaving the conference when it is idle
+    pub fn set_enabled(&mut self, conference_id: ConferenceId, enabled: bool, now: Instant) {
+        if let Some(activity) = self.conferences.get_mut(&conference_id) {
+            activity.kept = !enabled;
+            activity.last_message = now;
+            activity.warned = false;
+        }
+    }
+
+    /// The conferences to warn about and the ones to leave, left conferences are forgotten
+    pub fn check(&mut self, now: Instant) -> Vec<IdleCheck> {
+        let Some(leave_after) = self.leave_after
+        else {
+            return Vec::new();
+        };
+        let mut checks = Vec::new();
+        for (&conference_id, activity) in self.conferences.iter_mut().filter(|(_, activity)| !activity.kept) {
+            let idle = now.saturating_duration_since(activity.last_message);
+            if idle >= leave_after {
+                checks.push(IdleCheck::Leave(conference_id));
+            } else if !activity.warned && idle + self.warn_before >= leave_after {
+                activity.warned = true;
+                checks.push(IdleCheck::Warn((conference_id, leave_after - idle)));
+            }
+        }
+        for check in &checks {
+            if let IdleCheck::Leave(conference_id) = check {
+                self.conferences.remove(conference_id);
+            }
+        }
+        checks
+    }
+}
+
+/// Disconnects an unattended client, any action of the user postpones it
+pub struct IdleSession {
+    /// `None` never disconnects
+    disconnect_after: Option<Duration>,
+    last_activity: Instant,
+}
+
+impl IdleSession {
+    pub fn new(config: &IdleConfig, now: Instant) -> IdleSession {
+        let disconnect_after = Some(Duration::from_secs(config.disconnect_after_mins * 60)).filter(|after| !after.is_zero());
+        IdleSession { disconnect_after, last_activity: now }
+    }
+
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = now;
+    }
+
+    /// The user was away long enough to disconnect
+    pub fn expired(&self, now: Instant) -> bool {
+        self.disconnect_after.is_some_and(|after| now.saturating_duration_since(self.last_activity) >= after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_conferences() {
+        let mut idle = IdleConferences::new(&IdleConfig { leave_after_mins: 60, warn_before_mins: 5, ..IdleConfig::default() });
+        let start = Instant::now();
+        idle.add(1, start);
+        idle.add(2, start);
+        idle.set_enabled(2, false, start);
+        assert!(idle.check(start + Duration::from_secs(54 * 60)).is_empty());
+        assert_eq!(idle.check(start + Duration::from_secs(56 * 60)), vec![IdleCheck::Warn((1, Duration::from_secs(4 * 60)))]);
+        assert!(idle.check(start + Duration::from_secs(57 * 60)).is_empty());
+
+        // a message keeps the conference
+        idle.message(1, start + Duration::from_secs(58 * 60));
+        assert!(idle.check(start + Duration::from_secs(61 * 60)).is_empty());
+        assert_eq!(idle.check(start + Duration::from_secs(118 * 60)), vec![IdleCheck::Leave(1)]);
+        assert!(idle.check(start + Duration::from_secs(200 * 60)).is_empty());
+
+        let mut disabled = IdleConferences::new(&IdleConfig { leave_after_mins: 0, warn_before_mins: 5, ..IdleConfig::default() });
+        disabled.add(1, start);
+        assert!(disabled.check(start + Duration::from_secs(1000 * 60)).is_empty());
+    }
+
+    #[test]
+    fn test_idle_session() {
+        let start = Instant::now();
+        let mut session = IdleSession::new(&IdleConfig { disconnect_after_mins: 30, ..IdleConfig::default() }, start);
+        assert!(!session.expired(start + Duration::from_secs(29 * 60)));
+        session.activity(start + Duration::from_secs(29 * 60));
+        assert!(!session.expired(start + Duration::from_secs(58 * 60)));
+        assert!(session.expired(start + Duration::from_secs(59 * 60)));
+
+        let never = IdleSession::new(&IdleConfig::default(), start);
+        assert!(!never.expired(start + Duration::from_secs(1000 * 60)));
+    }
+}
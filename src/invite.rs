@@ -0,0 +1,173 @@
+//! Invites bundle everything needed to join a conference into a single string,
+//! which can be shared as text or as a QR code.
+//!
+//! An invite is `acc` followed by the base58 encoding of
+//! `version | conference id | server address length | server address | password`,
+//! where the password is optional and encrypted under a passphrase that is shared separately.
+
+use std::{path::Path, str::FromStr};
+
+use crate::{
+    constants::{ConferenceId, Result},
+    crypto::{self, EncryptionResult, SALT_SIZE},
+};
+
+const INVITE_PREFIX: &str = "acc";
+const INVITE_VERSION: u8 = 1;
+
+const NO_PASSWORD: u8 = 0;
+const ENCRYPTED_PASSWORD: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    pub server_address: String,
+    pub conference_id: ConferenceId,
+    encrypted_password: Option<([u8; SALT_SIZE], Vec<u8>)>,
+}
+
+impl Invite {
+    pub fn new(server_address: impl Into<String>, conference_id: ConferenceId) -> Invite {
+        Invite { server_address: server_address.into(), conference_id, encrypted_password: None }
+    }
+
+    /// Embed the conference password, encrypted with a key derived from `passphrase`
+    pub fn with_password(mut self, password: &str, passphrase: &str) -> Result<Invite> {
+        let salt = crypto::generate_salt();
+        let key = crypto::hash_password_with_salt(passphrase.as_bytes(), &salt);
+        let encrypted = crypto::encrypt_message(password.as_bytes(), &key)
+            .map_err(|_| "Could not encrypt the password")?;
+        self.encrypted_password = Some((salt, encrypted.encode()));
+        Ok(self)
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.encrypted_password.is_some()
+    }
+
+    /// Decrypt the embedded password, `None` if the invite does not contain one
+    pub fn password(&self, passphrase: &str) -> Result<Option<String>> {
+        let Some((salt, encrypted)) = self.encrypted_password.as_ref()
+        else {
+            return Ok(None);
+        };
+        let key = crypto::hash_password_with_salt(passphrase.as_bytes(), salt);
+        let encrypted = EncryptionResult::decode(encrypted).map_err(|_| "Invalid encrypted password")?;
+        let password = crypto::decrypt_message(&key, &encrypted).map_err(|_| "Wrong passphrase")?;
+        Ok(Some(String::from_utf8(password)?))
+    }
+
+    /// Fails if the server address is longer than 255 bytes
+    pub fn encode(&self) -> Result<String> {
+        let server_address_length = u8::try_from(self.server_address.len())
+            .map_err(|_| "The server address is too long for an invite")?;
+        let mut data = vec![INVITE_VERSION];
+        data.extend_from_slice(&self.conference_id.to_be_bytes());
+        data.push(server_address_length);
+        data.extend_from_slice(self.server_address.as_bytes());
+        match self.encrypted_password.as_ref() {
+            Some((salt, encrypted)) => {
+                data.push(ENCRYPTED_PASSWORD);
+                data.extend_from_slice(salt);
+                data.extend_from_slice(encrypted);
+            },
+            None => data.push(NO_PASSWORD),
+        }
+        Ok(format!("{}{}", INVITE_PREFIX, bs58::encode(data).into_string()))
+    }
+
+    pub fn decode(invite: &str) -> Result<Invite> {
+        let data = invite.trim()
+            .strip_prefix(INVITE_PREFIX)
+            .ok_or("Not an invite")?;
+        let data = bs58::decode(data).into_vec()?;
+        let (&version, data) = data.split_first().ok_or("Invite is empty")?;
+        if version != INVITE_VERSION {
+            return Err(format!("Unsupported invite version {}", version).into());
+        }
+        let conference_id = ConferenceId::from_be_bytes(data.get(..4).ok_or("Invite is truncated")?.try_into()?);
+        let (&server_address_length, data) = data[4..].split_first().ok_or("Invite is truncated")?;
+        let server_address = data.get(..server_address_length as usize).ok_or("Invite is truncated")?;
+        let server_address = String::from_utf8(server_address.to_vec())?;
+        let data = &data[server_address_length as usize..];
+        let encrypted_password = match data.split_first() {
+            Some((&NO_PASSWORD, [])) => None,
+            Some((&ENCRYPTED_PASSWORD, data)) if data.len() > SALT_SIZE => {
+                let salt = data[..SALT_SIZE].try_into()?;
+                Some((salt, data[SALT_SIZE..].to_vec()))
+            },
+            _ => return Err("Invalid invite password".into()),
+        };
+        Ok(Invite { server_address, conference_id, encrypted_password })
+    }
+
+    /// Square matrix of QR code modules (`true` is dark), row by row, and its width
+    pub fn qr_code(&self) -> Result<(Vec<bool>, usize)> {
+        qr_code(&self.encode()?)
+    }
+}
+
+impl FromStr for Invite {
+    type Err = Box<dyn std::error::Error + Send + Sync>;
+
+    fn from_str(invite: &str) -> Result<Invite> {
+        Invite::decode(invite)
+    }
+}
+
+/// QR code modules of any text, as [`Invite::qr_code`]
+pub fn qr_code(text: &str) -> Result<(Vec<bool>, usize)> {
+    let code = qrcode::QrCode::new(text.as_bytes())?;
+    let width = code.width();
+    let modules = code.to_colors().into_iter().map(|color| color == qrcode::Color::Dark).collect();
+    Ok((modules, width))
+}
+
+/// Read the invite string from the first QR code found in an image file
+pub fn decode_qr_image(path: &Path) -> Result<String> {
+    let image = image::open(path)?.to_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare(image);
+    for grid in prepared.detect_grids() {
+        if let Ok((_, content)) = grid.decode() {
+            if content.starts_with(INVITE_PREFIX) {
+                return Ok(content);
+            }
+        }
+    }
+    Err("No invite QR code found in the image".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invite_roundtrip() {
+        let invite = Invite::new("example.com:7667", 1234);
+        let decoded = Invite::decode(&invite.encode().unwrap()).unwrap();
+        assert_eq!(decoded, invite);
+        assert_eq!(decoded.password("anything").unwrap(), None);
+
+        let invite = invite.with_password("secret", "passphrase").unwrap();
+        let decoded: Invite = invite.encode().unwrap().parse().unwrap();
+        assert!(decoded.has_password());
+        assert_eq!(decoded.password("passphrase").unwrap(), Some("secret".to_string()));
+        assert!(decoded.password("wrong").is_err());
+    }
+
+    #[test]
+    fn test_invite_decode_invalid() {
+        assert!(Invite::decode("").is_err());
+        assert!(Invite::decode("acc").is_err());
+        assert!(Invite::decode("xyz123").is_err());
+        let mut encoded = Invite::new("example.com:7667", 1234).encode().unwrap();
+        encoded.truncate(encoded.len() - 3);
+        assert!(Invite::decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_invite_long_server_address() {
+        let server_address = format!("{}.example.com:7667", "a".repeat(255));
+        assert!(Invite::new(server_address, 1234).encode().is_err());
+        assert!(Invite::new(format!("{}:7667", "a".repeat(250)), 1234).encode().is_ok());
+    }
+}
@@ -0,0 +1,60 @@
+pub mod constants;
+pub mod crypto;
+pub mod fingerprint;
+pub mod anonymity;
+pub mod codec;
+#[cfg(feature = "wire-schema")]
+pub mod wire_schema;
+pub mod dns;
+pub mod connection_manager;
+pub mod message_codec;
+pub mod conference_manager;
+pub mod state_manager;
+pub mod multi_server;
+#[cfg(feature = "cli")]
+pub mod cli_ui;
+#[cfg(feature = "cli")]
+pub mod tui;
+#[cfg(feature = "cli")]
+pub mod terminal_notification;
+#[cfg(feature = "gtk")]
+pub mod gtk_ui;
+pub mod paths;
+pub mod runtime;
+pub mod plugins;
+pub mod bot;
+pub mod oneshot;
+pub mod config;
+pub mod logging;
+pub mod crash_report;
+pub mod update_check;
+pub mod trace;
+pub mod protocol_dump;
+pub mod invite;
+pub mod peer_labels;
+pub mod recent;
+pub mod pending_requests;
+pub mod pinning;
+pub mod flood_protection;
+pub mod ordering;
+pub mod upload;
+pub mod file_transfer;
+pub mod thumbnail;
+pub mod outbound;
+pub mod liveness;
+pub mod heartbeat;
+pub mod rejoin;
+pub mod idle;
+#[cfg(feature = "network-monitor")]
+pub mod network_monitor;
+pub mod history;
+pub mod history_store;
+pub mod sanitize;
+pub mod device_link;
+pub mod password_source;
+pub mod settings_bundle;
+pub mod storage;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock_server;
+#[cfg(any(test, feature = "test-util"))]
+pub mod simulation;
@@ -0,0 +1,137 @@
+//! Detection of conferences the server stopped relaying for while the connection itself is alive.
+//! A conference without inbound traffic is probed, peers answer probes, and the conference is flagged
+//! as stale if the probe stays unanswered although the server kept answering requests in the meantime.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::constants::{ConferenceId, NumberOfPeers};
+
+/// A conference is probed after this long without inbound traffic
+pub const QUIET_PERIOD: Duration = Duration::from_secs(120);
+/// Peers have this long to answer a probe
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Check {
+    /// Ask the peers of the conference to answer
+    Probe(ConferenceId),
+    /// The probe was not answered, but the server was reachable
+    Stale(ConferenceId),
+}
+
+struct Activity {
+    number_of_peers: NumberOfPeers,
+    last_inbound: Instant,
+    probe_sent: Option<Instant>,
+    stale: bool,
+}
+
+#[derive(Default)]
+pub struct ConferenceLiveness {
+    conferences: HashMap<ConferenceId, Activity>,
+    /// when the server last sent anything, shows that the connection is alive
+    last_server_event: Option<Instant>,
+}
+
+impl ConferenceLiveness {
+    pub fn add(&mut self, conference_id: ConferenceId, number_of_peers: NumberOfPeers, now: Instant) {
+        self.conferences.insert(conference_id, Activity { number_of_peers, last_inbound: now, probe_sent: None, stale: false });
+    }
+
+    pub fn remove(&mut self, conference_id: &ConferenceId) {
+        self.conferences.remove(conference_id);
+    }
+
+    /// Returns the previous number of peers, `None` for unknown conferences
+    pub fn set_number_of_peers(&mut self, conference_id: ConferenceId, number_of_peers: NumberOfPeers) -> Option<NumberOfPeers> {
+        let activity = self.conferences.get_mut(&conference_id)?;
+        Some(std::mem::replace(&mut activity.number_of_peers, number_of_peers))
+    }
+
+    pub fn number_of_peers(&self, conference_id: ConferenceId) -> Option<NumberOfPeers> {
+        self.conferences.get(&conference_id).map(|activity| activity.number_of_peers)
+    }
+
+    /// Any event from the server, including the acceptance of a probe
+    pub fn server_event(&mut self, now: Instant) {
+        self.last_server_event = Some(now);
+    }
+
+    /// Traffic of the peers was relayed, returns `true` if the conference was flagged as stale before
+    pub fn inbound(&mut self, conference_id: ConferenceId, now: Instant) -> bool {
+        let Some(activity) = self.conferences.get_mut(&conference_id)
+        else {
+            return false;
+        };
+        activity.last_inbound = now;
+        activity.probe_sent = None;
+        std::mem::replace(&mut activity.stale, false)
+    }
+
+    /// The conferences to probe and the ones that just became stale
+    pub fn check(&mut self, now: Instant) -> Vec<Check> {
+        let mut checks = Vec::new();
+        for (&conference_id, activity) in self.conferences.iter_mut() {
+            // nobody could answer
+            if activity.number_of_peers < 2 || activity.stale {
+                continue;
+            }
+            match activity.probe_sent {
+                None if now.saturating_duration_since(activity.last_inbound) > QUIET_PERIOD => {
+                    activity.probe_sent = Some(now);
+                    checks.push(Check::Probe(conference_id));
+                },
+                Some(probe_sent) if now.saturating_duration_since(probe_sent) > PROBE_TIMEOUT => {
+                    activity.probe_sent = None;
+                    // a silent server is a problem of the connection, not of the conference, probe again later
+                    if self.last_server_event.is_some_and(|last_server_event| last_server_event >= probe_sent) {
+                        activity.stale = true;
+                        checks.push(Check::Stale(conference_id));
+                    }
+                },
+                _ => {},
+            }
+        }
+        checks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unanswered_probe() {
+        let mut liveness = ConferenceLiveness::default();
+        let start = Instant::now();
+        liveness.add(1, 3, start);
+        liveness.add(2, 1, start);
+        assert!(liveness.check(start + QUIET_PERIOD).is_empty());
+
+        let probed = start + QUIET_PERIOD * 2;
+        assert_eq!(liveness.check(probed), vec![Check::Probe(1)]);
+        assert!(liveness.check(probed + PROBE_TIMEOUT / 2).is_empty());
+        // the server accepted the probe, but no peer answered
+        liveness.server_event(probed + Duration::from_secs(1));
+        assert_eq!(liveness.check(probed + PROBE_TIMEOUT * 2), vec![Check::Stale(1)]);
+        assert!(liveness.check(probed + QUIET_PERIOD * 2).is_empty());
+
+        assert!(liveness.inbound(1, probed + QUIET_PERIOD * 2));
+        assert!(!liveness.inbound(1, probed + QUIET_PERIOD * 2));
+    }
+
+    #[test]
+    fn test_silent_server() {
+        let mut liveness = ConferenceLiveness::default();
+        let start = Instant::now();
+        liveness.add(1, 2, start);
+        let probed = start + QUIET_PERIOD * 2;
+        assert_eq!(liveness.check(probed), vec![Check::Probe(1)]);
+        assert!(liveness.check(probed + PROBE_TIMEOUT * 2).is_empty());
+        // probed again
+        assert_eq!(liveness.check(probed + PROBE_TIMEOUT * 3), vec![Check::Probe(1)]);
+    }
+}
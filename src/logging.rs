@@ -0,0 +1,205 @@
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicBool, Ordering}, Mutex},
+    time::SystemTime,
+};
+
+use log::{Log, Metadata, Record};
+use tracing_subscriber::fmt::format::FmtSpan;
+use crate::{config::LogConfig, constants::Result, paths};
+
+const LOG_FILE_NAME: &str = "client.log";
+const DEFAULT_FILTER: &str = "info";
+const RECENT_LINES_CAPACITY: usize = 200;
+
+/// Whether the log records are also written to the trace output, see [`init_trace_output`]
+static TRACE_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+/// The last log lines, included in crash reports
+static RECENT_LINES: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// The last lines that were logged, oldest first.
+/// Never blocks as the panic hook calls it, empty if the lines are locked or poisoned by a panic while logging.
+pub fn recent_lines() -> Vec<String> {
+    match RECENT_LINES.try_lock() {
+        Ok(lines) => lines.iter().cloned().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn remember_line(line: &str) {
+    if let Ok(mut lines) = RECENT_LINES.lock() {
+        if lines.len() == RECENT_LINES_CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_string());
+    }
+}
+
+/// Path of the current log file
+pub fn log_file_path() -> PathBuf {
+    paths::data_dir().join(LOG_FILE_NAME)
+}
+
+/// Install the logger, `filter_override` (from the command line) takes precedence over
+/// `RUST_LOG`, which takes precedence over the config file
+pub fn init(config: &LogConfig, filter_override: Option<&str>) -> Result<()> {
+    let filter_directives = filter_override.map(str::to_string)
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .or_else(|| config.filter.clone())
+        .unwrap_or_else(|| DEFAULT_FILTER.to_string());
+    let filter = env_filter::Builder::new().parse(&filter_directives).build();
+    let file = RotatingFile::open(log_file_path(), config.max_file_size, config.max_files)?;
+    let max_level = filter.filter();
+    let logger = Logger {
+        filter,
+        file: Mutex::new(file),
+        stderr: config.stderr,
+    };
+    log::set_boxed_logger(Box::new(logger))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Additionally write the tracing spans as JSON lines to `path`.
+/// The client logs with `log`, the records that pass the log filter are passed on as events of the span they are logged in.
+pub fn init_trace_output(path: &Path) -> Result<()> {
+    let file = File::create(path)?;
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
+        .with_current_span(true)
+        .with_span_list(true)
+        .with_max_level(tracing::Level::DEBUG)
+        .with_writer(Mutex::new(file))
+        .finish();
+    tracing::subscriber::set_global_default(subscriber)?;
+    TRACE_OUTPUT.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+struct Logger {
+    filter: env_filter::Filter,
+    file: Mutex<RotatingFile>,
+    stderr: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let line = format!(
+            "[{} {} {}] {}\n",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if self.stderr {
+            let _ = io::stderr().write_all(line.as_bytes());
+        }
+        remember_line(&line);
+        if let Ok(mut file) = self.file.lock() {
+            // nowhere to report a failure to write the log
+            let _ = file.write_line(&line);
+        }
+        if TRACE_OUTPUT.load(Ordering::Relaxed) {
+            let _ = tracing_log::format_trace(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// A log file that is renamed to `<name>.1`, `<name>.2`, ... once it grows too large
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, file, size, max_size, max_files })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for index in (1..self.max_files).rev() {
+                let from = rotated_path(&self.path, index);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(&self.path, index + 1))?;
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+            self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_lines() {
+        for index in 0..RECENT_LINES_CAPACITY + 5 {
+            remember_line(&format!("line {}\n", index));
+        }
+        let lines = recent_lines();
+        assert_eq!(lines.len(), RECENT_LINES_CAPACITY);
+        assert_eq!(lines.last().unwrap(), &format!("line {}\n", RECENT_LINES_CAPACITY + 4));
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("acc-log-test-{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.log");
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+        for line in ["first line\n", "second line\n", "third line\n", "fourth line\n"] {
+            file.write_line(line).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth line\n");
+        assert_eq!(fs::read_to_string(rotated_path(&path, 1)).unwrap(), "third line\n");
+        assert_eq!(fs::read_to_string(rotated_path(&path, 2)).unwrap(), "second line\n");
+        assert!(!rotated_path(&path, 3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
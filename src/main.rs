@@ -1,42 +1,326 @@
-#![windows_subsystem = "windows"]
-
-use log::{debug, error}; // hide console on windows
-mod constants;
-mod crypto;
-mod connection_manager;
-mod conference_manager;
-mod state_manager;
-mod cli_ui;
-mod gtk_ui;
-
-#[async_std::main]
-async fn main() {
-    env_logger::init();
-    let mut use_cli = false;
-    let mut server_address = "localhost:7667".to_string();
-
-    let mut args = std::env::args().skip(1); // skip binary name
-    while let Some(arg) = args.next() {
-        match arg.as_str() {
-            "--cli" => use_cli = true,
-            "--server-address" => {
-                if let Some(server_address_arg) = args.next() {
-                    server_address = server_address_arg;
-                }
-            }
-            _ => {
-                error!("Unknown argument: {}", arg);
-                return;
-            }
+#![cfg_attr(feature = "gtk", windows_subsystem = "windows")]
+
+use std::{io::{IsTerminal, Read}, path::PathBuf, process::ExitCode, time::Duration};
+
+use clap::{Parser, Subcommand};
+use futures::{channel::mpsc, StreamExt};
+use log::{debug, error, info}; // hide console on windows
+use anonymous_conference_client::{
+    config::{self, Config, Frontend, LogConfig}, constants::{ConferenceId, DisconnectReason}, crash_report, logging, runtime, state_manager, trace, protocol_dump,
+    password_source::{PasswordSource, PASSWORD_ENV_VARIABLE}, connection_manager, settings_bundle, storage,
+    oneshot::{self, CommandError, Oneshot},
+};
+#[cfg(feature = "cli")]
+use anonymous_conference_client::{cli_ui, tui};
+#[cfg(feature = "gtk")]
+use anonymous_conference_client::gtk_ui;
+
+/// Anonymous conferences, without a subcommand the frontend from `ui.frontend` is started
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Run the terminal frontend, same as `--set ui.frontend=cli`
+    #[arg(long)]
+    cli: bool,
+    /// Run the terminal frontend printing lines, for scripts, same as `--set ui.frontend=cli --set cli.plain=true`
+    #[arg(long)]
+    cli_plain: bool,
+    /// Same as `--set servers.address=<ADDRESS>`
+    #[arg(long, global = true, value_name = "ADDRESS")]
+    server_address: Option<String>,
+    /// Override a setting, can be given several times
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE", value_parser = parse_setting)]
+    settings: Vec<(String, String)>,
+    /// Print the effective settings and where they come from, then exit
+    #[arg(long)]
+    print_config: bool,
+    /// Bundle the settings into a file, then exit
+    #[arg(long, value_name = "FILE")]
+    export_settings: Option<PathBuf>,
+    /// Replace the settings with a bundle, then exit
+    #[arg(long, value_name = "FILE")]
+    import_settings: Option<PathBuf>,
+    /// Trust the PEM certificate in addition to the system trust store, can be given several times
+    #[arg(long = "tls-certificate", global = true, value_name = "FILE")]
+    tls_certificates: Vec<PathBuf>,
+    /// Per-module log levels in the `RUST_LOG` format
+    #[arg(long, global = true, value_name = "FILTER")]
+    log_filter: Option<String>,
+    /// Show how long sent messages took in the terminal frontend
+    #[arg(long)]
+    verbose: bool,
+    /// Record the frames exchanged with the server, encrypted
+    #[arg(long, global = true, value_name = "FILE")]
+    trace: Option<PathBuf>,
+    /// Replay a recorded trace and log the resulting events, then exit
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// Write the tracing spans as JSON lines
+    #[arg(long, global = true, value_name = "FILE")]
+    trace_output: Option<PathBuf>,
+    /// Write every decoded event, `-` for stderr
+    #[arg(long, global = true, value_name = "FILE")]
+    debug_protocol: Option<PathBuf>,
+    /// Read conference passwords from the first line of the file instead of the environment variable `ACC_CONFERENCE_PASSWORD`
+    #[arg(long, global = true, value_name = "FILE")]
+    password_file: Option<PathBuf>,
+    /// Wait until the server can be reached, forever without a timeout
+    #[arg(long, global = true, value_name = "SECONDS", num_args = 0..=1)]
+    wait_for_server: Option<Option<u64>>,
+}
+
+/// The subcommands other than `gui` are one-shot commands for scripts, see [`oneshot`]
+#[derive(Subcommand)]
+enum Command {
+    /// Start the graphical frontend
+    Gui,
+    /// Create a conference and print its id
+    Create,
+    /// Join a conference and print its messages as lines until the session ends
+    Join {
+        conference_id: ConferenceId,
+    },
+    /// Join a conference, send a message and leave
+    Send {
+        conference_id: ConferenceId,
+        /// `-` reads the message from stdin
+        message: String,
+    },
+}
+
+fn parse_setting(setting: &str) -> Result<(String, String), String> {
+    setting.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| "expected <key>=<value>".to_string())
+}
+
+fn main() -> ExitCode {
+    runtime::block_on(run(Args::parse()))
+}
+
+async fn run(args: Args) -> ExitCode {
+    let mut flag_overrides = Vec::new();
+    if args.cli {
+        flag_overrides.push(("ui.frontend".to_string(), "cli".to_string()));
+    }
+    if args.cli_plain {
+        flag_overrides.push(("ui.frontend".to_string(), "cli".to_string()));
+        flag_overrides.push(("cli.plain".to_string(), "true".to_string()));
+    }
+    if let Some(server_address) = &args.server_address {
+        flag_overrides.push(("servers.address".to_string(), server_address.clone()));
+    }
+    if !args.tls_certificates.is_empty() {
+        let paths = args.tls_certificates.iter().map(|path| toml::Value::String(path.display().to_string())).collect();
+        flag_overrides.push(("tls.certificates".to_string(), toml::Value::Array(paths).to_string()));
+    }
+    if let Some(log_filter) = &args.log_filter {
+        flag_overrides.push(("log.filter".to_string(), log_filter.clone()));
+    }
+    flag_overrides.extend(args.settings);
+
+    config::set_flag_overrides(flag_overrides);
+    if args.print_config {
+        print!("{}", Config::resolve());
+        return ExitCode::SUCCESS;
+    }
+    let config = Config::load();
+    // the graphical frontend asks for the master password in its window, everything else on the terminal
+    let graphical = args.export_settings.is_none() && args.import_settings.is_none() && args.replay.is_none()
+        && match &args.command {
+            None => config.ui.frontend == Frontend::Gtk && cfg!(feature = "gtk"),
+            Some(command) => matches!(command, Command::Gui),
+        };
+    // before the settings are exported or imported, they contain the encrypted files
+    if !graphical {
+        if let Err(e) = storage::unlock_interactive(&config.storage) {
+            eprintln!("Could not unlock the data directory: {}", e);
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(path) = args.export_settings {
+        return match settings_bundle::export(&path) {
+            Ok(()) => {
+                println!("Exported the settings to {}", path.display());
+                ExitCode::SUCCESS
+            },
+            Err(e) => {
+                eprintln!("Could not export the settings to {}: {}", path.display(), e);
+                ExitCode::FAILURE
+            },
+        };
+    }
+    if let Some(path) = args.import_settings {
+        return match settings_bundle::import(&path, &config.storage) {
+            Ok(files) => {
+                println!("Imported {} from {}", files.join(", "), path.display());
+                ExitCode::SUCCESS
+            },
+            Err(e) => {
+                eprintln!("Could not import the settings from {}: {}", path.display(), e);
+                ExitCode::FAILURE
+            },
+        };
+    }
+    let server_address = config.servers.address.clone();
+    // the full screen interface owns the terminal, log lines on stderr would tear it
+    let full_screen = cfg!(feature = "cli")
+        && args.command.is_none()
+        && (config.ui.frontend == Frontend::Cli || cfg!(not(feature = "gtk")))
+        && !config.cli.plain
+        && std::io::stdin().is_terminal()
+        && std::io::stdout().is_terminal();
+    let log_config = LogConfig { stderr: config.log.stderr && !full_screen, ..config.log.clone() };
+    if let Err(e) = logging::init(&log_config, args.log_filter.as_deref()) {
+        eprintln!("Could not initialize logging: {}", e);
+    }
+    if let Some(trace_output_path) = &args.trace_output {
+        if let Err(e) = logging::init_trace_output(trace_output_path) {
+            eprintln!("Could not write tracing spans to {}: {}", trace_output_path.display(), e);
+        }
+    }
+    crash_report::install_panic_hook();
+
+    if let Some(protocol_dump_path) = args.debug_protocol {
+        match protocol_dump::start(&protocol_dump_path) {
+            Ok(()) => info!("Dumping protocol events to {}", protocol_dump_path.display()),
+            Err(e) => error!("Could not start the protocol dump: {}", e),
+        }
+    }
+
+    if let Some(replay_path) = args.replay {
+        replay(replay_path).await;
+        return ExitCode::SUCCESS;
+    }
+    if let Some(timeout) = args.wait_for_server {
+        // with relays only the first hop can be reached directly
+        let address = config.relay.chain.first().unwrap_or(&server_address).clone();
+        let result = connection_manager::wait_for_server(&config.dns, &address, timeout.map(Duration::from_secs), |attempts, waited, e| {
+            eprintln!("Waiting for {} ({} attempts, {} seconds): {}", address, attempts, waited.as_secs(), e);
+        }).await;
+        if let Err(e) = result {
+            error!("{}", e);
+            return ExitCode::from(oneshot::EXIT_DISCONNECTED);
+        }
+    }
+
+    if let Some(trace_path) = args.trace {
+        match trace::start_recording(&trace_path) {
+            Ok(()) => info!("Recording protocol trace to {}", trace_path.display()),
+            Err(e) => error!("Could not start recording protocol trace: {}", e),
         }
     }
 
     debug!("Connecting to the server at {}", server_address);
 
-    if use_cli {
-        let mut ui = cli_ui::CLII_UI::new(server_address);
-        ui.start_ui().await;
+    let password_source = PasswordSource::from_args(args.password_file);
+    let frontend = match args.command {
+        None => config.ui.frontend,
+        Some(Command::Gui) if cfg!(not(feature = "gtk")) => {
+            error!("This build does not include the graphical frontend, rebuild with the `gtk` feature");
+            return ExitCode::FAILURE;
+        },
+        Some(Command::Gui) => Frontend::Gtk,
+        Some(command) => return run_command(command, server_address, password_source).await,
+    };
+
+    // without the GTK frontend the terminal is the only one
+    if frontend == Frontend::Cli || cfg!(not(feature = "gtk")) {
+        #[cfg(feature = "cli")]
+        {
+            let mut ui = cli_ui::CLII_UI::new(server_address, &config)
+                .with_password_source(password_source)
+                .with_verbose(args.verbose);
+            if full_screen {
+                if let Err(e) = tui::Tui::new(ui).start_ui().await {
+                    error!("The terminal interface failed: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            } else {
+                ui.start_ui().await;
+            }
+        }
+        #[cfg(not(feature = "cli"))]
+        {
+            error!("This build does not include the cli frontend, rebuild with the `cli` feature");
+            return ExitCode::FAILURE;
+        }
     } else {
-        gtk_ui::main_window::start_gtk_ui(server_address);
+        #[cfg(feature = "gtk")]
+        gtk_ui::main_window::start_gtk_ui(server_address, config);
+    }
+    ExitCode::SUCCESS
+}
+
+/// Run a one-shot command, the exit code tells scripts what went wrong
+async fn run_command(command: Command, server_address: String, password_source: Option<PasswordSource>) -> ExitCode {
+    let Some(password_source) = password_source
+    else {
+        eprintln!("The conference password is read from --password-file or the environment variable {}", PASSWORD_ENV_VARIABLE);
+        return ExitCode::FAILURE;
+    };
+    let password = match password_source.read() {
+        Ok(password) => password,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        },
+    };
+
+    let mut session = Oneshot::connect(server_address);
+    let result = match command {
+        Command::Gui => unreachable!("the frontends are started by run"),
+        Command::Create => session.create(password).await
+            .map(|conference_id| println!("{}", conference_id)),
+        Command::Join { conference_id } => match session.join(conference_id, password).await {
+            Ok(()) => Err(session.print_messages(conference_id, &mut std::io::stdout()).await),
+            Err(e) => Err(e),
+        },
+        Command::Send { conference_id, message } => {
+            let message = if message == "-" {
+                let mut message = String::new();
+                if let Err(e) = std::io::stdin().read_to_string(&mut message) {
+                    eprintln!("Could not read the message from stdin: {}", e);
+                    return ExitCode::FAILURE;
+                }
+                message.trim_end_matches('\n').to_string()
+            } else {
+                message
+            };
+            match session.join(conference_id, password).await {
+                Ok(()) => session.send(conference_id, message).await,
+                Err(e) => Err(e),
+            }
+        },
+    };
+    session.disconnect().await;
+    match result {
+        // nobody reads the messages anymore
+        Ok(()) | Err(CommandError::Disconnected(DisconnectReason::UserRequested)) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::from(e.exit_code())
+        },
+    }
+}
+
+/// Run the state manager on a recorded trace and log every UI event it produces
+async fn replay(path: PathBuf) {
+    let records = match trace::read_trace(&path) {
+        Ok(records) => records,
+        Err(e) => {
+            error!("Could not read trace {}: {}", path.display(), e);
+            return;
+        }
+    };
+    info!("Replaying {} trace records from {}", records.len(), path.display());
+    let (ui_event_sender, mut ui_event_receiver) = mpsc::unbounded();
+    // kept alive until the replay is done
+    let (_ui_action_sender, ui_action_receiver) = mpsc::unbounded();
+    runtime::spawn(state_manager::start_state_manager_replay(records, ui_event_sender, ui_action_receiver));
+    while let Some(ui_event) = ui_event_receiver.next().await {
+        info!("Replay: {:?}", ui_event);
     }
 }
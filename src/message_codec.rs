@@ -0,0 +1,453 @@
+//! Encoding and decoding of the messages exchanged between peers inside a conference.
+//!
+//! Everything here is synchronous and never panics on untrusted input, a message that cannot be read
+//! is reported as a [`CodecError`] saying what was wrong with it, so the errors can be counted and logged
+//! by the [`ConferenceManager`](crate::conference_manager::ConferenceManager) and the decoders fuzzed on their own.
+
+use std::fmt;
+
+use curve25519_dalek::{ristretto::CompressedRistretto, RistrettoPoint, Scalar};
+
+use crate::{constants::{EncryptionKey, KeyImage}, crypto};
+
+pub const SCALAR_BYTE_SIZE: usize = 32;
+
+/// Counts the restructurings of a conference, key exchange messages are tagged with the epoch of the sender
+/// so that messages of an exchange that was overtaken by a newer restructuring are not mixed into the new one.
+/// Peers that joined later start at `0` and adopt the newer epoch of the others from their key exchange messages.
+pub type Epoch = u32;
+
+/// Why a message of a peer could not be read, or an own one not be written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CodecError {
+    /// None of the conference keys decrypts the message
+    Undecryptable,
+    /// The decrypted message is empty, not even the message type is there
+    Empty,
+    UnknownType(u8),
+    /// `(message type, expected at least, actual)` length of the payload
+    Truncated((u8, usize, usize)),
+    /// `(message type, expected, actual)` length of a payload that has a fixed or announced length
+    BadLength((u8, usize, usize)),
+    /// `(expected at least, actual)` length of a signed message for the size of the ring
+    TruncatedSignature((usize, usize)),
+    /// The scalar at this position of the signature is not reduced, `0` is the challenge
+    NonCanonicalScalar(usize),
+    /// The key image is not the encoding of a ristretto point
+    InvalidKeyImage,
+    /// A signed message arrived before the public keys of the conference were exchanged
+    NoRing,
+    /// An own message of this many bytes does not fit its u32 length prefix
+    TooLarge(usize),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Undecryptable => write!(f, "could not decrypt message"),
+            CodecError::Empty => write!(f, "empty message"),
+            CodecError::UnknownType(message_type) => write!(f, "unknown message type {:#04x}", message_type),
+            CodecError::Truncated((message_type, expected, actual)) =>
+                write!(f, "message of type {:#04x} is truncated (expected at least {} bytes, got {})", message_type, expected, actual),
+            CodecError::BadLength((message_type, expected, actual)) =>
+                write!(f, "message of type {:#04x} has an invalid length (expected {} bytes, got {})", message_type, expected, actual),
+            CodecError::TruncatedSignature((expected, actual)) =>
+                write!(f, "signed message is truncated (expected at least {} bytes, got {})", expected, actual),
+            CodecError::NonCanonicalScalar(0) => write!(f, "signature challenge is not a canonical scalar"),
+            CodecError::NonCanonicalScalar(position) => write!(f, "signature response {} is not a canonical scalar", position),
+            CodecError::InvalidKeyImage => write!(f, "could not decompress key image"),
+            CodecError::NoRing => write!(f, "signed message before the ring was established"),
+            CodecError::TooLarge(length) => write!(f, "message of {} bytes is too large to send", length),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+#[repr(u8)]
+/// The different types of messages that can be sent between clients
+/// PublicKey = `0x01`, tagged with the epoch
+/// EncryptionKeyPart = `0x02`, tagged with the epoch
+/// Message = `0x03`
+/// ReadReceipt = `0x04`, a signed hash of the last message the sender has seen
+/// Breakout = `0x05`, a signed announcement of a sub-conference
+/// Probe = `0x06`, asks the peers to answer, see [`crate::liveness`]
+/// ProbeReply = `0x07`
+/// Migration = `0x08`, a signed announcement of the server and conference the conference moved to
+/// RingHash = `0x09`, epoch, hash and public keys of the sender's ring followed by the same signed with that ring, see `RingContext::hash`
+/// Chunk = `0x0a`, a signed part of a large message
+/// FileOffer = `0x0b`, a signed announcement of a file, see [`crate::file_transfer`]
+/// FileChunk = `0x0c`, a signed part of a file
+/// FileComplete = `0x0d`, the signed hash of a file sent after its last chunk
+/// DeliveryReceipt = `0x0e`, signed hashes of messages the sender received
+#[derive(Debug, PartialEq)]
+pub enum ClientToClientMessage {
+    PublicKey((Epoch, [u8; 32])),
+    EncryptionKeyPart((Epoch, Vec<u8>)),
+    Message(Vec<u8>),
+    ReadReceipt(Vec<u8>),
+    Breakout(Vec<u8>),
+    Probe,
+    ProbeReply,
+    Migration(Vec<u8>),
+    RingHash(Vec<u8>),
+    /// a signed part of a large message, see [`crate::upload`]
+    Chunk(Vec<u8>),
+    FileOffer(Vec<u8>),
+    FileChunk(Vec<u8>),
+    FileComplete(Vec<u8>),
+    DeliveryReceipt(Vec<u8>),
+}
+
+impl ClientToClientMessage {
+    pub fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        let mut result = Vec::new();
+        self.encode_into(&mut result)?;
+        Ok(result)
+    }
+
+    /// Encode into `result`, which is cleared first, so a scratch buffer can be reused
+    pub fn encode_into(&self, result: &mut Vec<u8>) -> Result<(), CodecError> {
+        result.clear();
+        match self {
+            ClientToClientMessage::PublicKey((epoch, pubkey)) => {
+                result.push(0x01);
+                result.extend_from_slice(&epoch.to_be_bytes());
+                result.extend_from_slice(pubkey);
+            },
+            ClientToClientMessage::EncryptionKeyPart((epoch, key_part)) => {
+                result.push(0x02);
+                result.extend_from_slice(&epoch.to_be_bytes());
+                result.extend_from_slice(key_part);
+            },
+            ClientToClientMessage::Message(message) => {
+                let length = u32::try_from(message.len()).map_err(|_| CodecError::TooLarge(message.len()))?;
+                result.push(0x03);
+                result.extend_from_slice(&length.to_be_bytes());
+                result.extend_from_slice(message);
+            },
+            ClientToClientMessage::ReadReceipt(receipt) => {
+                result.push(0x04);
+                result.extend_from_slice(receipt);
+            },
+            ClientToClientMessage::Breakout(announcement) => {
+                result.push(0x05);
+                result.extend_from_slice(announcement);
+            },
+            ClientToClientMessage::Probe => result.push(0x06),
+            ClientToClientMessage::ProbeReply => result.push(0x07),
+            ClientToClientMessage::Migration(announcement) => {
+                result.push(0x08);
+                result.extend_from_slice(announcement);
+            },
+            ClientToClientMessage::RingHash(announcement) => {
+                result.push(0x09);
+                result.extend_from_slice(announcement);
+            },
+            ClientToClientMessage::Chunk(chunk) => {
+                result.push(0x0a);
+                result.extend_from_slice(chunk);
+            },
+            ClientToClientMessage::FileOffer(offer) => {
+                result.push(0x0b);
+                result.extend_from_slice(offer);
+            },
+            ClientToClientMessage::FileChunk(chunk) => {
+                result.push(0x0c);
+                result.extend_from_slice(chunk);
+            },
+            ClientToClientMessage::FileComplete(complete) => {
+                result.push(0x0d);
+                result.extend_from_slice(complete);
+            },
+            ClientToClientMessage::DeliveryReceipt(receipt) => {
+                result.push(0x0e);
+                result.extend_from_slice(receipt);
+            },
+        }
+        Ok(())
+    }
+
+    /// Decode a decrypted message received from a peer
+    pub fn decode(message: &[u8]) -> Result<ClientToClientMessage, CodecError> {
+        let (&message_type, payload) = message.split_first().ok_or(CodecError::Empty)?;
+
+        match message_type {
+            0x01 => {
+                let (epoch, public_key) = split_epoch(message_type, payload)?;
+                let public_key = public_key.try_into().map_err(|_| CodecError::BadLength((message_type, 36, payload.len())))?;
+                Ok(ClientToClientMessage::PublicKey((epoch, public_key)))
+            },
+            0x02 => {
+                let (epoch, key_part) = split_epoch(message_type, payload)?;
+                Ok(ClientToClientMessage::EncryptionKeyPart((epoch, key_part.to_vec())))
+            },
+            0x03 => {
+                let (length, text) = split_array::<4>(payload).ok_or(CodecError::Truncated((message_type, 4, payload.len())))?;
+                let length = u32::from_be_bytes(length) as usize;
+                if text.len() != length {
+                    return Err(CodecError::BadLength((message_type, 4 + length, payload.len())));
+                }
+                Ok(ClientToClientMessage::Message(text.to_vec()))
+            },
+            0x04 => Ok(ClientToClientMessage::ReadReceipt(payload.to_vec())),
+            0x05 => Ok(ClientToClientMessage::Breakout(payload.to_vec())),
+            0x06 => Ok(ClientToClientMessage::Probe),
+            0x07 => Ok(ClientToClientMessage::ProbeReply),
+            0x08 => Ok(ClientToClientMessage::Migration(payload.to_vec())),
+            0x09 => Ok(ClientToClientMessage::RingHash(payload.to_vec())),
+            0x0a => Ok(ClientToClientMessage::Chunk(payload.to_vec())),
+            0x0b => Ok(ClientToClientMessage::FileOffer(payload.to_vec())),
+            0x0c => Ok(ClientToClientMessage::FileChunk(payload.to_vec())),
+            0x0d => Ok(ClientToClientMessage::FileComplete(payload.to_vec())),
+            0x0e => Ok(ClientToClientMessage::DeliveryReceipt(payload.to_vec())),
+            _ => Err(CodecError::UnknownType(message_type)),
+        }
+    }
+}
+
+/// The epoch a key exchange message is tagged with and the rest of its payload
+fn split_epoch(message_type: u8, payload: &[u8]) -> Result<(Epoch, &[u8]), CodecError> {
+    let (epoch, rest) = split_array::<4>(payload).ok_or(CodecError::Truncated((message_type, 4, payload.len())))?;
+    Ok((Epoch::from_be_bytes(epoch), rest))
+}
+
+/// The first `N` bytes and the rest, `None` if there are fewer
+fn split_array<const N: usize>(data: &[u8]) -> Option<([u8; N], &[u8])> {
+    let (head, rest) = data.split_at_checked(N)?;
+    Some((head.try_into().ok()?, rest))
+}
+
+/// A ring signature followed by the signed message, as sent between peers:
+/// challenge, one response per ring member, key image, message
+pub struct SignedMessage<'a> {
+    pub signature: crypto::BLSAG_COMPACT,
+    /// compressed key image of the signer, as on the wire
+    pub key_image: KeyImage,
+    pub message: &'a [u8],
+}
+
+impl<'a> SignedMessage<'a> {
+    pub fn new(signature: crypto::BLSAG_COMPACT, message: &'a [u8]) -> SignedMessage<'a> {
+        let key_image = signature.key_image.compress().to_bytes();
+        SignedMessage { signature, key_image, message }
+    }
+
+    /// Length of the signature over a ring of `ring_size` members
+    pub fn signature_length(ring_size: usize) -> usize {
+        SCALAR_BYTE_SIZE * (ring_size + 2)
+    }
+
+    pub fn encoded_length(&self) -> usize {
+        SignedMessage::signature_length(self.signature.responses.len()) + self.message.len()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.encoded_length());
+        result.extend_from_slice(self.signature.challenge.as_bytes());
+        for response in self.signature.responses.iter() {
+            result.extend_from_slice(response.as_bytes());
+        }
+        result.extend_from_slice(&self.key_image);
+        result.extend_from_slice(self.message);
+        result
+    }
+
+    /// `ring_size` is the number of responses in the signature, the message is borrowed from `signed_message`
+    pub fn decode(signed_message: &'a [u8], ring_size: usize) -> Result<SignedMessage<'a>, CodecError> {
+        let signature_length = SignedMessage::signature_length(ring_size);
+        let (signature, message) = signed_message.split_at_checked(signature_length)
+            .ok_or(CodecError::TruncatedSignature((signature_length, signed_message.len())))?;
+        let mut chunks = signature.chunks_exact(SCALAR_BYTE_SIZE)
+            .map(|chunk| <[u8; SCALAR_BYTE_SIZE]>::try_from(chunk).unwrap_or_default());
+
+        // the challenge is followed by one response per ring member
+        let mut scalars = Vec::with_capacity(ring_size + 1);
+        for position in 0..=ring_size {
+            let bytes = chunks.next().ok_or(CodecError::TruncatedSignature((signature_length, signed_message.len())))?;
+            let scalar = Option::<Scalar>::from(Scalar::from_canonical_bytes(bytes)).ok_or(CodecError::NonCanonicalScalar(position))?;
+            scalars.push(scalar);
+        }
+        let challenge = scalars.remove(0);
+
+        // ristretto encodings are canonical, so the received bytes are kept instead of compressing the point again
+        let key_image = chunks.next().ok_or(CodecError::TruncatedSignature((signature_length, signed_message.len())))?;
+        let key_image_point = CompressedRistretto(key_image).decompress().ok_or(CodecError::InvalidKeyImage)?;
+
+        let signature = crypto::BLSAG_COMPACT {
+            challenge,
+            responses: scalars,
+            key_image: key_image_point,
+        };
+        Ok(SignedMessage { signature, key_image, message })
+    }
+}
+
+/// Decrypt a message of a peer into `decrypt_buffer` with the first of `keys` that fits and decode it
+pub fn read_message(keys: &[EncryptionKey], message: &[u8], decrypt_buffer: &mut Vec<u8>) -> Result<ClientToClientMessage, CodecError> {
+    if !keys.iter().any(|key| crypto::decrypt_message_into(key, message, decrypt_buffer).is_ok()) {
+        return Err(CodecError::Undecryptable);
+    }
+    ClientToClientMessage::decode(decrypt_buffer)
+}
+
+/// Check the ring signature of a signed message,
+/// returns the message, `true` if the signature is valid and the key image of the signer
+pub fn check_message_signature(ring: &[RistrettoPoint], signed_message: &[u8]) -> Result<(Vec<u8>, bool, KeyImage), CodecError> {
+    let signed_message = SignedMessage::decode(signed_message, ring.len())?;
+    let signature_valid = crypto::verify_message(&signed_message.signature, ring, signed_message.message);
+    Ok((signed_message.message.to_vec(), signature_valid, signed_message.key_image))
+}
+
+#[cfg(test)]
+mod tests {
+    use curve25519_dalek::constants::RISTRETTO_BASEPOINT_POINT;
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+    use crate::crypto::KEY_SIZE;
+
+    #[test]
+    fn test_client_to_client_message_decode() {
+        let messages = [
+            ClientToClientMessage::PublicKey((3, [7; 32])),
+            ClientToClientMessage::EncryptionKeyPart((3, vec![1; KEY_SIZE])),
+            ClientToClientMessage::Message(b"hello".to_vec()),
+            ClientToClientMessage::ReadReceipt(vec![2; 64]),
+            ClientToClientMessage::Breakout(vec![3; 80]),
+            ClientToClientMessage::Probe,
+            ClientToClientMessage::ProbeReply,
+            ClientToClientMessage::Migration(vec![4; 90]),
+            ClientToClientMessage::RingHash(vec![5; 100]),
+            ClientToClientMessage::Chunk(vec![6; 110]),
+            ClientToClientMessage::FileOffer(vec![7; 120]),
+            ClientToClientMessage::FileChunk(vec![8; 130]),
+            ClientToClientMessage::FileComplete(vec![9; 140]),
+            ClientToClientMessage::DeliveryReceipt(vec![10; 96]),
+        ];
+        for message in messages {
+            assert_eq!(ClientToClientMessage::decode(&message.encode().unwrap()), Ok(message));
+        }
+    }
+
+    #[test]
+    fn test_client_to_client_message_errors() {
+        assert_eq!(ClientToClientMessage::decode(&[]), Err(CodecError::Empty));
+        assert_eq!(ClientToClientMessage::decode(&[0x01; 36]), Err(CodecError::BadLength((0x01, 36, 35))));
+        assert_eq!(ClientToClientMessage::decode(&[0x02, 0, 0]), Err(CodecError::Truncated((0x02, 4, 2))));
+        assert_eq!(ClientToClientMessage::decode(&[0x03, 0, 0]), Err(CodecError::Truncated((0x03, 4, 2))));
+        assert_eq!(ClientToClientMessage::decode(&[0x03, 0, 0, 0, 6, b'h']), Err(CodecError::BadLength((0x03, 10, 5))));
+        assert_eq!(ClientToClientMessage::decode(&[0x0f]), Err(CodecError::UnknownType(0x0f)));
+    }
+
+    #[test]
+    fn test_read_message() {
+        let message = ClientToClientMessage::Message(b"hello".to_vec());
+        let encrypted = crypto::encrypt_message(&message.encode().unwrap(), &[1; KEY_SIZE]).unwrap().encode();
+        let mut decrypt_buffer = Vec::new();
+        // the key that fits need not be the first
+        assert_eq!(read_message(&[[2; KEY_SIZE], [1; KEY_SIZE]], &encrypted, &mut decrypt_buffer), Ok(message));
+        assert_eq!(read_message(&[[2; KEY_SIZE]], &encrypted, &mut decrypt_buffer), Err(CodecError::Undecryptable));
+        assert_eq!(read_message(&[], &encrypted, &mut decrypt_buffer), Err(CodecError::Undecryptable));
+
+        let empty = crypto::encrypt_message(&[], &[1; KEY_SIZE]).unwrap().encode();
+        assert_eq!(read_message(&[[1; KEY_SIZE]], &empty, &mut decrypt_buffer), Err(CodecError::Empty));
+    }
+
+    fn client_to_client_message() -> impl Strategy<Value = ClientToClientMessage> {
+        prop_oneof![
+            any::<(Epoch, [u8; 32])>().prop_map(ClientToClientMessage::PublicKey),
+            (any::<Epoch>(), vec(any::<u8>(), 0..64)).prop_map(ClientToClientMessage::EncryptionKeyPart),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::Message),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::ReadReceipt),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::Breakout),
+            Just(ClientToClientMessage::Probe),
+            Just(ClientToClientMessage::ProbeReply),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::Migration),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::RingHash),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::Chunk),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::FileOffer),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::FileChunk),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::FileComplete),
+            vec(any::<u8>(), 0..256).prop_map(ClientToClientMessage::DeliveryReceipt),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn proptest_client_to_client_message_round_trip(message in client_to_client_message()) {
+            prop_assert_eq!(ClientToClientMessage::decode(&message.encode().unwrap()), Ok(message));
+        }
+
+        #[test]
+        fn proptest_client_to_client_message_decode(data in vec(any::<u8>(), 0..512)) {
+            // whatever decodes survives another round trip
+            if let Ok(message) = ClientToClientMessage::decode(&data) {
+                prop_assert_eq!(ClientToClientMessage::decode(&message.encode().unwrap()), Ok(message));
+            }
+        }
+
+        #[test]
+        fn proptest_signed_message_round_trip(
+            challenge in any::<[u8; 32]>(),
+            responses in vec(any::<[u8; 32]>(), 1..8),
+            key_image in any::<[u8; 32]>(),
+            message in vec(any::<u8>(), 0..128),
+        ) {
+            let signature = crypto::BLSAG_COMPACT {
+                challenge: Scalar::from_bytes_mod_order(challenge),
+                responses: responses.iter().map(|response| Scalar::from_bytes_mod_order(*response)).collect(),
+                key_image: Scalar::from_bytes_mod_order(key_image) * RISTRETTO_BASEPOINT_POINT,
+            };
+            let signed_message = SignedMessage::new(signature, &message);
+            let encoded = signed_message.encode();
+            prop_assert_eq!(encoded.len(), signed_message.encoded_length());
+
+            let decoded = SignedMessage::decode(&encoded, responses.len()).unwrap();
+            prop_assert_eq!(decoded.signature.challenge, signed_message.signature.challenge);
+            prop_assert_eq!(&decoded.signature.responses, &signed_message.signature.responses);
+            prop_assert_eq!(decoded.key_image, signed_message.key_image);
+            prop_assert_eq!(decoded.message, &message[..]);
+        }
+
+        #[test]
+        fn proptest_signed_message_decode(data in vec(any::<u8>(), 0..512), ring_size in 0..8usize) {
+            if let Ok(signed_message) = SignedMessage::decode(&data, ring_size) {
+                prop_assert_eq!(signed_message.encode(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_signed_message() {
+        let mut csprng = rand_core::OsRng;
+        let private_key = Scalar::random(&mut csprng);
+        let ring = vec![RistrettoPoint::random(&mut csprng), private_key * RISTRETTO_BASEPOINT_POINT];
+        let signature = crypto::sign_message(&private_key, 1, &ring, b"hi");
+        let encoded = SignedMessage::new(signature, b"hi").encode();
+        assert_eq!(encoded.len(), SignedMessage::signature_length(ring.len()) + 2);
+
+        let decoded = SignedMessage::decode(&encoded, ring.len()).unwrap();
+        assert_eq!(decoded.message, b"hi");
+        assert_eq!(decoded.encoded_length(), encoded.len());
+        assert!(crypto::verify_message(&decoded.signature, &ring, decoded.message));
+        assert_eq!(decoded.encode(), encoded);
+
+        let (message, signature_valid, _) = check_message_signature(&ring, &encoded).unwrap();
+        assert_eq!(message, b"hi");
+        assert!(signature_valid);
+    }
+
+    #[test]
+    fn test_signed_message_errors() {
+        assert_eq!(SignedMessage::decode(&[0; 32 * 4 - 1], 2).err(), Some(CodecError::TruncatedSignature((32 * 4, 32 * 4 - 1))));
+        assert_eq!(SignedMessage::decode(&[0xff; 32 * 4], 2).err(), Some(CodecError::NonCanonicalScalar(0)));
+        let mut responses = [0; 32 * 4];
+        responses[32..64].fill(0xff);
+        assert_eq!(SignedMessage::decode(&responses, 2).err(), Some(CodecError::NonCanonicalScalar(1)));
+        // the key image is not a valid encoding
+        let mut key_image = [0; 32 * 4];
+        key_image[32 * 3..].fill(0xff);
+        assert_eq!(SignedMessage::decode(&key_image, 2).err(), Some(CodecError::InvalidKeyImage));
+    }
+}
@@ -0,0 +1,396 @@
+//! A minimal in-process implementation of the server protocol for end-to-end tests.
+//!
+//! Clients connect with [`crate::state_manager::start_state_manager_plaintext`], no TLS certificates are needed.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use async_std::{
+    io::{self, ReadExt, WriteExt},
+    net::{TcpListener, TcpStream},
+    task,
+};
+use futures::{channel::mpsc, StreamExt};
+use log::debug;
+use crate::constants::{
+    ConferenceEncryptionSalt, ConferenceId, ConferenceJoinSalt, PasswordHash, Sender,
+    ServerToClientMessageTypePrimitive as ServerMessage, PROTOCOL_HEADER,
+};
+
+type ClientId = usize;
+
+struct MockConference {
+    password_hash: PasswordHash,
+    join_salt: ConferenceJoinSalt,
+    encryption_salt: ConferenceEncryptionSalt,
+    members: HashSet<ClientId>,
+}
+
+#[derive(Default)]
+struct ServerState {
+    clients: HashMap<ClientId, Sender<Vec<u8>>>,
+    conferences: HashMap<ConferenceId, MockConference>,
+    last_client_id: ClientId,
+    last_conference_id: ConferenceId,
+}
+
+impl ServerState {
+    fn send_to(&self, client_id: ClientId, frame: Vec<u8>) {
+        if let Some(sender) = self.clients.get(&client_id) {
+            let _ = sender.unbounded_send(frame);
+        }
+    }
+
+    /// Send a frame to every member of the conference except `except`
+    fn broadcast(&self, conference_id: ConferenceId, except: ClientId, frame: &[u8]) {
+        if let Some(conference) = self.conferences.get(&conference_id) {
+            for member in conference.members.iter().filter(|member| **member != except) {
+                self.send_to(*member, frame.to_vec());
+            }
+        }
+    }
+
+    fn notify_restructuring(&self, conference_id: ConferenceId, except: ClientId) {
+        if let Some(conference) = self.conferences.get(&conference_id) {
+            let number_of_peers = conference.members.len() as u32;
+            self.broadcast(conference_id, except, &frame(ServerMessage::ConferenceRestructuring, &[&conference_id.to_be_bytes(), &number_of_peers.to_be_bytes()]));
+        }
+    }
+
+    fn remove_client(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id);
+        let conference_ids: Vec<ConferenceId> = self.conferences.iter()
+            .filter(|(_, conference)| conference.members.contains(&client_id))
+            .map(|(conference_id, _)| *conference_id)
+            .collect();
+        for conference_id in conference_ids {
+            self.conferences.get_mut(&conference_id).unwrap().members.remove(&client_id);
+            self.notify_restructuring(conference_id, client_id);
+        }
+    }
+}
+
+pub struct MockServer {
+    address: SocketAddr,
+}
+
+impl MockServer {
+    /// Start listening on a random local port
+    pub async fn start() -> io::Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(ServerState::default()));
+        task::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                let state = state.clone();
+                task::spawn(async move {
+                    if let Err(e) = handle_client(stream, state).await {
+                        debug!("Mock server client disconnected: {}", e);
+                    }
+                });
+            }
+        });
+        Ok(MockServer { address })
+    }
+
+    pub fn address(&self) -> String {
+        self.address.to_string()
+    }
+}
+
+fn frame(message_type: ServerMessage, parts: &[&[u8]]) -> Vec<u8> {
+    let mut result = vec![message_type as u8];
+    for part in parts {
+        result.extend_from_slice(part);
+    }
+    result
+}
+
+async fn read_u32(stream: &mut TcpStream) -> io::Result<u32> {
+    let mut buffer = [0u8; 4];
+    stream.read_exact(&mut buffer).await?;
+    Ok(u32::from_be_bytes(buffer))
+}
+
+async fn read_array<const N: usize>(stream: &mut TcpStream) -> io::Result<[u8; N]> {
+    let mut buffer = [0u8; N];
+    stream.read_exact(&mut buffer).await?;
+    Ok(buffer)
+}
+
+async fn handle_client(mut stream: TcpStream, state: Arc<Mutex<ServerState>>) -> io::Result<()> {
+    let mut header = vec![0u8; PROTOCOL_HEADER.len()];
+    stream.read_exact(&mut header).await?;
+    if header != PROTOCOL_HEADER {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid protocol header"));
+    }
+    stream.write_all(&[ServerMessage::HandshakeAcknowledged as u8]).await?;
+
+    let (frame_sender, mut frame_receiver) = mpsc::unbounded::<Vec<u8>>();
+    let client_id = {
+        let mut state = state.lock().unwrap();
+        state.last_client_id += 1;
+        let client_id = state.last_client_id;
+        state.clients.insert(client_id, frame_sender);
+        client_id
+    };
+    let mut writer = stream.clone();
+    task::spawn(async move {
+        while let Some(frame) = frame_receiver.next().await {
+            if writer.write_all(&frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let result = handle_client_events(&mut stream, client_id, &state).await;
+    state.lock().unwrap().remove_client(client_id);
+    result
+}
+
+async fn handle_client_events(stream: &mut TcpStream, client_id: ClientId, state: &Mutex<ServerState>) -> io::Result<()> {
+    loop {
+        let [event_type] = read_array::<1>(stream).await?;
+        match event_type {
+            0x01 => {
+                // CreateConference
+                let nonce = read_u32(stream).await?;
+                let password_hash = read_array::<32>(stream).await?;
+                let join_salt = read_array::<32>(stream).await?;
+                let encryption_salt = read_array::<32>(stream).await?;
+                let mut state = state.lock().unwrap();
+                state.last_conference_id += 1;
+                let conference_id = state.last_conference_id;
+                state.conferences.insert(conference_id, MockConference { password_hash, join_salt, encryption_salt, members: HashSet::new() });
+                state.send_to(client_id, frame(ServerMessage::ConferenceCreated, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes()]));
+            },
+            0x02 => {
+                // GetConferenceJoinSalt
+                let nonce = read_u32(stream).await?;
+                let conference_id = read_u32(stream).await?;
+                let state = state.lock().unwrap();
+                let reply = match state.conferences.get(&conference_id) {
+                    Some(conference) => frame(ServerMessage::ConferenceJoinSalt, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes(), &conference.join_salt]),
+                    None => frame(ServerMessage::ConferenceJoinSaltError, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes()]),
+                };
+                state.send_to(client_id, reply);
+            },
+            0x03 => {
+                // JoinConference
+                let nonce = read_u32(stream).await?;
+                let conference_id = read_u32(stream).await?;
+                let password_hash = read_array::<32>(stream).await?;
+                let mut state = state.lock().unwrap();
+                let joined = match state.conferences.get_mut(&conference_id) {
+                    Some(conference) if conference.password_hash == password_hash && !conference.members.contains(&client_id) => {
+                        conference.members.insert(client_id);
+                        Some((conference.members.len() as u32, conference.encryption_salt))
+                    },
+                    _ => None,
+                };
+                if let Some((number_of_peers, encryption_salt)) = joined {
+                    state.notify_restructuring(conference_id, client_id);
+                    state.send_to(client_id, frame(ServerMessage::ConferenceJoined, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes(), &number_of_peers.to_be_bytes(), &encryption_salt]));
+                } else {
+                    state.send_to(client_id, frame(ServerMessage::ConferenceJoinError, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes()]));
+                }
+            },
+            0x04 => {
+                // LeaveConference
+                let nonce = read_u32(stream).await?;
+                let conference_id = read_u32(stream).await?;
+                let mut state = state.lock().unwrap();
+                let left = state.conferences.get_mut(&conference_id).is_some_and(|conference| conference.members.remove(&client_id));
+                if left {
+                    state.notify_restructuring(conference_id, client_id);
+                    state.send_to(client_id, frame(ServerMessage::ConferenceLeft, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes()]));
+                } else {
+                    state.send_to(client_id, frame(ServerMessage::ConferenceLeaveError, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes()]));
+                }
+            },
+            0x05 => {
+                // SendMessage
+                let nonce = read_u32(stream).await?;
+                let conference_id = read_u32(stream).await?;
+                let message_length = read_u32(stream).await?;
+                let mut message = vec![0u8; message_length as usize];
+                stream.read_exact(&mut message).await?;
+                let state = state.lock().unwrap();
+                if state.conferences.get(&conference_id).is_some_and(|conference| conference.members.contains(&client_id)) {
+                    state.broadcast(conference_id, client_id, &frame(ServerMessage::IncomingMessage, &[&conference_id.to_be_bytes(), &message_length.to_be_bytes(), &message]));
+                    state.send_to(client_id, frame(ServerMessage::MessageAccepted, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes()]));
+                } else {
+                    state.send_to(client_id, frame(ServerMessage::MessageError, &[&nonce.to_be_bytes(), &conference_id.to_be_bytes()]));
+                }
+            },
+            0x06 => {
+                // Disconnect
+                return Ok(());
+            },
+            0x0A => {
+                // Ping
+                let number = read_u32(stream).await?;
+                state.lock().unwrap().send_to(client_id, frame(ServerMessage::Pong, &[&number.to_be_bytes()]));
+            },
+            0x0B => {
+                // Pong
+                read_u32(stream).await?;
+            },
+            _ => {
+                state.lock().unwrap().send_to(client_id, frame(ServerMessage::GeneralError, &[]));
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid client event"));
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::SinkExt;
+    use super::*;
+    use crate::{
+        constants::{DisconnectReason, Receiver, UIAction, UIEvent},
+        oneshot::{self, Oneshot},
+        state_manager,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn start_client(server: &MockServer) -> (Sender<UIAction>, Receiver<UIEvent>) {
+        let (ui_event_sender, ui_event_receiver) = mpsc::unbounded();
+        let (ui_action_sender, ui_action_receiver) = mpsc::unbounded();
+        let server_address = server.address();
+        task::spawn(state_manager::start_state_manager_plaintext(server_address, ui_event_sender, ui_action_receiver));
+        (ui_action_sender, ui_event_receiver)
+    }
+
+    /// Wait for the first event for which `f` returns `Some`
+    async fn wait_for<T>(receiver: &mut Receiver<UIEvent>, f: impl Fn(&UIEvent) -> Option<T>) -> T {
+        async_std::future::timeout(TIMEOUT, async {
+            loop {
+                let event = receiver.next().await.expect("state manager exited");
+                if let Some(value) = f(&event) {
+                    return value;
+                }
+            }
+        }).await.expect("timed out waiting for event")
+    }
+
+    #[async_std::test]
+    async fn test_two_clients_exchange_message() {
+        let server = MockServer::start().await.unwrap();
+        let (mut alice_actions, mut alice_events) = start_client(&server);
+        let (mut bob_actions, mut bob_events) = start_client(&server);
+
+        alice_actions.send(UIAction::CreateConference("password".to_string())).await.unwrap();
+        let conference_id = wait_for(&mut alice_events, |e| match e { UIEvent::ConferenceCreated(id) => Some(*id), _ => None }).await;
+
+        alice_actions.send(UIAction::JoinConference((conference_id, "password".to_string()))).await.unwrap();
+        wait_for(&mut alice_events, |e| match e { UIEvent::ConferenceJoined((id, 1)) if *id == conference_id => Some(()), _ => None }).await;
+        bob_actions.send(UIAction::JoinConference((conference_id, "password".to_string()))).await.unwrap();
+        wait_for(&mut bob_events, |e| match e { UIEvent::ConferenceJoined((id, 2)) if *id == conference_id => Some(()), _ => None }).await;
+
+        wait_for(&mut alice_events, |e| match e { UIEvent::ConferenceRestructuringFinished(id) if *id == conference_id => Some(()), _ => None }).await;
+        wait_for(&mut bob_events, |e| match e { UIEvent::ConferenceRestructuringFinished(id) if *id == conference_id => Some(()), _ => None }).await;
+
+        bob_actions.send(UIAction::SendMessage((conference_id, 1, "hello".to_string()))).await.unwrap();
+        wait_for(&mut bob_events, |e| match e { UIEvent::MessageAccepted((id, 1)) if *id == conference_id => Some(()), _ => None }).await;
+        let (message, signature_valid) = wait_for(&mut alice_events, |e| match e {
+            UIEvent::IncomingMessage((id, message, signature_valid, _)) if *id == conference_id => Some((message.clone(), *signature_valid)),
+            _ => None,
+        }).await;
+        assert_eq!(&message[..], b"hello");
+        assert!(signature_valid);
+    }
+
+    #[async_std::test]
+    async fn test_join_with_wrong_password_fails() {
+        let server = MockServer::start().await.unwrap();
+        let (mut actions, mut events) = start_client(&server);
+
+        actions.send(UIAction::CreateConference("password".to_string())).await.unwrap();
+        let conference_id = wait_for(&mut events, |e| match e { UIEvent::ConferenceCreated(id) => Some(*id), _ => None }).await;
+        actions.send(UIAction::JoinConference((conference_id, "wrong".to_string()))).await.unwrap();
+        // the password is hashed on the blocking pool before the join request is sent
+        wait_for(&mut events, |e| match e { UIEvent::DerivingKey(Some(id)) if *id == conference_id => Some(()), _ => None }).await;
+        wait_for(&mut events, |e| match e { UIEvent::ConferenceJoinFailed(id) if *id == conference_id => Some(()), _ => None }).await;
+    }
+
+    #[async_std::test]
+    async fn test_cancelled_join_is_not_joined() {
+        let server = MockServer::start().await.unwrap();
+        let (mut actions, mut events) = start_client(&server);
+
+        actions.send(UIAction::CreateConference("password".to_string())).await.unwrap();
+        let conference_id = wait_for(&mut events, |e| match e { UIEvent::ConferenceCreated(id) => Some(*id), _ => None }).await;
+        actions.send(UIAction::JoinConference((conference_id, "password".to_string()))).await.unwrap();
+        let packet_nonce = wait_for(&mut events, |e| match e { UIEvent::RequestPending((nonce, Some(_))) => Some(*nonce), _ => None }).await;
+        actions.send(UIAction::CancelRequest(packet_nonce)).await.unwrap();
+        wait_for(&mut events, |e| match e { UIEvent::RequestCancelled(nonce) if *nonce == packet_nonce => Some(()), _ => None }).await;
+
+        // a join answered after the cancellation is left again, so joining once more succeeds
+        actions.send(UIAction::JoinConference((conference_id, "password".to_string()))).await.unwrap();
+        let second_nonce = wait_for(&mut events, |e| match e {
+            UIEvent::ConferenceJoined(_) => panic!("the cancelled join was reported"),
+            UIEvent::RequestPending((nonce, Some(_))) => Some(*nonce),
+            _ => None,
+        }).await;
+        assert_ne!(second_nonce, packet_nonce);
+        wait_for(&mut events, |e| match e { UIEvent::ConferenceJoined((id, _)) if *id == conference_id => Some(()), _ => None }).await;
+    }
+
+    #[async_std::test]
+    async fn test_state_manager_disconnects_when_ui_is_gone() {
+        let server = MockServer::start().await.unwrap();
+        let (ui_event_sender, mut events) = mpsc::unbounded();
+        let (mut actions, ui_action_receiver) = mpsc::unbounded();
+        let state_manager = task::spawn(state_manager::start_state_manager_plaintext(server.address(), ui_event_sender, ui_action_receiver));
+
+        actions.send(UIAction::CreateConference("password".to_string())).await.unwrap();
+        let conference_id = wait_for(&mut events, |e| match e { UIEvent::ConferenceCreated(id) => Some(*id), _ => None }).await;
+        actions.send(UIAction::JoinConference((conference_id, "password".to_string()))).await.unwrap();
+        wait_for(&mut events, |e| match e { UIEvent::ConferenceJoined((id, _)) if *id == conference_id => Some(()), _ => None }).await;
+
+        // the next event for the UI finds the channel closed
+        drop(events);
+        actions.send(UIAction::ListPlugins).await.unwrap();
+        async_std::future::timeout(TIMEOUT, state_manager).await.expect("state manager did not exit");
+    }
+    #[async_std::test]
+    async fn test_disconnect_reports_reason() {
+        let server = MockServer::start().await.unwrap();
+        let (mut actions, mut events) = start_client(&server);
+
+        actions.send(UIAction::ListConferences).await.unwrap();
+        wait_for(&mut events, |e| match e { UIEvent::ConferenceDirectory(_) => Some(()), _ => None }).await;
+        actions.send(UIAction::Disconnect).await.unwrap();
+        let reason = wait_for(&mut events, |e| match e { UIEvent::Disconnected(reason) => Some(reason.clone()), _ => None }).await;
+        assert_eq!(reason, DisconnectReason::UserRequested);
+    }
+
+    #[async_std::test]
+    async fn test_oneshot_commands() {
+        let server = MockServer::start().await.unwrap();
+        let (mut actions, mut events) = start_client(&server);
+        let mut script = Oneshot::connect_plaintext(server.address());
+
+        let conference_id = async_std::future::timeout(TIMEOUT, script.create("password".to_string())).await.unwrap().unwrap();
+        actions.send(UIAction::JoinConference((conference_id, "password".to_string()))).await.unwrap();
+        wait_for(&mut events, |e| match e { UIEvent::ConferenceJoined((id, 1)) if *id == conference_id => Some(()), _ => None }).await;
+
+        let join = script.join(conference_id + 1, "password".to_string());
+        let error = async_std::future::timeout(TIMEOUT, join).await.unwrap().unwrap_err();
+        assert_eq!(error.exit_code(), oneshot::EXIT_REFUSED);
+        async_std::future::timeout(TIMEOUT, script.join(conference_id, "password".to_string())).await.unwrap().unwrap();
+        async_std::future::timeout(TIMEOUT, script.send(conference_id, "hello".to_string())).await.unwrap().unwrap();
+        let message = wait_for(&mut events, |e| match e { UIEvent::IncomingMessage((id, message, _, _)) if *id == conference_id => Some(message.clone()), _ => None }).await;
+        assert_eq!(&message[..], b"hello");
+        script.disconnect().await;
+    }
+}
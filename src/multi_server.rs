@@ -0,0 +1,707 @@
+//! Sessions with several servers at once. Every server gets its own state manager, the UI actions are routed
+//! to the session hosting the conference and the UI events of all sessions are merged into one stream.
+//!
+//! Conferences are kept by session and the id assigned by its server. The UIs see the id of the server, or another
+//! unused id when a conference on another server already has it, together with a [`UIEvent::ConferenceServer`]
+//! before the conference is joined. Joining takes the id of the server, like created, listed and announced
+//! conferences, every other action and event carries the id shown to the UI.
+//! Creating, joining and listing conferences goes to the server selected with [`UIAction::SelectServer`],
+//! breakouts are joined on the server they were announced on.
+//!
+//! Moving a conference to another server connects to it if needed, creates and joins a conference there,
+//! announces it to the ring of the old conference and leaves the old one, see [`MigrationStage`].
+//! While a conference is moved to a server, conferences created on it by the user are taken for the move.
+
+use std::collections::HashMap;
+
+use futures::{channel::mpsc, select, stream, SinkExt, StreamExt};
+use log::{info, warn};
+use crate::{
+    connection_manager::ServerCapabilities,
+    constants::{ConferenceId, Migration, MigrationStage, PacketNonce, Receiver, Sender, UIAction, UIEvent, MIGRATION_MESSAGE_ID},
+    crypto,
+    runtime,
+    state_manager,
+};
+
+type SessionEvents = stream::BoxStream<'static, (usize, UIEvent)>;
+
+struct Session {
+    server_address: String,
+    action_sender: Sender<UIAction>,
+    capabilities: Option<ServerCapabilities>,
+    connected: bool,
+}
+
+/// A conference being moved to another server
+struct MigrationState {
+    old_session: usize,
+    new_session: usize,
+    /// where the conference moves to, the conference id is only known once it was created
+    target: Migration,
+    /// `false` when following a peer, the new conference exists already
+    announce: bool,
+    stage: MigrationStage,
+}
+
+struct Router {
+    sessions: Vec<Session>,
+    /// index of the session new conferences are created and joined on
+    selected: usize,
+    /// the session hosting each joined conference, by the id shown to the UI
+    conferences: HashMap<ConferenceId, usize>,
+    /// the id shown to the UI of the joined and pending conferences by `(session, id on its server)`
+    ui_ids: HashMap<(usize, ConferenceId), ConferenceId>,
+    /// the session a breakout was announced on, it is hosted by the same server as its parent
+    breakouts: HashMap<ConferenceId, usize>,
+    /// by the id of the old conference shown to the UI
+    migrations: HashMap<ConferenceId, MigrationState>,
+    /// `(session, nonce of the session, conference)` of the cancellable requests by the nonce shown to the UI,
+    /// the sessions count their nonces independently
+    requests: HashMap<PacketNonce, (usize, PacketNonce, Option<ConferenceId>)>,
+    last_request: PacketNonce,
+    /// events of the sessions started since the streams were last merged
+    new_sessions: Vec<SessionEvents>,
+    ui_event_sink: Sender<UIEvent>,
+}
+
+/// Connect to all servers, the first one is selected
+pub async fn start_state_managers(server_addresses: Vec<String>, ui_event_sink: Sender<UIEvent>, ui_action_receiver: Receiver<UIAction>) {
+    let mut router = Router::new(ui_event_sink);
+    for server_address in server_addresses {
+        router.start_session(server_address);
+    }
+    if router.sessions.len() > 1 {
+        info!("Connecting to {} servers", router.sessions.len());
+    }
+    run_router(&mut router, ui_action_receiver).await;
+}
+
+async fn run_router(router: &mut Router, mut ui_action_receiver: Receiver<UIAction>) {
+    let mut events = stream::SelectAll::new();
+    loop {
+        events.extend(router.new_sessions.drain(..));
+        select! {
+            event = events.next() => match event {
+                Some((session, event)) => {
+                    if !router.forward(session, event).await {
+                        break;
+                    }
+                },
+                None => break,
+            },
+            action = ui_action_receiver.next() => match action {
+                Some(action) => router.route(action).await,
+                None => {
+                    // the UI is gone, close all sessions
+                    router.broadcast(|| UIAction::Disconnect).await;
+                    break;
+                },
+            },
+        }
+    }
+}
+
+impl Router {
+    fn new(ui_event_sink: Sender<UIEvent>) -> Self {
+        Router {
+            sessions: Vec::new(),
+            selected: 0,
+            conferences: HashMap::new(),
+            ui_ids: HashMap::new(),
+            breakouts: HashMap::new(),
+            migrations: HashMap::new(),
+            requests: HashMap::new(),
+            last_request: 0,
+            new_sessions: Vec::new(),
+            ui_event_sink,
+        }
+    }
+
+    fn start_session(&mut self, server_address: String) -> usize {
+        let (action_sender, action_receiver) = mpsc::unbounded();
+        let (event_sender, event_receiver) = mpsc::unbounded();
+        let session = self.sessions.len();
+        let address = server_address.clone();
+        runtime::spawn(async move {
+            state_manager::start_state_manager(address, event_sender, action_receiver).await;
+        });
+        self.sessions.push(Session { server_address, action_sender, capabilities: None, connected: true });
+        self.new_sessions.push(event_receiver.map(move |event| (session, event)).boxed());
+        session
+    }
+
+    /// The session with a server, connecting to it if there is none
+    fn session_for(&mut self, server_address: &str) -> usize {
+        match self.sessions.iter().position(|session| session.connected && session.server_address == server_address) {
+            Some(session) => session,
+            None => {
+                info!("Connecting to {}", server_address);
+                self.start_session(server_address.to_string())
+            },
+        }
+    }
+
+    /// Pass an event of a session on to the UI, returns `false` once the UI is gone or all sessions ended
+    async fn forward(&mut self, session: usize, mut event: UIEvent) -> bool {
+        // a created conference is joined with the id of its server
+        if !matches!(event, UIEvent::ConferenceCreated(_)) {
+            if let Some(conference_id) = event.conference_id_mut() {
+                *conference_id = self.ui_id(session, *conference_id);
+            }
+        }
+        match &event {
+            UIEvent::RequestPending((packet_nonce, conference_id)) => {
+                let conference_id = conference_id.map(|conference_id| self.ui_id(session, conference_id));
+                // the steps of a move are not the user's to cancel
+                let migrating = match conference_id {
+                    Some(conference_id) => self.migration_to(session, conference_id).is_some(),
+                    None => self.migration_at(session, MigrationStage::Creating).is_some(),
+                };
+                if migrating {
+                    return true;
+                }
+                self.last_request = self.last_request.wrapping_add(1).max(1);
+                self.requests.insert(self.last_request, (session, *packet_nonce, conference_id));
+                return self.ui_event_sink.send(UIEvent::RequestPending((self.last_request, conference_id))).await.is_ok();
+            },
+            UIEvent::RequestCancelled(packet_nonce) => {
+                let Some(request) = self.request_of(session, |&(_, nonce, _)| nonce == *packet_nonce)
+                else {
+                    return true;
+                };
+                if let Some((_, _, Some(conference_id))) = self.requests.remove(&request) {
+                    self.forget(session, conference_id);
+                }
+                return self.ui_event_sink.send(UIEvent::RequestCancelled(request)).await.is_ok();
+            },
+            UIEvent::ConferenceCreated(conference_id) => {
+                self.finish_request(session, None);
+                if let Some(old_conference_id) = self.migration_at(session, MigrationStage::Creating) {
+                    let migration = self.migrations.get_mut(&old_conference_id).unwrap();
+                    migration.target.conference_id = *conference_id;
+                    let join = UIAction::JoinConference((*conference_id, migration.target.password.clone()));
+                    let _ = self.sessions[session].action_sender.send(join).await;
+                    return self.progress(old_conference_id, MigrationStage::Joining).await;
+                }
+            },
+            UIEvent::ConferenceCreateFailed => {
+                self.finish_request(session, None);
+                if let Some(old_conference_id) = self.migration_at(session, MigrationStage::Creating) {
+                    return self.progress(old_conference_id, MigrationStage::Failed("The conference could not be created".to_string())).await;
+                }
+            },
+            UIEvent::ConferenceJoined((conference_id, number_of_peers)) => {
+                self.finish_request(session, Some(*conference_id));
+                self.conferences.insert(*conference_id, session);
+                if self.sessions.len() > 1 {
+                    let server_address = self.sessions[session].server_address.clone();
+                    if self.ui_event_sink.send(UIEvent::ConferenceServer((*conference_id, server_address))).await.is_err() {
+                        return false;
+                    }
+                }
+                if self.ui_event_sink.send(UIEvent::ConferenceJoined((*conference_id, *number_of_peers))).await.is_err() {
+                    return false;
+                }
+                let Some(old_conference_id) = self.migration_to(session, *conference_id)
+                else {
+                    return true;
+                };
+                let migration = &self.migrations[&old_conference_id];
+                let (next_stage, action) = if migration.announce {
+                    (MigrationStage::Announcing, UIAction::AnnounceMigration((old_conference_id, migration.target.clone())))
+                } else {
+                    (MigrationStage::Leaving, UIAction::LeaveConference(old_conference_id))
+                };
+                self.route(action).await;
+                return self.progress(old_conference_id, next_stage).await;
+            },
+            UIEvent::ConferenceJoinFailed(conference_id) => {
+                self.finish_request(session, Some(*conference_id));
+                if let Some(old_conference_id) = self.migration_to(session, *conference_id) {
+                    let failed = MigrationStage::Failed("The new conference could not be joined".to_string());
+                    if !self.progress(old_conference_id, failed).await {
+                        return false;
+                    }
+                }
+                self.forget(session, *conference_id);
+            },
+            UIEvent::MessageAccepted((conference_id, MIGRATION_MESSAGE_ID)) => {
+                if self.migrations.get(conference_id).is_some_and(|migration| migration.stage == MigrationStage::Announcing) {
+                    self.route(UIAction::LeaveConference(*conference_id)).await;
+                    return self.progress(*conference_id, MigrationStage::Leaving).await;
+                }
+                return true;
+            },
+            UIEvent::MessageRejected((conference_id, MIGRATION_MESSAGE_ID))
+            | UIEvent::MessageError((conference_id, MIGRATION_MESSAGE_ID))
+            | UIEvent::MessageTimedOut((conference_id, MIGRATION_MESSAGE_ID))
+            | UIEvent::MessageTooLarge((conference_id, MIGRATION_MESSAGE_ID, _)) => {
+                if self.migrations.contains_key(conference_id) {
+                    return self.progress(*conference_id, MigrationStage::Failed("The move could not be announced".to_string())).await;
+                }
+                return true;
+            },
+            UIEvent::ConferenceLeft(conference_id) => {
+                let conference_id = *conference_id;
+                self.conferences.remove(&conference_id);
+                self.forget(session, conference_id);
+                if self.migrations.get(&conference_id).is_some_and(|migration| migration.stage == MigrationStage::Leaving) {
+                    if self.ui_event_sink.send(event).await.is_err() {
+                        return false;
+                    }
+                    let target = self.migrations[&conference_id].target.clone();
+                    return self.progress(conference_id, MigrationStage::Finished(target)).await;
+                }
+            },
+            UIEvent::ConferenceLeaveFailed(conference_id) => {
+                if self.migrations.get(conference_id).is_some_and(|migration| migration.stage == MigrationStage::Leaving) {
+                    let failed = MigrationStage::Failed("The old conference could not be left".to_string());
+                    if !self.progress(*conference_id, failed).await {
+                        return false;
+                    }
+                }
+            },
+            UIEvent::BreakoutAnnounced((_, breakout, _)) => {
+                self.breakouts.insert(breakout.conference_id, session);
+            },
+            UIEvent::ServerCapabilities(capabilities) => {
+                self.sessions[session].capabilities = Some(*capabilities);
+                if session != self.selected {
+                    return true;
+                }
+            },
+            // every session loads the same plugins
+            UIEvent::PluginList(_) if session != 0 => return true,
+            UIEvent::Disconnected(reason) => {
+                self.sessions[session].connected = false;
+                self.requests.retain(|_, (s, _, _)| *s != session);
+                self.ui_ids.retain(|&(s, _), _| s != session);
+                let server_address = self.sessions[session].server_address.clone();
+                info!("Session with {} ended: {}", server_address, reason);
+                let failed: Vec<ConferenceId> = self.migrations.iter()
+                    .filter(|(_, migration)| migration.old_session == session || migration.new_session == session)
+                    .map(|(&conference_id, _)| conference_id)
+                    .collect();
+                for conference_id in failed {
+                    if !self.progress(conference_id, MigrationStage::Failed(reason.to_string())).await {
+                        return false;
+                    }
+                }
+                if self.sessions.iter().any(|session| session.connected) {
+                    let left: Vec<ConferenceId> = self.conferences.iter().filter(|(_, &s)| s == session).map(|(&id, _)| id).collect();
+                    for conference_id in left {
+                        self.conferences.remove(&conference_id);
+                        if self.ui_event_sink.send(UIEvent::ConferenceLeft(conference_id)).await.is_err() {
+                            return false;
+                        }
+                    }
+                    return self.ui_event_sink.send(UIEvent::ServerDisconnected((server_address, reason.clone()))).await.is_ok();
+                }
+                let _ = self.ui_event_sink.send(event).await;
+                return false;
+            },
+            _ => {},
+        }
+        self.ui_event_sink.send(event).await.is_ok()
+    }
+
+    async fn route(&mut self, mut action: UIAction) {
+        let session = match &mut action {
+            UIAction::JoinConference((conference_id, _)) => {
+                let session = self.breakouts.get(conference_id).copied().unwrap_or(self.selected);
+                if let Some(ui_id) = self.ui_ids.get(&(session, *conference_id)).copied().filter(|ui_id| self.conferences.contains_key(ui_id)) {
+                    warn!("Conference {} is already joined on {}", conference_id, self.sessions[session].server_address);
+                    let _ = self.ui_event_sink.send(UIEvent::ConferenceJoinFailed(ui_id)).await;
+                    return;
+                }
+                session
+            },
+            UIAction::CreateConference(_) | UIAction::ListConferences => self.selected,
+            UIAction::ListPlugins => 0,
+            UIAction::LeaveConference(conference_id)
+            | UIAction::SendMessage((conference_id, _, _))
+            | UIAction::SendFile((conference_id, _, _))
+            | UIAction::MessagesDisplayed(conference_id)
+            | UIAction::SetReadReceipts((conference_id, _))
+            | UIAction::SetUnlinkable((conference_id, _))
+            | UIAction::SetListenOnly((conference_id, _))
+            | UIAction::SetAutoLeave((conference_id, _))
+            | UIAction::SetRetentionPolicy((conference_id, _))
+            | UIAction::AllowFlooding((conference_id, _))
+            | UIAction::CreateBreakout((conference_id, _))
+            | UIAction::RejoinConference(conference_id)
+            | UIAction::AnnounceMigration((conference_id, _)) => {
+                match self.server_id(*conference_id) {
+                    Some((session, server_id)) => {
+                        *conference_id = server_id;
+                        session
+                    },
+                    // the selected session reports conferences that are not joined
+                    None => self.selected,
+                }
+            },
+            UIAction::CancelRequest(packet_nonce) => {
+                let Some(&(session, session_nonce, _)) = self.requests.get(packet_nonce)
+                else {
+                    warn!("Attempted to cancel unknown request {}", packet_nonce);
+                    return;
+                };
+                let _ = self.sessions[session].action_sender.send(UIAction::CancelRequest(session_nonce)).await;
+                return;
+            },
+            UIAction::SetPeerLabel((key_image, label)) => {
+                let (key_image, label) = (*key_image, label.clone());
+                self.broadcast(|| UIAction::SetPeerLabel((key_image, label.clone()))).await;
+                return;
+            },
+            UIAction::ReloadPlugins => {
+                self.broadcast(|| UIAction::ReloadPlugins).await;
+                return;
+            },
+            UIAction::Disconnect => {
+                self.broadcast(|| UIAction::Disconnect).await;
+                return;
+            },
+            UIAction::LeaveAllConferences => {
+                self.broadcast(|| UIAction::LeaveAllConferences).await;
+                return;
+            },
+            UIAction::UserActive => {
+                self.broadcast(|| UIAction::UserActive).await;
+                return;
+            },
+            UIAction::SelectServer(server_address) => {
+                self.select(server_address).await;
+                return;
+            },
+            UIAction::MigrateConference((conference_id, server_address)) => {
+                self.migrate(*conference_id, server_address).await;
+                return;
+            },
+            UIAction::FollowMigration((conference_id, migration)) => {
+                self.follow(*conference_id, migration.clone()).await;
+                return;
+            },
+        };
+        if self.sessions[session].action_sender.send(action).await.is_err() {
+            warn!("Dropped UI action, the session with {} ended", self.sessions[session].server_address);
+        }
+    }
+
+    async fn broadcast(&mut self, action: impl Fn() -> UIAction) {
+        for session in self.sessions.iter_mut().filter(|session| session.connected) {
+            let _ = session.action_sender.send(action()).await;
+        }
+    }
+
+    async fn select(&mut self, server_address: &str) {
+        let Some(session) = self.sessions.iter().position(|session| session.connected && session.server_address == server_address)
+        else {
+            warn!("Attempted to select unknown server {}", server_address);
+            return;
+        };
+        info!("Selected server {}", server_address);
+        self.selected = session;
+        if let Some(capabilities) = self.sessions[session].capabilities {
+            let _ = self.ui_event_sink.send(UIEvent::ServerCapabilities(capabilities)).await;
+        }
+    }
+
+    /// Move a joined conference to another server
+    async fn migrate(&mut self, conference_id: ConferenceId, server_address: &str) {
+        let Some(&old_session) = self.conferences.get(&conference_id)
+        else {
+            warn!("Attempted to move conference {} which is not joined", conference_id);
+            let _ = self.ui_event_sink.send(UIEvent::MigrationProgress((conference_id, MigrationStage::Failed("Not joined".to_string())))).await;
+            return;
+        };
+        if self.migrations.contains_key(&conference_id) {
+            warn!("Conference {} is already being moved", conference_id);
+            return;
+        }
+        if self.sessions[old_session].server_address == server_address {
+            let _ = self.ui_event_sink.send(UIEvent::MigrationProgress((conference_id, MigrationStage::Failed("Already on that server".to_string())))).await;
+            return;
+        }
+        info!("Moving conference {} to {}", conference_id, server_address);
+        let new_session = self.session_for(server_address);
+        // the password is only shared through the announcement in the old conference
+        let password = bs58::encode(&crypto::generate_salt()[..16]).into_string();
+        let target = Migration { server_address: server_address.to_string(), conference_id: 0, password: password.clone() };
+        self.migrations.insert(conference_id, MigrationState { old_session, new_session, target, announce: true, stage: MigrationStage::Connecting });
+        if !self.progress(conference_id, MigrationStage::Connecting).await {
+            return;
+        }
+        let _ = self.sessions[new_session].action_sender.send(UIAction::CreateConference(password)).await;
+        self.progress(conference_id, MigrationStage::Creating).await;
+    }
+
+    /// Join the conference a peer moved a joined conference to
+    async fn follow(&mut self, conference_id: ConferenceId, target: Migration) {
+        let Some(&old_session) = self.conferences.get(&conference_id)
+        else {
+            warn!("Attempted to follow the move of conference {} which is not joined", conference_id);
+            let _ = self.ui_event_sink.send(UIEvent::MigrationProgress((conference_id, MigrationStage::Failed("Not joined".to_string())))).await;
+            return;
+        };
+        if self.migrations.contains_key(&conference_id) {
+            warn!("Conference {} is already being moved", conference_id);
+            return;
+        }
+        let joined = self.ui_ids.iter().any(|(&(session, conference_id), ui_id)| {
+            conference_id == target.conference_id && self.sessions[session].server_address == target.server_address && self.conferences.contains_key(ui_id)
+        });
+        if joined {
+            let failed = MigrationStage::Failed(format!("Conference {} is already joined", target.conference_id));
+            let _ = self.ui_event_sink.send(UIEvent::MigrationProgress((conference_id, failed))).await;
+            return;
+        }
+        info!("Following conference {} to {}", conference_id, target.server_address);
+        let new_session = self.session_for(&target.server_address);
+        let join = UIAction::JoinConference((target.conference_id, target.password.clone()));
+        self.migrations.insert(conference_id, MigrationState { old_session, new_session, target, announce: false, stage: MigrationStage::Connecting });
+        if !self.progress(conference_id, MigrationStage::Connecting).await {
+            return;
+        }
+        let _ = self.sessions[new_session].action_sender.send(join).await;
+        self.progress(conference_id, MigrationStage::Joining).await;
+    }
+
+    /// The old conference id of a migration to `session` in `stage`
+    fn migration_at(&self, session: usize, stage: MigrationStage) -> Option<ConferenceId> {
+        self.migrations.iter()
+            .find(|(_, migration)| migration.new_session == session && migration.stage == stage)
+            .map(|(&conference_id, _)| conference_id)
+    }
+
+    /// The nonce shown to the UI of a pending request of `session`
+    fn request_of(&self, session: usize, matches: impl Fn(&(usize, PacketNonce, Option<ConferenceId>)) -> bool) -> Option<PacketNonce> {
+        self.requests.iter()
+            .find(|(_, request)| request.0 == session && matches(request))
+            .map(|(&packet_nonce, _)| packet_nonce)
+    }
+
+    /// Forget a request once it was answered, `None` for a create
+    fn finish_request(&mut self, session: usize, conference_id: Option<ConferenceId>) {
+        if let Some(packet_nonce) = self.request_of(session, |&(_, _, id)| id == conference_id) {
+            self.requests.remove(&packet_nonce);
+        }
+    }
+
+    /// The old conference id of a migration joining the conference shown to the UI as `conference_id` on `session`
+    fn migration_to(&self, session: usize, conference_id: ConferenceId) -> Option<ConferenceId> {
+        self.migrations.iter()
+            .find(|(_, migration)| {
+                migration.new_session == session && migration.stage == MigrationStage::Joining
+                    && self.ui_ids.get(&(session, migration.target.conference_id)) == Some(&conference_id)
+            })
+            .map(|(&old_conference_id, _)| old_conference_id)
+    }
+
+    /// The id shown to the UI of a conference of `session`, the id of its server unless another conference has it
+    fn ui_id(&mut self, session: usize, conference_id: ConferenceId) -> ConferenceId {
+        if let Some(&ui_id) = self.ui_ids.get(&(session, conference_id)) {
+            return ui_id;
+        }
+        let taken = |ui_id: ConferenceId| self.ui_ids.values().any(|&id| id == ui_id);
+        let ui_id = if taken(conference_id) {
+            let ui_id = (0..=ConferenceId::MAX).rev().find(|&ui_id| !taken(ui_id)).unwrap();
+            info!("Conference {} on {} is shown as {}, another server has a conference with its id",
+                conference_id, self.sessions[session].server_address, ui_id);
+            ui_id
+        } else {
+            conference_id
+        };
+        self.ui_ids.insert((session, conference_id), ui_id);
+        ui_id
+    }
+
+    /// The session and the id on its server of a conference shown to the UI
+    fn server_id(&self, ui_id: ConferenceId) -> Option<(usize, ConferenceId)> {
+        self.ui_ids.iter().find(|(_, &id)| id == ui_id).map(|(&conference, _)| conference)
+    }
+
+    /// Free the id shown to the UI of a conference of `session` that is not joined
+    fn forget(&mut self, session: usize, ui_id: ConferenceId) {
+        if !self.conferences.contains_key(&ui_id) {
+            self.ui_ids.retain(|&(s, _), &mut id| s != session || id != ui_id);
+        }
+    }
+
+    /// Record and report the next stage of a migration, returns `false` once the UI is gone
+    async fn progress(&mut self, conference_id: ConferenceId, stage: MigrationStage) -> bool {
+        match &stage {
+            MigrationStage::Finished(_) => {
+                self.migrations.remove(&conference_id);
+            },
+            MigrationStage::Failed(reason) => {
+                warn!("Moving conference {} failed: {}", conference_id, reason);
+                self.migrations.remove(&conference_id);
+            },
+            _ => {
+                if let Some(migration) = self.migrations.get_mut(&conference_id) {
+                    migration.stage = stage.clone();
+                }
+            },
+        }
+        self.ui_event_sink.send(UIEvent::MigrationProgress((conference_id, stage))).await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::DisconnectReason;
+
+    fn router(servers: &[&str]) -> (Router, Vec<Receiver<UIAction>>, Receiver<UIEvent>) {
+        let (ui_event_sink, ui_event_receiver) = mpsc::unbounded();
+        let mut sessions = Vec::new();
+        let mut action_receivers = Vec::new();
+        for server_address in servers {
+            let (action_sender, action_receiver) = mpsc::unbounded();
+            sessions.push(Session { server_address: server_address.to_string(), action_sender, capabilities: None, connected: true });
+            action_receivers.push(action_receiver);
+        }
+        let mut router = Router::new(ui_event_sink);
+        router.sessions = sessions;
+        (router, action_receivers, ui_event_receiver)
+    }
+
+    #[test]
+    fn test_route_by_conference() {
+        runtime::block_on(async {
+            let (mut router, mut action_receivers, mut ui_event_receiver) = router(&["a:1", "b:2"]);
+            router.route(UIAction::SelectServer("b:2".to_string())).await;
+            router.route(UIAction::JoinConference((7, "password".to_string()))).await;
+            assert!(matches!(action_receivers[1].try_next(), Ok(Some(UIAction::JoinConference((7, _))))));
+
+            assert!(router.forward(1, UIEvent::ConferenceJoined((7, 2))).await);
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::ConferenceServer((7, server)))) if server == "b:2"));
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::ConferenceJoined((7, 2))))));
+
+            // the same id on the other server is shown with another id
+            router.route(UIAction::SelectServer("a:1".to_string())).await;
+            router.route(UIAction::JoinConference((7, "password".to_string()))).await;
+            assert!(matches!(action_receivers[0].try_next(), Ok(Some(UIAction::JoinConference((7, _))))));
+            assert!(router.forward(0, UIEvent::ConferenceJoined((7, 3))).await);
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::ConferenceServer((ConferenceId::MAX, server)))) if server == "a:1"));
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::ConferenceJoined((ConferenceId::MAX, 3))))));
+
+            router.route(UIAction::SendMessage((7, 1, "hello".to_string()))).await;
+            assert!(matches!(action_receivers[1].try_next(), Ok(Some(UIAction::SendMessage((7, 1, _))))));
+            router.route(UIAction::SendMessage((ConferenceId::MAX, 2, "hello".to_string()))).await;
+            assert!(matches!(action_receivers[0].try_next(), Ok(Some(UIAction::SendMessage((7, 2, _))))));
+
+            // each one only once per server
+            router.route(UIAction::JoinConference((7, "password".to_string()))).await;
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::ConferenceJoinFailed(ConferenceId::MAX)))));
+            assert!(action_receivers[0].try_next().is_err());
+        });
+    }
+
+    #[test]
+    fn test_cancel_request() {
+        runtime::block_on(async {
+            let (mut router, mut action_receivers, mut ui_event_receiver) = router(&["a:1", "b:2"]);
+            // both sessions start counting at the same nonce
+            assert!(router.forward(0, UIEvent::RequestPending((1, Some(7)))).await);
+            assert!(router.forward(1, UIEvent::RequestPending((1, None))).await);
+            let Ok(Some(UIEvent::RequestPending((join, Some(7))))) = ui_event_receiver.try_next() else { panic!() };
+            let Ok(Some(UIEvent::RequestPending((create, None)))) = ui_event_receiver.try_next() else { panic!() };
+            assert_ne!(join, create);
+
+            router.route(UIAction::CancelRequest(create)).await;
+            assert!(matches!(action_receivers[1].try_next(), Ok(Some(UIAction::CancelRequest(1)))));
+            assert!(router.forward(1, UIEvent::RequestCancelled(1)).await);
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::RequestCancelled(nonce))) if nonce == create));
+
+            // answered requests can no longer be cancelled
+            assert!(router.forward(0, UIEvent::ConferenceJoined((7, 2))).await);
+            router.route(UIAction::CancelRequest(join)).await;
+            assert!(action_receivers[0].try_next().is_err());
+        });
+    }
+
+    #[test]
+    fn test_single_server_disconnected() {
+        runtime::block_on(async {
+            let (mut router, _action_receivers, mut ui_event_receiver) = router(&["a:1", "b:2"]);
+            assert!(router.forward(0, UIEvent::ConferenceJoined((7, 2))).await);
+            while ui_event_receiver.try_next().is_ok() {}
+
+            assert!(router.forward(0, UIEvent::Disconnected(DisconnectReason::ServerClosed)).await);
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::ConferenceLeft(7)))));
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::ServerDisconnected((server, _)))) if server == "a:1"));
+
+            assert!(!router.forward(1, UIEvent::Disconnected(DisconnectReason::ServerClosed)).await);
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::Disconnected(DisconnectReason::ServerClosed)))));
+        });
+    }
+
+    #[test]
+    fn test_migrate_conference() {
+        runtime::block_on(async {
+            let (mut router, mut action_receivers, mut ui_event_receiver) = router(&["a:1", "b:2"]);
+            assert!(router.forward(0, UIEvent::ConferenceJoined((7, 2))).await);
+            while ui_event_receiver.try_next().is_ok() {}
+            // the joined and left conferences are reported as usual
+            let mut next_stage = move || loop {
+                match ui_event_receiver.try_next() {
+                    Ok(Some(UIEvent::MigrationProgress((7, stage)))) => return stage,
+                    Ok(Some(UIEvent::ConferenceServer(_) | UIEvent::ConferenceJoined(_) | UIEvent::ConferenceLeft(_))) => continue,
+                    event => panic!("unexpected {:?}", event),
+                }
+            };
+
+            router.route(UIAction::MigrateConference((7, "b:2".to_string()))).await;
+            assert_eq!(next_stage(), MigrationStage::Connecting);
+            assert_eq!(next_stage(), MigrationStage::Creating);
+            let Ok(Some(UIAction::CreateConference(password))) = action_receivers[1].try_next() else { panic!() };
+
+            assert!(router.forward(1, UIEvent::ConferenceCreated(9)).await);
+            assert_eq!(next_stage(), MigrationStage::Joining);
+            assert!(matches!(action_receivers[1].try_next(), Ok(Some(UIAction::JoinConference((9, p)))) if p == password));
+
+            assert!(router.forward(1, UIEvent::ConferenceJoined((9, 1))).await);
+            assert_eq!(next_stage(), MigrationStage::Announcing);
+            let target = Migration { server_address: "b:2".to_string(), conference_id: 9, password };
+            assert!(matches!(action_receivers[0].try_next(), Ok(Some(UIAction::AnnounceMigration((7, ref migration)))) if *migration == target));
+
+            assert!(router.forward(0, UIEvent::MessageAccepted((7, MIGRATION_MESSAGE_ID))).await);
+            assert_eq!(next_stage(), MigrationStage::Leaving);
+            assert!(matches!(action_receivers[0].try_next(), Ok(Some(UIAction::LeaveConference(7)))));
+
+            assert!(router.forward(0, UIEvent::ConferenceLeft(7)).await);
+            assert_eq!(next_stage(), MigrationStage::Finished(target));
+            assert!(router.migrations.is_empty());
+            assert_eq!(router.conferences.get(&9), Some(&1));
+        });
+    }
+
+    #[test]
+    fn test_same_id_on_two_servers() {
+        runtime::block_on(async {
+            let (mut router, mut action_receivers, mut ui_event_receiver) = router(&["a:1", "b:2"]);
+            assert!(router.forward(0, UIEvent::ConferenceJoined((7, 2))).await);
+            while ui_event_receiver.try_next().is_ok() {}
+
+            // e.g. rejoined after reconnecting, both stay joined
+            assert!(router.forward(1, UIEvent::ConferenceJoined((7, 1))).await);
+            while ui_event_receiver.try_next().is_ok() {}
+            assert!(router.forward(1, UIEvent::MessageAccepted((7, 1))).await);
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::MessageAccepted((ConferenceId::MAX, 1))))));
+            assert!(router.forward(0, UIEvent::MessageAccepted((7, 1))).await);
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::MessageAccepted((7, 1))))));
+
+            router.route(UIAction::LeaveConference(ConferenceId::MAX)).await;
+            assert!(matches!(action_receivers[1].try_next(), Ok(Some(UIAction::LeaveConference(7)))));
+            assert!(router.forward(1, UIEvent::ConferenceLeft(7)).await);
+            assert!(matches!(ui_event_receiver.try_next(), Ok(Some(UIEvent::ConferenceLeft(ConferenceId::MAX)))));
+            assert_eq!(router.ui_ids.len(), 1);
+
+            router.route(UIAction::SendMessage((7, 1, "hello".to_string()))).await;
+            assert!(matches!(action_receivers[0].try_next(), Ok(Some(UIAction::SendMessage((7, 1, _))))));
+        });
+    }
+}
@@ -0,0 +1,89 @@
+//! Notices when the client moves to another network, e.g. from Wi-Fi to Ethernet, so the server connection is
+//! established again right away instead of once the dead TCP connection times out.
+//!
+//! Interface changes are watched with netlink on Linux and the equivalent APIs on macOS and Windows, through
+//! `if-watch`. Only a change of the local address of the default route counts, addresses coming and going on
+//! other interfaces do not touch the connection.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use futures::{FutureExt, StreamExt};
+use log::{debug, info, warn};
+use crate::{constants::Sender, runtime};
+
+#[cfg(feature = "runtime-async-std")]
+use if_watch::smol::IfWatcher;
+#[cfg(all(feature = "runtime-tokio", not(feature = "runtime-async-std")))]
+use if_watch::tokio::IfWatcher;
+
+/// Documentation addresses, only used to ask the OS for a route, nothing is sent to them
+const ROUTE_PROBE_V4: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 9);
+const ROUTE_PROBE_V6: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)), 9);
+/// Interfaces change in bursts, the routes are looked at once they settled
+const SETTLE_DELAY: Duration = Duration::from_secs(2);
+
+/// The local address packets to the internet leave from, `None` without a default route
+pub fn default_route_source() -> Option<IpAddr> {
+    [ROUTE_PROBE_V4, ROUTE_PROBE_V6].into_iter().find_map(|probe| {
+        let unspecified = match probe {
+            SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        };
+        // connecting a UDP socket only picks the route
+        let socket = UdpSocket::bind(unspecified).ok()?;
+        socket.connect(probe).ok()?;
+        socket.local_addr().ok().map(|address| address.ip())
+    })
+}
+
+/// Send to `sender` whenever the default route moved to another address, until the receiver is dropped
+pub fn spawn_network_monitor(sender: Sender<()>) {
+    runtime::spawn(async move {
+        let mut watcher = match IfWatcher::new() {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                warn!("Could not watch the network interfaces: {}", e);
+                return;
+            },
+        };
+        let mut source = default_route_source();
+        debug!("Default route leaves from {:?}", source);
+        while let Some(event) = watcher.next().await {
+            match event {
+                Ok(event) => debug!("Network interface changed: {:?}", event),
+                Err(e) => warn!("Error watching the network interfaces: {}", e),
+            }
+            runtime::sleep(SETTLE_DELAY).await;
+            while let Some(Some(_)) = watcher.next().now_or_never() {}
+            if sender.is_closed() {
+                break;
+            }
+
+            let new_source = default_route_source();
+            if new_source == source {
+                continue;
+            }
+            info!("The default route moved from {:?} to {:?}", source, new_source);
+            source = new_source;
+            if sender.unbounded_send(()).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_route_source() {
+        // the sandbox may have no route at all, but a found address is never a wildcard
+        if let Some(source) = default_route_source() {
+            assert!(!source.is_unspecified());
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! One-shot commands for scripts: create a conference, send a single message, or join a conference
+//! and print its messages. They drive the state manager without a frontend, and every way they can
+//! fail has its own exit code.
+
+use std::io::Write;
+
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use log::{debug, info};
+use crate::{
+    constants::{ConferenceId, DisconnectReason, MessageID, Receiver, Sender, UIAction, UIEvent},
+    runtime,
+    state_manager,
+};
+
+/// The server could not be reached or the session ended
+pub const EXIT_DISCONNECTED: u8 = 3;
+/// The server refused to create or join the conference
+pub const EXIT_REFUSED: u8 = 4;
+/// The server did not accept the message
+pub const EXIT_NOT_DELIVERED: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    Disconnected(DisconnectReason),
+    CreateFailed,
+    JoinFailed(ConferenceId),
+    NotDelivered(ConferenceId),
+}
+
+impl CommandError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CommandError::Disconnected(_) => EXIT_DISCONNECTED,
+            CommandError::CreateFailed | CommandError::JoinFailed(_) => EXIT_REFUSED,
+            CommandError::NotDelivered(_) => EXIT_NOT_DELIVERED,
+        }
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::Disconnected(reason) => write!(f, "{}", reason),
+            CommandError::CreateFailed => write!(f, "The server could not create the conference"),
+            CommandError::JoinFailed(conference_id) => write!(f, "Could not join conference {}, check the id and the password", conference_id),
+            CommandError::NotDelivered(conference_id) => write!(f, "The message to conference {} was not delivered", conference_id),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// A session with the server driven by a script instead of a frontend
+pub struct Oneshot {
+    ui_action_sender: Sender<UIAction>,
+    ui_event_receiver: Receiver<UIEvent>,
+    last_message_id: MessageID,
+}
+
+impl Oneshot {
+    pub fn connect(server_address: String) -> Oneshot {
+        let (ui_event_sender, ui_event_receiver) = mpsc::unbounded();
+        let (ui_action_sender, ui_action_receiver) = mpsc::unbounded();
+        runtime::spawn(state_manager::start_state_manager(server_address, ui_event_sender, ui_action_receiver));
+        Oneshot { ui_action_sender, ui_event_receiver, last_message_id: 0 }
+    }
+
+    /// Connect without TLS, only meant for talking to the in-process mock server
+    #[cfg(any(test, feature = "test-util"))]
+    pub fn connect_plaintext(server_address: String) -> Oneshot {
+        let (ui_event_sender, ui_event_receiver) = mpsc::unbounded();
+        let (ui_action_sender, ui_action_receiver) = mpsc::unbounded();
+        runtime::spawn(state_manager::start_state_manager_plaintext(server_address, ui_event_sender, ui_action_receiver));
+        Oneshot { ui_action_sender, ui_event_receiver, last_message_id: 0 }
+    }
+
+    /// Returns the id of the new conference, it is not joined
+    pub async fn create(&mut self, password: String) -> Result<ConferenceId, CommandError> {
+        self.act(UIAction::CreateConference(password)).await?;
+        self.wait_for(|event| match event {
+            UIEvent::ConferenceCreated(conference_id) => Some(Ok(*conference_id)),
+            UIEvent::ConferenceCreateFailed => Some(Err(CommandError::CreateFailed)),
+            _ => None,
+        }).await
+    }
+
+    /// Returns once the setup of the conference is finished and messages can be sent
+    pub async fn join(&mut self, conference_id: ConferenceId, password: String) -> Result<(), CommandError> {
+        self.act(UIAction::JoinConference((conference_id, password))).await?;
+        self.wait_for(|event| match event {
+            UIEvent::ConferenceRestructuringFinished(id) if *id == conference_id => Some(Ok(())),
+            UIEvent::ConferenceJoinFailed(id) if *id == conference_id => Some(Err(CommandError::JoinFailed(conference_id))),
+            _ => None,
+        }).await?;
+        info!("Joined conference {}", conference_id);
+        Ok(())
+    }
+
+    /// Returns once the server accepted the message
+    pub async fn send(&mut self, conference_id: ConferenceId, message: String) -> Result<(), CommandError> {
+        self.last_message_id += 1;
+        let message_id = self.last_message_id;
+        self.act(UIAction::SendMessage((conference_id, message_id, message))).await?;
+        self.wait_for(|event| match *event {
+            UIEvent::MessageAccepted((id, accepted)) if (id, accepted) == (conference_id, message_id) => Some(Ok(())),
+            UIEvent::MessageRejected((id, failed)) | UIEvent::MessageError((id, failed)) | UIEvent::MessageTimedOut((id, failed))
+                | UIEvent::MessageTooLarge((id, failed, _))
+                if (id, failed) == (conference_id, message_id) => Some(Err(CommandError::NotDelivered(conference_id))),
+            _ => None,
+        }).await
+    }
+
+    /// Writes every incoming message of the conference as a line `<peer>: <message>` until the session ends
+    pub async fn print_messages(&mut self, conference_id: ConferenceId, out: &mut impl Write) -> CommandError {
+        loop {
+            match self.next_event().await {
+                Ok(UIEvent::IncomingMessage((id, message, signature_valid, peer))) if id == conference_id => {
+                    let warning = if signature_valid { "" } else { "(!invalid signature!) " };
+                    let message = String::from_utf8_lossy(&message).replace('\n', " ");
+                    if writeln!(out, "{}: {}{}", peer.display_name(), warning, message).and_then(|_| out.flush()).is_err() {
+                        // the reading end of the pipe is gone
+                        return CommandError::Disconnected(DisconnectReason::UserRequested);
+                    }
+                },
+                Ok(UIEvent::ConferenceLeft(id)) if id == conference_id => debug!("Left conference {}, waiting to join it again", conference_id),
+                Ok(_) => {},
+                Err(e) => return e,
+            }
+        }
+    }
+
+    /// Leave the server in an orderly way
+    pub async fn disconnect(mut self) {
+        if self.act(UIAction::Disconnect).await.is_ok() {
+            while self.next_event().await.is_ok() {}
+        }
+    }
+
+    async fn act(&mut self, action: UIAction) -> Result<(), CommandError> {
+        self.ui_action_sender.send(action).await
+            .map_err(|_| CommandError::Disconnected(DisconnectReason::ConnectionLost("The state manager exited".to_string())))
+    }
+
+    async fn next_event(&mut self) -> Result<UIEvent, CommandError> {
+        match self.ui_event_receiver.next().await {
+            Some(UIEvent::Disconnected(reason)) => Err(CommandError::Disconnected(reason)),
+            Some(event) => Ok(event),
+            None => Err(CommandError::Disconnected(DisconnectReason::ConnectionLost("The state manager exited".to_string()))),
+        }
+    }
+
+    /// Wait for the first event for which `f` returns `Some`
+    async fn wait_for<T>(&mut self, f: impl Fn(&UIEvent) -> Option<Result<T, CommandError>>) -> Result<T, CommandError> {
+        loop {
+            if let Some(result) = f(&self.next_event().await?) {
+                return result;
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+//! Every client shows the messages of different peers in the same order, even if the server delivered
+//! them in a different order. Senders embed a Lamport clock in the signed message, received messages are
+//! held back for a moment and released sorted by the clock and then by the key image of the sender.
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use crate::constants::KeyImage;
+
+pub type LamportClock = u64;
+
+/// How long received messages are held back to sort in messages that were delivered later
+pub const HOLD_BACK: Duration = Duration::from_millis(300);
+const CLOCK_SIZE: usize = 8;
+/// First byte of the signed payload of a text message, so that a later layout can be told apart
+const TEXT_PAYLOAD_VERSION: u8 = 1;
+/// Received clocks are taken at most this far ahead of the own clock,
+/// so a single forged clock can not run the clock of the conference up to its end
+const MAX_CLOCK_AHEAD: LamportClock = 1 << 20;
+
+#[derive(Debug, Default)]
+pub struct LogicalClock {
+    time: LamportClock,
+}
+
+impl LogicalClock {
+    /// The clock of a message that is sent now
+    pub fn tick(&mut self) -> LamportClock {
+        self.time = self.time.saturating_add(1);
+        self.time
+    }
+
+    /// The clock to order a received message by, at most [`MAX_CLOCK_AHEAD`] past the own clock
+    pub fn clamp(&self, time: LamportClock) -> LamportClock {
+        time.min(self.time.saturating_add(MAX_CLOCK_AHEAD))
+    }
+
+    /// Move past the clock of a received message, so own messages are ordered after it
+    pub fn observe(&mut self, time: LamportClock) {
+        self.time = self.time.max(self.clamp(time));
+    }
+}
+
+/// The signed payload of a text message, the version and the clock followed by the text
+pub fn encode_text(time: LamportClock, text: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(1 + CLOCK_SIZE + text.len());
+    result.push(TEXT_PAYLOAD_VERSION);
+    result.extend_from_slice(&time.to_be_bytes());
+    result.extend_from_slice(text);
+    result
+}
+
+/// `None` for payloads of another version
+pub fn decode_text(payload: &[u8]) -> Option<(LamportClock, &[u8])> {
+    let (&version, payload) = payload.split_first()?;
+    if version != TEXT_PAYLOAD_VERSION || payload.len() < CLOCK_SIZE {
+        return None;
+    }
+    let (time, text) = payload.split_at(CLOCK_SIZE);
+    Some((LamportClock::from_be_bytes(time.try_into().unwrap()), text))
+}
+
+/// Received messages waiting to be released in order
+pub struct HoldBack<T> {
+    /// by clock, sender and arrival, the arrival keeps messages of the same sender and clock apart
+    messages: BTreeMap<(LamportClock, KeyImage, u64), T>,
+    /// arrival of the oldest held message
+    oldest: Option<Instant>,
+    arrivals: u64,
+}
+
+impl<T> HoldBack<T> {
+    pub fn new() -> HoldBack<T> {
+        HoldBack { messages: BTreeMap::new(), oldest: None, arrivals: 0 }
+    }
+
+    pub fn push(&mut self, time: LamportClock, sender: KeyImage, message: T, now: Instant) {
+        self.messages.insert((time, sender, self.arrivals), message);
+        self.arrivals += 1;
+        self.oldest.get_or_insert(now);
+    }
+
+    /// When the held messages are due
+    pub fn deadline(&self) -> Option<Instant> {
+        self.oldest.map(|oldest| oldest + HOLD_BACK)
+    }
+
+    /// All held messages in order once the oldest one was held long enough
+    pub fn release(&mut self, now: Instant) -> Vec<T> {
+        if !self.deadline().is_some_and(|deadline| deadline <= now) {
+            return Vec::new();
+        }
+        self.oldest = None;
+        std::mem::take(&mut self.messages).into_values().collect()
+    }
+}
+
+impl<T> Default for HoldBack<T> {
+    fn default() -> Self {
+        HoldBack::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logical_clock() {
+        let mut clock = LogicalClock::default();
+        assert_eq!(clock.tick(), 1);
+        clock.observe(7);
+        assert_eq!(clock.tick(), 8);
+        clock.observe(3);
+        assert_eq!(clock.tick(), 9);
+        // a forged clock moves the clock only so far
+        assert_eq!(clock.clamp(LamportClock::MAX), 9 + MAX_CLOCK_AHEAD);
+        clock.observe(LamportClock::MAX);
+        assert_eq!(clock.tick(), 10 + MAX_CLOCK_AHEAD);
+
+        let payload = encode_text(9, b"hello");
+        assert_eq!(decode_text(&payload), Some((9, &b"hello"[..])));
+        assert_eq!(decode_text(&payload[..8]), None);
+        assert_eq!(decode_text(&payload[1..]), None);
+    }
+
+    #[test]
+    fn test_hold_back_orders_messages() {
+        let start = Instant::now();
+        let mut hold_back = HoldBack::new();
+        assert_eq!(hold_back.deadline(), None);
+        hold_back.push(5, [2; 32], "b5", start);
+        hold_back.push(5, [1; 32], "a5", start + Duration::from_millis(100));
+        hold_back.push(3, [3; 32], "c3", start + Duration::from_millis(200));
+        assert_eq!(hold_back.deadline(), Some(start + HOLD_BACK));
+        assert!(hold_back.release(start + HOLD_BACK - Duration::from_millis(1)).is_empty());
+        assert_eq!(hold_back.release(start + HOLD_BACK), vec!["c3", "a5", "b5"]);
+        assert_eq!(hold_back.deadline(), None);
+    }
+}
@@ -0,0 +1,122 @@
+//! Messages sent to the server that it has not accepted yet.
+//!
+//! The server answers every message with an acceptance or an error. A message it does not answer in time is sent
+//! again a few times under a new nonce before it is reported as timed out, so a single lost reply does not fail
+//! the message. Peers may then see it twice if only the reply was lost. A late reply to an earlier nonce of the
+//! message still answers it, until the message is answered or given up on. Large messages are retried chunk by
+//! chunk by [`crate::upload`] instead.
+
+use std::{collections::HashMap, time::Duration};
+
+use crate::constants::{ConferenceId, Message, MessageID, PacketNonce};
+
+/// How long the server has to accept a message before it is sent again
+pub const MESSAGE_TIMEOUT: Duration = Duration::from_secs(20);
+/// A message the server did not answer in time is sent this often before it is reported as timed out
+pub const MAX_MESSAGE_RETRIES: u32 = 2;
+
+pub enum MessageRetry {
+    /// Send the message again
+    Resend(Message),
+    /// The message was retried too often
+    GiveUp,
+    /// The message was answered or given up on in the meantime
+    Stale,
+}
+
+struct Outbound {
+    message: Message,
+    /// how often it was sent again
+    retries: u32,
+    /// every nonce it was sent under, the last one is pending
+    nonces: Vec<PacketNonce>,
+}
+
+#[derive(Default)]
+pub struct OutboundMessages {
+    messages: HashMap<(ConferenceId, MessageID), Outbound>,
+}
+
+impl OutboundMessages {
+    /// Keep a message that is sent to the server under `packet_nonce`, messages without an id are not retried
+    pub fn sent(&mut self, message: &Message, packet_nonce: PacketNonce) {
+        if let Some(message_id) = message.message_id {
+            self.messages.entry((message.conference, message_id))
+                .or_insert_with(|| Outbound { message: message.clone(), retries: 0, nonces: Vec::new() })
+                .nonces.push(packet_nonce);
+        }
+    }
+
+    /// The message an earlier nonce was sent for, a reply to it answers the message
+    pub fn sent_under(&self, packet_nonce: &PacketNonce) -> Option<(ConferenceId, MessageID)> {
+        self.messages.iter()
+            .find(|(_, outbound)| outbound.nonces.contains(packet_nonce))
+            .map(|(&key, _)| key)
+    }
+
+    /// The server accepted or refused the message, returns the nonces it was sent under
+    pub fn finished(&mut self, conference_id: ConferenceId, message_id: MessageID) -> Vec<PacketNonce> {
+        self.messages.remove(&(conference_id, message_id)).map(|outbound| outbound.nonces).unwrap_or_default()
+    }
+
+    pub fn timed_out(&mut self, conference_id: ConferenceId, message_id: MessageID) -> MessageRetry {
+        let Some(outbound) = self.messages.get_mut(&(conference_id, message_id))
+        else {
+            return MessageRetry::Stale;
+        };
+        outbound.retries += 1;
+        if outbound.retries > MAX_MESSAGE_RETRIES {
+            self.messages.remove(&(conference_id, message_id));
+            return MessageRetry::GiveUp;
+        }
+        MessageRetry::Resend(outbound.message.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use super::*;
+
+    fn message(message_id: Option<MessageID>) -> Message {
+        Message { conference: 1, message: Bytes::from_static(b"abc"), message_id }
+    }
+
+    #[test]
+    fn test_retries() {
+        let mut outbound = OutboundMessages::default();
+        outbound.sent(&message(Some(1)), 1);
+        for _ in 0..MAX_MESSAGE_RETRIES {
+            let MessageRetry::Resend(resent) = outbound.timed_out(1, 1) else { panic!("not resent") };
+            // a resent message keeps its count
+            outbound.sent(&resent, 2);
+        }
+        assert!(matches!(outbound.timed_out(1, 1), MessageRetry::GiveUp));
+        assert!(matches!(outbound.timed_out(1, 1), MessageRetry::Stale));
+    }
+
+    #[test]
+    fn test_finished() {
+        let mut outbound = OutboundMessages::default();
+        outbound.sent(&message(None), 1);
+        outbound.sent(&message(Some(1)), 2);
+        outbound.sent(&message(Some(2)), 3);
+        assert_eq!(outbound.finished(1, 1), vec![2]);
+        assert!(matches!(outbound.timed_out(1, 1), MessageRetry::Stale));
+        assert!(matches!(outbound.timed_out(1, 2), MessageRetry::Resend(_)));
+        assert_eq!(outbound.sent_under(&1), None);
+    }
+
+    #[test]
+    fn test_late_reply() {
+        let mut outbound = OutboundMessages::default();
+        outbound.sent(&message(Some(1)), 1);
+        let MessageRetry::Resend(resent) = outbound.timed_out(1, 1) else { panic!("not resent") };
+        outbound.sent(&resent, 2);
+        // the reply to the first send arrives after the message was sent again
+        assert_eq!(outbound.sent_under(&1), Some((1, 1)));
+        assert_eq!(outbound.finished(1, 1), vec![1, 2]);
+        assert_eq!(outbound.sent_under(&1), None);
+        assert_eq!(outbound.sent_under(&2), None);
+    }
+}
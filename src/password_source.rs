@@ -0,0 +1,95 @@
+//! Conference passwords for automated joins, read from a file or an environment variable
+//! so they do not show up in process listings or shell history.
+
+use std::path::PathBuf;
+
+use log::warn;
+use crate::constants::Result;
+
+/// Environment variable checked when no password file is given
+pub const PASSWORD_ENV_VARIABLE: &str = "ACC_CONFERENCE_PASSWORD";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PasswordSource {
+    /// The first line of a file
+    File(PathBuf),
+    /// An environment variable
+    Env(String),
+}
+
+impl PasswordSource {
+    /// The password file if one was given, otherwise [`PASSWORD_ENV_VARIABLE`] if it is set
+    pub fn from_args(password_file: Option<PathBuf>) -> Option<PasswordSource> {
+        match password_file {
+            Some(path) => Some(PasswordSource::File(path)),
+            None if std::env::var_os(PASSWORD_ENV_VARIABLE).is_some() => Some(PasswordSource::Env(PASSWORD_ENV_VARIABLE.to_string())),
+            None => None,
+        }
+    }
+
+    /// Read the password, it is read again on every call so a rotated password is picked up
+    pub fn read(&self) -> Result<String> {
+        let password = match self {
+            PasswordSource::File(path) => {
+                warn_if_readable_by_others(path);
+                let content = std::fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read the password file {}: {}", path.display(), e))?;
+                content.lines().next().unwrap_or_default().to_string()
+            },
+            PasswordSource::Env(name) => std::env::var(name)
+                .map_err(|_| format!("The environment variable {} is not set", name))?,
+        };
+        if password.is_empty() {
+            return Err(format!("The password from {} is empty", self).into());
+        }
+        Ok(password)
+    }
+}
+
+impl std::fmt::Display for PasswordSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordSource::File(path) => write!(f, "the file {}", path.display()),
+            PasswordSource::Env(name) => write!(f, "the environment variable {}", name),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn warn_if_readable_by_others(path: &std::path::Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            warn!("The password file {} is readable by other users", path.display());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable_by_others(_path: &std::path::Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_password_file() {
+        let path = std::env::temp_dir().join(format!("acc-password-test-{}", rand::random::<u32>()));
+        std::fs::write(&path, "hunter2\nignored\n").unwrap();
+        assert_eq!(PasswordSource::File(path.clone()).read().unwrap(), "hunter2");
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(PasswordSource::File(path.clone()).read().is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(PasswordSource::File(path).read().is_err());
+    }
+
+    #[test]
+    fn test_read_password_env() {
+        let name = format!("ACC_PASSWORD_TEST_{}", rand::random::<u32>());
+        assert!(PasswordSource::Env(name.clone()).read().is_err());
+        std::env::set_var(&name, "hunter2");
+        assert_eq!(PasswordSource::Env(name.clone()).read().unwrap(), "hunter2");
+        std::env::remove_var(&name);
+    }
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+const APP_DIR_NAME: &str = "anonymous-conference";
+
+/// Directory for application data (plugins, logs, ...), created on demand
+pub fn data_dir() -> PathBuf {
+    let dir = dirs::data_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR_NAME);
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::warn!("Could not create data directory {}: {}", dir.display(), e);
+    }
+    dir
+}
+
+/// Path of the config file
+pub fn config_file() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join(APP_DIR_NAME)
+        .join("config.toml")
+}
+
+/// Directory holding the plugins that are loaded on startup
+pub fn plugins_dir() -> PathBuf {
+    data_dir().join("plugins")
+}
+
+/// Directory holding the rhai scripts that are loaded on startup
+pub fn scripts_dir() -> PathBuf {
+    data_dir().join("scripts")
+}
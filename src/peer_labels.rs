@@ -0,0 +1,134 @@
+//! Local names for peers, keyed by the key image of their ring signatures.
+//! The labels never leave this machine, the protocol stays anonymous.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use crate::{config::StorageConfig, constants::{KeyImage, Result}, paths, storage};
+
+pub const PEER_LABELS_FILE_NAME: &str = "peer_labels.toml";
+
+pub type SharedPeerLabels = Arc<Mutex<PeerLabels>>;
+
+#[derive(Default, Serialize, Deserialize)]
+struct PeerLabelsFile {
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Default)]
+pub struct PeerLabels {
+    /// `None` keeps the labels in memory only
+    path: Option<PathBuf>,
+    labels: HashMap<KeyImage, String>,
+    storage: StorageConfig,
+}
+
+impl PeerLabels {
+    /// Load the labels from the data directory, they are saved encrypted if `storage_config` says so
+    pub fn load(storage_config: &StorageConfig) -> PeerLabels {
+        let path = paths::data_dir().join(PEER_LABELS_FILE_NAME);
+        let labels = match storage::read_to_string(&path) {
+            Ok(Some(contents)) => match toml::from_str::<PeerLabelsFile>(&contents) {
+                Ok(file) => file.labels.iter()
+                    .filter_map(|(key_image, label)| Some((key_image_from_hex(key_image)?, label.clone())))
+                    .collect(),
+                Err(e) => {
+                    warn!("Could not parse peer labels {}: {}", path.display(), e);
+                    HashMap::new()
+                },
+            },
+            Ok(None) => HashMap::new(), // no labels yet
+            Err(e) => {
+                warn!("Could not read peer labels {}: {}", path.display(), e);
+                HashMap::new()
+            },
+        };
+        PeerLabels { path: Some(path), labels, storage: storage_config.clone() }
+    }
+
+    pub fn shared(self) -> SharedPeerLabels {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn get(&self, key_image: &KeyImage) -> Option<&str> {
+        self.labels.get(key_image).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&KeyImage, &str)> {
+        self.labels.iter().map(|(key_image, label)| (key_image, label.as_str()))
+    }
+
+    /// Set or remove (`None`) the label of a key image and save the labels
+    pub fn set(&mut self, key_image: KeyImage, label: Option<String>) -> Result<()> {
+        match label {
+            Some(label) => self.labels.insert(key_image, label),
+            None => self.labels.remove(&key_image),
+        };
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = self.path.as_ref()
+        else {
+            return Ok(());
+        };
+        let file = PeerLabelsFile {
+            labels: self.labels.iter().map(|(key_image, label)| (key_image_to_hex(key_image), label.clone())).collect(),
+        };
+        storage::write(path, &toml::to_string(&file)?, &self.storage)?;
+        Ok(())
+    }
+}
+
+pub fn key_image_to_hex(key_image: &KeyImage) -> String {
+    key_image.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn key_image_from_hex(hex: &str) -> Option<KeyImage> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut key_image = [0u8; 32];
+    for (index, byte) in key_image.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(key_image)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn test_peer_labels_persist() {
+        let dir = std::env::temp_dir().join(format!("acc-labels-test-{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(PEER_LABELS_FILE_NAME);
+        let mut labels = PeerLabels { path: Some(path.clone()), labels: HashMap::new(), storage: StorageConfig::default() };
+        labels.set([1; 32], Some("Alice-laptop".to_string())).unwrap();
+        labels.set([2; 32], Some("Bob".to_string())).unwrap();
+        labels.set([2; 32], None).unwrap();
+
+        let file: PeerLabelsFile = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(file.labels.len(), 1);
+        assert_eq!(file.labels.get(&key_image_to_hex(&[1; 32])).unwrap(), "Alice-laptop");
+        assert_eq!(labels.get(&[1; 32]), Some("Alice-laptop"));
+        assert_eq!(labels.get(&[2; 32]), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_image_hex() {
+        let key_image = [0xab; 32];
+        assert_eq!(key_image_from_hex(&key_image_to_hex(&key_image)), Some(key_image));
+        assert_eq!(key_image_from_hex("ab"), None);
+        assert_eq!(key_image_from_hex(&"zz".repeat(32)), None);
+    }
+}
@@ -0,0 +1,144 @@
+//! Requests sent to the server that are waiting for a reply, by packet nonce.
+//! Every request has a tracing span that is closed once it is answered or expired, and its own timeout.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+use tracing::{debug_span, Span};
+use crate::constants::PacketNonce;
+
+pub struct PendingRequests<T> {
+    /// request, when it was sent, its timeout and its span
+    requests: HashMap<PacketNonce, (T, Instant, Duration, Span)>,
+    last_nonce: PacketNonce,
+    timeout: Duration,
+}
+
+impl<T> PendingRequests<T> {
+    /// Requests that are not answered within `timeout` are expired, unless they were inserted with their own timeout
+    pub fn new(timeout: Duration) -> PendingRequests<T> {
+        PendingRequests {
+            requests: HashMap::new(),
+            last_nonce: 0,
+            timeout,
+        }
+    }
+
+    /// Store a request under a new nonce.
+    /// Nonces wrap around, but a nonce is never reused while its request is pending, and `0` is never used.
+    pub fn insert(&mut self, request: T) -> PacketNonce {
+        self.insert_with_timeout(request, self.timeout)
+    }
+
+    /// Store a request that is expired after `timeout` instead of the default
+    pub fn insert_with_timeout(&mut self, request: T, timeout: Duration) -> PacketNonce {
+        loop {
+            self.last_nonce = self.last_nonce.wrapping_add(1);
+            if self.last_nonce != 0 && !self.requests.contains_key(&self.last_nonce) {
+                break;
+            }
+        }
+        let span = debug_span!("request", nonce = self.last_nonce);
+        span.in_scope(|| debug!("Sending request"));
+        self.requests.insert(self.last_nonce, (request, Instant::now(), timeout, span));
+        self.last_nonce
+    }
+
+    pub fn get(&self, nonce: &PacketNonce) -> Option<&T> {
+        self.requests.get(nonce).map(|(request, _, _, _)| request)
+    }
+
+    pub fn get_mut(&mut self, nonce: &PacketNonce) -> Option<&mut T> {
+        self.requests.get_mut(nonce).map(|(request, _, _, _)| request)
+    }
+
+    /// Continue a request with its next step under the same nonce, the timeout starts again.
+    /// Returns the previous step, `None` if `nonce` is not pending.
+    pub fn replace(&mut self, nonce: &PacketNonce, request: T) -> Option<T> {
+        let (previous, sent_at, _, span) = self.requests.get_mut(nonce)?;
+        span.in_scope(|| debug!("Request continued"));
+        *sent_at = Instant::now();
+        Some(std::mem::replace(previous, request))
+    }
+
+    pub fn remove(&mut self, nonce: &PacketNonce) -> Option<T> {
+        self.requests.remove(nonce).map(|(request, _, _, span)| {
+            span.in_scope(|| debug!("Request answered"));
+            request
+        })
+    }
+
+    /// Remove and return the requests that were sent more than their timeout before `now`
+    pub fn expire(&mut self, now: Instant) -> Vec<(PacketNonce, T)> {
+        let expired: Vec<PacketNonce> = self.requests.iter()
+            .filter(|(_, (_, sent_at, timeout, _))| now.saturating_duration_since(*sent_at) > *timeout)
+            .map(|(nonce, _)| *nonce)
+            .collect();
+        expired.into_iter()
+            .filter_map(|nonce| self.requests.remove(&nonce).map(|(request, _, _, span)| {
+                span.in_scope(|| debug!("Request expired"));
+                (nonce, request)
+            }))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonce_wraps_around_pending_requests() {
+        let mut requests = PendingRequests::new(Duration::from_secs(60));
+        requests.last_nonce = PacketNonce::MAX - 1;
+        assert_eq!(requests.insert("a"), PacketNonce::MAX);
+        assert_eq!(requests.insert("b"), 1);
+        assert_eq!(requests.insert("c"), 2);
+        requests.remove(&2);
+
+        // still pending nonces are skipped
+        requests.last_nonce = PacketNonce::MAX - 1;
+        assert_eq!(requests.insert("d"), 2);
+        assert_eq!(requests.get(&PacketNonce::MAX), Some(&"a"));
+        assert_eq!(requests.get(&2), Some(&"d"));
+    }
+
+    #[test]
+    fn test_expire() {
+        let timeout = Duration::from_secs(60);
+        let mut requests = PendingRequests::new(timeout);
+        let nonce = requests.insert("a");
+        assert!(requests.expire(Instant::now()).is_empty());
+        assert_eq!(requests.expire(Instant::now() + timeout * 2), vec![(nonce, "a")]);
+        assert!(requests.is_empty());
+        assert_eq!(requests.replace(&nonce, "b"), None);
+    }
+
+    #[test]
+    fn test_expire_with_own_timeout() {
+        let timeout = Duration::from_secs(60);
+        let mut requests = PendingRequests::new(timeout);
+        let short = requests.insert_with_timeout("short", timeout / 4);
+        let long = requests.insert("long");
+        let now = Instant::now();
+        assert_eq!(requests.expire(now + timeout / 2), vec![(short, "short")]);
+        assert_eq!(requests.get(&long), Some(&"long"));
+        assert_eq!(requests.expire(now + timeout * 2), vec![(long, "long")]);
+    }
+
+    #[test]
+    fn test_replace_keeps_nonce() {
+        let mut requests = PendingRequests::new(Duration::from_secs(60));
+        let nonce = requests.insert("salt");
+        assert_eq!(requests.replace(&nonce, "join"), Some("salt"));
+        assert_eq!(requests.get(&nonce), Some(&"join"));
+        assert_ne!(requests.insert("other"), nonce);
+    }
+}
@@ -0,0 +1,94 @@
+//! Pinning of the server's public key by the SHA-256 fingerprint of its SubjectPublicKeyInfo, the
+//! fingerprint HPKP used. Several pins can be configured at once, so a server can rotate its key
+//! after the fingerprint of the new key was added next to the old one.
+//!
+//! The fingerprint of a certificate is printed by
+//! `openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256`.
+
+use sha2::{Digest, Sha256};
+
+/// DER tags of the certificate fields up to the public key
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_EXPLICIT_VERSION: u8 = 0xa0;
+/// serial number, signature algorithm, issuer, validity and subject
+const FIELDS_BEFORE_PUBLIC_KEY: usize = 5;
+
+/// Hex SHA-256 fingerprint of the SubjectPublicKeyInfo of a DER certificate, `None` if it can not be parsed
+pub fn spki_fingerprint(certificate: &[u8]) -> Option<String> {
+    let spki = subject_public_key_info(certificate)?;
+    Some(Sha256::digest(spki).iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Pins in hex with or without colons, in any case
+pub fn normalize_pin(pin: &str) -> String {
+    pin.chars().filter(|c| *c != ':' && !c.is_whitespace()).collect::<String>().to_lowercase()
+}
+
+/// Whether the fingerprint matches any of the pins, any fingerprint matches without pins
+pub fn matches_pins(fingerprint: &str, pins: &[String]) -> bool {
+    pins.is_empty() || pins.iter().any(|pin| normalize_pin(pin) == fingerprint)
+}
+
+/// The whole SubjectPublicKeyInfo element of the TBSCertificate
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (TAG_SEQUENCE, _, certificate, _) = read_element(certificate)?
+    else { return None };
+    let (TAG_SEQUENCE, _, mut fields, _) = read_element(certificate)?
+    else { return None };
+    if fields.first() == Some(&TAG_EXPLICIT_VERSION) {
+        fields = read_element(fields)?.3;
+    }
+    for _ in 0..FIELDS_BEFORE_PUBLIC_KEY {
+        fields = read_element(fields)?.3;
+    }
+    match read_element(fields)? {
+        (TAG_SEQUENCE, spki, _, _) => Some(spki),
+        _ => None,
+    }
+}
+
+/// Returns the tag, the whole element, its contents and the bytes after it
+fn read_element(der: &[u8]) -> Option<(u8, &[u8], &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (length, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        // long form, at most four length bytes
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let bytes = rest.get(..count)?;
+            (bytes.iter().fold(0usize, |length, byte| length << 8 | *byte as usize), &rest[count..])
+        },
+        _ => return None,
+    };
+    let contents = rest.get(..length)?;
+    let header = der.len() - rest.len();
+    Some((tag, &der[..header + length], contents, &rest[length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundled_certificate() -> Vec<u8> {
+        async_native_tls::Certificate::from_pem(include_bytes!("../certs/certificate.pem")).unwrap().to_der().unwrap()
+    }
+
+    #[test]
+    fn test_spki_fingerprint() {
+        let fingerprint = spki_fingerprint(&bundled_certificate()).unwrap();
+        assert_eq!(fingerprint, "30d3ab4587440f76c21c329b447eb7c9187705db797589ef415267ac6a3a1842");
+        assert!(matches_pins(&fingerprint, &[]));
+        assert!(matches_pins(&fingerprint, &["00".repeat(32), "30:D3:AB:45:87:44:0F:76:C2:1C:32:9B:44:7E:B7:C9:18:77:05:DB:79:75:89:EF:41:52:67:AC:6A:3A:18:42".to_string()]));
+        assert!(!matches_pins(&fingerprint, &["00".repeat(32)]));
+    }
+
+    #[test]
+    fn test_truncated_certificate() {
+        let certificate = bundled_certificate();
+        for length in [0, 1, 4, 100, certificate.len() / 2] {
+            assert!(spki_fingerprint(&certificate[..length]).is_none());
+        }
+        assert!(read_element(&[0x30, 0x85, 0, 0, 0, 0, 1]).is_none());
+    }
+}
@@ -0,0 +1,214 @@
+use std::sync::Mutex;
+
+#[cfg(feature = "scripting")]
+pub mod scripting;
+
+use bytes::Bytes;
+use log::{debug, info};
+use crate::constants::{ConferenceId, MessageID, UIEvent};
+
+/// Message ID used for messages sent by plugins, reserved at the top of the range next to
+/// [`crate::constants::MIGRATION_MESSAGE_ID`] so it never collides with the ids the UIs count up from 1
+pub const PLUGIN_MESSAGE_ID: MessageID = MessageID::MAX - 1;
+
+/// Name of the symbol a dynamic plugin library has to export, see [`PluginCreate`]
+#[cfg(feature = "dynamic-plugins")]
+pub const PLUGIN_CREATE_SYMBOL: &[u8] = b"acc_plugin_create";
+
+/// Signature of the constructor exported by dynamic plugin libraries.
+/// Dynamic plugins have to be built with the same compiler version as the client.
+pub type PluginCreate = fn() -> Box<dyn Plugin>;
+
+static REGISTERED_PLUGINS: Mutex<Vec<PluginCreate>> = Mutex::new(Vec::new());
+
+/// Register a compiled-in plugin, has to be called before the state manager is started
+pub fn register_plugin(create: PluginCreate) {
+    REGISTERED_PLUGINS.lock().unwrap().push(create);
+}
+
+/// Lets plugin hooks act on the conference they were called for
+#[derive(Default)]
+pub struct PluginContext {
+    outbound_messages: Vec<(ConferenceId, String)>,
+}
+
+impl PluginContext {
+    /// Queue a text message to be sent to a conference once the hook returns
+    pub fn send_message(&mut self, conference_id: ConferenceId, message: String) {
+        self.outbound_messages.push((conference_id, message));
+    }
+
+    pub fn take_outbound_messages(&mut self) -> Vec<(ConferenceId, String)> {
+        std::mem::take(&mut self.outbound_messages)
+    }
+}
+
+/// A message processor hooked into the state manager.
+/// All hooks are optional, returning `false` from a message hook drops the message.
+pub trait Plugin: Send {
+    fn name(&self) -> &str;
+
+    /// Called for every incoming text message before it is passed to the UI
+    fn on_incoming_message(&mut self, _ctx: &mut PluginContext, _conference_id: ConferenceId, _message: &mut Bytes) -> bool {
+        true
+    }
+
+    /// Called for every text message the user sends before it is signed and encrypted
+    fn on_before_send(&mut self, _ctx: &mut PluginContext, _conference_id: ConferenceId, _message: &mut String) -> bool {
+        true
+    }
+
+    /// Called for every event passed to the UI
+    fn on_conference_event(&mut self, _ctx: &mut PluginContext, _event: &UIEvent) {}
+}
+
+pub struct PluginManager {
+    plugins: Vec<Box<dyn Plugin>>,
+    // must be dropped after the plugins created from them
+    #[cfg(feature = "dynamic-plugins")]
+    _libraries: Vec<libloading::Library>,
+}
+
+impl PluginManager {
+    /// Instantiate all registered plugins and load the dynamic ones from the plugins directory
+    pub fn load() -> PluginManager {
+        PluginManager::load_from(&crate::paths::plugins_dir(), &crate::paths::scripts_dir())
+    }
+
+    /// Like [`PluginManager::load`], with dynamic plugins and scripts read from the given directories
+    #[allow(unused_variables)]
+    pub fn load_from(plugins_dir: &std::path::Path, scripts_dir: &std::path::Path) -> PluginManager {
+        let plugins: Vec<Box<dyn Plugin>> = REGISTERED_PLUGINS.lock().unwrap().iter().map(|create| create()).collect();
+        #[allow(unused_mut)]
+        let mut manager = PluginManager {
+            plugins,
+            #[cfg(feature = "dynamic-plugins")]
+            _libraries: Vec::new(),
+        };
+        #[cfg(feature = "dynamic-plugins")]
+        manager.load_dynamic_plugins(plugins_dir);
+        #[cfg(feature = "scripting")]
+        manager.plugins.extend(
+            scripting::load_scripts(scripts_dir).into_iter().map(|script| Box::new(script) as Box<dyn Plugin>)
+        );
+        for plugin in manager.plugins.iter() {
+            info!("Loaded plugin {}", plugin.name());
+        }
+        manager
+    }
+
+    #[cfg(feature = "dynamic-plugins")]
+    fn load_dynamic_plugins(&mut self, dir: &std::path::Path) {
+        let Ok(entries) = std::fs::read_dir(dir)
+        else {
+            debug!("No plugins directory at {}", dir.display());
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(std::env::consts::DLL_EXTENSION) {
+                continue;
+            }
+            // SAFETY: plugins are trusted code placed into the plugins directory by the user
+            let result = unsafe {
+                libloading::Library::new(&path).and_then(|library| {
+                    let create = *library.get::<PluginCreate>(PLUGIN_CREATE_SYMBOL)?;
+                    Ok((create(), library))
+                })
+            };
+            match result {
+                Ok((plugin, library)) => {
+                    self.plugins.push(plugin);
+                    self._libraries.push(library);
+                },
+                Err(e) => log::warn!("Could not load plugin {}: {}", path.display(), e),
+            }
+        }
+    }
+
+    pub fn add(&mut self, plugin: Box<dyn Plugin>) {
+        info!("Loaded plugin {}", plugin.name());
+        self.plugins.push(plugin);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn plugin_names(&self) -> Vec<String> {
+        self.plugins.iter().map(|plugin| plugin.name().to_string()).collect()
+    }
+
+    /// Returns `false` if a plugin dropped the message
+    pub fn on_incoming_message(&mut self, ctx: &mut PluginContext, conference_id: ConferenceId, message: &mut Bytes) -> bool {
+        for plugin in self.plugins.iter_mut() {
+            if !plugin.on_incoming_message(ctx, conference_id, message) {
+                debug!("Plugin {} dropped incoming message in conference {}", plugin.name(), conference_id);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Returns `false` if a plugin dropped the message
+    pub fn on_before_send(&mut self, ctx: &mut PluginContext, conference_id: ConferenceId, message: &mut String) -> bool {
+        for plugin in self.plugins.iter_mut() {
+            if !plugin.on_before_send(ctx, conference_id, message) {
+                debug!("Plugin {} dropped outbound message in conference {}", plugin.name(), conference_id);
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn on_conference_event(&mut self, ctx: &mut PluginContext, event: &UIEvent) {
+        for plugin in self.plugins.iter_mut() {
+            plugin.on_conference_event(ctx, event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Uppercase;
+
+    impl Plugin for Uppercase {
+        fn name(&self) -> &str { "uppercase" }
+
+        fn on_before_send(&mut self, _ctx: &mut PluginContext, _conference_id: ConferenceId, message: &mut String) -> bool {
+            *message = message.to_uppercase();
+            true
+        }
+    }
+
+    struct DropAll;
+
+    impl Plugin for DropAll {
+        fn name(&self) -> &str { "drop-all" }
+
+        fn on_incoming_message(&mut self, ctx: &mut PluginContext, conference_id: ConferenceId, _message: &mut Bytes) -> bool {
+            ctx.send_message(conference_id, "dropped".to_string());
+            false
+        }
+    }
+
+    #[test]
+    fn test_plugin_hooks() {
+        let dir = std::env::temp_dir().join(format!("acc-plugins-test-{}", rand::random::<u32>()));
+        let mut manager = PluginManager::load_from(&dir.join("plugins"), &dir.join("scripts"));
+        manager.add(Box::new(Uppercase));
+        manager.add(Box::new(DropAll));
+        let mut ctx = PluginContext::default();
+
+        let mut message = "hello".to_string();
+        assert!(manager.on_before_send(&mut ctx, 1, &mut message));
+        assert_eq!(message, "HELLO");
+
+        let mut incoming = Bytes::from_static(b"hi");
+        assert!(!manager.on_incoming_message(&mut ctx, 1, &mut incoming));
+        assert_eq!(ctx.take_outbound_messages(), vec![(1, "dropped".to_string())]);
+        assert!(ctx.take_outbound_messages().is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use log::{debug, info, warn};
+use rhai::{Dynamic, Engine, Scope, AST, INT};
+use crate::constants::{ConferenceId, UIEvent};
+use super::{Plugin, PluginContext};
+
+const SCRIPT_EXTENSION: &str = "rhai";
+const SCRIPT_MAX_OPERATIONS: u64 = 100_000;
+const SCRIPT_MAX_CALL_LEVELS: usize = 32;
+const SCRIPT_MAX_STRING_SIZE: usize = 64 * 1024;
+const SCRIPT_MAX_ARRAY_SIZE: usize = 1024;
+const SCRIPT_TIME_LIMIT: Duration = Duration::from_millis(100);
+
+const ON_MESSAGE_FN: &str = "on_message";
+const ON_SEND_FN: &str = "on_send";
+const ON_EVENT_FN: &str = "on_event";
+
+type Outbox = Arc<Mutex<Vec<(ConferenceId, String)>>>;
+
+/// A rhai script reacting to conference events.
+///
+/// Scripts can define the following functions, all of them are optional:
+/// - `on_message(conference_id, text)`: called for incoming messages, returning `false` hides the message
+/// - `on_send(conference_id, text)`: called for outbound messages, returning `false` drops the message
+/// - `on_event(name, conference_id)`: called for conference events (`"joined"`, `"left"`, `"ready"`, ...)
+///
+/// and call `send(conference_id, text)` to send a message or `log(text)` to write to the log.
+pub struct ScriptPlugin {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    outbox: Outbox,
+}
+
+/// Load all scripts in `dir`, scripts that fail to compile are skipped
+pub fn load_scripts(dir: &Path) -> Vec<ScriptPlugin> {
+    let Ok(entries) = std::fs::read_dir(dir)
+    else {
+        debug!("No scripts directory at {}", dir.display());
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some(SCRIPT_EXTENSION))
+        .collect();
+    paths.sort();
+    paths.iter().filter_map(|path| {
+        let name = path.file_name()?.to_string_lossy().to_string();
+        let source = std::fs::read_to_string(path).map_err(|e| warn!("Could not read script {}: {}", path.display(), e)).ok()?;
+        ScriptPlugin::new(name, &source).map_err(|e| warn!("Could not compile script {}: {}", path.display(), e)).ok()
+    }).collect()
+}
+
+impl ScriptPlugin {
+    pub fn new(name: String, source: &str) -> Result<ScriptPlugin, Box<rhai::EvalAltResult>> {
+        let outbox: Outbox = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(SCRIPT_MAX_OPERATIONS);
+        engine.set_max_call_levels(SCRIPT_MAX_CALL_LEVELS);
+        engine.set_max_string_size(SCRIPT_MAX_STRING_SIZE);
+        engine.set_max_array_size(SCRIPT_MAX_ARRAY_SIZE);
+        engine.set_max_map_size(SCRIPT_MAX_ARRAY_SIZE);
+        // scripts must not touch the file system or run code built from strings
+        engine.disable_symbol("import");
+        engine.disable_symbol("eval");
+
+        let outbox_clone = outbox.clone();
+        engine.register_fn("send", move |conference_id: INT, text: &str| {
+            if let Ok(conference_id) = ConferenceId::try_from(conference_id) {
+                outbox_clone.lock().unwrap().push((conference_id, text.to_string()));
+            }
+        });
+        let script_name = name.clone();
+        engine.register_fn("log", move |text: &str| {
+            info!("[script {}] {}", script_name, text);
+        });
+
+        let ast = engine.compile(source)?;
+        let mut plugin = ScriptPlugin { name, engine, ast, scope: Scope::new(), outbox };
+        // run the top level statements once, e.g. to initialize global variables
+        plugin.limit_time();
+        plugin.engine.run_ast_with_scope(&mut plugin.scope, &plugin.ast)?;
+        Ok(plugin)
+    }
+
+    fn limit_time(&mut self) {
+        let deadline = Instant::now() + SCRIPT_TIME_LIMIT;
+        self.engine.on_progress(move |_| {
+            if Instant::now() > deadline {
+                Some(Dynamic::from("script time limit exceeded"))
+            } else {
+                None
+            }
+        });
+    }
+
+    fn has_fn(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|f| f.name == name)
+    }
+
+    /// Call a script function, returns `None` if the function does not exist or failed
+    fn call(&mut self, ctx: &mut PluginContext, name: &str, args: impl rhai::FuncArgs) -> Option<Dynamic> {
+        if !self.has_fn(name) {
+            return None;
+        }
+        self.limit_time();
+        let result = self.engine.call_fn::<Dynamic>(&mut self.scope, &self.ast, name, args);
+        for (conference_id, message) in self.outbox.lock().unwrap().drain(..) {
+            ctx.send_message(conference_id, message);
+        }
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!("Script {} failed in {}: {}", self.name, name, e);
+                None
+            }
+        }
+    }
+}
+
+impl Plugin for ScriptPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn on_incoming_message(&mut self, ctx: &mut PluginContext, conference_id: ConferenceId, message: &mut Bytes) -> bool {
+        let text = String::from_utf8_lossy(message).to_string();
+        let result = self.call(ctx, ON_MESSAGE_FN, (conference_id as INT, text));
+        result.and_then(|value| value.as_bool().ok()).unwrap_or(true)
+    }
+
+    fn on_before_send(&mut self, ctx: &mut PluginContext, conference_id: ConferenceId, message: &mut String) -> bool {
+        let result = self.call(ctx, ON_SEND_FN, (conference_id as INT, message.clone()));
+        result.and_then(|value| value.as_bool().ok()).unwrap_or(true)
+    }
+
+    fn on_conference_event(&mut self, ctx: &mut PluginContext, event: &UIEvent) {
+        let (name, conference_id) = match event {
+            UIEvent::ConferenceCreated(conference_id) => ("created", *conference_id),
+            UIEvent::ConferenceJoined((conference_id, _)) => ("joined", *conference_id),
+            UIEvent::ConferenceLeft(conference_id) => ("left", *conference_id),
+            UIEvent::ConferenceRestructuring((conference_id, _)) => ("restructuring", *conference_id),
+            UIEvent::ConferenceRestructuringFinished(conference_id) => ("ready", *conference_id),
+            _ => return,
+        };
+        self.call(ctx, ON_EVENT_FN, (name.to_string(), conference_id as INT));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_auto_reply() {
+        let source = r#"
+            fn on_message(conference_id, text) {
+                if text == "ping" {
+                    send(conference_id, "pong");
+                }
+                text != "secret"
+            }
+        "#;
+        let mut plugin = ScriptPlugin::new("reply.rhai".to_string(), source).unwrap();
+        let mut ctx = PluginContext::default();
+        assert!(plugin.on_incoming_message(&mut ctx, 7, &mut Bytes::from_static(b"ping")));
+        assert!(!plugin.on_incoming_message(&mut ctx, 7, &mut Bytes::from_static(b"secret")));
+        assert_eq!(ctx.take_outbound_messages(), vec![(7, "pong".to_string())]);
+    }
+
+    #[test]
+    fn test_script_runaway_loop_is_stopped() {
+        let source = "fn on_send(conference_id, text) { loop { } }";
+        let mut plugin = ScriptPlugin::new("loop.rhai".to_string(), source).unwrap();
+        let mut ctx = PluginContext::default();
+        // a failing script does not drop the message
+        assert!(plugin.on_before_send(&mut ctx, 1, &mut "hi".to_string()));
+    }
+
+    #[test]
+    fn test_script_eval_is_disabled() {
+        assert!(ScriptPlugin::new("eval.rhai".to_string(), r#"eval("40 + 2")"#).is_err());
+    }
+}
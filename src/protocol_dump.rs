@@ -0,0 +1,230 @@
+//! Human readable dump of every decoded event exchanged with the server, for diagnosing
+//! disagreements between client and server in the field.
+//!
+//! Unlike a [`trace`](crate::trace) the dump is plain text and written as it happens, to stderr or a file,
+//! independent of the UI and the log level. Password hashes, salts and message contents are never written,
+//! only their sizes.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::Instant,
+};
+
+use log::warn;
+use crate::{
+    constants::{ClientEvent, ConferenceId, PacketNonce, Result, ServerEvent},
+    trace::Direction,
+};
+
+static DUMP: Mutex<Option<ProtocolDump>> = Mutex::new(None);
+/// Requests the server never answered are forgotten, oldest first, beyond this many
+const MAX_WAITING_REQUESTS: usize = 1024;
+
+/// The parts of an event that are safe to show
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DumpedEvent {
+    pub name: &'static str,
+    pub nonce: Option<PacketNonce>,
+    pub conference_id: Option<ConferenceId>,
+    /// Size of the variable part, the message or the list
+    pub payload: Option<usize>,
+}
+
+impl DumpedEvent {
+    fn new(name: &'static str) -> Self {
+        DumpedEvent { name, nonce: None, conference_id: None, payload: None }
+    }
+
+    fn nonce(mut self, nonce: PacketNonce) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    fn conference(mut self, conference_id: ConferenceId) -> Self {
+        self.conference_id = Some(conference_id);
+        self
+    }
+
+    fn payload(mut self, payload: usize) -> Self {
+        self.payload = Some(payload);
+        self
+    }
+}
+
+impl From<&ClientEvent> for DumpedEvent {
+    fn from(event: &ClientEvent) -> Self {
+        match event {
+            ClientEvent::CreateConference((nonce, _, _, _)) => DumpedEvent::new("CreateConference").nonce(*nonce),
+            ClientEvent::GetConferenceJoinSalt((nonce, conference_id)) => DumpedEvent::new("GetConferenceJoinSalt").nonce(*nonce).conference(*conference_id),
+            ClientEvent::JoinConference((nonce, conference_id, _)) => DumpedEvent::new("JoinConference").nonce(*nonce).conference(*conference_id),
+            ClientEvent::LeaveConference((nonce, conference_id)) => DumpedEvent::new("LeaveConference").nonce(*nonce).conference(*conference_id),
+            ClientEvent::SendMessage((nonce, message)) => DumpedEvent::new("SendMessage").nonce(*nonce).conference(message.conference).payload(message.message.len()),
+            ClientEvent::Disconnect => DumpedEvent::new("Disconnect"),
+            ClientEvent::ListConferences(nonce) => DumpedEvent::new("ListConferences").nonce(*nonce),
+            ClientEvent::SelectWireSchema(_) => DumpedEvent::new("SelectWireSchema"),
+            ClientEvent::Ping(_) => DumpedEvent::new("Ping"),
+            ClientEvent::Pong(_) => DumpedEvent::new("Pong"),
+        }
+    }
+}
+
+impl From<&ServerEvent> for DumpedEvent {
+    fn from(event: &ServerEvent) -> Self {
+        match event {
+            ServerEvent::HandshakeAcknowledged => DumpedEvent::new("HandshakeAcknowledged"),
+            ServerEvent::ConferenceCreated((nonce, conference_id)) => DumpedEvent::new("ConferenceCreated").nonce(*nonce).conference(*conference_id),
+            ServerEvent::ConferenceJoinSalt((nonce, conference_id, _)) => DumpedEvent::new("ConferenceJoinSalt").nonce(*nonce).conference(*conference_id),
+            ServerEvent::ConferenceJoined((nonce, conference_id, number_of_peers, _)) => DumpedEvent::new("ConferenceJoined").nonce(*nonce).conference(*conference_id).payload(*number_of_peers as usize),
+            ServerEvent::ConferenceLeft((nonce, conference_id)) => DumpedEvent::new("ConferenceLeft").nonce(*nonce).conference(*conference_id),
+            ServerEvent::MessageAccepted((nonce, conference_id)) => DumpedEvent::new("MessageAccepted").nonce(*nonce).conference(*conference_id),
+            ServerEvent::ConferenceRestructuring((conference_id, number_of_peers)) => DumpedEvent::new("ConferenceRestructuring").conference(*conference_id).payload(*number_of_peers as usize),
+            ServerEvent::IncomingMessage((conference_id, message)) => DumpedEvent::new("IncomingMessage").conference(*conference_id).payload(message.len()),
+            ServerEvent::HandshakeAcknowledgedWithCapabilities(_) => DumpedEvent::new("HandshakeAcknowledgedWithCapabilities"),
+            ServerEvent::ConferenceList((nonce, conferences)) => DumpedEvent::new("ConferenceList").nonce(*nonce).payload(conferences.len()),
+            ServerEvent::Ping(_) => DumpedEvent::new("Ping"),
+            ServerEvent::Pong(_) => DumpedEvent::new("Pong"),
+            ServerEvent::GeneralError => DumpedEvent::new("GeneralError"),
+            ServerEvent::ConferenceCreationError(nonce) => DumpedEvent::new("ConferenceCreationError").nonce(*nonce),
+            ServerEvent::ConferenceJoinSaltError((nonce, conference_id)) => DumpedEvent::new("ConferenceJoinSaltError").nonce(*nonce).conference(*conference_id),
+            ServerEvent::ConferenceJoinError((nonce, conference_id)) => DumpedEvent::new("ConferenceJoinError").nonce(*nonce).conference(*conference_id),
+            ServerEvent::ConferenceLeaveError((nonce, conference_id)) => DumpedEvent::new("ConferenceLeaveError").nonce(*nonce).conference(*conference_id),
+            ServerEvent::MessageError((nonce, conference_id)) => DumpedEvent::new("MessageError").nonce(*nonce).conference(*conference_id),
+            ServerEvent::Unknown((_, length)) => DumpedEvent::new("Unknown").payload(*length as usize),
+            ServerEvent::PinMismatch(_) => DumpedEvent::new("PinMismatch"),
+        }
+    }
+}
+
+impl fmt::Display for DumpedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(nonce) = self.nonce {
+            write!(f, " nonce={}", nonce)?;
+        }
+        if let Some(conference_id) = self.conference_id {
+            write!(f, " conference={}", conference_id)?;
+        }
+        if let Some(payload) = self.payload {
+            write!(f, " payload={}", payload)?;
+        }
+        Ok(())
+    }
+}
+
+struct ProtocolDump {
+    writer: Box<dyn Write + Send>,
+    started: Instant,
+    /// When the requests that are still waiting for a reply were sent
+    requests: HashMap<PacketNonce, Instant>,
+}
+
+impl ProtocolDump {
+    fn line(&mut self, direction: Direction, event: DumpedEvent, frame_length: usize) -> std::io::Result<()> {
+        let now = Instant::now();
+        let arrow = match direction {
+            Direction::Inbound => "<-",
+            Direction::Outbound => "->",
+        };
+        write!(self.writer, "{:>10.3} {} {} ({} bytes)", now.duration_since(self.started).as_secs_f64(), arrow, event, frame_length)?;
+        match (direction, event.nonce) {
+            (Direction::Outbound, Some(nonce)) => {
+                if self.requests.len() >= MAX_WAITING_REQUESTS {
+                    let oldest = self.requests.iter().min_by_key(|(_, sent)| **sent).map(|(nonce, _)| *nonce);
+                    if let Some(oldest) = oldest {
+                        self.requests.remove(&oldest);
+                    }
+                }
+                self.requests.insert(nonce, now);
+            },
+            (Direction::Inbound, Some(nonce)) => {
+                if let Some(sent) = self.requests.remove(&nonce) {
+                    write!(self.writer, " after {} ms", now.duration_since(sent).as_millis())?;
+                }
+            },
+            _ => {},
+        }
+        writeln!(self.writer)
+    }
+}
+
+/// Start dumping the events of this process to `path`, `-` is stderr
+pub fn start(path: &Path) -> Result<()> {
+    let writer: Box<dyn Write + Send> = if path.as_os_str() == "-" {
+        Box::new(std::io::stderr())
+    } else {
+        Box::new(LineWriter::new(File::create(path)?))
+    };
+    *DUMP.lock().unwrap() = Some(ProtocolDump { writer, started: Instant::now(), requests: HashMap::new() });
+    Ok(())
+}
+
+/// Dump an event if a dump was started, dumping stops on the first write error
+pub fn dump(direction: Direction, event: DumpedEvent, frame_length: usize) {
+    let Ok(mut dump) = DUMP.lock()
+    else {
+        return;
+    };
+    if let Some(active) = dump.as_mut() {
+        if let Err(e) = active.line(direction, event, frame_length) {
+            warn!("Could not write the protocol dump, stopping it: {}", e);
+            *dump = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use crate::constants::Message;
+
+    #[test]
+    fn test_dumped_event() {
+        let message = Message { conference: 7, message: Bytes::from_static(b"secret"), message_id: None };
+        let event = DumpedEvent::from(&ClientEvent::SendMessage((3, message)));
+        assert_eq!(event.to_string(), "SendMessage nonce=3 conference=7 payload=6");
+
+        // the password hash is left out
+        let event = DumpedEvent::from(&ClientEvent::JoinConference((4, 7, [0xAA; 32])));
+        assert_eq!(event.to_string(), "JoinConference nonce=4 conference=7");
+
+        let event = DumpedEvent::from(&ServerEvent::ConferenceRestructuring((7, 3)));
+        assert_eq!(event.to_string(), "ConferenceRestructuring conference=7 payload=3");
+    }
+
+    #[test]
+    fn test_reply_timing() {
+        let output = std::sync::Arc::new(Mutex::new(Vec::new()));
+        struct Shared(std::sync::Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut dump = ProtocolDump { writer: Box::new(Shared(output.clone())), started: Instant::now(), requests: HashMap::new() };
+        dump.line(Direction::Outbound, DumpedEvent::from(&ClientEvent::LeaveConference((5, 7))), 9).unwrap();
+        dump.line(Direction::Inbound, DumpedEvent::from(&ServerEvent::ConferenceLeft((5, 7))), 9).unwrap();
+        let text = String::from_utf8(output.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].ends_with("-> LeaveConference nonce=5 conference=7 (9 bytes)"));
+        assert!(lines[1].contains("<- ConferenceLeft nonce=5 conference=7 (9 bytes) after "));
+        assert!(dump.requests.is_empty());
+    }
+
+    #[test]
+    fn test_unanswered_requests_are_bounded() {
+        let mut dump = ProtocolDump { writer: Box::new(std::io::sink()), started: Instant::now(), requests: HashMap::new() };
+        for nonce in 1..=MAX_WAITING_REQUESTS as PacketNonce + 10 {
+            dump.line(Direction::Outbound, DumpedEvent::from(&ClientEvent::ListConferences(nonce)), 5).unwrap();
+        }
+        assert_eq!(dump.requests.len(), MAX_WAITING_REQUESTS);
+    }
+}
@@ -0,0 +1,109 @@
+//! Recently used servers and conferences, offered as suggestions by the UIs.
+//! Only addresses and conference ids are kept, never passwords.
+
+use std::path::PathBuf;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use crate::{config::{RecentConfig, StorageConfig}, constants::{ConferenceId, Result}, paths, storage};
+
+pub const RECENT_FILE_NAME: &str = "recent.toml";
+
+#[derive(Default, Serialize, Deserialize)]
+struct RecentFile {
+    #[serde(default)]
+    servers: Vec<String>,
+    #[serde(default)]
+    conferences: Vec<ConferenceId>,
+}
+
+pub struct Recent {
+    /// `None` keeps the lists in memory only
+    path: Option<PathBuf>,
+    max_entries: usize,
+    /// most recently used first
+    servers: Vec<String>,
+    conferences: Vec<ConferenceId>,
+    storage: StorageConfig,
+}
+
+impl Recent {
+    /// Load the lists from the data directory, nothing is remembered if it is disabled.
+    /// They are saved encrypted if `storage_config` says so
+    pub fn load(config: &RecentConfig, storage_config: &StorageConfig) -> Recent {
+        if !config.enabled {
+            return Recent { path: None, max_entries: 0, servers: Vec::new(), conferences: Vec::new(), storage: storage_config.clone() };
+        }
+        let path = paths::data_dir().join(RECENT_FILE_NAME);
+        let file = match storage::read_to_string(&path) {
+            Ok(Some(contents)) => toml::from_str::<RecentFile>(&contents).unwrap_or_else(|e| {
+                warn!("Could not parse recent servers and conferences {}: {}", path.display(), e);
+                RecentFile::default()
+            }),
+            Ok(None) => RecentFile::default(), // nothing used yet
+            Err(e) => {
+                warn!("Could not read recent servers and conferences {}: {}", path.display(), e);
+                RecentFile::default()
+            },
+        };
+        let mut recent = Recent { path: Some(path), max_entries: config.max_entries, servers: file.servers, conferences: file.conferences, storage: storage_config.clone() };
+        recent.servers.truncate(recent.max_entries);
+        recent.conferences.truncate(recent.max_entries);
+        recent
+    }
+
+    pub fn servers(&self) -> &[String] {
+        &self.servers
+    }
+
+    pub fn conferences(&self) -> &[ConferenceId] {
+        &self.conferences
+    }
+
+    /// Move the server and conference to the front of the lists and save them
+    pub fn used(&mut self, server_address: &str, conference_id: ConferenceId) {
+        if self.max_entries == 0 {
+            return;
+        }
+        move_to_front(&mut self.servers, server_address.to_string(), self.max_entries);
+        move_to_front(&mut self.conferences, conference_id, self.max_entries);
+        if let Err(e) = self.save() {
+            warn!("Could not save recent servers and conferences: {}", e);
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = self.path.as_ref()
+        else {
+            return Ok(());
+        };
+        let file = RecentFile { servers: self.servers.clone(), conferences: self.conferences.clone() };
+        storage::write(path, &toml::to_string(&file)?, &self.storage)?;
+        Ok(())
+    }
+}
+
+fn move_to_front<T: PartialEq>(list: &mut Vec<T>, item: T, max_entries: usize) {
+    list.retain(|existing| existing != &item);
+    list.insert(0, item);
+    list.truncate(max_entries);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recent_used() {
+        let mut recent = Recent { path: None, max_entries: 2, servers: Vec::new(), conferences: Vec::new(), storage: StorageConfig::default() };
+        recent.used("a:1", 1);
+        recent.used("b:2", 2);
+        recent.used("a:1", 3);
+        assert_eq!(recent.servers(), ["a:1", "b:2"]);
+        assert_eq!(recent.conferences(), [3, 1]);
+
+        let mut disabled = Recent::load(&RecentConfig { enabled: false, max_entries: 10 }, &StorageConfig::default());
+        disabled.used("a:1", 1);
+        assert!(disabled.servers().is_empty() && disabled.conferences().is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+//! Joining the conferences of a lost connection again once it is restored.
+//! The passwords of the joined conferences are kept across sessions, a lost connection is established
+//! again with a growing delay as configured in `[reconnect]`, and conferences not yet joined again are
+//! kept until a session joins them or the server refuses them.
+
+use std::{collections::HashMap, time::Duration};
+
+use crate::{config::ReconnectConfig, constants::{ConferenceId, DisconnectReason}};
+
+pub struct Rejoin {
+    /// joined in the current session
+    joined: HashMap<ConferenceId, String>,
+    /// joined in a previous session and not joined again yet
+    pending: HashMap<ConferenceId, String>,
+    /// attempts since the last successful handshake
+    attempts: u32,
+    /// a handshake succeeded once, so the server is worth trying again
+    established: bool,
+    max_attempts: u32,
+    first_delay: Duration,
+}
+
+impl Rejoin {
+    pub fn new(config: &ReconnectConfig) -> Self {
+        Rejoin {
+            joined: HashMap::new(),
+            pending: HashMap::new(),
+            attempts: 0,
+            established: false,
+            max_attempts: config.max_attempts,
+            first_delay: Duration::from_secs(config.first_delay_secs),
+        }
+    }
+
+    /// Starts a session, returns the conferences to join again
+    pub fn start_session(&mut self) -> Vec<(ConferenceId, String)> {
+        self.pending.extend(self.joined.drain());
+        self.pending.iter().map(|(&conference_id, password)| (conference_id, password.clone())).collect()
+    }
+
+    /// The server acknowledged the handshake
+    pub fn connected(&mut self) {
+        self.attempts = 0;
+        self.established = true;
+    }
+
+    pub fn joined(&mut self, conference_id: ConferenceId, password: String) {
+        self.pending.remove(&conference_id);
+        self.joined.insert(conference_id, password);
+    }
+
+    pub fn left(&mut self, conference_id: &ConferenceId) {
+        self.joined.remove(conference_id);
+    }
+
+    /// The server refused to join the conference, it is not tried again
+    pub fn failed(&mut self, conference_id: &ConferenceId) {
+        self.pending.remove(conference_id);
+    }
+
+    /// The user left the conference while disconnected, it is not joined again.
+    /// Returns `false` if the conference was not joined.
+    pub fn forget(&mut self, conference_id: &ConferenceId) -> bool {
+        let joined = self.joined.remove(conference_id);
+        let pending = self.pending.remove(conference_id);
+        joined.is_some() || pending.is_some()
+    }
+
+    /// The user left all conferences while disconnected, returns the forgotten ones
+    pub fn forget_all(&mut self) -> Vec<ConferenceId> {
+        self.joined.drain().chain(self.pending.drain()).map(|(conference_id, _)| conference_id).collect()
+    }
+
+    /// The password of a conference joined in the current session
+    pub fn password(&self, conference_id: &ConferenceId) -> Option<&String> {
+        self.joined.get(conference_id)
+    }
+
+    /// The conferences joined in the current session
+    pub fn conferences(&self) -> impl Iterator<Item = ConferenceId> + '_ {
+        self.joined.keys().copied()
+    }
+
+    /// Joined or still to be joined again
+    pub fn contains(&self, conference_id: &ConferenceId) -> bool {
+        self.joined.contains_key(conference_id) || self.pending.contains_key(conference_id)
+    }
+
+    /// Returns the delay before a session that ended for the reason is established again, `None` if it is not
+    pub fn reconnect(&mut self, reason: &DisconnectReason) -> Option<Duration> {
+        match reason {
+            DisconnectReason::NetworkChanged => Some(Duration::ZERO),
+            // the server may come back, or the network may recover, an untrusted certificate stays untrusted
+            DisconnectReason::ConnectionLost(_) | DisconnectReason::ConnectionTimedOut | DisconnectReason::ServerClosed | DisconnectReason::ConnectionFailed(_)
+                if self.established && self.attempts < self.max_attempts => {
+                let delay = self.first_delay.saturating_mul(2u32.saturating_pow(self.attempts));
+                self.attempts += 1;
+                Some(delay)
+            },
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_until_joined() {
+        let mut rejoin = Rejoin::new(&ReconnectConfig::default());
+        assert!(rejoin.start_session().is_empty());
+        rejoin.joined(1, "one".to_string());
+        rejoin.joined(2, "two".to_string());
+        rejoin.left(&2);
+
+        assert_eq!(rejoin.start_session(), vec![(1, "one".to_string())]);
+        assert!(rejoin.password(&1).is_none());
+        assert!(rejoin.contains(&1));
+        // the next session failed before joining, the conference is still queued
+        assert_eq!(rejoin.start_session(), vec![(1, "one".to_string())]);
+        rejoin.joined(1, "one".to_string());
+        assert_eq!(rejoin.password(&1), Some(&"one".to_string()));
+        assert_eq!(rejoin.conferences().collect::<Vec<_>>(), vec![1]);
+
+        rejoin.start_session();
+        rejoin.failed(&1);
+        assert!(!rejoin.contains(&1));
+        assert!(rejoin.start_session().is_empty());
+    }
+
+    #[test]
+    fn test_forget_while_disconnected() {
+        let mut rejoin = Rejoin::new(&ReconnectConfig::default());
+        rejoin.joined(1, "one".to_string());
+        rejoin.joined(2, "two".to_string());
+        rejoin.start_session();
+        rejoin.joined(3, "three".to_string());
+
+        assert!(rejoin.forget(&1));
+        assert!(!rejoin.forget(&1));
+        let mut forgotten = rejoin.forget_all();
+        forgotten.sort();
+        assert_eq!(forgotten, vec![2, 3]);
+        assert!(rejoin.start_session().is_empty());
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let config = ReconnectConfig::default();
+        let first_delay = Duration::from_secs(config.first_delay_secs);
+        let mut rejoin = Rejoin::new(&config);
+        // never connected, the server is not tried again
+        assert_eq!(rejoin.reconnect(&DisconnectReason::ConnectionFailed("refused".to_string())), None);
+        assert_eq!(rejoin.reconnect(&DisconnectReason::NetworkChanged), Some(Duration::ZERO));
+
+        rejoin.connected();
+        assert_eq!(rejoin.reconnect(&DisconnectReason::UserRequested), None);
+        assert_eq!(rejoin.reconnect(&DisconnectReason::TlsFailed("unknown issuer".to_string())), None);
+        assert_eq!(rejoin.reconnect(&DisconnectReason::ServerClosed), Some(first_delay));
+        assert_eq!(rejoin.reconnect(&DisconnectReason::ConnectionFailed("refused".to_string())), Some(first_delay * 2));
+        assert_eq!(rejoin.reconnect(&DisconnectReason::ConnectionTimedOut), Some(first_delay * 4));
+        for _ in 3..config.max_attempts {
+            assert!(rejoin.reconnect(&DisconnectReason::ConnectionLost("reset".to_string())).is_some());
+        }
+        assert_eq!(rejoin.reconnect(&DisconnectReason::ConnectionLost("reset".to_string())), None);
+
+        rejoin.connected();
+        assert_eq!(rejoin.reconnect(&DisconnectReason::ConnectionLost("reset".to_string())), Some(first_delay));
+
+        let mut rejoin = Rejoin::new(&ReconnectConfig { max_attempts: 0, ..config });
+        rejoin.connected();
+        assert_eq!(rejoin.reconnect(&DisconnectReason::ServerClosed), None);
+        assert_eq!(rejoin.reconnect(&DisconnectReason::NetworkChanged), Some(Duration::ZERO));
+    }
+}
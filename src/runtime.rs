@@ -0,0 +1,136 @@
+//! The async runtime the client runs on.
+//!
+//! Spawning tasks, timers and TCP connections go through this module so the library can be embedded
+//! into applications on either runtime. async-std is used with the `runtime-async-std` feature (the default),
+//! tokio with `runtime-tokio`. If both are enabled async-std wins.
+//! Sockets are exposed with the `futures` io traits on both.
+
+#[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio")))]
+compile_error!("Either the `runtime-async-std` or the `runtime-tokio` feature has to be enabled");
+
+#[cfg(feature = "runtime-async-std")]
+pub use self::async_std_runtime::*;
+#[cfg(all(feature = "runtime-tokio", not(feature = "runtime-async-std")))]
+pub use self::tokio_runtime::*;
+
+#[cfg(feature = "runtime-async-std")]
+mod async_std_runtime {
+    use std::{future::Future, time::Duration};
+    use futures::AsyncRead;
+
+    pub use async_std::{net::{TcpStream, ToSocketAddrs}, task::JoinHandle};
+
+    pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        async_std::task::spawn(future)
+    }
+
+    /// Run blocking or CPU heavy work off the executor
+    pub fn spawn_blocking<F, T>(function: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        async_std::task::spawn_blocking(function)
+    }
+
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        async_std::task::block_on(future)
+    }
+
+    pub async fn sleep(duration: Duration) {
+        async_std::task::sleep(duration).await
+    }
+
+    /// `None` if `future` did not complete within `duration`
+    pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+        async_std::future::timeout(duration, future).await.ok()
+    }
+
+    pub async fn connect(address: impl ToSocketAddrs) -> std::io::Result<TcpStream> {
+        TcpStream::connect(address).await
+    }
+
+    pub fn stdin() -> impl AsyncRead + Unpin + Send {
+        async_std::io::stdin()
+    }
+}
+
+#[cfg(all(feature = "runtime-tokio", not(feature = "runtime-async-std")))]
+mod tokio_runtime {
+    use std::{future::Future, pin::Pin, sync::OnceLock, task::{Context, Poll}, time::Duration};
+    use futures::AsyncRead;
+    use tokio::runtime::{Handle, Runtime};
+    use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+    pub use tokio::net::ToSocketAddrs;
+
+    pub type TcpStream = Compat<tokio::net::TcpStream>;
+
+    /// Resolves to the output of the task, panics in the task are propagated
+    pub struct JoinHandle<T>(tokio::task::JoinHandle<T>);
+
+    impl<T> Future for JoinHandle<T> {
+        type Output = T;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+            Pin::new(&mut self.get_mut().0).poll(cx).map(|result| match result {
+                Ok(output) => output,
+                Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Err(e) => panic!("Task failed: {}", e),
+            })
+        }
+    }
+
+    /// The runtime of the application if called from within one, e.g. when embedded,
+    /// otherwise our own, which is started on first use. GTK callbacks run outside of any runtime.
+    fn handle() -> Handle {
+        Handle::try_current().unwrap_or_else(|_| runtime().handle().clone())
+    }
+
+    fn runtime() -> &'static Runtime {
+        static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+        RUNTIME.get_or_init(|| Runtime::new().expect("Failed to start the tokio runtime"))
+    }
+
+    pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        JoinHandle(handle().spawn(future))
+    }
+
+    /// Run blocking or CPU heavy work off the executor
+    pub fn spawn_blocking<F, T>(function: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        JoinHandle(handle().spawn_blocking(function))
+    }
+
+    pub fn block_on<F: Future>(future: F) -> F::Output {
+        runtime().block_on(future)
+    }
+
+    pub async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+
+    /// `None` if `future` did not complete within `duration`
+    pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+        tokio::time::timeout(duration, future).await.ok()
+    }
+
+    pub async fn connect(address: impl ToSocketAddrs) -> std::io::Result<TcpStream> {
+        Ok(tokio::net::TcpStream::connect(address).await?.compat())
+    }
+
+    pub fn stdin() -> impl AsyncRead + Unpin + Send {
+        tokio::io::stdin().compat()
+    }
+}
@@ -0,0 +1,65 @@
+//! Cleans text received from peers before it reaches the UIs.
+//!
+//! Control characters are replaced with visible symbols so a message can not move the cursor or change
+//! the colors of the terminal, and bidirectional overrides are dropped so a message can not reorder
+//! the text around it. Invalid UTF-8 is replaced with `U+FFFD`.
+
+/// Longer messages are cut off, in characters
+pub const MAX_RENDERED_LENGTH: usize = 4096;
+
+const TRUNCATION_MARKER: char = '…';
+
+/// Decode the bytes of a message and make them safe to render
+pub fn sanitize_message(message: &[u8]) -> String {
+    sanitize_text(&String::from_utf8_lossy(message))
+}
+
+/// Make text safe to render, e.g. the topic of a breakout
+pub fn sanitize_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len().min(MAX_RENDERED_LENGTH));
+    for (rendered, c) in text.chars().filter(|c| !is_bidi_control(*c)).enumerate() {
+        if rendered == MAX_RENDERED_LENGTH {
+            result.push(TRUNCATION_MARKER);
+            break;
+        }
+        result.push(escape_control(c));
+    }
+    result
+}
+
+/// Line breaks and tabs are kept, other C0 controls and DEL become their control picture, C1 controls `U+FFFD`
+fn escape_control(c: char) -> char {
+    match c {
+        '\n' | '\t' => c,
+        '\0'..='\x1f' => char::from_u32(0x2400 + c as u32).unwrap(),
+        '\x7f' => '\u{2421}',
+        '\u{80}'..='\u{9f}' => char::REPLACEMENT_CHARACTER,
+        _ => c,
+    }
+}
+
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_message() {
+        assert_eq!(sanitize_message(b"hello\nworld\t!"), "hello\nworld\t!");
+        assert_eq!(sanitize_message(b"\x1b[2J\rok\x7f"), "\u{241b}[2J\u{240d}ok\u{2421}");
+        assert_eq!(sanitize_message(b"\xff\xc2\x9b"), "\u{fffd}\u{fffd}");
+        assert_eq!(sanitize_message("abc\u{202e}def\u{2066}".as_bytes()), "abcdef");
+    }
+
+    #[test]
+    fn test_sanitize_truncates() {
+        let long = "a".repeat(MAX_RENDERED_LENGTH + 10);
+        let sanitized = sanitize_text(&long);
+        assert_eq!(sanitized.chars().count(), MAX_RENDERED_LENGTH + 1);
+        assert!(sanitized.ends_with(TRUNCATION_MARKER));
+        assert_eq!(sanitize_text(&long[..MAX_RENDERED_LENGTH]), long[..MAX_RENDERED_LENGTH]);
+    }
+}
@@ -0,0 +1,125 @@
+//! All settings in a single file, to move them to another machine: the config file and the lists
+//! kept in the data directory. Conference passwords are never stored, so there are no credentials to bundle.
+//!
+//! The bundle is validated completely before any file is replaced. Encrypted files are bundled decrypted,
+//! so the storage has to be unlocked, and are encrypted again when the bundle is applied, see [`crate::storage`].
+
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}};
+
+use serde::{Deserialize, Serialize};
+use crate::{config::{Config, StorageConfig}, constants::Result, paths, peer_labels::PEER_LABELS_FILE_NAME, recent::RECENT_FILE_NAME, storage};
+
+const BUNDLE_VERSION: u32 = 1;
+const CONFIG_FILE_NAME: &str = "config.toml";
+/// kept by the GTK frontend, see `gtk_ui::layout`
+const LAYOUT_FILE_NAME: &str = "layout.toml";
+/// the files in the data directory that are bundled
+const DATA_FILE_NAMES: [&str; 3] = [RECENT_FILE_NAME, PEER_LABELS_FILE_NAME, LAYOUT_FILE_NAME];
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SettingsBundle {
+    pub version: u32,
+    /// contents by file name, files that did not exist are left out
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+}
+
+impl SettingsBundle {
+    /// The settings files of this machine
+    pub fn collect() -> SettingsBundle {
+        let files = bundled_files().into_iter()
+            .filter_map(|(name, path)| Some((name.to_string(), storage::read_to_string(&path).ok()??)))
+            .collect();
+        SettingsBundle { version: BUNDLE_VERSION, files }
+    }
+
+    pub fn encode(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Parse a bundle and check every file in it
+    pub fn decode(contents: &str) -> Result<SettingsBundle> {
+        let bundle: SettingsBundle = toml::from_str(contents).map_err(|e| format!("Not a settings bundle: {}", e))?;
+        if bundle.version == 0 || bundle.version > BUNDLE_VERSION {
+            return Err(format!("Unsupported settings bundle version {}", bundle.version).into());
+        }
+        for (name, contents) in &bundle.files {
+            match name.as_str() {
+                CONFIG_FILE_NAME => {
+                    Config::parse(contents).map_err(|e| format!("Invalid {} in the settings bundle: {}", name, e))?;
+                },
+                name if DATA_FILE_NAMES.contains(&name) => {
+                    contents.parse::<toml::Table>().map_err(|e| format!("Invalid {} in the settings bundle: {}", name, e))?;
+                },
+                name => return Err(format!("Unknown file {} in the settings bundle", name).into()),
+            }
+        }
+        Ok(bundle)
+    }
+
+    /// Replace the settings files of this machine with the bundled ones, returns the names of the replaced files.
+    /// The files of the data directory are encrypted if `storage_config` says so
+    pub fn apply(&self, storage_config: &StorageConfig) -> Result<Vec<String>> {
+        let mut replaced = Vec::new();
+        for (name, path) in bundled_files() {
+            let Some(contents) = self.files.get(name)
+            else {
+                continue;
+            };
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            if storage::ENCRYPTED_FILE_NAMES.contains(&name) {
+                storage::write(&path, contents, storage_config)?;
+            } else {
+                fs::write(&path, contents)?;
+            }
+            replaced.push(name.to_string());
+        }
+        Ok(replaced)
+    }
+}
+
+fn bundled_files() -> Vec<(&'static str, PathBuf)> {
+    let data_dir = paths::data_dir();
+    let mut files = vec![(CONFIG_FILE_NAME, paths::config_file())];
+    files.extend(DATA_FILE_NAMES.iter().map(|&name| (name, data_dir.join(name))));
+    files
+}
+
+/// Write the settings of this machine to `path`
+pub fn export(path: &Path) -> Result<()> {
+    fs::write(path, SettingsBundle::collect().encode()?)?;
+    Ok(())
+}
+
+/// Replace the settings of this machine with the bundle at `path`, returns the names of the replaced files.
+/// The settings are used from the next start.
+pub fn import(path: &Path, storage_config: &StorageConfig) -> Result<Vec<String>> {
+    SettingsBundle::decode(&fs::read_to_string(path)?)?.apply(storage_config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_bundle_decode() {
+        let mut bundle = SettingsBundle { version: BUNDLE_VERSION, files: BTreeMap::new() };
+        bundle.files.insert(CONFIG_FILE_NAME.to_string(), "[history]\nmax_messages = 20\n".to_string());
+        bundle.files.insert(RECENT_FILE_NAME.to_string(), "servers = [\"example:7667\"]\n".to_string());
+        assert_eq!(SettingsBundle::decode(&bundle.encode().unwrap()).unwrap(), bundle);
+
+        let newer = SettingsBundle { version: BUNDLE_VERSION + 1, files: BTreeMap::new() };
+        assert!(SettingsBundle::decode(&newer.encode().unwrap()).is_err());
+        assert!(SettingsBundle::decode("not a bundle").is_err());
+
+        let mut invalid = SettingsBundle { version: BUNDLE_VERSION, files: BTreeMap::new() };
+        invalid.files.insert(CONFIG_FILE_NAME.to_string(), "[history]\nmax_messages = \"many\"\n".to_string());
+        assert!(SettingsBundle::decode(&invalid.encode().unwrap()).is_err());
+
+        let mut unknown = SettingsBundle { version: BUNDLE_VERSION, files: BTreeMap::new() };
+        unknown.files.insert("../.bashrc".to_string(), String::new());
+        assert!(SettingsBundle::decode(&unknown.encode().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Several conference managers wired together in process, a router fans the messages of each member out
+//! to the others like the server does. Drives the protocol between clients end to end without a network:
+//! setup, restructuring when members join or leave, message exchange and signature verification.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::{channel::mpsc, SinkExt, StreamExt};
+use crate::{
+    conference_manager::ConferenceManager,
+    constants::{ConferenceEvent, ConferenceId, EncryptionKey, Message, NumberOfPeers, Receiver, Sender, UIEvent},
+    runtime,
+};
+
+/// How long a member may take to reach an expected event
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+type MemberId = usize;
+
+/// The conference event senders of the current members, by member id
+type Routes = Arc<Mutex<Vec<(MemberId, Sender<ConferenceEvent>)>>>;
+
+pub struct Member {
+    pub id: MemberId,
+    conference_event_sender: Sender<ConferenceEvent>,
+    pub ui_events: Receiver<UIEvent>,
+}
+
+impl Member {
+    /// Hand a message to the manager as the UI would
+    pub async fn send_message(&mut self, message_id: usize, text: &str) {
+        let event = ConferenceEvent::OutboundMessage((message_id, text.as_bytes().to_vec().into()));
+        self.conference_event_sender.send(event).await.expect("conference manager stopped");
+    }
+
+    /// Skip UI events until `f` matches one, panics after [`TIMEOUT`]
+    pub async fn wait_for<T>(&mut self, f: impl Fn(&UIEvent) -> Option<T>) -> T {
+        let ui_events = &mut self.ui_events;
+        runtime::timeout(TIMEOUT, async {
+            loop {
+                let event = ui_events.next().await.expect("conference manager stopped");
+                if let Some(value) = f(&event) {
+                    return value;
+                }
+            }
+        }).await.unwrap_or_else(|| panic!("member {} timed out waiting for an event", self.id))
+    }
+
+    pub async fn wait_ready(&mut self) {
+        self.wait_for(|event| matches!(event, UIEvent::ConferenceRestructuringFinished(_)).then_some(())).await;
+    }
+}
+
+pub struct Simulation {
+    conference_id: ConferenceId,
+    initial_encryption_key: EncryptionKey,
+    routes: Routes,
+    next_member_id: MemberId,
+}
+
+impl Simulation {
+    pub fn new(conference_id: ConferenceId) -> Simulation {
+        Simulation { conference_id, initial_encryption_key: [7; 32], routes: Arc::default(), next_member_id: 0 }
+    }
+
+    fn number_of_peers(&self) -> NumberOfPeers {
+        self.routes.lock().unwrap().len() as NumberOfPeers
+    }
+
+    /// A new member joins, the others are told about the restructuring before any of its messages arrive
+    pub fn join(&mut self) -> Member {
+        let id = self.next_member_id;
+        self.next_member_id += 1;
+        let (conference_event_sender, conference_event_receiver) = mpsc::unbounded();
+        let (message_sender, message_receiver) = mpsc::unbounded();
+        let (ui_event_sender, ui_events) = mpsc::unbounded();
+
+        let number_of_peers = self.number_of_peers() + 1;
+        {
+            let mut routes = self.routes.lock().unwrap();
+            for (_, sender) in routes.iter() {
+                let _ = sender.unbounded_send(ConferenceEvent::ConferenceRestructuring(number_of_peers));
+            }
+            routes.push((id, conference_event_sender.clone()));
+        }
+
+        let mut conference_manager = ConferenceManager::new(
+            self.conference_id,
+            number_of_peers,
+            self.initial_encryption_key,
+            conference_event_receiver,
+            message_sender,
+            ui_event_sender,
+            Default::default(),
+            Default::default(),
+        );
+        runtime::spawn(async move { conference_manager.start_conference_manager().await });
+        runtime::spawn(route(id, message_receiver, self.routes.clone()));
+        Member { id, conference_event_sender, ui_events }
+    }
+
+    /// The member leaves, its manager stops and the others restructure
+    pub fn leave(&mut self, member: Member) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.retain(|(id, _)| *id != member.id);
+        let number_of_peers = routes.len() as NumberOfPeers;
+        for (_, sender) in routes.iter() {
+            let _ = sender.unbounded_send(ConferenceEvent::ConferenceRestructuring(number_of_peers));
+        }
+    }
+}
+
+/// Deliver every message of a member to all other current members
+async fn route(sender_id: MemberId, mut message_receiver: Receiver<Message>, routes: Routes) {
+    while let Some(message) = message_receiver.next().await {
+        for (_, sender) in routes.lock().unwrap().iter().filter(|(id, _)| *id != sender_id) {
+            let _ = sender.unbounded_send(ConferenceEvent::IncomingMessage(message.message.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use crate::constants::KeyImage;
+
+    async fn wait_ready(members: &mut [Member]) {
+        for member in members.iter_mut() {
+            member.wait_ready().await;
+        }
+    }
+
+    /// The text and key image of the sender of the next valid message
+    async fn next_message(member: &mut Member) -> (Bytes, KeyImage) {
+        member.wait_for(|event| match event {
+            UIEvent::IncomingMessage((_, message, true, peer)) => Some((message.clone(), peer.key_image)),
+            UIEvent::IncomingMessage((_, _, false, _)) => panic!("message with invalid signature"),
+            UIEvent::RingMismatch(_) => panic!("members computed different rings"),
+            _ => None,
+        }).await
+    }
+
+    #[test]
+    fn test_members_exchange_messages() {
+        runtime::block_on(async {
+            let mut simulation = Simulation::new(1);
+            let mut members = Vec::new();
+            for _ in 0..3 {
+                members.push(simulation.join());
+                // a member alone in the conference has nobody to exchange keys with
+                if members.len() > 1 {
+                    wait_ready(&mut members).await;
+                }
+            }
+
+            members[0].send_message(1, "hello").await;
+            let (text, first_sender) = next_message(&mut members[1]).await;
+            assert_eq!(text, "hello");
+            let (text, sender) = next_message(&mut members[2]).await;
+            assert_eq!(text, "hello");
+            // the signer is recognized as the same peer by everyone
+            assert_eq!(sender, first_sender);
+
+            members[2].send_message(1, "hi").await;
+            let (text, sender) = next_message(&mut members[0]).await;
+            assert_eq!(text, "hi");
+            assert_ne!(sender, first_sender);
+        });
+    }
+
+    #[test]
+    fn test_members_restructure_after_leave() {
+        runtime::block_on(async {
+            let mut simulation = Simulation::new(2);
+            let mut members = Vec::new();
+            for _ in 0..3 {
+                members.push(simulation.join());
+                // a member alone in the conference has nobody to exchange keys with
+                if members.len() > 1 {
+                    wait_ready(&mut members).await;
+                }
+            }
+
+            simulation.leave(members.remove(1));
+            wait_ready(&mut members).await;
+            members[1].send_message(1, "still here").await;
+            assert_eq!(next_message(&mut members[0]).await.0, "still here");
+
+            // a member joining later adopts the epoch of the others
+            members.push(simulation.join());
+            wait_ready(&mut members).await;
+            members[2].send_message(1, "joined").await;
+            assert_eq!(next_message(&mut members[0]).await.0, "joined");
+            assert_eq!(next_message(&mut members[1]).await.0, "joined");
+        });
+    }
+}
@@ -1,46 +1,311 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
 
-use async_std::{prelude::*, task};
-use futures::{channel::mpsc, select, FutureExt, SinkExt};
-use log::{error, info, warn};
+use futures::{channel::mpsc, select, FutureExt, SinkExt, StreamExt};
+use log::{debug, error, info, warn};
+use tracing::{info_span, Instrument, Span};
 use crate::{
-    connection_manager,
+    config::{Config, FilesConfig, FloodProtectionConfig, HistoryConfig},
+    connection_manager::{self, ServerCapabilities},
     conference_manager,
     constants::{
-        ClientEvent, ConferenceEvent, ConferenceId, Message, MessageID, NumberOfPeers, PacketNonce, Receiver, Sender, ServerEvent, UIAction, UIEvent
+        Breakout, ClientEvent, ConferenceEvent, ConferenceId, DisconnectReason, Message, MessageID, MessageLatency, MigrationStage, NumberOfPeers, PacketNonce, Receiver, Sender,
+        ServerEvent, UIAction, UIEvent, MIGRATION_MESSAGE_ID,
     },
     crypto,
+    file_transfer::{self, IncomingFiles},
+    history_store::{self, HistoryStore, RetentionPolicy},
+    idle::{IdleCheck, IdleConferences, IdleSession},
+    liveness::{Check, ConferenceLiveness},
+    outbound::{MessageRetry, OutboundMessages, MESSAGE_TIMEOUT},
+    #[cfg(feature = "network-monitor")]
+    network_monitor,
+    peer_labels::{PeerLabels, SharedPeerLabels},
+    pending_requests::PendingRequests,
+    plugins::{PluginContext, PluginManager, PLUGIN_MESSAGE_ID},
+    rejoin::Rejoin,
+    runtime,
+    trace::TraceRecord,
+    upload::{self, Chunk, ChunkAccepted, ChunkTimedOut, Uploads},
 };
 
+/// The session is only given up when the server reports this many general errors within the window
+const GENERAL_ERROR_LIMIT: usize = 3;
+const GENERAL_ERROR_WINDOW: Duration = Duration::from_secs(60);
+/// Requests the server has not answered within this time are reported as failed
+const PENDING_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+const PENDING_REQUEST_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Send times of this many messages are kept to report when the first peer saw them
+const SEND_TIMES_KEPT: usize = 100;
+
+/// Numbers the connection spans, every run of the state manager is one connection attempt
+static CONNECTION_ATTEMPT: AtomicU64 = AtomicU64::new(0);
+
+/// A password hash computed on the blocking pool, with what is needed to send the request it belongs to
+#[derive(PartialEq, Eq, Debug)]
+enum DerivedKey {
+    /// `(nonce of the request, password hash, join salt)` of a new conference or breakout
+    Create((PacketNonce, [u8; 32], [u8; crypto::SALT_SIZE])),
+    /// `(nonce of the request, conference id, password hash)`
+    Join((PacketNonce, ConferenceId, [u8; 32])),
+}
+
 #[derive(PartialEq, Eq, Debug)]
 enum SentEvent {
     CreateConference,
+    /// parent conference and the breakout without its id
+    CreateBreakout((ConferenceId, Breakout)),
     GetConferenceJoinSalt((ConferenceId, String)),
     JoinConference((ConferenceId, String)),
     LeaveConference(ConferenceId),
+    /// leave a conference to join it again with the password
+    RejoinConference((ConferenceId, String)),
     SendMessage((ConferenceId, Option<MessageID>)),
+    /// chunk `index` of the upload of a message, see [`crate::upload`]
+    SendChunk((ConferenceId, MessageID, u32)),
+    ListConferences,
     Disconnect,
+    /// a request the UI gave up on, its reply is ignored
+    Cancelled(Box<SentEvent>),
+}
+
+/// Recent general errors reported by the server
+#[derive(Default)]
+struct GeneralErrors {
+    recent: VecDeque<Instant>,
+}
+
+impl GeneralErrors {
+    /// Record an error, returns `true` if there were too many errors to keep the session
+    fn record(&mut self, now: Instant) -> bool {
+        while self.recent.front().is_some_and(|&error| now.duration_since(error) > GENERAL_ERROR_WINDOW) {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(now);
+        self.recent.len() >= GENERAL_ERROR_LIMIT
+    }
+}
+
+/// When the UI sent its messages, to report their latency
+#[derive(Default)]
+struct SendTimes {
+    sent: HashMap<(ConferenceId, MessageID), Instant>,
+}
+
+impl SendTimes {
+    fn sent(&mut self, conference_id: ConferenceId, message_id: MessageID, now: Instant) {
+        self.sent.insert((conference_id, message_id), now);
+        // without read receipts no peer ever reports seeing the message
+        if self.sent.len() > SEND_TIMES_KEPT {
+            if let Some(oldest) = self.sent.iter().min_by_key(|(_, sent_at)| **sent_at).map(|(key, _)| *key) {
+                self.sent.remove(&oldest);
+            }
+        }
+    }
+
+    fn accepted(&self, conference_id: ConferenceId, message_id: MessageID, now: Instant) -> Option<MessageLatency> {
+        let sent_at = self.sent.get(&(conference_id, message_id))?;
+        Some(MessageLatency::Accepted(now.saturating_duration_since(*sent_at)))
+    }
+
+    /// Only the first peer is reported, the send time is forgotten
+    fn seen(&mut self, conference_id: ConferenceId, message_id: MessageID, now: Instant) -> Option<MessageLatency> {
+        let sent_at = self.sent.remove(&(conference_id, message_id))?;
+        Some(MessageLatency::FirstSeen(now.saturating_duration_since(sent_at)))
+    }
+
+    fn failed(&mut self, conference_id: ConferenceId, message_id: MessageID) {
+        self.sent.remove(&(conference_id, message_id));
+    }
+}
+
+/// Peers that joined and left between two counts, only the total is known so one of them is always `0`
+fn peers_changed(previous: NumberOfPeers, number_of_peers: NumberOfPeers) -> Option<(NumberOfPeers, NumberOfPeers)> {
+    match number_of_peers.cmp(&previous) {
+        std::cmp::Ordering::Greater => Some((number_of_peers - previous, 0)),
+        std::cmp::Ordering::Less => Some((0, previous - number_of_peers)),
+        std::cmp::Ordering::Equal => None,
+    }
+}
+
+enum Transport {
+    Tls,
+    #[cfg(any(test, feature = "test-util"))]
+    Plaintext,
+    Replay(Vec<TraceRecord>),
+}
+
+pub async fn start_state_manager(server_address: String, ui_event_sink: Sender<UIEvent>, ui_action_receiver: Receiver<UIAction>) {
+    run_state_manager(server_address, Transport::Tls, ui_event_sink, ui_action_receiver).await
+}
+
+/// Start the state manager without TLS, only meant for talking to the in-process mock server
+#[cfg(any(test, feature = "test-util"))]
+pub async fn start_state_manager_plaintext(server_address: String, ui_event_sink: Sender<UIEvent>, ui_action_receiver: Receiver<UIAction>) {
+    run_state_manager(server_address, Transport::Plaintext, ui_event_sink, ui_action_receiver).await
+}
+
+/// Start the state manager on a recorded trace instead of a server connection
+pub async fn start_state_manager_replay(records: Vec<TraceRecord>, ui_event_sink: Sender<UIEvent>, ui_action_receiver: Receiver<UIAction>) {
+    run_state_manager(String::new(), Transport::Replay(records), ui_event_sink, ui_action_receiver).await
 }
 
-enum Void {}
+async fn run_state_manager(server_address: String, transport: Transport, ui_event_sink: Sender<UIEvent>, mut ui_action_receiver: Receiver<UIAction>) {
+    let mut transport = Some(transport);
+    // read once, every session of this state manager uses the same settings
+    let config = Config::load();
+    let mut rejoin = Rejoin::new(&config.reconnect);
+    // continued from the last accepted chunk once the conference is joined again
+    let mut uploads = Uploads::default();
+    // conferences set to keep no history, the log holds no policy once it is removed, so it is applied again on joining
+    let mut unstored: HashSet<ConferenceId> = HashSet::new();
+    loop {
+        let attempt = CONNECTION_ATTEMPT.fetch_add(1, Ordering::Relaxed) + 1;
+        let span = info_span!("connection", attempt, server_address = %server_address);
+        // only TLS sessions are established again
+        let transport = transport.take().unwrap_or(Transport::Tls);
+        match run_session(&config, server_address.clone(), transport, ui_event_sink.clone(), &mut ui_action_receiver, &mut rejoin, &mut uploads, &mut unstored).instrument(span).await {
+            Some(delay) => {
+                let reconnect = wait_to_reconnect(delay, ui_event_sink.clone(), &mut ui_action_receiver, &mut rejoin).await;
+                uploads.retain_conferences(|conference_id| rejoin.contains(&conference_id));
+                if !reconnect {
+                    break;
+                }
+            },
+            None => break,
+        }
+    }
+}
 
-pub async fn start_state_manager(server_address: String, mut ui_event_sender: Sender<UIEvent>, mut ui_action_receiver: Receiver<UIAction>) {
+/// Wait before the session is established again, the UI is still answered meanwhile.
+/// Returns `false` if the user disconnected while waiting.
+async fn wait_to_reconnect(delay: Duration, mut ui_event_sink: Sender<UIEvent>, ui_action_receiver: &mut Receiver<UIAction>, rejoin: &mut Rejoin) -> bool {
+    let sleep = runtime::sleep(delay).fuse();
+    futures::pin_mut!(sleep);
+    loop {
+        select! {
+            _ = sleep => return true,
+            ui_action = ui_action_receiver.next().fuse() => {
+                let ui_event = match ui_action {
+                    Some(UIAction::Disconnect) | None => {
+                        info!("Not connecting again, disconnected while waiting");
+                        let _ = ui_event_sink.send(UIEvent::Disconnected(DisconnectReason::UserRequested)).await;
+                        return false;
+                    },
+                    Some(UIAction::LeaveConference(conference_id)) => match rejoin.forget(&conference_id) {
+                        true => UIEvent::ConferenceLeft(conference_id),
+                        false => UIEvent::ConferenceLeaveFailed(conference_id),
+                    },
+                    Some(UIAction::LeaveAllConferences) => {
+                        for conference_id in rejoin.forget_all() {
+                            let _ = ui_event_sink.send(UIEvent::ConferenceLeft(conference_id)).await;
+                        }
+                        continue;
+                    },
+                    // nothing can be sent to the server until the session is established again
+                    Some(UIAction::CreateConference(_) | UIAction::CreateBreakout(_)) => UIEvent::ConferenceCreateFailed,
+                    Some(UIAction::JoinConference((conference_id, _)) | UIAction::RejoinConference(conference_id)) => UIEvent::ConferenceJoinFailed(conference_id),
+                    Some(UIAction::SendMessage((conference_id, message_id, _)) | UIAction::SendFile((conference_id, message_id, _))) => UIEvent::MessageError((conference_id, message_id)),
+                    Some(UIAction::AnnounceMigration((conference_id, _))) => UIEvent::MessageError((conference_id, MIGRATION_MESSAGE_ID)),
+                    Some(UIAction::ListConferences) => UIEvent::ConferenceDirectory(None),
+                    Some(_) => {
+                        debug!("Ignored a UI action while waiting to connect again");
+                        continue;
+                    },
+                };
+                let _ = ui_event_sink.send(ui_event).await;
+            },
+        }
+    }
+}
+
+/// Returns the delay before the session is established again, `None` if it is not
+async fn run_session(
+    config: &Config,
+    server_address: String,
+    transport: Transport,
+    mut ui_event_sink: Sender<UIEvent>,
+    ui_action_receiver: &mut Receiver<UIAction>,
+    rejoin: &mut Rejoin,
+    uploads: &mut Uploads,
+    unstored: &mut HashSet<ConferenceId>,
+) -> Option<Duration> {
     let (server_event_sender, mut server_event_receiver) = mpsc::unbounded();
+    // ui events pass through the plugins before reaching the UI
+    let (mut ui_event_sender, mut ui_event_receiver) = mpsc::unbounded::<UIEvent>();
+    let mut plugin_manager = PluginManager::load();
+    let mut plugin_context = PluginContext::default();
+    let peer_labels = PeerLabels::load(&config.storage).shared();
+    let mut idle = IdleConferences::new(&config.idle);
+    let mut idle_session = IdleSession::new(&config.idle, Instant::now());
+    let flood_protection = config.flood_protection.clone();
+    let history = config.history.clone();
+    let files = config.files.clone();
+    let relays = config.relay.chain.clone();
+    let dns = config.dns.clone();
+    let tls = config.tls.clone();
+    let argon2 = config.argon2.params();
     let (mut client_event_sender, client_event_receiver) = mpsc::unbounded();
     let (message_sender, mut message_receiver) = mpsc::unbounded::<Message>();
-    let (disconnect_sender, mut disconnect_receiver) = mpsc::unbounded::<Void>();
+    let (disconnect_sender, mut disconnect_receiver) = mpsc::unbounded::<DisconnectReason>();
+    let (derived_key_sender, mut derived_key_receiver) = mpsc::unbounded::<DerivedKey>();
+    // never sends without the `network-monitor` feature
+    let (network_change_sender, mut network_change_receiver) = mpsc::unbounded::<()>();
+    let reconnectable = matches!(transport, Transport::Tls);
+    #[cfg(feature = "network-monitor")]
+    if reconnectable {
+        network_monitor::spawn_network_monitor(network_change_sender.clone());
+    }
 
     // start connection_manager
-    task::spawn(async move {
-        if let Err(e) = connection_manager::start_connection_manager(server_address, server_event_sender, client_event_receiver).await {
+    runtime::spawn(async move {
+        let result = match transport {
+            Transport::Tls => connection_manager::start_connection_manager_via_relays(&dns, &tls, &relays, server_address, server_event_sender, client_event_receiver).await,
+            #[cfg(any(test, feature = "test-util"))]
+            Transport::Plaintext => connection_manager::start_connection_manager_plaintext(server_address, server_event_sender, client_event_receiver).await,
+            Transport::Replay(records) => connection_manager::replay_trace(records, server_event_sender, client_event_receiver).await,
+        };
+        if let Err(e) = result {
             error!("Error in connection manager: {:?}", e);
-            drop(disconnect_sender);
+            let reason = match e.downcast::<DisconnectReason>() {
+                Ok(reason) => *reason,
+                Err(e) => DisconnectReason::ConnectionLost(e.to_string()),
+            };
+            let _ = disconnect_sender.unbounded_send(reason);
         }
-    });
+    }.in_current_span());
 
     let mut conferences: HashMap<ConferenceId, Sender<ConferenceEvent>> = HashMap::new();
-    let mut send_packets_last_index: PacketNonce = 0;
-    let mut sent_packets: HashMap<PacketNonce, SentEvent> = HashMap::new();
+    let mut sent_packets: PendingRequests<SentEvent> = PendingRequests::new(PENDING_REQUEST_TIMEOUT);
+    let mut server_capabilities = ServerCapabilities::default();
+    // the connection manager reports the handshake once per connection
+    let mut handshake_done = false;
+    let mut general_errors = GeneralErrors::default();
+    let mut send_times = SendTimes::default();
+    let mut outbound = OutboundMessages::default();
+    let mut liveness = ConferenceLiveness::default();
+    // conferences that generated a new key pair since their last key exchange, their uploads start over
+    let mut new_keys: HashSet<ConferenceId> = HashSet::new();
+    // reported to the UI once the session ends, unless the UI is gone
+    let mut disconnect_reason = None;
+    // the conferences of the previous session, queued until the handshake is done
+    for (conference_id, password) in rejoin.start_session() {
+        rejoin_conference(&mut sent_packets, &mut client_event_sender, conference_id, password).await;
+    }
+
+    // check for requests the server never answered
+    let (expiry_sender, mut expiry_receiver) = mpsc::unbounded::<()>();
+    runtime::spawn(async move {
+        loop {
+            runtime::sleep(PENDING_REQUEST_CHECK_INTERVAL).await;
+            if expiry_sender.unbounded_send(()).is_err() {
+                break;
+            }
+        }
+    });
 
 
     loop {
@@ -48,202 +313,219 @@ pub async fn start_state_manager(server_address: String, mut ui_event_sender: Se
             server_event = server_event_receiver.next().fuse() => match server_event {
                 // handle server events
                 Some(server_event) => {
+                    if let Err(reason) = validate_server_event(&server_event, &sent_packets, &outbound, &conferences, handshake_done) {
+                        warn!("Ignoring inconsistent server event: {}", reason);
+                        send_ui_event(&mut ui_event_sender, UIEvent::ProtocolViolation(reason)).await;
+                        continue;
+                    }
+                    liveness.server_event(Instant::now());
+                    // the nonces and conference ids were validated above
                     match server_event {
-                        ServerEvent::HandshakeAcknowledged => {
-                            panic!("This shouldn't happen");
+                        // refused by validate_server_event
+                        ServerEvent::HandshakeAcknowledged => {},
+                        ServerEvent::HandshakeAcknowledgedWithCapabilities(capabilities) => {
+                            info!("Server capabilities: {}", capabilities);
+                            handshake_done = true;
+                            server_capabilities = capabilities;
+                            rejoin.connected();
+                            send_ui_event(&mut ui_event_sender, UIEvent::ServerCapabilities(capabilities)).await;
+                        },
+                        ServerEvent::ConferenceList((packet_nonce, conference_list)) => {
+                            sent_packets.remove(&packet_nonce);
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceDirectory(Some(conference_list))).await;
                         },
                         ServerEvent::ConferenceCreated((packet_nonce, conference_id)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::CreateConference = sent_event {
-                                    ui_event_sender.send(UIEvent::ConferenceCreated(conference_id)).await.unwrap();
-                                    sent_packets.remove(&packet_nonce);
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from CreateConference event, instead got {:?}", packet_nonce, sent_event);
-                                }
-                            } else {
-                                warn!("Received unexpected CreateConference packet with nonce {}", packet_nonce);
+                            match sent_packets.remove(&packet_nonce) {
+                                Some(SentEvent::CreateBreakout((parent_conference_id, breakout))) => {
+                                    let breakout = Breakout { conference_id, ..breakout };
+                                    if let Some(mut conference_sender) = conferences.get(&parent_conference_id) {
+                                        conference_sender.send(ConferenceEvent::AnnounceBreakout(breakout.clone())).await.unwrap();
+                                    }
+                                    send_ui_event(&mut ui_event_sender, UIEvent::BreakoutAnnounced((parent_conference_id, breakout, None))).await;
+                                },
+                                Some(SentEvent::Cancelled(_)) => {
+                                    info!("Ignoring conference {}, its creation was cancelled", conference_id);
+                                },
+                                _ => send_ui_event(&mut ui_event_sender, UIEvent::ConferenceCreated(conference_id)).await,
                             }
                         },
                         ServerEvent::ConferenceJoinSalt((packet_nonce, conference_id, join_salt)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::GetConferenceJoinSalt((expected_conference_id, password)) = sent_event {
-                                    if conference_id != *expected_conference_id {
-                                        warn!("Received unexpected conference id {} from GetConferenceJoinSalt event, instead got {}", conference_id, expected_conference_id);
-                                        continue;
-                                    }
-                                    send_packets_last_index += 1;
-                                    let new_packet_nonce = send_packets_last_index;
-                                    let password_hash = crypto::hash_password_with_salt(password.as_bytes(), &join_salt);
-                                    let packet = ClientEvent::JoinConference((new_packet_nonce, conference_id, password_hash));
-                                    let password_clone = password.clone();
-                                    sent_packets.remove(&packet_nonce);
-                                    sent_packets.insert(new_packet_nonce, SentEvent::JoinConference((conference_id, password_clone)));
-                                    client_event_sender.send(packet).await.unwrap();
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from GetConferenceJoinSalt event, instead got {:?}", packet_nonce, sent_event);
-                                }
-                            } else {
-                                warn!("Received unexpected ConferenceJoinSalt packet with nonce {}", packet_nonce);
-                            }
+                            let Some(SentEvent::GetConferenceJoinSalt((_, password))) = sent_packets.get(&packet_nonce)
+                            else {
+                                // cancelled
+                                sent_packets.remove(&packet_nonce);
+                                continue;
+                            };
+                            // the join continues under the same nonce, so it can still be cancelled
+                            let password = password.clone();
+                            sent_packets.replace(&packet_nonce, SentEvent::JoinConference((conference_id, password.clone())));
+                            send_ui_event(&mut ui_event_sender, UIEvent::DerivingKey(Some(conference_id))).await;
+                            let argon2 = argon2.clone();
+                            derive_key(&derived_key_sender, move || {
+                                let password_hash = crypto::hash_password_with_params(password.as_bytes(), &join_salt, argon2);
+                                DerivedKey::Join((packet_nonce, conference_id, password_hash))
+                            });
+                        },
+                        ServerEvent::ConferenceJoined((packet_nonce, conference_id, _, _)) if matches!(sent_packets.get(&packet_nonce), Some(SentEvent::Cancelled(_))) => {
+                            // the server was faster than the cancellation, leave right away
+                            sent_packets.remove(&packet_nonce);
+                            info!("Leaving conference {}, joining it was cancelled", conference_id);
+                            let packet_nonce = sent_packets.insert(SentEvent::Cancelled(Box::new(SentEvent::LeaveConference(conference_id))));
+                            client_event_sender.send(ClientEvent::LeaveConference((packet_nonce, conference_id))).await.unwrap();
                         },
                         ServerEvent::ConferenceJoined((packet_nonce, conference_id, number_of_peers, encryption_salt)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::JoinConference((expected_conference_id, password)) = sent_event {
-                                    if conference_id != *expected_conference_id {
-                                        warn!("Received unexpected conference id {} from JoinConference event, instead got {}", conference_id, expected_conference_id);
-                                        continue;
-                                    }
-                                    let password_clone = password.clone();
-                                    sent_packets.remove(&packet_nonce);
-                                    conferences.insert(conference_id,
-                                        create_conference(
-                                            conference_id, number_of_peers, password_clone.as_bytes(), 
-                                            &encryption_salt, message_sender.clone(), ui_event_sender.clone()
-                                    ).await);
-                                    ui_event_sender.send(UIEvent::ConferenceJoined((conference_id, number_of_peers))).await.unwrap();
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from CreateConference event, instead got {:?}", packet_nonce, sent_event);
-                                }
-                            } else {
-                                warn!("Received unexpected CreateConference packet with nonce {}", packet_nonce);
-                            }
+                            let Some(SentEvent::JoinConference((_, password))) = sent_packets.remove(&packet_nonce) else { continue };
+                            rejoin.joined(conference_id, password.clone());
+                            liveness.add(conference_id, number_of_peers, Instant::now());
+                            idle.add(conference_id, Instant::now());
+                            conferences.insert(conference_id,
+                                create_conference(
+                                    conference_id, number_of_peers, password,
+                                    encryption_salt, argon2.clone(), message_sender.clone(), ui_event_sender.clone(), peer_labels.clone(), flood_protection.clone(), history.clone(), files.clone(),
+                                    unstored.contains(&conference_id)
+                            ));
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceJoined((conference_id, number_of_peers))).await;
+                        },
+                        ServerEvent::ConferenceLeft((packet_nonce, conference_id)) | ServerEvent::ConferenceLeaveError((packet_nonce, conference_id))
+                            if matches!(sent_packets.get(&packet_nonce), Some(SentEvent::Cancelled(_))) => {
+                            // a conference joined after the join was cancelled, the UI never saw it
+                            sent_packets.remove(&packet_nonce);
+                            debug!("Left conference {} after a cancelled join", conference_id);
                         },
                         ServerEvent::ConferenceLeft((packet_nonce, conference_id)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::LeaveConference(expected_conference_id) = sent_event {
-                                    if conference_id != *expected_conference_id {
-                                        warn!("Received unexpected conference id {} from LeaveConference event, instead got {}", conference_id, expected_conference_id);
-                                        continue;
+                            let sent_event = sent_packets.remove(&packet_nonce);
+                            if !matches!(sent_event, Some(SentEvent::RejoinConference(_))) {
+                                uploads.retain_conferences(|upload_conference_id| upload_conference_id != conference_id);
+                            }
+                            if let Some(mut conference_sender) = conferences.remove(&conference_id) {
+                                // the history is kept for joining again right away
+                                if !matches!(sent_event, Some(SentEvent::RejoinConference(_))) {
+                                    if conference_sender.send(ConferenceEvent::Left).await.is_err() {
+                                        debug!("Conference manager of conference {} stopped before it was left", conference_id);
                                     }
-                                    ui_event_sender.send(UIEvent::ConferenceLeft(conference_id)).await.unwrap();
-                                    sent_packets.remove(&packet_nonce);
-                                    conferences.remove(&conference_id);
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from LeaveConference event, instead got {:?}", packet_nonce, sent_event);
                                 }
-                            } else {
-                                warn!("Received unexpected LeaveConference packet with nonce {}", packet_nonce);
+                            }
+                            liveness.remove(&conference_id);
+                            idle.remove(&conference_id);
+                            rejoin.left(&conference_id);
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceLeft(conference_id)).await;
+                            if let Some(SentEvent::RejoinConference((_, password))) = sent_event {
+                                rejoin_conference(&mut sent_packets, &mut client_event_sender, conference_id, password).await;
                             }
                         },
                         ServerEvent::MessageAccepted((packet_nonce, conference_id)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::SendMessage((expected_conference_id, message_id)) = sent_event {
-                                    if conference_id != *expected_conference_id {
-                                        warn!("Received unexpected conference id {} from SendMessage event, instead got {}", conference_id, expected_conference_id);
-                                        continue;
-                                    }
-                                    if let Some(message_id) = message_id {
-                                        // only notify ui when a text message is accepted
-                                        ui_event_sender.send(UIEvent::MessageAccepted((conference_id, *message_id))).await.unwrap();
-                                    }
-                                    sent_packets.remove(&packet_nonce);
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from SendMessage event, instead got {:?}", packet_nonce, sent_event);
+                            // only notify ui when a text message is accepted
+                            let accepted = match answered_request(&mut sent_packets, &outbound, &packet_nonce) {
+                                Some(SentEvent::SendMessage((_, Some(message_id)))) => {
+                                    finish_message(&mut sent_packets, &mut outbound, conference_id, message_id);
+                                    Some(message_id)
+                                },
+                                Some(SentEvent::SendChunk((_, message_id, index))) => match uploads.accepted(conference_id, message_id, index) {
+                                    ChunkAccepted::Next(chunk) => {
+                                        let progress = match uploads.file(conference_id, message_id) {
+                                            Some(file) => UIEvent::FileTransferProgress((conference_id, file.progress(message_id, chunk.index as usize))),
+                                            None => UIEvent::UploadProgress((conference_id, message_id, chunk.index as usize, chunk.total as usize)),
+                                        };
+                                        send_ui_event(&mut ui_event_sender, progress).await;
+                                        if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                            conference_sender.send(outbound_chunk(uploads, conference_id, message_id, chunk)).await.unwrap();
+                                        }
+                                        None
+                                    },
+                                    ChunkAccepted::Finished => Some(message_id),
+                                    ChunkAccepted::Stale => None,
+                                },
+                                _ => None,
+                            };
+                            if let Some(message_id) = accepted {
+                                // own messages are kept in the history once the server accepted them
+                                if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                    conference_sender.send(ConferenceEvent::MessageAccepted(message_id)).await.unwrap();
+                                }
+                                send_ui_event(&mut ui_event_sender, UIEvent::MessageAccepted((conference_id, message_id))).await;
+                                if let Some(latency) = send_times.accepted(conference_id, message_id, Instant::now()) {
+                                    send_ui_event(&mut ui_event_sender, UIEvent::MessageLatency((conference_id, message_id, latency))).await;
                                 }
-                            } else {
-                                warn!("Received unexpected SendMessage packet with nonce {}", packet_nonce);
                             }
                         },
                         ServerEvent::ConferenceRestructuring((conference_id, number_of_peers)) => {
                             if let Some(mut conference_sender) = conferences.get(&conference_id) {
                                 conference_sender.send(ConferenceEvent::ConferenceRestructuring(number_of_peers)).await.unwrap();
-                                ui_event_sender.send(UIEvent::ConferenceRestructuring((conference_id, number_of_peers))).await.unwrap();
-                            } else {
-                                warn!("Attempted to restructure non-existent conference {}", conference_id);
+                                let previous = liveness.set_number_of_peers(conference_id, number_of_peers);
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceRestructuring((conference_id, number_of_peers))).await;
+                                if let Some((joined, left)) = previous.and_then(|previous| peers_changed(previous, number_of_peers)) {
+                                    send_ui_event(&mut ui_event_sender, UIEvent::PeersChanged((conference_id, joined, left))).await;
+                                }
                             }
                         },
                         ServerEvent::IncomingMessage((conference_id, message)) => {
                             if let Some(mut conference_sender) = conferences.get(&conference_id) {
                                 conference_sender.send(ConferenceEvent::IncomingMessage(message)).await.unwrap();
-                            } else {
-                                warn!("Received a message for a non-existent conference {}", conference_id);
+                            }
+                            if liveness.inbound(conference_id, Instant::now()) {
+                                info!("Conference {} is relaying messages again", conference_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceStale((conference_id, false))).await;
                             }
                         },
                         ServerEvent::GeneralError => {
-                            error!("Received a general error from the server");
-                            break;
-                        },
-                        ServerEvent::ConferenceCreationError(packet_nonce) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::CreateConference = sent_event {
-                                    ui_event_sender.send(UIEvent::ConferenceCreateFailed).await.unwrap();
-                                    sent_packets.remove(&packet_nonce);
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from CreateConference event, instead got {:?}", packet_nonce, sent_event);
-                                }
-                            } else {
-                                warn!("Received unexpected ConferenceCreationError packet with nonce {}", packet_nonce);
+                            // the error is not tied to a request, so the pending requests are kept
+                            if general_errors.record(Instant::now()) {
+                                error!("Received {} general errors from the server within {:?}, disconnecting", GENERAL_ERROR_LIMIT, GENERAL_ERROR_WINDOW);
+                                send_ui_event(&mut ui_event_sender, UIEvent::ServerError(true)).await;
+                                let _ = client_event_sender.send(ClientEvent::Disconnect).await;
+                                disconnect_reason = Some(DisconnectReason::ServerErrors);
+                                break;
                             }
+                            warn!("Received a general error from the server");
+                            send_ui_event(&mut ui_event_sender, UIEvent::ServerError(false)).await;
                         },
-                        ServerEvent::ConferenceJoinSaltError((packet_nonce, conference_id)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::GetConferenceJoinSalt((expected_conference_id, _)) = sent_event {
-                                    if conference_id != *expected_conference_id {
-                                        warn!("Received unexpected conference id {} from ConferenceJoinSaltError event, instead got {}", conference_id, expected_conference_id);
-                                        continue;
-                                    }
-                                    ui_event_sender.send(UIEvent::ConferenceJoinFailed(conference_id)).await.unwrap();
-                                    sent_packets.remove(&packet_nonce);
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from ConferenceJoinSaltError event, instead got {:?}", packet_nonce, sent_event);
-                                }
-                            } else {
-                                warn!("Received unexpected ConferenceJoinSaltError packet with nonce {}", packet_nonce);
+                        ServerEvent::ConferenceCreationError(packet_nonce) => {
+                            if !matches!(sent_packets.remove(&packet_nonce), Some(SentEvent::Cancelled(_))) {
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceCreateFailed).await;
                             }
                         },
-                        ServerEvent::ConferenceJoinError((packet_nonce, conference_id)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::JoinConference((expected_conference_id, _)) = sent_event {
-                                    if conference_id != *expected_conference_id {
-                                        warn!("Received unexpected conference id {} from ConferenceJoinError event, instead got {}", conference_id, expected_conference_id);
-                                        continue;
-                                    }
-                                    ui_event_sender.send(UIEvent::ConferenceJoinFailed(conference_id)).await.unwrap();
-                                    sent_packets.remove(&packet_nonce);
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from ConferenceJoinError event, instead got {:?}", packet_nonce, sent_event);
-                                }
-                            } else {
-                                warn!("Received unexpected ConferenceJoinError packet with nonce {}", packet_nonce);
+                        ServerEvent::ConferenceJoinSaltError((packet_nonce, conference_id)) | ServerEvent::ConferenceJoinError((packet_nonce, conference_id)) => {
+                            if !matches!(sent_packets.remove(&packet_nonce), Some(SentEvent::Cancelled(_))) {
+                                rejoin.failed(&conference_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceJoinFailed(conference_id)).await;
                             }
                         },
                         ServerEvent::ConferenceLeaveError((packet_nonce, conference_id)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::LeaveConference(expected_conference_id) = sent_event {
-                                    if conference_id != *expected_conference_id {
-                                        warn!("Received unexpected conference id {} from ConferenceLeaveError event, instead got {}", conference_id, expected_conference_id);
-                                        continue;
-                                    }
-                                    warn!("Received a ConferenceLeaveError event for conference {}", conference_id);
-                                    // ignore error and still remove conference
-                                    ui_event_sender.send(UIEvent::ConferenceLeft(conference_id)).await.unwrap();
-                                    sent_packets.remove(&packet_nonce);
-                                    conferences.remove(&conference_id);
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from ConferenceLeaveError event, instead got {:?}", packet_nonce, sent_event);
-                                }
-                            } else {
-                                warn!("Received unexpected ConferenceLeaveError packet with nonce {}", packet_nonce);
+                            warn!("Received a ConferenceLeaveError event for conference {}", conference_id);
+                            // ignore error and still remove conference
+                            let sent_event = sent_packets.remove(&packet_nonce);
+                            if !matches!(sent_event, Some(SentEvent::RejoinConference(_))) {
+                                uploads.retain_conferences(|upload_conference_id| upload_conference_id != conference_id);
+                            }
+                            conferences.remove(&conference_id);
+                            liveness.remove(&conference_id);
+                            idle.remove(&conference_id);
+                            rejoin.left(&conference_id);
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceLeft(conference_id)).await;
+                            if let Some(SentEvent::RejoinConference((_, password))) = sent_event {
+                                rejoin_conference(&mut sent_packets, &mut client_event_sender, conference_id, password).await;
                             }
                         },
+                        ServerEvent::Ping(_) | ServerEvent::Pong(_) => {
+                            // answered by the connection manager, never forwarded
+                        },
+                        ServerEvent::Unknown((event_type, length)) => {
+                            warn!("Skipped unknown server event of type {:#04x} with {} bytes, the server may be newer than this client", event_type, length);
+                        },
+                        ServerEvent::PinMismatch(mismatch) => {
+                            // the connection manager ends the session, the UI warns about the interception
+                            error!("The key of the server matches none of the pins: {}", mismatch);
+                            disconnect_reason = Some(DisconnectReason::PinMismatch(mismatch));
+                            break;
+                        },
                         ServerEvent::MessageError((packet_nonce, conference_id)) => {
-                            if let Some(sent_event) = sent_packets.get(&packet_nonce) {
-                                if let SentEvent::SendMessage((expected_conference_id, message_id)) = sent_event {
-                                    if conference_id != *expected_conference_id {
-                                        warn!("Received unexpected conference id {} from MessageError event, instead got {}", conference_id, expected_conference_id);
-                                        continue;
-                                    }
-                                    warn!("Received a MessageError event for conference {}", conference_id);
-                                    if let Some(message_id) = message_id {
-                                        // only notify ui when a text message is rejected
-                                        ui_event_sender.send(UIEvent::MessageRejected((conference_id, *message_id))).await.unwrap();
-                                    }
-                                    sent_packets.remove(&packet_nonce);
-                                } else {
-                                    warn!("Received unexpected packet with nonce {} from MessageError event, instead got {:?}", packet_nonce, sent_event);
-                                }
-                            } else {
-                                warn!("Received unexpected MessageError packet with nonce {}", packet_nonce);
+                            warn!("Received a MessageError event for conference {}", conference_id);
+                            // only notify ui when a text message is rejected
+                            if let Some(SentEvent::SendMessage((_, Some(message_id))) | SentEvent::SendChunk((_, message_id, _))) = answered_request(&mut sent_packets, &outbound, &packet_nonce) {
+                                finish_message(&mut sent_packets, &mut outbound, conference_id, message_id);
+                                uploads.remove(conference_id, message_id);
+                                send_times.failed(conference_id, message_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::MessageRejected((conference_id, message_id))).await;
                             }
                         },
                     }
@@ -253,84 +535,451 @@ pub async fn start_state_manager(server_address: String, mut ui_event_sender: Se
             message = message_receiver.next().fuse() => match message {
                 // handle messages
                 Some(message) => {
-                    send_packets_last_index += 1;
-                    let packet_nonce = send_packets_last_index;
-                    let message_id = message.message_id;
-                    let conference_id = message.conference;
+                    if let Some(max_message_size) = server_capabilities.max_message_size.filter(|&max| message.message.len() > max as usize) {
+                        warn!("Not sending a message of {} bytes to conference {}, the server accepts up to {} bytes", message.message.len(), message.conference, max_message_size);
+                        if let Some(message_id) = message.message_id {
+                            uploads.remove(message.conference, message_id);
+                            send_times.failed(message.conference, message_id);
+                            send_ui_event(&mut ui_event_sender, UIEvent::MessageTooLarge((message.conference, message_id, max_message_size))).await;
+                        }
+                        continue;
+                    }
+                    let pending_chunk = message.message_id
+                        .and_then(|message_id| Some((message_id, uploads.pending_chunk(message.conference, message_id)?)));
+                    let packet_nonce = match pending_chunk {
+                        Some((message_id, index)) => sent_packets.insert(SentEvent::SendChunk((message.conference, message_id, index))),
+                        None => {
+                            let packet_nonce = sent_packets.insert_with_timeout(SentEvent::SendMessage((message.conference, message.message_id)), MESSAGE_TIMEOUT);
+                            outbound.sent(&message, packet_nonce);
+                            packet_nonce
+                        },
+                    };
                     let packet = ClientEvent::SendMessage((packet_nonce, message));
-                    sent_packets.insert(packet_nonce, SentEvent::SendMessage((conference_id, message_id)));
                     client_event_sender.send(packet).await.unwrap();
                 },
                 None => continue,
             },
+            ui_event = ui_event_receiver.next().fuse() => match ui_event {
+                // pass ui events through the plugins
+                Some(mut ui_event) => {
+                    if let UIEvent::IncomingMessage((conference_id, message, _, _)) = &mut ui_event {
+                        idle.message(*conference_id, Instant::now());
+                        if !plugin_manager.on_incoming_message(&mut plugin_context, *conference_id, message) {
+                            continue;
+                        }
+                    }
+                    plugin_manager.on_conference_event(&mut plugin_context, &ui_event);
+                    send_plugin_messages(&mut plugin_context, &conferences).await;
+                    let latency = match &ui_event {
+                        UIEvent::MessageSeen((conference_id, message_id, _)) => send_times.seen(*conference_id, *message_id, Instant::now())
+                            .map(|latency| UIEvent::MessageLatency((*conference_id, *message_id, latency))),
+                        UIEvent::MessageError((conference_id, message_id)) => {
+                            uploads.remove(*conference_id, *message_id);
+                            send_times.failed(*conference_id, *message_id);
+                            None
+                        },
+                        UIEvent::OwnFingerprint((conference_id, _)) => {
+                            new_keys.insert(*conference_id);
+                            None
+                        },
+                        // uploads pause while the keys are exchanged and until a lost connection is back
+                        UIEvent::ConferenceRestructuringFinished(conference_id) => {
+                            if new_keys.remove(conference_id) {
+                                // peers cannot link the chunks signed with the new key to the ones before
+                                uploads.restart(*conference_id);
+                            }
+                            if let Some(mut conference_sender) = conferences.get(conference_id) {
+                                for (message_id, chunk) in uploads.resume(*conference_id) {
+                                    debug!("Continuing the upload of message {} to conference {} at chunk {} of {}", message_id, conference_id, chunk.index + 1, chunk.total);
+                                    conference_sender.send(outbound_chunk(uploads, *conference_id, message_id, chunk)).await.unwrap();
+                                }
+                            }
+                            None
+                        },
+                        _ => None,
+                    };
+                    if ui_event_sink.send(ui_event).await.is_err() {
+                        // nobody is left to show the conferences, leave the server instead of panicking
+                        warn!("UI event channel is closed, disconnecting");
+                        let _ = client_event_sender.send(ClientEvent::Disconnect).await;
+                        break;
+                    }
+                    if let Some(latency) = latency {
+                        let _ = ui_event_sink.send(latency).await;
+                    }
+                },
+                None => continue,
+            },
             ui_event = ui_action_receiver.next().fuse() => match ui_event {
                 // handle UI events
                 Some(ui_event) => {
+                    // shown messages are no sign that the user is there
+                    if !matches!(ui_event, UIAction::MessagesDisplayed(_)) {
+                        idle_session.activity(Instant::now());
+                    }
                     match ui_event {
+                        UIAction::UserActive => {},
                         UIAction::CreateConference(password) => {
-                            let (password_hash, join_salt) = crypto::hash_password(password.as_bytes());
-                            let encryption_salt = crypto::generate_salt();
-                            send_packets_last_index += 1;
-                            let packet_nonce = send_packets_last_index;
-                            let packet = ClientEvent::CreateConference((packet_nonce, password_hash, join_salt, encryption_salt));
-
-                            sent_packets.insert(packet_nonce, SentEvent::CreateConference);
-
-                            client_event_sender.send(packet).await.unwrap();
+                            // the nonce is taken before hashing, so the request can be cancelled meanwhile
+                            let packet_nonce = sent_packets.insert(SentEvent::CreateConference);
+                            send_ui_event(&mut ui_event_sender, UIEvent::RequestPending((packet_nonce, None))).await;
+                            send_ui_event(&mut ui_event_sender, UIEvent::DerivingKey(None)).await;
+                            let argon2 = argon2.clone();
+                            derive_key(&derived_key_sender, move || {
+                                let join_salt = crypto::generate_salt();
+                                let password_hash = crypto::hash_password_with_params(password.as_bytes(), &join_salt, argon2);
+                                DerivedKey::Create((packet_nonce, password_hash, join_salt))
+                            });
+                        },
+                        UIAction::CancelRequest(packet_nonce) => {
+                            if let Some(request @ (SentEvent::CreateConference | SentEvent::GetConferenceJoinSalt(_) | SentEvent::JoinConference(_)))
+                                = sent_packets.get_mut(&packet_nonce) {
+                                info!("Cancelling request {}", packet_nonce);
+                                // kept until the reply or the timeout, so a late reply is recognized
+                                let original = std::mem::replace(request, SentEvent::Disconnect);
+                                *request = SentEvent::Cancelled(Box::new(original));
+                                send_ui_event(&mut ui_event_sender, UIEvent::RequestCancelled(packet_nonce)).await;
+                            } else {
+                                warn!("Attempted to cancel request {}, which is not a pending join or create", packet_nonce);
+                            }
                         },
                         UIAction::JoinConference((conference_id, password)) => {
                             if !conferences.contains_key(&conference_id) {
-                                send_packets_last_index += 1;
-                                let packet_nonce = send_packets_last_index;
+                                let packet_nonce = sent_packets.insert(SentEvent::GetConferenceJoinSalt((conference_id, password)));
                                 let packet = ClientEvent::GetConferenceJoinSalt((packet_nonce, conference_id));
-
-                                sent_packets.insert(packet_nonce, SentEvent::GetConferenceJoinSalt((conference_id, password)));
+                                send_ui_event(&mut ui_event_sender, UIEvent::RequestPending((packet_nonce, Some(conference_id)))).await;
 
                                 client_event_sender.send(packet).await.unwrap();
                             } else {
                                 warn!("Attempted to join conference we are already a part of: {}", conference_id);
-                                ui_event_sender.send(UIEvent::ConferenceJoinFailed(conference_id)).await.unwrap();
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceJoinFailed(conference_id)).await;
                             }
                         },
                         UIAction::LeaveConference(conference_id) => {
                             if conferences.contains_key(&conference_id) {
-                                send_packets_last_index += 1;
-                                let packet_nonce = send_packets_last_index;
+                                let packet_nonce = sent_packets.insert(SentEvent::LeaveConference(conference_id));
                                 let packet = ClientEvent::LeaveConference((packet_nonce, conference_id));
 
-                                sent_packets.insert(packet_nonce, SentEvent::LeaveConference(conference_id));
-
                                 client_event_sender.send(packet).await.unwrap();
                             } else {
                                 warn!("Attempted to leave conference we are not a part of: {}", conference_id);
-                                ui_event_sender.send(UIEvent::ConferenceLeaveFailed(conference_id)).await.unwrap();
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceLeaveFailed(conference_id)).await;
                             }
                         },
-                        UIAction::SendMessage((conference_id, message_id, message)) => {
+                        UIAction::LeaveAllConferences => {
+                            info!("Leaving all {} conferences", conferences.len());
+                            for &conference_id in conferences.keys() {
+                                let packet_nonce = sent_packets.insert(SentEvent::LeaveConference(conference_id));
+                                client_event_sender.send(ClientEvent::LeaveConference((packet_nonce, conference_id))).await.unwrap();
+                            }
+                        },
+                        UIAction::SendMessage((conference_id, message_id, mut message)) => {
+                            if !plugin_manager.on_before_send(&mut plugin_context, conference_id, &mut message) {
+                                send_plugin_messages(&mut plugin_context, &conferences).await;
+                                continue;
+                            }
+                            send_plugin_messages(&mut plugin_context, &conferences).await;
+                            let number_of_peers = liveness.number_of_peers(conference_id).unwrap_or(1);
+                            let chunk_size = upload::chunk_size(server_capabilities.max_message_size, number_of_peers);
+                            // larger messages are uploaded in chunks, unless the server accepts too little for a chunk
+                            let max_message_size = match chunk_size {
+                                Some(_) => Some(upload::MAX_UPLOAD_SIZE as u32),
+                                None => server_capabilities.max_message_size,
+                            };
+                            if let Some(max_message_size) = max_message_size.filter(|&max| message.len() > max as usize) {
+                                // the signed and encrypted message is larger still, no need to sign this one
+                                send_ui_event(&mut ui_event_sender, UIEvent::MessageTooLarge((conference_id, message_id, max_message_size))).await;
+                                continue;
+                            }
                             if let Some(mut conference_sender) = conferences.get(&conference_id) {
-                                conference_sender.send(ConferenceEvent::OutboundMessage((message_id, message.as_bytes().to_vec()))).await.unwrap();
+                                send_times.sent(conference_id, message_id, Instant::now());
+                                idle.message(conference_id, Instant::now());
+                                let conference_event = match chunk_size.filter(|&chunk_size| message.len() > chunk_size) {
+                                    Some(chunk_size) => {
+                                        info!("Uploading a message of {} bytes to conference {} in chunks of {} bytes", message.len(), conference_id, chunk_size);
+                                        ConferenceEvent::OutboundChunk((message_id, uploads.start(conference_id, message_id, message.into(), chunk_size)))
+                                    },
+                                    None => ConferenceEvent::OutboundMessage((message_id, message.into())),
+                                };
+                                conference_sender.send(conference_event).await.unwrap();
                             } else {
                                 warn!("Attempted to send message to non-existent conference {}", conference_id);
-                                ui_event_sender.send(UIEvent::MessageError((conference_id, message_id))).await.unwrap();
+                                send_ui_event(&mut ui_event_sender, UIEvent::MessageError((conference_id, message_id))).await;
+                            }
+                        },
+                        UIAction::SendFile((conference_id, message_id, path)) => {
+                            let Some(mut conference_sender) = conferences.get(&conference_id)
+                            else {
+                                warn!("Attempted to send a file to non-existent conference {}", conference_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::MessageError((conference_id, message_id))).await;
+                                continue;
+                            };
+                            let number_of_peers = liveness.number_of_peers(conference_id).unwrap_or(1);
+                            let Some(chunk_size) = upload::chunk_size(server_capabilities.max_message_size, number_of_peers)
+                            else {
+                                let reason = "the server does not accept messages large enough for a part of a file".to_string();
+                                send_ui_event(&mut ui_event_sender, UIEvent::FileNotSent((conference_id, message_id, reason))).await;
+                                continue;
+                            };
+                            let max_size = files.max_size();
+                            let (name, content) = match runtime::spawn_blocking(move || file_transfer::read_file(&path, max_size)).await {
+                                Ok(file) => file,
+                                Err(e) => {
+                                    warn!("Could not send a file to conference {}: {}", conference_id, e);
+                                    send_ui_event(&mut ui_event_sender, UIEvent::FileNotSent((conference_id, message_id, e.to_string()))).await;
+                                    continue;
+                                },
+                            };
+                            info!("Sending a file of {} bytes to conference {} in chunks of {} bytes", content.len(), conference_id, chunk_size);
+                            send_times.sent(conference_id, message_id, Instant::now());
+                            idle.message(conference_id, Instant::now());
+                            let chunk = uploads.start_file(conference_id, message_id, name, content, chunk_size);
+                            conference_sender.send(outbound_chunk(uploads, conference_id, message_id, chunk)).await.unwrap();
+                        },
+                        UIAction::RejoinConference(conference_id) => {
+                            if let Some(password) = rejoin.password(&conference_id) {
+                                info!("Leaving conference {} to join it again", conference_id);
+                                let packet_nonce = sent_packets.insert(SentEvent::RejoinConference((conference_id, password.clone())));
+                                client_event_sender.send(ClientEvent::LeaveConference((packet_nonce, conference_id))).await.unwrap();
+                            } else {
+                                warn!("Attempted to rejoin conference we are not a part of: {}", conference_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceJoinFailed(conference_id)).await;
+                            }
+                        },
+                        UIAction::ListPlugins => {
+                            send_ui_event(&mut ui_event_sender, UIEvent::PluginList(plugin_manager.plugin_names())).await;
+                        },
+                        UIAction::ReloadPlugins => {
+                            info!("Reloading plugins");
+                            plugin_manager = PluginManager::load();
+                            send_ui_event(&mut ui_event_sender, UIEvent::PluginList(plugin_manager.plugin_names())).await;
+                        },
+                        UIAction::SetPeerLabel((key_image, label)) => {
+                            if let Err(e) = peer_labels.lock().unwrap().set(key_image, label) {
+                                warn!("Could not save peer labels: {}", e);
+                            }
+                        },
+                        UIAction::MessagesDisplayed(conference_id) => {
+                            if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                conference_sender.send(ConferenceEvent::MessagesDisplayed).await.unwrap();
+                            }
+                        },
+                        UIAction::SetReadReceipts((conference_id, enabled)) => {
+                            if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                conference_sender.send(ConferenceEvent::SetReadReceipts(enabled)).await.unwrap();
+                            } else {
+                                warn!("Attempted to change read receipts of non-existent conference {}", conference_id);
+                            }
+                        },
+                        UIAction::SetUnlinkable((conference_id, enabled)) => {
+                            if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                conference_sender.send(ConferenceEvent::SetUnlinkable(enabled)).await.unwrap();
+                            } else {
+                                warn!("Attempted to change the unlinkable mode of non-existent conference {}", conference_id);
+                            }
+                        },
+                        UIAction::SetAutoLeave((conference_id, enabled)) => {
+                            idle.set_enabled(conference_id, enabled, Instant::now());
+                        },
+                        UIAction::SetRetentionPolicy((conference_id, policy)) => {
+                            if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                if policy == RetentionPolicy::Never {
+                                    unstored.insert(conference_id);
+                                } else {
+                                    unstored.remove(&conference_id);
+                                }
+                                conference_sender.send(ConferenceEvent::SetRetentionPolicy(policy)).await.unwrap();
+                            } else {
+                                warn!("Attempted to change the retention policy of non-existent conference {}", conference_id);
+                            }
+                        },
+                        UIAction::SetListenOnly((conference_id, enabled)) => {
+                            if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                conference_sender.send(ConferenceEvent::SetListenOnly(enabled)).await.unwrap();
+                            } else {
+                                warn!("Attempted to change listen only mode of non-existent conference {}", conference_id);
+                            }
+                        },
+                        UIAction::AllowFlooding((conference_id, key_image)) => {
+                            if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                conference_sender.send(ConferenceEvent::AllowFlooding(key_image)).await.unwrap();
+                            } else {
+                                warn!("Attempted to unmute a sender in non-existent conference {}", conference_id);
+                            }
+                        },
+                        UIAction::CreateBreakout((parent_conference_id, topic)) => {
+                            if !conferences.contains_key(&parent_conference_id) {
+                                warn!("Attempted to create a breakout of non-existent conference {}", parent_conference_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceCreateFailed).await;
+                                continue;
+                            }
+                            // the password is only shared through the announcement in the parent conference
+                            let password = bs58::encode(&crypto::generate_salt()[..16]).into_string();
+                            let breakout = Breakout { conference_id: 0, password: password.clone(), topic };
+                            let packet_nonce = sent_packets.insert(SentEvent::CreateBreakout((parent_conference_id, breakout)));
+                            send_ui_event(&mut ui_event_sender, UIEvent::DerivingKey(None)).await;
+                            let argon2 = argon2.clone();
+                            derive_key(&derived_key_sender, move || {
+                                let join_salt = crypto::generate_salt();
+                                let password_hash = crypto::hash_password_with_params(password.as_bytes(), &join_salt, argon2);
+                                DerivedKey::Create((packet_nonce, password_hash, join_salt))
+                            });
+                        },
+                        UIAction::ListConferences => {
+                            if !server_capabilities.directory() {
+                                send_ui_event(&mut ui_event_sender, UIEvent::ConferenceDirectory(None)).await;
+                                continue;
+                            }
+                            let packet_nonce = sent_packets.insert(SentEvent::ListConferences);
+                            client_event_sender.send(ClientEvent::ListConferences(packet_nonce)).await.unwrap();
+                        },
+                        UIAction::SelectServer(address) => {
+                            warn!("Attempted to select server {}, only connected to one server", address);
+                        },
+                        UIAction::MigrateConference((conference_id, _)) | UIAction::FollowMigration((conference_id, _)) => {
+                            // moving between servers is done by multi_server, which owns the sessions
+                            warn!("Attempted to move conference {} without a connection to the other server", conference_id);
+                            send_ui_event(&mut ui_event_sender, UIEvent::MigrationProgress((conference_id, MigrationStage::Failed("Not supported".to_string())))).await;
+                        },
+                        UIAction::AnnounceMigration((conference_id, migration)) => {
+                            if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                conference_sender.send(ConferenceEvent::AnnounceMigration(migration)).await.unwrap();
+                            } else {
+                                warn!("Attempted to announce the move of non-existent conference {}", conference_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::MessageError((conference_id, MIGRATION_MESSAGE_ID))).await;
                             }
                         },
                         UIAction::Disconnect => {
-                            send_packets_last_index += 1;
-                            let packet_nonce = send_packets_last_index;
                             let packet = ClientEvent::Disconnect;
 
-                            sent_packets.insert(packet_nonce, SentEvent::Disconnect); // todo might be useless
+                            sent_packets.insert(SentEvent::Disconnect); // todo might be useless
 
                             client_event_sender.send(packet).await.unwrap();
+                            disconnect_reason = Some(DisconnectReason::UserRequested);
                             break;
                         },
                     }
                 },
                 None => continue,
             },
-            disconnect = disconnect_receiver.next().fuse() => match disconnect {
-                Some(disconnect) => match disconnect {}, // compile time unreachable!
-                None => break,
+            _ = expiry_receiver.next().fuse() => {
+                for (packet_nonce, sent_event) in sent_packets.expire(Instant::now()) {
+                    warn!("The server did not answer the request with nonce {} in time: {:?}", packet_nonce, sent_event);
+                    match sent_event {
+                        SentEvent::CreateConference | SentEvent::CreateBreakout(_) => {
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceCreateFailed).await;
+                        },
+                        SentEvent::GetConferenceJoinSalt((conference_id, _)) | SentEvent::JoinConference((conference_id, _)) => {
+                            rejoin.failed(&conference_id);
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceJoinFailed(conference_id)).await;
+                        },
+                        SentEvent::LeaveConference(conference_id) | SentEvent::RejoinConference((conference_id, _)) => {
+                            // same as a ConferenceLeaveError, the conference is removed anyway
+                            conferences.remove(&conference_id);
+                            liveness.remove(&conference_id);
+                            idle.remove(&conference_id);
+                            rejoin.left(&conference_id);
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceLeft(conference_id)).await;
+                        },
+                        SentEvent::SendMessage((conference_id, Some(message_id))) => match outbound.timed_out(conference_id, message_id) {
+                            MessageRetry::Resend(message) if conferences.contains_key(&conference_id) => {
+                                info!("Sending message {} to conference {} again", message_id, conference_id);
+                                let packet_nonce = sent_packets.insert_with_timeout(SentEvent::SendMessage((conference_id, Some(message_id))), MESSAGE_TIMEOUT);
+                                outbound.sent(&message, packet_nonce);
+                                client_event_sender.send(ClientEvent::SendMessage((packet_nonce, message))).await.unwrap();
+                            },
+                            MessageRetry::Resend(_) | MessageRetry::GiveUp => {
+                                // the conference was left meanwhile, or the message was retried too often
+                                outbound.finished(conference_id, message_id);
+                                send_times.failed(conference_id, message_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::MessageTimedOut((conference_id, message_id))).await;
+                            },
+                            MessageRetry::Stale => {},
+                        },
+                        SentEvent::SendChunk((conference_id, message_id, index)) => match uploads.timed_out(conference_id, message_id, index) {
+                            ChunkTimedOut::Resend(chunk) => {
+                                if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                    conference_sender.send(outbound_chunk(uploads, conference_id, message_id, chunk)).await.unwrap();
+                                }
+                            },
+                            ChunkTimedOut::GiveUp => {
+                                send_times.failed(conference_id, message_id);
+                                send_ui_event(&mut ui_event_sender, UIEvent::MessageTimedOut((conference_id, message_id))).await;
+                            },
+                            ChunkTimedOut::Stale => {},
+                        },
+                        SentEvent::ListConferences => {
+                            // stops the UI waiting for the directory
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceDirectory(None)).await;
+                        },
+                        SentEvent::SendMessage((_, None)) | SentEvent::Disconnect | SentEvent::Cancelled(_) => {},
+                    }
+                }
+                if idle_session.expired(Instant::now()) {
+                    info!("No user activity for too long, leaving all {} conferences and disconnecting", conferences.len());
+                    for &conference_id in conferences.keys() {
+                        let packet_nonce = sent_packets.insert(SentEvent::LeaveConference(conference_id));
+                        client_event_sender.send(ClientEvent::LeaveConference((packet_nonce, conference_id))).await.unwrap();
+                    }
+                    client_event_sender.send(ClientEvent::Disconnect).await.unwrap();
+                    disconnect_reason = Some(DisconnectReason::Idle);
+                    break;
+                }
+                for check in idle.check(Instant::now()) {
+                    match check {
+                        IdleCheck::Warn((conference_id, remaining)) => {
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceIdle((conference_id, remaining))).await;
+                        },
+                        IdleCheck::Leave(conference_id) if conferences.contains_key(&conference_id) => {
+                            info!("Leaving conference {}, no messages were sent or received for too long", conference_id);
+                            let packet_nonce = sent_packets.insert(SentEvent::LeaveConference(conference_id));
+                            client_event_sender.send(ClientEvent::LeaveConference((packet_nonce, conference_id))).await.unwrap();
+                        },
+                        IdleCheck::Leave(_) => {},
+                    }
+                }
+                for check in liveness.check(Instant::now()) {
+                    match check {
+                        Check::Probe(conference_id) => {
+                            if let Some(mut conference_sender) = conferences.get(&conference_id) {
+                                conference_sender.send(ConferenceEvent::Probe).await.unwrap();
+                            }
+                        },
+                        Check::Stale(conference_id) => {
+                            warn!("Conference {} had no traffic and its peers did not answer, the server may have stopped relaying it", conference_id);
+                            send_ui_event(&mut ui_event_sender, UIEvent::ConferenceStale((conference_id, true))).await;
+                        },
+                    }
+                }
+            },
+            derived_key = derived_key_receiver.next().fuse() => match derived_key {
+                // cancelled or expired while hashing, nothing was sent for the nonce yet
+                Some(DerivedKey::Create((packet_nonce, _, _)) | DerivedKey::Join((packet_nonce, _, _)))
+                    if !matches!(sent_packets.get(&packet_nonce), Some(SentEvent::CreateConference | SentEvent::CreateBreakout(_) | SentEvent::JoinConference(_))) => {
+                    if matches!(sent_packets.get(&packet_nonce), Some(SentEvent::Cancelled(_))) {
+                        sent_packets.remove(&packet_nonce);
+                    }
+                },
+                Some(DerivedKey::Create((packet_nonce, password_hash, join_salt))) => {
+                    let encryption_salt = crypto::generate_salt();
+                    let packet = ClientEvent::CreateConference((packet_nonce, password_hash, join_salt, encryption_salt));
+                    client_event_sender.send(packet).await.unwrap();
+                },
+                Some(DerivedKey::Join((packet_nonce, conference_id, password_hash))) => {
+                    let packet = ClientEvent::JoinConference((packet_nonce, conference_id, password_hash));
+                    client_event_sender.send(packet).await.unwrap();
+                },
+                None => continue,
+            },
+            _ = network_change_receiver.next().fuse() => {
+                info!("The network changed, connecting again and joining {} conferences again", rejoin.conferences().count());
+                disconnect_reason = Some(DisconnectReason::NetworkChanged);
+                break;
+            },
+            disconnect = disconnect_receiver.next().fuse() => {
+                // the connection manager only exits without an error when the connection is closed
+                disconnect_reason = Some(disconnect.unwrap_or(DisconnectReason::ServerClosed));
+                break;
             },
             complete => {
                 warn!("complete")
@@ -340,33 +989,375 @@ pub async fn start_state_manager(server_address: String, mut ui_event_sender: Se
 
     drop(conferences);
     drop(client_event_sender);
+    drop(network_change_sender);
+
+    // deliver the events that were queued before the loop exited
+    drop(ui_event_sender);
+    while let Ok(Some(ui_event)) = ui_event_receiver.try_next() {
+        let _ = ui_event_sink.send(ui_event).await;
+    }
+    let delay = match &disconnect_reason {
+        Some(reason) if reconnectable => rejoin.reconnect(reason),
+        _ => None,
+    };
+    if let (Some(delay), Some(reason)) = (delay, disconnect_reason.clone()) {
+        // the conferences are joined again by the next session
+        info!("{}, connecting again in {:?}", reason, delay);
+        let _ = ui_event_sink.send(UIEvent::Reconnecting(reason)).await;
+        for conference_id in rejoin.conferences() {
+            let _ = ui_event_sink.send(UIEvent::ConferenceLeft(conference_id)).await;
+        }
+        return Some(delay);
+    }
+    if let Some(reason) = disconnect_reason {
+        info!("Disconnected: {}", reason);
+        if let DisconnectReason::HandshakeFailed(diagnosis) = &reason {
+            let _ = ui_event_sink.send(UIEvent::HandshakeFailed(diagnosis.clone())).await;
+        }
+        let _ = ui_event_sink.send(UIEvent::Disconnected(reason)).await;
+    }
+    None
+}
+
+/// Check a server event against the pending requests and the joined conferences.
+/// Replies must answer a pending request of the matching kind and conference, other events must be for a joined conference.
+fn validate_server_event<T>(server_event: &ServerEvent, sent_packets: &PendingRequests<SentEvent>, outbound: &OutboundMessages, conferences: &HashMap<ConferenceId, T>, handshake_done: bool) -> std::result::Result<(), String> {
+    let expect_joined = |conference_id: &ConferenceId| {
+        if conferences.contains_key(conference_id) {
+            Ok(())
+        } else {
+            // not formatting the whole event, it may carry a message
+            Err(format!("Received an event for conference {}, which was not joined", conference_id))
+        }
+    };
+    // a cancelled request is still answered like the original one
+    let reply_to = |packet_nonce: &PacketNonce| match sent_packets.get(packet_nonce) {
+        Some(SentEvent::Cancelled(request)) => Ok(request.as_ref()),
+        Some(request) => Ok(request),
+        None => Err(format!("{:?} does not answer a pending request", server_event)),
+    };
+    let mismatch = |sent_event: &SentEvent| -> std::result::Result<(), String> {
+        Err(format!("{:?} does not match the request {:?}", server_event, sent_event))
+    };
+
+    match server_event {
+        // the connection manager turns the handshake into capabilities, anything later is sent by the server
+        ServerEvent::HandshakeAcknowledged => Err("Received a handshake acknowledgement after the handshake".to_string()),
+        ServerEvent::HandshakeAcknowledgedWithCapabilities(_) if handshake_done => Err("Received the server capabilities twice".to_string()),
+        ServerEvent::HandshakeAcknowledgedWithCapabilities(_) | ServerEvent::GeneralError
+            | ServerEvent::Ping(_) | ServerEvent::Pong(_) | ServerEvent::Unknown(_) | ServerEvent::PinMismatch(_) => Ok(()),
+        ServerEvent::ConferenceList((packet_nonce, _)) => match reply_to(packet_nonce)? {
+            SentEvent::ListConferences => Ok(()),
+            sent_event => mismatch(sent_event),
+        },
+        ServerEvent::ConferenceCreated((packet_nonce, conference_id)) => match reply_to(packet_nonce)? {
+            SentEvent::CreateConference | SentEvent::CreateBreakout(_) if !conferences.contains_key(conference_id) => Ok(()),
+            sent_event => mismatch(sent_event),
+        },
+        ServerEvent::ConferenceCreationError(packet_nonce) => match reply_to(packet_nonce)? {
+            SentEvent::CreateConference | SentEvent::CreateBreakout(_) => Ok(()),
+            sent_event => mismatch(sent_event),
+        },
+        ServerEvent::ConferenceJoinSalt((packet_nonce, conference_id, _)) | ServerEvent::ConferenceJoinSaltError((packet_nonce, conference_id)) => match reply_to(packet_nonce)? {
+            SentEvent::GetConferenceJoinSalt((expected_conference_id, _)) if expected_conference_id == conference_id => Ok(()),
+            sent_event => mismatch(sent_event),
+        },
+        ServerEvent::ConferenceJoined((packet_nonce, conference_id, number_of_peers, _)) => match reply_to(packet_nonce)? {
+            SentEvent::JoinConference((expected_conference_id, _))
+                if expected_conference_id == conference_id && *number_of_peers > 0 && !conferences.contains_key(conference_id) => Ok(()),
+            sent_event => mismatch(sent_event),
+        },
+        ServerEvent::ConferenceJoinError((packet_nonce, conference_id)) => match reply_to(packet_nonce)? {
+            SentEvent::JoinConference((expected_conference_id, _)) if expected_conference_id == conference_id => Ok(()),
+            sent_event => mismatch(sent_event),
+        },
+        ServerEvent::ConferenceLeft((packet_nonce, conference_id)) | ServerEvent::ConferenceLeaveError((packet_nonce, conference_id)) => match reply_to(packet_nonce)? {
+            SentEvent::LeaveConference(expected_conference_id) | SentEvent::RejoinConference((expected_conference_id, _))
+                if expected_conference_id == conference_id => Ok(()),
+            sent_event => mismatch(sent_event),
+        },
+        ServerEvent::MessageAccepted((packet_nonce, conference_id)) | ServerEvent::MessageError((packet_nonce, conference_id)) => {
+            // a late reply to an earlier send of a message that was sent again
+            let late_reply = sent_packets.get(packet_nonce).is_none().then(|| outbound.sent_under(packet_nonce)).flatten();
+            match late_reply {
+                Some((expected_conference_id, _)) if expected_conference_id == *conference_id => Ok(()),
+                Some(_) => Err(format!("{:?} does not match the conference of the message it answers", server_event)),
+                None => match reply_to(packet_nonce)? {
+                    SentEvent::SendMessage((expected_conference_id, _)) | SentEvent::SendChunk((expected_conference_id, _, _))
+                        if expected_conference_id == conference_id => Ok(()),
+                    sent_event => mismatch(sent_event),
+                },
+            }
+        },
+        ServerEvent::ConferenceRestructuring((conference_id, number_of_peers)) => {
+            expect_joined(conference_id)?;
+            if *number_of_peers == 0 {
+                return Err(format!("Conference {} was restructured to zero peers", conference_id));
+            }
+            Ok(())
+        },
+        ServerEvent::IncomingMessage((conference_id, _)) => expect_joined(conference_id),
+    }
+}
+
+/// The request a reply answers, a late reply to an earlier send of a message that was sent again answers the message
+fn answered_request(sent_packets: &mut PendingRequests<SentEvent>, outbound: &OutboundMessages, packet_nonce: &PacketNonce) -> Option<SentEvent> {
+    sent_packets.remove(packet_nonce).or_else(|| {
+        let (conference_id, message_id) = outbound.sent_under(packet_nonce)?;
+        Some(SentEvent::SendMessage((conference_id, Some(message_id))))
+    })
 }
 
-async fn create_conference(
+/// The message was answered, it is no longer waited for under any of its nonces
+fn finish_message(sent_packets: &mut PendingRequests<SentEvent>, outbound: &mut OutboundMessages, conference_id: ConferenceId, message_id: MessageID) {
+    for packet_nonce in outbound.finished(conference_id, message_id) {
+        sent_packets.remove(&packet_nonce);
+    }
+}
+
+/// Queue an event for the UI, conference managers may still report events while shutting down
+async fn send_ui_event(ui_event_sender: &mut Sender<UIEvent>, ui_event: UIEvent) {
+    if ui_event_sender.send(ui_event).await.is_err() {
+        warn!("Dropped UI event, the UI event channel is closed");
+    }
+}
+
+/// Run Argon2 on the blocking pool so the event loop keeps running, the result arrives through `derived_key_sender`
+fn derive_key(derived_key_sender: &Sender<DerivedKey>, derive: impl FnOnce() -> DerivedKey + Send + 'static) {
+    let derived_key_sender = derived_key_sender.clone();
+    runtime::spawn(async move {
+        let derived_key = runtime::spawn_blocking(derive).await;
+        // the session may have ended while hashing
+        let _ = derived_key_sender.unbounded_send(derived_key);
+    }.in_current_span());
+}
+
+/// Join a conference again after leaving it, the join salt is requested first as for any join
+async fn rejoin_conference(sent_packets: &mut PendingRequests<SentEvent>, client_event_sender: &mut Sender<ClientEvent>, conference_id: ConferenceId, password: String) {
+    info!("Joining conference {} again", conference_id);
+    let packet_nonce = sent_packets.insert(SentEvent::GetConferenceJoinSalt((conference_id, password)));
+    client_event_sender.send(ClientEvent::GetConferenceJoinSalt((packet_nonce, conference_id))).await.unwrap();
+}
+
+/// Send the messages queued by plugins
+async fn send_plugin_messages(plugin_context: &mut PluginContext, conferences: &HashMap<ConferenceId, Sender<ConferenceEvent>>) {
+    for (conference_id, message) in plugin_context.take_outbound_messages() {
+        if let Some(mut conference_sender) = conferences.get(&conference_id) {
+            conference_sender.send(ConferenceEvent::OutboundMessage((PLUGIN_MESSAGE_ID, message.into()))).await.unwrap();
+        } else {
+            warn!("Plugin attempted to send message to non-existent conference {}", conference_id);
+        }
+    }
+}
+
+/// The event sending the next chunk of an upload, chunks of files are sent with the file they belong to
+fn outbound_chunk(uploads: &Uploads, conference_id: ConferenceId, message_id: MessageID, chunk: Chunk) -> ConferenceEvent {
+    match uploads.file(conference_id, message_id) {
+        Some(file) => ConferenceEvent::OutboundFileChunk((message_id, file.clone(), chunk)),
+        None => ConferenceEvent::OutboundChunk((message_id, chunk)),
+    }
+}
+
+/// The initial key is derived, the history is read and the conference manager with its key pair is built
+/// on the blocking pool, so setting up many conferences at once does not stall the event loop.
+/// Events for the conference are queued in the returned channel until the manager starts
+fn create_conference(
     conference_id: ConferenceId,
     number_of_peers: NumberOfPeers,
-    password: &[u8],
-    encryption_salt: &[u8; 32],
+    password: String,
+    encryption_salt: [u8; 32],
+    argon2: argon2::Params,
     message_sender: Sender<Message>,
     ui_event_sender: Sender<UIEvent>,
+    peer_labels: SharedPeerLabels,
+    flood_protection: FloodProtectionConfig,
+    history: HistoryConfig,
+    files: FilesConfig,
+    unstored: bool,
 ) -> Sender<ConferenceEvent> {
     info!("Creating conference manager for conference {}", conference_id);
     let (sender, receiver) = mpsc::unbounded();
-    let initial_encryption_key = crypto::hash_password_with_salt(password, encryption_salt);
-    let mut manager = conference_manager::ConferenceManager::new(
-        conference_id,
-        number_of_peers,
-        initial_encryption_key,
-        receiver,
-        message_sender,
-        ui_event_sender
-    );
-    task::spawn(async move {
+    let mut history_event_sender = ui_event_sender.clone();
+    runtime::spawn(async move {
+        let span = Span::current();
+        let (mut manager, recent) = runtime::spawn_blocking(move || {
+            let _entered = span.enter();
+            let initial_encryption_key = crypto::hash_password_with_params(password.as_bytes(), &encryption_salt, argon2);
+            let history_store = match history.persist.then(|| HistoryStore::open(&history_store::history_dir(), &initial_encryption_key)) {
+                Some(Ok(mut store)) => {
+                    // set to keep no history in an earlier session of this process
+                    if unstored {
+                        if let Err(e) = store.set_policy(RetentionPolicy::Never) {
+                            warn!("Could not remove the history of conference {}: {}", conference_id, e);
+                        }
+                    }
+                    Some(store)
+                },
+                Some(Err(e)) => {
+                    warn!("Could not open the history of conference {}: {}", conference_id, e);
+                    None
+                },
+                None => None,
+            };
+            let mut recent = match &history_store {
+                Some(store) => store.recent(history.load_recent).unwrap_or_else(|e| {
+                    warn!("Could not read the history of conference {}: {}", conference_id, e);
+                    Vec::new()
+                }),
+                None => Vec::new(),
+            };
+            for peer in recent.iter_mut().filter_map(|entry| entry.sender.as_mut()) {
+                peer.label = peer_labels.lock().unwrap().get(&peer.key_image).map(str::to_string);
+            }
+            let mut manager = conference_manager::ConferenceManager::new(
+                conference_id,
+                number_of_peers,
+                initial_encryption_key,
+                receiver,
+                message_sender,
+                ui_event_sender,
+                peer_labels,
+                flood_protection,
+            );
+            if let Some(history_store) = history_store {
+                manager = manager.with_history(history_store);
+            }
+            if files.receive {
+                manager = manager.with_incoming_files(IncomingFiles::new(file_transfer::downloads_dir(), files.max_size()));
+            }
+            (manager, recent)
+        }).await;
+        if !recent.is_empty() {
+            let _ = history_event_sender.send(UIEvent::HistoryLoaded((conference_id, recent))).await;
+        }
         if let Ok(()) = manager.start_conference_manager().await {
             info!("Conference manager for conference {} exited successfully", conference_id);
         } else {
             warn!("Conference manager for conference {} exited with an error", conference_id);
         }
-    });
+    }.instrument(info_span!("conference", conference_id)));
     sender
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use super::*;
+
+    #[test]
+    fn test_general_errors() {
+        let mut general_errors = GeneralErrors::default();
+        let start = Instant::now();
+        assert!(!general_errors.record(start));
+        assert!(!general_errors.record(start + GENERAL_ERROR_WINDOW / 2));
+        // the first error has expired
+        assert!(!general_errors.record(start + GENERAL_ERROR_WINDOW + Duration::from_secs(1)));
+        assert!(general_errors.record(start + GENERAL_ERROR_WINDOW + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_peers_changed() {
+        assert_eq!(peers_changed(3, 5), Some((2, 0)));
+        assert_eq!(peers_changed(5, 4), Some((0, 1)));
+        assert_eq!(peers_changed(4, 4), None);
+    }
+
+    #[test]
+    fn test_send_times() {
+        let mut send_times = SendTimes::default();
+        let start = Instant::now();
+        send_times.sent(1, 1, start);
+        assert_eq!(send_times.accepted(1, 1, start + Duration::from_millis(30)), Some(MessageLatency::Accepted(Duration::from_millis(30))));
+        assert_eq!(send_times.accepted(2, 1, start), None);
+        assert_eq!(send_times.seen(1, 1, start + Duration::from_millis(80)), Some(MessageLatency::FirstSeen(Duration::from_millis(80))));
+        // only the first peer is reported
+        assert_eq!(send_times.seen(1, 1, start + Duration::from_millis(90)), None);
+
+        for message_id in 0..=SEND_TIMES_KEPT {
+            send_times.sent(1, message_id, start + Duration::from_millis(message_id as u64));
+        }
+        assert_eq!(send_times.sent.len(), SEND_TIMES_KEPT);
+        assert_eq!(send_times.accepted(1, 0, start), None);
+    }
+
+    #[test]
+    fn test_validate_replies() {
+        let outbound = OutboundMessages::default();
+        let mut sent_packets = PendingRequests::new(PENDING_REQUEST_TIMEOUT);
+        let conferences: HashMap<ConferenceId, ()> = HashMap::from([(1, ())]);
+        let join_nonce = sent_packets.insert(SentEvent::JoinConference((2, "password".to_string())));
+        let message_nonce = sent_packets.insert(SentEvent::SendMessage((1, Some(1))));
+
+        assert!(validate_server_event(&ServerEvent::ConferenceJoined((join_nonce, 2, 2, [0; 32])), &sent_packets, &outbound, &conferences, true).is_ok());
+        assert!(validate_server_event(&ServerEvent::MessageAccepted((message_nonce, 1)), &sent_packets, &outbound, &conferences, true).is_ok());
+        // reply for another conference than requested
+        assert!(validate_server_event(&ServerEvent::ConferenceJoined((join_nonce, 3, 2, [0; 32])), &sent_packets, &outbound, &conferences, true).is_err());
+        assert!(validate_server_event(&ServerEvent::MessageError((message_nonce, 2)), &sent_packets, &outbound, &conferences, true).is_err());
+        // reply of the wrong kind
+        assert!(validate_server_event(&ServerEvent::ConferenceLeft((join_nonce, 2)), &sent_packets, &outbound, &conferences, true).is_err());
+        assert!(validate_server_event(&ServerEvent::ConferenceCreated((message_nonce, 4)), &sent_packets, &outbound, &conferences, true).is_err());
+        // nonce that was never used
+        assert!(validate_server_event(&ServerEvent::ConferenceJoinError((join_nonce + 10, 2)), &sent_packets, &outbound, &conferences, true).is_err());
+        // joined without peers
+        assert!(validate_server_event(&ServerEvent::ConferenceJoined((join_nonce, 2, 0, [0; 32])), &sent_packets, &outbound, &conferences, true).is_err());
+    }
+
+    #[test]
+    fn test_validate_late_message_replies() {
+        let mut sent_packets = PendingRequests::new(PENDING_REQUEST_TIMEOUT);
+        let mut outbound = OutboundMessages::default();
+        let conferences: HashMap<ConferenceId, ()> = HashMap::from([(1, ())]);
+        let message = Message { conference: 1, message: Bytes::from_static(b"abc"), message_id: Some(1) };
+        let first_nonce = sent_packets.insert(SentEvent::SendMessage((1, Some(1))));
+        outbound.sent(&message, first_nonce);
+        // the first send timed out and the message was sent again
+        sent_packets.remove(&first_nonce);
+        let second_nonce = sent_packets.insert(SentEvent::SendMessage((1, Some(1))));
+        outbound.sent(&message, second_nonce);
+
+        assert!(validate_server_event(&ServerEvent::MessageAccepted((first_nonce, 1)), &sent_packets, &outbound, &conferences, true).is_ok());
+        assert!(validate_server_event(&ServerEvent::MessageError((first_nonce, 2)), &sent_packets, &outbound, &conferences, true).is_err());
+        assert!(matches!(answered_request(&mut sent_packets, &outbound, &first_nonce), Some(SentEvent::SendMessage((1, Some(1))))));
+        finish_message(&mut sent_packets, &mut outbound, 1, 1);
+        // the reply to the second send comes too late as well
+        assert!(sent_packets.is_empty());
+        assert!(validate_server_event(&ServerEvent::MessageAccepted((second_nonce, 1)), &sent_packets, &outbound, &conferences, true).is_err());
+    }
+
+    #[test]
+    fn test_validate_cancelled_replies() {
+        let outbound = OutboundMessages::default();
+        let mut sent_packets = PendingRequests::new(PENDING_REQUEST_TIMEOUT);
+        let conferences: HashMap<ConferenceId, ()> = HashMap::new();
+        let join_nonce = sent_packets.insert(SentEvent::Cancelled(Box::new(SentEvent::JoinConference((2, "password".to_string())))));
+        // a late reply is still checked against the cancelled request
+        assert!(validate_server_event(&ServerEvent::ConferenceJoined((join_nonce, 2, 2, [0; 32])), &sent_packets, &outbound, &conferences, true).is_ok());
+        assert!(validate_server_event(&ServerEvent::ConferenceJoined((join_nonce, 3, 2, [0; 32])), &sent_packets, &outbound, &conferences, true).is_err());
+        assert!(validate_server_event(&ServerEvent::ConferenceCreated((join_nonce, 3)), &sent_packets, &outbound, &conferences, true).is_err());
+    }
+
+    #[test]
+    fn test_validate_unsolicited_events() {
+        let outbound = OutboundMessages::default();
+        let mut sent_packets = PendingRequests::new(PENDING_REQUEST_TIMEOUT);
+        let conferences: HashMap<ConferenceId, ()> = HashMap::from([(1, ())]);
+        assert!(validate_server_event(&ServerEvent::IncomingMessage((1, Bytes::new())), &sent_packets, &outbound, &conferences, true).is_ok());
+        assert!(validate_server_event(&ServerEvent::IncomingMessage((2, Bytes::new())), &sent_packets, &outbound, &conferences, true).is_err());
+        assert!(validate_server_event(&ServerEvent::ConferenceRestructuring((1, 3)), &sent_packets, &outbound, &conferences, true).is_ok());
+        assert!(validate_server_event(&ServerEvent::ConferenceRestructuring((1, 0)), &sent_packets, &outbound, &conferences, true).is_err());
+        assert!(validate_server_event(&ServerEvent::ConferenceRestructuring((2, 3)), &sent_packets, &outbound, &conferences, true).is_err());
+
+        // a server claiming to have created a conference we are already in
+        let create_nonce = sent_packets.insert(SentEvent::CreateConference);
+        assert!(validate_server_event(&ServerEvent::ConferenceCreated((create_nonce, 1)), &sent_packets, &outbound, &conferences, true).is_err());
+        assert!(validate_server_event(&ServerEvent::ConferenceCreated((create_nonce, 5)), &sent_packets, &outbound, &conferences, true).is_ok());
+
+        // the handshake is only acknowledged once, a stray acknowledgement is a single byte from the server
+        let capabilities = ServerEvent::HandshakeAcknowledgedWithCapabilities(ServerCapabilities::default());
+        assert!(validate_server_event(&capabilities, &sent_packets, &outbound, &conferences, false).is_ok());
+        assert!(validate_server_event(&capabilities, &sent_packets, &outbound, &conferences, true).is_err());
+        assert!(validate_server_event(&ServerEvent::HandshakeAcknowledged, &sent_packets, &outbound, &conferences, true).is_err());
+        // reported by the connection manager before the handshake
+        assert!(validate_server_event(&ServerEvent::PinMismatch(String::new()), &sent_packets, &outbound, &conferences, false).is_ok());
+    }
+}
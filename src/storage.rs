@@ -0,0 +1,240 @@
+//! Encryption of the files kept in the data directory, with a key derived from a master password.
+//!
+//! With `storage.encrypt` enabled the master password is asked for at startup, on the terminal by
+//! [`unlock_interactive`] or in the window of the graphical frontend, and every file written through [`write`] is encrypted. Files written before encryption was enabled
+//! are still read and are encrypted when the storage is unlocked.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::Path,
+    sync::OnceLock,
+};
+
+use log::{info, warn};
+use crate::{
+    config::StorageConfig,
+    constants::{EncryptionKey, Result},
+    crypto::{self, EncryptionResult, SALT_SIZE},
+    paths,
+    peer_labels::PEER_LABELS_FILE_NAME,
+    recent::RECENT_FILE_NAME,
+};
+
+const STORAGE_MAGIC: &[u8; 8] = b"ACCSTORE";
+const STORAGE_VERSION: u8 = 1;
+const HEADER_SIZE: usize = STORAGE_MAGIC.len() + 1;
+/// Salt of the master password and a known text to check the password with
+pub const STORAGE_KEY_FILE_NAME: &str = "storage.key";
+const VERIFIER: &[u8] = b"anonymous-conference storage key";
+/// The files in the data directory that are encrypted
+pub const ENCRYPTED_FILE_NAMES: [&str; 2] = [RECENT_FILE_NAME, PEER_LABELS_FILE_NAME];
+#[cfg(feature = "keyring")]
+const KEYRING_SERVICE: &str = "anonymous-conference-client";
+#[cfg(feature = "keyring")]
+const KEYRING_USER: &str = "master-password";
+
+/// Set once the master password was entered
+static KEY: OnceLock<EncryptionKey> = OnceLock::new();
+
+/// Derive the key from the master password and encrypt the files that are not encrypted yet.
+/// The first unlock sets the master password.
+pub fn unlock(password: &str) -> Result<()> {
+    let key = derive_key(&paths::data_dir().join(STORAGE_KEY_FILE_NAME), password)?;
+    if KEY.set(key).is_err() {
+        return Err("The storage is already unlocked".into());
+    }
+    for name in ENCRYPTED_FILE_NAMES {
+        let path = paths::data_dir().join(name);
+        match fs::read(&path) {
+            Ok(data) if !is_encrypted(&data) => {
+                fs::write(&path, encrypt(&key, &data)?)?;
+                info!("Encrypted {}", path.display());
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Whether the master password still has to be entered
+pub fn needs_unlock(config: &StorageConfig) -> bool {
+    config.encrypt && !is_unlocked()
+}
+
+/// Unlock with the password cached in the keyring if enabled, returns `false` if the password still has to be entered
+pub fn unlock_from_keyring(config: &StorageConfig) -> bool {
+    if !needs_unlock(config) {
+        return true;
+    }
+    #[cfg(feature = "keyring")]
+    if config.keyring {
+        if let Some(password) = cached_password() {
+            match unlock(&password) {
+                Ok(()) => return true,
+                Err(e) => warn!("The master password in the keyring does not work: {}", e),
+            }
+        }
+    }
+    #[cfg(not(feature = "keyring"))]
+    if config.keyring {
+        warn!("This build does not include the keyring, rebuild with the `keyring` feature");
+    }
+    false
+}
+
+/// Unlock with a master password the user entered, it is cached in the keyring if enabled
+pub fn unlock_entered(password: &str, config: &StorageConfig) -> Result<()> {
+    if password.is_empty() {
+        return Err("The master password is empty".into());
+    }
+    unlock(password)?;
+    #[cfg(feature = "keyring")]
+    if config.keyring {
+        if let Err(e) = cache_password(password) {
+            warn!("Could not store the master password in the keyring: {}", e);
+        }
+    }
+    #[cfg(not(feature = "keyring"))]
+    let _ = config;
+    Ok(())
+}
+
+/// Unlock with the password cached in the keyring if enabled, otherwise ask for it on the terminal.
+/// Does nothing if encryption is disabled.
+pub fn unlock_interactive(config: &StorageConfig) -> Result<()> {
+    if unlock_from_keyring(config) {
+        return Ok(());
+    }
+    let password = rpassword::prompt_password("Master password: ")?;
+    unlock_entered(&password, config)
+}
+
+pub fn is_unlocked() -> bool {
+    KEY.get().is_some()
+}
+
+/// Whether `password` is the master password the storage was unlocked with
+pub fn verify(password: &str) -> bool {
+    let Some(key) = KEY.get()
+    else {
+        return false;
+    };
+    derive_key(&paths::data_dir().join(STORAGE_KEY_FILE_NAME), password).is_ok_and(|candidate| &candidate == key)
+}
+
+/// The contents of a file in the data directory, `None` if it does not exist
+pub fn read_to_string(path: &Path) -> Result<Option<String>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let data = decode(KEY.get(), data)?;
+    Ok(Some(String::from_utf8(data)?))
+}
+
+/// Write a file in the data directory, encrypted if encryption is enabled in `config`
+pub fn write(path: &Path, contents: &str, config: &StorageConfig) -> Result<()> {
+    let data = match KEY.get() {
+        Some(key) => encrypt(key, contents.as_bytes())?,
+        None if config.encrypt => return Err("The storage is locked".into()),
+        None => contents.as_bytes().to_vec(),
+    };
+    fs::write(path, data)?;
+    Ok(())
+}
+
+fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(STORAGE_MAGIC)
+}
+
+/// `magic | version | iv | ciphertext`
+fn encrypt(key: &EncryptionKey, data: &[u8]) -> Result<Vec<u8>> {
+    let encrypted = crypto::encrypt_message(data, key).map_err(|_| "Could not encrypt the file")?;
+    let mut result = Vec::with_capacity(HEADER_SIZE + data.len());
+    result.extend_from_slice(STORAGE_MAGIC);
+    result.push(STORAGE_VERSION);
+    result.extend_from_slice(&encrypted.encode());
+    Ok(result)
+}
+
+/// Decrypt `data` if it is encrypted, files without the magic are plain text
+fn decode(key: Option<&EncryptionKey>, data: Vec<u8>) -> Result<Vec<u8>> {
+    if !is_encrypted(&data) {
+        return Ok(data);
+    }
+    let Some(key) = key
+    else {
+        return Err("The storage is locked".into());
+    };
+    if data.get(STORAGE_MAGIC.len()) != Some(&STORAGE_VERSION) {
+        return Err("Unsupported storage version".into());
+    }
+    let encrypted = EncryptionResult::decode(&data[HEADER_SIZE..]).map_err(|_| "The file is truncated")?;
+    Ok(crypto::decrypt_message(key, &encrypted).map_err(|_| "Could not decrypt the file")?)
+}
+
+/// The key of `password`, checked against the key file at `path`, which is created if it does not exist
+fn derive_key(path: &Path, password: &str) -> Result<EncryptionKey> {
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let (key, salt) = crypto::hash_password(password.as_bytes());
+            let mut contents = salt.to_vec();
+            contents.extend_from_slice(&encrypt(&key, VERIFIER)?);
+            fs::write(path, contents)?;
+            return Ok(key);
+        },
+        Err(e) => return Err(e.into()),
+    };
+    if contents.len() < SALT_SIZE {
+        return Err(format!("The key file {} is truncated", path.display()).into());
+    }
+    let (salt, verifier) = contents.split_at(SALT_SIZE);
+    let key = crypto::hash_password_with_salt(password.as_bytes(), salt.try_into().unwrap());
+    match decode(Some(&key), verifier.to_vec()) {
+        Ok(verifier) if verifier == VERIFIER => Ok(key),
+        _ => Err("Wrong master password".into()),
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn cached_password() -> Option<String> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).ok()?.get_password().ok()
+}
+
+#[cfg(feature = "keyring")]
+fn cache_password(password: &str) -> Result<()> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER)?.set_password(password)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derive_key() {
+        let dir = std::env::temp_dir().join(format!("acc-storage-test-{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(STORAGE_KEY_FILE_NAME);
+        let key = derive_key(&path, "correct horse").unwrap();
+        assert_eq!(derive_key(&path, "correct horse").unwrap(), key);
+        assert!(derive_key(&path, "battery staple").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_encrypt_decode() {
+        let key = crypto::generate_ephemeral_key();
+        let encrypted = encrypt(&key, b"servers = []\n").unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(decode(Some(&key), encrypted.clone()).unwrap(), b"servers = []\n");
+        assert!(decode(None, encrypted.clone()).is_err());
+        assert!(decode(Some(&[0; 32]), encrypted.clone()).is_err());
+        assert!(decode(Some(&key), encrypted[..HEADER_SIZE + 4].to_vec()).is_err());
+        // files from before encryption was enabled
+        assert_eq!(decode(None, b"servers = []\n".to_vec()).unwrap(), b"servers = []\n");
+    }
+}
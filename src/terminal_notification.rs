@@ -0,0 +1,84 @@
+//! Escape sequences that make the terminal draw attention to an incoming message.
+//!
+//! Inside tmux and screen the OSC sequence has to be wrapped in a passthrough sequence to reach the
+//! outer terminal, the plain bell is forwarded by the multiplexers themselves.
+
+use crate::config::TerminalNotification;
+
+const BELL: &str = "\x07";
+/// The body is cut off after this many characters, notifications only show a preview
+const MAX_BODY_LENGTH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Multiplexer {
+    Tmux,
+    Screen,
+}
+
+impl Multiplexer {
+    /// The multiplexer the client runs in, from the variables they set for their panes
+    pub fn detect() -> Option<Multiplexer> {
+        if std::env::var_os("TMUX").is_some() {
+            Some(Multiplexer::Tmux)
+        } else if std::env::var_os("STY").is_some() {
+            Some(Multiplexer::Screen)
+        } else {
+            None
+        }
+    }
+
+    fn passthrough(&self, sequence: &str) -> String {
+        match self {
+            Multiplexer::Tmux => format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")),
+            Multiplexer::Screen => format!("\x1bP{}\x1b\\", sequence),
+        }
+    }
+}
+
+/// The sequence to print for a message, `None` if notifications are disabled
+pub fn sequence(notification: TerminalNotification, multiplexer: Option<Multiplexer>, title: &str, body: &str) -> Option<String> {
+    match notification {
+        TerminalNotification::None => None,
+        TerminalNotification::Bell => Some(BELL.to_string()),
+        TerminalNotification::Osc777 => {
+            // fields are separated by `;`, only the body may contain it
+            let title = field(title).replace(';', ",");
+            let sequence = format!("\x1b]777;notify;{};{}{}", title, field(body), BELL);
+            Some(match multiplexer {
+                Some(multiplexer) => multiplexer.passthrough(&sequence),
+                None => sequence,
+            })
+        }
+    }
+}
+
+/// A single line without control characters that could end the sequence early
+fn field(text: &str) -> String {
+    text.chars()
+        .take(MAX_BODY_LENGTH)
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_sequence() {
+        assert_eq!(sequence(TerminalNotification::None, None, "title", "body"), None);
+        assert_eq!(sequence(TerminalNotification::Bell, Some(Multiplexer::Tmux), "title", "body").unwrap(), "\x07");
+        assert_eq!(
+            sequence(TerminalNotification::Osc777, None, "Conference 1; a", "hi;\nthere\x07").unwrap(),
+            "\x1b]777;notify;Conference 1, a;hi; there \x07"
+        );
+        assert_eq!(
+            sequence(TerminalNotification::Osc777, Some(Multiplexer::Tmux), "t", "b").unwrap(),
+            "\x1bPtmux;\x1b\x1b]777;notify;t;b\x07\x1b\\"
+        );
+        assert_eq!(
+            sequence(TerminalNotification::Osc777, Some(Multiplexer::Screen), "t", "b").unwrap(),
+            "\x1bP\x1b]777;notify;t;b\x07\x1b\\"
+        );
+    }
+}
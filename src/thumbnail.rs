@@ -0,0 +1,115 @@
+//! Small previews of received images.
+//!
+//! Only PNG and JPEG files are previewed. Files and images beyond the limits below are not decoded at all, so a
+//! peer can not make the client allocate large amounts of memory with a small, highly compressed image.
+
+use std::{fmt, fs, path::Path};
+
+use bytes::Bytes;
+use image::{io::{Limits, Reader}, ImageFormat};
+use crate::constants::Result;
+
+/// Larger files are shown without a preview
+pub const MAX_PREVIEW_FILE_SIZE: u64 = 16 * 1024 * 1024;
+/// Wider or higher images are shown without a preview
+const MAX_IMAGE_DIMENSION: u32 = 8192;
+/// Memory the decoder may allocate, a decoded image of the maximum dimensions does not fit
+const MAX_DECODER_ALLOCATION: u64 = 128 * 1024 * 1024;
+/// Previews fit into a square of this size, in pixels
+pub const THUMBNAIL_SIZE: u32 = 240;
+
+/// A decoded preview, in 8 bit RGBA without padding
+#[derive(Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Bytes,
+}
+
+/// Without the pixels, they would fill the log
+impl fmt::Debug for Thumbnail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thumbnail")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Thumbnail {
+    /// Bytes per row of pixels
+    pub fn stride(&self) -> usize {
+        self.width as usize * 4
+    }
+}
+
+/// Whether the file is previewed, judged by its name
+pub fn is_previewable(path: &Path) -> bool {
+    matches!(ImageFormat::from_path(path), Ok(ImageFormat::Png | ImageFormat::Jpeg))
+}
+
+/// Decode the image at `path` and scale it down to fit [`THUMBNAIL_SIZE`], blocking
+pub fn decode(path: &Path) -> Result<Thumbnail> {
+    let size = fs::metadata(path)?.len();
+    if size > MAX_PREVIEW_FILE_SIZE {
+        return Err(format!("the image has {} bytes, previews are limited to {} bytes", size, MAX_PREVIEW_FILE_SIZE).into());
+    }
+    let mut reader = Reader::open(path)?.with_guessed_format()?;
+    if !matches!(reader.format(), Some(ImageFormat::Png | ImageFormat::Jpeg)) {
+        return Err("the file is not a PNG or JPEG image".into());
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+    limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODER_ALLOCATION);
+    reader.limits(limits);
+    let image = reader.decode()?.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8();
+    Ok(Thumbnail {
+        width: image.width(),
+        height: image.height(),
+        rgba: Bytes::from(image.into_raw()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    fn temporary_file(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("acc-thumbnail-test-{}-{}", rand::random::<u32>(), name))
+    }
+
+    #[test]
+    fn test_is_previewable() {
+        assert!(is_previewable(Path::new("photo.JPG")));
+        assert!(is_previewable(Path::new("downloads/screenshot.png")));
+        assert!(!is_previewable(Path::new("notes.txt")));
+        assert!(!is_previewable(Path::new("image")));
+    }
+
+    #[test]
+    fn test_decode_scales_down() {
+        let path = temporary_file("wide.png");
+        ImageBuffer::from_pixel(960, 480, Rgba([200u8, 10, 10, 255])).save(&path).unwrap();
+        let thumbnail = decode(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!((thumbnail.width, thumbnail.height), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+        assert_eq!(thumbnail.rgba.len(), thumbnail.stride() * thumbnail.height as usize);
+        assert_eq!(&thumbnail.rgba[..4], &[200, 10, 10, 255]);
+    }
+
+    #[test]
+    fn test_decode_refuses_other_content() {
+        // named like an image, but is not one
+        let path = temporary_file("fake.png");
+        fs::write(&path, b"not an image").unwrap();
+        assert!(decode(&path).is_err());
+        fs::remove_file(&path).unwrap();
+        // beyond the dimension limit
+        let path = temporary_file("tall.png");
+        ImageBuffer::from_pixel(1, MAX_IMAGE_DIMENSION + 1, Rgba([0u8, 0, 0, 255])).save(&path).unwrap();
+        assert!(decode(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//! Recording of the raw frames exchanged with the server, to reproduce protocol bugs.
+//!
+//! A trace is a sequence of records, each encrypted separately with a random key that is
+//! stored next to the trace in `<trace>.key`, so the trace can be shared without the key.
+//! Only frames after the handshake are recorded.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use log::warn;
+use crate::{constants::{EncryptionKey, Result}, crypto::{self, EncryptionResult}};
+
+const TRACE_MAGIC: &[u8; 8] = b"ACCTRACE";
+const TRACE_VERSION: u8 = 1;
+
+static RECORDER: Mutex<Option<TraceRecorder>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Server to client
+    Inbound = 0,
+    /// Client to server
+    Outbound = 1,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub direction: Direction,
+    /// Milliseconds since the recording started
+    pub timestamp: u64,
+    /// The complete frame, starting with the event type
+    pub frame: Vec<u8>,
+}
+
+impl TraceRecord {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(9 + self.frame.len());
+        data.push(self.direction as u8);
+        data.extend_from_slice(&self.timestamp.to_be_bytes());
+        data.extend_from_slice(&self.frame);
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<TraceRecord> {
+        let (&direction, rest) = data.split_first()?;
+        let direction = match direction {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            _ => return None,
+        };
+        let timestamp = u64::from_be_bytes(rest.get(..8)?.try_into().ok()?);
+        Some(TraceRecord { direction, timestamp, frame: rest[8..].to_vec() })
+    }
+}
+
+/// Path of the key belonging to a trace file
+pub fn key_path(trace_path: &Path) -> PathBuf {
+    let mut name = trace_path.as_os_str().to_owned();
+    name.push(".key");
+    PathBuf::from(name)
+}
+
+/// Start recording all frames of this process to `path`
+pub fn start_recording(path: &Path) -> Result<()> {
+    let recorder = TraceRecorder::create(path)?;
+    *RECORDER.lock().unwrap() = Some(recorder);
+    Ok(())
+}
+
+pub fn is_recording() -> bool {
+    RECORDER.lock().map(|recorder| recorder.is_some()).unwrap_or(false)
+}
+
+/// Record a frame if a recording was started, recording stops on the first write error
+pub fn record(direction: Direction, frame: &[u8]) {
+    let Ok(mut recorder) = RECORDER.lock()
+    else {
+        return;
+    };
+    if let Some(active) = recorder.as_mut() {
+        if let Err(e) = active.record(direction, frame) {
+            warn!("Could not write trace, stopping the recording: {}", e);
+            *recorder = None;
+        }
+    }
+}
+
+struct TraceRecorder {
+    writer: BufWriter<File>,
+    key: EncryptionKey,
+    started: Instant,
+}
+
+impl TraceRecorder {
+    fn create(path: &Path) -> Result<TraceRecorder> {
+        let key = crypto::generate_ephemeral_key();
+        write_key_file(&key_path(path), &key)?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(TRACE_MAGIC)?;
+        writer.write_all(&[TRACE_VERSION])?;
+        writer.flush()?;
+        Ok(TraceRecorder { writer, key, started: Instant::now() })
+    }
+
+    fn record(&mut self, direction: Direction, frame: &[u8]) -> Result<()> {
+        let record = TraceRecord {
+            direction,
+            timestamp: self.started.elapsed().as_millis() as u64,
+            frame: frame.to_vec(),
+        };
+        let encrypted = crypto::encrypt_message(&record.encode(), &self.key)
+            .map_err(|_| "Could not encrypt trace record")?
+            .encode();
+        self.writer.write_all(&u32::try_from(encrypted.len())?.to_be_bytes())?;
+        self.writer.write_all(&encrypted)?;
+        // flush every record so the trace is complete even if the client crashes
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+fn write_key_file(path: &Path, key: &EncryptionKey) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(key)?;
+    Ok(())
+}
+
+/// Read and decrypt a trace using the key stored next to it
+pub fn read_trace(path: &Path) -> Result<Vec<TraceRecord>> {
+    let key: EncryptionKey = fs::read(key_path(path))?
+        .try_into()
+        .map_err(|_| "Invalid trace key")?;
+    read_trace_with_key(BufReader::new(File::open(path)?), &key)
+}
+
+fn read_trace_with_key(mut reader: impl Read, key: &EncryptionKey) -> Result<Vec<TraceRecord>> {
+    let mut header = [0u8; 9];
+    reader.read_exact(&mut header)?;
+    if &header[..8] != TRACE_MAGIC {
+        return Err("Not a trace file".into());
+    }
+    if header[8] != TRACE_VERSION {
+        return Err(format!("Unsupported trace version {}", header[8]).into());
+    }
+
+    let mut records = Vec::new();
+    let mut length = [0u8; 4];
+    loop {
+        match reader.read_exact(&mut length) {
+            Ok(()) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let mut encrypted = Vec::new();
+        reader.by_ref().take(u32::from_be_bytes(length).into()).read_to_end(&mut encrypted)?;
+        let Ok(encrypted) = EncryptionResult::decode(&encrypted)
+        else {
+            warn!("Trace is truncated after {} records", records.len());
+            break;
+        };
+        let data = crypto::decrypt_message(key, &encrypted).map_err(|_| "Could not decrypt trace record")?;
+        records.push(TraceRecord::decode(&data).ok_or("Invalid trace record")?);
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("acc-trace-test-{}", rand::random::<u32>()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.trace");
+
+        let mut recorder = TraceRecorder::create(&path).unwrap();
+        recorder.record(Direction::Outbound, b"\x02\x00\x00\x00\x01").unwrap();
+        recorder.record(Direction::Inbound, b"\x01\x00\x00\x00\x01\x00\x00\x00\x02").unwrap();
+        drop(recorder);
+
+        let records = read_trace(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].direction, Direction::Outbound);
+        assert_eq!(records[0].frame, b"\x02\x00\x00\x00\x01");
+        assert_eq!(records[1].direction, Direction::Inbound);
+        assert_eq!(records[1].frame, b"\x01\x00\x00\x00\x01\x00\x00\x00\x02");
+
+        // the trace is useless without its key
+        let wrong_key = crypto::generate_ephemeral_key();
+        assert!(read_trace_with_key(File::open(&path).unwrap(), &wrong_key).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
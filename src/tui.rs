@@ -0,0 +1,628 @@
+//! Full screen terminal frontend: a sidebar with the conferences, a scrollable history per conference,
+//! an input line with editing and a status bar with the connection and the setup of the conference.
+//! Commands and events are handled by [`CLII_UI`], which sends its output here instead of printing it,
+//! the plain line based frontend is still started with `--cli-plain` or when the terminal is not interactive.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Stdout, Write},
+};
+
+use crossterm::{
+    event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use futures::{channel::mpsc, select, FutureExt, StreamExt};
+use ratatui::{
+    backend::CrosstermBackend,
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Frame, Terminal,
+};
+use unicode_width::UnicodeWidthChar;
+use crate::{
+    cli_ui::{TaggedLine, CLII_UI},
+    constants::{ConferenceId, NumberOfPeers, Receiver, UIEvent},
+};
+
+/// Lines kept per conference, older ones are dropped
+const SCROLLBACK_LINES: usize = 5000;
+/// Lines entered before, for going back with the arrow keys
+const INPUT_HISTORY: usize = 100;
+const SIDEBAR_WIDTH: u16 = 24;
+const SERVER_PANE_TITLE: &str = "Server";
+const SIDEBAR_TITLE: &str = "Conferences";
+const COMMAND_INPUT_TITLE: &str = "Command, join a conference to send messages";
+const KEYS_TEXT: &str = "Tab: switch  PgUp/PgDn: scroll  Ctrl+C: quit";
+
+type CrosstermTerminal = Terminal<CrosstermBackend<Stdout>>;
+
+enum Connection {
+    Connecting,
+    Connected,
+    Reconnecting,
+    Disconnected(String),
+}
+
+impl std::fmt::Display for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Connection::Connecting => write!(f, "Connecting"),
+            Connection::Connected => write!(f, "Connected"),
+            Connection::Reconnecting => write!(f, "Connecting again"),
+            Connection::Disconnected(reason) => write!(f, "Disconnected: {}", reason),
+        }
+    }
+}
+
+/// The history of a conference, or of the output that belongs to no conference
+struct Pane {
+    conference_id: Option<ConferenceId>,
+    lines: VecDeque<TaggedLine>,
+    /// rows scrolled up from the newest one
+    scroll: usize,
+    unread: bool,
+    joined: bool,
+    number_of_peers: NumberOfPeers,
+    /// the rows of every line and the width they were wrapped for, see [`Pane::rows`]
+    wrapped: Option<(usize, VecDeque<Vec<Line<'static>>>)>,
+}
+
+impl Pane {
+    fn new(conference_id: Option<ConferenceId>) -> Self {
+        Pane { conference_id, lines: VecDeque::new(), scroll: 0, unread: false, joined: false, number_of_peers: 0, wrapped: None }
+    }
+
+    fn push(&mut self, line: TaggedLine) {
+        if self.lines.len() == SCROLLBACK_LINES {
+            self.lines.pop_front();
+            if let Some((_, wrapped)) = &mut self.wrapped {
+                wrapped.pop_front();
+            }
+        }
+        if let Some((width, wrapped)) = &mut self.wrapped {
+            wrapped.push_back(wrap_line(&line, *width));
+        }
+        self.lines.push_back(line);
+    }
+
+    fn title(&self) -> String {
+        let unread = if self.unread { "* " } else { "" };
+        match self.conference_id {
+            None => format!("{}{}", unread, SERVER_PANE_TITLE),
+            Some(conference_id) if self.joined => format!("{}{} ({})", unread, conference_id, self.number_of_peers),
+            Some(conference_id) => format!("{}{} (left)", unread, conference_id),
+        }
+    }
+
+    /// The rows of every line at `width` columns, only wrapped again when the width changes
+    fn rows(&mut self, width: usize) -> &VecDeque<Vec<Line<'static>>> {
+        if self.wrapped.as_ref().is_some_and(|(wrapped_width, _)| *wrapped_width != width) {
+            self.wrapped = None;
+        }
+        let lines = &self.lines;
+        &self.wrapped.get_or_insert_with(|| (width, lines.iter().map(|line| wrap_line(line, width)).collect())).1
+    }
+}
+
+/// A line broken into rows of at most `width` columns, indented below its tag
+fn wrap_line(line: &TaggedLine, width: usize) -> Vec<Line<'static>> {
+    let tag = if line.tag.is_empty() { String::new() } else { format!("[{}]: ", line.tag) };
+    let indent = " ".repeat(text_width(&tag));
+    let text_width = width.saturating_sub(indent.len()).max(1);
+    let style = tag_style(&line.tag);
+    let mut rows = Vec::new();
+    for text_line in line.text.split('\n') {
+        for chunk in wrap(text_line, text_width) {
+            let prefix = if rows.is_empty() { Span::styled(tag.clone(), style) } else { Span::raw(indent.clone()) };
+            rows.push(Line::from(vec![prefix, Span::raw(chunk)]));
+        }
+    }
+    rows
+}
+
+fn tag_style(tag: &str) -> Style {
+    match tag {
+        "SYSTEM" => Style::default().fg(Color::Yellow),
+        "YOU" => Style::default().fg(Color::Cyan),
+        _ => Style::default().fg(Color::Green).add_modifier(Modifier::BOLD),
+    }
+}
+
+/// Columns taken on the terminal, wide characters such as CJK and most emoji take two
+fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+fn text_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+/// Split a line into pieces of at most `width` columns, an empty line stays one empty piece.
+/// A character wider than `width` gets a piece of its own.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut piece_width = 0;
+    for c in text.chars() {
+        if piece_width + char_width(c) > width && !piece.is_empty() {
+            pieces.push(std::mem::take(&mut piece));
+            piece_width = 0;
+        }
+        piece.push(c);
+        piece_width += char_width(c);
+    }
+    pieces.push(piece);
+    pieces
+}
+
+/// The line being typed and the lines entered before
+#[derive(Default)]
+struct LineEditor {
+    text: Vec<char>,
+    /// in characters
+    cursor: usize,
+    history: VecDeque<String>,
+    /// the entered line shown while going back through the history
+    browsing: Option<usize>,
+}
+
+impl LineEditor {
+    fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.text.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.text.len() {
+            self.text.remove(self.cursor);
+        }
+    }
+
+    fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.text.len());
+    }
+
+    fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Delete the word before the cursor and the spaces after it
+    fn delete_word(&mut self) {
+        let mut start = self.cursor;
+        while start > 0 && self.text[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.text[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// Delete everything before the cursor
+    fn clear_to_start(&mut self) {
+        self.text.drain(..self.cursor);
+        self.cursor = 0;
+    }
+
+    fn previous(&mut self) {
+        let index = match self.browsing {
+            Some(0) => return,
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        self.show(Some(index));
+    }
+
+    fn next(&mut self) {
+        match self.browsing {
+            Some(index) if index + 1 < self.history.len() => self.show(Some(index + 1)),
+            Some(_) => self.show(None),
+            None => {},
+        }
+    }
+
+    fn show(&mut self, index: Option<usize>) {
+        self.browsing = index;
+        self.text = index.map(|index| self.history[index].chars().collect()).unwrap_or_default();
+        self.cursor = self.text.len();
+    }
+
+    /// Returns the line and keeps it for the history
+    fn take(&mut self) -> String {
+        let line: String = self.text.drain(..).collect();
+        self.cursor = 0;
+        self.browsing = None;
+        if !line.trim().is_empty() && self.history.back() != Some(&line) {
+            if self.history.len() == INPUT_HISTORY {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+        line
+    }
+
+    fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+}
+
+pub struct Tui {
+    cli: CLII_UI,
+    output_receiver: Receiver<TaggedLine>,
+    /// escape sequences of the notifications, written after drawing so they do not end up inside a frame
+    notification_receiver: Receiver<String>,
+    /// the first pane is for the output that belongs to no conference
+    panes: Vec<Pane>,
+    selected: usize,
+    editor: LineEditor,
+    connection: Connection,
+    /// conferences that are being set up, and how far
+    setup: HashMap<ConferenceId, String>,
+    /// rows of the history shown at once, for scrolling by pages
+    history_height: usize,
+}
+
+impl Tui {
+    pub fn new(cli: CLII_UI) -> Self {
+        let (output_sender, output_receiver) = mpsc::unbounded();
+        let (notification_sender, notification_receiver) = mpsc::unbounded();
+        Tui {
+            cli: cli.with_output(output_sender, notification_sender),
+            output_receiver,
+            notification_receiver,
+            panes: vec![Pane::new(None)],
+            selected: 0,
+            editor: LineEditor::default(),
+            connection: Connection::Connecting,
+            setup: HashMap::new(),
+            history_height: 0,
+        }
+    }
+
+    /// Returns once the user quits or the connections ended, the terminal is restored in both cases
+    pub async fn start_ui(&mut self) -> io::Result<()> {
+        let mut terminal = enter_terminal()?;
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let _ = leave_terminal();
+            previous_hook(info);
+        }));
+
+        let result = self.run(&mut terminal).await;
+        leave_terminal()?;
+        result
+    }
+
+    async fn run(&mut self, terminal: &mut CrosstermTerminal) -> io::Result<()> {
+        let mut terminal_events = EventStream::new().fuse();
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            while let Ok(Some(sequence)) = self.notification_receiver.try_next() {
+                terminal.backend_mut().write_all(sequence.as_bytes())?;
+                terminal.backend_mut().flush()?;
+            }
+            select! {
+                event = terminal_events.next() => match event {
+                    Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                        if !self.process_key(key).await {
+                            return Ok(());
+                        }
+                    },
+                    Some(Ok(_)) => {},
+                    Some(Err(e)) => return Err(e),
+                    None => return Ok(()),
+                },
+                ui_event = self.cli.next_ui_event().fuse() => match ui_event {
+                    Some(ui_event) => self.process_ui_event(ui_event).await,
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+
+    /// Returns `false` to quit
+    async fn process_key(&mut self, key: KeyEvent) -> bool {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char('c') if control => return false,
+            KeyCode::Char('d') if control && self.editor.text.is_empty() => return false,
+            KeyCode::Char('w') if control => self.editor.delete_word(),
+            KeyCode::Char('u') if control => self.editor.clear_to_start(),
+            KeyCode::Char('a') if control => self.editor.home(),
+            KeyCode::Char('e') if control => self.editor.end(),
+            KeyCode::Char('n') if control => self.select((self.selected + 1) % self.panes.len()),
+            KeyCode::Char('p') if control => self.select((self.selected + self.panes.len() - 1) % self.panes.len()),
+            KeyCode::Char(c) if !control => self.editor.insert(c),
+            KeyCode::Backspace => self.editor.backspace(),
+            KeyCode::Delete => self.editor.delete(),
+            KeyCode::Left => self.editor.left(),
+            KeyCode::Right => self.editor.right(),
+            KeyCode::Home => self.editor.home(),
+            KeyCode::End => self.editor.end(),
+            KeyCode::Up if key.modifiers.contains(KeyModifiers::SHIFT) => self.scroll_up(1),
+            KeyCode::Down if key.modifiers.contains(KeyModifiers::SHIFT) => self.scroll_down(1),
+            KeyCode::Up => self.editor.previous(),
+            KeyCode::Down => self.editor.next(),
+            KeyCode::PageUp => self.scroll_up(self.history_height / 2),
+            KeyCode::PageDown => self.scroll_down(self.history_height / 2),
+            KeyCode::Tab => self.select((self.selected + 1) % self.panes.len()),
+            KeyCode::BackTab => self.select((self.selected + self.panes.len() - 1) % self.panes.len()),
+            KeyCode::Enter => {
+                let line = self.editor.take();
+                let conference_id = self.cli.conference_id();
+                self.cli.process_input(line).await;
+                self.collect_output(conference_id);
+                // what was typed is shown, also when another conference was looked at
+                let index = self.pane_index(self.cli.conference_id().or(conference_id)).unwrap_or(0);
+                self.select(index);
+                self.panes[index].scroll = 0;
+            },
+            _ => {},
+        }
+        true
+    }
+
+    async fn process_ui_event(&mut self, ui_event: UIEvent) {
+        let conference_id = event_conference(&ui_event);
+        match &ui_event {
+            UIEvent::ConferenceJoined((conference_id, number_of_peers)) => {
+                let index = self.pane_index(Some(*conference_id)).unwrap_or_else(|| {
+                    self.panes.push(Pane::new(Some(*conference_id)));
+                    self.panes.len() - 1
+                });
+                self.panes[index].joined = true;
+                self.panes[index].number_of_peers = *number_of_peers;
+                self.setup.insert(*conference_id, "Setting up".to_string());
+                self.connection = Connection::Connected;
+                self.select(index);
+            },
+            UIEvent::ConferenceLeft(conference_id) => {
+                if let Some(index) = self.pane_index(Some(*conference_id)) {
+                    self.panes[index].joined = false;
+                }
+                self.setup.remove(conference_id);
+            },
+            UIEvent::ConferenceRestructuring((conference_id, number_of_peers)) => {
+                if let Some(index) = self.pane_index(Some(*conference_id)) {
+                    self.panes[index].number_of_peers = *number_of_peers;
+                }
+                self.setup.insert(*conference_id, format!("Restructuring for {} peers", number_of_peers));
+            },
+            UIEvent::SetupProgress((conference_id, stage)) => {
+                self.setup.insert(*conference_id, stage.to_string());
+            },
+            UIEvent::ConferenceRenegotiating(conference_id) => {
+                self.setup.insert(*conference_id, "Renegotiating the keys".to_string());
+            },
+            UIEvent::ConferenceRestructuringFinished(conference_id) => {
+                self.setup.remove(conference_id);
+            },
+            UIEvent::ServerCapabilities(_) => self.connection = Connection::Connected,
+            UIEvent::Reconnecting(_) => self.connection = Connection::Reconnecting,
+            UIEvent::Disconnected(reason) => self.connection = Connection::Disconnected(reason.to_string()),
+            _ => {},
+        }
+        self.cli.process_ui_event(ui_event).await;
+        self.collect_output(conference_id);
+    }
+
+    /// Move the output of the last input or event to the pane of its conference
+    fn collect_output(&mut self, conference_id: Option<ConferenceId>) {
+        let index = self.pane_index(conference_id).unwrap_or(0);
+        while let Ok(Some(line)) = self.output_receiver.try_next() {
+            self.panes[index].push(line);
+        }
+        if index != self.selected {
+            self.panes[index].unread = true;
+        }
+    }
+
+    fn pane_index(&self, conference_id: Option<ConferenceId>) -> Option<usize> {
+        self.panes.iter().position(|pane| pane.conference_id == conference_id)
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index;
+        self.panes[index].unread = false;
+    }
+
+    fn scroll_up(&mut self, rows: usize) {
+        // limited to the history when drawing
+        let pane = &mut self.panes[self.selected];
+        pane.scroll = pane.scroll.saturating_add(rows.max(1));
+    }
+
+    fn scroll_down(&mut self, rows: usize) {
+        let pane = &mut self.panes[self.selected];
+        pane.scroll = pane.scroll.saturating_sub(rows.max(1));
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, input, status] = Layout::vertical([Constraint::Min(3), Constraint::Length(3), Constraint::Length(1)]).areas(frame.size());
+        let [sidebar, history] = Layout::horizontal([Constraint::Length(SIDEBAR_WIDTH), Constraint::Min(10)]).areas(main);
+
+        let items: Vec<ListItem> = self.panes.iter().map(|pane| ListItem::new(pane.title())).collect();
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(SIDEBAR_TITLE))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, sidebar, &mut ListState::default().with_selected(Some(self.selected)));
+
+        self.history_height = history.height.saturating_sub(2) as usize;
+        let width = history.width.saturating_sub(2) as usize;
+        let pane = &mut self.panes[self.selected];
+        let number_of_rows: usize = pane.rows(width).iter().map(Vec::len).sum();
+        pane.scroll = pane.scroll.min(number_of_rows.saturating_sub(self.history_height));
+        let scroll = pane.scroll;
+        // only the shown rows are copied, from the newest one back
+        let mut rows: Vec<Line> = pane.rows(width).iter().rev()
+            .flat_map(|line_rows| line_rows.iter().rev())
+            .skip(scroll)
+            .take(self.history_height)
+            .cloned()
+            .collect();
+        rows.reverse();
+        let title = match pane.conference_id {
+            Some(conference_id) => format!("Conference {}", conference_id),
+            None => SERVER_PANE_TITLE.to_string(),
+        };
+        let title = if pane.scroll > 0 { format!("{} (scrolled up {} lines)", title, pane.scroll) } else { title };
+        frame.render_widget(Paragraph::new(rows).block(Block::default().borders(Borders::ALL).title(title)), history);
+
+        let input_title = match self.cli.conference_id() {
+            Some(conference_id) if self.cli.can_send_messages() => format!("Message to conference {}", conference_id),
+            Some(conference_id) => format!("Conference {} is not ready, commands only", conference_id),
+            None => COMMAND_INPUT_TITLE.to_string(),
+        };
+        // the end of the line stays visible
+        let width = input.width.saturating_sub(2) as usize;
+        let before_cursor = |offset: usize| self.editor.text[offset..self.editor.cursor].iter().copied().map(char_width).sum::<usize>();
+        let mut offset = 0;
+        while offset < self.editor.cursor && before_cursor(offset) + 1 > width {
+            offset += 1;
+        }
+        let text: String = self.editor.text[offset..].iter().collect();
+        frame.render_widget(Paragraph::new(text).block(Block::default().borders(Borders::ALL).title(input_title)), input);
+        frame.set_cursor(input.x + 1 + before_cursor(offset) as u16, input.y + 1);
+
+        let conference_state = self.cli.conference_id().map(|conference_id| match self.setup.get(&conference_id) {
+            Some(stage) => format!("Conference {}: {}", conference_id, stage),
+            None => format!("Conference {}: ready", conference_id),
+        });
+        let mut spans = vec![Span::raw(format!(" {} | {} ", self.cli.current_server(), self.connection))];
+        if let Some(conference_state) = conference_state {
+            spans.push(Span::raw(format!("| {} ", conference_state)));
+        }
+        spans.push(Span::styled(format!("| {}", KEYS_TEXT), Style::default().add_modifier(Modifier::DIM)));
+        frame.render_widget(Paragraph::new(Line::from(spans)).style(Style::default().add_modifier(Modifier::REVERSED)), status);
+    }
+}
+
+/// The conference whose pane shows the output for the event, `None` for the server pane
+fn event_conference(ui_event: &UIEvent) -> Option<ConferenceId> {
+    match ui_event {
+        UIEvent::ConferenceJoined((conference_id, _))
+        | UIEvent::ConferenceLeft(conference_id)
+        | UIEvent::ConferenceLeaveFailed(conference_id)
+        | UIEvent::IncomingMessage((conference_id, _, _, _))
+        | UIEvent::MessageAccepted((conference_id, _))
+        | UIEvent::MessageRejected((conference_id, _))
+        | UIEvent::MessageError((conference_id, _))
+        | UIEvent::MessageTimedOut((conference_id, _))
+        | UIEvent::MessageTooLarge((conference_id, _, _))
+        | UIEvent::UploadProgress((conference_id, _, _, _))
+        | UIEvent::FileTransferProgress((conference_id, _))
+        | UIEvent::FileReceived((conference_id, _))
+        | UIEvent::FileNotSent((conference_id, _, _))
+        | UIEvent::ConferenceRestructuring((conference_id, _))
+        | UIEvent::PeersChanged((conference_id, _, _))
+        | UIEvent::ConferenceRestructuringFinished(conference_id)
+        | UIEvent::SetupProgress((conference_id, _))
+        | UIEvent::ConferenceRenegotiating(conference_id)
+        | UIEvent::RingMismatch(conference_id)
+        | UIEvent::InvalidMessages((conference_id, _, _))
+        | UIEvent::MessageSeen((conference_id, _, _))
+        | UIEvent::MessageReceivedBy((conference_id, _, _, _))
+        | UIEvent::ConferenceIdle((conference_id, _))
+        | UIEvent::MessageLatency((conference_id, _, _))
+        | UIEvent::SenderMuted((conference_id, _))
+        | UIEvent::SenderUnmuted((conference_id, _, _))
+        | UIEvent::BreakoutAnnounced((conference_id, _, _))
+        | UIEvent::ConferenceServer((conference_id, _))
+        | UIEvent::Anonymity((conference_id, _))
+        | UIEvent::OwnFingerprint((conference_id, _))
+        | UIEvent::MigrationAnnounced((conference_id, _, _))
+        | UIEvent::MigrationProgress((conference_id, _))
+        | UIEvent::ConferenceStale((conference_id, _))
+        | UIEvent::HistoryLoaded((conference_id, _))
+        | UIEvent::RetentionPolicy((conference_id, _)) => Some(*conference_id),
+        _ => None,
+    }
+}
+
+fn enter_terminal() -> io::Result<CrosstermTerminal> {
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    Terminal::new(CrosstermBackend::new(io::stdout()))
+}
+
+fn leave_terminal() -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(io::stdout(), LeaveAlternateScreen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_editor() {
+        let mut editor = LineEditor::default();
+        for c in "hello world".chars() {
+            editor.insert(c);
+        }
+        editor.delete_word();
+        assert_eq!(editor.text(), "hello ");
+        editor.home();
+        editor.delete();
+        editor.end();
+        editor.backspace();
+        assert_eq!(editor.take(), "ello");
+        editor.insert('x');
+        assert_eq!(editor.take(), "x");
+
+        editor.insert('y');
+        editor.previous();
+        assert_eq!(editor.text(), "x");
+        editor.previous();
+        assert_eq!(editor.text(), "ello");
+        editor.next();
+        editor.next();
+        assert_eq!(editor.text(), "");
+    }
+
+    #[test]
+    fn test_pane_rows() {
+        let mut pane = Pane::new(Some(1));
+        pane.push(TaggedLine { tag: "YOU".to_string(), text: "abcdefgh\nij".to_string() });
+        pane.push(TaggedLine { tag: String::new(), text: String::new() });
+        // "[YOU]: " takes 7 of the 11 columns
+        let rows: Vec<String> = pane.rows(11).iter().flatten().map(|row| row.to_string()).collect();
+        assert_eq!(rows, vec!["[YOU]: abcd", "       efgh", "       ij", ""]);
+        assert_eq!(wrap("", 4), vec![""]);
+
+        // lines added later are wrapped for the cached width
+        pane.push(TaggedLine { tag: String::new(), text: "klmnopqrstuvw".to_string() });
+        let rows: Vec<String> = pane.rows(11).iter().flatten().map(|row| row.to_string()).collect();
+        assert_eq!(rows[4..], ["klmnopqrstu", "vw"]);
+        assert_eq!(pane.rows(9).iter().map(Vec::len).sum::<usize>(), 8);
+    }
+
+    #[test]
+    fn test_wrap_wide_characters() {
+        // every CJK character takes two columns
+        assert_eq!(wrap("会议客户端", 4), vec!["会议", "客户", "端"]);
+        assert_eq!(wrap("ab会议", 3), vec!["ab", "会", "议"]);
+        assert_eq!(wrap("会", 1), vec!["会"]);
+    }
+}
@@ -0,0 +1,119 @@
+use async_native_tls::TlsConnector;
+use futures::{AsyncReadExt, AsyncWriteExt, SinkExt};
+use log::{debug, info, warn};
+use serde::Deserialize;
+use crate::{
+    config::UpdateCheckConfig,
+    constants::{Result, Sender, UIEvent},
+    runtime,
+};
+
+const MAX_MANIFEST_SIZE: u64 = 64 * 1024;
+
+/// The version manifest published next to the releases
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct UpdateManifest {
+    pub version: String,
+    pub url: String,
+}
+
+/// Check for a newer release in the background if enabled in the config, sends `UIEvent::UpdateAvailable` if there is one
+pub fn spawn_update_check(config: &UpdateCheckConfig, mut ui_event_sender: Sender<UIEvent>) {
+    if !config.enabled {
+        return;
+    }
+    let manifest_url = config.manifest_url.clone();
+    runtime::spawn(async move {
+        match fetch_manifest(&manifest_url).await {
+            Ok(manifest) => {
+                if is_newer_version(&manifest.version, env!("CARGO_PKG_VERSION")) {
+                    info!("Version {} is available", manifest.version);
+                    let _ = ui_event_sender.send(UIEvent::UpdateAvailable((manifest.version, manifest.url))).await;
+                } else {
+                    debug!("No update available, latest version is {}", manifest.version);
+                }
+            },
+            Err(e) => warn!("Could not check for updates: {}", e),
+        }
+    });
+}
+
+/// Fetch the manifest with a single GET request, no identifying headers are sent
+pub async fn fetch_manifest(manifest_url: &str) -> Result<UpdateManifest> {
+    let (host, port, path) = split_https_url(manifest_url)
+        .map_err(|e| format!("Invalid update manifest url: {}", e))?;
+
+    let stream = runtime::connect((host, port)).await?;
+    let mut stream = TlsConnector::new().connect(host, stream).await?;
+    // HTTP/1.0 so that the response is never chunked
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nAccept: */*\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.take(MAX_MANIFEST_SIZE).read_to_end(&mut response).await?;
+    parse_response(&String::from_utf8_lossy(&response))
+}
+
+/// Host, port and path of an `https://` url
+pub(crate) fn split_https_url(url: &str) -> Result<(&str, u16, &str)> {
+    let Some(url) = url.strip_prefix("https://")
+    else {
+        return Err("Only https urls are supported".into());
+    };
+    let (authority, path) = match url.find('/') {
+        Some(index) => (&url[..index], &url[index..]),
+        None => (url, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse::<u16>()?),
+        None => (authority, 443),
+    };
+    Ok((host, port, path))
+}
+
+fn parse_response(response: &str) -> Result<UpdateManifest> {
+    let Some((head, body)) = response.split_once("\r\n\r\n")
+    else {
+        return Err("Invalid HTTP response".into());
+    };
+    let status_line = head.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("Unexpected HTTP status: {}", status_line).into());
+    }
+    Ok(toml::from_str(body)?)
+}
+
+/// Compare dotted version numbers, e.g. `0.10.0` is newer than `0.9.1`
+pub fn is_newer_version(candidate: &str, current: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version.trim_start_matches('v')
+            .split(['.', '-'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    parse(candidate) > parse(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer_version() {
+        assert!(is_newer_version("0.2.0", "0.1.0"));
+        assert!(is_newer_version("0.10.0", "0.9.1"));
+        assert!(is_newer_version("v1.0.0", "0.9.9"));
+        assert!(!is_newer_version("0.1.0", "0.1.0"));
+        assert!(!is_newer_version("0.1.0", "0.2.0"));
+        assert!(!is_newer_version("garbage", "0.1.0"));
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nversion = \"0.2.0\"\nurl = \"https://example.com\"\n";
+        assert_eq!(parse_response(response).unwrap(), UpdateManifest { version: "0.2.0".to_string(), url: "https://example.com".to_string() });
+        assert!(parse_response("HTTP/1.1 404 Not Found\r\n\r\n").is_err());
+        assert!(parse_response("garbage").is_err());
+    }
+}
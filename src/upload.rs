@@ -0,0 +1,401 @@
+//! Chunked uploads of messages too large for a single server message.
+//!
+//! A large message is split into chunks that are signed and sent as separate server messages. The next chunk is
+//! only sent once the server accepted the previous one, so when the connection is lost the upload continues after
+//! rejoining without being sent again by the user. Peers put the chunks with valid signatures together by the key
+//! image of their signatures and the random upload id, so no other member can add chunks to the upload. Peers cannot tell that
+//! chunks signed with a new key pair come from the same sender, so the upload starts over from the first chunk once
+//! the own key changed, after rejoining or at a key exchange in the unlinkable mode.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use log::warn;
+use rand_core::{OsRng, RngCore};
+use crate::{
+    constants::{ConferenceId, KeyImage, MessageID, NumberOfPeers},
+    file_transfer::OutgoingFile,
+};
+
+pub type UploadId = u64;
+
+/// Chunks are kept small even where the server accepts more, so little is sent again after a reconnect
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+/// Smaller chunks would be mostly signature
+pub const MIN_CHUNK_SIZE: usize = 1024;
+/// Largest message sent as an upload, also the largest one put together from the chunks of a peer
+pub const MAX_UPLOAD_SIZE: usize = 16 * 1024 * 1024;
+/// A chunk the server did not answer in time is sent this often before the upload fails
+const MAX_CHUNK_RETRIES: u32 = 3;
+/// Incomplete uploads of peers are dropped after this long without a new chunk
+const INCOMING_UPLOAD_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Uploads of peers put together at the same time, the oldest one is dropped for a new one
+const MAX_INCOMING_UPLOADS: usize = 16;
+/// upload id, index and number of chunks in front of the data
+const CHUNK_HEADER_SIZE: usize = 8 + 4 + 4;
+
+/// Bytes a chunk grows by until it is sent: encryption, message type, clock, ring signature and chunk header
+fn chunk_overhead(number_of_peers: NumberOfPeers) -> usize {
+    // the ring may grow while uploading
+    let ring_size = number_of_peers as usize * 2 + 8;
+    12 + 16 + 1 + 8 + 32 * (ring_size + 2) + CHUNK_HEADER_SIZE
+}
+
+/// The size of the chunks in a conference of `number_of_peers`, `None` if the server accepts too little for a chunk
+pub fn chunk_size(max_message_size: Option<u32>, number_of_peers: NumberOfPeers) -> Option<usize> {
+    let Some(max_message_size) = max_message_size
+    else {
+        return Some(MAX_CHUNK_SIZE);
+    };
+    let chunk_size = (max_message_size as usize).saturating_sub(chunk_overhead(number_of_peers)).min(MAX_CHUNK_SIZE);
+    (chunk_size >= MIN_CHUNK_SIZE).then_some(chunk_size)
+}
+
+/// One part of an upload, signed on its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    pub upload_id: UploadId,
+    pub index: u32,
+    pub total: u32,
+    pub data: Bytes,
+}
+
+impl Chunk {
+    pub fn is_last(&self) -> bool {
+        self.index + 1 == self.total
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(CHUNK_HEADER_SIZE + self.data.len());
+        result.extend_from_slice(&self.upload_id.to_be_bytes());
+        result.extend_from_slice(&self.index.to_be_bytes());
+        result.extend_from_slice(&self.total.to_be_bytes());
+        result.extend_from_slice(&self.data);
+        result
+    }
+
+    pub fn decode(chunk: &[u8]) -> Option<Chunk> {
+        if chunk.len() < CHUNK_HEADER_SIZE {
+            return None;
+        }
+        let upload_id = UploadId::from_be_bytes(chunk[..8].try_into().unwrap());
+        let index = u32::from_be_bytes(chunk[8..12].try_into().unwrap());
+        let total = u32::from_be_bytes(chunk[12..16].try_into().unwrap());
+        if index >= total {
+            return None;
+        }
+        Some(Chunk { upload_id, index, total, data: Bytes::copy_from_slice(&chunk[CHUNK_HEADER_SIZE..]) })
+    }
+}
+
+/// What to do after the server accepted a chunk
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkAccepted {
+    Next(Chunk),
+    /// the last chunk was accepted, the upload is forgotten
+    Finished,
+    /// the chunk was accepted before or the upload is gone
+    Stale,
+}
+
+/// What to do after the server did not answer a chunk in time
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChunkTimedOut {
+    Resend(Chunk),
+    /// sent too often, the upload is forgotten
+    GiveUp,
+    Stale,
+}
+
+struct Upload {
+    upload_id: UploadId,
+    chunks: Vec<Bytes>,
+    /// chunks accepted by the server, the next one to send has this index
+    acknowledged: usize,
+    retries: u32,
+    /// the content of a file instead of a message, see [`crate::file_transfer`]
+    file: Option<OutgoingFile>,
+}
+
+impl Upload {
+    fn chunk(&self, index: usize) -> Chunk {
+        Chunk { upload_id: self.upload_id, index: index as u32, total: self.chunks.len() as u32, data: self.chunks[index].clone() }
+    }
+}
+
+/// The own uploads, kept across reconnects of the state manager
+#[derive(Default)]
+pub struct Uploads {
+    uploads: HashMap<(ConferenceId, MessageID), Upload>,
+}
+
+impl Uploads {
+    /// Split `message` into chunks of `chunk_size`, returns the first chunk to send
+    pub fn start(&mut self, conference_id: ConferenceId, message_id: MessageID, message: Bytes, chunk_size: usize) -> Chunk {
+        self.insert(conference_id, message_id, OsRng.next_u64(), message, chunk_size, None)
+    }
+
+    /// Split the content of a file named `name` into chunks of `chunk_size`, returns the first chunk to send
+    pub fn start_file(&mut self, conference_id: ConferenceId, message_id: MessageID, name: String, content: Bytes, chunk_size: usize) -> Chunk {
+        let upload_id = OsRng.next_u64();
+        let file = OutgoingFile::new(upload_id, name, &content, chunk_size);
+        self.insert(conference_id, message_id, upload_id, content, chunk_size, Some(file))
+    }
+
+    fn insert(
+        &mut self,
+        conference_id: ConferenceId,
+        message_id: MessageID,
+        upload_id: UploadId,
+        content: Bytes,
+        chunk_size: usize,
+        file: Option<OutgoingFile>,
+    ) -> Chunk {
+        let chunks = (0..content.len()).step_by(chunk_size)
+            .map(|start| content.slice(start..content.len().min(start + chunk_size)))
+            .collect();
+        let upload = Upload { upload_id, chunks, acknowledged: 0, retries: 0, file };
+        let chunk = upload.chunk(0);
+        self.uploads.insert((conference_id, message_id), upload);
+        chunk
+    }
+
+    /// The file sent with `message_id`, `None` for messages
+    pub fn file(&self, conference_id: ConferenceId, message_id: MessageID) -> Option<&OutgoingFile> {
+        self.uploads.get(&(conference_id, message_id)).and_then(|upload| upload.file.as_ref())
+    }
+
+    /// The index of the chunk sent next or waiting for the server, `None` if `message_id` is no upload
+    pub fn pending_chunk(&self, conference_id: ConferenceId, message_id: MessageID) -> Option<u32> {
+        self.uploads.get(&(conference_id, message_id)).map(|upload| upload.acknowledged as u32)
+    }
+
+    /// Chunks accepted by the server and the number of chunks
+    pub fn progress(&self, conference_id: ConferenceId, message_id: MessageID) -> Option<(usize, usize)> {
+        self.uploads.get(&(conference_id, message_id)).map(|upload| (upload.acknowledged, upload.chunks.len()))
+    }
+
+    pub fn accepted(&mut self, conference_id: ConferenceId, message_id: MessageID, index: u32) -> ChunkAccepted {
+        let Some(upload) = self.uploads.get_mut(&(conference_id, message_id)).filter(|upload| upload.acknowledged == index as usize)
+        else {
+            return ChunkAccepted::Stale;
+        };
+        upload.acknowledged += 1;
+        upload.retries = 0;
+        if upload.acknowledged == upload.chunks.len() {
+            self.uploads.remove(&(conference_id, message_id));
+            return ChunkAccepted::Finished;
+        }
+        ChunkAccepted::Next(upload.chunk(upload.acknowledged))
+    }
+
+    pub fn timed_out(&mut self, conference_id: ConferenceId, message_id: MessageID, index: u32) -> ChunkTimedOut {
+        let Some(upload) = self.uploads.get_mut(&(conference_id, message_id)).filter(|upload| upload.acknowledged == index as usize)
+        else {
+            return ChunkTimedOut::Stale;
+        };
+        upload.retries += 1;
+        if upload.retries > MAX_CHUNK_RETRIES {
+            self.uploads.remove(&(conference_id, message_id));
+            return ChunkTimedOut::GiveUp;
+        }
+        ChunkTimedOut::Resend(upload.chunk(upload.acknowledged))
+    }
+
+    /// The first chunk not accepted yet of every upload to `conference_id`, to continue once it is set up again
+    pub fn resume(&self, conference_id: ConferenceId) -> Vec<(MessageID, Chunk)> {
+        self.uploads.iter()
+            .filter(|((upload_conference_id, _), _)| *upload_conference_id == conference_id)
+            .map(|((_, message_id), upload)| (*message_id, upload.chunk(upload.acknowledged)))
+            .collect()
+    }
+
+    /// Send the uploads to `conference_id` again from the first chunk, the chunks accepted so far were signed with
+    /// a key the peers can no longer link to the following chunks
+    pub fn restart(&mut self, conference_id: ConferenceId) {
+        for ((upload_conference_id, _), upload) in self.uploads.iter_mut() {
+            if *upload_conference_id == conference_id {
+                upload.acknowledged = 0;
+                upload.retries = 0;
+            }
+        }
+    }
+
+    /// Returns `true` if `message_id` was an upload
+    pub fn remove(&mut self, conference_id: ConferenceId, message_id: MessageID) -> bool {
+        self.uploads.remove(&(conference_id, message_id)).is_some()
+    }
+
+    /// Forget the uploads to the conferences `keep` returns `false` for
+    pub fn retain_conferences(&mut self, keep: impl Fn(ConferenceId) -> bool) {
+        self.uploads.retain(|(conference_id, _), _| keep(*conference_id));
+    }
+}
+
+struct IncomingUpload {
+    chunks: Vec<Option<Bytes>>,
+    received: usize,
+    size: usize,
+    last_chunk: Instant,
+}
+
+/// Puts the uploads of peers together, by the key image that signed the chunks and the upload id
+#[derive(Default)]
+pub struct Reassembly {
+    uploads: HashMap<(KeyImage, UploadId), IncomingUpload>,
+}
+
+impl Reassembly {
+    /// Add a chunk validly signed with `key_image`, returns the message once all chunks arrived
+    pub fn add(&mut self, key_image: KeyImage, chunk: Chunk, now: Instant) -> Option<Vec<u8>> {
+        self.uploads.retain(|_, upload| now.duration_since(upload.last_chunk) < INCOMING_UPLOAD_TIMEOUT);
+        if chunk.total as usize > MAX_UPLOAD_SIZE / MIN_CHUNK_SIZE {
+            warn!("Dropped a chunk of an upload with {} chunks", chunk.total);
+            return None;
+        }
+        let key = (key_image, chunk.upload_id);
+        if !self.uploads.contains_key(&key) && self.uploads.len() >= MAX_INCOMING_UPLOADS {
+            if let Some(oldest) = self.uploads.iter().min_by_key(|(_, upload)| upload.last_chunk).map(|(key, _)| *key) {
+                warn!("Too many incomplete uploads, dropping the oldest one");
+                self.uploads.remove(&oldest);
+            }
+        }
+        let upload = self.uploads.entry(key).or_insert_with(|| IncomingUpload {
+            chunks: vec![None; chunk.total as usize],
+            received: 0,
+            size: 0,
+            last_chunk: now,
+        });
+        if upload.chunks.len() != chunk.total as usize {
+            warn!("Dropped a chunk that does not match the other chunks of its upload");
+            return None;
+        }
+        upload.last_chunk = now;
+        // a chunk sent again after a reconnect is already known
+        let slot = &mut upload.chunks[chunk.index as usize];
+        if slot.is_some() {
+            return None;
+        }
+        if upload.size + chunk.data.len() > MAX_UPLOAD_SIZE {
+            warn!("Dropped an upload larger than {} bytes", MAX_UPLOAD_SIZE);
+            self.uploads.remove(&key);
+            return None;
+        }
+        upload.size += chunk.data.len();
+        upload.received += 1;
+        *slot = Some(chunk.data);
+        if upload.received < upload.chunks.len() {
+            return None;
+        }
+
+        let upload = self.uploads.remove(&key).unwrap();
+        let mut message = Vec::with_capacity(upload.size);
+        for data in upload.chunks.into_iter().flatten() {
+            message.extend_from_slice(&data);
+        }
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upload_resumes_from_last_accepted_chunk() {
+        let mut uploads = Uploads::default();
+        let first = uploads.start(1, 2, Bytes::from_static(b"abcdefg"), 3);
+        assert_eq!((first.index, first.total, &first.data[..]), (0, 3, &b"abc"[..]));
+        let ChunkAccepted::Next(second) = uploads.accepted(1, 2, 0)
+        else {
+            panic!("expected the next chunk");
+        };
+        assert_eq!(&second.data[..], b"def");
+        // the acceptance of a chunk sent twice only counts once
+        assert_eq!(uploads.accepted(1, 2, 0), ChunkAccepted::Stale);
+        assert_eq!(uploads.progress(1, 2), Some((1, 3)));
+
+        // after a reconnect the second chunk is sent again
+        assert_eq!(uploads.resume(1), vec![(2, second.clone())]);
+        assert!(uploads.resume(3).is_empty());
+        assert!(matches!(uploads.accepted(1, 2, 1), ChunkAccepted::Next(Chunk { index: 2, .. })));
+        assert_eq!(uploads.accepted(1, 2, 2), ChunkAccepted::Finished);
+        assert_eq!(uploads.pending_chunk(1, 2), None);
+    }
+
+    #[test]
+    fn test_file_upload() {
+        let mut uploads = Uploads::default();
+        uploads.start(1, 2, Bytes::from_static(b"message"), 4);
+        let first = uploads.start_file(1, 3, "notes.txt".to_string(), Bytes::from_static(b"hello world"), 4);
+        assert_eq!(uploads.file(1, 2), None);
+        let file = uploads.file(1, 3).unwrap();
+        assert_eq!((file.offer.file_id, file.offer.chunks, file.offer.size), (first.upload_id, first.total, 11));
+        assert_eq!(file.complete.file_id, first.upload_id);
+    }
+
+    #[test]
+    fn test_chunk_timeout() {
+        let mut uploads = Uploads::default();
+        uploads.start(1, 2, Bytes::from_static(b"abcd"), 2);
+        for _ in 0..MAX_CHUNK_RETRIES {
+            assert!(matches!(uploads.timed_out(1, 2, 0), ChunkTimedOut::Resend(Chunk { index: 0, .. })));
+        }
+        assert_eq!(uploads.timed_out(1, 2, 1), ChunkTimedOut::Stale);
+        assert_eq!(uploads.timed_out(1, 2, 0), ChunkTimedOut::GiveUp);
+        assert!(!uploads.remove(1, 2));
+    }
+
+    #[test]
+    fn test_reassembly() {
+        let mut uploads = Uploads::default();
+        let mut reassembly = Reassembly::default();
+        let now = Instant::now();
+        let first = uploads.start(1, 2, Bytes::from_static(b"hello world"), 4);
+        let encoded = first.encode();
+        assert_eq!(Chunk::decode(&encoded), Some(first.clone()));
+        assert_eq!(Chunk::decode(&encoded[..CHUNK_HEADER_SIZE - 1]), None);
+
+        assert_eq!(reassembly.add([1; 32], first.clone(), now), None);
+        // sent again after a timeout
+        assert_eq!(reassembly.add([1; 32], first, now), None);
+        let ChunkAccepted::Next(second) = uploads.accepted(1, 2, 0) else { panic!("expected the next chunk") };
+        assert_eq!(reassembly.add([1; 32], second, now), None);
+        let ChunkAccepted::Next(third) = uploads.accepted(1, 2, 1) else { panic!("expected the next chunk") };
+        assert!(third.is_last());
+        assert_eq!(reassembly.add([1; 32], third, now), Some(b"hello world".to_vec()));
+    }
+
+    #[test]
+    fn test_chunks_of_another_signer_are_kept_apart() {
+        let mut uploads = Uploads::default();
+        let mut reassembly = Reassembly::default();
+        let now = Instant::now();
+        let first = uploads.start(1, 2, Bytes::from_static(b"hello"), 4);
+        assert_eq!(reassembly.add([1; 32], first, now), None);
+        let ChunkAccepted::Next(last) = uploads.accepted(1, 2, 0) else { panic!("expected the next chunk") };
+        // another member cannot complete the upload
+        let forged = Chunk { data: Bytes::from_static(b"!"), ..last.clone() };
+        assert_eq!(reassembly.add([2; 32], forged, now), None);
+        assert_eq!(reassembly.add([1; 32], last, now), Some(b"hello".to_vec()));
+
+        // with a new key the upload starts over
+        let mut uploads = Uploads::default();
+        uploads.start(1, 2, Bytes::from_static(b"hello"), 4);
+        assert!(matches!(uploads.accepted(1, 2, 0), ChunkAccepted::Next(_)));
+        uploads.restart(1);
+        assert_eq!(uploads.pending_chunk(1, 2), Some(0));
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        assert_eq!(chunk_size(None, 3), Some(MAX_CHUNK_SIZE));
+        assert_eq!(chunk_size(Some(u32::MAX), 3), Some(MAX_CHUNK_SIZE));
+        assert_eq!(chunk_size(Some(1024), 3), None);
+        let chunk_size = chunk_size(Some(32 * 1024), 3).unwrap();
+        assert!(chunk_size + chunk_overhead(3) <= 32 * 1024);
+    }
+}
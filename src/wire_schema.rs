@@ -0,0 +1,220 @@
+//! Versioned serde schema of the events exchanged with the server, an alternative to the hand-written
+//! framing in [`crate::codec`].
+//!
+//! Servers that support it announce their highest schema version in the handshake, see
+//! [`crate::constants::SERVER_CAPABILITY_WIRE_SCHEMA`]. The client selects a version with a
+//! [`ClientEvent::SelectWireSchema`] in the classic framing, from then on every frame in both directions is
+//! the length of the payload as u32 followed by the postcard encoding of a [`ClientFrame`] or [`ServerFrame`].
+//! The variant of the frame is the version tag, so variants are only ever appended.
+//! Traces recorded with the schema can not be replayed yet.
+
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
+use crate::{
+    codec::{Decoder, Encoder, MAX_SERVER_FRAME_LENGTH},
+    constants::{
+        ClientEvent, ConferenceEncryptionSalt, ConferenceId, ConferenceJoinSalt, Message, NumberOfPeers, PacketNonce, PasswordHash,
+        Result, ServerEvent,
+    },
+};
+
+/// Highest schema version this client speaks
+pub const WIRE_SCHEMA_VERSION: u8 = 1;
+
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// The version to use with a server that speaks up to `server_version`, `None` if there is no common one
+pub fn select_version(server_version: u8) -> Option<u8> {
+    (server_version >= 1).then_some(server_version.min(WIRE_SCHEMA_VERSION))
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClientFrame {
+    V1(ClientEventV1),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum ServerFrame {
+    V1(ServerEventV1),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClientEventV1 {
+    CreateConference { nonce: PacketNonce, password_hash: PasswordHash, join_salt: ConferenceJoinSalt, encryption_salt: ConferenceEncryptionSalt },
+    GetConferenceJoinSalt { nonce: PacketNonce, conference_id: ConferenceId },
+    JoinConference { nonce: PacketNonce, conference_id: ConferenceId, password_hash: PasswordHash },
+    LeaveConference { nonce: PacketNonce, conference_id: ConferenceId },
+    SendMessage { nonce: PacketNonce, conference_id: ConferenceId, message: Bytes },
+    Disconnect,
+    ListConferences { nonce: PacketNonce },
+    Ping { number: u32 },
+    Pong { number: u32 },
+}
+
+/// The handshake is not framed, so it is not part of the schema
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub enum ServerEventV1 {
+    ConferenceCreated { nonce: PacketNonce, conference_id: ConferenceId },
+    ConferenceJoinSalt { nonce: PacketNonce, conference_id: ConferenceId, join_salt: ConferenceJoinSalt },
+    ConferenceJoined { nonce: PacketNonce, conference_id: ConferenceId, number_of_peers: NumberOfPeers, encryption_salt: ConferenceEncryptionSalt },
+    ConferenceLeft { nonce: PacketNonce, conference_id: ConferenceId },
+    MessageAccepted { nonce: PacketNonce, conference_id: ConferenceId },
+    ConferenceRestructuring { conference_id: ConferenceId, number_of_peers: NumberOfPeers },
+    IncomingMessage { conference_id: ConferenceId, message: Bytes },
+    ConferenceList { nonce: PacketNonce, conferences: Vec<(ConferenceId, NumberOfPeers)> },
+    GeneralError,
+    ConferenceCreationError { nonce: PacketNonce },
+    ConferenceJoinSaltError { nonce: PacketNonce, conference_id: ConferenceId },
+    ConferenceJoinError { nonce: PacketNonce, conference_id: ConferenceId },
+    ConferenceLeaveError { nonce: PacketNonce, conference_id: ConferenceId },
+    MessageError { nonce: PacketNonce, conference_id: ConferenceId },
+    Ping { number: u32 },
+    Pong { number: u32 },
+}
+
+impl TryFrom<ClientEvent> for ClientEventV1 {
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn try_from(event: ClientEvent) -> Result<ClientEventV1> {
+        Ok(match event {
+            ClientEvent::CreateConference((nonce, password_hash, join_salt, encryption_salt)) => {
+                ClientEventV1::CreateConference { nonce, password_hash, join_salt, encryption_salt }
+            },
+            ClientEvent::GetConferenceJoinSalt((nonce, conference_id)) => ClientEventV1::GetConferenceJoinSalt { nonce, conference_id },
+            ClientEvent::JoinConference((nonce, conference_id, password_hash)) => ClientEventV1::JoinConference { nonce, conference_id, password_hash },
+            ClientEvent::LeaveConference((nonce, conference_id)) => ClientEventV1::LeaveConference { nonce, conference_id },
+            ClientEvent::SendMessage((nonce, Message { conference, message, .. })) => {
+                ClientEventV1::SendMessage { nonce, conference_id: conference, message }
+            },
+            ClientEvent::Disconnect => ClientEventV1::Disconnect,
+            ClientEvent::ListConferences(nonce) => ClientEventV1::ListConferences { nonce },
+            ClientEvent::Ping(number) => ClientEventV1::Ping { number },
+            ClientEvent::Pong(number) => ClientEventV1::Pong { number },
+            ClientEvent::SelectWireSchema(_) => return Err("The wire schema was already selected".into()),
+        })
+    }
+}
+
+impl From<ServerEventV1> for ServerEvent {
+    fn from(event: ServerEventV1) -> ServerEvent {
+        match event {
+            ServerEventV1::ConferenceCreated { nonce, conference_id } => ServerEvent::ConferenceCreated((nonce, conference_id)),
+            ServerEventV1::ConferenceJoinSalt { nonce, conference_id, join_salt } => ServerEvent::ConferenceJoinSalt((nonce, conference_id, join_salt)),
+            ServerEventV1::ConferenceJoined { nonce, conference_id, number_of_peers, encryption_salt } => {
+                ServerEvent::ConferenceJoined((nonce, conference_id, number_of_peers, encryption_salt))
+            },
+            ServerEventV1::ConferenceLeft { nonce, conference_id } => ServerEvent::ConferenceLeft((nonce, conference_id)),
+            ServerEventV1::MessageAccepted { nonce, conference_id } => ServerEvent::MessageAccepted((nonce, conference_id)),
+            ServerEventV1::ConferenceRestructuring { conference_id, number_of_peers } => ServerEvent::ConferenceRestructuring((conference_id, number_of_peers)),
+            ServerEventV1::IncomingMessage { conference_id, message } => ServerEvent::IncomingMessage((conference_id, message)),
+            ServerEventV1::ConferenceList { nonce, conferences } => ServerEvent::ConferenceList((nonce, conferences)),
+            ServerEventV1::GeneralError => ServerEvent::GeneralError,
+            ServerEventV1::ConferenceCreationError { nonce } => ServerEvent::ConferenceCreationError(nonce),
+            ServerEventV1::ConferenceJoinSaltError { nonce, conference_id } => ServerEvent::ConferenceJoinSaltError((nonce, conference_id)),
+            ServerEventV1::ConferenceJoinError { nonce, conference_id } => ServerEvent::ConferenceJoinError((nonce, conference_id)),
+            ServerEventV1::ConferenceLeaveError { nonce, conference_id } => ServerEvent::ConferenceLeaveError((nonce, conference_id)),
+            ServerEventV1::MessageError { nonce, conference_id } => ServerEvent::MessageError((nonce, conference_id)),
+            ServerEventV1::Ping { number } => ServerEvent::Ping(number),
+            ServerEventV1::Pong { number } => ServerEvent::Pong(number),
+        }
+    }
+}
+
+/// Decodes server events and encodes client events in the versioned schema
+#[derive(Debug, Default)]
+pub struct SchemaCodec;
+
+impl SchemaCodec {
+    /// Split the next complete frame, including its length prefix, off the start of `buffer`
+    pub fn split_frame(&mut self, buffer: &mut BytesMut) -> Result<Option<Bytes>> {
+        let Some(payload_length) = buffer.get(..LENGTH_PREFIX_SIZE).map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        else {
+            return Ok(None);
+        };
+        let length = (payload_length as usize).checked_add(LENGTH_PREFIX_SIZE).ok_or("Schema frame is too long")?;
+        if length > MAX_SERVER_FRAME_LENGTH {
+            return Err(format!("Schema frame of {} bytes is too long", length).into());
+        }
+        Ok((buffer.len() >= length).then(|| buffer.split_to(length).freeze()))
+    }
+
+    /// Decode a frame returned by [`SchemaCodec::split_frame`]
+    pub fn decode_frame(frame: Bytes) -> Result<ServerEvent> {
+        match postcard::from_bytes::<ServerFrame>(&frame[LENGTH_PREFIX_SIZE..])? {
+            ServerFrame::V1(event) => Ok(event.into()),
+        }
+    }
+}
+
+impl Decoder for SchemaCodec {
+    type Item = ServerEvent;
+
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<ServerEvent>> {
+        self.split_frame(buffer)?.map(SchemaCodec::decode_frame).transpose()
+    }
+}
+
+impl Encoder for SchemaCodec {
+    type Item = ClientEvent;
+
+    fn encode(&mut self, event: ClientEvent, buffer: &mut BytesMut) -> Result<()> {
+        let payload = postcard::to_allocvec(&ClientFrame::V1(event.try_into()?))?;
+        buffer.put_u32(u32::try_from(payload.len()).map_err(|_| "Client event is too long")?);
+        buffer.put_slice(&payload);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_frame(event: ServerEventV1) -> Vec<u8> {
+        let payload = postcard::to_allocvec(&ServerFrame::V1(event)).unwrap();
+        let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    #[test]
+    fn test_select_version() {
+        assert_eq!(select_version(0), None);
+        assert_eq!(select_version(1), Some(1));
+        assert_eq!(select_version(7), Some(WIRE_SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn test_decode_server_events() {
+        let mut buffer = BytesMut::new();
+        buffer.put_slice(&server_frame(ServerEventV1::ConferenceCreated { nonce: 1, conference_id: 2 }));
+        buffer.put_slice(&server_frame(ServerEventV1::IncomingMessage { conference_id: 2, message: Bytes::from_static(b"abc") }));
+        let last = server_frame(ServerEventV1::GeneralError);
+        buffer.put_slice(&last[..last.len() - 1]);
+
+        assert!(matches!(SchemaCodec.decode(&mut buffer).unwrap(), Some(ServerEvent::ConferenceCreated((1, 2)))));
+        let Some(ServerEvent::IncomingMessage((2, message))) = SchemaCodec.decode(&mut buffer).unwrap() else { panic!() };
+        assert_eq!(&message[..], b"abc");
+        assert!(SchemaCodec.decode(&mut buffer).unwrap().is_none());
+        buffer.put_u8(last[last.len() - 1]);
+        assert!(matches!(SchemaCodec.decode(&mut buffer).unwrap(), Some(ServerEvent::GeneralError)));
+        assert!(buffer.is_empty());
+
+        // an unknown version
+        let mut buffer = BytesMut::from(&[0, 0, 0, 1, 9][..]);
+        assert!(SchemaCodec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_encode_client_events() {
+        let mut buffer = BytesMut::new();
+        let message = Message { conference: 2, message: Bytes::from_static(b"abc"), message_id: Some(3) };
+        SchemaCodec.encode(ClientEvent::SendMessage((1, message)), &mut buffer).unwrap();
+        let payload_length = u32::from_be_bytes(buffer[..4].try_into().unwrap()) as usize;
+        assert_eq!(buffer.len(), 4 + payload_length);
+        assert_eq!(
+            postcard::from_bytes::<ClientFrame>(&buffer[4..]).unwrap(),
+            ClientFrame::V1(ClientEventV1::SendMessage { nonce: 1, conference_id: 2, message: Bytes::from_static(b"abc") }),
+        );
+        assert!(SchemaCodec.encode(ClientEvent::SelectWireSchema(1), &mut buffer).is_err());
+    }
+}